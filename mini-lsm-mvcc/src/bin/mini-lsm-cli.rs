@@ -1 +1 @@
-../../../mini-lsm-starter/src/bin/mini-lsm-cli.rs
\ No newline at end of file
+../../../mini-lsm/src/bin/mini-lsm-cli.rs
\ No newline at end of file
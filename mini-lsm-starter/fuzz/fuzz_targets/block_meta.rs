@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_lsm_starter::fuzzing::decode_block_meta;
+
+fuzz_target!(|data: &[u8]| decode_block_meta(data));
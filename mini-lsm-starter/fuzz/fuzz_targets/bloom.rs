@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mini_lsm_starter::fuzzing::decode_bloom;
+
+fuzz_target!(|data: &[u8]| decode_bloom(data));
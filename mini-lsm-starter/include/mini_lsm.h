@@ -0,0 +1,86 @@
+/*
+ * The C ABI of mini-lsm, implemented by src/capi.rs. Build the shared library with
+ *
+ *     cargo rustc -p mini-lsm-starter --release --features capi --lib --crate-type cdylib
+ *
+ * Every function returning an int returns one of the MINI_LSM_* codes, and on failure keeps a message for
+ * mini_lsm_last_error on the calling thread. Handles are released exactly once, and an iterator is destroyed before
+ * the database it reads is closed.
+ *
+ * Which threads may use a handle at the same time is noted at its type.
+ */
+
+#ifndef MINI_LSM_H
+#define MINI_LSM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MINI_LSM_OK 0
+#define MINI_LSM_NOT_FOUND 1
+#define MINI_LSM_INVALID_ARGUMENT 2
+#define MINI_LSM_IO_ERROR 3
+#define MINI_LSM_ERROR 4
+
+/* Used by one thread at a time. */
+typedef struct MiniLsmOptions MiniLsmOptions;
+/* Used by any number of threads at once; mini_lsm_close runs once no other call on it does. */
+typedef struct MiniLsmDb MiniLsmDb;
+/* Used by one thread at a time, or read by mini_lsm_write from several threads while no thread modifies it. */
+typedef struct MiniLsmWriteBatch MiniLsmWriteBatch;
+/* Used by one thread at a time. */
+typedef struct MiniLsmIterator MiniLsmIterator;
+
+/* The message of the last failure on this thread, valid until the next one; NULL if none failed. */
+const char *mini_lsm_last_error(void);
+/* Release the value returned by mini_lsm_get. */
+void mini_lsm_free(uint8_t *ptr, size_t len);
+
+MiniLsmOptions *mini_lsm_options_create(void);
+void mini_lsm_options_destroy(MiniLsmOptions *options);
+/* Set a field of the OPTIONS file to a JSON value, e.g. "4096" or "true"; other values are taken as strings. */
+int mini_lsm_options_set(MiniLsmOptions *options, const char *name, const char *value);
+
+int mini_lsm_open(const char *path, const MiniLsmOptions *options, MiniLsmDb **db);
+/* Flush and close the database, releasing the handle even on failure. */
+int mini_lsm_close(MiniLsmDb *db);
+/* Change an option of the open database that can change while it is open. */
+int mini_lsm_set_option(MiniLsmDb *db, const char *name, const char *value);
+
+int mini_lsm_put(MiniLsmDb *db, const uint8_t *key, size_t key_len, const uint8_t *value, size_t value_len);
+int mini_lsm_delete(MiniLsmDb *db, const uint8_t *key, size_t key_len);
+/* Returns MINI_LSM_NOT_FOUND when the key has no value; the value is released with mini_lsm_free. */
+int mini_lsm_get(MiniLsmDb *db, const uint8_t *key, size_t key_len, uint8_t **value, size_t *value_len);
+int mini_lsm_flush(MiniLsmDb *db);
+int mini_lsm_sync(MiniLsmDb *db);
+
+MiniLsmWriteBatch *mini_lsm_writebatch_create(void);
+void mini_lsm_writebatch_destroy(MiniLsmWriteBatch *batch);
+int mini_lsm_writebatch_put(MiniLsmWriteBatch *batch, const uint8_t *key, size_t key_len, const uint8_t *value,
+                            size_t value_len);
+int mini_lsm_writebatch_delete(MiniLsmWriteBatch *batch, const uint8_t *key, size_t key_len);
+size_t mini_lsm_writebatch_count(const MiniLsmWriteBatch *batch);
+void mini_lsm_writebatch_clear(MiniLsmWriteBatch *batch);
+/* Write the batch atomically, leaving it as is. */
+int mini_lsm_write(MiniLsmDb *db, const MiniLsmWriteBatch *batch);
+
+/* Iterate from lower to upper, a NULL bound meaning no bound, starting at the first key. */
+int mini_lsm_iter_create(MiniLsmDb *db, const uint8_t *lower, size_t lower_len, bool lower_inclusive,
+                         const uint8_t *upper, size_t upper_len, bool upper_inclusive, MiniLsmIterator **iter);
+void mini_lsm_iter_destroy(MiniLsmIterator *iter);
+bool mini_lsm_iter_valid(const MiniLsmIterator *iter);
+/* The key and value of the current entry, valid until the iterator moves; NULL if it is not valid. */
+const uint8_t *mini_lsm_iter_key(const MiniLsmIterator *iter, size_t *len);
+const uint8_t *mini_lsm_iter_value(const MiniLsmIterator *iter, size_t *len);
+int mini_lsm_iter_next(MiniLsmIterator *iter);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MINI_LSM_H */
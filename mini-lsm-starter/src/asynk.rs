@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+
+/// Entries an `AsyncScan` reads on the blocking pool at once.
+const SCAN_CHUNK_SIZE: usize = 128;
+
+/// The writes waiting for the batch being written to finish, with where each one's result goes.
+#[derive(Default)]
+struct WriteQueue {
+    records: Vec<WriteBatchRecord<Bytes>>,
+    waiters: Vec<oneshot::Sender<Result<()>>>,
+    /// Whether a task is writing the queued batches.
+    writing: bool,
+}
+
+/// A database for async code, built on `MiniLsm`: gets read the SST blocks missing from the block cache on tokio's
+/// blocking pool, and the other operations run there entirely, so that executor threads never wait on disk.
+/// Concurrent writes are grouped into a single batch, written at a single commit ts, while the previous group is
+/// being written. Must be used within a tokio runtime.
+pub struct AsyncLsmStorage {
+    storage: Arc<MiniLsm>,
+    queue: Arc<Mutex<WriteQueue>>,
+}
+
+impl AsyncLsmStorage {
+    pub async fn open(path: impl Into<PathBuf>, options: LsmStorageOptions) -> Result<Self> {
+        let path = path.into();
+        let storage = tokio::task::spawn_blocking(move || MiniLsm::open(path, options)).await??;
+        Ok(Self::new(storage))
+    }
+
+    /// Wrap a database that is already open, which may keep being used directly.
+    pub fn new(storage: Arc<MiniLsm>) -> Self {
+        Self {
+            storage,
+            queue: Arc::default(),
+        }
+    }
+
+    /// The wrapped database, for the operations this one does not have.
+    pub fn storage(&self) -> &Arc<MiniLsm> {
+        &self.storage
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.storage.get_async(key).await
+    }
+
+    pub async fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Put(key, value)]).await
+    }
+
+    pub async fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Del(key)]).await
+    }
+
+    /// Write a batch, which readers see all of or none of. It may be written together with the batches of other
+    /// concurrent writes, of which the later ones win on the same key.
+    pub async fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
+        let records = batch.iter().map(|record| match record {
+            WriteBatchRecord::Put(key, value) => WriteBatchRecord::Put(
+                Bytes::copy_from_slice(key.as_ref()),
+                Bytes::copy_from_slice(value.as_ref()),
+            ),
+            WriteBatchRecord::Del(key) => {
+                WriteBatchRecord::Del(Bytes::copy_from_slice(key.as_ref()))
+            }
+        });
+        let (sender, receiver) = oneshot::channel();
+        let start_writing = {
+            let mut queue = self.queue.lock();
+            queue.records.extend(records);
+            queue.waiters.push(sender);
+            !std::mem::replace(&mut queue.writing, true)
+        };
+        if start_writing {
+            // a task rather than this write, so that dropping its future does not strand the others
+            tokio::spawn(Self::write_queued(self.storage.clone(), self.queue.clone()));
+        }
+        receiver
+            .await
+            .map_err(|_| anyhow!("the grouped write was cancelled"))?
+    }
+
+    /// Write the queued batches a group at a time until the queue is empty.
+    async fn write_queued(storage: Arc<MiniLsm>, queue: Arc<Mutex<WriteQueue>>) {
+        loop {
+            let (records, waiters) = {
+                let mut queue = queue.lock();
+                if queue.waiters.is_empty() {
+                    queue.writing = false;
+                    return;
+                }
+                (
+                    std::mem::take(&mut queue.records),
+                    std::mem::take(&mut queue.waiters),
+                )
+            };
+            let storage = storage.clone();
+            let result = tokio::task::spawn_blocking(move || storage.write_batch(&records))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+            for waiter in waiters {
+                // the error is shared by the whole group
+                let result = match &result {
+                    Ok(()) => Ok(()),
+                    Err(err) => Err(anyhow!("{:#}", err)),
+                };
+                let _ = waiter.send(result);
+            }
+        }
+    }
+
+    /// Iterate over the keys in the range, read from a snapshot taken now, a chunk at a time on the blocking pool.
+    pub async fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<AsyncScan> {
+        let (lower, upper) = (owned_bound(lower), owned_bound(upper));
+        let storage = self.storage.clone();
+        let iter = tokio::task::spawn_blocking(move || {
+            storage.scan(as_slice_bound(&lower), as_slice_bound(&upper))
+        })
+        .await??;
+        Ok(AsyncScan {
+            iter: Some(iter),
+            buffered: VecDeque::new(),
+        })
+    }
+
+    /// Flush the memtables to SSTs, see `MiniLsm::force_flush`.
+    pub async fn flush(&self) -> Result<()> {
+        let storage = self.storage.clone();
+        tokio::task::spawn_blocking(move || storage.force_flush()).await?
+    }
+
+    /// Sync the WAL, see `MiniLsm::sync`.
+    pub async fn sync(&self) -> Result<()> {
+        let storage = self.storage.clone();
+        tokio::task::spawn_blocking(move || storage.sync()).await?
+    }
+
+    /// Stop the background jobs and flush, see `MiniLsm::close`.
+    pub async fn close(&self) -> Result<()> {
+        let storage = self.storage.clone();
+        tokio::task::spawn_blocking(move || storage.close()).await?
+    }
+}
+
+fn owned_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
+    bound.map(Bytes::copy_from_slice)
+}
+
+fn as_slice_bound(bound: &Bound<Bytes>) -> Bound<&[u8]> {
+    bound.as_ref().map(|key| key.as_ref())
+}
+
+/// The entries of `AsyncLsmStorage::scan`, in key order.
+pub struct AsyncScan {
+    /// `None` while a chunk is being read, or once the scan is over.
+    iter: Option<FusedIterator<LsmIterator>>,
+    buffered: VecDeque<(Bytes, Bytes)>,
+}
+
+impl AsyncScan {
+    /// The next key and value, `None` once past the end of the range or after an error.
+    pub async fn next(&mut self) -> Result<Option<(Bytes, Bytes)>> {
+        if self.buffered.is_empty() {
+            let Some(mut iter) = self.iter.take() else {
+                return Ok(None);
+            };
+            let (iter, chunk) = tokio::task::spawn_blocking(move || {
+                let mut chunk = VecDeque::with_capacity(SCAN_CHUNK_SIZE);
+                while iter.is_valid() && chunk.len() < SCAN_CHUNK_SIZE {
+                    chunk.push_back((
+                        Bytes::copy_from_slice(iter.key()),
+                        Bytes::copy_from_slice(iter.value()),
+                    ));
+                    iter.next()?;
+                }
+                Ok::<_, anyhow::Error>((iter.is_valid().then_some(iter), chunk))
+            })
+            .await??;
+            self.iter = iter;
+            self.buffered = chunk;
+        }
+        Ok(self.buffered.pop_front())
+    }
+}
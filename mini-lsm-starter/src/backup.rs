@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::UNIX_EPOCH;
+use crate::fs::{default_file_system, FileSystem};
+use crate::logger::{db_log, DbLogger};
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::manifest::CURRENT_FILE_NAME;
+use crate::table::FileChecksum;
+
+/// The SSTs and the blob files, shared by all the backups holding them.
+const SHARED_DIR: &str = "shared";
+/// The other files of the backups, which are never the same in two of them.
+const PRIVATE_DIR: &str = "private";
+/// The metadata of the backups, one file for each.
+const META_DIR: &str = "meta";
+/// Where the checkpoint of the backup in progress is written.
+const STAGING_DIR: &str = "staging";
+
+/// A file of a backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// The name of the file in the database directory.
+    pub name: String,
+    /// The path of its copy, relative to the backup directory.
+    pub path: String,
+    pub checksum: FileChecksum,
+}
+
+/// A backup, which only exists once its metadata is written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub id: u64,
+    /// When the backup was taken, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub files: Vec<BackupFile>,
+}
+
+impl BackupInfo {
+    /// The size of the database the backup holds, counting the SSTs shared with other backups.
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|file| file.checksum.size).sum()
+    }
+}
+
+/// Incremental backups of databases to a directory. A backup is a checkpoint of the database with its SSTs
+/// copied to a directory shared by all the backups, under the id and checksum the manifest knows them by, so
+/// that the SSTs a previous backup holds are not copied again. The files of a backup not in its metadata, left by
+/// a failure or by deleted backups, are removed by the next backup or deletion, and when opening.
+pub struct BackupEngine {
+    fs: Arc<dyn FileSystem>,
+    path: PathBuf,
+    /// Held while backing up or deleting backups.
+    lock: Mutex<()>,
+    logger: DbLogger,
+}
+
+impl BackupEngine {
+    /// Open the backups in the directory `path` on the local disk, created if missing.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_file_system(path, default_file_system())
+    }
+
+    /// Same as `open`, with the backups in `fs`.
+    pub fn open_with_file_system(path: impl AsRef<Path>, fs: Arc<dyn FileSystem>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        for dir in [SHARED_DIR, PRIVATE_DIR, META_DIR, STAGING_DIR] {
+            fs.create_dir_all(&path.join(dir))?;
+        }
+        let engine = Self {
+            fs,
+            path,
+            lock: Mutex::new(()),
+            logger: DbLogger::default(),
+        };
+        engine.collect_garbage(&engine.backups()?)?;
+        Ok(engine)
+    }
+
+    /// Log through `logger` instead of the `log` facade, e.g. the one of the database backed up.
+    pub fn with_logger(mut self, logger: DbLogger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The backups, from the oldest to the newest.
+    pub fn backups(&self) -> Result<Vec<BackupInfo>> {
+        let mut backups = Vec::new();
+        for name in self.fs.list_dir(&self.path.join(META_DIR))? {
+            if name.parse::<u64>().is_err() {
+                continue;
+            }
+            let path = self.path.join(META_DIR).join(&name);
+            let json = self
+                .fs
+                .read(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            backups.push(serde_json::from_slice::<BackupInfo>(&json)?);
+        }
+        backups.sort_by_key(|backup| backup.id);
+        Ok(backups)
+    }
+
+    /// The backup `id`.
+    pub fn backup(&self, id: u64) -> Result<BackupInfo> {
+        match self.backups()?.into_iter().find(|backup| backup.id == id) {
+            Some(backup) => Ok(backup),
+            None => bail!("no backup {} in {}", id, self.path.display()),
+        }
+    }
+
+    /// Back up `db`, copying the SSTs and blob files no other backup holds. The memtables are not flushed, their
+    /// writes are in the WALs, which are copied, unless the WAL is disabled.
+    pub(crate) fn create_backup(&self, db: &LsmStorageInner) -> Result<BackupInfo> {
+        let _lock = self.lock.lock();
+        let backups = self.backups()?;
+        self.collect_garbage(&backups)?;
+        let id = backups.last().map_or(1, |backup| backup.id + 1);
+        let shared_files = self
+            .fs
+            .list_dir(&self.path.join(SHARED_DIR))?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let staging = self.path.join(STAGING_DIR);
+        let mut files = Vec::new();
+        let mut copied_size = 0;
+        db.write_checkpoint(&self.fs, &staging, |name, file_checksum| {
+            let file_path = db.path.join(name);
+            let read = || {
+                db.fs
+                    .read(&file_path)
+                    .with_context(|| format!("failed to read {}", file_path.display()))
+            };
+            let (checksum, mut data) = match file_checksum {
+                Some(checksum) => (checksum, None),
+                None => {
+                    let data = read()?;
+                    (FileChecksum::of_data(&data), Some(data))
+                }
+            };
+            let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+            let shared_name = format!(
+                "{}_{:08x}_{}.{}",
+                stem, checksum.crc32, checksum.size, extension
+            );
+            if !shared_files.contains(&shared_name) {
+                let data = match data.take() {
+                    Some(data) => data,
+                    None => read()?,
+                };
+                if FileChecksum::of_data(&data) != checksum {
+                    bail!(
+                        "{} does not match the checksum of the manifest",
+                        file_path.display()
+                    );
+                }
+                self.write_file(&self.path.join(SHARED_DIR).join(&shared_name), &data)?;
+                copied_size += checksum.size;
+            }
+            files.push(BackupFile {
+                name: name.to_string(),
+                path: format!("{}/{}", SHARED_DIR, shared_name),
+                checksum,
+            });
+            Ok(())
+        })?;
+        // the rest of the checkpoint belongs to this backup only
+        for name in self.fs.list_dir(&staging)? {
+            let private_name = format!("{:05}_{}", id, name);
+            let checksum = FileChecksum::of_file(self.fs.as_ref(), &staging.join(&name))?;
+            self.fs.rename(
+                &staging.join(&name),
+                &self.path.join(PRIVATE_DIR).join(&private_name),
+            )?;
+            files.push(BackupFile {
+                name,
+                path: format!("{}/{}", PRIVATE_DIR, private_name),
+                checksum,
+            });
+        }
+        self.fs.sync_dir(&self.path.join(SHARED_DIR))?;
+        self.fs.sync_dir(&self.path.join(PRIVATE_DIR))?;
+
+        let backup = BackupInfo {
+            id,
+            timestamp: db
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            files,
+        };
+        let meta_path = self.path.join(META_DIR).join(id.to_string());
+        self.write_file(&meta_path, &serde_json::to_vec_pretty(&backup)?)?;
+        self.fs.sync_dir(&self.path.join(META_DIR))?;
+        db_log!(
+            db.options(),
+            Info,
+            "created backup {} of {} bytes, {} of them copied",
+            id,
+            backup.size(),
+            copied_size
+        );
+        Ok(backup)
+    }
+
+    /// Restore the backup `id` to the directory `target_dir`, which must be missing or empty, so that it opens as the
+    /// database backed up. Every file is checked against the checksum the backup recorded for it. `CURRENT` is
+    /// written last, and on a failure the files restored so far are removed, so that `target_dir` is never left
+    /// with a database missing some of its files.
+    pub fn restore_backup(&self, id: u64, target_dir: impl AsRef<Path>) -> Result<()> {
+        let target_dir = target_dir.as_ref();
+        if self.fs.exists(target_dir) && !self.fs.list_dir(target_dir)?.is_empty() {
+            bail!("{} is not empty", target_dir.display());
+        }
+        // the files of the backup are not deleted meanwhile
+        let _lock = self.lock.lock();
+        let backup = self.backup(id)?;
+        self.fs.create_dir_all(target_dir)?;
+        let mut files = backup.files.iter().collect::<Vec<_>>();
+        files.sort_by_key(|file| file.name == CURRENT_FILE_NAME);
+        let mut restored = Vec::new();
+        let result = files.iter().try_for_each(|file| {
+            let from = self.path.join(&file.path);
+            let data = self
+                .fs
+                .read(&from)
+                .with_context(|| format!("failed to read {}", from.display()))?;
+            if FileChecksum::of_data(&data) != file.checksum {
+                bail!(
+                    "{} does not match the checksum of backup {}",
+                    from.display(),
+                    id
+                );
+            }
+            let to = target_dir.join(&file.name);
+            restored.push(to.clone());
+            let mut target = self.fs.create_new(&to)?;
+            target.write_all(&data)?;
+            target.sync()?;
+            if file.name == CURRENT_FILE_NAME {
+                self.fs.sync_dir(target_dir)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            for path in restored.iter().rev() {
+                if let Err(e) = self.fs.remove_file(path) {
+                    db_log!(
+                        self.logger,
+                        Warn,
+                        "failed to remove {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            return Err(e.context(format!(
+                "failed to restore backup {} to {}",
+                id,
+                target_dir.display()
+            )));
+        }
+        db_log!(
+            self.logger,
+            Info,
+            "restored backup {} of {} files to {}",
+            id,
+            files.len(),
+            target_dir.display()
+        );
+        Ok(())
+    }
+
+    /// Delete the backup `id`, and the SSTs no other backup holds.
+    pub fn delete_backup(&self, id: u64) -> Result<()> {
+        let _lock = self.lock.lock();
+        self.backup(id)?;
+        self.fs
+            .remove_file(&self.path.join(META_DIR).join(id.to_string()))?;
+        self.fs.sync_dir(&self.path.join(META_DIR))?;
+        self.collect_garbage(&self.backups()?)
+    }
+
+    /// Delete the oldest backups, keeping the `num_backups_to_keep` newest ones, and return the ids of the deleted
+    /// ones.
+    pub fn purge_old_backups(&self, num_backups_to_keep: usize) -> Result<Vec<u64>> {
+        let _lock = self.lock.lock();
+        let backups = self.backups()?;
+        let num_to_delete = backups.len().saturating_sub(num_backups_to_keep);
+        let (deleted, kept) = backups.split_at(num_to_delete);
+        for backup in deleted {
+            self.fs
+                .remove_file(&self.path.join(META_DIR).join(backup.id.to_string()))?;
+        }
+        self.fs.sync_dir(&self.path.join(META_DIR))?;
+        self.collect_garbage(kept)?;
+        Ok(deleted.iter().map(|backup| backup.id).collect())
+    }
+
+    /// Remove the files none of `backups` holds.
+    fn collect_garbage(&self, backups: &[BackupInfo]) -> Result<()> {
+        let referenced = backups
+            .iter()
+            .flat_map(|backup| &backup.files)
+            .map(|file| file.path.as_str())
+            .collect::<HashSet<_>>();
+        for dir in [SHARED_DIR, PRIVATE_DIR, STAGING_DIR] {
+            for name in self.fs.list_dir(&self.path.join(dir))? {
+                if !referenced.contains(format!("{}/{}", dir, name).as_str()) {
+                    self.fs.remove_file(&self.path.join(dir).join(&name))?;
+                }
+            }
+        }
+        // metadata whose write was interrupted
+        for name in self.fs.list_dir(&self.path.join(META_DIR))? {
+            if name.ends_with(".tmp") {
+                self.fs.remove_file(&self.path.join(META_DIR).join(&name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `data` to `path` through a temporary file, so that `path` only ever has all of it.
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = self.fs.create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync()?;
+        self.fs.rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Restore the backup `backup_id` of the backups in `backup_dir` on the local disk to `target_dir`, see
+/// `BackupEngine::restore_backup`.
+pub fn restore_from_backup(
+    backup_dir: impl AsRef<Path>,
+    target_dir: impl AsRef<Path>,
+    backup_id: u64,
+) -> Result<()> {
+    BackupEngine::open(backup_dir)?.restore_backup(backup_id, target_dir)
+}
+
+impl MiniLsm {
+    pub fn create_backup(&self, engine: &BackupEngine) -> Result<BackupInfo> {
+        engine.create_backup(&self.inner)
+    }
+}
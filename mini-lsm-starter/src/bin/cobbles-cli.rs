@@ -0,0 +1,323 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use log::LevelFilter;
+use mini_lsm_starter::iterators::StorageIterator;
+use mini_lsm_starter::logger::StderrLog;
+use mini_lsm_starter::lsm_storage::{LsmStorageOptions, MiniLsm};
+use mini_lsm_starter::property::PROPERTIES;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const HELP: &str = "\
+open <path> [read-only | secondary <dir>]  open a database, closing the one open
+close                                      close the database
+get <key>                                  read a key
+put <key> <value>                          write a key
+del <key>                                  delete a key
+scan [<begin> [<end>]] [limit <n>]         read the keys from begin to end, both included
+levels                                     show the memtables and the SSTs of each level
+level_stats                                show the compaction statistics of each level
+properties                                 show every property
+property <name>                            show a property, e.g. cobblesdb.num-files-at-level0
+flush                                      flush the memtables
+compact                                    compact every SST into the bottom level
+refresh                                    catch up with the primary, for a secondary
+help                                       show this help
+quit                                       close the database and exit";
+
+/// An interactive shell over a database directory. Open it read-only to look into a database without writing to
+/// it, or as a secondary instance to follow a database another process has open.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The database to open at start
+    path: Option<PathBuf>,
+    /// Open the database read-only
+    #[arg(long)]
+    read_only: bool,
+    /// Open the database as a secondary instance keeping its own files in this directory, for a database another
+    /// process has open
+    #[arg(long, conflicts_with = "read_only")]
+    secondary: Option<PathBuf>,
+    /// Run these commands, then exit, instead of reading them from the terminal
+    #[arg(short = 'e', long = "execute")]
+    commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OpenMode {
+    ReadWrite,
+    ReadOnly,
+    Secondary(PathBuf),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Open {
+        path: PathBuf,
+        mode: OpenMode,
+    },
+    Close,
+    Get {
+        key: String,
+    },
+    Put {
+        key: String,
+        value: String,
+    },
+    Del {
+        key: String,
+    },
+    Scan {
+        begin: Option<String>,
+        end: Option<String>,
+        limit: Option<usize>,
+    },
+    Levels,
+    LevelStats,
+    Properties,
+    Property {
+        name: String,
+    },
+    Flush,
+    Compact,
+    Refresh,
+    Help,
+    Quit,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let command = match words.as_slice() {
+            ["open", path, mode @ ..] => Command::Open {
+                path: PathBuf::from(path),
+                mode: match mode {
+                    [] => OpenMode::ReadWrite,
+                    ["read-only"] => OpenMode::ReadOnly,
+                    ["secondary", dir] => OpenMode::Secondary(PathBuf::from(dir)),
+                    _ => bail!("usage: open <path> [read-only | secondary <dir>]"),
+                },
+            },
+            ["close"] => Command::Close,
+            ["get", key] => Command::Get {
+                key: key.to_string(),
+            },
+            ["put", key, value] => Command::Put {
+                key: key.to_string(),
+                value: value.to_string(),
+            },
+            ["del", key] => Command::Del {
+                key: key.to_string(),
+            },
+            ["scan", args @ ..] => {
+                let (bounds, limit) = match args {
+                    [bounds @ .., "limit", limit] => (bounds, Some(limit.parse()?)),
+                    bounds => (bounds, None),
+                };
+                match bounds {
+                    [] => Command::Scan {
+                        begin: None,
+                        end: None,
+                        limit,
+                    },
+                    [begin] => Command::Scan {
+                        begin: Some(begin.to_string()),
+                        end: None,
+                        limit,
+                    },
+                    [begin, end] => Command::Scan {
+                        begin: Some(begin.to_string()),
+                        end: Some(end.to_string()),
+                        limit,
+                    },
+                    _ => bail!("usage: scan [<begin> [<end>]] [limit <n>]"),
+                }
+            }
+            ["levels"] => Command::Levels,
+            ["level_stats"] => Command::LevelStats,
+            ["properties"] => Command::Properties,
+            ["property", name] => Command::Property {
+                name: name.to_string(),
+            },
+            ["flush"] => Command::Flush,
+            ["compact"] => Command::Compact,
+            ["refresh"] => Command::Refresh,
+            ["help"] => Command::Help,
+            ["quit"] | ["exit"] => Command::Quit,
+            _ => bail!("unknown command {:?}, see help", line.trim()),
+        };
+        Ok(command)
+    }
+}
+
+/// The options of the database at `path`: the ones it was last opened with, or the defaults with a WAL for a new
+/// one.
+fn options(path: &Path) -> Result<LsmStorageOptions> {
+    let mut options = if path.join("OPTIONS").exists() {
+        LsmStorageOptions::load_latest(path)?
+    } else {
+        LsmStorageOptions {
+            enable_wal: true,
+            ..LsmStorageOptions::default()
+        }
+    };
+    options.info_log = Some(Arc::new(StderrLog));
+    options.info_log_level = LevelFilter::Warn;
+    Ok(options)
+}
+
+struct Shell {
+    lsm: Option<(PathBuf, Arc<MiniLsm>)>,
+}
+
+impl Shell {
+    fn lsm(&self) -> Result<&MiniLsm> {
+        match &self.lsm {
+            Some((_, lsm)) => Ok(lsm),
+            None => bail!("no database is open, see open"),
+        }
+    }
+
+    fn prompt(&self) -> String {
+        match &self.lsm {
+            Some((path, _)) => format!("cobbles-cli {}> ", path.display()),
+            None => "cobbles-cli> ".to_string(),
+        }
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some((_, lsm)) = self.lsm.take() {
+            lsm.close()?;
+        }
+        Ok(())
+    }
+
+    /// Run a command, returning false once the shell should exit.
+    fn handle(&mut self, command: Command) -> Result<bool> {
+        match command {
+            Command::Open { path, mode } => {
+                self.close()?;
+                let options = options(&path)?;
+                let lsm = match &mode {
+                    OpenMode::ReadWrite => MiniLsm::open(&path, options)?,
+                    OpenMode::ReadOnly => MiniLsm::open_read_only(&path, options)?,
+                    OpenMode::Secondary(dir) => MiniLsm::open_as_secondary(&path, dir, options)?,
+                };
+                println!("opened {}", path.display());
+                self.lsm = Some((path, lsm));
+            }
+            Command::Close => self.close()?,
+            Command::Get { key } => match self.lsm()?.get(key.as_bytes())? {
+                Some(value) => println!("{}", value.escape_ascii()),
+                None => println!("(not found)"),
+            },
+            Command::Put { key, value } => self.lsm()?.put(key.as_bytes(), value.as_bytes())?,
+            Command::Del { key } => self.lsm()?.delete(key.as_bytes())?,
+            Command::Scan { begin, end, limit } => {
+                let lower = begin
+                    .as_ref()
+                    .map_or(Bound::Unbounded, |begin| Bound::Included(begin.as_bytes()));
+                let upper = end
+                    .as_ref()
+                    .map_or(Bound::Unbounded, |end| Bound::Included(end.as_bytes()));
+                let mut iter = self.lsm()?.scan(lower, upper)?;
+                let mut count = 0;
+                while iter.is_valid() && limit.is_none_or(|limit| count < limit) {
+                    println!(
+                        "{} => {}",
+                        iter.key().escape_ascii(),
+                        iter.value().escape_ascii()
+                    );
+                    count += 1;
+                    iter.next()?;
+                }
+                println!("({} keys)", count);
+            }
+            Command::Levels => print!("{}", self.lsm()?.dump_structure()),
+            Command::LevelStats => println!("{}", self.lsm()?.level_stats()),
+            Command::Properties => {
+                let lsm = self.lsm()?;
+                for name in PROPERTIES {
+                    if let Some(value) = lsm.get_property(name) {
+                        // the multi-line properties go below their name
+                        let separator = if value.trim_end().contains('\n') {
+                            "\n"
+                        } else {
+                            " "
+                        };
+                        println!("{}:{}{}", name, separator, value.trim_end());
+                    }
+                }
+            }
+            Command::Property { name } => match self.lsm()?.get_property(&name) {
+                Some(value) => println!("{}", value.trim_end()),
+                None => println!("no property {}", name),
+            },
+            Command::Flush => self.lsm()?.force_flush()?,
+            Command::Compact => self.lsm()?.force_full_compaction()?,
+            Command::Refresh => self.lsm()?.try_catch_up_with_primary()?,
+            Command::Help => println!("{}", HELP),
+            Command::Quit => {
+                self.close()?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Parse and run a line, printing the error it fails with.
+    fn run_line(&mut self, line: &str) -> bool {
+        match Command::parse(line).and_then(|command| self.handle(command)) {
+            Ok(keep_going) => keep_going,
+            Err(e) => {
+                println!("error: {:#}", e);
+                true
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut shell = Shell { lsm: None };
+    if let Some(path) = args.path {
+        let mode = match (args.read_only, args.secondary) {
+            (true, _) => OpenMode::ReadOnly,
+            (false, Some(dir)) => OpenMode::Secondary(dir),
+            (false, None) => OpenMode::ReadWrite,
+        };
+        shell.handle(Command::Open { path, mode })?;
+    }
+
+    if !args.commands.is_empty() {
+        for command in &args.commands {
+            if !shell.run_line(command) {
+                return Ok(());
+            }
+        }
+        return shell.close();
+    }
+
+    let mut editor = DefaultEditor::new()?;
+    println!("cobbles-cli, type help for the commands");
+    loop {
+        let line = match editor.readline(&shell.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        if !shell.run_line(&line) {
+            return Ok(());
+        }
+    }
+    shell.close()
+}
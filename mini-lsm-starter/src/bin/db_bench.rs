@@ -0,0 +1,382 @@
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use mini_lsm_starter::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use mini_lsm_starter::iterators::StorageIterator;
+use mini_lsm_starter::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use mini_lsm_starter::statistics::{HistogramData, Statistics};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Benchmark {
+    /// Write the keys in order
+    Fillseq,
+    /// Write the keys in random order
+    Fillrandom,
+    /// Read random keys
+    Readrandom,
+    /// Read random keys with `threads` readers while one more thread writes random keys
+    Readwhilewriting,
+    /// Seek to random keys and read the keys after them
+    Seekrandom,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum CompactionStrategy {
+    Simple,
+    Leveled,
+    #[value(alias = "universal")]
+    Tiered,
+    None,
+}
+
+/// Measure the throughput and the latencies of workloads, run one after the other on the same database.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long, default_value = "bench.db")]
+    path: PathBuf,
+    /// The workloads to run, in order
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "fillrandom,readrandom"
+    )]
+    benchmarks: Vec<Benchmark>,
+    /// Run on the database at `path` rather than a new one
+    #[arg(long)]
+    use_existing_db: bool,
+    /// The number of keys
+    #[arg(long, default_value_t = 100_000)]
+    num: u64,
+    /// The number of reads or seeks of each thread, `num` split across the threads if not set
+    #[arg(long)]
+    reads: Option<u64>,
+    #[arg(long, default_value_t = 1)]
+    threads: u64,
+    #[arg(long, default_value_t = 16)]
+    key_size: usize,
+    #[arg(long, default_value_t = 100)]
+    value_size: usize,
+    /// The keys written by each write batch
+    #[arg(long, default_value_t = 1)]
+    batch_size: u64,
+    /// The keys read after each seek
+    #[arg(long, default_value_t = 10)]
+    seek_nexts: u64,
+    #[arg(long, default_value_t = 301)]
+    seed: u64,
+    #[arg(long, default_value = "leveled")]
+    compaction: CompactionStrategy,
+    #[arg(long)]
+    enable_wal: bool,
+    #[arg(long, default_value_t = 4096)]
+    block_size: usize,
+    #[arg(long, default_value_t = 2 << 20)]
+    target_sst_size: usize,
+    #[arg(long, default_value_t = 3)]
+    num_memtable_limit: usize,
+    /// Options to set once the database is open, as `name=value` pairs, see `MiniLsm::set_options`
+    #[arg(long, value_delimiter = ',')]
+    options: Vec<String>,
+    /// Print the statistics of the database and its levels at the end
+    #[arg(long)]
+    statistics: bool,
+}
+
+/// The results of a thread running a workload.
+#[derive(Default)]
+struct ThreadStats {
+    ops: u64,
+    bytes: u64,
+    found: u64,
+    /// Latency of each operation, in nanoseconds.
+    latencies: HistogramData,
+}
+
+impl ThreadStats {
+    fn record(&mut self, started: Instant, ops: u64, bytes: u64) {
+        self.latencies.add(started.elapsed().as_nanos() as u64);
+        self.ops += ops;
+        self.bytes += bytes;
+    }
+
+    fn merge(&mut self, other: &ThreadStats) {
+        self.ops += other.ops;
+        self.bytes += other.bytes;
+        self.found += other.found;
+        self.latencies.merge(&other.latencies);
+    }
+}
+
+struct Bench {
+    args: Args,
+    lsm: Arc<MiniLsm>,
+    /// The values are slices of it, random bytes so that they do not compress away.
+    values: Vec<u8>,
+}
+
+impl Bench {
+    fn key(&self, i: u64) -> Vec<u8> {
+        format!("{:0width$}", i, width = self.args.key_size).into_bytes()
+    }
+
+    fn value(&self, rng: &mut StdRng) -> &[u8] {
+        let start = rng.gen_range(0..=self.values.len() - self.args.value_size);
+        &self.values[start..start + self.args.value_size]
+    }
+
+    fn reads_per_thread(&self) -> u64 {
+        self.args.reads.unwrap_or(self.args.num / self.args.threads)
+    }
+
+    /// Write `count` keys, the ones from `first` in order, or random ones.
+    fn write(
+        &self,
+        rng: &mut StdRng,
+        first: u64,
+        count: u64,
+        random: bool,
+        stop: Option<&AtomicBool>,
+    ) -> Result<ThreadStats> {
+        let mut stats = ThreadStats::default();
+        let mut i = 0;
+        while i < count && !stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+            let batch = (i..count.min(i + self.args.batch_size))
+                .map(|i| {
+                    let key = if random {
+                        self.key(rng.gen_range(0..self.args.num))
+                    } else {
+                        self.key(first + i)
+                    };
+                    (key, self.value(rng))
+                })
+                .collect::<Vec<_>>();
+            let records = batch
+                .iter()
+                .map(|(key, value)| WriteBatchRecord::Put(key.as_slice(), *value))
+                .collect::<Vec<_>>();
+            let bytes = batch
+                .iter()
+                .map(|(key, value)| (key.len() + value.len()) as u64)
+                .sum();
+            let started = Instant::now();
+            self.lsm.write_batch(&records)?;
+            stats.record(started, batch.len() as u64, bytes);
+            i += batch.len() as u64;
+        }
+        Ok(stats)
+    }
+
+    fn read(&self, rng: &mut StdRng, count: u64) -> Result<ThreadStats> {
+        let mut stats = ThreadStats::default();
+        for _ in 0..count {
+            let key = self.key(rng.gen_range(0..self.args.num));
+            let started = Instant::now();
+            let value = self.lsm.get(&key)?;
+            let bytes = value.as_ref().map_or(0, |value| value.len()) + key.len();
+            stats.record(started, 1, bytes as u64);
+            stats.found += value.is_some() as u64;
+        }
+        Ok(stats)
+    }
+
+    fn seek(&self, rng: &mut StdRng, count: u64) -> Result<ThreadStats> {
+        let mut stats = ThreadStats::default();
+        for _ in 0..count {
+            let key = self.key(rng.gen_range(0..self.args.num));
+            let started = Instant::now();
+            let mut iter = self.lsm.scan(Bound::Included(&key), Bound::Unbounded)?;
+            let mut bytes = 0;
+            if iter.is_valid() {
+                stats.found += 1;
+            }
+            for _ in 0..=self.args.seek_nexts {
+                if !iter.is_valid() {
+                    break;
+                }
+                bytes += (iter.key().len() + iter.value().len()) as u64;
+                iter.next()?;
+            }
+            stats.record(started, 1, bytes);
+        }
+        Ok(stats)
+    }
+
+    /// Run `benchmark` on every thread, returning the results of all of them, how long the slowest took, and the
+    /// writes done meanwhile by `readwhilewriting`.
+    fn run(&self, benchmark: Benchmark) -> Result<(ThreadStats, Duration, Option<u64>)> {
+        let threads = self.args.threads;
+        let per_thread = self.args.num / threads;
+        let stop = AtomicBool::new(false);
+        let started = Instant::now();
+        let (results, background_writes) = std::thread::scope(|scope| {
+            let workers = (0..threads)
+                .map(|t| {
+                    scope.spawn(move || {
+                        let mut rng = StdRng::seed_from_u64(self.args.seed + t);
+                        // the last thread writes the keys left by the division
+                        let count = if t == threads - 1 {
+                            self.args.num - per_thread * t
+                        } else {
+                            per_thread
+                        };
+                        match benchmark {
+                            Benchmark::Fillseq => {
+                                self.write(&mut rng, per_thread * t, count, false, None)
+                            }
+                            Benchmark::Fillrandom => self.write(&mut rng, 0, count, true, None),
+                            Benchmark::Readrandom | Benchmark::Readwhilewriting => {
+                                self.read(&mut rng, self.reads_per_thread())
+                            }
+                            Benchmark::Seekrandom => self.seek(&mut rng, self.reads_per_thread()),
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            let writer = (benchmark == Benchmark::Readwhilewriting).then(|| {
+                let stop = &stop;
+                scope.spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(self.args.seed + threads);
+                    self.write(&mut rng, 0, u64::MAX, true, Some(stop))
+                })
+            });
+            let results = workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect::<Result<Vec<_>>>()?;
+            // the writer stops once the readers are done, its writes are not part of the results
+            stop.store(true, Ordering::Relaxed);
+            let background_writes = writer
+                .map(|writer| writer.join().unwrap().map(|stats| stats.ops))
+                .transpose()?;
+            anyhow::Ok((results, background_writes))
+        })?;
+        let elapsed = started.elapsed();
+        let mut stats = ThreadStats::default();
+        for result in &results {
+            stats.merge(result);
+        }
+        Ok((stats, elapsed, background_writes))
+    }
+}
+
+fn report(
+    benchmark: Benchmark,
+    stats: &ThreadStats,
+    elapsed: Duration,
+    background_writes: Option<u64>,
+) {
+    let seconds = elapsed.as_secs_f64();
+    let name = format!("{:?}", benchmark).to_lowercase();
+    let mut line = format!(
+        "{:<16}: {:>11.3} micros/op {:>9.0} ops/sec {:>8.1} MB/s",
+        name,
+        seconds * 1e6 / stats.ops.max(1) as f64,
+        stats.ops as f64 / seconds,
+        stats.bytes as f64 / (1 << 20) as f64 / seconds
+    );
+    if matches!(
+        benchmark,
+        Benchmark::Readrandom | Benchmark::Readwhilewriting | Benchmark::Seekrandom
+    ) {
+        line += &format!(" ({} of {} found)", stats.found, stats.ops);
+    }
+    if let Some(writes) = background_writes {
+        line += &format!(" ({} writes meanwhile)", writes);
+    }
+    println!("{}", line);
+    let latencies = &stats.latencies;
+    println!(
+        "{:<16}  micros per op: avg {:.2} P50 {:.2} P95 {:.2} P99 {:.2} P99.9 {:.2} max {:.2}",
+        "",
+        latencies.average() / 1e3,
+        latencies.percentile(50.0) / 1e3,
+        latencies.percentile(95.0) / 1e3,
+        latencies.percentile(99.0) / 1e3,
+        latencies.percentile(99.9) / 1e3,
+        latencies.max as f64 / 1e3
+    );
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.threads == 0 || args.batch_size == 0 || args.num == 0 {
+        bail!("--threads, --batch-size and --num must be positive");
+    }
+    if !args.use_existing_db && args.path.exists() {
+        bail!(
+            "{} already exists, remove it or pass --use-existing-db",
+            args.path.display()
+        );
+    }
+    let compaction_options = match args.compaction {
+        CompactionStrategy::None => CompactionOptions::NoCompaction,
+        CompactionStrategy::Simple => CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+        }),
+        CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }),
+        CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 128,
+            level_size_multiplier: 2,
+        }),
+    };
+    let statistics = Arc::new(Statistics::new());
+    let mut builder = LsmStorageOptions::builder()
+        .block_size(args.block_size)
+        .target_sst_size(args.target_sst_size)
+        .num_memtable_limit(args.num_memtable_limit)
+        .compaction_options(compaction_options)
+        .enable_wal(args.enable_wal);
+    if args.statistics {
+        builder = builder.statistics(statistics.clone());
+    }
+    let lsm = MiniLsm::open(&args.path, builder.build()?)?;
+    let changes = args
+        .options
+        .iter()
+        .map(|option| match option.split_once('=') {
+            Some(change) => Ok(change),
+            None => bail!("option {} is not a name=value pair", option),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    lsm.set_options(&changes)?;
+
+    let mut values = vec![0; args.value_size.max(1 << 20)];
+    StdRng::seed_from_u64(args.seed).fill_bytes(&mut values);
+    println!(
+        "keys: {} bytes, values: {} bytes, entries: {}, threads: {}",
+        args.key_size, args.value_size, args.num, args.threads
+    );
+    let benchmarks = args.benchmarks.clone();
+    let bench = Bench { args, lsm, values };
+    for benchmark in benchmarks {
+        let (stats, elapsed, background_writes) = bench.run(benchmark)?;
+        report(benchmark, &stats, elapsed, background_writes);
+    }
+    if bench.args.statistics {
+        println!("{}", bench.lsm.level_stats());
+        print!("{}", statistics);
+    }
+    bench.lsm.close()?;
+    Ok(())
+}
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use mini_lsm_starter::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use mini_lsm_starter::fs::{
+    FaultInjectionFileSystem, FileSystem, InMemoryFileSystem, LocalFileSystem,
+};
+use mini_lsm_starter::iterators::StorageIterator;
+use mini_lsm_starter::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Debug, Clone, ValueEnum)]
+enum CompactionStrategy {
+    Simple,
+    Leveled,
+    Tiered,
+    None,
+}
+
+/// Run random operations from several threads, kill the database at a random point of each cycle through the
+/// fault-injection file system, reopen it and check what it recovered against what the threads wrote. Every
+/// synced write must survive, and every key must come back with its synced value or one of the values written
+/// after it.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Run on this directory, which must not exist, rather than in memory
+    #[arg(long)]
+    path: Option<PathBuf>,
+    /// The number of kill and reopen cycles, the database is closed cleanly at the end of the last one
+    #[arg(long, default_value_t = 20)]
+    cycles: u64,
+    #[arg(long, default_value_t = 4)]
+    threads: u64,
+    /// The number of keys, each of them written by one thread only
+    #[arg(long, default_value_t = 1000)]
+    keys: u64,
+    #[arg(long, default_value_t = 64)]
+    value_size: usize,
+    /// The longest a cycle runs before the database is killed
+    #[arg(long, default_value_t = 500)]
+    max_kill_delay_ms: u64,
+    /// Kill one cycle in this many by failing the writes to the files for a while before the crash, rather than
+    /// by crashing at once, 0 to never fail them
+    #[arg(long, default_value_t = 3)]
+    write_fault_one_in: u64,
+    /// The operations of a thread that sync the WAL, one in this many
+    #[arg(long, default_value_t = 20)]
+    sync_one_in: u64,
+    /// The operations of a thread that flush the memtables, one in this many
+    #[arg(long, default_value_t = 500)]
+    flush_one_in: u64,
+    /// The operations of a thread that compact L0 into L1 with `force_full_compaction`, one in this many. Only
+    /// with the simple compaction or none, under which L1 is the level below L0
+    #[arg(long, default_value_t = 5000)]
+    compact_one_in: u64,
+    /// The most keys written by a write batch
+    #[arg(long, default_value_t = 4)]
+    max_batch_size: u64,
+    /// The keys read by a scan
+    #[arg(long, default_value_t = 20)]
+    scan_length: u64,
+    #[arg(long, default_value_t = 301)]
+    seed: u64,
+    #[arg(long, default_value = "simple")]
+    compaction: CompactionStrategy,
+    #[arg(long, default_value_t = 4096)]
+    block_size: usize,
+    #[arg(long, default_value_t = 1 << 14)]
+    target_sst_size: usize,
+    #[arg(long, default_value_t = 3)]
+    num_memtable_limit: usize,
+}
+
+/// What the oracle knows of a key: the version synced last, then the ones written after it, in order. `None` is
+/// a deletion, or a key never written.
+#[derive(Debug, Clone, Default)]
+struct KeyState {
+    synced: Option<u64>,
+    unsynced: Vec<Option<u64>>,
+}
+
+impl KeyState {
+    fn latest(&self) -> Option<u64> {
+        *self.unsynced.last().unwrap_or(&self.synced)
+    }
+
+    /// Whether a crash may leave the key at `version`: a crash keeps any prefix of the writes not synced.
+    fn may_recover(&self, version: Option<u64>) -> bool {
+        self.synced == version || self.unsynced.contains(&version)
+    }
+}
+
+/// The key states of the keys a thread writes, the ones equal to its index modulo the number of threads.
+#[derive(Default)]
+struct Oracle {
+    keys: BTreeMap<u64, KeyState>,
+    /// The version of the thread's last write, increasing across the cycles.
+    version: u64,
+}
+
+/// How a cycle ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kill {
+    /// Lose the writes not synced at once.
+    Crash,
+    /// Fail the writes to the files after a random number of them, then crash.
+    WriteFault,
+    /// Close the database, losing nothing.
+    Close,
+}
+
+struct Stress {
+    args: Args,
+    fs: Arc<FaultInjectionFileSystem>,
+    path: PathBuf,
+    /// Set once the running cycle stops, for the threads to stop too. The reads done after it are not checked.
+    stopping: AtomicBool,
+    /// Set before the faults are injected: the operations failing after it do not fail the run.
+    faulty: AtomicBool,
+    ops: AtomicU64,
+}
+
+impl Stress {
+    fn key(&self, i: u64) -> Vec<u8> {
+        format!("key{:010}", i).into_bytes()
+    }
+
+    /// The value of `version` of key `i`, naming both so that a value read back under the wrong key is caught.
+    fn value(&self, i: u64, version: u64) -> Vec<u8> {
+        let mut value = format!("{}:{}:", i, version).into_bytes();
+        value.resize(value.len().max(self.args.value_size), b'.');
+        value
+    }
+
+    /// The version a value read back for key `i` holds.
+    fn version(&self, i: u64, value: Option<&[u8]>) -> Result<Option<u64>> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let text = String::from_utf8_lossy(value);
+        let mut fields = text.split(':');
+        match (fields.next(), fields.next().map(str::parse::<u64>)) {
+            (Some(key), Some(Ok(version))) if key == i.to_string() => Ok(Some(version)),
+            _ => bail!("key {} holds a corrupted value {:?}", i, text),
+        }
+    }
+
+    fn options(&self) -> Result<LsmStorageOptions> {
+        let compaction_options = match self.args.compaction {
+            CompactionStrategy::None => CompactionOptions::NoCompaction,
+            CompactionStrategy::Simple => {
+                CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                    size_ratio_percent: 200,
+                    level0_file_num_compaction_trigger: 2,
+                    max_levels: 4,
+                })
+            }
+            CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+                num_tiers: 3,
+                max_size_amplification_percent: 200,
+                size_ratio: 1,
+                min_merge_width: 2,
+            }),
+            CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 4,
+                base_level_size_mb: 1,
+                level_size_multiplier: 2,
+            }),
+        };
+        LsmStorageOptions::builder()
+            .block_size(self.args.block_size)
+            .target_sst_size(self.args.target_sst_size)
+            .num_memtable_limit(self.args.num_memtable_limit)
+            .compaction_options(compaction_options)
+            .enable_wal(true)
+            .file_system(self.fs.clone())
+            .build()
+    }
+
+    /// Check the keys of a reopened database against the oracles, then make what was recovered their synced
+    /// state. After a clean close, every key must come back with its latest version.
+    fn verify(&self, lsm: &MiniLsm, oracles: &mut [Oracle], clean: bool) -> Result<(u64, u64)> {
+        let mut scanned = BTreeMap::new();
+        let mut iter = lsm.scan(Bound::Unbounded, Bound::Unbounded)?;
+        while iter.is_valid() {
+            scanned.insert(iter.key().to_vec(), iter.value().to_vec());
+            iter.next()?;
+        }
+        let (mut kept, mut lost) = (0, 0);
+        for oracle in oracles {
+            for (&i, state) in oracle.keys.iter_mut() {
+                let key = self.key(i);
+                let value = lsm.get(&key)?;
+                if value.as_deref() != scanned.remove(&key).as_deref() {
+                    bail!("key {} reads differently from a get and from a scan", i);
+                }
+                let version = self.version(i, value.as_deref())?;
+                if (clean && version != state.latest()) || !state.may_recover(version) {
+                    bail!(
+                        "key {} recovered at version {:?}, expected {:?} or one of {:?} written after it",
+                        i,
+                        version,
+                        state.synced,
+                        state.unsynced
+                    );
+                }
+                if !state.unsynced.is_empty() {
+                    if version == state.latest() {
+                        kept += 1;
+                    } else {
+                        lost += 1;
+                    }
+                }
+                *state = KeyState {
+                    synced: version,
+                    unsynced: Vec::new(),
+                };
+            }
+        }
+        if let Some(key) = scanned.keys().next() {
+            bail!(
+                "key {} was never written, but was recovered",
+                key.escape_ascii()
+            );
+        }
+        Ok((kept, lost))
+    }
+
+    /// Check a key read while the cycle runs: it is written only by this thread, so it must be at its latest
+    /// version, unless the cycle started stopping meanwhile.
+    fn check_read(&self, oracle: &Oracle, i: u64, value: Option<&[u8]>) -> Result<()> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let expected = oracle.keys.get(&i).and_then(KeyState::latest);
+        let version = self.version(i, value)?;
+        if version != expected {
+            bail!(
+                "key {} read at version {:?}, but was written at {:?}",
+                i,
+                version,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    /// A key of thread `t`.
+    fn random_key(&self, rng: &mut StdRng, t: u64) -> Option<u64> {
+        let threads = self.args.threads;
+        let count = (self.args.keys + threads - 1 - t) / threads;
+        (count > 0).then(|| rng.gen_range(0..count) * threads + t)
+    }
+
+    /// Run one operation of thread `t`, recording its writes in `oracle` before doing them so that a write
+    /// failing or cut by the crash counts as possibly done.
+    fn operation(
+        &self,
+        lsm: &MiniLsm,
+        oracle: &mut Oracle,
+        rng: &mut StdRng,
+        t: u64,
+    ) -> Result<()> {
+        let Some(i) = self.random_key(rng, t) else {
+            return Ok(());
+        };
+        let one_in = |rng: &mut StdRng, n: u64| n > 0 && rng.gen_range(0..n) == 0;
+        let full_compaction = matches!(
+            self.args.compaction,
+            CompactionStrategy::Simple | CompactionStrategy::None
+        );
+        if full_compaction && one_in(rng, self.args.compact_one_in) {
+            return lsm.force_full_compaction();
+        }
+        if one_in(rng, self.args.flush_one_in) {
+            return lsm.force_flush();
+        }
+        if one_in(rng, self.args.sync_one_in) {
+            lsm.sync()?;
+            // every write of the thread was done before the sync
+            for state in oracle.keys.values_mut() {
+                if !state.unsynced.is_empty() {
+                    state.synced = state.latest();
+                    state.unsynced.clear();
+                }
+            }
+            return Ok(());
+        }
+        let threads = self.args.threads;
+        match rng.gen_range(0..100) {
+            0..=34 => {
+                oracle.version += 1;
+                let version = oracle.version;
+                oracle
+                    .keys
+                    .entry(i)
+                    .or_default()
+                    .unsynced
+                    .push(Some(version));
+                lsm.put(&self.key(i), &self.value(i, version))
+            }
+            35..=44 => {
+                oracle.keys.entry(i).or_default().unsynced.push(None);
+                lsm.delete(&self.key(i))
+            }
+            45..=59 => {
+                let mut writes = BTreeMap::new();
+                for _ in 0..rng.gen_range(1..=self.args.max_batch_size) {
+                    if let Some(i) = self.random_key(rng, t) {
+                        oracle.version += 1;
+                        writes.insert(i, rng.gen_bool(0.8).then_some(oracle.version));
+                    }
+                }
+                let batch = writes
+                    .iter()
+                    .map(|(&i, &version)| match version {
+                        Some(version) => (self.key(i), Some(self.value(i, version))),
+                        None => (self.key(i), None),
+                    })
+                    .collect::<Vec<_>>();
+                let records = batch
+                    .iter()
+                    .map(|(key, value)| match value {
+                        Some(value) => WriteBatchRecord::Put(key.as_slice(), value.as_slice()),
+                        None => WriteBatchRecord::Del(key.as_slice()),
+                    })
+                    .collect::<Vec<_>>();
+                for (&i, &version) in &writes {
+                    oracle.keys.entry(i).or_default().unsynced.push(version);
+                }
+                lsm.write_batch(&records)
+            }
+            60..=84 => {
+                let value = lsm.get(&self.key(i))?;
+                self.check_read(oracle, i, value.as_deref())
+            }
+            _ => {
+                let end = i + self.args.scan_length;
+                let mut iter = lsm.scan(
+                    Bound::Included(&self.key(i)),
+                    Bound::Excluded(&self.key(end)),
+                )?;
+                let mut read = BTreeMap::new();
+                let mut last_key: Option<Vec<u8>> = None;
+                while iter.is_valid() {
+                    if last_key.as_deref().is_some_and(|last| last >= iter.key()) {
+                        bail!("scan from key {} is out of order", i);
+                    }
+                    last_key = Some(iter.key().to_vec());
+                    read.insert(iter.key().to_vec(), iter.value().to_vec());
+                    iter.next()?;
+                }
+                // the keys of the thread in the range, present or not, must be as it wrote them
+                for i in (i..end.min(self.args.keys)).filter(|i| i % threads == t) {
+                    let value = read.get(&self.key(i));
+                    self.check_read(oracle, i, value.map(Vec::as_slice))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Run the operations of thread `t` until the cycle stops. An operation failing before any fault is
+    /// injected fails the run.
+    fn worker(&self, lsm: &MiniLsm, oracle: &mut Oracle, cycle: u64, t: u64) -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(self.args.seed + cycle * self.args.threads + t);
+        while !self.stopping.load(Ordering::SeqCst) {
+            if let Err(e) = self.operation(lsm, oracle, &mut rng, t) {
+                if self.faulty.load(Ordering::SeqCst) {
+                    break;
+                }
+                return Err(e.context(format!("thread {} of cycle {}", t, cycle)));
+            }
+            self.ops.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Stop the cycle after a random delay, injecting the faults of `kill`.
+    fn killer(&self, rng: &mut StdRng, kill: Kill) -> Result<()> {
+        std::thread::sleep(Duration::from_millis(
+            rng.gen_range(0..=self.args.max_kill_delay_ms),
+        ));
+        if kill != Kill::Close {
+            self.faulty.store(true, Ordering::SeqCst);
+        }
+        if kill == Kill::WriteFault {
+            self.fs.fail_writes_after(rng.gen_range(0..50));
+            std::thread::sleep(Duration::from_millis(rng.gen_range(0..=50)));
+        }
+        self.stopping.store(true, Ordering::SeqCst);
+        if kill != Kill::Close {
+            self.fs.crash()?;
+        }
+        Ok(())
+    }
+
+    fn run(&self) -> Result<()> {
+        let mut oracles = (0..self.args.threads)
+            .map(|_| Oracle::default())
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(self.args.seed);
+        let mut lsm = MiniLsm::open(&self.path, self.options()?)?;
+        for cycle in 0..self.args.cycles {
+            let kill = if cycle == self.args.cycles - 1 {
+                Kill::Close
+            } else if self.args.write_fault_one_in > 0
+                && rng.gen_range(0..self.args.write_fault_one_in) == 0
+            {
+                Kill::WriteFault
+            } else {
+                Kill::Crash
+            };
+            self.stopping.store(false, Ordering::SeqCst);
+            self.faulty.store(false, Ordering::SeqCst);
+            self.ops.store(0, Ordering::Relaxed);
+            let started = Instant::now();
+            std::thread::scope(|scope| {
+                let lsm = &lsm;
+                let workers = oracles
+                    .iter_mut()
+                    .zip(0..)
+                    .map(|(oracle, t)| scope.spawn(move || self.worker(lsm, oracle, cycle, t)))
+                    .collect::<Vec<_>>();
+                let killer = scope.spawn(|| self.killer(&mut rng, kill));
+                let results = workers
+                    .into_iter()
+                    .map(|worker| worker.join().unwrap())
+                    .collect::<Vec<_>>();
+                killer.join().unwrap()?;
+                results.into_iter().collect::<Result<()>>()
+            })?;
+            let elapsed = started.elapsed();
+            if kill == Kill::Close {
+                lsm.close()?;
+            }
+            // the background jobs stop before the faults are cleared, so that none of them writes after the crash
+            drop(lsm);
+            self.fs.clear_faults();
+            lsm = MiniLsm::open(&self.path, self.options()?)
+                .with_context(|| format!("failed to reopen after cycle {}", cycle))?;
+            let (kept, lost) = self
+                .verify(&lsm, &mut oracles, kill == Kill::Close)
+                .with_context(|| format!("wrong recovery after cycle {} ({:?})", cycle, kill))?;
+            println!(
+                "cycle {:>3}: {:<10} after {:>5} ms, {:>7} ops, {} keys with unsynced writes kept, {} rolled back",
+                cycle,
+                format!("{:?}", kill).to_lowercase(),
+                elapsed.as_millis(),
+                self.ops.load(Ordering::Relaxed),
+                kept,
+                lost
+            );
+        }
+        let live = oracles
+            .iter()
+            .flat_map(|oracle| oracle.keys.values())
+            .filter(|state| state.synced.is_some())
+            .count();
+        println!("verified {} cycles, {} keys live", self.args.cycles, live);
+        lsm.close()
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.threads == 0 || args.keys == 0 || args.cycles == 0 || args.max_batch_size == 0 {
+        bail!("--threads, --keys, --cycles and --max-batch-size must be positive");
+    }
+    let (base, path): (Arc<dyn FileSystem>, _) = match &args.path {
+        Some(path) if path.exists() => bail!("{} already exists, remove it", path.display()),
+        Some(path) => (Arc::new(LocalFileSystem), path.clone()),
+        None => (Arc::new(InMemoryFileSystem::new()), PathBuf::from("/db")),
+    };
+    let stress = Stress {
+        args,
+        fs: Arc::new(FaultInjectionFileSystem::new(base)),
+        path,
+        stopping: AtomicBool::new(false),
+        faulty: AtomicBool::new(false),
+        ops: AtomicU64::new(0),
+    };
+    stress.run()
+}
@@ -6,12 +6,16 @@ use wrapper::mini_lsm_wrapper;
 use anyhow::Result;
 use bytes::Bytes;
 use clap::{Parser, ValueEnum};
+use log::LevelFilter;
 use mini_lsm_wrapper::compact::{
     CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
     TieredCompactionOptions,
 };
 use mini_lsm_wrapper::iterators::StorageIterator;
+use mini_lsm_wrapper::logger::StderrLog;
 use mini_lsm_wrapper::lsm_storage::{LsmStorageOptions, MiniLsm};
+use mini_lsm_wrapper::mvcc::txn::ConflictGranularity;
+use mini_lsm_wrapper::table::MetadataCaching;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -19,6 +23,7 @@ use std::sync::Arc;
 enum CompactionStrategy {
     Simple,
     Leveled,
+    #[value(alias = "universal")]
     Tiered,
     None,
 }
@@ -34,6 +39,16 @@ struct Args {
     enable_wal: bool,
     #[arg(long)]
     serializable: bool,
+    /// Rebuild the manifest from the SSTs before opening the database
+    #[arg(long)]
+    repair: bool,
+    /// Open with the latest state whose SSTs are all readable
+    #[arg(long)]
+    best_effort_recovery: bool,
+    /// Create the database from the LevelDB or RocksDB directory at this path before opening it
+    #[cfg(feature = "leveldb")]
+    #[arg(long)]
+    import_leveldb: Option<PathBuf>,
 }
 
 struct ReplHandler {
@@ -109,10 +124,36 @@ impl ReplHandler {
                     println!("invalid command");
                 }
             },
+            Command::Property { name } => match self.lsm.get_property(name) {
+                Some(value) => println!("{}", value.trim_end()),
+                None => println!("no property {}", name),
+            },
             Command::Dump => {
-                self.lsm.dump_structure();
+                print!("{}", self.lsm.dump_structure());
                 println!("dump success");
             }
+            Command::LevelStats => {
+                println!("{}", self.lsm.level_stats());
+            }
+            Command::PlanCompactions => {
+                let plan = self.lsm.plan_compactions(100);
+                for job in &plan {
+                    println!(
+                        "{:?} L{} input={}B output~{}B{} {:?}",
+                        job.priority,
+                        job.output_level,
+                        job.input_bytes,
+                        job.estimated_output_bytes,
+                        if job.is_trivial_move {
+                            " (trivial move)"
+                        } else {
+                            ""
+                        },
+                        job.task
+                    );
+                }
+                println!("{} compactions planned", plan.len());
+            }
             Command::Flush => {
                 self.lsm.force_flush()?;
                 println!("flush success");
@@ -150,7 +191,13 @@ enum Command {
         end: Option<String>,
     },
 
+    Property {
+        name: String,
+    },
+
     Dump,
+    LevelStats,
+    PlanCompactions,
     Flush,
     FullCompaction,
     Quit,
@@ -203,6 +250,13 @@ impl Command {
             )(i)
         };
 
+        let property = |i| {
+            map(
+                tuple((tag_no_case("property"), space1, string)),
+                |(_, _, name)| Command::Property { name },
+            )(i)
+        };
+
         let scan = |i| {
             map(
                 tuple((
@@ -223,7 +277,12 @@ impl Command {
                 del,
                 get,
                 scan,
+                property,
                 map(tag_no_case("dump"), |_| Command::Dump),
+                map(tag_no_case("level_stats"), |_| Command::LevelStats),
+                map(tag_no_case("plan_compactions"), |_| {
+                    Command::PlanCompactions
+                }),
                 map(tag_no_case("flush"), |_| Command::Flush),
                 map(tag_no_case("full_compaction"), |_| Command::FullCompaction),
                 map(tag_no_case("quit"), |_| Command::Quit),
@@ -314,40 +373,95 @@ impl ReplBuilder {
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let lsm = MiniLsm::open(
-        args.path,
-        LsmStorageOptions {
-            block_size: 4096,
-            target_sst_size: 2 << 20, // 2MB
-            num_memtable_limit: 3,
-            compaction_options: match args.compaction {
-                CompactionStrategy::None => CompactionOptions::NoCompaction,
-                CompactionStrategy::Simple => {
-                    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
-                        size_ratio_percent: 200,
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                    })
-                }
-                CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
-                    num_tiers: 3,
-                    max_size_amplification_percent: 200,
-                    size_ratio: 1,
-                    min_merge_width: 2,
-                }),
-                CompactionStrategy::Leveled => {
-                    CompactionOptions::Leveled(LeveledCompactionOptions {
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                        base_level_size_mb: 128,
-                        level_size_multiplier: 2,
-                    })
-                }
-            },
-            enable_wal: args.enable_wal,
-            serializable: args.serializable,
+    let options = LsmStorageOptions {
+        block_size: 4096,
+        target_sst_size: 2 << 20, // 2MB
+        num_memtable_limit: 3,
+        compaction_options: match args.compaction {
+            CompactionStrategy::None => CompactionOptions::NoCompaction,
+            CompactionStrategy::Simple => {
+                CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                    size_ratio_percent: 200,
+                    level0_file_num_compaction_trigger: 2,
+                    max_levels: 4,
+                })
+            }
+            CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+                num_tiers: 3,
+                max_size_amplification_percent: 200,
+                size_ratio: 1,
+                min_merge_width: 2,
+            }),
+            CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 4,
+                base_level_size_mb: 128,
+                level_size_multiplier: 2,
+            }),
         },
-    )?;
+        enable_wal: args.enable_wal,
+        serializable: args.serializable,
+        row_cache_capacity: 0,
+        metadata_caching: MetadataCaching::Table,
+        secondary_cache: None,
+        warm_block_cache_on_open: false,
+        block_cache: None,
+        write_buffer_manager: None,
+        periodic_compaction_seconds: 0,
+        tombstone_compaction_ratio: 0.0,
+        bottommost_recompaction_bytes: 0,
+        max_subcompactions: 1,
+        max_background_jobs: 2,
+        sst_partitioner: None,
+        compression_per_level: Vec::new(),
+        target_sst_size_per_level: Vec::new(),
+        min_blob_size: 0,
+        max_blob_space_amplification_percent: 0,
+        event_listeners: Vec::new(),
+        replication_sinks: Vec::new(),
+        rate_limiter: None,
+        statistics: None,
+        file_system: None,
+        clock: None,
+        max_manifest_file_size: 64 << 20,
+        best_effort_recovery: args.best_effort_recovery,
+        delete_obsolete_files_period_seconds: 6 * 60 * 60,
+        paranoid_checks: false,
+        hybrid_logical_clock: false,
+        txn_spill_threshold: 64 << 20,
+        txn_conflict_granularity: ConflictGranularity::Key,
+        txn_expiration_millis: 0,
+        txn_lock_timeout_millis: 0,
+        snapshot_warn_age_millis: 600_000,
+        slow_read_threshold_micros: 0,
+        info_log: Some(Arc::new(StderrLog)),
+        info_log_level: LevelFilter::Info,
+        memtable_stop_writes_trigger: 0,
+        level0_stop_writes_trigger: 0,
+        hard_pending_compaction_bytes_limit: 0,
+        cold_bottom_level: false,
+        inline_background_jobs: false,
+    };
+    if args.repair {
+        let summary = MiniLsm::repair(&args.path, &options)?;
+        println!(
+            "repaired {} SSTs, {} files moved to lost",
+            summary.sst_ids.len(),
+            summary.lost_files.len()
+        );
+    }
+    #[cfg(feature = "leveldb")]
+    if let Some(source) = &args.import_leveldb {
+        let summary = MiniLsm::import_leveldb(source, &args.path, options.clone())?;
+        println!(
+            "imported {} keys from {} SSTs and {} WAL batches into {} SSTs",
+            summary.num_keys, summary.num_source_ssts, summary.num_wal_batches, summary.num_ssts
+        );
+        for name in &summary.skipped_column_families {
+            println!("skipped the column family {}", name);
+        }
+    }
+    let lsm = MiniLsm::open(args.path, options)?;
 
     let repl = ReplBuilder::new()
         .app_name("mini-lsm-cli")
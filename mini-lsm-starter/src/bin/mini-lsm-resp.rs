@@ -0,0 +1,47 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::Parser;
+use mini_lsm_starter::asynk::AsyncLsmStorage;
+use mini_lsm_starter::logger::StderrLog;
+use mini_lsm_starter::lsm_storage::LsmStorageOptions;
+use mini_lsm_starter::resp::RespServer;
+use tokio::net::TcpListener;
+
+/// Serve a database to Redis clients, over the subset of the protocol of `RespServer`.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long, default_value = "lsm.db")]
+    path: PathBuf,
+    #[arg(long, default_value = "127.0.0.1:6379")]
+    addr: SocketAddr,
+    /// Open with the options the database was last opened with, instead of the defaults
+    #[arg(long)]
+    latest_options: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut options = if args.latest_options {
+        LsmStorageOptions::load_latest(&args.path)?
+    } else {
+        LsmStorageOptions {
+            enable_wal: true,
+            ..LsmStorageOptions::default()
+        }
+    };
+    options.info_log = Some(Arc::new(StderrLog));
+    let storage = Arc::new(AsyncLsmStorage::open(&args.path, options).await?);
+    let listener = TcpListener::bind(args.addr).await?;
+    println!("serving {} on {}", args.path.display(), args.addr);
+    tokio::select! {
+        result = Arc::new(RespServer::new(storage.clone())).serve(listener) => result?,
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    storage.close().await?;
+    Ok(())
+}
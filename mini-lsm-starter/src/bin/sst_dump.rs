@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+use mini_lsm_starter::fs::default_file_system;
+use mini_lsm_starter::table::{dump_sst, SstDumpOptions};
+
+/// Print the footer, the properties, the block metadata and the bloom filter of SSTs, and optionally their entries.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The SST files to dump
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Print every entry of the SST
+    #[arg(long)]
+    scan: bool,
+    /// Print the commit ts of the entries
+    #[arg(long)]
+    timestamps: bool,
+    /// Print the keys and values in hex
+    #[arg(long)]
+    hex: bool,
+    /// Check the checksum of every data block
+    #[arg(long)]
+    verify_checksums: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let options = SstDumpOptions {
+        entries: args.scan,
+        timestamps: args.timestamps,
+        hex: args.hex,
+        verify_checksums: args.verify_checksums,
+    };
+    let fs = default_file_system();
+    let mut out = std::io::stdout().lock();
+    for file in &args.files {
+        println!("{}:", file.display());
+        dump_sst(fs.as_ref(), file, &options, &mut out)?;
+    }
+    Ok(())
+}
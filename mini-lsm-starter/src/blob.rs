@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::fs::{FileSystem, ReadableFile, WritableFile};
+use crate::iterators::StorageIterator;
+use crate::logger::{db_log, DbLogger};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState};
+use crate::mem_table::MemTable;
+use crate::table::{SsTable, SsTableBuilder};
+
+/// The first byte of every value of an SST written with blob files, see `TableProperties::tagged_values`: the
+/// value follows it as it is.
+pub(crate) const VALUE_KIND_INLINE: u8 = 0;
+/// The first byte of a value of an SST that is a `BlobIndex` to where the value is.
+pub(crate) const VALUE_KIND_BLOB_INDEX: u8 = 1;
+/// The first byte of a value of an SST that is a `StreamIndex` to the chunks of a value written by
+/// `put_streaming`.
+pub(crate) const VALUE_KIND_STREAM: u8 = 2;
+/// Size of the chunks `put_streaming` cuts values into, each checksummed on its own.
+pub(crate) const STREAM_CHUNK_SIZE: usize = 1 << 20;
+
+/// Where a value written to a blob file is, stored in an SST instead of the value. Values at least
+/// `min_blob_size` long are moved to blob files when flushed, so that compactions only rewrite their indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobIndex {
+    pub file_id: usize,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BlobIndex {
+    const ENCODED_LEN: usize = 24;
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u64(self.size);
+    }
+
+    /// Bytes of the record in the blob file, the value followed by its checksum.
+    pub fn record_size(&self) -> u64 {
+        self.size + 4
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN {
+            bail!("blob index of {} bytes", buf.len());
+        }
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            size: buf.get_u64(),
+        })
+    }
+}
+
+/// Where a value written by `put_streaming` is: records of `chunk_size` bytes, but for the last one, one after the
+/// other from `offset`, so that it is read a chunk at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamIndex {
+    pub file_id: usize,
+    pub offset: u64,
+    pub size: u64,
+    pub chunk_size: u64,
+}
+
+impl StreamIndex {
+    const ENCODED_LEN: usize = 32;
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u64(self.size);
+        buf.put_u64(self.chunk_size);
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN {
+            bail!("stream index of {} bytes", buf.len());
+        }
+        let index = Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            size: buf.get_u64(),
+            chunk_size: buf.get_u64(),
+        };
+        if index.chunk_size == 0 {
+            bail!("stream index with empty chunks");
+        }
+        Ok(index)
+    }
+
+    pub fn num_chunks(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size)
+    }
+
+    /// Where the chunk `i` is.
+    pub fn chunk(&self, i: u64) -> BlobIndex {
+        BlobIndex {
+            file_id: self.file_id,
+            offset: self.offset + i * (self.chunk_size + 4),
+            size: self.chunk_size.min(self.size - i * self.chunk_size),
+        }
+    }
+
+    /// Bytes of the records of the chunks in the blob file.
+    pub fn record_size(&self) -> u64 {
+        self.size + 4 * self.num_chunks()
+    }
+}
+
+/// A value an SST stores the location of instead of the value, as the SST stores it: the kind of the value and
+/// where it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobReference {
+    Index(BlobIndex),
+    Stream(StreamIndex),
+}
+
+impl BlobReference {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Index(index) => {
+                buf.put_u8(VALUE_KIND_BLOB_INDEX);
+                index.encode(buf);
+            }
+            Self::Stream(index) => {
+                buf.put_u8(VALUE_KIND_STREAM);
+                index.encode(buf);
+            }
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        match buf.first() {
+            Some(&VALUE_KIND_BLOB_INDEX) => Ok(Self::Index(BlobIndex::decode(&buf[1..])?)),
+            Some(&VALUE_KIND_STREAM) => Ok(Self::Stream(StreamIndex::decode(&buf[1..])?)),
+            kind => bail!("not a blob reference: value kind {:?}", kind),
+        }
+    }
+
+    pub fn file_id(&self) -> usize {
+        match self {
+            Self::Index(index) => index.file_id,
+            Self::Stream(index) => index.file_id,
+        }
+    }
+
+    /// Bytes of the records of the value in the blob file.
+    pub fn record_size(&self) -> u64 {
+        match self {
+            Self::Index(index) => index.record_size(),
+            Self::Stream(index) => index.record_size(),
+        }
+    }
+}
+
+/// A blob file opened for reads. The SSTs pointing into it hold it, so that the readers of a state it is not part
+/// of any more can still read it once it is deleted.
+pub struct BlobFile {
+    id: usize,
+    file: Box<dyn ReadableFile>,
+    size: u64,
+}
+
+impl BlobFile {
+    pub fn open(fs: &dyn FileSystem, path: &Path, id: usize) -> Result<Self> {
+        let file = fs
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let size = file.size()?;
+        Ok(Self { id, file, size })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Read the value `index` points to, checking its checksum.
+    pub fn read(&self, index: &BlobIndex) -> Result<Bytes> {
+        if index.file_id != self.id {
+            bail!(
+                "blob index into {}.blob read from {}.blob",
+                index.file_id,
+                self.id
+            );
+        }
+        if index.offset + index.record_size() > self.size {
+            bail!(
+                "blob at offset {} of {} bytes is past the end of {}.blob",
+                index.offset,
+                index.size,
+                self.id
+            );
+        }
+        let mut data = vec![0; index.record_size() as usize];
+        self.file.read_at(&mut data, index.offset)?;
+        let checksum = (&data[index.size as usize..]).get_u32();
+        data.truncate(index.size as usize);
+        if checksum != crc32fast::hash(&data) {
+            bail!(
+                "checksum mismatched for the blob at offset {} of {}.blob",
+                index.offset,
+                self.id
+            );
+        }
+        Ok(data.into())
+    }
+
+    /// Read the whole value `reference` points to, the chunks of a stream put back together.
+    pub fn read_reference(&self, reference: &BlobReference) -> Result<Bytes> {
+        match reference {
+            BlobReference::Index(index) => self.read(index),
+            BlobReference::Stream(index) => {
+                let mut value = Vec::with_capacity(index.size as usize);
+                for i in 0..index.num_chunks() {
+                    value.extend_from_slice(&self.read(&index.chunk(i))?);
+                }
+                Ok(value.into())
+            }
+        }
+    }
+}
+
+/// Reads a value a chunk at a time, see `get_streaming`. Every chunk of a value written by `put_streaming` is
+/// checked against its checksum when read, the other values are in memory already.
+pub struct ValueReader {
+    stream: Option<(Arc<BlobFile>, StreamIndex)>,
+    next_chunk: u64,
+    chunk: Bytes,
+    size: u64,
+}
+
+impl ValueReader {
+    pub(crate) fn from_bytes(value: Bytes) -> Self {
+        Self {
+            stream: None,
+            next_chunk: 0,
+            size: value.len() as u64,
+            chunk: value,
+        }
+    }
+
+    pub(crate) fn from_stream(blob_file: Arc<BlobFile>, index: StreamIndex) -> Self {
+        Self {
+            size: index.size,
+            stream: Some((blob_file, index)),
+            next_chunk: 0,
+            chunk: Bytes::new(),
+        }
+    }
+
+    /// Size of the whole value in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.chunk.is_empty() {
+            if let Some((blob_file, index)) = &self.stream {
+                if self.next_chunk < index.num_chunks() {
+                    self.chunk = blob_file
+                        .read(&index.chunk(self.next_chunk))
+                        .map_err(std::io::Error::other)?;
+                    self.next_chunk += 1;
+                }
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk[..len]);
+        self.chunk.advance(len);
+        Ok(len)
+    }
+}
+
+/// Writes the large values of a flush to a new blob file, each followed by its checksum.
+pub(crate) struct BlobFileBuilder {
+    id: usize,
+    file: Box<dyn WritableFile>,
+    size: u64,
+    logger: DbLogger,
+}
+
+impl BlobFileBuilder {
+    pub(crate) fn create(
+        fs: &dyn FileSystem,
+        path: PathBuf,
+        id: usize,
+        logger: DbLogger,
+    ) -> Result<Self> {
+        let file = fs
+            .create_new(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Self {
+            id,
+            file,
+            size: 0,
+            logger,
+        })
+    }
+
+    pub(crate) fn add(&mut self, value: &[u8]) -> Result<BlobIndex> {
+        let mut record = Vec::with_capacity(value.len() + 4);
+        record.put_slice(value);
+        record.put_u32(crc32fast::hash(value));
+        self.file.write_all(&record)?;
+        let index = BlobIndex {
+            file_id: self.id,
+            offset: self.size,
+            size: value.len() as u64,
+        };
+        self.size += record.len() as u64;
+        Ok(index)
+    }
+
+    /// Add the value `reader` reads until its end, in records of up to `chunk_size` bytes.
+    pub(crate) fn add_stream(
+        &mut self,
+        reader: &mut impl Read,
+        chunk_size: usize,
+    ) -> Result<StreamIndex> {
+        let offset = self.size;
+        let mut size = 0;
+        let mut chunk = vec![0; chunk_size];
+        loop {
+            let mut len = 0;
+            while len < chunk_size {
+                match reader.read(&mut chunk[len..]) {
+                    Ok(0) => break,
+                    Ok(read) => len += read,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if len == 0 {
+                break;
+            }
+            self.add(&chunk[..len])?;
+            size += len as u64;
+            if len < chunk_size {
+                break;
+            }
+        }
+        Ok(StreamIndex {
+            file_id: self.id,
+            offset,
+            size,
+            chunk_size: chunk_size as u64,
+        })
+    }
+
+    /// Copy the value `reference` points to into this file, a chunk at a time for a stream, and return where the
+    /// copy is. Used by compactions to move the values out of a blob file being garbage collected.
+    pub(crate) fn relocate(
+        &mut self,
+        table: &SsTable,
+        reference: &BlobReference,
+    ) -> Result<BlobReference> {
+        match reference {
+            BlobReference::Index(_) => Ok(BlobReference::Index(
+                self.add(&table.read_blob_reference(reference)?)?,
+            )),
+            BlobReference::Stream(index) => {
+                let mut reader = table.stream_reader(index)?;
+                let copy = self.add_stream(&mut reader, index.chunk_size as usize)?;
+                if copy.size != index.size {
+                    bail!(
+                        "read {} bytes of a stream of {} bytes from {}.blob",
+                        copy.size,
+                        index.size,
+                        index.file_id
+                    );
+                }
+                Ok(BlobReference::Stream(copy))
+            }
+        }
+    }
+
+    /// Make the file durable, before the SST pointing into it is written.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.sync()?;
+        db_log!(
+            self.logger,
+            Info,
+            "wrote {}.blob with size={}",
+            self.id,
+            self.size
+        );
+        Ok(())
+    }
+}
+
+/// How much of a blob file the SSTs of a state still point to, see `LsmStorageState::blob_file_usage`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlobFileUsage {
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Bytes of the records the SSTs point to, the rest of the file is garbage.
+    pub live_bytes: u64,
+}
+
+impl BlobFileUsage {
+    /// The size of the file as a percentage of its live bytes.
+    pub fn space_amplification_percent(&self) -> u64 {
+        self.size * 100 / self.live_bytes.max(1)
+    }
+}
+
+impl LsmStorageState {
+    /// The usage of every blob file the SSTs point into, by blob file id. The versions dropped by compactions are
+    /// the garbage of the blob files.
+    pub fn blob_file_usage(&self) -> BTreeMap<usize, BlobFileUsage> {
+        let mut usage = BTreeMap::<usize, BlobFileUsage>::new();
+        for sst_id in self.sst_ids() {
+            let sst = &self.sstables[sst_id];
+            for blob_file in sst.blob_files() {
+                usage.entry(blob_file.id()).or_default().size = blob_file.size();
+            }
+            for (blob_file_id, live_bytes) in &sst.properties().blob_files {
+                usage.entry(*blob_file_id).or_default().live_bytes += live_bytes;
+            }
+        }
+        usage
+    }
+
+    /// The blob files larger than `max_space_amplification_percent` of their live bytes, the most wasteful first.
+    /// None with 0.
+    pub(crate) fn wasteful_blob_files(&self, max_space_amplification_percent: u64) -> Vec<usize> {
+        if max_space_amplification_percent == 0 {
+            return Vec::new();
+        }
+        let mut wasteful = self
+            .blob_file_usage()
+            .into_iter()
+            .map(|(id, usage)| (id, usage.space_amplification_percent()))
+            .filter(|(_, percent)| *percent > max_space_amplification_percent)
+            .collect::<Vec<_>>();
+        wasteful.sort_by_key(|(id, percent)| (std::cmp::Reverse(*percent), *id));
+        wasteful.into_iter().map(|(id, _)| id).collect()
+    }
+}
+
+impl LsmStorageInner {
+    /// Create a blob file taking the next SST id, for a flush or a compaction to write to.
+    pub(crate) fn create_blob_file(&self) -> Result<BlobFileBuilder> {
+        let id = self.next_sst_id();
+        BlobFileBuilder::create(
+            self.fs.as_ref(),
+            self.path_of_blob(id),
+            id,
+            DbLogger::new(&self.options()),
+        )
+    }
+
+    /// Add the entries of `memtable` to `builder`, created `with_blob_indexes`, writing the values at least
+    /// `min_blob_size` long to a new blob file instead. Called by flushes, with the state lock, so that the blob
+    /// file is not deleted as obsolete before the SST pointing into it is recorded.
+    pub(crate) fn flush_to_blob_file(
+        &self,
+        memtable: &MemTable,
+        builder: &mut SsTableBuilder,
+        min_blob_size: usize,
+    ) -> Result<()> {
+        let mut blob_file: Option<BlobFileBuilder> = None;
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            let value = iter.value();
+            if value.len() >= min_blob_size {
+                let blob_file = match &mut blob_file {
+                    Some(blob_file) => blob_file,
+                    None => blob_file.insert(self.create_blob_file()?),
+                };
+                let index = blob_file.add(value)?;
+                builder.add_blob_reference(iter.key(), &BlobReference::Index(index));
+            } else {
+                builder.add(iter.key(), value);
+            }
+            iter.next()?;
+        }
+        if let Some(blob_file) = blob_file {
+            blob_file.finish()?;
+        }
+        Ok(())
+    }
+
+    /// The blob files the SSTs of the column families point into, the dropped ones not purged yet and the ones being
+    /// written by `put_streaming` included.
+    pub(crate) fn live_blob_file_ids(&self) -> BTreeSet<usize> {
+        let mut live_blob_file_ids = self
+            .all_column_family_states()
+            .iter()
+            .flat_map(|state| state.blob_file_ids().collect::<Vec<_>>())
+            .collect::<BTreeSet<_>>();
+        for cf in self.dropped_column_families.lock().iter() {
+            live_blob_file_ids.extend(cf.state.blob_file_ids());
+        }
+        live_blob_file_ids.extend(self.pending_blob_files.lock().iter());
+        live_blob_file_ids
+    }
+
+    /// Delete the blob files in `path` that are not in `live_blob_file_ids`, returning their ids.
+    pub(crate) fn remove_obsolete_blob_files(
+        options: &LsmStorageOptions,
+        fs: &dyn FileSystem,
+        path: &Path,
+        live_blob_file_ids: &BTreeSet<usize>,
+    ) -> Result<Vec<usize>> {
+        let mut removed = Vec::new();
+        for file_name in fs.list_dir(path)? {
+            let Some(id) = file_name
+                .strip_suffix(".blob")
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if !live_blob_file_ids.contains(&id) {
+                db_log!(options, Info, "removing obsolete {}.blob", id);
+                fs.remove_file(&Self::path_of_blob_static(path, id))?;
+                removed.push(id);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Open the blob files `sst` points into and attach them to it.
+    pub(crate) fn open_blob_files(
+        fs: &dyn FileSystem,
+        path: &Path,
+        sst: SsTable,
+    ) -> Result<SsTable> {
+        if sst.properties().blob_files.is_empty() {
+            return Ok(sst);
+        }
+        let mut blob_files = HashMap::new();
+        for id in sst.properties().blob_files.keys() {
+            let blob_file = BlobFile::open(fs, &Self::path_of_blob_static(path, *id), *id)?;
+            blob_files.insert(*id, Arc::new(blob_file));
+        }
+        Ok(sst.with_blob_files(blob_files))
+    }
+}
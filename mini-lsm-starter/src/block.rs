@@ -2,12 +2,20 @@
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
 mod builder;
+mod cache;
 mod iterator;
+mod secondary_cache;
 
+use anyhow::{bail, Result};
 pub use builder::BlockBuilder;
 use bytes::BufMut;
 use bytes::Bytes;
+pub use cache::{
+    BlockCache, BlockCacheCounters, BlockCacheCounts, BlockCacheKey, BlockCacheStats, CachePriority,
+    CacheReservation, DEFAULT_NUM_SHARDS,
+};
 pub use iterator::BlockIterator;
+pub use secondary_cache::{SecondaryCache, SecondaryCacheStats};
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted key-value pairs.
 pub struct Block {
@@ -28,17 +36,52 @@ impl Block {
         result.into()
     }
 
-    /// Decode from the data layout, transform the input `data` to a single `Block`
-    pub fn decode(data: &[u8]) -> Self {
-        let entry_size = data[data.len() - 1];
-        Self {
-            // offset is u16 spillit to 2 u8 , so we need to multiply by 2
-            // -1 for element number at the end of the data
-            data: data[..data.len() - entry_size as usize * 2 - 1].to_vec(),
-            offsets: data[data.len() - entry_size as usize * 2 - 1..data.len() - 1]
-                .chunks(2)
-                .map(|x| u16::from_be_bytes([x[0], x[1]]))
-                .collect(),
+    /// Decode from the data layout, transform the input `data` to a single `Block`. Every entry is checked to lie
+    /// within the block, so that iterating on it cannot read out of bounds.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let Some((&num_entries, data)) = data.split_last() else {
+            bail!("empty block");
+        };
+        let num_entries = num_entries as usize;
+        if num_entries == 0 {
+            bail!("block has no entry");
         }
+        let Some(data_len) = data.len().checked_sub(num_entries * 2) else {
+            bail!(
+                "block of {} bytes too short for {} entries",
+                data.len(),
+                num_entries
+            );
+        };
+        // offset is u16 spillit to 2 u8
+        let (data, offsets) = data.split_at(data_len);
+        let offsets = offsets
+            .chunks(2)
+            .map(|x| u16::from_be_bytes([x[0], x[1]]))
+            .collect::<Vec<_>>();
+        let entry_field = |start: usize, len: usize| -> Result<usize> {
+            match data.get(start..start + len) {
+                Some(_) => Ok(start + len),
+                None => bail!("block entry at offset {} out of bounds", start),
+            }
+        };
+        for (idx, &offset) in offsets.iter().enumerate() {
+            let offset = offset as usize;
+            if idx == 0 && offset != 0 || idx > 0 && offset <= offsets[idx - 1] as usize {
+                bail!("block entry {} at offset {} is out of order", idx, offset);
+            }
+            // the key and its timestamp, then the value, each after its u16 length
+            let key_start = entry_field(offset, 2)?;
+            let key_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
+            let value_len_start = entry_field(key_start, key_len + 8)?;
+            let value_start = entry_field(value_len_start, 2)?;
+            let value_len =
+                u16::from_be_bytes([data[value_len_start], data[value_len_start + 1]]) as usize;
+            entry_field(value_start, value_len)?;
+        }
+        Ok(Self {
+            data: data.to_vec(),
+            offsets,
+        })
     }
 }
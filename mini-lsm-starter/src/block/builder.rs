@@ -11,7 +11,7 @@ pub struct BlockBuilder {
     /// | 0 | Entry1_len |
     offsets: Vec<u16>,
     /// All serialized key-value pairs in the block.
-    /// |key_len(2)|Key|value_len(2)|value|
+    /// |key_len(2)|Key|ts(8)|value_len(2)|value|
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
@@ -39,7 +39,7 @@ impl BlockBuilder {
             // offsets is u16 and data is u8 so we need to multiply by 2,last U16 is element number at the end of the data
             let data_size = self.data.len() + self.offsets.len() * U16_SIZE + U16_SIZE;
             // 3 U16 for key_len, value_len, offset
-            let entry_size = key.len() + value.len() + U16_SIZE * 3;
+            let entry_size = key.raw_len() + value.len() + U16_SIZE * 3;
             // println!("data_size: {}, entry_size: {}", data_size, entry_size);
             if data_size + entry_size > self.block_size {
                 return false;
@@ -47,8 +47,9 @@ impl BlockBuilder {
         }
 
         self.offsets.push(self.data.len() as u16);
-        self.data.put_u16(key.len() as u16);
-        self.data.put(key.raw_ref());
+        self.data.put_u16(key.key_len() as u16);
+        self.data.put(key.key_ref());
+        self.data.put_u64(key.ts());
         self.data.put_u16(value.len() as u16);
         self.data.put(value);
         true
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use super::secondary_cache::{SecondaryCache, SecondaryCacheStats};
+use super::Block;
+use crate::table::bloom::Bloom;
+
+/// Blocks are cached by (table id, block_idx), see `BlockCache::new_table_id`.
+pub type BlockCacheKey = (usize, usize);
+
+/// Default number of shards for `BlockCache::new`.
+pub const DEFAULT_NUM_SHARDS: usize = 16;
+
+/// A block cache split into independently locked shards, so that concurrent readers missing on different blocks do
+/// not contend on the same cache. A block always maps to the same shard by the hash of its key.
+///
+/// The capacity is in bytes. Besides data blocks, the cache can hold SST bloom filters at a high priority and be
+/// charged for metadata pinned by the SSTs, so that all of it is accounted against the same budget.
+pub struct BlockCache {
+    shards: Vec<Shard>,
+    capacity: AtomicU64,
+    counters: Arc<BlockCacheCounters>,
+    /// Where evicted blocks go, checked on a miss before reading the SST.
+    secondary: Option<SecondaryCache>,
+    next_table_id: AtomicUsize,
+}
+
+impl std::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("capacity", &self.capacity())
+            .field("num_shards", &self.shards.len())
+            .field("secondary", &self.secondary.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Entries are evicted bottom priority first, then low, then high.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CachePriority {
+    /// SST metadata.
+    High,
+    /// Data blocks above the bottommost level.
+    #[default]
+    Low,
+    /// Data blocks of the bottommost level, which holds most of the data and is mostly read by scans and
+    /// compactions.
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum EntryKey {
+    Block(usize, usize),
+    Filter(usize),
+}
+
+#[derive(Clone)]
+enum CacheValue {
+    Block(Arc<Block>),
+    Filter(Arc<Bloom>),
+}
+
+impl CacheValue {
+    fn charge(&self) -> u64 {
+        match self {
+            Self::Block(block) => (block.data.len() + block.offsets.len() * 2) as u64,
+            Self::Filter(bloom) => bloom.filter.len() as u64,
+        }
+    }
+}
+
+struct Entry {
+    value: CacheValue,
+    priority: CachePriority,
+    /// Position in the recency list of the entry's priority.
+    tick: u64,
+    /// Counters of the table the entry was read for.
+    owner: Option<Arc<BlockCacheCounters>>,
+}
+
+struct Shard {
+    capacity: AtomicU64,
+    state: Mutex<ShardState>,
+}
+
+#[derive(Default)]
+struct ShardState {
+    entries: HashMap<EntryKey, Entry>,
+    /// Least recently used first, for each priority.
+    high: BTreeMap<u64, EntryKey>,
+    low: BTreeMap<u64, EntryKey>,
+    bottom: BTreeMap<u64, EntryKey>,
+    next_tick: u64,
+    /// Bytes of the cached entries and of the pinned charges.
+    usage: u64,
+    pinned_usage: u64,
+}
+
+impl ShardState {
+    fn lru(&mut self, priority: CachePriority) -> &mut BTreeMap<u64, EntryKey> {
+        match priority {
+            CachePriority::High => &mut self.high,
+            CachePriority::Low => &mut self.low,
+            CachePriority::Bottom => &mut self.bottom,
+        }
+    }
+
+    fn touch(&mut self, key: &EntryKey) -> Option<CacheValue> {
+        let tick = self.next_tick;
+        let entry = self.entries.get_mut(key)?;
+        let (old_tick, priority) = (entry.tick, entry.priority);
+        entry.tick = tick;
+        let value = entry.value.clone();
+        self.next_tick += 1;
+        let lru = self.lru(priority);
+        lru.remove(&old_tick);
+        lru.insert(tick, *key);
+        Some(value)
+    }
+
+    fn remove(&mut self, key: &EntryKey) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.lru(entry.priority).remove(&entry.tick);
+        self.usage -= entry.value.charge();
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: EntryKey, entry: Entry) {
+        self.remove(&key);
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.usage += entry.value.charge();
+        self.lru(entry.priority).insert(tick, key);
+        self.entries.insert(key, Entry { tick, ..entry });
+    }
+
+    /// Evict least recently used entries, lowest priority first, until the shard fits into `capacity`.
+    fn evict(&mut self, capacity: u64) -> Vec<(EntryKey, Entry)> {
+        let mut evicted = Vec::new();
+        while self.usage > capacity {
+            let Some((_, key)) = self
+                .bottom
+                .pop_first()
+                .or_else(|| self.low.pop_first())
+                .or_else(|| self.high.pop_first())
+            else {
+                // only pinned charges are left
+                break;
+            };
+            let entry = self.entries.remove(&key).unwrap();
+            self.usage -= entry.value.charge();
+            evicted.push((key, entry));
+        }
+        evicted
+    }
+}
+
+/// A charge for memory held outside of the cache, released when dropped.
+pub struct CacheReservation {
+    cache: Arc<BlockCache>,
+    shard: usize,
+    charge: u64,
+}
+
+impl Drop for CacheReservation {
+    fn drop(&mut self) {
+        let mut state = self.cache.shards[self.shard].state.lock();
+        state.usage -= self.charge;
+        state.pinned_usage -= self.charge;
+    }
+}
+
+/// Hit, miss, insert and eviction counters, kept both for the whole cache and for every table reading through it.
+#[derive(Debug, Default)]
+pub struct BlockCacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A point-in-time copy of `BlockCacheCounters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockCacheCounts {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    /// Entries dropped to make room for others. Explicit invalidations are not counted.
+    pub evictions: u64,
+}
+
+impl BlockCacheCounters {
+    pub fn counts(&self) -> BlockCacheCounts {
+        BlockCacheCounts {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl BlockCacheCounts {
+    /// Share of lookups served from the cache, 0 if there was no lookup yet.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Statistics aggregated over all shards of a `BlockCache`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub num_shards: usize,
+    /// Capacity in bytes, summed over the shards.
+    pub capacity: u64,
+    /// Bytes charged to the cache, including `pinned_usage`.
+    pub usage: u64,
+    /// Bytes of metadata pinned by the SSTs.
+    pub pinned_usage: u64,
+    /// Number of cached blocks and filters.
+    pub entry_count: u64,
+    /// Number of cached blocks and filters in each shard.
+    pub shard_entry_counts: Vec<u64>,
+    /// Counters over all tables.
+    pub counts: BlockCacheCounts,
+    pub secondary: Option<SecondaryCacheStats>,
+}
+
+impl BlockCache {
+    /// Create a block cache holding up to `capacity` bytes over `DEFAULT_NUM_SHARDS` shards.
+    pub fn new(capacity: u64) -> Self {
+        Self::with_num_shards(capacity, DEFAULT_NUM_SHARDS)
+    }
+
+    /// Create a block cache holding up to `capacity` bytes, split evenly over `num_shards` shards.
+    pub fn with_num_shards(capacity: u64, num_shards: usize) -> Self {
+        let num_shards = num_shards.max(1);
+        let shard_capacity = capacity.div_ceil(num_shards as u64).max(1);
+        Self {
+            shards: (0..num_shards)
+                .map(|_| Shard {
+                    capacity: AtomicU64::new(shard_capacity),
+                    state: Mutex::new(ShardState::default()),
+                })
+                .collect(),
+            capacity: AtomicU64::new(shard_capacity * num_shards as u64),
+            counters: Arc::default(),
+            secondary: None,
+            next_table_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Write the blocks evicted from memory to `secondary`, and look them up there before going to the SSTs.
+    pub fn with_secondary_cache(self, secondary: SecondaryCache) -> Self {
+        Self {
+            secondary: Some(secondary),
+            ..self
+        }
+    }
+
+    fn evicted(&self, evicted: Vec<(EntryKey, Entry)>) {
+        for (key, entry) in evicted {
+            self.record(entry.owner.as_ref(), |c| &c.evictions);
+            if let (
+                Some(secondary),
+                EntryKey::Block(table_id, block_idx),
+                CacheValue::Block(block),
+            ) = (&self.secondary, key, &entry.value)
+            {
+                // the secondary cache is best-effort, a block that fails to be written is read from the SST again
+                let _ = secondary.insert((table_id, block_idx), block);
+            }
+        }
+    }
+
+    fn shard_idx(&self, table_id: usize, idx: usize) -> usize {
+        // SSTs are made of many blocks, so mix both halves of the key before picking a shard
+        const MUL: u64 = 0x9e37_79b9_7f4a_7c15;
+        let hash = ((table_id as u64).wrapping_mul(MUL) ^ idx as u64).wrapping_mul(MUL);
+        (hash >> 32) as usize % self.shards.len()
+    }
+
+    fn shard_of(&self, key: &EntryKey) -> &Shard {
+        let idx = match *key {
+            EntryKey::Block(table_id, block_idx) => self.shard_idx(table_id, block_idx),
+            EntryKey::Filter(table_id) => self.shard_idx(table_id, usize::MAX),
+        };
+        &self.shards[idx]
+    }
+
+    fn record(
+        &self,
+        owner: Option<&Arc<BlockCacheCounters>>,
+        counter: fn(&BlockCacheCounters) -> &AtomicU64,
+    ) {
+        counter(&self.counters).fetch_add(1, Ordering::Relaxed);
+        if let Some(owner) = owner {
+            counter(owner).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn lookup(
+        &self,
+        key: &EntryKey,
+        owner: Option<&Arc<BlockCacheCounters>>,
+    ) -> Option<CacheValue> {
+        let value = self.shard_of(key).state.lock().touch(key);
+        if value.is_some() {
+            self.record(owner, |c| &c.hits);
+        } else {
+            self.record(owner, |c| &c.misses);
+        }
+        value
+    }
+
+    fn insert_entry(
+        &self,
+        key: EntryKey,
+        value: CacheValue,
+        priority: CachePriority,
+        owner: Option<&Arc<BlockCacheCounters>>,
+    ) {
+        self.record(owner, |c| &c.inserts);
+        let shard = self.shard_of(&key);
+        let evicted = {
+            let mut state = shard.state.lock();
+            let entry = Entry {
+                value,
+                priority,
+                tick: 0,
+                owner: owner.cloned(),
+            };
+            state.insert(key, entry);
+            state.evict(shard.capacity.load(Ordering::Relaxed))
+        };
+        self.evicted(evicted);
+    }
+
+    pub fn get(&self, key: &BlockCacheKey) -> Option<Arc<Block>> {
+        self.get_for_table(key, None)
+    }
+
+    /// Same as `get`, also counting the lookup against the counters of the table reading the block.
+    pub fn get_for_table(
+        &self,
+        key: &BlockCacheKey,
+        owner: Option<&Arc<BlockCacheCounters>>,
+    ) -> Option<Arc<Block>> {
+        match self.lookup(&EntryKey::Block(key.0, key.1), owner)? {
+            CacheValue::Block(block) => Some(block),
+            CacheValue::Filter(_) => unreachable!(),
+        }
+    }
+
+    pub fn insert(&self, key: BlockCacheKey, block: Arc<Block>) {
+        self.insert_for_table(key, block, None, CachePriority::Low)
+    }
+
+    /// Insert a block at `priority`, counting it against the counters of the table it was read for.
+    pub fn insert_for_table(
+        &self,
+        key: BlockCacheKey,
+        block: Arc<Block>,
+        owner: Option<&Arc<BlockCacheCounters>>,
+        priority: CachePriority,
+    ) {
+        self.insert_entry(
+            EntryKey::Block(key.0, key.1),
+            CacheValue::Block(block),
+            priority,
+            owner,
+        )
+    }
+
+    /// Allocate the id an SST's blocks are cached under, unique among all tables using this cache.
+    pub fn new_table_id(&self) -> usize {
+        self.next_table_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn has_secondary_cache(&self) -> bool {
+        self.secondary.is_some()
+    }
+
+    /// Look a block missing from memory up in the secondary cache, moving it back into memory when found. This
+    /// reads from disk.
+    pub fn get_from_secondary(
+        &self,
+        key: &BlockCacheKey,
+        owner: Option<&Arc<BlockCacheCounters>>,
+        priority: CachePriority,
+    ) -> Option<Arc<Block>> {
+        let block = self.secondary.as_ref()?.get(key)?;
+        self.insert_for_table(*key, block.clone(), owner, priority);
+        Some(block)
+    }
+
+    /// Get a block, loading it with `init` on a miss. Concurrent misses on the same block may each load it.
+    pub fn try_get_with<F, E>(&self, key: BlockCacheKey, init: F) -> Result<Arc<Block>, E>
+    where
+        F: FnOnce() -> Result<Arc<Block>, E>,
+    {
+        self.try_get_with_for_table(key, None, CachePriority::Low, init)
+    }
+
+    /// Same as `try_get_with`, also counting the lookup against the counters of the table reading the block and
+    /// inserting a loaded block at `priority`.
+    pub fn try_get_with_for_table<F, E>(
+        &self,
+        key: BlockCacheKey,
+        owner: Option<&Arc<BlockCacheCounters>>,
+        priority: CachePriority,
+        init: F,
+    ) -> Result<Arc<Block>, E>
+    where
+        F: FnOnce() -> Result<Arc<Block>, E>,
+    {
+        if let Some(block) = self.get_for_table(&key, owner) {
+            return Ok(block);
+        }
+        if let Some(block) = self.get_from_secondary(&key, owner, priority) {
+            return Ok(block);
+        }
+        let block = init()?;
+        self.insert_for_table(key, block.clone(), owner, priority);
+        Ok(block)
+    }
+
+    /// Get the bloom filter of an SST, loading it with `init` on a miss. Filters are cached at a high priority.
+    pub(crate) fn try_get_filter_with<F, E>(
+        &self,
+        table_id: usize,
+        owner: Option<&Arc<BlockCacheCounters>>,
+        init: F,
+    ) -> Result<Arc<Bloom>, E>
+    where
+        F: FnOnce() -> Result<Arc<Bloom>, E>,
+    {
+        let key = EntryKey::Filter(table_id);
+        if let Some(CacheValue::Filter(bloom)) = self.lookup(&key, owner) {
+            return Ok(bloom);
+        }
+        let bloom = init()?;
+        self.insert_filter(table_id, bloom.clone(), owner);
+        Ok(bloom)
+    }
+
+    pub(crate) fn insert_filter(
+        &self,
+        table_id: usize,
+        bloom: Arc<Bloom>,
+        owner: Option<&Arc<BlockCacheCounters>>,
+    ) {
+        self.insert_entry(
+            EntryKey::Filter(table_id),
+            CacheValue::Filter(bloom),
+            CachePriority::High,
+            owner,
+        )
+    }
+
+    /// Charge `charge` bytes of memory pinned by an SST against the capacity until the reservation is dropped.
+    /// Pinned charges are never evicted: cached entries are evicted to make room for them instead.
+    pub fn reserve(self: &Arc<Self>, table_id: usize, charge: u64) -> CacheReservation {
+        let shard = self.shard_idx(table_id, usize::MAX - 1);
+        let evicted = {
+            let mut state = self.shards[shard].state.lock();
+            state.usage += charge;
+            state.pinned_usage += charge;
+            state.evict(self.shards[shard].capacity.load(Ordering::Relaxed))
+        };
+        self.evicted(evicted);
+        CacheReservation {
+            cache: self.clone(),
+            shard,
+            charge,
+        }
+    }
+
+    pub fn invalidate(&self, key: &BlockCacheKey) {
+        if let Some(secondary) = &self.secondary {
+            secondary.invalidate(key);
+        }
+        let key = EntryKey::Block(key.0, key.1);
+        self.shard_of(&key).state.lock().remove(&key);
+    }
+
+    /// Drop all cached blocks and filters, including the secondary cache. Pinned charges are kept.
+    pub fn invalidate_all(&self) {
+        if let Some(secondary) = &self.secondary {
+            secondary.invalidate_all();
+        }
+        for shard in &self.shards {
+            let mut state = shard.state.lock();
+            let keys: Vec<_> = state.entries.keys().copied().collect();
+            for key in keys {
+                state.remove(&key);
+            }
+        }
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Capacity in bytes, summed over the shards.
+    pub fn capacity(&self) -> u64 {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Change the capacity, split evenly over the shards, evicting the entries over a smaller one right away.
+    pub fn set_capacity(&self, capacity: u64) {
+        let shard_capacity = capacity.div_ceil(self.shards.len() as u64).max(1);
+        self.capacity
+            .store(shard_capacity * self.shards.len() as u64, Ordering::Relaxed);
+        for shard in &self.shards {
+            let evicted = {
+                let mut state = shard.state.lock();
+                shard.capacity.store(shard_capacity, Ordering::Relaxed);
+                state.evict(shard_capacity)
+            };
+            self.evicted(evicted);
+        }
+    }
+
+    /// Bytes charged to the cache, including the pinned charges.
+    pub fn usage(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.state.lock().usage)
+            .sum()
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        let mut stats = BlockCacheStats {
+            num_shards: self.shards.len(),
+            capacity: self.capacity(),
+            counts: self.counters.counts(),
+            secondary: self.secondary.as_ref().map(SecondaryCache::stats),
+            ..Default::default()
+        };
+        for shard in &self.shards {
+            let state = shard.state.lock();
+            stats.usage += state.usage;
+            stats.pinned_usage += state.pinned_usage;
+            stats.entry_count += state.entries.len() as u64;
+            stats.shard_entry_counts.push(state.entries.len() as u64);
+        }
+        stats
+    }
+}
@@ -1,5 +1,7 @@
 use std::{cmp::Ordering, sync::Arc};
 
+use bytes::Buf;
+
 use crate::key::{KeySlice, KeyVec};
 
 use super::Block;
@@ -39,13 +41,14 @@ impl BlockIterator {
         // first 2 elements in data is length so need to skip it
         key_start + 2..key_start + 2 + key_len
     }
+    fn get_key(&self, idx: usize) -> KeyVec {
+        let key_range = self.get_key_range(idx);
+        let ts = (&self.block.data[key_range.end..]).get_u64();
+        KeyVec::from_vec_with_ts(self.block.data[key_range].to_vec(), ts)
+    }
     fn get_value_range(&self, idx: usize) -> (usize, usize) {
-        let key_start = self.block.offsets[idx] as usize;
-        let key_len =
-            u16::from_be_bytes([self.block.data[key_start], self.block.data[key_start + 1]])
-                as usize;
-
-        let value_start = key_start + 2 + key_len;
+        // the value follows the key and its 8-byte timestamp
+        let value_start = self.get_key_range(idx).end + 8;
         let value_len = u16::from_be_bytes([
             self.block.data[value_start],
             self.block.data[value_start + 1],
@@ -85,6 +88,11 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the block and the range of the current value inside `block.data`.
+    pub(crate) fn value_in_block(&self) -> (Arc<Block>, Range<usize>) {
+        (self.block.clone(), self.value_range.0..self.value_range.1)
+    }
+
     /// Returns true if the iterator is valid.
     /// Note: You may want to make use of `key`
     pub fn is_valid(&self) -> bool {
@@ -97,7 +105,7 @@ impl BlockIterator {
         assert!(!self.block.data.is_empty());
         // update value range
         if self.first_key.is_empty() {
-            self.first_key = KeyVec::from_vec(self.block.data[self.get_key_range(0)].to_vec());
+            self.first_key = self.get_key(0);
         }
         self.key = self.first_key.clone();
 
@@ -115,7 +123,7 @@ impl BlockIterator {
         }
 
         self.idx += 1;
-        self.key = KeyVec::from_vec(self.block.data[self.get_key_range(self.idx)].to_vec());
+        self.key = self.get_key(self.idx);
         self.value_range = self.get_value_range(self.idx);
     }
 
@@ -129,9 +137,7 @@ impl BlockIterator {
             return;
         }
         // target key is not exzit, must need is_valid = false
-        let last_key = KeyVec::from_vec(
-            self.block.data[self.get_key_range(self.block.offsets.len() - 1)].to_vec(),
-        );
+        let last_key = self.get_key(self.block.offsets.len() - 1);
 
         if key.cmp(&last_key.as_key_slice()) == Ordering::Greater {
             self.seek_to_first();
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+
+use super::{Block, BlockCacheKey};
+use crate::fs::{read_exact_at, write_all_at};
+
+/// table_id, block_idx and the block length, before the encoded block and its checksum.
+const RECORD_HEADER_SIZE: usize = 8 + 8 + 4;
+
+/// A second cache tier in a bounded local file, typically on a fast local disk, holding the blocks evicted from
+/// the in-memory `BlockCache` so that they are not read from the SST again.
+///
+/// The file is written as a ring buffer: once full, new blocks overwrite the oldest ones. The index lives in memory
+/// only and the file is truncated on creation, so after a crash or a restart the cache simply starts empty. Every
+/// record carries its key and a checksum, which a reader verifies so that a block overwritten concurrently is seen
+/// as a miss.
+pub struct SecondaryCache {
+    file: File,
+    capacity: u64,
+    state: Mutex<SecondaryCacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+}
+
+#[derive(Default)]
+struct SecondaryCacheState {
+    /// Offset and length of the record of every cached block.
+    index: HashMap<BlockCacheKey, (u64, u64)>,
+    /// Cached blocks by record offset, to find the records a write overwrites.
+    records: BTreeMap<u64, BlockCacheKey>,
+    write_offset: u64,
+    usage: u64,
+}
+
+impl SecondaryCacheState {
+    fn remove(&mut self, key: &BlockCacheKey) {
+        if let Some((offset, len)) = self.index.remove(key) {
+            self.records.remove(&offset);
+            self.usage -= len;
+        }
+    }
+}
+
+/// Statistics of a `SecondaryCache`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecondaryCacheStats {
+    /// Size of the cache file in bytes.
+    pub capacity: u64,
+    /// Bytes of the cached records.
+    pub usage: u64,
+    pub entry_count: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+}
+
+impl SecondaryCache {
+    /// Create the cache file at `path` holding up to `capacity` bytes, discarding whatever a previous process left.
+    pub fn create(path: impl AsRef<Path>, capacity: u64) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            file,
+            capacity,
+            state: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+        })
+    }
+
+    fn encode_record(key: &BlockCacheKey, block: &Block) -> Vec<u8> {
+        let encoded = block.encode();
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + encoded.len() + 4);
+        record.put_u64(key.0 as u64);
+        record.put_u64(key.1 as u64);
+        record.put_u32(encoded.len() as u32);
+        record.put_slice(&encoded);
+        record.put_u32(crc32fast::hash(&record));
+        record
+    }
+
+    fn decode_record(key: &BlockCacheKey, record: &[u8]) -> Option<Block> {
+        let (body, mut checksum) = record.split_at(record.len().checked_sub(4)?);
+        if body.len() < RECORD_HEADER_SIZE || checksum.get_u32() != crc32fast::hash(body) {
+            return None;
+        }
+        let mut header = &body[..RECORD_HEADER_SIZE];
+        let (table_id, block_idx) = (header.get_u64() as usize, header.get_u64() as usize);
+        let len = header.get_u32() as usize;
+        if (table_id, block_idx) != *key || len != body.len() - RECORD_HEADER_SIZE {
+            return None;
+        }
+        Block::decode(&body[RECORD_HEADER_SIZE..]).ok()
+    }
+
+    /// Write a block evicted from the in-memory cache. Blocks larger than the whole cache are skipped.
+    pub fn insert(&self, key: BlockCacheKey, block: &Block) -> Result<()> {
+        let record = Self::encode_record(&key, block);
+        let len = record.len() as u64;
+        if len > self.capacity {
+            return Ok(());
+        }
+        let offset = {
+            let mut state = self.state.lock();
+            state.remove(&key);
+            if state.write_offset + len > self.capacity {
+                state.write_offset = 0;
+            }
+            let offset = state.write_offset;
+            // drop the records that are about to be overwritten
+            let overwritten: Vec<_> = state
+                .records
+                .range(..offset + len)
+                .filter(|(_, key)| state.index[*key].0 + state.index[*key].1 > offset)
+                .map(|(_, key)| *key)
+                .collect();
+            for key in overwritten {
+                state.remove(&key);
+            }
+            state.write_offset += len;
+            offset
+        };
+        write_all_at(&self.file, &record, offset)?;
+        // only index the block once it is fully written
+        let mut state = self.state.lock();
+        let overlaps = state
+            .records
+            .range(..offset + len)
+            .next_back()
+            .is_some_and(|(&other, key)| other + state.index[key].1 > offset);
+        if !overlaps {
+            state.index.insert(key, (offset, len));
+            state.records.insert(offset, key);
+            state.usage += len;
+        }
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read a block back. Unreadable or overwritten records are misses.
+    pub fn get(&self, key: &BlockCacheKey) -> Option<Arc<Block>> {
+        let location = self.state.lock().index.get(key).copied();
+        let block = location.and_then(|(offset, len)| {
+            let mut record = vec![0; len as usize];
+            read_exact_at(&self.file, &mut record, offset).ok()?;
+            Self::decode_record(key, &record)
+        });
+        match block {
+            Some(block) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(Arc::new(block))
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn invalidate(&self, key: &BlockCacheKey) {
+        self.state.lock().remove(key);
+    }
+
+    pub fn invalidate_all(&self) {
+        let mut state = self.state.lock();
+        state.index.clear();
+        state.records.clear();
+        state.usage = 0;
+    }
+
+    pub fn stats(&self) -> SecondaryCacheStats {
+        let state = self.state.lock();
+        SecondaryCacheStats {
+            capacity: self.capacity,
+            usage: state.usage,
+            entry_count: state.index.len() as u64,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! A C ABI for embedding the engine, declared in `include/mini_lsm.h`. Build the shared library with
+//! `cargo rustc -p mini-lsm-starter --release --features capi --lib --crate-type cdylib`.
+//!
+//! Every function returning a `c_int` returns one of the `MINI_LSM_*` codes, and on failure keeps a message for
+//! `mini_lsm_last_error` on the calling thread. Handles are created by `*_open` or `*_create` and released by
+//! `mini_lsm_close` or `*_destroy`, exactly once. Pointers must be valid for the lengths passed with them, and an
+//! iterator must be destroyed before the database it reads is closed.
+//!
+//! A database handle can be used by many threads at once, and is closed once no other call on it runs. An options,
+//! write batch or iterator handle is used by one thread at a time, save for a write batch written from several
+//! threads while none modifies it.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ops::Bound;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use bytes::Bytes;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+
+pub const MINI_LSM_OK: c_int = 0;
+/// `mini_lsm_get` found no value for the key.
+pub const MINI_LSM_NOT_FOUND: c_int = 1;
+/// A null handle or pointer, a string that is not UTF-8, or an unknown or invalid option.
+pub const MINI_LSM_INVALID_ARGUMENT: c_int = 2;
+/// Reading or writing a file failed.
+pub const MINI_LSM_IO_ERROR: c_int = 3;
+/// Any other failure, including a panic of the engine.
+pub const MINI_LSM_ERROR: c_int = 4;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A failure with the code it is reported with.
+struct CapiError(c_int, anyhow::Error);
+
+impl From<anyhow::Error> for CapiError {
+    fn from(err: anyhow::Error) -> Self {
+        let code = if err.chain().any(|cause| cause.is::<std::io::Error>()) {
+            MINI_LSM_IO_ERROR
+        } else {
+            MINI_LSM_ERROR
+        };
+        CapiError(code, err)
+    }
+}
+
+fn invalid_argument(err: anyhow::Error) -> CapiError {
+    CapiError(MINI_LSM_INVALID_ARGUMENT, err)
+}
+
+/// Run `f`, turning its errors and panics into a code and the last error message.
+fn run(f: impl FnOnce() -> std::result::Result<c_int, CapiError>) -> c_int {
+    let (code, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(code)) => return code,
+        Ok(Err(CapiError(code, err))) => (code, format!("{:#}", err)),
+        Err(_) => (MINI_LSM_ERROR, "the engine panicked".to_string()),
+    };
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+/// A handle used by one thread at a time.
+unsafe fn handle<'a, T>(ptr: *mut T) -> std::result::Result<&'a mut T, CapiError> {
+    ptr.as_mut()
+        .ok_or_else(|| invalid_argument(anyhow!("null handle")))
+}
+
+/// A database handle, shared by the threads using it at the same time.
+unsafe fn database<'a>(ptr: *const MiniLsmDb) -> std::result::Result<&'a MiniLsmDb, CapiError> {
+    ptr.as_ref()
+        .ok_or_else(|| invalid_argument(anyhow!("null handle")))
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> std::result::Result<&'a [u8], CapiError> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return Err(invalid_argument(anyhow!("null pointer of {} bytes", len)));
+    }
+    Ok(std::slice::from_raw_parts(ptr, len))
+}
+
+unsafe fn string<'a>(ptr: *const c_char) -> std::result::Result<&'a str, CapiError> {
+    if ptr.is_null() {
+        return Err(invalid_argument(anyhow!("null string")));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .context("string is not UTF-8")
+        .map_err(invalid_argument)
+}
+
+/// A bound of a range, the key when `ptr` is not null, unbounded otherwise.
+unsafe fn bound<'a>(
+    ptr: *const u8,
+    len: usize,
+    inclusive: bool,
+) -> std::result::Result<Bound<&'a [u8]>, CapiError> {
+    if ptr.is_null() {
+        return Ok(Bound::Unbounded);
+    }
+    let key = bytes(ptr, len)?;
+    Ok(if inclusive {
+        Bound::Included(key)
+    } else {
+        Bound::Excluded(key)
+    })
+}
+
+/// Hand bytes over to the caller, to be released by `mini_lsm_free`.
+unsafe fn give(
+    data: &[u8],
+    ptr: *mut *mut u8,
+    len: *mut usize,
+) -> std::result::Result<(), CapiError> {
+    if ptr.is_null() || len.is_null() {
+        return Err(invalid_argument(anyhow!("null output pointer")));
+    }
+    let boxed = Box::<[u8]>::from(data);
+    *len = boxed.len();
+    *ptr = Box::into_raw(boxed) as *mut u8;
+    Ok(())
+}
+
+/// The message of the last failure on this thread, valid until the next failure on it; null if none failed.
+#[no_mangle]
+pub extern "C" fn mini_lsm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Release the bytes returned by `mini_lsm_get`.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+pub struct MiniLsmOptions(LsmStorageOptions);
+
+/// Options with the default values of `LsmStorageOptions`.
+#[no_mangle]
+pub extern "C" fn mini_lsm_options_create() -> *mut MiniLsmOptions {
+    Box::into_raw(Box::new(MiniLsmOptions(LsmStorageOptions::default())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_options_destroy(options: *mut MiniLsmOptions) {
+    if !options.is_null() {
+        drop(Box::from_raw(options));
+    }
+}
+
+/// Set the option `name`, a field of `LsmStorageOptions` as in the `OPTIONS` file, to `value` in JSON, e.g.
+/// `4096`, `true` or `{"Leveled": {...}}`; a value that is not JSON is taken as a string.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_options_set(
+    options: *mut MiniLsmOptions,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    run(|| {
+        let options = handle(options)?;
+        let (name, value) = (string(name)?, string(value)?);
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        let updated = (|| {
+            let mut json = serde_json::to_value(&options.0)?;
+            let Some(field) = json.get_mut(name) else {
+                bail!("unknown option {}", name);
+            };
+            *field = value;
+            // the objects the OPTIONS file does not hold cannot be set from C, nothing is lost
+            serde_json::from_value::<LsmStorageOptions>(json)
+                .with_context(|| format!("invalid value for {}", name))
+        })()
+        .map_err(invalid_argument)?;
+        options.0 = updated;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+pub struct MiniLsmDb(Arc<MiniLsm>);
+
+/// Open the database at `path`, creating it if needed, and store its handle in `db`.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_open(
+    path: *const c_char,
+    options: *const MiniLsmOptions,
+    db: *mut *mut MiniLsmDb,
+) -> c_int {
+    run(|| {
+        let path = string(path)?;
+        let options = options
+            .as_ref()
+            .ok_or_else(|| invalid_argument(anyhow!("null options")))?;
+        if db.is_null() {
+            return Err(invalid_argument(anyhow!("null output pointer")));
+        }
+        options.0.validate().map_err(invalid_argument)?;
+        let storage = MiniLsm::open(path, options.0.clone())?;
+        *db = Box::into_raw(Box::new(MiniLsmDb(storage)));
+        Ok(MINI_LSM_OK)
+    })
+}
+
+/// Flush and close the database, and release its handle even if closing fails.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_close(db: *mut MiniLsmDb) -> c_int {
+    run(|| {
+        database(db)?;
+        let db = Box::from_raw(db);
+        db.0.close()?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+/// Change options of the open database, see `MiniLsm::set_options`.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_set_option(
+    db: *mut MiniLsmDb,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    run(|| {
+        let db = database(db)?;
+        let (name, value) = (string(name)?, string(value)?);
+        db.0.set_options(&[(name, value)])
+            .map_err(invalid_argument)?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_put(
+    db: *mut MiniLsmDb,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    run(|| {
+        let db = database(db)?;
+        db.0.put(bytes(key, key_len)?, bytes(value, value_len)?)?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_delete(
+    db: *mut MiniLsmDb,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    run(|| {
+        let db = database(db)?;
+        db.0.delete(bytes(key, key_len)?)?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+/// Store the value of the key in `value` and `value_len`, to be released by `mini_lsm_free`, or return
+/// `MINI_LSM_NOT_FOUND` leaving them untouched.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_get(
+    db: *mut MiniLsmDb,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+) -> c_int {
+    run(|| {
+        let db = database(db)?;
+        match db.0.get(bytes(key, key_len)?)? {
+            Some(found) => {
+                give(&found, value, value_len)?;
+                Ok(MINI_LSM_OK)
+            }
+            None => Ok(MINI_LSM_NOT_FOUND),
+        }
+    })
+}
+
+/// Flush the memtables to SSTs.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_flush(db: *mut MiniLsmDb) -> c_int {
+    run(|| {
+        database(db)?.0.force_flush()?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+/// Sync the WAL.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_sync(db: *mut MiniLsmDb) -> c_int {
+    run(|| {
+        database(db)?.0.sync()?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+#[derive(Default)]
+pub struct MiniLsmWriteBatch(Vec<WriteBatchRecord<Bytes>>);
+
+#[no_mangle]
+pub extern "C" fn mini_lsm_writebatch_create() -> *mut MiniLsmWriteBatch {
+    Box::into_raw(Box::default())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_writebatch_destroy(batch: *mut MiniLsmWriteBatch) {
+    if !batch.is_null() {
+        drop(Box::from_raw(batch));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_writebatch_put(
+    batch: *mut MiniLsmWriteBatch,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    run(|| {
+        let batch = handle(batch)?;
+        let (key, value) = (bytes(key, key_len)?, bytes(value, value_len)?);
+        batch.0.push(WriteBatchRecord::Put(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+        ));
+        Ok(MINI_LSM_OK)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_writebatch_delete(
+    batch: *mut MiniLsmWriteBatch,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    run(|| {
+        let batch = handle(batch)?;
+        let key = bytes(key, key_len)?;
+        batch
+            .0
+            .push(WriteBatchRecord::Del(Bytes::copy_from_slice(key)));
+        Ok(MINI_LSM_OK)
+    })
+}
+
+/// Number of puts and deletes in the batch, 0 for a null batch.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_writebatch_count(batch: *const MiniLsmWriteBatch) -> usize {
+    batch.as_ref().map_or(0, |batch| batch.0.len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_writebatch_clear(batch: *mut MiniLsmWriteBatch) {
+    if let Some(batch) = batch.as_mut() {
+        batch.0.clear();
+    }
+}
+
+/// Write the batch atomically, leaving it as is to be cleared or destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_write(
+    db: *mut MiniLsmDb,
+    batch: *const MiniLsmWriteBatch,
+) -> c_int {
+    run(|| {
+        let db = database(db)?;
+        let batch = batch
+            .as_ref()
+            .ok_or_else(|| invalid_argument(anyhow!("null write batch")))?;
+        db.0.write_batch(&batch.0)?;
+        Ok(MINI_LSM_OK)
+    })
+}
+
+pub struct MiniLsmIterator(FusedIterator<LsmIterator>);
+
+/// Iterate over the keys from `lower` to `upper` from a snapshot taken now, with a null bound for no bound, and
+/// store the iterator in `iter`. The iterator starts at the first key, if any.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn mini_lsm_iter_create(
+    db: *mut MiniLsmDb,
+    lower: *const u8,
+    lower_len: usize,
+    lower_inclusive: bool,
+    upper: *const u8,
+    upper_len: usize,
+    upper_inclusive: bool,
+    iter: *mut *mut MiniLsmIterator,
+) -> c_int {
+    run(|| {
+        let db = database(db)?;
+        let lower = bound(lower, lower_len, lower_inclusive)?;
+        let upper = bound(upper, upper_len, upper_inclusive)?;
+        if iter.is_null() {
+            return Err(invalid_argument(anyhow!("null output pointer")));
+        }
+        let scan = db.0.scan(lower, upper)?;
+        *iter = Box::into_raw(Box::new(MiniLsmIterator(scan)));
+        Ok(MINI_LSM_OK)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_iter_destroy(iter: *mut MiniLsmIterator) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+/// Whether the iterator is at an entry; false past the end, after a failed `mini_lsm_iter_next`, or for a null
+/// iterator.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_iter_valid(iter: *const MiniLsmIterator) -> bool {
+    iter.as_ref().is_some_and(|iter| iter.0.is_valid())
+}
+
+/// The key of the current entry, valid until the iterator moves; null if it is not valid.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_iter_key(
+    iter: *const MiniLsmIterator,
+    len: *mut usize,
+) -> *const u8 {
+    current(iter, len, |iter| iter.key())
+}
+
+/// The value of the current entry, valid until the iterator moves; null if it is not valid.
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_iter_value(
+    iter: *const MiniLsmIterator,
+    len: *mut usize,
+) -> *const u8 {
+    current(iter, len, |iter| iter.value())
+}
+
+unsafe fn current(
+    iter: *const MiniLsmIterator,
+    len: *mut usize,
+    part: impl FnOnce(&FusedIterator<LsmIterator>) -> &[u8],
+) -> *const u8 {
+    match iter.as_ref().filter(|iter| iter.0.is_valid()) {
+        Some(iter) => {
+            let data = part(&iter.0);
+            if let Some(len) = len.as_mut() {
+                *len = data.len();
+            }
+            data.as_ptr()
+        }
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mini_lsm_iter_next(iter: *mut MiniLsmIterator) -> c_int {
+    run(|| {
+        let iter = handle(iter)?;
+        if !iter.0.is_valid() {
+            return Err(invalid_argument(anyhow!("the iterator is not at an entry")));
+        }
+        iter.0.next()?;
+        Ok(MINI_LSM_OK)
+    })
+}
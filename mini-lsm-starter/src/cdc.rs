@@ -0,0 +1,192 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm, WriteBatchRecord};
+use crate::mem_table::map_bound;
+
+/// A write committed to the default column family.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub key: Bytes,
+    /// `None` for a deletion.
+    pub value: Option<Bytes>,
+    pub commit_ts: u64,
+}
+
+/// A change stream waiting for the writes to its range.
+pub(crate) struct ChangeSubscriber {
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
+    sender: Sender<Change>,
+}
+
+fn in_range(lower: &Bound<Bytes>, upper: &Bound<Bytes>, key: &[u8]) -> bool {
+    let after_lower = match lower {
+        Bound::Included(lower) => key >= lower.as_ref(),
+        Bound::Excluded(lower) => key > lower.as_ref(),
+        Bound::Unbounded => true,
+    };
+    let before_upper = match upper {
+        Bound::Included(upper) => key <= upper.as_ref(),
+        Bound::Excluded(upper) => key < upper.as_ref(),
+        Bound::Unbounded => true,
+    };
+    after_lower && before_upper
+}
+
+/// The changes of a range of keys in commit ts order, those before the subscription first, then the ones committed
+/// since as they are. The changes written by a batch share its commit ts and come in key order. It ends when the
+/// database is closed.
+pub struct ChangeStream {
+    backfill: std::vec::IntoIter<Change>,
+    receiver: Receiver<Change>,
+}
+
+impl ChangeStream {
+    /// The next change, without waiting for one.
+    pub fn try_next(&mut self) -> Option<Change> {
+        self.backfill
+            .next()
+            .or_else(|| self.receiver.try_recv().ok())
+    }
+
+    /// The next change, waiting up to `timeout` for one. `None` on timeout, or when the stream ended.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Change> {
+        self.backfill
+            .next()
+            .or_else(|| self.receiver.recv_timeout(timeout).ok())
+    }
+}
+
+/// Waits for the next change, `None` once the database is closed.
+impl Iterator for ChangeStream {
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Change> {
+        self.backfill.next().or_else(|| self.receiver.recv().ok())
+    }
+}
+
+impl LsmStorageInner {
+    /// Subscribe to the changes of the keys of the default column family from `lower` to `upper`, starting after
+    /// `from_ts`: the ones committed before are read back from the memtables and SSTs, every version the database
+    /// still has, then the ones committed since are sent as they are, by the writes and transactions, with their
+    /// commit ts. Fails if the GC watermark has passed `from_ts`, as compactions may have dropped the versions
+    /// after it. The values put by streaming and the ingested SSTs are not sent live.
+    pub fn subscribe(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        from_ts: u64,
+    ) -> Result<ChangeStream> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        // nothing is committed between the state backfilled and the subscription
+        let (start_ts, state) = {
+            let _write_lock = self.mvcc().write_lock.lock();
+            self.change_subscribers.lock().push(ChangeSubscriber {
+                lower: map_bound(lower),
+                upper: map_bound(upper),
+                sender,
+            });
+            (self.mvcc().latest_commit_ts(), self.state.read().clone())
+        };
+        let gc_watermark = self.mvcc().gc_watermark();
+        if from_ts < gc_watermark {
+            bail!(
+                "from ts {} is below the GC watermark {}, changes after it may have been garbage collected",
+                from_ts,
+                gc_watermark
+            );
+        }
+
+        let mut backfill = Vec::new();
+        if from_ts < start_ts {
+            let upper_bound = map_bound(upper);
+            let mut iter = Self::scan_state_versions(&state, lower, upper)?;
+            while iter.is_valid() && in_range(&Bound::Unbounded, &upper_bound, iter.key().key_ref())
+            {
+                let ts = iter.key().ts();
+                if ts > from_ts && ts <= start_ts {
+                    let value = match iter.blob_index_table() {
+                        Some(table) => table.read_blob(iter.value())?,
+                        None => Bytes::copy_from_slice(iter.value()),
+                    };
+                    backfill.push(Change {
+                        key: Bytes::copy_from_slice(iter.key().key_ref()),
+                        value: (!value.is_empty()).then_some(value),
+                        commit_ts: ts,
+                    });
+                }
+                iter.next()?;
+            }
+            backfill.sort_by(|a, b| a.commit_ts.cmp(&b.commit_ts).then(a.key.cmp(&b.key)));
+        }
+        Ok(ChangeStream {
+            backfill: backfill.into_iter(),
+            receiver,
+        })
+    }
+
+    /// Send the writes of a batch just committed at `ts` to the change streams of their keys, dropping the streams
+    /// gone. Must be called with the write lock, for the streams to get the batches in commit order.
+    pub(crate) fn publish_changes<T: AsRef<[u8]>>(
+        &self,
+        ts: u64,
+        batches: &[(u32, &[WriteBatchRecord<T>])],
+    ) {
+        let mut subscribers = self.change_subscribers.lock();
+        if subscribers.is_empty() {
+            return;
+        }
+        let Some((_, batch)) = batches
+            .iter()
+            .find(|(cf_id, _)| *cf_id == DEFAULT_COLUMN_FAMILY_ID)
+        else {
+            return;
+        };
+        let mut changes = batch
+            .iter()
+            .map(|record| match record {
+                // putting an empty value deletes the key
+                WriteBatchRecord::Put(key, value) => Change {
+                    key: Bytes::copy_from_slice(key.as_ref()),
+                    value: (!value.as_ref().is_empty())
+                        .then(|| Bytes::copy_from_slice(value.as_ref())),
+                    commit_ts: ts,
+                },
+                WriteBatchRecord::Del(key) => Change {
+                    key: Bytes::copy_from_slice(key.as_ref()),
+                    value: None,
+                    commit_ts: ts,
+                },
+            })
+            .collect::<Vec<_>>();
+        // the last write of a key in a batch is the one committed, as in the memtable
+        changes.reverse();
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        changes.dedup_by(|a, b| a.key == b.key);
+        subscribers.retain(|subscriber| {
+            changes
+                .iter()
+                .filter(|change| in_range(&subscriber.lower, &subscriber.upper, &change.key))
+                .all(|change| subscriber.sender.send(change.clone()).is_ok())
+        });
+    }
+}
+
+impl MiniLsm {
+    pub fn subscribe(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        from_ts: u64,
+    ) -> Result<ChangeStream> {
+        self.inner.subscribe(lower, upper, from_ts)
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::fs::FileSystem;
+use crate::logger::db_log;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::manifest::{manifest_file_name, set_current, Manifest};
+use crate::table::FileChecksum;
+
+impl LsmStorageInner {
+    /// Create an openable copy of the database in the directory `path`, which must not exist. The SSTs are hard
+    /// linked, so that it takes time in the number of files rather than their size, and must be on the same file
+    /// system. The checkpoint gets a manifest of its own holding the current state, and a copy of the WALs, which
+    /// hold the writes not flushed yet; without the WAL, the memtables are flushed first. `CURRENT` is written last,
+    /// a checkpoint left without it by a failure is not a database.
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if self.fs.exists(path) {
+            bail!("{} already exists", path.display());
+        }
+        self.fs.create_dir_all(path)?;
+        let sst_count = self.write_checkpoint(&self.fs, path, |name, _| {
+            self.fs
+                .hard_link(&self.path.join(name), &path.join(name))
+                .with_context(|| format!("failed to link {}", name))
+        })?;
+        db_log!(
+            self.options(),
+            Info,
+            "checkpoint at {} with {} SSTs",
+            path.display(),
+            sst_count
+        );
+        Ok(())
+    }
+
+    /// Write a checkpoint to the existing directory `path` of `fs`, leaving the SSTs and the blob files they point into
+    /// to `add_file`, called with their names and, for the SSTs, the checksums the manifest knows them by while
+    /// nothing can delete them. Return the number of SSTs.
+    pub(crate) fn write_checkpoint(
+        &self,
+        fs: &Arc<dyn FileSystem>,
+        path: &Path,
+        mut add_file: impl FnMut(&str, Option<FileChecksum>) -> Result<()>,
+    ) -> Result<usize> {
+        if !self.options().enable_wal && self.check_writable().is_ok() {
+            self.flush_all_memtables()?;
+        }
+        // nothing is flushed, compacted or deleted meanwhile
+        let _compaction_lock = self.compaction_lock.lock();
+        let state_lock = self.state_lock.lock();
+        let states = self.all_column_family_states();
+        let ssts = states
+            .iter()
+            .flat_map(|state| {
+                state
+                    .sst_ids()
+                    .map(|sst_id| (*sst_id, state.sstables[sst_id].file_checksum()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<BTreeMap<_, _>>();
+        let blob_file_ids = states
+            .iter()
+            .flat_map(|state| state.blob_file_ids().collect::<Vec<_>>())
+            .collect::<BTreeSet<_>>();
+        for (sst_id, file_checksum) in &ssts {
+            add_file(&format!("{:05}.sst", sst_id), *file_checksum)?;
+        }
+        for blob_file_id in &blob_file_ids {
+            add_file(&format!("{:05}.blob", blob_file_id), None)?;
+        }
+        // the writes buffered so far make it to the copy
+        self.sync()?;
+        for wal_id in Self::wal_ids(self.fs.as_ref(), &self.path)? {
+            let data = self.fs.read(&self.path_of_wal(wal_id))?;
+            let mut file = fs.create_new(&Self::path_of_wal_static(path, wal_id))?;
+            file.write_all(&data)?;
+            file.sync()?;
+        }
+        self.options().persist(fs.as_ref(), path)?;
+        let name = manifest_file_name(1);
+        let manifest = Manifest::create(fs, path.join(&name))?;
+        manifest.add_records(&state_lock, &self.manifest_snapshot())?;
+        drop(manifest);
+        set_current(fs.as_ref(), path, &name)?;
+        Ok(ssts.len())
+    }
+
+    /// Flush every memtable, freezing the current ones if they were written to.
+    fn flush_all_memtables(&self) -> Result<()> {
+        let any_written = self
+            .all_column_family_states()
+            .iter()
+            .any(|state| !state.memtable.is_empty());
+        if any_written {
+            self.force_freeze_memtable(&self.state_lock.lock())?;
+        }
+        while !self.state.read().imm_memtables.is_empty() {
+            self.force_flush_next_imm_memtable()?;
+        }
+        Ok(())
+    }
+}
+
+impl MiniLsm {
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.inner.checkpoint(path)
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+// `std::time` panics on wasm32, which reads the time of the browser instead
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub use web_time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Where the storage reads the time: the creation time of the SSTs, the FIFO TTL and periodic compaction compare
+/// against, the hybrid logical clock, and the durations of flushes and compactions. A `MockClock` makes them
+/// deterministic in tests. What waits for time to pass, like rate limiting, lock timeouts and the background
+/// periods, uses the real time since it sleeps.
+pub trait Clock: Send + Sync + Debug {
+    /// The wall-clock time.
+    fn now(&self) -> SystemTime;
+
+    /// A monotonic instant, to measure durations with.
+    fn instant(&self) -> Instant;
+
+    /// The seconds since the UNIX epoch, as recorded in the table properties.
+    fn unix_seconds(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+
+    /// The milliseconds since the UNIX epoch.
+    fn unix_millis(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
+
+/// The time of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+/// The clock of the options not setting one, the system's.
+pub fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when advanced, shared by the storage and the test driving it.
+#[derive(Debug)]
+pub struct MockClock {
+    start: SystemTime,
+    start_instant: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// A clock reading `start`.
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            start,
+            start_instant: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.start + *self.elapsed.lock()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + *self.elapsed.lock()
+    }
+}
@@ -0,0 +1,633 @@
+mod export;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Instant;
+use crate::compact::{CompactionController, CompactionOptions};
+use crate::logger::db_log;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{
+    LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm, SstOpenContext, WriteBatchRecord,
+};
+use crate::manifest::ManifestRecord;
+use crate::mem_table::MemTable;
+use crate::mvcc::Snapshot;
+use crate::table::CompressionType;
+use crate::wal::WalRecord;
+
+/// The column family of every database, written to without naming one.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+pub(crate) const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+pub use export::ExportedColumnFamily;
+
+/// A handle on a column family: a keyspace of the database with its own memtables and LSM tree. Once its column
+/// family is dropped, the handle fails, even if another one was created with the same name since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    /// The id the manifest and the WALs know the column family by, 0 for the default one.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_default(&self) -> bool {
+        self.id == DEFAULT_COLUMN_FAMILY_ID
+    }
+}
+
+/// The options of a column family besides the default one, fixed when it is created and recorded in the manifest.
+/// The ones left `None` take the value of the database options, following their changes by `set_options`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColumnFamilyOptions {
+    pub block_size: Option<usize>,
+    /// Same as `LsmStorageOptions::compression_per_level`.
+    pub compression_per_level: Option<Vec<CompressionType>>,
+    pub compaction_options: Option<CompactionOptions>,
+    /// The size its memtables are frozen at, `target_sst_size` by default. A memtable reaching it freezes the
+    /// memtables of every column family, which are frozen together.
+    pub write_buffer_size: Option<usize>,
+}
+
+impl ColumnFamilyOptions {
+    /// The database options with the ones set here in place of theirs.
+    pub fn resolve(&self, options: &LsmStorageOptions) -> LsmStorageOptions {
+        let mut resolved = options.clone();
+        if let Some(block_size) = self.block_size {
+            resolved.block_size = block_size;
+        }
+        if let Some(compression_per_level) = &self.compression_per_level {
+            resolved.compression_per_level = compression_per_level.clone();
+        }
+        if let Some(compaction_options) = &self.compaction_options {
+            resolved.compaction_options = compaction_options.clone();
+        }
+        resolved
+    }
+
+    /// The size the memtables are frozen at with the database options `options`.
+    pub fn write_buffer_size(&self, options: &LsmStorageOptions) -> usize {
+        self.write_buffer_size.unwrap_or(options.target_sst_size)
+    }
+
+    /// The compaction controller of its own, if it sets `compaction_options`.
+    pub(crate) fn compaction_controller(
+        &self,
+        options: &LsmStorageOptions,
+    ) -> Option<CompactionController> {
+        self.compaction_options
+            .is_some()
+            .then(|| CompactionController::new(&self.resolve(options)))
+    }
+
+    fn validate(&self, options: &LsmStorageOptions) -> Result<()> {
+        if self.write_buffer_size == Some(0) {
+            bail!("write_buffer_size must be positive");
+        }
+        self.resolve(options).validate()
+    }
+}
+
+/// A column family besides the default one. Its memtables are frozen and flushed together with the default one,
+/// taking the same id, and its writes go to the WAL of the default memtable. A WAL is deleted once every column
+/// family flushed the memtables written to it.
+#[derive(Clone)]
+pub(crate) struct ColumnFamilyState {
+    pub(crate) name: String,
+    pub(crate) options: ColumnFamilyOptions,
+    pub(crate) state: Arc<LsmStorageState>,
+}
+
+/// A column family besides the default one, as the records of the manifest lead to it.
+#[derive(Clone)]
+pub(crate) struct ReplayedColumnFamily {
+    pub(crate) name: String,
+    pub(crate) options: ColumnFamilyOptions,
+    pub(crate) state: LsmStorageState,
+    /// The WALs below it hold none of its writes that are not flushed.
+    pub(crate) oldest_memtable_id: usize,
+}
+
+impl ReplayedColumnFamily {
+    /// The column family with an empty current memtable `memtable_id`.
+    pub(crate) fn into_state(self, memtable_id: usize) -> ColumnFamilyState {
+        let mut state = self.state;
+        state.memtable = Arc::new(MemTable::create(memtable_id));
+        ColumnFamilyState {
+            name: self.name,
+            options: self.options,
+            state: Arc::new(state),
+        }
+    }
+}
+
+impl LsmStorageInner {
+    /// The column family named `name`, if it exists.
+    pub fn cf(&self, name: &str) -> Option<ColumnFamily> {
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            return Some(ColumnFamily::new(DEFAULT_COLUMN_FAMILY_ID, name));
+        }
+        self.column_families
+            .read()
+            .iter()
+            .find(|(_, cf)| cf.name == name)
+            .map(|(id, _)| ColumnFamily::new(*id, name))
+    }
+
+    /// The names of the column families, the default one first.
+    pub fn list_cfs(&self) -> Vec<String> {
+        std::iter::once(DEFAULT_COLUMN_FAMILY_NAME.to_string())
+            .chain(
+                self.column_families
+                    .read()
+                    .values()
+                    .map(|cf| cf.name.clone()),
+            )
+            .collect()
+    }
+
+    /// Create an empty column family with the options of the database. It shares the WAL, the manifest and the
+    /// block cache with the default one.
+    pub fn create_cf(&self, name: &str) -> Result<ColumnFamily> {
+        self.create_cf_with_options(name, ColumnFamilyOptions::default())
+    }
+
+    /// Same as `create_cf`, with options of its own.
+    pub fn create_cf_with_options(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily> {
+        self.check_writable()?;
+        if name.is_empty() {
+            bail!("the name of a column family cannot be empty");
+        }
+        options.validate(&self.options())?;
+        let state_lock = self.state_lock.lock();
+        if self.cf(name).is_some() {
+            bail!("column family {} already exists", name);
+        }
+        let id = self.next_column_family_id.fetch_add(1, Ordering::SeqCst);
+        // the state lock keeps the memtable from being frozen meanwhile
+        let memtable_id = self.state.read().memtable.id();
+        let mut state = LsmStorageState::create(&options.resolve(&self.options()));
+        state.memtable = Arc::new(MemTable::create(memtable_id));
+        // installed first, so that a manifest rolled over by the record includes it
+        self.column_families.write().insert(
+            id,
+            ColumnFamilyState {
+                name: name.to_string(),
+                options: options.clone(),
+                state: Arc::new(state),
+            },
+        );
+        let record = ManifestRecord::CreateColumnFamily {
+            id,
+            name: name.to_string(),
+            memtable_id,
+            options,
+        };
+        if let Err(e) = self.add_manifest_record(&state_lock, record) {
+            self.column_families.write().remove(&id);
+            return Err(e);
+        }
+        db_log!(
+            self.options(),
+            Info,
+            "created column family {} with id {}",
+            name,
+            id
+        );
+        Ok(ColumnFamily::new(id, name))
+    }
+
+    /// Drop a column family with its data. The default column family cannot be dropped. Reads and writes of the
+    /// column family fail once the drop is recorded, its SSTs are deleted by `purge_dropped_column_families` once
+    /// none of its iterators and compactions is left.
+    pub fn drop_cf(&self, name: &str) -> Result<()> {
+        self.check_writable()?;
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            bail!("the default column family cannot be dropped");
+        }
+        let state_lock = self.state_lock.lock();
+        let Some(cf) = self.cf(name) else {
+            bail!("column family {} does not exist", name);
+        };
+        let dropped = self.column_families.write().remove(&cf.id).unwrap();
+        if let Err(e) =
+            self.add_manifest_record(&state_lock, ManifestRecord::DropColumnFamily(cf.id))
+        {
+            self.column_families.write().insert(cf.id, dropped);
+            return Err(e);
+        }
+        drop(state_lock);
+        let state = &dropped.state;
+        if let Some(manager) = &self.options().write_buffer_manager {
+            for memtable in std::iter::once(&state.memtable).chain(state.imm_memtables.iter()) {
+                manager.free(memtable.approximate_size());
+            }
+        }
+        db_log!(
+            self.options(),
+            Info,
+            "dropped column family {} with {} SSTs",
+            name,
+            state.sstables.len()
+        );
+        self.dropped_column_families.lock().push(dropped);
+        self.purge_dropped_column_families()?;
+        Ok(())
+    }
+
+    /// Delete the SSTs of the dropped column families no iterator or compaction reads anymore, and return their
+    /// ids. Runs in the background while any is left. The SSTs left when the database is closed are deleted when it
+    /// is opened again, as the manifest does not know about them.
+    pub fn purge_dropped_column_families(&self) -> Result<Vec<usize>> {
+        let mut dropped = self.dropped_column_families.lock();
+        if !self.file_deletions_enabled() {
+            return Ok(Vec::new());
+        }
+        let mut removed = Vec::new();
+        let mut result = Ok(());
+        dropped.retain(|cf| {
+            // iterators hold the SSTs they read, compactions and point reads the whole state
+            let in_use = Arc::strong_count(&cf.state) > 1
+                || cf
+                    .state
+                    .sstables
+                    .values()
+                    .any(|sst| Arc::strong_count(sst) > 1);
+            if in_use || result.is_err() {
+                return true;
+            }
+            for sst_id in cf.state.sst_ids() {
+                // already deleted by a purge that failed on a later SST
+                if !self.fs.exists(&self.path_of_sst(*sst_id)) {
+                    continue;
+                }
+                if let Err(e) = self.fs.remove_file(&self.path_of_sst(*sst_id)) {
+                    result = Err(e);
+                    return true;
+                }
+                removed.push(*sst_id);
+            }
+            db_log!(
+                self.options(),
+                Info,
+                "deleted the {} SSTs of dropped column family {}",
+                cf.state.sstables.len(),
+                cf.name
+            );
+            false
+        });
+        result?;
+        if !removed.is_empty() {
+            self.sync_dir()?;
+        }
+        Ok(removed)
+    }
+
+    /// Whether a dropped column family still has SSTs to delete.
+    pub(crate) fn has_dropped_column_families(&self) -> bool {
+        !self.dropped_column_families.lock().is_empty()
+    }
+
+    /// The LSM tree of the column family `cf_id`, `None` once it is dropped.
+    pub(crate) fn column_family_state(&self, cf_id: u32) -> Option<Arc<LsmStorageState>> {
+        if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+            return Some(self.state.read().clone());
+        }
+        self.column_families
+            .read()
+            .get(&cf_id)
+            .map(|cf| cf.state.clone())
+    }
+
+    /// Replace the LSM tree of the column family `cf_id`. Must be called with the state lock.
+    pub(crate) fn set_column_family_state(&self, cf_id: u32, state: LsmStorageState) {
+        if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+            *self.state.write() = Arc::new(state);
+        } else if let Some(cf) = self.column_families.write().get_mut(&cf_id) {
+            cf.state = Arc::new(state);
+        }
+    }
+
+    /// The options of the column family `cf_id`, resolved against the ones of the database. A column family dropped
+    /// meanwhile gets the ones of the database.
+    pub(crate) fn column_family_options(&self, cf_id: u32) -> Arc<LsmStorageOptions> {
+        let options = self.options();
+        match self.column_families.read().get(&cf_id) {
+            Some(cf) => Arc::new(cf.options.resolve(&options)),
+            None => Arc::clone(&options),
+        }
+    }
+
+    /// The compaction controller of the column family `cf_id`.
+    pub(crate) fn column_family_compaction_controller(
+        &self,
+        cf_id: u32,
+    ) -> Arc<CompactionController> {
+        let own_controller = self
+            .column_families
+            .read()
+            .get(&cf_id)
+            .and_then(|cf| cf.options.compaction_controller(&self.options()));
+        match own_controller {
+            Some(controller) => Arc::new(controller),
+            None => Arc::clone(&self.compaction_controller()),
+        }
+    }
+
+    /// The LSM trees of all the column families, the default one first.
+    pub(crate) fn all_column_family_states(&self) -> Vec<Arc<LsmStorageState>> {
+        let state = self.state.read();
+        std::iter::once(state.clone())
+            .chain(
+                self.column_families
+                    .read()
+                    .values()
+                    .map(|cf| cf.state.clone()),
+            )
+            .collect()
+    }
+
+    /// The SSTs of all the column families.
+    pub(crate) fn live_sst_ids(&self) -> BTreeSet<usize> {
+        self.all_column_family_states()
+            .iter()
+            .flat_map(|state| state.sst_ids().copied().collect::<Vec<_>>())
+            .collect()
+    }
+
+    fn cf_state(&self, cf: &ColumnFamily) -> Result<Arc<LsmStorageState>> {
+        match self.column_family_state(cf.id) {
+            Some(state) => Ok(state),
+            None => bail!("column family {} was dropped", cf.name),
+        }
+    }
+
+    /// The options `cf` was created with, none set for the default one.
+    fn cf_options(&self, cf: &ColumnFamily) -> Result<ColumnFamilyOptions> {
+        if cf.is_default() {
+            return Ok(ColumnFamilyOptions::default());
+        }
+        match self.column_families.read().get(&cf.id) {
+            Some(state) => Ok(state.options.clone()),
+            None => bail!("column family {} was dropped", cf.name),
+        }
+    }
+
+    /// Same as `write_batch`, in the column family `cf`.
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.write_cf_batches_with_new_ts(&[(cf.id, batch)], None)
+            .map(|_| ())
+    }
+
+    /// Write the batches of several column families atomically. They go to the WAL as a single record and share a
+    /// commit ts, so that readers see all of them or none, and recovery keeps all of them or none.
+    pub fn write_batch_multi_cf<T: AsRef<[u8]>>(
+        &self,
+        batches: &[(&ColumnFamily, &[WriteBatchRecord<T>])],
+    ) -> Result<()> {
+        let batches = batches
+            .iter()
+            .map(|(cf, batch)| (cf.id, *batch))
+            .collect::<Vec<_>>();
+        self.write_cf_batches_with_new_ts(&batches, None)
+            .map(|_| ())
+    }
+
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch_cf(cf, &[WriteBatchRecord::Put(key, value)])
+    }
+
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.write_batch_cf(cf, &[WriteBatchRecord::Del(key)])
+    }
+
+    /// Same as `get`, in the column family `cf`. The row cache only holds the default column family.
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        if cf.is_default() {
+            return self.get(key);
+        }
+        self.get_cf_with_ts(cf, key, None)
+    }
+
+    /// Same as `get_with_snapshot`, in the column family `cf`. Reads of several column families through the same
+    /// snapshot see the batches written across them entirely or not at all.
+    pub fn get_cf_with_snapshot(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        snapshot: &Snapshot,
+    ) -> Result<Option<Bytes>> {
+        if cf.is_default() {
+            return self.get_with_snapshot(key, snapshot);
+        }
+        self.get_cf_with_ts(cf, key, Some(snapshot.read_ts()))
+    }
+
+    /// The value of a key in a column family besides the default one at `read_ts`, the latest commit ts if `None`.
+    fn get_cf_with_ts(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        read_ts: Option<u64>,
+    ) -> Result<Option<Bytes>> {
+        let started = Instant::now();
+        let snapshot = self.cf_state(cf)?;
+        // see `get_pinned`
+        let read_ts = read_ts.unwrap_or_else(|| self.mvcc().latest_commit_ts());
+        let value = match Self::get_from_memtables(&snapshot, key, read_ts) {
+            Some(value) => Some(value),
+            None => self
+                .get_from_ssts(&snapshot, key, read_ts)?
+                .map(|value| value.to_bytes()),
+        };
+        let value = value.filter(|value| !value.is_empty());
+        self.record_get(started, key, value.as_deref());
+        Ok(value)
+    }
+
+    /// Same as `scan`, in the column family `cf`.
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        if cf.is_default() {
+            return self.scan(lower, upper);
+        }
+        let snapshot = self.cf_state(cf)?;
+        let read_ts = self.mvcc().latest_commit_ts();
+        Self::scan_state(&snapshot, lower, upper, read_ts)
+    }
+
+    /// Same as `scan_with_snapshot`, in the column family `cf`.
+    pub fn scan_cf_with_snapshot(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        if cf.is_default() {
+            return self.scan_with_snapshot(lower, upper, snapshot);
+        }
+        let state = self.cf_state(cf)?;
+        Self::scan_state(&state, lower, upper, snapshot.read_ts())
+    }
+
+    /// Open the SSTs of the column families recovered from the manifest, reusing the ones in `opened`, and return
+    /// their largest ts. `compaction_controller` is the one of the database.
+    pub(crate) fn open_column_family_ssts(
+        context: &SstOpenContext<'_>,
+        column_families: &mut BTreeMap<u32, ReplayedColumnFamily>,
+        compaction_controller: &CompactionController,
+    ) -> Result<u64> {
+        let mut max_ts = 0;
+        for cf in column_families.values_mut() {
+            let own_controller = cf.options.compaction_controller(context.options);
+            max_ts = max_ts.max(Self::open_ssts(
+                context,
+                &mut cf.state,
+                own_controller.as_ref().unwrap_or(compaction_controller),
+            )?);
+        }
+        Ok(max_ts)
+    }
+
+    /// Recover the writes `records` of the WAL `wal_id` hold to other column families than the default one into
+    /// immutable memtables, unless they were flushed. Returns whether any was recovered.
+    pub(crate) fn recover_column_family_memtables(
+        column_families: &mut BTreeMap<u32, ReplayedColumnFamily>,
+        wal_id: usize,
+        records: &[WalRecord],
+    ) -> bool {
+        let mut recovered = false;
+        for (cf_id, cf) in column_families.iter_mut() {
+            if wal_id < cf.oldest_memtable_id {
+                continue;
+            }
+            let memtable = MemTable::from_wal_records(wal_id, *cf_id, records);
+            if !memtable.is_empty() {
+                cf.state.imm_memtables.insert(0, Arc::new(memtable));
+                recovered = true;
+            }
+        }
+        recovered
+    }
+}
+
+impl MiniLsm {
+    pub fn cf(&self, name: &str) -> Option<ColumnFamily> {
+        self.inner.cf(name)
+    }
+
+    pub fn list_cfs(&self) -> Vec<String> {
+        self.inner.list_cfs()
+    }
+
+    pub fn create_cf(&self, name: &str) -> Result<ColumnFamily> {
+        self.inner.create_cf(name)
+    }
+
+    pub fn create_cf_with_options(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily> {
+        self.inner.create_cf_with_options(name, options)
+    }
+
+    pub fn drop_cf(&self, name: &str) -> Result<()> {
+        self.inner.drop_cf(name)
+    }
+
+    pub fn purge_dropped_column_families(&self) -> Result<Vec<usize>> {
+        self.inner.purge_dropped_column_families()
+    }
+
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.inner.write_batch_cf(cf, batch)
+    }
+
+    pub fn write_batch_multi_cf<T: AsRef<[u8]>>(
+        &self,
+        batches: &[(&ColumnFamily, &[WriteBatchRecord<T>])],
+    ) -> Result<()> {
+        self.inner.write_batch_multi_cf(batches)
+    }
+
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put_cf(cf, key, value)
+    }
+
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.inner.delete_cf(cf, key)
+    }
+
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_cf(cf, key)
+    }
+
+    pub fn get_cf_with_snapshot(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        snapshot: &Snapshot,
+    ) -> Result<Option<Bytes>> {
+        self.inner.get_cf_with_snapshot(cf, key, snapshot)
+    }
+
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_cf(cf, lower, upper)
+    }
+
+    pub fn scan_cf_with_snapshot(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_cf_with_snapshot(cf, lower, upper, snapshot)
+    }
+}
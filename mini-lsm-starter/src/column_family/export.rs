@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{ColumnFamily, ColumnFamilyOptions, ColumnFamilyState};
+use crate::fs::FileSystem;
+use crate::logger::db_log;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState, MiniLsm, SstOpenContext};
+use crate::manifest::ManifestRecord;
+use crate::mem_table::MemTable;
+use crate::table::FileChecksum;
+
+/// The file of an export describing it, written once all of its SSTs are.
+const EXPORT_METADATA_FILE_NAME: &str = "EXPORT_METADATA";
+
+/// A column family exported by `export_cf`: its SSTs, named by their ids in the database they were exported from,
+/// and how they are laid out in its LSM tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedColumnFamily {
+    pub name: String,
+    pub options: ColumnFamilyOptions,
+    pub l0_sstables: Vec<usize>,
+    pub levels: Vec<(usize, Vec<usize>)>,
+    pub file_checksums: HashMap<usize, FileChecksum>,
+    /// The largest commit ts of the exported versions.
+    pub max_ts: u64,
+}
+
+impl ExportedColumnFamily {
+    /// Read the export in the directory `path`.
+    pub fn read(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().join(EXPORT_METADATA_FILE_NAME);
+        let json = fs
+            .read(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    fn sst_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.l0_sstables
+            .iter()
+            .chain(self.levels.iter().flat_map(|(_, ssts)| ssts))
+            .copied()
+    }
+}
+
+/// Copy the file `from` to `to`, which must not exist, and return the checksum of its data.
+fn copy_file(fs: &dyn FileSystem, from: &Path, to: &Path) -> Result<FileChecksum> {
+    let data = fs
+        .read(from)
+        .with_context(|| format!("failed to read {}", from.display()))?;
+    let mut file = fs
+        .create_new(to)
+        .with_context(|| format!("failed to create {}", to.display()))?;
+    file.write_all(&data)?;
+    file.sync()?;
+    Ok(FileChecksum::of_data(&data))
+}
+
+impl LsmStorageInner {
+    /// Copy the SSTs of the column family `cf` to the directory `path`, created if missing, along with the
+    /// metadata `import_cf` needs to add them to another database. Its memtables are flushed first, and nothing
+    /// is compacted meanwhile.
+    pub fn export_cf(
+        &self,
+        cf: &ColumnFamily,
+        path: impl AsRef<Path>,
+    ) -> Result<ExportedColumnFamily> {
+        let path = path.as_ref();
+        if self.fs.exists(&path.join(EXPORT_METADATA_FILE_NAME)) {
+            bail!("{} already holds an export", path.display());
+        }
+        let options = self.cf_options(cf)?;
+        self.flush_column_family(cf)?;
+        // compactions would delete the SSTs being copied
+        let _compaction_lock = self.compaction_lock.lock();
+        let state = self.cf_state(cf)?;
+        // the blob files would need new ids too, which the SSTs pointing into them have no way to follow
+        if state.blob_file_ids().next().is_some() {
+            bail!("column family {} has blob files", cf.name());
+        }
+        self.fs.create_dir_all(path)?;
+        let mut file_checksums = HashMap::new();
+        for sst_id in state.sst_ids() {
+            let checksum = copy_file(
+                self.fs.as_ref(),
+                &self.path_of_sst(*sst_id),
+                &Self::path_of_sst_static(path, *sst_id),
+            )?;
+            file_checksums.insert(*sst_id, checksum);
+        }
+        let exported = ExportedColumnFamily {
+            name: cf.name().to_string(),
+            options,
+            l0_sstables: state.l0_sstables.clone(),
+            levels: state.levels.clone(),
+            file_checksums,
+            max_ts: state
+                .sstables
+                .values()
+                .map(|sst| sst.max_ts())
+                .max()
+                .unwrap_or(0),
+        };
+        let mut file = self.fs.create_new(&path.join(EXPORT_METADATA_FILE_NAME))?;
+        file.write_all(&serde_json::to_vec_pretty(&exported)?)?;
+        file.sync()?;
+        self.fs.sync_dir(path)?;
+        db_log!(
+            self.options(),
+            Info,
+            "exported column family {} with {} SSTs to {}",
+            cf.name(),
+            state.sstables.len(),
+            path.display()
+        );
+        Ok(exported)
+    }
+
+    /// Create the column family `name` from the export in the directory `path`, copying its SSTs under new ids.
+    /// The data is not rewritten: instead of a sequence number assigned to the whole import, the versions keep the
+    /// commit ts of the database they were exported from, and the commit ts of this one moves past them so that
+    /// they are visible at once. The column family is recorded in the manifest by a single record.
+    pub fn import_cf(&self, name: &str, path: impl AsRef<Path>) -> Result<ColumnFamily> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let exported = ExportedColumnFamily::read(self.fs.as_ref(), path)?;
+        if name.is_empty() {
+            bail!("the name of a column family cannot be empty");
+        }
+        if self.cf(name).is_some() {
+            bail!("column family {} already exists", name);
+        }
+        let db_options = self.options();
+        exported.options.validate(&db_options)?;
+        let options = exported.options.resolve(&db_options);
+        let compaction_controller = match exported.options.compaction_controller(&db_options) {
+            Some(controller) => Arc::new(controller),
+            None => Arc::clone(&self.compaction_controller()),
+        };
+        let mut state = LsmStorageState::create(&options);
+        if compaction_controller.flush_to_l0() {
+            let level_ids = |levels: &[(usize, Vec<usize>)]| {
+                levels.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+            };
+            if level_ids(&exported.levels) != level_ids(&state.levels) {
+                bail!(
+                    "the {} levels of the export do not fit the compaction options of the column family",
+                    exported.levels.len()
+                );
+            }
+        } else if !exported.l0_sstables.is_empty() {
+            bail!("the export has L0 SSTs, which tiered compaction does not keep");
+        }
+
+        // copied under new ids, not deleted as obsolete before they are recorded; the ones left behind by a failure
+        // are deleted as obsolete later
+        let _compaction_lock = self.compaction_lock.lock();
+        let mut new_ids = HashMap::new();
+        let mut file_checksums = HashMap::new();
+        for sst_id in exported.sst_ids() {
+            let new_id = self.next_sst_id();
+            let from = Self::path_of_sst_static(path, sst_id);
+            let checksum = copy_file(self.fs.as_ref(), &from, &self.path_of_sst(new_id))?;
+            if let Some(expected) = exported.file_checksums.get(&sst_id) {
+                if *expected != checksum {
+                    bail!(
+                        "{} does not match the checksum of the export",
+                        from.display()
+                    );
+                }
+            }
+            new_ids.insert(sst_id, new_id);
+            file_checksums.insert(new_id, checksum);
+        }
+        state.l0_sstables = exported.l0_sstables.iter().map(|id| new_ids[id]).collect();
+        state.levels = exported
+            .levels
+            .iter()
+            .map(|(level, ssts)| {
+                let ssts = ssts.iter().map(|id| new_ids[id]).collect::<Vec<_>>();
+                // tiers are named after one of their SSTs
+                let level = if compaction_controller.flush_to_l0() {
+                    *level
+                } else {
+                    new_ids
+                        .get(level)
+                        .or(ssts.first())
+                        .copied()
+                        .unwrap_or(*level)
+                };
+                (level, ssts)
+            })
+            .collect();
+        let context = SstOpenContext {
+            fs: self.fs.as_ref(),
+            path: &self.path,
+            block_cache: &self.block_cache,
+            file_checksums: &file_checksums,
+            options: &options,
+            opened: &HashMap::new(),
+        };
+        let max_ts = Self::open_ssts(&context, &mut state, &compaction_controller)?;
+        self.sync_dir()?;
+
+        let state_lock = self.state_lock.lock();
+        if self.cf(name).is_some() {
+            bail!("column family {} already exists", name);
+        }
+        let id = self
+            .next_column_family_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let memtable_id = self.state.read().memtable.id();
+        state.memtable = Arc::new(MemTable::create(memtable_id));
+        let record = ManifestRecord::ColumnFamilySnapshot {
+            id,
+            name: name.to_string(),
+            l0_sstables: state.l0_sstables.clone(),
+            levels: state.levels.clone(),
+            oldest_memtable_id: memtable_id,
+            options: exported.options.clone(),
+        };
+        let new_ssts = state.sstables.values().cloned().collect::<Vec<_>>();
+        // installed first, so that a manifest rolled over by the record includes it
+        self.column_families.write().insert(
+            id,
+            ColumnFamilyState {
+                name: name.to_string(),
+                options: exported.options,
+                state: Arc::new(state),
+            },
+        );
+        if let Err(e) = self.add_manifest_records_with_ssts(&state_lock, vec![record], &new_ssts) {
+            self.column_families.write().remove(&id);
+            return Err(e);
+        }
+        drop(state_lock);
+        let mvcc = self.mvcc();
+        mvcc.update_commit_ts(mvcc.latest_commit_ts().max(max_ts));
+        db_log!(
+            self.options(),
+            Info,
+            "imported column family {} with {} SSTs from {}",
+            name,
+            new_ssts.len(),
+            path.display()
+        );
+        Ok(ColumnFamily::new(id, name))
+    }
+
+    /// Flush the memtables of `cf`, along with the ones of the other column families frozen with them.
+    pub(crate) fn flush_column_family(&self, cf: &ColumnFamily) -> Result<()> {
+        if !self.cf_state(cf)?.memtable.is_empty() {
+            self.force_freeze_memtable(&self.state_lock.lock())?;
+        }
+        // the memtables are flushed in the order they were frozen in
+        while !self.cf_state(cf)?.imm_memtables.is_empty() {
+            self.force_flush_next_imm_memtable()?;
+        }
+        Ok(())
+    }
+}
+
+impl MiniLsm {
+    pub fn export_cf(
+        &self,
+        cf: &ColumnFamily,
+        path: impl AsRef<Path>,
+    ) -> Result<ExportedColumnFamily> {
+        self.inner.export_cf(cf, path)
+    }
+
+    pub fn import_cf(&self, name: &str, path: impl AsRef<Path>) -> Result<ColumnFamily> {
+        self.inner.import_cf(name, path)
+    }
+}
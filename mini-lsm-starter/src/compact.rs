@@ -1,25 +1,46 @@
+mod fifo;
 mod leveled;
+mod partitioner;
 mod simple_leveled;
+mod stats;
 mod tiered;
 
+use crate::blob::{BlobFileBuilder, BlobReference};
+use crate::block::CachePriority;
+use crate::clock::Instant;
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::logger::{db_log, DbLogger};
+use crate::table::FileObject;
 use crate::table::SsTableBuilder;
 use crate::table::SsTableIterator;
-use std::path::Path;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use std::vec;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+pub use fifo::{FifoCompactionController, FifoCompactionOptions, FifoCompactionTask};
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+pub use partitioner::{FixedPrefixPartitioner, SstPartitioner};
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
+pub(crate) use stats::CompactionStatsRecorder;
+pub use stats::{CompactionStats, LevelCompactionCounters, LevelStats};
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
 
-use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::event_listener::CompactionJobInfo;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::scheduler::{BackgroundScheduler, JobPriority};
+use crate::statistics::Ticker;
 use crate::table::SsTable;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,31 +48,169 @@ pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
     Simple(SimpleLeveledCompactionTask),
+    Fifo(FifoCompactionTask),
     ForceFullCompaction {
         l0_sstables: Vec<usize>,
         l1_sstables: Vec<usize>,
+        /// Whether the levels below L1 hold no SST, so that the tombstones can be dropped.
+        #[serde(default)]
+        is_l1_bottom_level: bool,
     },
 }
 
 impl CompactionTask {
+    /// The SSTs read by the task.
+    pub(crate) fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+                ..
+            } => [&l0_sstables[..], &l1_sstables[..]].concat(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => [&upper_level_sst_ids[..], &lower_level_sst_ids[..]].concat(),
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, ssts)| ssts.iter().copied())
+                .collect(),
+            CompactionTask::Fifo(task) => task.sst_ids_to_delete.clone(),
+        }
+    }
+
+    /// The SSTs read from the upper level and from the level written to. A tiered compaction writes to the position
+    /// of its oldest tier.
+    fn upper_and_lower_sst_ids(&self) -> (Vec<usize>, Vec<usize>) {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+                ..
+            } => (l0_sstables.clone(), l1_sstables.clone()),
+            CompactionTask::Leveled(task) => (
+                task.upper_level_sst_ids.clone(),
+                task.lower_level_sst_ids.clone(),
+            ),
+            CompactionTask::Simple(task) => (
+                task.upper_level_sst_ids.clone(),
+                task.lower_level_sst_ids.clone(),
+            ),
+            CompactionTask::Tiered(task) => match task.tiers.split_last() {
+                Some(((_, oldest_tier), upper_tiers)) => (
+                    upper_tiers
+                        .iter()
+                        .flat_map(|(_, ssts)| ssts.iter().copied())
+                        .collect(),
+                    oldest_tier.clone(),
+                ),
+                None => (Vec::new(), Vec::new()),
+            },
+            CompactionTask::Fifo(_) => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Compactions reading L0 run before the others.
+    fn job_priority(&self) -> JobPriority {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } | CompactionTask::Fifo(_) => {
+                JobPriority::L0Compaction
+            }
+            CompactionTask::Leveled(task) if task.upper_level.is_none() => {
+                JobPriority::L0Compaction
+            }
+            CompactionTask::Simple(task) if task.upper_level.is_none() => JobPriority::L0Compaction,
+            _ => JobPriority::Compaction,
+        }
+    }
+
+    /// The level the task writes to, which picks the per-level options of its output. A tiered compaction writes
+    /// the bottom level when it includes the bottom tier, and L1 otherwise.
+    fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(task) if task.bottom_tier_included => usize::MAX,
+            CompactionTask::Tiered(_) => 1,
+            CompactionTask::Fifo(_) => 0,
+        }
+    }
+
     fn compact_to_bottom_level(&self) -> bool {
         match self {
-            CompactionTask::ForceFullCompaction { .. } => true,
+            CompactionTask::ForceFullCompaction {
+                is_l1_bottom_level, ..
+            } => *is_l1_bottom_level,
             CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
             CompactionTask::Simple(task) => task.is_lower_level_bottom_level,
             CompactionTask::Tiered(task) => task.bottom_tier_included,
+            CompactionTask::Fifo(_) => false,
         }
     }
 }
 
+/// A compaction `plan_compactions` expects the background jobs to run.
+#[derive(Debug)]
+pub struct PlannedCompaction {
+    pub task: CompactionTask,
+    pub priority: JobPriority,
+    /// The level written to, numbered like `CompactionJobInfo::output_level`.
+    pub output_level: usize,
+    pub input_bytes: u64,
+    /// The input less the garbage a compaction to the bottom level drops at the current watermark. SSTs moved
+    /// without rewriting them keep their size.
+    pub estimated_output_bytes: u64,
+    pub is_trivial_move: bool,
+    /// The SSTs the compaction writes. A rewritten output is simulated by a single SST spanning the key range of
+    /// the input, with an id above every live SST, which the compactions planned after it may read.
+    pub output_sst_ids: Vec<usize>,
+}
+
 pub(crate) enum CompactionController {
     Leveled(LeveledCompactionController),
     Tiered(TieredCompactionController),
     Simple(SimpleLeveledCompactionController),
+    Fifo(FifoCompactionController),
     NoCompaction,
 }
 
 impl CompactionController {
+    pub(crate) fn new(options: &LsmStorageOptions) -> Self {
+        match &options.compaction_options {
+            CompactionOptions::Leveled(leveled_options) => CompactionController::Leveled(
+                LeveledCompactionController::new(leveled_options.clone())
+                    .with_periodic_compaction_seconds(options.periodic_compaction_seconds)
+                    .with_tombstone_compaction_ratio(options.tombstone_compaction_ratio)
+                    .with_clock(options.clock_or_default())
+                    .with_logger(DbLogger::new(options)),
+            ),
+            CompactionOptions::Tiered(tiered_options) => CompactionController::Tiered(
+                TieredCompactionController::new(tiered_options.clone())
+                    .with_periodic_compaction_seconds(options.periodic_compaction_seconds)
+                    .with_tombstone_compaction_ratio(options.tombstone_compaction_ratio)
+                    .with_clock(options.clock_or_default())
+                    .with_logger(DbLogger::new(options)),
+            ),
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::Fifo(fifo_options) => CompactionController::Fifo(
+                FifoCompactionController::new(fifo_options.clone())
+                    .with_clock(options.clock_or_default())
+                    .with_logger(DbLogger::new(options)),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
     pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
@@ -63,6 +222,9 @@ impl CompactionController {
             CompactionController::Tiered(ctrl) => ctrl
                 .generate_compaction_task(snapshot)
                 .map(CompactionTask::Tiered),
+            CompactionController::Fifo(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(CompactionTask::Fifo),
             CompactionController::NoCompaction => unreachable!(),
         }
     }
@@ -83,170 +245,1460 @@ impl CompactionController {
             (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
+            (CompactionController::Fifo(ctrl), CompactionTask::Fifo(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            (
+                _,
+                CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                    ..
+                },
+            ) => {
+                let mut snapshot = snapshot.clone();
+                let compacted = l0_sstables.iter().collect::<HashSet<_>>();
+                snapshot.l0_sstables.retain(|id| !compacted.contains(id));
+                assert_eq!(&snapshot.levels[0].1, l1_sstables);
+                snapshot.levels[0].1 = output.to_vec();
+                let mut files_to_remove = l0_sstables.clone();
+                files_to_remove.extend(l1_sstables);
+                (snapshot, files_to_remove)
+            }
             _ => unreachable!(),
         }
     }
 }
 
+/// Stops the merged input of a subcompaction at the user key where the next one starts.
+struct SubcompactionIterator<I> {
+    iter: I,
+    end: Option<Bytes>,
+}
+
+impl<I> SubcompactionIterator<I> {
+    fn new(iter: I, end: Option<&[u8]>) -> Self {
+        Self {
+            iter,
+            end: end.map(Bytes::copy_from_slice),
+        }
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for SubcompactionIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn blob_index_table(&self) -> Option<&SsTable> {
+        self.iter.blob_index_table()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+            && self
+                .end
+                .as_ref()
+                .is_none_or(|end| self.iter.key().key_ref() < &end[..])
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
+
+/// Whether an SST was built at least `period` seconds before `now`. Periodic compaction is disabled by a zero
+/// period, and tables without a recorded creation time are never due.
+pub(crate) fn due_for_periodic_compaction(sst: &SsTable, period: u64, now: u64) -> bool {
+    let creation_time = sst.properties().creation_time;
+    period > 0 && creation_time > 0 && creation_time + period <= now
+}
+
+/// Whether the share of tombstones in an SST is above `ratio`. Tombstone-triggered compaction is disabled by a zero
+/// ratio.
+pub(crate) fn dense_in_tombstones(sst: &SsTable, ratio: f64) -> bool {
+    ratio > 0.0 && sst.properties().deletion_ratio() > ratio
+}
+
+/// Validate a selection of SSTs to compact with `compact_files`, see there.
+fn compact_files_task(
+    snapshot: &LsmStorageState,
+    sst_ids: &[usize],
+    output_level: usize,
+    max_levels: usize,
+) -> Result<LeveledCompactionTask> {
+    if sst_ids.is_empty() {
+        bail!("no SST to compact");
+    }
+    if output_level == 0 || output_level > max_levels {
+        bail!("output level {} not in 1..={}", output_level, max_levels);
+    }
+    let level_ssts = |level: usize| {
+        if level == 0 {
+            &snapshot.l0_sstables
+        } else {
+            &snapshot.levels[level - 1].1
+        }
+    };
+    let selected = sst_ids.iter().copied().collect::<HashSet<_>>();
+    if selected.len() != sst_ids.len() {
+        bail!("duplicated SSTs in {:?}", sst_ids);
+    }
+    let mut upper_level = output_level;
+    for sst_id in sst_ids {
+        let Some(level) = (0..=max_levels).find(|level| level_ssts(*level).contains(sst_id)) else {
+            bail!("{}.sst is not a live SST", sst_id);
+        };
+        if level > output_level {
+            bail!("{}.sst is in L{}, below the output level", sst_id, level);
+        }
+        if level != output_level {
+            if upper_level != output_level && upper_level != level {
+                bail!("the SSTs must come from at most one level besides the output level");
+            }
+            upper_level = level;
+        }
+    }
+    // in the order of their levels, newest first for L0
+    let selected_in = |level: usize| {
+        level_ssts(level)
+            .iter()
+            .copied()
+            .filter(|id| selected.contains(id))
+            .collect::<Vec<_>>()
+    };
+    let upper_level_sst_ids = selected_in(upper_level);
+    let lower_level_sst_ids = if upper_level == output_level {
+        Vec::new()
+    } else {
+        selected_in(output_level)
+    };
+
+    // L0 SSTs overlap each other: an L0 SST compacted below an older one would be shadowed by it
+    if upper_level == 0 {
+        let oldest =
+            &snapshot.l0_sstables[snapshot.l0_sstables.len() - upper_level_sst_ids.len()..];
+        if oldest != upper_level_sst_ids {
+            bail!(
+                "L0 SSTs must be compacted oldest first, expected {:?}",
+                oldest
+            );
+        }
+    }
+
+    let first_key = sst_ids
+        .iter()
+        .map(|id| snapshot.sstables[id].first_key().key_ref())
+        .min()
+        .unwrap();
+    let last_key = sst_ids
+        .iter()
+        .map(|id| snapshot.sstables[id].last_key().key_ref())
+        .max()
+        .unwrap();
+    let overlapping = |level: usize| {
+        level_ssts(level)
+            .iter()
+            .copied()
+            .filter(|id| {
+                let sst = &snapshot.sstables[id];
+                sst.first_key().key_ref() <= last_key && sst.last_key().key_ref() >= first_key
+            })
+            .collect::<Vec<_>>()
+    };
+    for level in upper_level + 1..output_level {
+        let overlapping = overlapping(level);
+        if !overlapping.is_empty() {
+            bail!(
+                "{:?} in L{} overlap the selection and would end up above newer versions",
+                overlapping,
+                level
+            );
+        }
+    }
+    let missing = overlapping(output_level)
+        .into_iter()
+        .filter(|id| !selected.contains(id))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bail!(
+            "{:?} in L{} overlap the selection and must be compacted with it",
+            missing,
+            output_level
+        );
+    }
+
+    Ok(LeveledCompactionTask {
+        upper_level: (upper_level > 0).then_some(upper_level),
+        upper_level_sst_ids,
+        lower_level: output_level,
+        lower_level_sst_ids,
+        is_lower_level_bottom_level: output_level == max_levels,
+    })
+}
+
 impl CompactionController {
     pub fn flush_to_l0(&self) -> bool {
         matches!(
             self,
-            Self::Leveled(_) | Self::Simple(_) | Self::NoCompaction
+            Self::Leveled(_) | Self::Simple(_) | Self::Fifo(_) | Self::NoCompaction
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionOptions {
     /// Leveled compaction with partial compaction + dynamic level support (= RocksDB's Leveled
     /// Compaction)
     Leveled(LeveledCompactionOptions),
-    /// Tiered compaction (= RocksDB's universal compaction)
+    /// Tiered compaction (= RocksDB's universal compaction), also read from options spelled `Universal`
+    #[serde(alias = "Universal")]
     Tiered(TieredCompactionOptions),
+    /// FIFO compaction: the oldest SSTs are deleted once the data is too large or too old
+    Fifo(FifoCompactionOptions),
     /// Simple leveled compaction
     Simple(SimpleLeveledCompactionOptions),
     /// In no compaction mode (week 1), always flush to L0
     NoCompaction,
 }
 
+/// What a compaction does with the values in blob files.
+struct CompactionBlobs {
+    /// Whether any input points into a blob file, the output SSTs then do too.
+    blob_indexes: bool,
+    /// The blob files garbage collected, see `max_blob_space_amplification_percent`: their values the output
+    /// points to are moved to a new blob file written along with every output SST.
+    relocated: BTreeSet<usize>,
+}
+
 impl LsmStorageInner {
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
-        // 1.use margeiterator to merge all sstables
-        let mut sst_iters: Vec<Box<SsTableIterator>> = Vec::new();
-        let state = self.state.read();
-        match task {
-            CompactionTask::ForceFullCompaction {
-                l0_sstables,
-                l1_sstables,
-            } => {
-                for l0_sst in l0_sstables {
-                    let sst = state.sstables.get(l0_sst).unwrap().clone();
-                    sst_iters.push(Box::new(
-                        SsTableIterator::create_and_seek_to_first(sst).unwrap(),
-                    ));
+    /// Write the merged entries of `iter` into SSTs of about the target size of the output level, dropping the
+    /// versions no reader can see any more. All versions of a key go into the same SST so that a level never splits
+    /// a key across tables, and an SST also ends where the `sst_partitioner` asks for it.
+    /// On failure, the SSTs already written are deleted. `options` are the ones of the column family compacted.
+    /// The values in blob files stay there, the new SSTs point to them as the inputs did, except for the values of
+    /// the blob files `blobs` relocates.
+    fn compact_generate_sst_from_iter(
+        &self,
+        iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+        watermark: u64,
+        task: &CompactionTask,
+        options: &LsmStorageOptions,
+        blobs: &CompactionBlobs,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let mut new_ssts = Vec::new();
+        match self.write_compaction_output(iter, watermark, task, options, blobs, &mut new_ssts) {
+            Ok(()) => Ok(new_ssts),
+            Err(e) => {
+                self.remove_unused_ssts(&new_ssts);
+                Err(e)
+            }
+        }
+    }
+
+    fn write_compaction_output(
+        &self,
+        mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+        watermark: u64,
+        task: &CompactionTask,
+        options: &LsmStorageOptions,
+        blobs: &CompactionBlobs,
+        new_ssts: &mut Vec<Arc<SsTable>>,
+    ) -> Result<()> {
+        let compact_to_bottom_level = task.compact_to_bottom_level();
+        let block_size = options.block_size;
+        let target_sst_size = options.target_sst_size_of_level(task.output_level());
+        let compression = options.compression_of_level(task.output_level());
+        let partitioner = options.sst_partitioner.as_deref();
+        let mut builder: Option<SsTableBuilder> = None;
+        // the relocated values of `builder`, an unfinished one is left to `delete_obsolete_files` on failure
+        let mut blob_file: Option<BlobFileBuilder> = None;
+        let mut last_key = Vec::<u8>::new();
+        // the last user key written to `builder`
+        let mut last_added_key = Vec::<u8>::new();
+        let mut kept_below_watermark = false;
+        // bytes read but not yet charged to the rate limiter, charged a block at a time
+        let mut bytes_read = 0;
+        while iter.is_valid() {
+            // nothing is installed yet, a cancelled compaction can stop anywhere
+            if self.compactions_cancelled.load(Ordering::Acquire) {
+                bail!("compaction cancelled");
+            }
+            let key = iter.key();
+            if let Some(rate_limiter) = &options.rate_limiter {
+                bytes_read += key.raw_len() + iter.value().len();
+                if bytes_read >= block_size {
+                    rate_limiter.request(bytes_read as u64);
+                    bytes_read = 0;
+                }
+            }
+            let same_as_last_key = key.key_ref() == last_key;
+            if !same_as_last_key {
+                last_key.clear();
+                last_key.extend(key.key_ref());
+                kept_below_watermark = false;
+            }
+            let keep = if key.ts() > watermark {
+                true
+            } else if !kept_below_watermark {
+                // only the latest version at or below the watermark is visible, and a tombstone only needs to be
+                // kept while an older version below the bottom level may still exist
+                kept_below_watermark = true;
+                !(compact_to_bottom_level && iter.value().is_empty())
+            } else {
+                false
+            };
+            if keep {
+                let split = match (&builder, partitioner) {
+                    (Some(_), _) if same_as_last_key => false,
+                    (Some(builder), _) if builder.estimated_size() >= target_sst_size => true,
+                    (Some(_), Some(partitioner)) => {
+                        partitioner.should_partition(&last_added_key, key.key_ref())
+                    }
+                    _ => false,
+                };
+                if split {
+                    let full = builder.take().unwrap();
+                    new_ssts.push(self.build_compacted_sst(
+                        full,
+                        blob_file.take(),
+                        compact_to_bottom_level,
+                    )?);
+                }
+                if builder.is_none() {
+                    let new_builder = SsTableBuilder::new(block_size)
+                        .with_compression(compression)
+                        .with_db_identity(self.db_id(), self.session_id())
+                        .with_file_system(self.fs.clone())
+                        .with_clock(self.clock.clone());
+                    builder = Some(if blobs.blob_indexes {
+                        new_builder.with_blob_indexes()
+                    } else {
+                        new_builder
+                    });
                 }
-                for l1_sst in l1_sstables {
-                    let sst = state.sstables.get(l1_sst).unwrap().clone();
-                    sst_iters.push(Box::new(
-                        SsTableIterator::create_and_seek_to_first(sst).unwrap(),
-                    ));
+                if let Some(table) = iter.blob_index_table() {
+                    let mut reference = BlobReference::decode(iter.value())?;
+                    if blobs.relocated.contains(&reference.file_id()) {
+                        let blob_file = match &mut blob_file {
+                            Some(blob_file) => blob_file,
+                            None => blob_file.insert(self.create_blob_file()?),
+                        };
+                        reference = blob_file.relocate(table, &reference)?;
+                    }
+                    builder
+                        .as_mut()
+                        .unwrap()
+                        .add_blob_reference(key, &reference);
+                } else {
+                    builder.as_mut().unwrap().add(key, iter.value());
+                }
+                if !same_as_last_key {
+                    last_added_key.clear();
+                    last_added_key.extend(key.key_ref());
                 }
             }
-            _ => {}
+            iter.next()?;
         }
+        if let Some(builder) = builder {
+            new_ssts.push(self.build_compacted_sst(builder, blob_file, compact_to_bottom_level)?);
+        }
+        Ok(())
+    }
 
-        let mut merge_iter = MergeIterator::create(sst_iters);
-
-        // 2.write into new sstable by sst builder
-        let mut sst_builder = SsTableBuilder::new(self.options.block_size);
-        while merge_iter.is_valid() {
-            if !merge_iter.value().is_empty() {
-                sst_builder.add(merge_iter.key(), merge_iter.value());
+    /// Delete the SSTs written by a compaction that failed before installing them, and the blob files written
+    /// along with them.
+    fn remove_unused_ssts(&self, ssts: &[Arc<SsTable>]) {
+        let live_blob_file_ids = self.live_blob_file_ids();
+        for sst in ssts {
+            self.fs.remove_file(&self.path_of_sst(sst.sst_id())).ok();
+            for blob_file in sst.blob_files() {
+                if !live_blob_file_ids.contains(&blob_file.id()) {
+                    self.fs.remove_file(&self.path_of_blob(blob_file.id())).ok();
+                }
             }
-            merge_iter.next()?;
+        }
+    }
+
+    /// Build the SST of `builder`, after the blob file holding the values it relocated.
+    fn build_compacted_sst(
+        &self,
+        builder: SsTableBuilder,
+        blob_file: Option<BlobFileBuilder>,
+        compact_to_bottom_level: bool,
+    ) -> Result<Arc<SsTable>> {
+        if let Some(blob_file) = blob_file {
+            blob_file.finish()?;
         }
         let sst_id = self.next_sst_id();
-        let new_sst = sst_builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
+        let sst = builder
+            .build(
+                sst_id,
+                Some(self.block_cache.clone()),
+                self.path_of_sst(sst_id),
+            )?
+            .with_metadata_caching(self.options().metadata_caching)
+            .with_statistics(self.options().statistics.clone())
+            .with_cache_priority(if compact_to_bottom_level {
+                CachePriority::Bottom
+            } else {
+                CachePriority::Low
+            });
+        let mut sst = Self::open_blob_files(self.fs.as_ref(), &self.path, sst)?;
+        if compact_to_bottom_level && self.options().cold_bottom_level {
+            let path = self.path_of_sst(sst_id);
+            if self.fs.move_to_cold_storage(&path)? {
+                db_log!(self.options(), Info, "moved {}.sst to cold storage", sst_id);
+                sst.file = FileObject::open(self.fs.as_ref(), &path)?;
+            }
+        }
+        if let Some(rate_limiter) = &self.options().rate_limiter {
+            rate_limiter.request(sst.table_size());
+        }
+        Ok(Arc::new(sst))
+    }
+
+    fn compact(&self, cf_id: u32, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+        let Some(snapshot) = self.column_family_state(cf_id) else {
+            bail!("column family {} was dropped", cf_id);
+        };
+        let options = self.column_family_options(cf_id);
+        if let Some(ssts) = self.trivial_move_ssts(&snapshot, task, &options) {
+            db_log!(
+                self.options(),
+                Info,
+                "moving {:?} to the lower level without rewriting them",
+                ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>()
+            );
+            return Ok(ssts);
+        }
+        let watermark = self.mvcc().watermark();
+        self.mvcc().advance_gc_watermark(watermark);
+        let boundaries = self.subcompaction_boundaries(&snapshot, task);
+        if boundaries.is_empty() {
+            return self.compact_range(&snapshot, task, &options, watermark, None, None);
+        }
+
+        // every subcompaction covers the keys from one boundary to the next and writes its own SSTs, their outputs
+        // are installed together by a single manifest record
+        db_log!(
+            self.options(),
+            Info,
+            "running {} subcompactions split at {:?}",
+            boundaries.len() + 1,
+            boundaries
         );
+        let ranges = std::iter::once(None)
+            .chain(boundaries.iter().map(Some))
+            .zip(boundaries.iter().map(Some).chain(std::iter::once(None)))
+            .collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            let handles = ranges
+                .iter()
+                .map(|(begin, end)| {
+                    let (snapshot, options) = (&snapshot, &options);
+                    scope.spawn(move || {
+                        self.compact_range(
+                            snapshot,
+                            task,
+                            options,
+                            watermark,
+                            begin.map(|key| &key[..]),
+                            end.map(|key| &key[..]),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut new_ssts = Vec::new();
+            let mut result = Ok(());
+            for handle in handles {
+                match handle.join().expect("subcompaction panicked") {
+                    Ok(ssts) => new_ssts.extend(ssts),
+                    Err(e) => result = Err(e),
+                }
+            }
+            // the subcompactions that succeeded leave their output behind
+            if let Err(e) = result {
+                self.remove_unused_ssts(&new_ssts);
+                return Err(e);
+            }
+            Ok(new_ssts)
+        })
+    }
+
+    /// The input SSTs of a task that can be moved to the lower level as they are, sorted by key: the task reads no
+    /// SST of the lower level and its SSTs do not overlap each other, so that the lower level stays sorted and only
+    /// the manifest records the move. Compaction filters need the data to be rewritten, and so do SSTs the
+    /// `sst_partitioner` would cut, compressed differently from the lower level, or going to a bottom level kept in
+    /// cold storage.
+    fn trivial_move_ssts(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        options: &LsmStorageOptions,
+    ) -> Option<Vec<Arc<SsTable>>> {
+        let (upper_level, upper_level_sst_ids, lower_level, lower_level_sst_ids) = match task {
+            CompactionTask::Leveled(task) => (
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                task.lower_level,
+                &task.lower_level_sst_ids,
+            ),
+            CompactionTask::Simple(task) => (
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                task.lower_level,
+                &task.lower_level_sst_ids,
+            ),
+            _ => return None,
+        };
+        // a level compacted into itself, e.g. by periodic compaction, has to be rewritten
+        if upper_level_sst_ids.is_empty()
+            || !lower_level_sst_ids.is_empty()
+            || upper_level == Some(lower_level)
+            || !self.compaction_filters.lock().is_empty()
+            || (task.compact_to_bottom_level() && self.options().cold_bottom_level)
+        {
+            return None;
+        }
+        let mut ssts = upper_level_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].clone())
+            .collect::<Vec<_>>();
+        // tombstones reaching the bottom level are dropped by rewriting the SSTs
+        if task.compact_to_bottom_level()
+            && ssts.iter().any(|sst| sst.properties().num_deletions > 0)
+        {
+            return None;
+        }
+        let compression = options.compression_of_level(task.output_level());
+        if ssts
+            .iter()
+            .any(|sst| sst.properties().compression != compression)
+        {
+            return None;
+        }
+        if let Some(partitioner) = &options.sst_partitioner {
+            if ssts.iter().any(|sst| {
+                partitioner.should_partition(sst.first_key().key_ref(), sst.last_key().key_ref())
+            }) {
+                return None;
+            }
+        }
+        ssts.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+        ssts.windows(2)
+            .all(|pair| pair[0].last_key().key_ref() < pair[1].first_key().key_ref())
+            .then_some(ssts)
+    }
+
+    /// The user keys splitting a compaction into at most `max_subcompactions` key ranges, taken from the first
+    /// keys of the input SSTs so that every range holds about as many of them.
+    fn subcompaction_boundaries(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Vec<Bytes> {
+        let options = self.options();
+        // the subcompactions run on threads of their own
+        let max_subcompactions = if options.inline_background_jobs {
+            1
+        } else {
+            options.max_subcompactions
+        };
+        let mut first_keys = task
+            .input_sst_ids()
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key().key_ref())
+            .collect::<Vec<_>>();
+        first_keys.sort();
+        first_keys.dedup();
+        // the smallest first key starts the first range anyway
+        if max_subcompactions <= 1 || first_keys.len() <= 1 {
+            return Vec::new();
+        }
+        let candidates = &first_keys[1..];
+        let num_ranges = max_subcompactions.min(first_keys.len());
+        (1..num_ranges)
+            .map(|i| Bytes::copy_from_slice(candidates[i * candidates.len() / num_ranges]))
+            .collect()
+    }
 
-        Ok(vec![Arc::new(new_sst.unwrap())])
+    /// Compact the keys of the task in `[begin, end)`, unbounded when `None`.
+    fn compact_range(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        options: &LsmStorageOptions,
+        watermark: u64,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let ssts_of = |ids: &[usize]| -> Vec<Arc<SsTable>> {
+            ids.iter().map(|id| snapshot.sstables[id].clone()).collect()
+        };
+        let seek_key = begin.map(|key| KeySlice::from_slice(key, TS_RANGE_BEGIN));
+        // L0 SSTs overlap each other and are merged, the SSTs of a sorted level are concatenated
+        let l0_iter = |ids: &[usize]| -> Result<MergeIterator<SsTableIterator>> {
+            let mut iters = Vec::with_capacity(ids.len());
+            for sst in ssts_of(ids) {
+                iters.push(Box::new(match seek_key {
+                    Some(key) => SsTableIterator::create_and_seek_to_key(sst, key)?,
+                    None => SsTableIterator::create_and_seek_to_first(sst)?,
+                }));
+            }
+            Ok(MergeIterator::create(iters))
+        };
+        let blobs = CompactionBlobs {
+            blob_indexes: task
+                .input_sst_ids()
+                .iter()
+                .any(|id| !snapshot.sstables[id].properties().blob_files.is_empty()),
+            relocated: snapshot
+                .wasteful_blob_files(options.max_blob_space_amplification_percent)
+                .into_iter()
+                .collect(),
+        };
+        let level_iter = |ids: &[usize]| -> Result<SstConcatIterator> {
+            match seek_key {
+                Some(key) => SstConcatIterator::create_and_seek_to_key(ssts_of(ids), key),
+                None => SstConcatIterator::create_and_seek_to_first(ssts_of(ids)),
+            }
+        };
+        match task {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+                ..
+            } => self.compact_generate_sst_from_iter(
+                SubcompactionIterator::new(
+                    TwoMergeIterator::create(l0_iter(l0_sstables)?, level_iter(l1_sstables)?)?,
+                    end,
+                ),
+                watermark,
+                task,
+                options,
+                &blobs,
+            ),
+            CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => match upper_level {
+                Some(_) => self.compact_generate_sst_from_iter(
+                    SubcompactionIterator::new(
+                        TwoMergeIterator::create(
+                            level_iter(upper_level_sst_ids)?,
+                            level_iter(lower_level_sst_ids)?,
+                        )?,
+                        end,
+                    ),
+                    watermark,
+                    task,
+                    options,
+                    &blobs,
+                ),
+                None => self.compact_generate_sst_from_iter(
+                    SubcompactionIterator::new(
+                        TwoMergeIterator::create(
+                            l0_iter(upper_level_sst_ids)?,
+                            level_iter(lower_level_sst_ids)?,
+                        )?,
+                        end,
+                    ),
+                    watermark,
+                    task,
+                    options,
+                    &blobs,
+                ),
+            },
+            // FIFO compaction only deletes SSTs
+            CompactionTask::Fifo(_) => Ok(Vec::new()),
+            CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
+                let mut iters = Vec::with_capacity(tiers.len());
+                for (_, tier_sst_ids) in tiers {
+                    iters.push(Box::new(level_iter(tier_sst_ids)?));
+                }
+                self.compact_generate_sst_from_iter(
+                    SubcompactionIterator::new(MergeIterator::create(iters), end),
+                    watermark,
+                    task,
+                    options,
+                    &blobs,
+                )
+            }
+        }
     }
 
     pub fn force_full_compaction(&self) -> Result<()> {
-        let mut new_sst_l1 = vec![];
-        let mut new_ssts = vec![];
-        // 1. compact all sstables to new sstable
-        {
+        let _compaction_lock = self.compaction_lock.lock();
+        let task = {
             let state = self.state.read();
-            new_ssts = self.compact(&CompactionTask::ForceFullCompaction {
+            CompactionTask::ForceFullCompaction {
                 l0_sstables: state.l0_sstables.clone(),
                 l1_sstables: state.levels[0].1.clone(),
-            })?;
+                is_l1_bottom_level: state.levels[1..].iter().all(|(_, ssts)| ssts.is_empty()),
+            }
+        };
+        // compact all sstables to new sstables, added to L1
+        self.run_compaction_task(DEFAULT_COLUMN_FAMILY_ID, task)?;
+        Ok(())
+    }
+
+    /// Compact the SSTs `sst_ids` into `output_level` and return the ids of the SSTs written. Only supported with
+    /// leveled compaction.
+    ///
+    /// The selection must come from the output level and at most one level above it, and must keep every read
+    /// returning the newest version of a key: L0 SSTs are compacted oldest first, the levels in between hold no SST
+    /// overlapping the selection, and the SSTs of the output level overlapping it belong to the selection.
+    pub fn compact_files(&self, sst_ids: &[usize], output_level: usize) -> Result<Vec<usize>> {
+        let CompactionOptions::Leveled(options) = &self.options().compaction_options else {
+            bail!("compact_files requires leveled compaction");
+        };
+        let _compaction_lock = self.compaction_lock.lock();
+        let snapshot = self.state.read().clone();
+        let task = CompactionTask::Leveled(compact_files_task(
+            &snapshot,
+            sst_ids,
+            output_level,
+            options.max_levels,
+        )?);
+        self.run_compaction_task(DEFAULT_COLUMN_FAMILY_ID, task)
+    }
+
+    /// Run the next compaction task in the calling thread, if any level needs one.
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.lock();
+        let Some((cf_id, task)) = self.next_compaction_task() else {
+            return Ok(());
+        };
+        let result = self.run_compaction_task(cf_id, task);
+        // the compaction may have been the last reader of a dropped column family
+        if self.has_dropped_column_families() {
+            self.purge_dropped_column_families()?;
         }
+        result.map(|_| ())
+    }
+
+    /// The next compaction task with its column family, the default one first. Every column family is compacted
+    /// with its own compaction options.
+    fn next_compaction_task(&self) -> Option<(u32, CompactionTask)> {
+        let snapshot = self.state.read().clone();
+        if let Some(task) =
+            self.generate_compaction_task(&snapshot, &self.options(), &self.compaction_controller())
         {
-            let state_lock = self.state_lock.lock();
-            let mut state_guard = self.state.write();
-            let state = Arc::make_mut(&mut state_guard);
+            return Some((DEFAULT_COLUMN_FAMILY_ID, task));
+        }
+        let column_families = self.column_families.read().clone();
+        column_families.into_iter().find_map(|(cf_id, cf)| {
+            let options = cf.options.resolve(&self.options());
+            let controller = self.column_family_compaction_controller(cf_id);
+            self.generate_compaction_task(&cf.state, &options, &controller)
+                .map(|task| (cf_id, task))
+        })
+    }
+
+    /// The task the compaction strategy asks for, or else a rewrite of the bottom level dropping the garbage no
+    /// snapshot can see anymore, or else one garbage collecting the blob files. `None` with compaction disabled by
+    /// `options`.
+    fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        options: &LsmStorageOptions,
+        controller: &CompactionController,
+    ) -> Option<CompactionTask> {
+        if let CompactionOptions::NoCompaction = options.compaction_options {
+            return None;
+        }
+        controller
+            .generate_compaction_task(snapshot)
+            .or_else(|| self.generate_bottommost_recompaction_task(snapshot, options))
+            .or_else(|| self.generate_blob_garbage_collection_task(snapshot, options))
+    }
 
-            // 2.clear old sstables
-            for sst_id in state.l0_sstables.iter() {
-                state.sstables.remove(sst_id);
+    /// Rewrite the bottom-level SST, or the bottom tier, holding at least `bottommost_recompaction_bytes` of
+    /// versions and tombstones below the MVCC watermark. Its output holds no such garbage, so it is not picked
+    /// again until the watermark moves past newer versions.
+    fn generate_bottommost_recompaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        options: &LsmStorageOptions,
+    ) -> Option<CompactionTask> {
+        let threshold = options.bottommost_recompaction_bytes;
+        if threshold == 0 {
+            return None;
+        }
+        let watermark = self.mvcc().watermark();
+        let reclaimable_bytes = |id: &usize| {
+            snapshot.sstables[id]
+                .properties()
+                .reclaimable_bytes(watermark)
+        };
+        match &options.compaction_options {
+            CompactionOptions::Leveled(options) => {
+                let level = options.max_levels;
+                let sst_id = snapshot.levels[level - 1]
+                    .1
+                    .iter()
+                    .filter(|id| reclaimable_bytes(id) >= threshold)
+                    .max_by_key(|id| reclaimable_bytes(id))?;
+                db_log!(
+                    self.options(),
+                    Info,
+                    "bottommost recompaction of {}.sst ({} bytes reclaimable at watermark {})",
+                    sst_id,
+                    reclaimable_bytes(sst_id),
+                    watermark
+                );
+                Some(CompactionTask::Leveled(LeveledCompactionTask {
+                    upper_level: Some(level),
+                    upper_level_sst_ids: vec![*sst_id],
+                    lower_level: level,
+                    lower_level_sst_ids: Vec::new(),
+                    is_lower_level_bottom_level: true,
+                }))
+            }
+            CompactionOptions::Tiered(_) => {
+                let bottom_tier = snapshot.levels.last()?;
+                let bytes = bottom_tier.1.iter().map(reclaimable_bytes).sum::<u64>();
+                if bytes < threshold {
+                    return None;
+                }
+                db_log!(
+                    self.options(),
+                    Info,
+                    "bottommost recompaction of tier {} ({} bytes reclaimable at watermark {})",
+                    bottom_tier.0,
+                    bytes,
+                    watermark
+                );
+                Some(CompactionTask::Tiered(TieredCompactionTask {
+                    tiers: vec![bottom_tier.clone()],
+                    bottom_tier_included: true,
+                }))
             }
-            for sst_id in state.levels[0].1.iter() {
-                state.sstables.remove(sst_id);
+            CompactionOptions::Simple(_)
+            | CompactionOptions::Fifo(_)
+            | CompactionOptions::NoCompaction => None,
+        }
+    }
+
+    /// Once the blob files together are larger than `max_blob_space_amplification_percent` of their live bytes,
+    /// rewrite the SSTs of the first level, or tier, pointing into the most wasteful one, which moves their values
+    /// out of it. L0 SSTs are left to the compactions that take them anyway, so a blob file only pointed to from L0
+    /// is skipped.
+    fn generate_blob_garbage_collection_task(
+        &self,
+        snapshot: &LsmStorageState,
+        options: &LsmStorageOptions,
+    ) -> Option<CompactionTask> {
+        let max_percent = options.max_blob_space_amplification_percent;
+        if max_percent == 0 {
+            return None;
+        }
+        let usage = snapshot.blob_file_usage();
+        let size = usage.values().map(|usage| usage.size).sum::<u64>();
+        let live_bytes = usage.values().map(|usage| usage.live_bytes).sum::<u64>();
+        if size * 100 <= live_bytes * max_percent {
+            return None;
+        }
+        let points_into = |sst_id: &usize, blob_file_id: usize| {
+            snapshot.sstables[sst_id]
+                .properties()
+                .blob_files
+                .contains_key(&blob_file_id)
+        };
+        for blob_file_id in snapshot.wasteful_blob_files(max_percent) {
+            let Some(position) = snapshot
+                .levels
+                .iter()
+                .position(|(_, ssts)| ssts.iter().any(|id| points_into(id, blob_file_id)))
+            else {
+                continue;
+            };
+            db_log!(
+                self.options(),
+                Info,
+                "garbage collecting {}.blob ({}% of its live bytes)",
+                blob_file_id,
+                usage[&blob_file_id].space_amplification_percent()
+            );
+            let (tier_id, ssts) = &snapshot.levels[position];
+            return match &options.compaction_options {
+                CompactionOptions::Leveled(options) => {
+                    Some(CompactionTask::Leveled(LeveledCompactionTask {
+                        upper_level: Some(position + 1),
+                        upper_level_sst_ids: ssts
+                            .iter()
+                            .copied()
+                            .filter(|id| points_into(id, blob_file_id))
+                            .collect(),
+                        lower_level: position + 1,
+                        lower_level_sst_ids: Vec::new(),
+                        is_lower_level_bottom_level: position + 1 == options.max_levels,
+                    }))
+                }
+                CompactionOptions::Tiered(_) => {
+                    Some(CompactionTask::Tiered(TieredCompactionTask {
+                        tiers: vec![(*tier_id, ssts.clone())],
+                        bottom_tier_included: position + 1 == snapshot.levels.len(),
+                    }))
+                }
+                CompactionOptions::Simple(_)
+                | CompactionOptions::Fifo(_)
+                | CompactionOptions::NoCompaction => None,
+            };
+        }
+        None
+    }
+
+    /// Compact and install the output of the task, notifying the event listeners, and return the ids of the SSTs
+    /// written.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "compaction", skip(self, task), err)
+    )]
+    fn run_compaction_task(&self, cf_id: u32, task: CompactionTask) -> Result<Vec<usize>> {
+        self.check_writable()?;
+        db_log!(self.options(), Info, "running compaction task: {:?}", task);
+        #[cfg(feature = "tracing")]
+        tracing::info!(?task, "running compaction task");
+        let listeners = &self.options().event_listeners;
+        let input_sst_ids = task.input_sst_ids();
+        if !listeners.is_empty() {
+            let Some(snapshot) = self.column_family_state(cf_id) else {
+                bail!("column family {} was dropped", cf_id);
+            };
+            let info = CompactionJobInfo {
+                output_level: self.stats_level(&snapshot, &task, &[]),
+                input_sst_ids: input_sst_ids.clone(),
+                output_sst_ids: Vec::new(),
+                counters: LevelCompactionCounters::default(),
+            };
+            for listener in listeners {
+                listener.on_compaction_begin(&info);
             }
-            state.l0_sstables.clear();
+        }
+        let started = self.clock.instant();
+        let new_ssts = self.compact(cf_id, &task)?;
+        let output = new_ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>();
+        let compaction_time = self.clock.instant().duration_since(started);
+        let (output_level, counters) =
+            self.apply_compaction(cf_id, task, new_ssts, compaction_time)?;
+        let info = CompactionJobInfo {
+            output_level,
+            input_sst_ids,
+            output_sst_ids: output.clone(),
+            counters,
+        };
+        for listener in listeners {
+            listener.on_compaction_completed(&info);
+        }
+        self.update_write_stall();
+        Ok(output)
+    }
 
-            // 3.replace  by new sstables
+    /// Install the output of a compaction task, record it in the manifest and delete the compacted SSTs. Returns
+    /// the level written to and what the task added to its counters.
+    fn apply_compaction(
+        &self,
+        cf_id: u32,
+        task: CompactionTask,
+        new_ssts: Vec<Arc<SsTable>>,
+        compaction_time: Duration,
+    ) -> Result<(usize, LevelCompactionCounters)> {
+        let output = new_ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>();
+        // SSTs moved by a trivial move are both an input and an output of the task
+        let moved = task
+            .input_sst_ids()
+            .into_iter()
+            .filter(|id| output.contains(id))
+            .collect::<HashSet<_>>();
+        let written = new_ssts
+            .iter()
+            .filter(|sst| !moved.contains(&sst.sst_id()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let (files_to_remove, blob_files_to_remove, stats) = {
+            let state_lock = self.state_lock.lock();
+            // the state may have changed while compacting, e.g. new L0 SSTs were flushed
+            let Some(snapshot) = self.column_family_state(cf_id) else {
+                // the SSTs moved belong to the dropped column family, which deletes them
+                self.remove_unused_ssts(&written);
+                bail!("column family {} was dropped while compacting", cf_id);
+            };
+            let mut snapshot = snapshot.as_ref().clone();
             for sst in new_ssts {
-                new_sst_l1.push(sst.sst_id());
-                state.sstables.insert(sst.sst_id(), sst);
+                let result = snapshot.sstables.insert(sst.sst_id(), sst);
+                assert!(result.is_none() || moved.contains(&result.unwrap().sst_id()));
             }
-            state.levels[0] = (0, new_sst_l1); // new SSTs added to L1
+            let (mut snapshot, mut files_to_remove) = self
+                .column_family_compaction_controller(cf_id)
+                .apply_compaction_result(&snapshot, &task, &output);
+            files_to_remove.retain(|id| !moved.contains(id));
+            let stats =
+                self.record_compaction_stats(&snapshot, &task, &output, &moved, compaction_time);
+            let mut blob_files_to_remove = BTreeSet::new();
+            for sst_id in &files_to_remove {
+                let result = snapshot.sstables.remove(sst_id);
+                assert!(result.is_some(), "cannot remove {}.sst", sst_id);
+                blob_files_to_remove.extend(result.unwrap().properties().blob_files.keys());
+            }
+            // a blob file is written by a flush of a single column family, only its SSTs can point into it
+            for blob_file_id in snapshot.blob_file_ids() {
+                blob_files_to_remove.remove(&blob_file_id);
+            }
+            self.set_column_family_state(cf_id, snapshot);
+            self.sync_dir()?;
+            let record = if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+                ManifestRecord::Compaction(task, output.clone())
+            } else {
+                ManifestRecord::ColumnFamilyCompaction(cf_id, task, output.clone())
+            };
+            self.add_manifest_record_with_ssts(&state_lock, record, &written)?;
+            (files_to_remove, blob_files_to_remove, stats)
         };
-        Ok(())
+        db_log!(
+            self.options(),
+            Info,
+            "compaction finished: {} files removed, {} files added, output={:?}",
+            files_to_remove.len(),
+            output.len(),
+            output
+        );
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            files_removed = files_to_remove.len(),
+            files_added = output.len(),
+            ?output,
+            "compaction finished"
+        );
+        // otherwise the SSTs are left to `enable_file_deletions`
+        if self.file_deletions_enabled() {
+            for sst_id in &files_to_remove {
+                self.fs.remove_file(&self.path_of_sst(*sst_id))?;
+            }
+            for blob_file_id in &blob_files_to_remove {
+                db_log!(
+                    self.options(),
+                    Info,
+                    "removing obsolete {}.blob",
+                    blob_file_id
+                );
+                self.fs.remove_file(&self.path_of_blob(*blob_file_id))?;
+            }
+            self.sync_dir()?;
+        }
+        Ok(stats)
+    }
+
+    /// The level a compaction is counted against: the level written to, or for tiered compaction the position of
+    /// the output tier in `snapshot`, or of the newest input tier before there is any output.
+    fn stats_level(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        output: &[usize],
+    ) -> usize {
+        match task {
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Tiered(task) => output
+                .first()
+                .or(task.tiers.first().map(|(tier_id, _)| tier_id))
+                .and_then(|id| {
+                    snapshot
+                        .levels
+                        .iter()
+                        .position(|(tier_id, _)| tier_id == id)
+                })
+                .map_or(snapshot.levels.len(), |position| position + 1),
+            CompactionTask::Fifo(_) => 0,
+        }
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
-        unimplemented!()
+    /// Count a compaction against the level it wrote to, and return the level and the counters of the compaction
+    /// alone. `snapshot` still holds the compacted SSTs.
+    fn record_compaction_stats(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        output: &[usize],
+        moved: &HashSet<usize>,
+        compaction_time: Duration,
+    ) -> (usize, LevelCompactionCounters) {
+        let size = |ids: &mut dyn Iterator<Item = &usize>| {
+            ids.map(|id| snapshot.sstables[id].table_size())
+                .sum::<u64>()
+        };
+        let rewritten = |ids: &[usize]| size(&mut ids.iter().filter(|id| !moved.contains(id)));
+        let level = self.stats_level(snapshot, task, output);
+        let (upper, lower) = task.upper_and_lower_sst_ids();
+        let job = LevelCompactionCounters {
+            bytes_read_from_upper_level: rewritten(&upper),
+            bytes_read_from_level: rewritten(&lower),
+            bytes_written: rewritten(output),
+            bytes_moved: size(&mut moved.iter()),
+            num_compactions: 1,
+            compaction_time,
+        };
+        self.compaction_stats.record(level, |counters| {
+            counters.bytes_read_from_upper_level += job.bytes_read_from_upper_level;
+            counters.bytes_read_from_level += job.bytes_read_from_level;
+            counters.bytes_written += job.bytes_written;
+            counters.bytes_moved += job.bytes_moved;
+            counters.compaction_time += job.compaction_time;
+        });
+        if let Some(statistics) = &self.options().statistics {
+            statistics.record_tick(
+                Ticker::CompactReadBytes,
+                job.bytes_read_from_upper_level + job.bytes_read_from_level,
+            );
+            statistics.record_tick(Ticker::CompactWriteBytes, job.bytes_written);
+        }
+        (level, job)
     }
 
-    pub(crate) fn spawn_compaction_thread(
-        self: &Arc<Self>,
-        rx: crossbeam_channel::Receiver<()>,
-    ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        if let CompactionOptions::Leveled(_)
-        | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
-        {
-            let this = self.clone();
-            let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+    /// Statistics of every level, L0 first.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        let snapshot = self.state.read().clone();
+        let level_size = |ssts: &[usize]| {
+            ssts.iter()
+                .map(|id| snapshot.sstables[id].table_size())
+                .sum::<u64>()
+        };
+        let pending_compaction_bytes = self.pending_compaction_bytes(&snapshot);
+        let levels = std::iter::once(&snapshot.l0_sstables)
+            .chain(snapshot.levels.iter().map(|(_, ssts)| ssts))
+            .enumerate()
+            .map(|(level, ssts)| LevelStats {
+                level,
+                num_files: ssts.len(),
+                size_bytes: level_size(ssts),
+                pending_compaction_bytes: pending_compaction_bytes.get(level).copied().unwrap_or(0),
+                counters: self.compaction_stats.counters(level),
+            })
+            .collect();
+        CompactionStats { levels }
+    }
+
+    /// The statistics of every level formatted as a table.
+    pub fn level_stats(&self) -> String {
+        self.compaction_stats().to_string()
+    }
+
+    /// The next `max_jobs` compactions the background jobs would run, in order, if nothing were written meanwhile.
+    /// Nothing is compacted: every planned compaction is applied to a copy of the state, with simulated output
+    /// SSTs, to plan the next one.
+    pub fn plan_compactions(&self, max_jobs: usize) -> Vec<PlannedCompaction> {
+        let mut snapshot = self.state.read().as_ref().clone();
+        let watermark = self.mvcc().watermark();
+        let mut next_sst_id = snapshot
+            .sstables
+            .keys()
+            .map(|id| id + 1)
+            .chain(std::iter::once(snapshot.memtable.id() + 1))
+            .max()
+            .unwrap();
+        let options = self.options();
+        let compaction_controller = self.compaction_controller();
+        let mut plan = Vec::new();
+        while plan.len() < max_jobs {
+            let Some(task) =
+                self.generate_compaction_task(&snapshot, &options, &compaction_controller)
+            else {
+                break;
+            };
+            let input = task
+                .input_sst_ids()
+                .into_iter()
+                .map(|id| snapshot.sstables[&id].clone())
+                .collect::<Vec<_>>();
+            let input_bytes = input.iter().map(|sst| sst.table_size()).sum::<u64>();
+            let moved = self.trivial_move_ssts(&snapshot, &task, &options);
+            let is_trivial_move = moved.is_some();
+            let (output, estimated_output_bytes) = if let Some(ssts) = moved {
+                (ssts.iter().map(|sst| sst.sst_id()).collect(), input_bytes)
+            } else {
+                let garbage_bytes = if task.compact_to_bottom_level() {
+                    input
+                        .iter()
+                        .map(|sst| sst.properties().reclaimable_bytes(watermark))
+                        .sum()
+                } else {
+                    0
+                };
+                let bytes = input_bytes.saturating_sub(garbage_bytes);
+                match (
+                    input.iter().map(|sst| sst.first_key()).min(),
+                    input.iter().map(|sst| sst.last_key()).max(),
+                ) {
+                    (Some(first_key), Some(last_key))
+                        if bytes > 0 && !matches!(task, CompactionTask::Fifo(_)) =>
+                    {
+                        let sst = SsTable::create_meta_only(
+                            next_sst_id,
+                            bytes,
+                            first_key.clone(),
+                            last_key.clone(),
+                        );
+                        snapshot.sstables.insert(next_sst_id, Arc::new(sst));
+                        next_sst_id += 1;
+                        (vec![next_sst_id - 1], bytes)
                     }
+                    _ => (Vec::new(), 0),
                 }
+            };
+            let (next_snapshot, files_to_remove) =
+                compaction_controller.apply_compaction_result(&snapshot, &task, &output);
+            snapshot = next_snapshot;
+            for sst_id in files_to_remove.iter().filter(|id| !output.contains(id)) {
+                snapshot.sstables.remove(sst_id);
+            }
+            plan.push(PlannedCompaction {
+                priority: task.job_priority(),
+                output_level: self.stats_level(&snapshot, &task, &output),
+                task,
+                input_bytes,
+                estimated_output_bytes,
+                is_trivial_move,
+                output_sst_ids: output,
             });
-            return Ok(Some(handle));
         }
-        Ok(None)
+        plan
+    }
+
+    /// Bytes compactions still have to move out of every level, L0 first. Leveled compaction estimates the bytes
+    /// over the target of each level; the other strategies count the input of the compaction they would run next.
+    pub(crate) fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> Vec<u64> {
+        if let CompactionController::Leveled(controller) = &**self.compaction_controller() {
+            return controller.pending_compaction_bytes(snapshot);
+        }
+        let mut pending = vec![0; snapshot.levels.len() + 1];
+        if !self.compaction_enabled() {
+            return pending;
+        }
+        let Some(task) = self
+            .compaction_controller()
+            .generate_compaction_task(snapshot)
+        else {
+            return pending;
+        };
+        let (upper, _) = task.upper_and_lower_sst_ids();
+        for (level, ssts) in std::iter::once(&snapshot.l0_sstables)
+            .chain(snapshot.levels.iter().map(|(_, ssts)| ssts))
+            .enumerate()
+        {
+            pending[level] = ssts
+                .iter()
+                .filter(|id| upper.contains(id))
+                .map(|id| snapshot.sstables[id].table_size())
+                .sum();
+        }
+        pending
+    }
+
+    fn compaction_enabled(&self) -> bool {
+        !matches!(
+            self.options().compaction_options,
+            CompactionOptions::NoCompaction
+        )
+    }
+
+    fn needs_flush(&self) -> bool {
+        let state = self.state.read();
+        state.imm_memtables.len() >= self.options().num_memtable_limit
+            || (!state.imm_memtables.is_empty()
+                && self
+                    .options()
+                    .write_buffer_manager
+                    .as_ref()
+                    .is_some_and(|manager| manager.should_flush()))
     }
 
     fn trigger_flush(&self) -> Result<()> {
-        if {
-            let state = self.state.read();
-            state.imm_memtables.len() >= self.options.num_memtable_limit
-        } {
+        if self.needs_flush() {
             self.force_flush_next_imm_memtable()?;
         }
 
         Ok(())
     }
 
-    pub(crate) fn spawn_flush_thread(
+    /// Queue a flush and a compaction when they are needed and not already queued or running. At most one
+    /// compaction is in flight so that two tasks never pick the same SSTs.
+    fn schedule_background_jobs(
+        self: &Arc<Self>,
+        scheduler: &BackgroundScheduler,
+        flush_scheduled: &Arc<AtomicBool>,
+        compaction_scheduled: &Arc<AtomicBool>,
+    ) {
+        if !flush_scheduled.load(Ordering::Acquire) && self.needs_flush() {
+            flush_scheduled.store(true, Ordering::Release);
+            let (this, flush_scheduled) = (self.clone(), flush_scheduled.clone());
+            let scheduled = scheduler.schedule(JobPriority::Flush, move || {
+                if let Err(e) = this.trigger_flush() {
+                    db_log!(this.options(), Error, "flush failed: {}", e);
+                }
+                flush_scheduled.store(false, Ordering::Release);
+            });
+            if !scheduled {
+                return;
+            }
+        }
+
+        if compaction_scheduled.load(Ordering::Acquire) {
+            return;
+        }
+        let Some((_, task)) = self.next_compaction_task() else {
+            return;
+        };
+        compaction_scheduled.store(true, Ordering::Release);
+        let (this, compaction_scheduled) = (self.clone(), compaction_scheduled.clone());
+        // the task only sets the priority, the job picks its own once it holds the compaction lock
+        scheduler.schedule(task.job_priority(), move || {
+            if let Err(e) = this.trigger_compaction() {
+                db_log!(this.options(), Error, "compaction failed: {}", e);
+            }
+            compaction_scheduled.store(false, Ordering::Release);
+        });
+    }
+
+    /// Run the flush and the compactions needed in the calling thread, with `inline_background_jobs`. They fail as
+    /// they would in the background, logging the error, as the write that ran them succeeded.
+    pub(crate) fn run_background_jobs_inline(&self) {
+        if let Err(e) = self.trigger_flush() {
+            db_log!(self.options(), Error, "flush failed: {}", e);
+            return;
+        }
+        while self.next_compaction_task().is_some() {
+            if let Err(e) = self.trigger_compaction() {
+                db_log!(self.options(), Error, "compaction failed: {}", e);
+                return;
+            }
+        }
+        if self.has_dropped_column_families() {
+            if let Err(e) = self.purge_dropped_column_families() {
+                db_log!(
+                    self.options(),
+                    Error,
+                    "deleting the SSTs of dropped column families failed: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Queue the deletion of the obsolete files unless it is already queued or running.
+    fn schedule_obsolete_files_deletion(
+        self: &Arc<Self>,
+        scheduler: &BackgroundScheduler,
+        deletion_scheduled: &Arc<AtomicBool>,
+    ) {
+        if deletion_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let (this, job_scheduled) = (self.clone(), deletion_scheduled.clone());
+        let scheduled = scheduler.schedule(JobPriority::Compaction, move || {
+            if let Err(e) = this.delete_obsolete_files() {
+                db_log!(
+                    this.options(),
+                    Error,
+                    "deleting obsolete files failed: {}",
+                    e
+                );
+            }
+            job_scheduled.store(false, Ordering::Release);
+        });
+        if !scheduled {
+            deletion_scheduled.store(false, Ordering::Release);
+        }
+    }
+
+    /// Queue the deletion of the SSTs of the dropped column families unless it is already queued or running.
+    fn schedule_dropped_column_families_purge(
         self: &Arc<Self>,
+        scheduler: &BackgroundScheduler,
+        purge_scheduled: &Arc<AtomicBool>,
+    ) {
+        if purge_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let (this, job_scheduled) = (self.clone(), purge_scheduled.clone());
+        let scheduled = scheduler.schedule(JobPriority::Compaction, move || {
+            if let Err(e) = this.purge_dropped_column_families() {
+                db_log!(
+                    this.options(),
+                    Error,
+                    "deleting the SSTs of dropped column families failed: {}",
+                    e
+                );
+            }
+            job_scheduled.store(false, Ordering::Release);
+        });
+        if !scheduled {
+            purge_scheduled.store(false, Ordering::Release);
+        }
+    }
+
+    /// Spawn the thread checking every 50ms whether a flush or a compaction is needed, and queueing them on the
+    /// scheduler, along with the periodic deletion of the obsolete files, the deletion of the SSTs of dropped column
+    /// families and the warnings about old snapshots. Stops when `rx` receives a message or is disconnected.
+    pub(crate) fn spawn_background_dispatcher(
+        self: &Arc<Self>,
+        scheduler: Arc<BackgroundScheduler>,
         rx: crossbeam_channel::Receiver<()>,
-    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+    ) -> Result<std::thread::JoinHandle<()>> {
         let this = self.clone();
         let handle = std::thread::spawn(move || {
             let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+            let flush_scheduled = Arc::new(AtomicBool::new(false));
+            let compaction_scheduled = Arc::new(AtomicBool::new(false));
+            let deletion_scheduled = Arc::new(AtomicBool::new(false));
+            let purge_scheduled = Arc::new(AtomicBool::new(false));
+            let mut last_deletion = Instant::now();
             loop {
                 crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("flush failed: {}", e);
-                    },
+                    recv(ticker) -> _ => {
+                        this.schedule_background_jobs(
+                            &scheduler,
+                            &flush_scheduled,
+                            &compaction_scheduled,
+                        );
+                        // read on every tick, since `set_options` can change it
+                        let deletion_period =
+                            Duration::from_secs(this.options().delete_obsolete_files_period_seconds);
+                        if !deletion_period.is_zero() && last_deletion.elapsed() >= deletion_period {
+                            last_deletion = Instant::now();
+                            this.schedule_obsolete_files_deletion(&scheduler, &deletion_scheduled);
+                        }
+                        if this.has_dropped_column_families() {
+                            this.schedule_dropped_column_families_purge(&scheduler, &purge_scheduled);
+                        }
+                        this.mvcc().warn_old_snapshots();
+                    }
                     recv(rx) -> _ => return
                 }
             }
         });
-        Ok(Some(handle))
+        Ok(handle)
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{default_clock, Clock};
+use crate::logger::{db_log, DbLogger};
+use crate::lsm_storage::LsmStorageState;
+
+/// Deletes whole SSTs, oldest first, and never rewrites data.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FifoCompactionTask {
+    pub sst_ids_to_delete: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FifoCompactionOptions {
+    /// Total size of the SSTs in bytes above which the oldest ones are deleted.
+    pub max_table_files_size: u64,
+    /// SSTs built longer ago than this are deleted. Zero keeps SSTs regardless of their age.
+    pub ttl_seconds: u64,
+}
+
+/// FIFO compaction keeps every SST in L0 and expires the oldest ones wholesale, for data that is only kept for a
+/// retention period such as metrics.
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+    clock: Arc<dyn Clock>,
+    logger: DbLogger,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions) -> Self {
+        Self {
+            options,
+            clock: default_clock(),
+            logger: DbLogger::default(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Log the compactions picked through `logger` instead of the `log` facade.
+    pub fn with_logger(mut self, logger: DbLogger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<FifoCompactionTask> {
+        let now = self.clock.unix_seconds();
+        let mut total_size = snapshot
+            .l0_sstables
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        let mut sst_ids_to_delete = Vec::new();
+        // L0 is ordered newest first
+        for sst_id in snapshot.l0_sstables.iter().rev() {
+            let sst = &snapshot.sstables[sst_id];
+            let expired = self.options.ttl_seconds > 0
+                && sst.properties().creation_time + self.options.ttl_seconds <= now;
+            if !expired && total_size <= self.options.max_table_files_size {
+                break;
+            }
+            total_size -= sst.table_size();
+            sst_ids_to_delete.push(*sst_id);
+        }
+        if sst_ids_to_delete.is_empty() {
+            return None;
+        }
+        db_log!(
+            self.logger,
+            Info,
+            "FIFO compaction deletes {:?}, {} bytes left",
+            sst_ids_to_delete,
+            total_size
+        );
+        Some(FifoCompactionTask { sst_ids_to_delete })
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &FifoCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        assert!(output.is_empty(), "FIFO compaction never writes SSTs");
+        let mut snapshot = snapshot.clone();
+        let sst_ids_to_delete = task.sst_ids_to_delete.iter().collect::<HashSet<_>>();
+        snapshot
+            .l0_sstables
+            .retain(|id| !sst_ids_to_delete.contains(id));
+        (snapshot, task.sst_ids_to_delete.clone())
+    }
+}
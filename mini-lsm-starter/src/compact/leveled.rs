@@ -1,5 +1,10 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 
+use crate::clock::{default_clock, Clock};
+use crate::logger::{db_log, DbLogger};
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,7 +17,7 @@ pub struct LeveledCompactionTask {
     pub is_lower_level_bottom_level: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionOptions {
     pub level_size_multiplier: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -20,37 +25,398 @@ pub struct LeveledCompactionOptions {
     pub base_level_size_mb: usize,
 }
 
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level_size_multiplier: 10,
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 6,
+            base_level_size_mb: 256,
+        }
+    }
+}
+
 pub struct LeveledCompactionController {
     options: LeveledCompactionOptions,
+    periodic_compaction_seconds: u64,
+    tombstone_compaction_ratio: f64,
+    clock: Arc<dyn Clock>,
+    logger: DbLogger,
 }
 
 impl LeveledCompactionController {
     pub fn new(options: LeveledCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            periodic_compaction_seconds: 0,
+            tombstone_compaction_ratio: 0.0,
+            clock: default_clock(),
+            logger: DbLogger::default(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Log the compactions picked through `logger` instead of the `log` facade.
+    pub fn with_logger(mut self, logger: DbLogger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Recompact SSTs built at least `seconds` ago when no level needs compacting, so that the tombstones and
+    /// expired entries they hold are eventually purged. Zero disables periodic compaction.
+    pub fn with_periodic_compaction_seconds(mut self, seconds: u64) -> Self {
+        self.periodic_compaction_seconds = seconds;
+        self
+    }
+
+    /// Compact SSTs whose share of tombstones is above `ratio` when no level needs compacting, so that the space
+    /// of the deleted keys is reclaimed soon after a bulk delete. Zero disables it.
+    pub fn with_tombstone_compaction_ratio(mut self, ratio: f64) -> Self {
+        self.tombstone_compaction_ratio = ratio;
+        self
     }
 
     fn find_overlapping_ssts(
         &self,
-        _snapshot: &LsmStorageState,
-        _sst_ids: &[usize],
-        _in_level: usize,
+        snapshot: &LsmStorageState,
+        sst_ids: &[usize],
+        in_level: usize,
     ) -> Vec<usize> {
-        unimplemented!()
+        let begin_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key())
+            .min()
+            .cloned()
+            .unwrap();
+        let end_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].last_key())
+            .max()
+            .cloned()
+            .unwrap();
+        snapshot.levels[in_level - 1]
+            .1
+            .iter()
+            .filter(|sst_id| {
+                let sst = &snapshot.sstables[*sst_id];
+                !(sst.last_key() < &begin_key || sst.first_key() > &end_key)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// The target size of every level. The bottommost level is targeted at its real size and every level above it
+    /// at `level_size_multiplier` times less, down to `base_level_size_mb`; the levels that would fall below that
+    /// stay empty, and L0 is compacted into the first non-empty target, the base level.
+    fn target_level_sizes(&self, real_level_sizes: &[usize]) -> (Vec<usize>, usize) {
+        let max_levels = self.options.max_levels;
+        let base_level_size_bytes = self.options.base_level_size_mb * 1024 * 1024;
+        let mut target_level_sizes = vec![0; max_levels];
+        let mut base_level = max_levels;
+        target_level_sizes[max_levels - 1] =
+            real_level_sizes[max_levels - 1].max(base_level_size_bytes);
+        for i in (0..max_levels - 1).rev() {
+            let next_level_size = target_level_sizes[i + 1];
+            if next_level_size > base_level_size_bytes {
+                target_level_sizes[i] = next_level_size / self.options.level_size_multiplier;
+            }
+            if target_level_sizes[i] > 0 {
+                base_level = i + 1;
+            }
+        }
+        (target_level_sizes, base_level)
+    }
+
+    /// Bytes over the target size of every level, L0 first. All of L0 is pending once it reaches the L0 trigger.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> Vec<u64> {
+        let real_level_sizes = snapshot
+            .levels
+            .iter()
+            .take(self.options.max_levels)
+            .map(|(_, ssts)| {
+                ssts.iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum::<u64>() as usize
+            })
+            .collect::<Vec<_>>();
+        let (target_level_sizes, _) = self.target_level_sizes(&real_level_sizes);
+        let l0_pending =
+            if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+                snapshot
+                    .l0_sstables
+                    .iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum()
+            } else {
+                0
+            };
+        // the bottom level has nowhere to go
+        std::iter::once(l0_pending)
+            .chain(
+                real_level_sizes
+                    .iter()
+                    .zip(&target_level_sizes)
+                    .take(self.options.max_levels - 1)
+                    .map(|(real, target)| real.saturating_sub(*target) as u64),
+            )
+            .chain(std::iter::once(0))
+            .collect()
+    }
+
+    /// Pick the SST of `level` whose key range overlaps the fewest bytes of the level below relative to its own
+    /// size, so that every compaction rewrites as little of the lower level as possible. Ties go to the oldest SST.
+    fn pick_sst_with_min_overlap_ratio(&self, snapshot: &LsmStorageState, level: usize) -> usize {
+        snapshot.levels[level - 1]
+            .1
+            .iter()
+            .map(|sst_id| {
+                let overlapping_size = self
+                    .find_overlapping_ssts(snapshot, &[*sst_id], level + 1)
+                    .iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum::<u64>();
+                let ratio =
+                    overlapping_size as f64 / snapshot.sstables[sst_id].table_size().max(1) as f64;
+                (ratio, *sst_id)
+            })
+            .min_by(|a, b| a.partial_cmp(b).unwrap())
+            .map(|(_, sst_id)| sst_id)
+            .unwrap()
     }
 
     pub fn generate_compaction_task(
         &self,
-        _snapshot: &LsmStorageState,
+        snapshot: &LsmStorageState,
     ) -> Option<LeveledCompactionTask> {
-        unimplemented!()
+        let real_level_sizes = snapshot
+            .levels
+            .iter()
+            .take(self.options.max_levels)
+            .map(|(_, ssts)| {
+                ssts.iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum::<u64>() as usize
+            })
+            .collect::<Vec<_>>();
+        let (target_level_sizes, base_level) = self.target_level_sizes(&real_level_sizes);
+
+        // L0 has the top priority as every L0 SST slows down reads
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            db_log!(
+                self.logger,
+                Info,
+                "compact L0 SSTs to base level {}",
+                base_level
+            );
+            return Some(self.l0_compaction_task(snapshot, base_level));
+        }
+
+        // then the level most over its target, never the bottommost level which has nowhere to go; data left
+        // above the base level has a target of zero and is moved down first
+        let most_over_target = (0..self.options.max_levels - 1)
+            .map(|i| {
+                let score = real_level_sizes[i] as f64 / target_level_sizes[i] as f64;
+                (score, i + 1)
+            })
+            .filter(|(score, _)| *score > 1.0)
+            .max_by(|a, b| a.partial_cmp(b).unwrap());
+        if let Some((score, level)) = most_over_target {
+            let selected_sst = self.pick_sst_with_min_overlap_ratio(snapshot, level);
+            db_log!(
+                self.logger,
+                Info,
+                "compact L{} (score {:.3}, target level sizes {:?}, base level {}), select {}.sst",
+                level,
+                score,
+                target_level_sizes,
+                base_level,
+                selected_sst
+            );
+            return Some(self.level_compaction_task(snapshot, level, selected_sst));
+        }
+
+        self.generate_tombstone_compaction_task(snapshot, base_level)
+            .or_else(|| self.generate_periodic_compaction_task(snapshot, base_level))
+    }
+
+    fn l0_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        base_level: usize,
+    ) -> LeveledCompactionTask {
+        LeveledCompactionTask {
+            upper_level: None,
+            upper_level_sst_ids: snapshot.l0_sstables.clone(),
+            lower_level: base_level,
+            lower_level_sst_ids: self.find_overlapping_ssts(
+                snapshot,
+                &snapshot.l0_sstables,
+                base_level,
+            ),
+            is_lower_level_bottom_level: base_level == self.options.max_levels,
+        }
+    }
+
+    /// Compact an SST into the level below, or rewrite it in place when it is in the bottommost level.
+    fn level_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        level: usize,
+        sst_id: usize,
+    ) -> LeveledCompactionTask {
+        let max_levels = self.options.max_levels;
+        let lower_level = (level + 1).min(max_levels);
+        LeveledCompactionTask {
+            upper_level: Some(level),
+            upper_level_sst_ids: vec![sst_id],
+            lower_level,
+            lower_level_sst_ids: if lower_level == level {
+                Vec::new()
+            } else {
+                self.find_overlapping_ssts(snapshot, &[sst_id], lower_level)
+            },
+            is_lower_level_bottom_level: lower_level == max_levels,
+        }
+    }
+
+    /// Compact the SST with the largest share of tombstones above the threshold down into the next level, where they
+    /// delete the keys they shadow. The bottom level is left alone: its tombstones are dropped whenever it is
+    /// compacted, and those still visible to a snapshot cannot be dropped by rewriting it again.
+    fn generate_tombstone_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        base_level: usize,
+    ) -> Option<LeveledCompactionTask> {
+        if self.tombstone_compaction_ratio <= 0.0 {
+            return None;
+        }
+        let (level, sst_id) = std::iter::repeat(0)
+            .zip(&snapshot.l0_sstables)
+            .chain(
+                snapshot
+                    .levels
+                    .iter()
+                    .take(self.options.max_levels - 1)
+                    .flat_map(|(level, ssts)| std::iter::repeat(*level).zip(ssts)),
+            )
+            .filter(|(_, sst_id)| {
+                super::dense_in_tombstones(
+                    &snapshot.sstables[*sst_id],
+                    self.tombstone_compaction_ratio,
+                )
+            })
+            .max_by(|(_, x), (_, y)| {
+                let ratio = |id: &usize| snapshot.sstables[id].properties().deletion_ratio();
+                ratio(x).total_cmp(&ratio(y))
+            })?;
+        db_log!(
+            self.logger,
+            Info,
+            "tombstone compaction of {}.sst in L{} (deletion ratio {:.3})",
+            sst_id,
+            level,
+            snapshot.sstables[sst_id].properties().deletion_ratio()
+        );
+        Some(if level == 0 {
+            self.l0_compaction_task(snapshot, base_level)
+        } else {
+            self.level_compaction_task(snapshot, level, *sst_id)
+        })
+    }
+
+    /// Compact the SST built the longest ago once it is older than the periodic compaction threshold.
+    fn generate_periodic_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        base_level: usize,
+    ) -> Option<LeveledCompactionTask> {
+        if self.periodic_compaction_seconds == 0 {
+            return None;
+        }
+        let now = self.clock.unix_seconds();
+        let (level, sst_id) = std::iter::repeat(0)
+            .zip(&snapshot.l0_sstables)
+            .chain(
+                snapshot
+                    .levels
+                    .iter()
+                    .flat_map(|(level, ssts)| std::iter::repeat(*level).zip(ssts)),
+            )
+            .filter(|(_, sst_id)| {
+                super::due_for_periodic_compaction(
+                    &snapshot.sstables[*sst_id],
+                    self.periodic_compaction_seconds,
+                    now,
+                )
+            })
+            .min_by_key(|(_, sst_id)| snapshot.sstables[*sst_id].properties().creation_time)?;
+        db_log!(
+            self.logger,
+            Info,
+            "periodic compaction of {}.sst in L{}",
+            sst_id,
+            level
+        );
+        Some(if level == 0 {
+            self.l0_compaction_task(snapshot, base_level)
+        } else {
+            self.level_compaction_task(snapshot, level, *sst_id)
+        })
     }
 
     pub fn apply_compaction_result(
         &self,
-        _snapshot: &LsmStorageState,
-        _task: &LeveledCompactionTask,
-        _output: &[usize],
+        snapshot: &LsmStorageState,
+        task: &LeveledCompactionTask,
+        output: &[usize],
     ) -> (LsmStorageState, Vec<usize>) {
-        unimplemented!()
+        let mut snapshot = snapshot.clone();
+        let upper_level_sst_ids = task
+            .upper_level_sst_ids
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let lower_level_sst_ids = task
+            .lower_level_sst_ids
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        match task.upper_level {
+            Some(upper_level) => snapshot.levels[upper_level - 1]
+                .1
+                .retain(|id| !upper_level_sst_ids.contains(id)),
+            // L0 SSTs flushed while compacting are not part of the task and stay
+            None => snapshot
+                .l0_sstables
+                .retain(|id| !upper_level_sst_ids.contains(id)),
+        }
+        let mut new_lower_level_ssts = snapshot.levels[task.lower_level - 1]
+            .1
+            .iter()
+            .filter(|id| !lower_level_sst_ids.contains(id))
+            .copied()
+            .chain(output.iter().copied())
+            .collect::<Vec<_>>();
+        // the SSTs are not opened yet while replaying the manifest, recovery sorts the levels once they are
+        if new_lower_level_ssts
+            .iter()
+            .all(|id| snapshot.sstables.contains_key(id))
+        {
+            new_lower_level_ssts.sort_by(|x, y| {
+                snapshot.sstables[x]
+                    .first_key()
+                    .cmp(snapshot.sstables[y].first_key())
+            });
+        }
+        snapshot.levels[task.lower_level - 1].1 = new_lower_level_ssts;
+
+        let mut files_to_remove = task.upper_level_sst_ids.clone();
+        files_to_remove.extend(&task.lower_level_sst_ids);
+        (snapshot, files_to_remove)
     }
 }
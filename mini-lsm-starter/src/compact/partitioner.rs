@@ -0,0 +1,30 @@
+use std::fmt::Debug;
+
+/// Decides where compaction output is cut into SSTs, in addition to the cut at `target_sst_size`. Cutting at the
+/// boundaries of a key space, e.g. a tenant prefix, keeps keys of different partitions out of the same SST.
+pub trait SstPartitioner: Send + Sync + Debug {
+    /// Whether the SST holding `prev_key` must end before `key`, both user keys, `prev_key` < `key`.
+    fn should_partition(&self, prev_key: &[u8], key: &[u8]) -> bool;
+}
+
+/// Partitions by the first `prefix_len` bytes of the keys.
+#[derive(Debug, Clone)]
+pub struct FixedPrefixPartitioner {
+    prefix_len: usize,
+}
+
+impl FixedPrefixPartitioner {
+    pub fn new(prefix_len: usize) -> Self {
+        Self { prefix_len }
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..key.len().min(self.prefix_len)]
+    }
+}
+
+impl SstPartitioner for FixedPrefixPartitioner {
+    fn should_partition(&self, prev_key: &[u8], key: &[u8]) -> bool {
+        self.prefix(prev_key) != self.prefix(key)
+    }
+}
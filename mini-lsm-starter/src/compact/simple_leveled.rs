@@ -4,12 +4,12 @@ use crate::lsm_storage::LsmStorageState;
 use crate::table::{SsTableIterator, SsTableBuilder,SsTable};
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::StorageIterator;
+use std::collections::HashSet;
 use std::path;
 use std::sync::Arc;
 use crate::lsm_storage::BlockCache;
-use crate::compact::Path;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionOptions {
     pub size_ratio_percent: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -96,33 +96,21 @@ impl SimpleLeveledCompactionController {
         snapshot: &LsmStorageState,
         task: &SimpleLeveledCompactionTask,
         output: &[usize],
-    ) -> (LsmStorageState, Vec<usize> ) {
-
-        // the max sst id is always the last element in the upper level
-        let mut next_sst_id = task.upper_level_sst_ids.last().unwrap()+1;
+    ) -> (LsmStorageState, Vec<usize>) {
         let mut state = snapshot.clone();
-        let mut output = vec![];
+        let mut files_to_remove = task.upper_level_sst_ids.clone();
+        files_to_remove.extend(&task.lower_level_sst_ids);
 
-        // 1.move sst id form upper&lower level to output
-        if task.upper_level.is_none() {
-            output.append(&mut state.l0_sstables);
-        } else {
+        if let Some(upper_level) = task.upper_level {
             // upper_level means real level number, so we need to -1
-            output.append(&mut state.levels[task.upper_level.unwrap()-1].1);
+            state.levels[upper_level - 1].1.clear();
+        } else {
+            // keep the L0 SSTs flushed while the compactor generated the new SSTs
+            let compacted = task.upper_level_sst_ids.iter().collect::<HashSet<_>>();
+            state.l0_sstables.retain(|id| !compacted.contains(id));
         }
-
-        let l0_size = state.l0_sstables.len();
-
         // lower_level means real level number, so we need to -1
-        output.append(&mut state.levels[task.lower_level-1].1);
-        // 2. add new sst id to lower level
-        for i in 0..output.len() {
-            // lower_level means real level number, so we need to -1
-            state.levels[task.lower_level-1].1.push(next_sst_id);
-            next_sst_id += 1;
-        }
-        // deal with L0 SST gets flushed while the compactor generates new SSTs
-        assert!(l0_size == state.l0_sstables.len());
-        (state, output)
+        state.levels[task.lower_level - 1].1 = output.to_vec();
+        (state, files_to_remove)
     }
 }
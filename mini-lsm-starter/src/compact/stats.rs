@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+/// Compaction counters of one level, accumulated since the database was opened. A compaction is counted against
+/// the level it writes to; flushes are counted against L0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelCompactionCounters {
+    /// Bytes read from the level above, or flushed memtables for L0.
+    pub bytes_read_from_upper_level: u64,
+    /// Bytes read from this level, rewritten together with the upper level.
+    pub bytes_read_from_level: u64,
+    pub bytes_written: u64,
+    /// Bytes moved into the level without rewriting them.
+    pub bytes_moved: u64,
+    pub num_compactions: u64,
+    pub compaction_time: Duration,
+}
+
+/// Statistics of one level. For tiered compaction the levels are the sorted runs, newest first, numbered from 1.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelStats {
+    /// 0 for L0.
+    pub level: usize,
+    pub num_files: usize,
+    pub size_bytes: u64,
+    /// Estimated bytes compactions still have to move out of the level to bring it back under its target.
+    pub pending_compaction_bytes: u64,
+    pub counters: LevelCompactionCounters,
+}
+
+impl LevelStats {
+    /// Bytes written into the level for every byte coming from above.
+    pub fn write_amplification(&self) -> f64 {
+        let counters = &self.counters;
+        if counters.bytes_read_from_upper_level == 0 {
+            return 0.0;
+        }
+        counters.bytes_written as f64 / counters.bytes_read_from_upper_level as f64
+    }
+
+    /// Bytes read by the compactions into the level for every byte coming from above.
+    pub fn read_amplification(&self) -> f64 {
+        let counters = &self.counters;
+        if counters.bytes_read_from_upper_level == 0 {
+            return 0.0;
+        }
+        (counters.bytes_read_from_upper_level + counters.bytes_read_from_level) as f64
+            / counters.bytes_read_from_upper_level as f64
+    }
+}
+
+/// Statistics of all the levels, L0 first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionStats {
+    pub levels: Vec<LevelStats>,
+}
+
+impl CompactionStats {
+    /// Bytes written by flushes and compactions for every byte flushed.
+    pub fn write_amplification(&self) -> f64 {
+        let flushed = self
+            .levels
+            .iter()
+            .find(|level| level.level == 0)
+            .map_or(0, |level| level.counters.bytes_written);
+        if flushed == 0 {
+            return 0.0;
+        }
+        let written = self
+            .levels
+            .iter()
+            .map(|level| level.counters.bytes_written)
+            .sum::<u64>();
+        written as f64 / flushed as f64
+    }
+}
+
+const MB: f64 = (1 << 20) as f64;
+
+impl fmt::Display for CompactionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>6} {:>10} {:>11} {:>10} {:>10} {:>10} {:>10} {:>6} {:>6} {:>9} {:>6}",
+            "Level",
+            "Files",
+            "Size(MB)",
+            "Pending(MB)",
+            "Rn(MB)",
+            "Rnp1(MB)",
+            "Write(MB)",
+            "Moved(MB)",
+            "W-Amp",
+            "R-Amp",
+            "Comp(sec)",
+            "Comp"
+        )?;
+        let mut total = LevelStats::default();
+        for level in &self.levels {
+            let counters = &level.counters;
+            writeln!(
+                f,
+                "{:>5} {:>6} {:>10.2} {:>11.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>6.2} {:>6.2} {:>9.3} {:>6}",
+                format!("L{}", level.level),
+                level.num_files,
+                level.size_bytes as f64 / MB,
+                level.pending_compaction_bytes as f64 / MB,
+                counters.bytes_read_from_upper_level as f64 / MB,
+                counters.bytes_read_from_level as f64 / MB,
+                counters.bytes_written as f64 / MB,
+                counters.bytes_moved as f64 / MB,
+                level.write_amplification(),
+                level.read_amplification(),
+                counters.compaction_time.as_secs_f64(),
+                counters.num_compactions
+            )?;
+            total.num_files += level.num_files;
+            total.size_bytes += level.size_bytes;
+            total.pending_compaction_bytes += level.pending_compaction_bytes;
+            total.counters.bytes_written += counters.bytes_written;
+            total.counters.bytes_moved += counters.bytes_moved;
+            total.counters.compaction_time += counters.compaction_time;
+            total.counters.num_compactions += counters.num_compactions;
+        }
+        write!(
+            f,
+            "{:>5} {:>6} {:>10.2} {:>11.2} {:>10} {:>10} {:>10.2} {:>10.2} {:>6.2} {:>6} {:>9.3} {:>6}",
+            "Sum",
+            total.num_files,
+            total.size_bytes as f64 / MB,
+            total.pending_compaction_bytes as f64 / MB,
+            "",
+            "",
+            total.counters.bytes_written as f64 / MB,
+            total.counters.bytes_moved as f64 / MB,
+            self.write_amplification(),
+            "",
+            total.counters.compaction_time.as_secs_f64(),
+            total.counters.num_compactions
+        )
+    }
+}
+
+/// Accumulates the compaction counters of every level.
+#[derive(Default)]
+pub(crate) struct CompactionStatsRecorder {
+    levels: Mutex<BTreeMap<usize, LevelCompactionCounters>>,
+}
+
+impl CompactionStatsRecorder {
+    pub(crate) fn record(&self, level: usize, update: impl FnOnce(&mut LevelCompactionCounters)) {
+        let mut levels = self.levels.lock();
+        let counters = levels.entry(level).or_default();
+        counters.num_compactions += 1;
+        update(counters);
+    }
+
+    pub(crate) fn counters(&self, level: usize) -> LevelCompactionCounters {
+        self.levels.lock().get(&level).cloned().unwrap_or_default()
+    }
+}
@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 
+use crate::clock::{default_clock, Clock};
+use crate::logger::{db_log, DbLogger};
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -8,7 +13,7 @@ pub struct TieredCompactionTask {
     pub bottom_tier_included: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
     pub max_size_amplification_percent: usize,
@@ -18,26 +23,216 @@ pub struct TieredCompactionOptions {
 
 pub struct TieredCompactionController {
     options: TieredCompactionOptions,
+    periodic_compaction_seconds: u64,
+    tombstone_compaction_ratio: f64,
+    clock: Arc<dyn Clock>,
+    logger: DbLogger,
 }
 
 impl TieredCompactionController {
     pub fn new(options: TieredCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            periodic_compaction_seconds: 0,
+            tombstone_compaction_ratio: 0.0,
+            clock: default_clock(),
+            logger: DbLogger::default(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Log the compactions picked through `logger` instead of the `log` facade.
+    pub fn with_logger(mut self, logger: DbLogger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Fully compact the tiers once one of their SSTs was built at least `seconds` ago, so that the tombstones and
+    /// expired entries it holds are eventually purged. Zero disables periodic compaction.
+    pub fn with_periodic_compaction_seconds(mut self, seconds: u64) -> Self {
+        self.periodic_compaction_seconds = seconds;
+        self
     }
 
+    /// Compact a tier holding an SST whose share of tombstones is above `ratio` together with all the older tiers,
+    /// so that the tombstones are dropped soon after a bulk delete. Zero disables it.
+    pub fn with_tombstone_compaction_ratio(mut self, ratio: f64) -> Self {
+        self.tombstone_compaction_ratio = ratio;
+        self
+    }
+
+    /// Every tier is a sorted run, newest first. The sizes of the tiers are measured in SSTs.
     pub fn generate_compaction_task(
         &self,
-        _snapshot: &LsmStorageState,
+        snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
-        unimplemented!()
+        assert!(
+            snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in tiered compaction"
+        );
+        let now = self.clock.unix_seconds();
+        let periodic_compaction_due = self.periodic_compaction_seconds > 0
+            && snapshot.levels.iter().any(|(_, ssts)| {
+                ssts.iter().any(|id| {
+                    super::due_for_periodic_compaction(
+                        &snapshot.sstables[id],
+                        self.periodic_compaction_seconds,
+                        now,
+                    )
+                })
+            });
+        if periodic_compaction_due {
+            db_log!(
+                self.logger,
+                Info,
+                "compaction triggered by periodic compaction"
+            );
+            return Some(TieredCompactionTask {
+                tiers: snapshot.levels.clone(),
+                bottom_tier_included: true,
+            });
+        }
+        // the newest tier holding too many tombstones is merged down into the bottom tier, where they are dropped
+        if let Some(tier) = self.find_tier_dense_in_tombstones(snapshot) {
+            db_log!(
+                self.logger,
+                Info,
+                "compaction triggered by tombstones in tier {}",
+                snapshot.levels[tier].0
+            );
+            return Some(TieredCompactionTask {
+                tiers: snapshot.levels[tier..].to_vec(),
+                bottom_tier_included: true,
+            });
+        }
+        if snapshot.levels.len() < self.options.num_tiers {
+            return None;
+        }
+
+        // space amplification: all the tiers above the last one are rewritten into it
+        let upper_size = snapshot.levels[..snapshot.levels.len() - 1]
+            .iter()
+            .map(|(_, ssts)| ssts.len())
+            .sum::<usize>();
+        let space_amp_ratio =
+            upper_size as f64 / snapshot.levels.last().unwrap().1.len() as f64 * 100.0;
+        if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
+            db_log!(
+                self.logger,
+                Info,
+                "compaction triggered by space amplification ratio: {:.3}%",
+                space_amp_ratio
+            );
+            return Some(TieredCompactionTask {
+                tiers: snapshot.levels.clone(),
+                bottom_tier_included: true,
+            });
+        }
+
+        // size ratio: the newest tiers are merged with the first tier that is not much larger than all of them
+        let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
+        let mut size = 0;
+        for id in 0..snapshot.levels.len() - 1 {
+            size += snapshot.levels[id].1.len();
+            let next_tier_size = snapshot.levels[id + 1].1.len();
+            let size_ratio = size as f64 / next_tier_size as f64;
+            if size_ratio >= size_ratio_trigger && id + 2 >= self.options.min_merge_width {
+                db_log!(
+                    self.logger,
+                    Info,
+                    "compaction triggered by size ratio: {:.3}%",
+                    size_ratio * 100.0
+                );
+                return Some(TieredCompactionTask {
+                    tiers: snapshot.levels[..id + 2].to_vec(),
+                    bottom_tier_included: id + 2 >= snapshot.levels.len(),
+                });
+            }
+        }
+
+        // too many sorted runs: merge the newest tiers regardless of their sizes
+        let num_tiers_to_take = snapshot.levels.len() - self.options.num_tiers + 2;
+        db_log!(
+            self.logger,
+            Info,
+            "compaction triggered by reducing sorted runs"
+        );
+        Some(TieredCompactionTask {
+            tiers: snapshot.levels[..num_tiers_to_take].to_vec(),
+            bottom_tier_included: num_tiers_to_take >= snapshot.levels.len(),
+        })
+    }
+
+    /// The newest tier holding an SST with too many tombstones. The bottom tier is left alone as rewriting it drops
+    /// nothing more.
+    fn find_tier_dense_in_tombstones(&self, snapshot: &LsmStorageState) -> Option<usize> {
+        if self.tombstone_compaction_ratio <= 0.0 {
+            return None;
+        }
+        snapshot
+            .levels
+            .iter()
+            .take(snapshot.levels.len().saturating_sub(1))
+            .position(|(_, ssts)| {
+                ssts.iter().any(|id| {
+                    super::dense_in_tombstones(
+                        &snapshot.sstables[id],
+                        self.tombstone_compaction_ratio,
+                    )
+                })
+            })
     }
 
+    /// Replace the compacted tiers by a single tier of the output, in the position of the oldest of them. Tiers
+    /// flushed while compacting stay on top.
     pub fn apply_compaction_result(
         &self,
-        _snapshot: &LsmStorageState,
-        _task: &TieredCompactionTask,
-        _output: &[usize],
+        snapshot: &LsmStorageState,
+        task: &TieredCompactionTask,
+        output: &[usize],
     ) -> (LsmStorageState, Vec<usize>) {
-        unimplemented!()
+        assert!(
+            snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in tiered compaction"
+        );
+        let mut snapshot = snapshot.clone();
+        let mut tiers_to_remove = task
+            .tiers
+            .iter()
+            .map(|(tier_id, ssts)| (*tier_id, ssts))
+            .collect::<HashMap<_, _>>();
+        let mut levels = Vec::with_capacity(snapshot.levels.len());
+        let mut files_to_remove = Vec::new();
+        let mut new_tier_added = false;
+        for (tier_id, ssts) in &snapshot.levels {
+            if let Some(compacted_ssts) = tiers_to_remove.remove(tier_id) {
+                assert_eq!(
+                    compacted_ssts, ssts,
+                    "tier {tier_id} changed after issuing compaction task"
+                );
+                files_to_remove.extend(ssts.iter().copied());
+            } else {
+                levels.push((*tier_id, ssts.clone()));
+            }
+            if tiers_to_remove.is_empty() && !new_tier_added {
+                new_tier_added = true;
+                // everything may have been deleted
+                if !output.is_empty() {
+                    levels.push((output[0], output.to_vec()));
+                }
+            }
+        }
+        assert!(
+            tiers_to_remove.is_empty(),
+            "tiers {:?} not found",
+            tiers_to_remove.keys()
+        );
+        snapshot.levels = levels;
+        (snapshot, files_to_remove)
     }
 }
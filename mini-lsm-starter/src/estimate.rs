@@ -0,0 +1,118 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState, MiniLsm};
+use crate::table::SsTable;
+
+/// A key range, with the same bounds as `scan`.
+pub type KeyRange<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+impl LsmStorageInner {
+    pub(crate) fn all_sstables(snapshot: &LsmStorageState) -> impl Iterator<Item = &Arc<SsTable>> {
+        snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, files)| files.iter()))
+            .map(|sst_id| &snapshot.sstables[sst_id])
+    }
+
+    /// Estimate the number of live keys from the entry and tombstone counts in the SST properties and memtables.
+    /// Every tombstone is assumed to shadow one older entry; overwritten keys are counted once per version.
+    pub fn estimate_num_keys(&self) -> u64 {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        let (mut entries, mut deletions) = (0, 0);
+        for table in Self::all_sstables(&snapshot) {
+            entries += table.properties().num_entries;
+            deletions += table.properties().num_deletions;
+        }
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            entries += memtable.num_entries() as u64;
+            deletions += memtable.num_deletions() as u64;
+        }
+        entries.saturating_sub(2 * deletions)
+    }
+
+    /// Same as `estimate_num_keys`, restricted to a key range. The SST counts are scaled by the share of data
+    /// bytes of each table that falls into the range, while the memtables are counted exactly.
+    pub fn estimate_num_keys_in_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> u64 {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        let (mut entries, mut deletions) = (0.0, 0.0);
+        for table in Self::all_sstables(&snapshot) {
+            if !Self::range_overlap(
+                lower,
+                upper,
+                table.first_key().key_ref(),
+                table.last_key().key_ref(),
+            ) {
+                continue;
+            }
+            let total = table.approximate_range_size(Bound::Unbounded, Bound::Unbounded);
+            if total == 0 {
+                continue;
+            }
+            let ratio = table.approximate_range_size(lower, upper) as f64 / total as f64;
+            entries += table.properties().num_entries as f64 * ratio;
+            deletions += table.properties().num_deletions as f64 * ratio;
+        }
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            let (memtable_entries, memtable_deletions) = memtable.count_range(lower, upper);
+            entries += memtable_entries as f64;
+            deletions += memtable_deletions as f64;
+        }
+        (entries - 2.0 * deletions).max(0.0).round() as u64
+    }
+
+    /// Estimate how many bytes each key range occupies on disk, based on the block offsets of the overlapping SSTs.
+    /// If `include_memtables` is set, the key and value bytes of the range in the memtables are added as well. The
+    /// result is block-granular and counts overwritten versions and tombstones, so it is meant for decisions such as
+    /// splitting a shard rather than for exact accounting.
+    pub fn get_approximate_sizes(&self, ranges: &[KeyRange], include_memtables: bool) -> Vec<u64> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        ranges
+            .iter()
+            .map(|&(lower, upper)| {
+                let mut size = Self::all_sstables(&snapshot)
+                    .filter(|table| {
+                        Self::range_overlap(
+                            lower,
+                            upper,
+                            table.first_key().key_ref(),
+                            table.last_key().key_ref(),
+                        )
+                    })
+                    .map(|table| table.approximate_range_size(lower, upper))
+                    .sum::<u64>();
+                if include_memtables {
+                    size += std::iter::once(&snapshot.memtable)
+                        .chain(snapshot.imm_memtables.iter())
+                        .map(|memtable| memtable.approximate_range_size(lower, upper) as u64)
+                        .sum::<u64>();
+                }
+                size
+            })
+            .collect()
+    }
+}
+
+impl MiniLsm {
+    pub fn estimate_num_keys(&self) -> u64 {
+        self.inner.estimate_num_keys()
+    }
+
+    pub fn estimate_num_keys_in_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> u64 {
+        self.inner.estimate_num_keys_in_range(lower, upper)
+    }
+
+    pub fn get_approximate_sizes(&self, ranges: &[KeyRange], include_memtables: bool) -> Vec<u64> {
+        self.inner.get_approximate_sizes(ranges, include_memtables)
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::compact::LevelCompactionCounters;
+use crate::write_stall::WriteStallInfo;
+
+/// A flush of an immutable memtable into an SST.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlushJobInfo {
+    /// The id of the flushed memtable, which is also the id of the SST.
+    pub sst_id: usize,
+    /// Approximate size of the memtable.
+    pub memtable_size: u64,
+    pub sst_size: u64,
+    pub num_entries: u64,
+    pub flush_time: Duration,
+}
+
+/// A compaction, with the SSTs it reads and writes. SSTs moved to the lower level without rewriting them are both
+/// an input and an output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionJobInfo {
+    /// The level written to, 0 for compactions that only delete SSTs. For tiered compaction the levels are the
+    /// sorted runs, newest first, numbered from 1 at the time of the event.
+    pub output_level: usize,
+    pub input_sst_ids: Vec<usize>,
+    /// Empty when the compaction begins.
+    pub output_sst_ids: Vec<usize>,
+    /// What the compaction added to the counters of `output_level`, all zero when it begins.
+    pub counters: LevelCompactionCounters,
+}
+
+/// Receives the flush and compaction events of a database, e.g. to export them as telemetry. The callbacks run in
+/// the thread doing the work, after it is done for the completion events, so they should return quickly.
+pub trait EventListener: Send + Sync + Debug {
+    fn on_flush_begin(&self, _memtable_id: usize) {}
+
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    fn on_compaction_begin(&self, _info: &CompactionJobInfo) {}
+
+    /// Called once the output is installed and the compacted SSTs are deleted. A failed compaction only gets
+    /// `on_compaction_begin`.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    /// Called when the writes are stopped or resumed, or stopped for another cause, by the thread that noticed.
+    fn on_stall_conditions_changed(&self, _info: &WriteStallInfo) {}
+}
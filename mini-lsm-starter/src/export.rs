@@ -0,0 +1,122 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_NAME};
+use crate::ingest::{ExternalSstFileInfo, SstFileWriter};
+use crate::iterators::StorageIterator;
+use crate::logger::db_log;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::mvcc::Snapshot;
+
+impl LsmStorageInner {
+    /// Same as `export_snapshot_cf`, in the default column family.
+    pub fn export_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        target_dir: impl AsRef<Path>,
+    ) -> Result<Vec<ExternalSstFileInfo>> {
+        let cf = self.cf(DEFAULT_COLUMN_FAMILY_NAME).unwrap();
+        self.export_snapshot_cf(&cf, snapshot, target_dir)
+    }
+
+    /// Write the column family `cf` as `snapshot` sees it to SSTs in `target_dir`, which must be missing or empty:
+    /// only the latest visible version of each key, without the deleted keys, so that the SSTs hold exactly the
+    /// data of the snapshot whatever their reader knows of timestamps. They are written by `SstFileWriter`, cut at
+    /// the target SST size of the bottom level and named `000001.sst`, `000002.sst` and so on in key order, so
+    /// they do not overlap and can be ingested as they are by `ingest_external_files`. On failure, the SSTs
+    /// written are removed.
+    pub fn export_snapshot_cf(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &Snapshot,
+        target_dir: impl AsRef<Path>,
+    ) -> Result<Vec<ExternalSstFileInfo>> {
+        let target_dir = target_dir.as_ref();
+        if self.fs.exists(target_dir) && !self.fs.list_dir(target_dir)?.is_empty() {
+            bail!("{} is not empty", target_dir.display());
+        }
+        self.fs.create_dir_all(target_dir)?;
+        let mut exported = Vec::new();
+        match self.write_snapshot_ssts(cf, snapshot, target_dir, &mut exported) {
+            Ok(()) => {
+                db_log!(
+                    self.options(),
+                    Info,
+                    "exported {} SSTs of column family {} to {}",
+                    exported.len(),
+                    cf.name(),
+                    target_dir.display()
+                );
+                Ok(exported)
+            }
+            Err(e) => {
+                for info in exported {
+                    if let Err(e) = self.fs.remove_file(&info.path) {
+                        db_log!(
+                            self.options(),
+                            Warn,
+                            "failed to remove {}: {}",
+                            info.path.display(),
+                            e
+                        );
+                    }
+                }
+                Err(e).with_context(|| format!("failed to export to {}", target_dir.display()))
+            }
+        }
+    }
+
+    /// Scan `cf` at `snapshot` into SSTs in `target_dir`, pushed to `exported` as they are written.
+    fn write_snapshot_ssts(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &Snapshot,
+        target_dir: &Path,
+        exported: &mut Vec<ExternalSstFileInfo>,
+    ) -> Result<()> {
+        let options = self.column_family_options(cf.id());
+        let target_sst_size = options.target_sst_size_of_level(usize::MAX);
+        let mut iter =
+            self.scan_cf_with_snapshot(cf, Bound::Unbounded, Bound::Unbounded, snapshot)?;
+        let mut writer: Option<SstFileWriter> = None;
+        while iter.is_valid() {
+            let sst_writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let path = target_dir.join(format!("{:06}.sst", exported.len() + 1));
+                    writer.insert(SstFileWriter::create(path, &options)?)
+                }
+            };
+            sst_writer.put(iter.key(), iter.value())?;
+            if sst_writer.estimated_size() >= target_sst_size {
+                exported.push(writer.take().unwrap().finish()?);
+            }
+            iter.next()?;
+        }
+        if let Some(writer) = writer {
+            exported.push(writer.finish()?);
+        }
+        Ok(())
+    }
+}
+
+impl MiniLsm {
+    pub fn export_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        target_dir: impl AsRef<Path>,
+    ) -> Result<Vec<ExternalSstFileInfo>> {
+        self.inner.export_snapshot(snapshot, target_dir)
+    }
+
+    pub fn export_snapshot_cf(
+        &self,
+        cf: &ColumnFamily,
+        snapshot: &Snapshot,
+        target_dir: impl AsRef<Path>,
+    ) -> Result<Vec<ExternalSstFileInfo>> {
+        self.inner.export_snapshot_cf(cf, snapshot, target_dir)
+    }
+}
@@ -0,0 +1,230 @@
+mod fault_injection;
+mod memory;
+#[cfg(feature = "object-store")]
+mod object_storage;
+#[cfg(all(target_arch = "wasm32", feature = "opfs"))]
+mod opfs;
+
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+pub use fault_injection::FaultInjectionFileSystem;
+pub use memory::InMemoryFileSystem;
+#[cfg(feature = "object-store")]
+pub use object_storage::ObjectStoreFileSystem;
+#[cfg(all(target_arch = "wasm32", feature = "opfs"))]
+pub use opfs::OpfsFileSystem;
+
+/// Every file operation of the storage goes through the `FileSystem` of its options, so that it can be
+/// instrumented, held to a quota, or backed by something else than the local disk. Paths are the ones the storage
+/// builds from the path it is opened with. The secondary cache is not part of it, since it lives on a disk of its
+/// own.
+pub trait FileSystem: Send + Sync + Debug {
+    /// Create the file at `path` to write it from the start, truncating it if it exists.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    /// Create the file at `path`, failing if it exists.
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    /// Open the existing file at `path` to append to it.
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    /// Open the existing file at `path` to read it.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>>;
+
+    /// Read the whole file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let file = self.open(path)?;
+        let mut data = vec![0; file.size()? as usize];
+        file.read_at(&mut data, 0)?;
+        Ok(data)
+    }
+
+    fn exists(&self, path: &Path) -> bool;
+
+    /// The names of the entries of the directory at `path`, in no particular order.
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>>;
+
+    /// Create the directory at `path` with its missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Rename the file at `from` to `to`, replacing `to` if it exists, atomically.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Make `to`, which must not exist, another name of the file at `from`. The default copies the file instead,
+    /// for file systems without links.
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let data = self.read(from)?;
+        let mut file = self.create_new(to)?;
+        file.write_all(&data)?;
+        file.sync()
+    }
+
+    /// Make the creations, renames and removals of files in the directory at `path` durable.
+    fn sync_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Move the file at `path` to the cold storage of the file system, e.g. object storage, from where `open` keeps
+    /// reading it. Returns false, leaving the file where it is, if there is none.
+    fn move_to_cold_storage(&self, _path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// A file being written, whose writes may be buffered until `sync`.
+pub trait WritableFile: Write + Send + Sync {
+    /// Make everything written so far durable.
+    fn sync(&mut self) -> io::Result<()>;
+
+    /// Cut the file to `size` bytes, the next writes going after them.
+    fn truncate(&mut self, size: u64) -> io::Result<()>;
+
+    /// Size of the file in bytes, including what is not synced yet.
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// A file being read at arbitrary offsets, possibly from several threads.
+pub trait ReadableFile: Send + Sync {
+    /// Fill `buf` with the bytes at `offset`, failing if the file ends before.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Size of the file in bytes.
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// The files of the local disk, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalFileSystem;
+
+/// The file system of the options not setting one, the local disk.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_file_system() -> Arc<dyn FileSystem> {
+    Arc::new(LocalFileSystem)
+}
+
+/// The file system of the options not setting one. wasm32 has no local disk, so it is an `InMemoryFileSystem`
+/// shared by the databases of the page, which can be reopened until it is reloaded. `OpfsFileSystem` persists them.
+#[cfg(target_arch = "wasm32")]
+pub fn default_file_system() -> Arc<dyn FileSystem> {
+    static FILE_SYSTEM: std::sync::OnceLock<Arc<InMemoryFileSystem>> = std::sync::OnceLock::new();
+    FILE_SYSTEM
+        .get_or_init(|| Arc::new(InMemoryFileSystem::new()))
+        .clone()
+}
+
+impl FileSystem for LocalFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new().create_new(true).write(true).open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            if let Ok(name) = entry?.file_name().into_string() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::hard_link(from, to)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        File::open(path)?.sync_all()
+    }
+}
+
+impl WritableFile for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+
+    fn truncate(&mut self, size: u64) -> io::Result<()> {
+        self.set_len(size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl ReadableFile for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        read_exact_at(self, buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+/// Fill `buf` with the bytes of `file` at `offset`, without moving its cursor.
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Write `buf` to `file` at `offset`, without moving its cursor.
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+// The other targets, such as wasm32 whose files are the ones of an `OpfsFileSystem` or an `InMemoryFileSystem`, have
+// no local files to read at an offset.
+#[cfg(not(unix))]
+pub(crate) fn read_exact_at(_file: &File, _buf: &mut [u8], _offset: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn write_all_at(_file: &File, _buf: &[u8], _offset: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
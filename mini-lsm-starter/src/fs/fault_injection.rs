@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::{FileSystem, ReadableFile, WritableFile};
+
+/// Wraps a file system to test how the storage copes with crashes and IO errors.
+///
+/// `crash` loses what a power failure would: the bytes written to files since they were last synced. The handles
+/// opened before fail afterwards, so that the storage instance that "crashed" cannot touch the files any more, and
+/// the database is reopened from what is left. The other faults apply until `clear_faults`: failing write
+/// operations, short reads and latency.
+pub struct FaultInjectionFileSystem {
+    base: Arc<dyn FileSystem>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Default)]
+struct FaultState {
+    /// Bumped by every crash, the handles opened before it are dead.
+    epoch: u64,
+    /// The length synced to each file written since the last crash.
+    synced_lens: HashMap<PathBuf, u64>,
+    /// The number of write operations that succeed before the others fail, `None` for no failures.
+    writes_before_failure: Option<usize>,
+    num_short_reads: usize,
+    latency: Duration,
+    num_injected_faults: usize,
+}
+
+impl FaultState {
+    fn check_epoch(&self, epoch: u64) -> io::Result<()> {
+        if epoch != self.epoch {
+            return Err(io::Error::other("the file was opened before a crash"));
+        }
+        Ok(())
+    }
+
+    fn write_operation(&mut self, epoch: u64) -> io::Result<()> {
+        self.check_epoch(epoch)?;
+        match &mut self.writes_before_failure {
+            Some(0) => {
+                self.num_injected_faults += 1;
+                Err(io::Error::other("injected write failure"))
+            }
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the next read comes back short.
+    fn short_read(&mut self) -> bool {
+        if self.num_short_reads == 0 {
+            return false;
+        }
+        self.num_short_reads -= 1;
+        self.num_injected_faults += 1;
+        true
+    }
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(base: Arc<dyn FileSystem>) -> Self {
+        Self {
+            base,
+            state: Arc::default(),
+        }
+    }
+
+    /// Simulate a power failure: cut every file back to what was synced of it, and fail the handles opened
+    /// before. Files created and never synced are left empty. Creating, renaming and removing files is not undone.
+    pub fn crash(&self) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.epoch += 1;
+        for (path, synced_len) in std::mem::take(&mut state.synced_lens) {
+            if !self.base.exists(&path) {
+                continue;
+            }
+            let mut file = self.base.open_append(&path)?;
+            if file.size()? > synced_len {
+                file.truncate(synced_len)?;
+                file.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Let `count` more write operations succeed, then fail all of them until `clear_faults`. Write operations are
+    /// the writes and syncs of files, and creating, renaming and removing them.
+    pub fn fail_writes_after(&self, count: usize) {
+        self.state.lock().writes_before_failure = Some(count);
+    }
+
+    /// Make the next `count` reads short, as from a device returning fewer bytes than asked without reporting it:
+    /// whole files read only half, and reads at an offset only fill the first half of their buffer.
+    pub fn short_reads(&self, count: usize) {
+        self.state.lock().num_short_reads = count;
+    }
+
+    /// Delay every read and write operation by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().latency = latency;
+    }
+
+    /// Stop injecting the write failures, short reads and latency.
+    pub fn clear_faults(&self) {
+        let mut state = self.state.lock();
+        state.writes_before_failure = None;
+        state.num_short_reads = 0;
+        state.latency = Duration::ZERO;
+    }
+
+    /// How many write failures and short reads were injected so far.
+    pub fn num_injected_faults(&self) -> usize {
+        self.state.lock().num_injected_faults
+    }
+
+    /// Count a write operation, returning the epoch of the handles it opens.
+    fn write_operation(&self) -> io::Result<u64> {
+        let (epoch, latency) = {
+            let mut state = self.state.lock();
+            let epoch = state.epoch;
+            state.write_operation(epoch)?;
+            (epoch, state.latency)
+        };
+        delay(latency);
+        Ok(epoch)
+    }
+
+    fn wrap_writable(
+        &self,
+        path: &Path,
+        file: Box<dyn WritableFile>,
+        epoch: u64,
+        synced_len: u64,
+    ) -> Box<dyn WritableFile> {
+        self.state
+            .lock()
+            .synced_lens
+            .entry(path.to_path_buf())
+            .or_insert(synced_len);
+        Box::new(FaultInjectionFile {
+            file,
+            path: path.to_path_buf(),
+            epoch,
+            state: self.state.clone(),
+        })
+    }
+}
+
+impl Debug for FaultInjectionFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjectionFileSystem")
+            .field("base", &self.base)
+            .finish()
+    }
+}
+
+fn delay(latency: Duration) {
+    if !latency.is_zero() {
+        std::thread::sleep(latency);
+    }
+}
+
+impl FileSystem for FaultInjectionFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let epoch = self.write_operation()?;
+        let file = self.base.create(path)?;
+        self.state.lock().synced_lens.insert(path.to_path_buf(), 0);
+        Ok(self.wrap_writable(path, file, epoch, 0))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let epoch = self.write_operation()?;
+        let file = self.base.create_new(path)?;
+        Ok(self.wrap_writable(path, file, epoch, 0))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let epoch = self.write_operation()?;
+        let file = self.base.open_append(path)?;
+        // what the file holds when opened outlived the last crash
+        let size = file.size()?;
+        Ok(self.wrap_writable(path, file, epoch, size))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>> {
+        let (epoch, latency) = {
+            let state = self.state.lock();
+            (state.epoch, state.latency)
+        };
+        delay(latency);
+        Ok(Box::new(FaultInjectionReadableFile {
+            file: self.base.open(path)?,
+            epoch,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let (short, latency) = {
+            let mut state = self.state.lock();
+            (state.short_read(), state.latency)
+        };
+        delay(latency);
+        let mut data = self.base.read(path)?;
+        if short {
+            data.truncate(data.len() / 2);
+        }
+        Ok(data)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.base.exists(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        self.base.list_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.write_operation()?;
+        self.base.create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.write_operation()?;
+        self.base.remove_file(path)?;
+        self.state.lock().synced_lens.remove(path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.write_operation()?;
+        self.base.rename(from, to)?;
+        let mut state = self.state.lock();
+        match state.synced_lens.remove(from) {
+            Some(synced_len) => state.synced_lens.insert(to.to_path_buf(), synced_len),
+            None => state.synced_lens.remove(to),
+        };
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.write_operation()?;
+        self.base.hard_link(from, to)?;
+        // both names lose the same writes to a crash
+        let mut state = self.state.lock();
+        if let Some(synced_len) = state.synced_lens.get(from).copied() {
+            state.synced_lens.insert(to.to_path_buf(), synced_len);
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.write_operation()?;
+        self.base.sync_dir(path)
+    }
+
+    fn move_to_cold_storage(&self, path: &Path) -> io::Result<bool> {
+        self.write_operation()?;
+        self.base.move_to_cold_storage(path)
+    }
+}
+
+struct FaultInjectionFile {
+    file: Box<dyn WritableFile>,
+    path: PathBuf,
+    epoch: u64,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjectionFile {
+    fn write_operation(&self) -> io::Result<()> {
+        let latency = {
+            let mut state = self.state.lock();
+            state.write_operation(self.epoch)?;
+            state.latency
+        };
+        delay(latency);
+        Ok(())
+    }
+}
+
+impl Write for FaultInjectionFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_operation()?;
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.lock().check_epoch(self.epoch)?;
+        self.file.flush()
+    }
+}
+
+impl WritableFile for FaultInjectionFile {
+    fn sync(&mut self) -> io::Result<()> {
+        self.write_operation()?;
+        self.file.sync()?;
+        let size = self.file.size()?;
+        let mut state = self.state.lock();
+        // a sync racing with a crash does not make anything durable after it
+        state.check_epoch(self.epoch)?;
+        state.synced_lens.insert(self.path.clone(), size);
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> io::Result<()> {
+        self.write_operation()?;
+        self.file.truncate(size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.state.lock().check_epoch(self.epoch)?;
+        self.file.size()
+    }
+}
+
+struct FaultInjectionReadableFile {
+    file: Box<dyn ReadableFile>,
+    epoch: u64,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl ReadableFile for FaultInjectionReadableFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let (short, latency) = {
+            let mut state = self.state.lock();
+            state.check_epoch(self.epoch)?;
+            (state.short_read(), state.latency)
+        };
+        delay(latency);
+        self.file.read_at(buf, offset)?;
+        if short {
+            let half = buf.len() / 2;
+            buf[half..].fill(0);
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.state.lock().check_epoch(self.epoch)?;
+        self.file.size()
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::{Mutex, RwLock};
+
+use super::{FileSystem, ReadableFile, WritableFile};
+
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+/// Files kept in memory, for tests and ephemeral databases. Everything is lost when it is dropped, and syncs do
+/// nothing. Files are shared by the handles opened on them, which keep reading a file renamed or removed after
+/// they opened it. Creating a file requires its directory to exist, as on disk.
+#[derive(Default)]
+pub struct InMemoryFileSystem {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    files: HashMap<PathBuf, FileData>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl InMemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Total size of the files in bytes.
+    pub fn total_size(&self) -> u64 {
+        let inner = self.inner.lock();
+        inner
+            .files
+            .values()
+            .map(|data| data.read().len() as u64)
+            .sum()
+    }
+}
+
+impl Debug for InMemoryFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        f.debug_struct("InMemoryFileSystem")
+            .field("num_files", &inner.files.len())
+            .field("num_dirs", &inner.dirs.len())
+            .finish()
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+impl Inner {
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.contains(parent) => {
+                Err(not_found(parent))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<FileData> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+}
+
+impl FileSystem for InMemoryFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(path)?;
+        let data = inner.files.entry(path.to_path_buf()).or_default().clone();
+        data.write().clear();
+        Ok(Box::new(InMemoryFile(data)))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(path)?;
+        if inner.files.contains_key(path) || inner.dirs.contains(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        let data = FileData::default();
+        inner.files.insert(path.to_path_buf(), data.clone());
+        Ok(Box::new(InMemoryFile(data)))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(Box::new(InMemoryFile(self.inner.lock().file(path)?)))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>> {
+        Ok(Box::new(InMemoryFile(self.inner.lock().file(path)?)))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let data = self.inner.lock().file(path)?;
+        let data = data.read().clone();
+        Ok(data)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let inner = self.inner.lock();
+        inner.files.contains_key(path) || inner.dirs.contains(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let inner = self.inner.lock();
+        if !inner.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        Ok(inner
+            .files
+            .keys()
+            .chain(inner.dirs.iter())
+            .filter(|entry| entry.parent() == Some(path))
+            .filter_map(|entry| entry.file_name()?.to_str().map(str::to_string))
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            if inner.files.contains_key(dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+            inner.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        inner
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(to)?;
+        let data = inner.files.remove(from).ok_or_else(|| not_found(from))?;
+        inner.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(to)?;
+        if inner.files.contains_key(to) || inner.dirs.contains(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        let data = inner.file(from)?;
+        inner.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        if !self.inner.lock().dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        Ok(())
+    }
+}
+
+/// A handle on a file of an `InMemoryFileSystem`, appending to it.
+struct InMemoryFile(FileData);
+
+impl Write for InMemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for InMemoryFile {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> io::Result<()> {
+        self.0.write().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.read().len() as u64)
+    }
+}
+
+impl ReadableFile for InMemoryFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let data = self.0.read();
+        let start = offset as usize;
+        let Some(bytes) = data.get(start..start + buf.len()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "reading {} bytes at {} past the end of the file of {} bytes",
+                    buf.len(),
+                    offset,
+                    data.len()
+                ),
+            ));
+        };
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.read().len() as u64)
+    }
+}
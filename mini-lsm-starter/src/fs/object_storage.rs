@@ -0,0 +1,293 @@
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, PutPayload};
+use tokio::runtime::Runtime;
+
+use super::{FileSystem, ReadableFile, WritableFile};
+
+/// A file system keeping the files moved to its cold storage in an object store, e.g. S3 or GCS with the `s3` and
+/// `gcs` features, and the others in a local one. The files under `root`, the directory of the database, are
+/// stored as the objects of the same relative path under `prefix`; a file missing locally is looked for there, so
+/// that the files moved keep being opened, listed and removed by their paths. Reads of a moved file are range GETs,
+/// which the block cache saves for the data blocks. Writes always go to the local file system.
+pub struct ObjectStoreFileSystem {
+    local: Arc<dyn FileSystem>,
+    root: PathBuf,
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    runtime: Arc<StoreRuntime>,
+}
+
+/// Runs the requests of the store, whose clients are async, for the callers blocking on them. It is shut down
+/// without waiting, since the last file system or file holding it may be dropped by a task of another runtime.
+struct StoreRuntime(Option<Runtime>);
+
+impl Drop for StoreRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl ObjectStoreFileSystem {
+    pub fn new(
+        local: Arc<dyn FileSystem>,
+        root: impl Into<PathBuf>,
+        store: Arc<dyn ObjectStore>,
+        prefix: ObjectPath,
+    ) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("object-store")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            local,
+            root: root.into(),
+            store,
+            prefix,
+            runtime: Arc::new(StoreRuntime(Some(runtime))),
+        })
+    }
+
+    /// The object of the file at `path`, `None` if it is not under the root.
+    fn location(&self, path: &Path) -> Option<ObjectPath> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let mut location = self.prefix.clone();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => location = location.child(part.to_str()?),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        Some(location)
+    }
+
+    /// Whether the file at `path` is in the object store, rather than on the local file system.
+    pub fn is_in_object_store(&self, path: &Path) -> io::Result<bool> {
+        if self.local.exists(path) {
+            return Ok(false);
+        }
+        match self.location(path) {
+            Some(location) => {
+                let store = self.store.clone();
+                match block_on(&self.runtime, async move { store.head(&location).await }) {
+                    Ok(_) => Ok(true),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+                    Err(err) => Err(err),
+                }
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Debug for ObjectStoreFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectStoreFileSystem")
+            .field("local", &self.local)
+            .field("root", &self.root)
+            .field("store", &self.store.to_string())
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+fn to_io_error(err: object_store::Error) -> io::Error {
+    match err {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, err),
+        err => io::Error::other(err),
+    }
+}
+
+/// Run a request of the store on its runtime and wait for it. The request is spawned rather than run by
+/// `Runtime::block_on`, which panics on the threads of another runtime, such as those of `AsyncLsmStorage`.
+fn block_on<T, F>(runtime: &StoreRuntime, request: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: Future<Output = object_store::Result<T>> + Send + 'static,
+{
+    let (sender, receiver) = std::sync::mpsc::channel();
+    runtime.0.as_ref().unwrap().spawn(async move {
+        let _ = sender.send(request.await);
+    });
+    receiver
+        .recv()
+        .map_err(|_| io::Error::other("the object store request was cancelled"))?
+        .map_err(to_io_error)
+}
+
+impl FileSystem for ObjectStoreFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.local.create(path)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        if self.is_in_object_store(path)? {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is in the object store", path.display()),
+            ));
+        }
+        self.local.create_new(path)
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.local.open_append(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>> {
+        match self.local.open(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let Some(location) = self.location(path) else {
+                    return Err(err);
+                };
+                let store = self.store.clone();
+                let head_location = location.clone();
+                let meta = block_on(
+                    &self.runtime,
+                    async move { store.head(&head_location).await },
+                )?;
+                Ok(Box::new(ObjectFile {
+                    store: self.store.clone(),
+                    location,
+                    size: meta.size as u64,
+                    runtime: self.runtime.clone(),
+                }))
+            }
+            result => result,
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.local.exists(path) || self.is_in_object_store(path).unwrap_or(false)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = self.local.list_dir(path)?;
+        if let Some(location) = self.location(path) {
+            let store = self.store.clone();
+            let listing = block_on(&self.runtime, async move {
+                store.list_with_delimiter(Some(&location)).await
+            })?;
+            for object in listing.objects {
+                if let Some(name) = object.location.filename() {
+                    if !names.iter().any(|local| local == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.local.create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.local.remove_file(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let Some(location) = self.location(path) else {
+                    return Err(err);
+                };
+                // stores deleting missing objects silently are asked first, to fail as the local file systems do
+                if !self.is_in_object_store(path)? {
+                    return Err(err);
+                }
+                let store = self.store.clone();
+                block_on(&self.runtime, async move { store.delete(&location).await })
+            }
+            result => result,
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if !self.is_in_object_store(from)? {
+            return self.local.rename(from, to);
+        }
+        let (Some(from), Some(to)) = (self.location(from), self.location(to)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot rename a file of the object store out of the root",
+            ));
+        };
+        let store = self.store.clone();
+        block_on(&self.runtime, async move { store.rename(&from, &to).await })
+    }
+
+    /// A file of the object store is copied to the local file system instead, e.g. for a checkpoint.
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if !self.is_in_object_store(from)? {
+            return self.local.hard_link(from, to);
+        }
+        let data = self.read(from)?;
+        let mut file = self.local.create_new(to)?;
+        file.write_all(&data)?;
+        file.sync()
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.local.sync_dir(path)
+    }
+
+    /// Upload the file, then remove it from the local file system.
+    fn move_to_cold_storage(&self, path: &Path) -> io::Result<bool> {
+        let Some(location) = self.location(path) else {
+            return Ok(false);
+        };
+        let data = self.local.read(path)?;
+        let store = self.store.clone();
+        block_on(&self.runtime, async move {
+            store.put(&location, PutPayload::from(data)).await
+        })?;
+        self.local.remove_file(path)?;
+        self.local.sync_dir(path.parent().unwrap_or(&self.root))?;
+        Ok(true)
+    }
+}
+
+/// A file of the object store, read by range GETs.
+struct ObjectFile {
+    store: Arc<dyn ObjectStore>,
+    location: ObjectPath,
+    size: u64,
+    runtime: Arc<StoreRuntime>,
+}
+
+impl ReadableFile for ObjectFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let end = offset + buf.len() as u64;
+        if end > self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("reading up to {} of {} bytes", end, self.size),
+            ));
+        }
+        let store = self.store.clone();
+        let location = self.location.clone();
+        let range = offset as usize..end as usize;
+        let data = block_on(&self.runtime, async move {
+            store.get_range(&location, range).await
+        })?;
+        if data.len() != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("read {} of {} bytes", data.len(), buf.len()),
+            ));
+        }
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+}
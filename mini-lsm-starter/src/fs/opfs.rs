@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetDirectoryOptions,
+    FileSystemGetFileOptions, FileSystemReadWriteOptions, FileSystemSyncAccessHandle,
+    WorkerGlobalScope,
+};
+
+use super::{FileSystem, ReadableFile, WritableFile};
+
+#[cfg(target_feature = "atomics")]
+compile_error!("the OPFS handles are only used from the thread opening them, wasm32 must be built without atomics");
+
+/// Every slot starts with a header naming the file or directory it holds, followed by the data of the file.
+const HEADER_SIZE: u64 = 4096;
+const KIND_FREE: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_DIR: u8 = 2;
+
+/// The files of the database in the origin private file system (OPFS) of the browser, read and written through
+/// sync access handles, so that the storage code runs unchanged in a dedicated worker, the only place they are
+/// available. Since a handle can only be created asynchronously, the file system holds a pool of slot files, all
+/// of them opened by `open`, which the files and directories of the database are mapped onto by a header naming
+/// them. Creating a file fails once every slot is taken, so `capacity` must cover the SSTs, WALs, manifests and
+/// directories of the database, with room for the files being compacted. The handles lock their files, so one
+/// worker at a time opens the pool.
+pub struct OpfsFileSystem {
+    inner: Mutex<Inner>,
+}
+
+/// A sync access handle, closed when dropped so that the slot can be opened again.
+struct SyncHandle(FileSystemSyncAccessHandle);
+
+// SAFETY: without atomics wasm32 runs a single thread, so the handle never leaves the one it was created on.
+unsafe impl Send for SyncHandle {}
+unsafe impl Sync for SyncHandle {}
+
+impl Drop for SyncHandle {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// A slot file of the pool, shared by the handles opened on the file it holds. A slot freed stays reserved until
+/// the last of them is dropped, so that they keep reading the file removed or replaced after they opened it.
+struct Slot(SyncHandle);
+
+#[derive(Default)]
+struct Inner {
+    slots: Vec<Arc<Slot>>,
+    files: HashMap<PathBuf, Arc<Slot>>,
+    dirs: HashMap<PathBuf, Arc<Slot>>,
+    /// Orders the headers written, the latest of two slots naming the same path after a crash holds it.
+    generation: u64,
+}
+
+fn js_error(err: JsValue) -> io::Error {
+    io::Error::other(format!("{:?}", err))
+}
+
+async fn resolve(promise: js_sys::Promise) -> io::Result<JsValue> {
+    JsFuture::from(promise).await.map_err(js_error)
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+impl Slot {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(offset as f64);
+        let read = (self.0)
+            .0
+            .read_with_u8_array_and_options(buf, &options)
+            .map_err(js_error)?;
+        Ok(read as usize)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(offset as f64);
+        let written = (self.0)
+            .0
+            .write_with_u8_array_and_options(buf, &options)
+            .map_err(js_error)? as usize;
+        if written != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("wrote {} of {} bytes", written, buf.len()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Size of the data, after the header.
+    fn size(&self) -> io::Result<u64> {
+        let size = (self.0).0.get_size().map_err(js_error)? as u64;
+        Ok(size.saturating_sub(HEADER_SIZE))
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        (self.0)
+            .0
+            .truncate_with_f64((HEADER_SIZE + size) as f64)
+            .map_err(js_error)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (self.0).0.flush().map_err(js_error)
+    }
+
+    fn write_header(&self, kind: u8, generation: u64, path: &Path) -> io::Result<()> {
+        let path = path.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not UTF-8", path.display()),
+            )
+        })?;
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.put_u8(kind);
+        header.put_u64(generation);
+        header.put_u16(path.len() as u16);
+        header.put_slice(path.as_bytes());
+        if header.len() > HEADER_SIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is too long", path),
+            ));
+        }
+        header.resize(HEADER_SIZE as usize, 0);
+        self.write_at(&header, 0)?;
+        self.flush()
+    }
+
+    /// The kind, generation and path of the header, `None` for a slot never written.
+    fn read_header(&self) -> io::Result<Option<(u8, u64, PathBuf)>> {
+        let mut header = vec![0; HEADER_SIZE as usize];
+        if self.read_at(&mut header, 0)? < header.len() {
+            return Ok(None);
+        }
+        let mut buf = &header[..];
+        let (kind, generation, len) = (buf.get_u8(), buf.get_u64(), buf.get_u16() as usize);
+        if kind == KIND_FREE {
+            return Ok(None);
+        }
+        let path = std::str::from_utf8(&buf[..len.min(buf.len())])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some((kind, generation, PathBuf::from(path))))
+    }
+}
+
+impl OpfsFileSystem {
+    /// Open the pool of the directory `name` at the root of the OPFS of the origin, creating it if needed. Only
+    /// available in a dedicated worker.
+    pub async fn open(name: &str, capacity: usize) -> io::Result<Self> {
+        let global = js_sys::global()
+            .dyn_into::<WorkerGlobalScope>()
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the OPFS sync access handles are only available in a dedicated worker",
+                )
+            })?;
+        let root: FileSystemDirectoryHandle = resolve(global.navigator().storage().get_directory())
+            .await?
+            .unchecked_into();
+        let options = FileSystemGetDirectoryOptions::new();
+        options.set_create(true);
+        let dir = resolve(root.get_directory_handle_with_options(name, &options))
+            .await?
+            .unchecked_into();
+        Self::open_in(&dir, capacity).await
+    }
+
+    /// Open the pool of `dir` with at least `capacity` slots, creating the missing ones. The slots beyond
+    /// `capacity` left by a previous open are kept.
+    pub async fn open_in(dir: &FileSystemDirectoryHandle, capacity: usize) -> io::Result<Self> {
+        let mut inner = Inner::default();
+        let mut headers = Vec::new();
+        for index in 0.. {
+            let name = format!("{}.slot", index);
+            let file = if index < capacity {
+                let options = FileSystemGetFileOptions::new();
+                options.set_create(true);
+                resolve(dir.get_file_handle_with_options(&name, &options)).await?
+            } else {
+                match resolve(dir.get_file_handle(&name)).await {
+                    Ok(file) => file,
+                    Err(_) => break,
+                }
+            };
+            let file: FileSystemFileHandle = file.unchecked_into();
+            let handle = resolve(file.create_sync_access_handle())
+                .await?
+                .unchecked_into();
+            let slot = Arc::new(Slot(SyncHandle(handle)));
+            headers.push(slot.read_header()?);
+            inner.slots.push(slot);
+        }
+
+        // a crash in the middle of a rename leaves two slots naming its target, the latest one holds it
+        let mut latest = HashMap::new();
+        for (index, header) in headers.iter().enumerate() {
+            if let Some((_, generation, path)) = header {
+                let current = latest.entry(path.clone()).or_insert(index);
+                if headers[*current].as_ref().unwrap().1 < *generation {
+                    *current = index;
+                }
+            }
+        }
+        for (index, header) in headers.into_iter().enumerate() {
+            let Some((kind, generation, path)) = header else {
+                continue;
+            };
+            inner.generation = inner.generation.max(generation);
+            let slot = inner.slots[index].clone();
+            if latest[&path] != index {
+                slot.write_header(KIND_FREE, generation, &path)?;
+            } else if kind == KIND_DIR {
+                inner.dirs.insert(path, slot);
+            } else {
+                inner.files.insert(path, slot);
+            }
+        }
+        Ok(Self {
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// The slots free to create files or directories in.
+    pub fn num_free_slots(&self) -> usize {
+        let inner = self.inner.lock();
+        inner
+            .slots
+            .iter()
+            .filter(|slot| Arc::strong_count(slot) == 1)
+            .count()
+    }
+}
+
+impl Debug for OpfsFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        f.debug_struct("OpfsFileSystem")
+            .field("num_slots", &inner.slots.len())
+            .field("num_files", &inner.files.len())
+            .field("num_dirs", &inner.dirs.len())
+            .finish()
+    }
+}
+
+impl Inner {
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.contains_key(parent) => {
+                Err(not_found(parent))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<Arc<Slot>> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+
+    /// Take a free slot to hold the file or directory at `path`.
+    fn claim(&mut self, kind: u8, path: &Path) -> io::Result<Arc<Slot>> {
+        let slot = self
+            .slots
+            .iter()
+            .find(|slot| Arc::strong_count(slot) == 1)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    format!("no free slot left for {}", path.display()),
+                )
+            })?;
+        slot.truncate(0)?;
+        self.generation += 1;
+        slot.write_header(kind, self.generation, path)?;
+        Ok(slot)
+    }
+
+    /// Free the slot of a file or directory removed, which the handles opened on it keep reading.
+    fn release(&mut self, slot: &Slot, path: &Path) -> io::Result<()> {
+        self.generation += 1;
+        slot.write_header(KIND_FREE, self.generation, path)
+    }
+}
+
+impl FileSystem for OpfsFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(path)?;
+        if let Some(slot) = inner.files.get(path) {
+            slot.truncate(0)?;
+            return Ok(Box::new(OpfsFile(slot.clone())));
+        }
+        if inner.dirs.contains_key(path) {
+            return Err(already_exists(path));
+        }
+        let slot = inner.claim(KIND_FILE, path)?;
+        inner.files.insert(path.to_path_buf(), slot.clone());
+        Ok(Box::new(OpfsFile(slot)))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(path)?;
+        if inner.files.contains_key(path) || inner.dirs.contains_key(path) {
+            return Err(already_exists(path));
+        }
+        let slot = inner.claim(KIND_FILE, path)?;
+        inner.files.insert(path.to_path_buf(), slot.clone());
+        Ok(Box::new(OpfsFile(slot)))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(Box::new(OpfsFile(self.inner.lock().file(path)?)))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>> {
+        Ok(Box::new(OpfsFile(self.inner.lock().file(path)?)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let inner = self.inner.lock();
+        inner.files.contains_key(path) || inner.dirs.contains_key(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let inner = self.inner.lock();
+        if !inner.dirs.contains_key(path) {
+            return Err(not_found(path));
+        }
+        Ok(inner
+            .files
+            .keys()
+            .chain(inner.dirs.keys())
+            .filter(|entry| entry.parent() == Some(path))
+            .filter_map(|entry| entry.file_name()?.to_str().map(str::to_string))
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        let mut missing = Vec::new();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            if inner.files.contains_key(dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+            if !inner.dirs.contains_key(dir) {
+                missing.push(dir);
+            }
+        }
+        for dir in missing.into_iter().rev() {
+            let slot = inner.claim(KIND_DIR, dir)?;
+            inner.dirs.insert(dir.to_path_buf(), slot);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        let slot = inner.files.remove(path).ok_or_else(|| not_found(path))?;
+        inner.release(&slot, path)
+    }
+
+    /// Rewrites the header of the slot of `from` before freeing the one of `to`, so that a crash in between
+    /// leaves `to` renamed, see `open_in`.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(to)?;
+        let slot = inner.file(from)?;
+        inner.generation += 1;
+        slot.write_header(KIND_FILE, inner.generation, to)?;
+        inner.files.remove(from);
+        if let Some(replaced) = inner.files.insert(to.to_path_buf(), slot) {
+            if !Arc::ptr_eq(&replaced, &inner.files[to]) {
+                inner.release(&replaced, to)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The headers are flushed as they are written.
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        if !self.inner.lock().dirs.contains_key(path) {
+            return Err(not_found(path));
+        }
+        Ok(())
+    }
+}
+
+/// A handle on a file of an `OpfsFileSystem`, appending to it.
+struct OpfsFile(Arc<Slot>);
+
+impl Write for OpfsFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = HEADER_SIZE + self.0.size()?;
+        self.0.write_at(buf, end)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for OpfsFile {
+    fn sync(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn truncate(&mut self, size: u64) -> io::Result<()> {
+        self.0.truncate(size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.0.size()
+    }
+}
+
+impl ReadableFile for OpfsFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let size = self.0.size()?;
+        if offset + buf.len() as u64 > size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "reading {} bytes at {} past the end of the file of {} bytes",
+                    buf.len(),
+                    offset,
+                    size
+                ),
+            ));
+        }
+        let read = self.0.read_at(buf, HEADER_SIZE + offset)?;
+        if read != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("read {} of {} bytes", read, buf.len()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.0.size()
+    }
+}
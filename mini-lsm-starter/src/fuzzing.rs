@@ -0,0 +1,74 @@
+//! The entry points of the cargo-fuzz targets of `fuzz/`, one per decoder of the data read back from the files.
+//! Malformed input must make them fail, never panic: each one decodes its input, then uses what it decoded the way a
+//! reader does, and drops the errors.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::block::{Block, BlockIterator};
+use crate::key::KeySlice;
+use crate::logger::DbLogger;
+use crate::manifest::{Manifest, ManifestRecord};
+use crate::table::bloom::Bloom;
+use crate::table::BlockMeta;
+use crate::wal::Wal;
+
+/// Decode a data block, then read all its entries and seek to a key taken from the input.
+pub fn decode_block(data: &[u8]) {
+    let Ok(block) = Block::decode(data) else {
+        return;
+    };
+    let block = Arc::new(block);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    while iter.is_valid() {
+        let _ = (iter.key(), iter.value());
+        iter.next();
+    }
+    let key = &data[..data.len().min(8)];
+    let mut iter = BlockIterator::create_and_seek_to_key(block, KeySlice::from_slice(key, 0));
+    while iter.is_valid() {
+        iter.next();
+    }
+}
+
+/// Decode the block metadata of an SST. The checksum of the input is computed and appended, for the fuzzer to get
+/// past it.
+pub fn decode_block_meta(data: &[u8]) {
+    let _ = BlockMeta::decode_block_meta(data);
+    if data.len() >= 4 {
+        let mut buf = data.to_vec();
+        buf.extend(crc32fast::hash(&data[4..]).to_be_bytes());
+        let _ = BlockMeta::decode_block_meta(&buf);
+    }
+}
+
+/// Decode a bloom filter, then probe it. The checksum of the input is computed and appended, for the fuzzer to get
+/// past it.
+pub fn decode_bloom(data: &[u8]) {
+    let mut buf = data.to_vec();
+    buf.extend(crc32fast::hash(data).to_be_bytes());
+    for buf in [data, &buf] {
+        if let Ok(bloom) = Bloom::decode(buf) {
+            bloom.may_contain(crc32fast::hash(data));
+        }
+    }
+}
+
+/// Decode the input both as the frames of a WAL, and as a single record.
+pub fn decode_wal(data: &[u8]) {
+    let data = Bytes::copy_from_slice(data);
+    let _ = Wal::decode_records(Path::new("fuzz.wal"), &data);
+    if let Ok(record) = Wal::decode_record(data) {
+        record.commit_ts();
+    }
+}
+
+/// Decode the input both as the frames of a manifest, and as the json of a single record, which is encoded back.
+pub fn decode_manifest(data: &[u8]) {
+    let _ = Manifest::decode_records(data, &DbLogger::default());
+    if let Ok(Some(record)) = ManifestRecord::decode(data, &DbLogger::default()) {
+        let _ = record.encode();
+    }
+}
@@ -0,0 +1,158 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::asynk::AsyncLsmStorage;
+use crate::lsm_storage::WriteBatchRecord;
+
+/// The messages, client and server of the `KeyValue` service, generated from `proto/kv.proto`.
+pub mod proto {
+    tonic::include_proto!("minilsm.kv.v1");
+}
+
+use proto::key_value_server::{KeyValue, KeyValueServer};
+use proto::write_operation::Op;
+use proto::{
+    BatchWriteRequest, BatchWriteResponse, DeleteRequest, DeleteResponse, GetRequest, GetResponse,
+    KeyValuePair, PutRequest, PutResponse, ScanRequest, ScanResponse,
+};
+
+/// Entries of a scan sent in a single `ScanResponse`.
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// The `KeyValue` gRPC service over a database. Concurrent writes are grouped as `AsyncLsmStorage` does, and a scan
+/// streams its entries from the snapshot taken when it starts.
+pub struct KeyValueService {
+    storage: Arc<AsyncLsmStorage>,
+}
+
+impl KeyValueService {
+    pub fn new(storage: Arc<AsyncLsmStorage>) -> Self {
+        Self { storage }
+    }
+
+    /// The service, to add to a `tonic` server.
+    pub fn into_server(self) -> KeyValueServer<Self> {
+        KeyValueServer::new(self)
+    }
+}
+
+fn internal(err: anyhow::Error) -> Status {
+    Status::internal(format!("{:#}", err))
+}
+
+fn bound(bound: &Option<proto::Bound>) -> Bound<&[u8]> {
+    match bound {
+        Some(bound) if bound.inclusive => Bound::Included(&bound.key),
+        Some(bound) => Bound::Excluded(&bound.key),
+        None => Bound::Unbounded,
+    }
+}
+
+#[tonic::async_trait]
+impl KeyValue for KeyValueService {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let value = self
+            .storage
+            .get(&request.get_ref().key)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(GetResponse {
+            found: value.is_some(),
+            value: value.map(Vec::from).unwrap_or_default(),
+        }))
+    }
+
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        let request = request.get_ref();
+        self.storage
+            .put(&request.key, &request.value)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(PutResponse {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        self.storage
+            .delete(&request.get_ref().key)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    type ScanStream = ReceiverStream<Result<ScanResponse, Status>>;
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        let request = request.into_inner();
+        let mut scan = self
+            .storage
+            .scan(bound(&request.lower), bound(&request.upper))
+            .await
+            .map_err(internal)?;
+        let limit = match request.limit {
+            0 => usize::MAX,
+            limit => usize::try_from(limit).unwrap_or(usize::MAX),
+        };
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut entries = Vec::with_capacity(SCAN_BATCH_SIZE);
+            let mut num_entries = 0;
+            while num_entries < limit {
+                match scan.next().await {
+                    Ok(Some((key, value))) => {
+                        entries.push(KeyValuePair {
+                            key: key.into(),
+                            value: value.into(),
+                        });
+                        num_entries += 1;
+                    }
+                    Ok(None) => break,
+                    Err(err) => {
+                        let _ = sender.send(Err(internal(err))).await;
+                        return;
+                    }
+                }
+                if entries.len() == SCAN_BATCH_SIZE {
+                    let response = ScanResponse {
+                        entries: std::mem::take(&mut entries),
+                    };
+                    if sender.send(Ok(response)).await.is_err() {
+                        // the client went away
+                        return;
+                    }
+                }
+            }
+            if !entries.is_empty() {
+                let _ = sender.send(Ok(ScanResponse { entries })).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn batch_write(
+        &self,
+        request: Request<BatchWriteRequest>,
+    ) -> Result<Response<BatchWriteResponse>, Status> {
+        let batch = request
+            .get_ref()
+            .operations
+            .iter()
+            .map(|operation| match operation.op.as_ref()? {
+                Op::Put(put) => Some(WriteBatchRecord::Put(&put.key[..], &put.value[..])),
+                Op::Delete(delete) => Some(WriteBatchRecord::Del(&delete.key[..])),
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Status::invalid_argument("write operation without an op"))?;
+        self.storage.write_batch(&batch).await.map_err(internal)?;
+        Ok(Response::new(BatchWriteResponse {}))
+    }
+}
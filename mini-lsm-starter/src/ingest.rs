@@ -0,0 +1,299 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+
+use crate::block::CachePriority;
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_NAME};
+use crate::fs::FileSystem;
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_DEFAULT, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::logger::db_log;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm};
+use crate::manifest::ManifestRecord;
+use crate::table::{FileChecksum, FileObject, SsTable, SsTableBuilder};
+
+/// An SST written by `SstFileWriter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalSstFileInfo {
+    pub path: PathBuf,
+    pub smallest_key: Bytes,
+    pub largest_key: Bytes,
+    pub num_entries: u64,
+    pub file_size: u64,
+}
+
+/// Writes an SST from sorted key-value pairs without a database, to be added to one by `ingest_external_files`.
+/// The versions are written at the timestamp 0, below the ones of every write, so that the SSTs can be ingested as
+/// they are: their keys must not be in the database already.
+pub struct SstFileWriter {
+    builder: SsTableBuilder,
+    path: PathBuf,
+    fs: Arc<dyn FileSystem>,
+    last_key: Option<Bytes>,
+    num_entries: u64,
+}
+
+impl SstFileWriter {
+    /// Start the SST at `path`, with the block size, compression of the bottom level and file system of `options`.
+    pub fn create(path: impl AsRef<Path>, options: &LsmStorageOptions) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let fs = options.file_system_or_default();
+        if fs.exists(&path) {
+            bail!("{} already exists", path.display());
+        }
+        Ok(Self {
+            builder: SsTableBuilder::new(options.block_size)
+                .with_compression(options.compression_of_level(usize::MAX))
+                .with_file_system(fs.clone())
+                .with_clock(options.clock_or_default()),
+            path,
+            fs,
+            last_key: None,
+            num_entries: 0,
+        })
+    }
+
+    /// Add `key`, which must be after the keys added so far.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            bail!("key cannot be empty");
+        }
+        if value.is_empty() {
+            bail!("value cannot be empty");
+        }
+        if let Some(last_key) = &self.last_key {
+            if key <= last_key.as_ref() {
+                bail!("keys must be added in increasing order");
+            }
+        }
+        self.builder
+            .add(KeySlice::from_slice(key, TS_DEFAULT), value);
+        self.last_key = Some(Bytes::copy_from_slice(key));
+        self.num_entries += 1;
+        Ok(())
+    }
+
+    /// The size of the SST written so far, to cut a stream of keys into SSTs of a target size.
+    pub fn estimated_size(&self) -> usize {
+        self.builder.estimated_size()
+    }
+
+    /// Write the SST, which must not be empty.
+    pub fn finish(self) -> Result<ExternalSstFileInfo> {
+        if self.num_entries == 0 {
+            bail!("cannot write an empty SST");
+        }
+        let sst = self.builder.build(0, None, &self.path)?;
+        self.fs
+            .sync_dir(self.path.parent().unwrap_or(Path::new(".")))?;
+        Ok(ExternalSstFileInfo {
+            path: self.path,
+            smallest_key: Bytes::copy_from_slice(sst.first_key().key_ref()),
+            largest_key: Bytes::copy_from_slice(sst.last_key().key_ref()),
+            num_entries: self.num_entries,
+            file_size: sst.table_size(),
+        })
+    }
+}
+
+impl LsmStorageInner {
+    /// Same as `ingest_external_files_cf`, in the default column family.
+    pub fn ingest_external_files(&self, paths: &[impl AsRef<Path>]) -> Result<()> {
+        let cf = self.cf(DEFAULT_COLUMN_FAMILY_NAME).unwrap();
+        self.ingest_external_files_cf(&cf, paths)
+    }
+
+    /// Add the SSTs written by `SstFileWriter` at `paths` to the column family `cf`, without rewriting them: they
+    /// are hard linked into the database, then go to its bottom level (the oldest sorted run for tiered compaction,
+    /// L0 when there are no levels) in a single manifest record. Their keys must not overlap each other nor the
+    /// keys of the column family, so that they do not need newer timestamps. As they are written below every
+    /// timestamp, the snapshots taken before see them. The files at `paths` are left as they are.
+    pub fn ingest_external_files_cf(
+        &self,
+        cf: &ColumnFamily,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<()> {
+        self.check_writable()?;
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let options = self.column_family_options(cf.id());
+        let mut external = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let sst = SsTable::open(0, None, FileObject::open(self.fs.as_ref(), path)?)
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            if sst.max_ts() != TS_DEFAULT {
+                bail!("{} was not written by SstFileWriter", path.display());
+            }
+            external.push((path, sst));
+        }
+        external.sort_by(|(_, x), (_, y)| x.first_key().key_ref().cmp(y.first_key().key_ref()));
+        for pair in external.windows(2) {
+            if pair[0].1.last_key().key_ref() >= pair[1].1.first_key().key_ref() {
+                bail!(
+                    "{} and {} overlap",
+                    pair[0].0.display(),
+                    pair[1].0.display()
+                );
+            }
+        }
+
+        // nothing deletes the linked SSTs as obsolete, nor changes the SSTs of the column family, meanwhile
+        let _compaction_lock = self.compaction_lock.lock();
+        let mut linked = Vec::new();
+        match self.link_external_files(cf, &external, &options, &mut linked) {
+            Ok(level) => {
+                db_log!(
+                    self.options(),
+                    Info,
+                    "ingested {} external SSTs into column family {} at level {}",
+                    linked.len(),
+                    cf.name(),
+                    level
+                );
+                Ok(())
+            }
+            Err(e) => {
+                for sst_id in linked {
+                    if let Err(e) = self.fs.remove_file(&self.path_of_sst(sst_id)) {
+                        db_log!(
+                            self.options(),
+                            Warn,
+                            "failed to remove {}.sst: {}",
+                            sst_id,
+                            e
+                        );
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Link the `external` SSTs into the database under new ids, pushed to `linked`, and add them to the bottom
+    /// level of `cf`, returned. Must be called with the compaction lock.
+    fn link_external_files(
+        &self,
+        cf: &ColumnFamily,
+        external: &[(&Path, SsTable)],
+        options: &LsmStorageOptions,
+        linked: &mut Vec<usize>,
+    ) -> Result<usize> {
+        let mut new_ssts = Vec::new();
+        for (path, _) in external {
+            let sst_id = self.next_sst_id();
+            let sst_path = self.path_of_sst(sst_id);
+            self.fs
+                .hard_link(path, &sst_path)
+                .with_context(|| format!("failed to link {}", path.display()))?;
+            linked.push(sst_id);
+            let file_checksum = FileChecksum::of_file(self.fs.as_ref(), &sst_path)?;
+            let sst = SsTable::open(
+                sst_id,
+                Some(self.block_cache.clone()),
+                FileObject::open(self.fs.as_ref(), &sst_path)?,
+            )?
+            .with_file_checksum(Some(file_checksum))
+            .with_metadata_caching(options.metadata_caching)
+            .with_statistics(options.statistics.clone())
+            .with_cache_priority(CachePriority::Bottom);
+            new_ssts.push(Arc::new(sst));
+        }
+        self.sync_dir()?;
+
+        let state_lock = self.state_lock.lock();
+        let Some(state) = self.column_family_state(cf.id()) else {
+            bail!("column family {} was dropped", cf.name());
+        };
+        if let Some((path, _)) = external
+            .iter()
+            .zip(&new_ssts)
+            .find(|(_, sst)| Self::overlaps(&state, sst))
+            .map(|(external, _)| external)
+        {
+            bail!(
+                "the keys of {} are already in column family {}",
+                path.display(),
+                cf.name()
+            );
+        }
+        let mut new_state = state.as_ref().clone();
+        let new_ids = new_ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>();
+        for sst in &new_ssts {
+            new_state.sstables.insert(sst.sst_id(), sst.clone());
+        }
+        let compaction_controller = self.column_family_compaction_controller(cf.id());
+        let level = if !compaction_controller.flush_to_l0() {
+            new_state.levels.push((new_ids[0], new_ids.clone()));
+            new_ids[0]
+        } else if let Some((level, ssts)) = new_state.levels.last_mut() {
+            ssts.extend(&new_ids);
+            ssts.sort_by(|x, y| {
+                new_state.sstables[x]
+                    .first_key()
+                    .cmp(new_state.sstables[y].first_key())
+            });
+            *level
+        } else {
+            new_state.l0_sstables.extend(&new_ids);
+            0
+        };
+        let ssts = if level == 0 {
+            new_state.l0_sstables.clone()
+        } else {
+            new_state
+                .levels
+                .iter()
+                .find(|(id, _)| *id == level)
+                .map(|(_, ssts)| ssts.clone())
+                .unwrap()
+        };
+        let record = ManifestRecord::IngestExternalFiles {
+            cf_id: cf.id(),
+            level,
+            ssts,
+        };
+        self.add_manifest_records_with_ssts(&state_lock, vec![record], &new_ssts)?;
+        self.set_column_family_state(cf.id(), new_state);
+        Ok(level)
+    }
+
+    /// Whether `state` has keys, in its memtables or SSTs, in the key range of `sst`.
+    fn overlaps(state: &LsmStorageState, sst: &SsTable) -> bool {
+        let (first_key, last_key) = (sst.first_key().key_ref(), sst.last_key().key_ref());
+        let in_memtables = std::iter::once(&state.memtable)
+            .chain(&state.imm_memtables)
+            .any(|memtable| {
+                memtable
+                    .scan(
+                        Bound::Included(KeySlice::from_slice(first_key, TS_RANGE_BEGIN)),
+                        Bound::Included(KeySlice::from_slice(last_key, TS_RANGE_END)),
+                    )
+                    .is_valid()
+            });
+        in_memtables
+            || state.sst_ids().any(|sst_id| {
+                let existing = &state.sstables[sst_id];
+                existing.first_key().key_ref() <= last_key
+                    && first_key <= existing.last_key().key_ref()
+            })
+    }
+}
+
+impl MiniLsm {
+    pub fn ingest_external_files(&self, paths: &[impl AsRef<Path>]) -> Result<()> {
+        self.inner.ingest_external_files(paths)
+    }
+
+    pub fn ingest_external_files_cf(
+        &self,
+        cf: &ColumnFamily,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<()> {
+        self.inner.ingest_external_files_cf(cf, paths)
+    }
+}
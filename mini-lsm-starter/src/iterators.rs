@@ -2,6 +2,8 @@ pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+use crate::table::SsTable;
+
 pub trait StorageIterator {
     type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
     where
@@ -19,6 +21,12 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// The SST the current entry comes from when its value is an encoded `BlobReference` instead of the value, to
+    /// read the value with `SsTable::read_blob`. Only SSTs hold blob references.
+    fn blob_index_table(&self) -> Option<&SsTable> {
+        None
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
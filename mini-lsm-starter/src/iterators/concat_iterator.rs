@@ -70,6 +70,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn blob_index_table(&self) -> Option<&SsTable> {
+        self.current.as_ref().unwrap().blob_index_table()
+    }
+
     fn is_valid(&self) -> bool {
         self.current.is_some()
     }
@@ -7,6 +7,7 @@ use std::f32::consts::E;
 use anyhow::Result;
 
 use crate::key::KeySlice;
+use crate::table::SsTable;
 
 use super::StorageIterator;
 
@@ -46,8 +47,15 @@ pub struct MergeIterator<I: StorageIterator> {
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
-    pub fn create(iters: Vec<Box<I>>) -> Self {
+    pub fn create(mut iters: Vec<Box<I>>) -> Self {
         let mut heap = BinaryHeap::new();
+        if iters.iter().all(|x| !x.is_valid()) {
+            // all invalid, keep the last one as current so that it is still counted as active
+            return Self {
+                current: iters.pop().map(|iter| HeapWrapper(0, iter)),
+                iters: heap,
+            };
+        }
         for (i, iter) in iters.into_iter().enumerate() {
             if iter.is_valid() {
                 heap.push(HeapWrapper(i, iter));
@@ -73,8 +81,14 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         self.current.as_ref().unwrap().1.value()
     }
 
+    fn blob_index_table(&self) -> Option<&SsTable> {
+        self.current.as_ref().unwrap().1.blob_index_table()
+    }
+
     fn is_valid(&self) -> bool {
-        self.current.is_some()
+        self.current
+            .as_ref()
+            .is_some_and(|current| current.1.is_valid())
     }
 
     fn next(&mut self) -> Result<()> {
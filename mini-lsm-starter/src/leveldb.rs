@@ -0,0 +1,370 @@
+mod log_reader;
+mod manifest;
+mod table;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use self::log_reader::read_records;
+use self::manifest::read_version;
+use self::table::Table;
+use crate::ingest::SstFileWriter;
+use crate::logger::db_log;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+/// What `import_leveldb` converted.
+#[derive(Debug, Clone, Default)]
+pub struct ImportSummary {
+    /// The live keys imported.
+    pub num_keys: u64,
+    /// The SSTs of the source read.
+    pub num_source_ssts: usize,
+    /// The write batches replayed from the WALs of the source, for the writes not flushed yet.
+    pub num_wal_batches: usize,
+    /// The SSTs the keys were written to.
+    pub num_ssts: usize,
+    /// The column families of a RocksDB source other than the default one, which were not imported.
+    pub skipped_column_families: Vec<String>,
+}
+
+const COMPARATOR: &str = "leveldb.BytewiseComparator";
+
+// The types of the entries of write batches and SSTs, those not listed here cannot be imported
+const TYPE_DELETION: u8 = 0x0;
+const TYPE_VALUE: u8 = 0x1;
+const TYPE_LOG_DATA: u8 = 0x3;
+const TYPE_CF_DELETION: u8 = 0x4;
+const TYPE_CF_VALUE: u8 = 0x5;
+const TYPE_SINGLE_DELETION: u8 = 0x7;
+const TYPE_CF_SINGLE_DELETION: u8 = 0x8;
+const TYPE_NOOP: u8 = 0xD;
+
+fn masked_crc32c(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(0xa282ead8)
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        bail!("unexpected end of data");
+    }
+    let (data, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(data)
+}
+
+fn get_varint64(buf: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(buf, 1)?[0];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("varint too long")
+}
+
+fn get_varint32(buf: &mut &[u8]) -> Result<u32> {
+    u32::try_from(get_varint64(buf)?).context("varint too long")
+}
+
+fn get_length_prefixed<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = get_varint32(buf)? as usize;
+    take(buf, len)
+}
+
+/// The newest version of a key in one of the sources, `None` for a deletion.
+struct Entry {
+    key: Vec<u8>,
+    seq: u64,
+    value: Option<Vec<u8>>,
+    source: usize,
+}
+
+/// Ordered so that the max-heap pops the smallest key first, and its newest version first.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .cmp(&self.key)
+            .then(self.seq.cmp(&other.seq))
+            .then(other.source.cmp(&self.source))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+/// The versions of one SST, or of the WALs.
+enum Source {
+    Table { table: Table, global_seqno: u64 },
+    Wal(std::collections::btree_map::IntoIter<Vec<u8>, (u64, Option<Vec<u8>>)>),
+}
+
+impl Source {
+    fn next(&mut self, source: usize) -> Result<Option<Entry>> {
+        match self {
+            Self::Table {
+                table,
+                global_seqno,
+            } => {
+                let Some((internal_key, value)) = table.next_entry()? else {
+                    return Ok(None);
+                };
+                let Some(split) = internal_key.len().checked_sub(8) else {
+                    bail!("internal key too short");
+                };
+                let trailer = u64::from_le_bytes(internal_key[split..].try_into().unwrap());
+                let mut seq = trailer >> 8;
+                if seq == 0 {
+                    seq = *global_seqno;
+                }
+                let value = match trailer as u8 {
+                    TYPE_VALUE => Some(value),
+                    TYPE_DELETION | TYPE_SINGLE_DELETION => None,
+                    value_type => bail!("{}", unsupported_type(value_type)),
+                };
+                Ok(Some(Entry {
+                    key: internal_key[..split].to_vec(),
+                    seq,
+                    value,
+                    source,
+                }))
+            }
+            Self::Wal(iter) => Ok(iter.next().map(|(key, (seq, value))| Entry {
+                key,
+                seq,
+                value,
+                source,
+            })),
+        }
+    }
+}
+
+fn unsupported_type(value_type: u8) -> String {
+    let name = match value_type {
+        0x2 | 0x6 => "merge operands",
+        0x9..=0xC | 0x12 | 0x13 | 0x15 => "two-phase commit markers",
+        0xE | 0xF => "range deletions",
+        0x10 | 0x11 => "blob indices",
+        0x14 => "timestamps",
+        0x16 | 0x17 => "wide columns",
+        0x18 | 0x19 => "preferred sequence numbers",
+        _ => return format!("unknown entry type {}", value_type),
+    };
+    format!("the database has {}, which cannot be imported", name)
+}
+
+/// Replay a write batch of a WAL: its sequence number, its count, then its records, each taking the next sequence
+/// number. Only the records of the default column family are kept.
+fn replay_write_batch(
+    mut batch: &[u8],
+    writes: &mut BTreeMap<Vec<u8>, (u64, Option<Vec<u8>>)>,
+) -> Result<()> {
+    let buf = &mut batch;
+    let mut seq = u64::from_le_bytes(take(buf, 8)?.try_into().unwrap());
+    let count = u32::from_le_bytes(take(buf, 4)?.try_into().unwrap());
+    let mut num_records = 0;
+    while !buf.is_empty() {
+        let record_type = take(buf, 1)?[0];
+        let column_family = match record_type {
+            TYPE_CF_VALUE | TYPE_CF_DELETION | TYPE_CF_SINGLE_DELETION => get_varint32(buf)?,
+            _ => 0,
+        };
+        let value = match record_type {
+            TYPE_VALUE | TYPE_CF_VALUE => {
+                let key = get_length_prefixed(buf)?;
+                Some((key, Some(get_length_prefixed(buf)?)))
+            }
+            TYPE_DELETION | TYPE_CF_DELETION | TYPE_SINGLE_DELETION | TYPE_CF_SINGLE_DELETION => {
+                Some((get_length_prefixed(buf)?, None))
+            }
+            TYPE_LOG_DATA => {
+                get_length_prefixed(buf)?;
+                None
+            }
+            TYPE_NOOP => None,
+            record_type => bail!("{}", unsupported_type(record_type)),
+        };
+        let Some((key, value)) = value else {
+            continue;
+        };
+        // the sequence numbers only grow from one batch to the next, so the last write of a key wins
+        if column_family == 0 {
+            writes.insert(key.to_vec(), (seq, value.map(<[u8]>::to_vec)));
+        }
+        seq += 1;
+        num_records += 1;
+    }
+    if num_records != count {
+        bail!("write batch of {} records holds {}", count, num_records);
+    }
+    Ok(())
+}
+
+impl MiniLsm {
+    /// Convert the LevelDB or RocksDB directory at `source` into a new database at `path`, which must not exist or
+    /// be empty, written with `options`. The source must not be open; it is only read, through the file system of
+    /// `options`.
+    ///
+    /// The SSTs the manifest of the source lists are merged with the writes of its WALs not flushed yet, and the
+    /// newest version of every key that is not deleted is written to SSTs ingested at the bottom level, so the
+    /// database starts compacted, with no history. Only the default column family of a RocksDB directory is
+    /// imported. The formats both write by default are read, with their Snappy and LZ4 compressions; the features
+    /// that change what a key means, such as merge operands, range deletions, blob files, timestamps or other
+    /// comparators, make the import fail, as do keys with empty values, which would be deletions here. Only CRC32C
+    /// checksums are verified.
+    pub fn import_leveldb(
+        source: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<ImportSummary> {
+        let source = source.as_ref();
+        let path = path.as_ref();
+        let fs = options.file_system_or_default();
+        if fs.exists(path) && !fs.list_dir(path)?.is_empty() {
+            bail!("{} is not empty", path.display());
+        }
+        let version = read_version(fs.as_ref(), source)?;
+        if let Some(comparator) = &version.comparator {
+            if comparator != COMPARATOR {
+                bail!(
+                    "the database is sorted by the comparator {}, only the bytewise one can be imported",
+                    comparator
+                );
+            }
+        }
+        let mut summary = ImportSummary {
+            num_source_ssts: version.files.len(),
+            skipped_column_families: version.column_families.values().cloned().collect(),
+            ..Default::default()
+        };
+
+        let mut sources = Vec::new();
+        for (&number, &global_seqno) in &version.files {
+            let file_path = [format!("{:06}.ldb", number), format!("{:06}.sst", number)]
+                .into_iter()
+                .map(|name| source.join(name))
+                .find(|path| fs.exists(path))
+                .with_context(|| format!("SST {} is missing", number))?;
+            sources.push(Source::Table {
+                table: Table::open(fs.as_ref(), &file_path)?,
+                global_seqno,
+            });
+        }
+        let mut logs = fs
+            .list_dir(source)?
+            .into_iter()
+            .filter_map(|name| Some((name.strip_suffix(".log")?.parse::<u64>().ok()?, name)))
+            .filter(|&(number, _)| {
+                number >= version.log_number
+                    || (version.prev_log_number != 0 && number == version.prev_log_number)
+            })
+            .collect::<Vec<_>>();
+        logs.sort_unstable();
+        let mut writes = BTreeMap::new();
+        for (number, name) in logs {
+            let data = fs.read(&source.join(&name))?;
+            for (i, batch) in read_records(&data, number)
+                .with_context(|| format!("{} is corrupted", name))?
+                .iter()
+                .enumerate()
+            {
+                replay_write_batch(batch, &mut writes)
+                    .with_context(|| format!("write batch {} of {} is corrupted", i, name))?;
+                summary.num_wal_batches += 1;
+            }
+        }
+        sources.push(Source::Wal(writes.into_iter()));
+
+        let storage = MiniLsm::open(path, options.clone())?;
+        let mut staged = Vec::new();
+        let result =
+            Self::write_import_ssts(&mut sources, path, &options, &mut staged, &mut summary)
+                .and_then(|()| storage.ingest_external_files(&staged));
+        for path in &staged {
+            let _ = fs.remove_file(path);
+        }
+        let closed = storage.close();
+        result?;
+        closed?;
+        summary.num_ssts = staged.len();
+        db_log!(
+            options,
+            Info,
+            "imported {} keys from {} into {} SSTs",
+            summary.num_keys,
+            source.display(),
+            summary.num_ssts
+        );
+        Ok(summary)
+    }
+
+    /// Merge the sources into SSTs staged in `path`, cut at the target size of the bottom level.
+    fn write_import_ssts(
+        sources: &mut [Source],
+        path: &Path,
+        options: &LsmStorageOptions,
+        staged: &mut Vec<PathBuf>,
+        summary: &mut ImportSummary,
+    ) -> Result<()> {
+        let target_sst_size = options.target_sst_size_of_level(usize::MAX);
+        let mut heap = BinaryHeap::new();
+        for (i, source) in sources.iter_mut().enumerate() {
+            if let Some(entry) = source.next(i)? {
+                heap.push(entry);
+            }
+        }
+        let mut writer: Option<SstFileWriter> = None;
+        let mut last_key: Option<Vec<u8>> = None;
+        while let Some(entry) = heap.pop() {
+            if let Some(next) = sources[entry.source].next(entry.source)? {
+                heap.push(next);
+            }
+            if last_key.as_deref() == Some(entry.key.as_slice()) {
+                continue;
+            }
+            if let Some(value) = &entry.value {
+                if value.is_empty() {
+                    bail!(
+                        "the key {:?} has an empty value, which cannot be imported",
+                        String::from_utf8_lossy(&entry.key)
+                    );
+                }
+                let sst_writer = match &mut writer {
+                    Some(writer) => writer,
+                    None => {
+                        let sst_path = path.join(format!("import-{:06}.sst", staged.len() + 1));
+                        let sst_writer = SstFileWriter::create(&sst_path, options)?;
+                        staged.push(sst_path);
+                        writer.insert(sst_writer)
+                    }
+                };
+                sst_writer.put(&entry.key, value)?;
+                summary.num_keys += 1;
+                if sst_writer.estimated_size() >= target_sst_size {
+                    writer.take().unwrap().finish()?;
+                }
+            }
+            last_key = Some(entry.key);
+        }
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{bail, Result};
+
+use super::masked_crc32c;
+
+const BLOCK_SIZE: usize = 32768;
+const HEADER_SIZE: usize = 7;
+/// The header of the records of RocksDB's recycled logs also holds the number of the log.
+const RECYCLABLE_HEADER_SIZE: usize = 11;
+
+const ZERO_TYPE: u8 = 0;
+const FULL_TYPE: u8 = 1;
+const FIRST_TYPE: u8 = 2;
+const MIDDLE_TYPE: u8 = 3;
+const LAST_TYPE: u8 = 4;
+const RECYCLABLE_FULL_TYPE: u8 = 5;
+const RECYCLABLE_LAST_TYPE: u8 = 8;
+
+/// The records of a log file, the format of the WALs and manifests of LevelDB and RocksDB: 32KB blocks of
+/// fragments, each with a checksum, a length and whether it is the full record, its first, a middle or its last
+/// fragment. The log ends at a record cut short or at the zeros of a preallocated file, as left by a crash while it
+/// was written, or at a record of the previous log a recycled one was written over.
+pub(super) fn read_records(data: &[u8], log_number: u64) -> Result<Vec<Vec<u8>>> {
+    let mut records = Vec::new();
+    let mut record: Option<Vec<u8>> = None;
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_left = BLOCK_SIZE - offset % BLOCK_SIZE;
+        if block_left < HEADER_SIZE {
+            // the trailer of the block, too small for a header
+            offset += block_left;
+            continue;
+        }
+        let Some(header) = data.get(offset..offset + HEADER_SIZE) else {
+            break;
+        };
+        let length = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let fragment_type = header[6];
+        if fragment_type == ZERO_TYPE && length == 0 {
+            break;
+        }
+        let recyclable = (RECYCLABLE_FULL_TYPE..=RECYCLABLE_LAST_TYPE).contains(&fragment_type);
+        let header_size = if recyclable {
+            RECYCLABLE_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        };
+        let Some(fragment) = data.get(offset + header_size..offset + header_size + length) else {
+            break;
+        };
+        let expected = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let checksummed = &data[offset + 6..offset + header_size];
+        let crc = crc32c::crc32c_append(crc32c::crc32c(checksummed), fragment);
+        if masked_crc32c(crc) != expected {
+            if offset + header_size + length == data.len() {
+                // the last write, torn by a crash
+                break;
+            }
+            bail!("checksum mismatch in the record at {}", offset);
+        }
+        if recyclable {
+            let number = u32::from_le_bytes(data[offset + 7..offset + 11].try_into().unwrap());
+            if u64::from(number) != log_number & 0xffff_ffff {
+                // written by the previous use of the file
+                break;
+            }
+        }
+        let start = offset;
+        offset += header_size + length;
+        let kind = if recyclable {
+            fragment_type - (RECYCLABLE_FULL_TYPE - FULL_TYPE)
+        } else {
+            fragment_type
+        };
+        match kind {
+            FULL_TYPE | FIRST_TYPE => {
+                if record.is_some() {
+                    bail!("record at {} interrupts the previous one", start);
+                }
+                if kind == FULL_TYPE {
+                    records.push(fragment.to_vec());
+                } else {
+                    record = Some(fragment.to_vec());
+                }
+            }
+            MIDDLE_TYPE | LAST_TYPE => {
+                let Some(partial) = record.as_mut() else {
+                    bail!("fragment at {} continues no record", start);
+                };
+                partial.extend_from_slice(fragment);
+                if kind == LAST_TYPE {
+                    records.push(record.take().unwrap());
+                }
+            }
+            _ => bail!("unknown record type {} at {}", fragment_type, start),
+        }
+    }
+    Ok(records)
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use super::log_reader::read_records;
+use super::{get_length_prefixed, get_varint32, get_varint64};
+use crate::fs::FileSystem;
+
+// The tags of the fields of a version edit, LevelDB's then RocksDB's
+const COMPARATOR: u32 = 1;
+const LOG_NUMBER: u32 = 2;
+const NEXT_FILE_NUMBER: u32 = 3;
+const LAST_SEQUENCE: u32 = 4;
+const COMPACT_POINTER: u32 = 5;
+const DELETED_FILE: u32 = 6;
+const NEW_FILE: u32 = 7;
+const PREV_LOG_NUMBER: u32 = 9;
+const MIN_LOG_NUMBER_TO_KEEP: u32 = 10;
+const NEW_FILE2: u32 = 100;
+const NEW_FILE3: u32 = 102;
+const NEW_FILE4: u32 = 103;
+const COLUMN_FAMILY: u32 = 200;
+const COLUMN_FAMILY_ADD: u32 = 201;
+const COLUMN_FAMILY_DROP: u32 = 202;
+const MAX_COLUMN_FAMILY: u32 = 203;
+const IN_ATOMIC_GROUP: u32 = 300;
+const BLOB_FILE_ADDITION: u32 = 400;
+const BLOB_FILE_GARBAGE: u32 = 401;
+/// The fields a reader not knowing them can skip, a length-prefixed value.
+const SAFE_IGNORE_MASK: u32 = 1 << 13;
+/// Ends the custom fields of `NEW_FILE4`.
+const CUSTOM_TERMINATE: u32 = 1;
+
+/// The state of the default column family the manifest ends with.
+#[derive(Debug, Default)]
+pub(super) struct Version {
+    pub(super) comparator: Option<String>,
+    /// The WALs before this one only hold flushed writes.
+    pub(super) log_number: u64,
+    /// The WAL of the memtable being flushed when the manifest was written by LevelDB, 0 if none.
+    pub(super) prev_log_number: u64,
+    /// The live SSTs by number, with the sequence number of all their keys when they have only one, as the SSTs
+    /// RocksDB ingests have, their keys being written with 0.
+    pub(super) files: BTreeMap<u64, u64>,
+    /// The other column families of a RocksDB directory, by id.
+    pub(super) column_families: BTreeMap<u32, String>,
+}
+
+/// The version of the manifest `CURRENT` names in `dir`.
+pub(super) fn read_version(fs: &dyn FileSystem, dir: &Path) -> Result<Version> {
+    let current = fs
+        .read(&dir.join("CURRENT"))
+        .with_context(|| format!("{} is not a LevelDB or RocksDB directory", dir.display()))?;
+    let name = std::str::from_utf8(&current)
+        .ok()
+        .and_then(|current| current.strip_suffix('\n'))
+        .context("CURRENT is corrupted")?;
+    let number = name
+        .strip_prefix("MANIFEST-")
+        .and_then(|number| number.parse().ok())
+        .with_context(|| format!("CURRENT names {}, which is not a manifest", name))?;
+    let data = fs.read(&dir.join(name))?;
+    let mut version = Version::default();
+    for (i, edit) in read_records(&data, number)
+        .with_context(|| format!("{} is corrupted", name))?
+        .iter()
+        .enumerate()
+    {
+        version
+            .apply(edit)
+            .with_context(|| format!("record {} of {} is corrupted", i, name))?;
+    }
+    Ok(version)
+}
+
+impl Version {
+    fn apply(&mut self, mut edit: &[u8]) -> Result<()> {
+        let buf = &mut edit;
+        let mut column_family = 0;
+        let mut comparator = None;
+        let mut log_number = None;
+        let mut prev_log_number = None;
+        let mut new_files = Vec::new();
+        let mut deleted_files = Vec::new();
+        let mut added_column_family = None;
+        let mut dropped_column_family = false;
+        while !buf.is_empty() {
+            let tag = get_varint32(buf)?;
+            match tag {
+                COMPARATOR => {
+                    comparator = Some(String::from_utf8(get_length_prefixed(buf)?.to_vec())?)
+                }
+                LOG_NUMBER => log_number = Some(get_varint64(buf)?),
+                PREV_LOG_NUMBER => prev_log_number = Some(get_varint64(buf)?),
+                NEXT_FILE_NUMBER | LAST_SEQUENCE | MIN_LOG_NUMBER_TO_KEEP => {
+                    get_varint64(buf)?;
+                }
+                COMPACT_POINTER => {
+                    get_varint32(buf)?;
+                    get_length_prefixed(buf)?;
+                }
+                DELETED_FILE => {
+                    get_varint32(buf)?;
+                    deleted_files.push(get_varint64(buf)?);
+                }
+                NEW_FILE | NEW_FILE2 | NEW_FILE3 | NEW_FILE4 => {
+                    // the level
+                    get_varint32(buf)?;
+                    let number = get_varint64(buf)?;
+                    if tag == NEW_FILE3 {
+                        // the path id
+                        get_varint32(buf)?;
+                    }
+                    // the size, then the smallest and largest keys
+                    get_varint64(buf)?;
+                    get_length_prefixed(buf)?;
+                    get_length_prefixed(buf)?;
+                    let mut global_seqno = 0;
+                    if tag != NEW_FILE {
+                        let smallest_seqno = get_varint64(buf)?;
+                        let largest_seqno = get_varint64(buf)?;
+                        if smallest_seqno == largest_seqno {
+                            global_seqno = largest_seqno;
+                        }
+                    }
+                    if tag == NEW_FILE4 {
+                        while get_varint32(buf)? != CUSTOM_TERMINATE {
+                            get_length_prefixed(buf)?;
+                        }
+                    }
+                    new_files.push((number, global_seqno));
+                }
+                COLUMN_FAMILY => column_family = get_varint32(buf)?,
+                COLUMN_FAMILY_ADD => {
+                    added_column_family =
+                        Some(String::from_utf8(get_length_prefixed(buf)?.to_vec())?)
+                }
+                COLUMN_FAMILY_DROP => dropped_column_family = true,
+                MAX_COLUMN_FAMILY | IN_ATOMIC_GROUP => {
+                    get_varint32(buf)?;
+                }
+                BLOB_FILE_ADDITION | BLOB_FILE_GARBAGE => {
+                    bail!("the database has blob files, which cannot be imported")
+                }
+                tag if tag & SAFE_IGNORE_MASK != 0 => {
+                    get_length_prefixed(buf)?;
+                }
+                tag => bail!("unknown tag {}", tag),
+            }
+        }
+
+        if let Some(name) = added_column_family {
+            self.column_families.insert(column_family, name);
+        }
+        if dropped_column_family {
+            self.column_families.remove(&column_family);
+        }
+        if column_family != 0 {
+            return Ok(());
+        }
+        if comparator.is_some() {
+            self.comparator = comparator;
+        }
+        if let Some(log_number) = log_number {
+            self.log_number = log_number;
+        }
+        if let Some(prev_log_number) = prev_log_number {
+            self.prev_log_number = prev_log_number;
+        }
+        for number in deleted_files {
+            self.files.remove(&number);
+        }
+        self.files.extend(new_files);
+        Ok(())
+    }
+}
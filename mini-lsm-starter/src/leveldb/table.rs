@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use super::{get_varint32, get_varint64, masked_crc32c, take};
+use crate::fs::{FileSystem, ReadableFile};
+
+const LEGACY_MAGIC: u64 = 0xdb4775248b80fb57;
+const MAGIC: u64 = 0x88e241b785f4cff7;
+const LEGACY_PLAIN_MAGIC: u64 = 0x4f3418eb7a8f13b8;
+const PLAIN_MAGIC: u64 = 0x8242229663bf9564;
+const CUCKOO_MAGIC: u64 = 0x926789d0c5f17873;
+const LEGACY_FOOTER_SIZE: u64 = 48;
+const FOOTER_SIZE: u64 = 53;
+/// The compression type and checksum after every block.
+const BLOCK_TRAILER_SIZE: u64 = 5;
+const CRC32C_CHECKSUM: u8 = 1;
+/// From this version on, the footer holds the size of the metaindex block, which ends where it starts, and the
+/// metaindex the handle of the index block; the checksums of blocks are offset by their offsets.
+const CONTEXT_CHECKSUM_FORMAT_VERSION: u32 = 6;
+/// The restarts of the data blocks with a hash index have this bit set in their count.
+const HASH_INDEX_BIT: u32 = 1 << 31;
+
+// The index types of RocksDB, which writes the two-level and first-key ones with their own formats
+const BINARY_SEARCH_INDEX: u32 = 0;
+const HASH_SEARCH_INDEX: u32 = 1;
+
+#[derive(Clone, Copy, Debug)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            offset: get_varint64(buf)?,
+            size: get_varint64(buf)?,
+        })
+    }
+}
+
+/// An SST of LevelDB or RocksDB, in the block-based format both write, read a data block at a time.
+pub(super) struct Table {
+    name: String,
+    file: Box<dyn ReadableFile>,
+    format_version: u32,
+    /// Whether the blocks have CRC32C checksums, the only ones verified.
+    crc32c: bool,
+    base_context_checksum: u32,
+    data_blocks: std::vec::IntoIter<BlockHandle>,
+    entries: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Table {
+    pub(super) fn open(fs: &dyn FileSystem, path: &Path) -> Result<Self> {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let file = fs.open(path)?;
+        let size = file.size()?;
+        if size < LEGACY_FOOTER_SIZE {
+            bail!("{} is too short for an SST", name);
+        }
+        let mut magic = [0; 8];
+        file.read_at(&mut magic, size - 8)?;
+        let mut table = Self {
+            name,
+            file,
+            format_version: 0,
+            crc32c: true,
+            base_context_checksum: 0,
+            data_blocks: Vec::new().into_iter(),
+            entries: Vec::new().into_iter(),
+        };
+        let mut index = None;
+        let metaindex = match u64::from_le_bytes(magic) {
+            LEGACY_MAGIC => {
+                let mut footer = [0; LEGACY_FOOTER_SIZE as usize];
+                table.file.read_at(&mut footer, size - LEGACY_FOOTER_SIZE)?;
+                let mut buf = &footer[..];
+                let metaindex = BlockHandle::decode(&mut buf)?;
+                index = Some(BlockHandle::decode(&mut buf)?);
+                metaindex
+            }
+            MAGIC => {
+                if size < FOOTER_SIZE {
+                    bail!("{} is too short for an SST", table.name);
+                }
+                let mut footer = [0; FOOTER_SIZE as usize];
+                table.file.read_at(&mut footer, size - FOOTER_SIZE)?;
+                table.crc32c = footer[0] == CRC32C_CHECKSUM;
+                table.format_version = u32::from_le_bytes(footer[41..45].try_into().unwrap());
+                if table.format_version >= CONTEXT_CHECKSUM_FORMAT_VERSION {
+                    table.base_context_checksum =
+                        u32::from_le_bytes(footer[9..13].try_into().unwrap());
+                    let metaindex_size = u32::from_le_bytes(footer[13..17].try_into().unwrap());
+                    BlockHandle {
+                        offset: (size - FOOTER_SIZE)
+                            .checked_sub(u64::from(metaindex_size) + BLOCK_TRAILER_SIZE)
+                            .with_context(|| {
+                                format!("the footer of {} is corrupted", table.name)
+                            })?,
+                        size: u64::from(metaindex_size),
+                    }
+                } else {
+                    let mut buf = &footer[1..];
+                    let metaindex = BlockHandle::decode(&mut buf)?;
+                    index = Some(BlockHandle::decode(&mut buf)?);
+                    metaindex
+                }
+            }
+            LEGACY_PLAIN_MAGIC | PLAIN_MAGIC | CUCKOO_MAGIC => bail!(
+                "{} is a plain or cuckoo table, only block-based tables can be imported",
+                table.name
+            ),
+            _ => bail!("{} is not an SST", table.name),
+        };
+
+        let mut properties = HashMap::new();
+        for (key, value) in block_entries(&table.read_block(metaindex)?)? {
+            match key.as_slice() {
+                b"rocksdb.properties" => {
+                    let handle = BlockHandle::decode(&mut value.as_slice())?;
+                    properties.extend(block_entries(&table.read_block(handle)?)?);
+                }
+                b"rocksdb.index" => index = Some(BlockHandle::decode(&mut value.as_slice())?),
+                _ => {}
+            }
+        }
+        let index = index.with_context(|| format!("{} has no index block", table.name))?;
+        if let Some(comparator) = properties.get(&b"rocksdb.comparator"[..]) {
+            if comparator != b"leveldb.BytewiseComparator" {
+                bail!(
+                    "{} is sorted by the comparator {}, only the bytewise one can be imported",
+                    table.name,
+                    String::from_utf8_lossy(comparator)
+                );
+            }
+        }
+        if let Some(index_type) = properties.get(&b"rocksdb.block.based.table.index.type"[..]) {
+            let index_type = u32::from_le_bytes(
+                index_type
+                    .as_slice()
+                    .try_into()
+                    .with_context(|| format!("the properties of {} are corrupted", table.name))?,
+            );
+            if index_type != BINARY_SEARCH_INDEX && index_type != HASH_SEARCH_INDEX {
+                bail!(
+                    "{} has a partitioned or first-key index, which cannot be imported",
+                    table.name
+                );
+            }
+        }
+        let integer = |name: &[u8]| -> Result<u64> {
+            match properties.get(name) {
+                Some(value) => get_varint64(&mut value.as_slice()),
+                None => Ok(0),
+            }
+        };
+        if integer(b"rocksdb.num.range-deletions")? > 0 {
+            bail!(
+                "{} has range deletions, which cannot be imported",
+                table.name
+            );
+        }
+        let delta_encoded = integer(b"rocksdb.index.value.is.delta.encoded")? != 0;
+        table.data_blocks = index_handles(&table.read_block(index)?, delta_encoded)
+            .with_context(|| format!("the index of {} is corrupted", table.name))?
+            .into_iter();
+        Ok(table)
+    }
+
+    /// The next entry, an internal key and its value, `None` past the last one.
+    pub(super) fn next_entry(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Ok(Some(entry));
+            }
+            let Some(handle) = self.data_blocks.next() else {
+                return Ok(None);
+            };
+            let block = self.read_block(handle)?;
+            self.entries = block_entries(&block)
+                .with_context(|| {
+                    format!(
+                        "the block at {} of {} is corrupted",
+                        handle.offset, self.name
+                    )
+                })?
+                .into_iter();
+        }
+    }
+
+    /// The contents of a block, checked and decompressed.
+    fn read_block(&self, handle: BlockHandle) -> Result<Vec<u8>> {
+        let size = handle.size as usize;
+        let mut data = vec![0; size + BLOCK_TRAILER_SIZE as usize];
+        self.file
+            .read_at(&mut data, handle.offset)
+            .with_context(|| {
+                format!(
+                    "failed to read the block at {} of {}",
+                    handle.offset, self.name
+                )
+            })?;
+        if self.crc32c {
+            let expected = u32::from_le_bytes(data[size + 1..].try_into().unwrap());
+            let crc = masked_crc32c(crc32c::crc32c(&data[..size + 1]))
+                .wrapping_add(self.checksum_modifier(handle.offset));
+            if crc != expected {
+                bail!(
+                    "checksum mismatch in the block at {} of {}",
+                    handle.offset,
+                    self.name
+                );
+            }
+        }
+        let compression = data[size];
+        data.truncate(size);
+        Ok(match compression {
+            0 => data,
+            1 => snap::raw::Decoder::new()
+                .decompress_vec(&data)
+                .with_context(|| format!("failed to decompress a block of {}", self.name))?,
+            4 | 5 if self.format_version >= 2 => {
+                let mut buf = data.as_slice();
+                let uncompressed_size = get_varint32(&mut buf)? as usize;
+                lz4_flex::block::decompress(buf, uncompressed_size)
+                    .with_context(|| format!("failed to decompress a block of {}", self.name))?
+            }
+            compression => bail!(
+                "{} has blocks compressed with {}, which cannot be imported",
+                self.name,
+                match compression {
+                    2 => "zlib",
+                    3 => "bzip2",
+                    4 | 5 => "the LZ4 format of format_version 1",
+                    6 => "xpress",
+                    7 => "zstd",
+                    _ => "an unknown compression",
+                }
+            ),
+        })
+    }
+
+    /// What the checksum of the block at `offset` is offset by from the format version 6 on.
+    fn checksum_modifier(&self, offset: u64) -> u32 {
+        if self.base_context_checksum == 0 {
+            return 0;
+        }
+        self.base_context_checksum ^ (offset as u32).wrapping_add((offset >> 32) as u32)
+    }
+}
+
+/// The end of the entries of a block, where its restart points start.
+fn restarts_offset(block: &[u8]) -> Result<usize> {
+    let len = block.len();
+    if len < 4 {
+        bail!("block too short");
+    }
+    let mut num_restarts = u32::from_le_bytes(block[len - 4..].try_into().unwrap()) as usize;
+    let mut end = len - 4;
+    if num_restarts as u32 & HASH_INDEX_BIT != 0 {
+        num_restarts &= !(HASH_INDEX_BIT as usize);
+        if len < 6 {
+            bail!("block too short");
+        }
+        let num_buckets = u16::from_le_bytes([block[len - 6], block[len - 5]]) as usize;
+        end = end
+            .checked_sub(2 + num_buckets)
+            .context("block too short")?;
+    }
+    end.checked_sub(4 * num_restarts).context("block too short")
+}
+
+/// Restore the key of an entry from the prefix it shares with the previous one.
+fn restore_key(buf: &mut &[u8], key: &mut Vec<u8>, shared: usize, non_shared: usize) -> Result<()> {
+    if shared > key.len() {
+        bail!("key shares more than the previous one");
+    }
+    key.truncate(shared);
+    key.extend_from_slice(take(buf, non_shared)?);
+    Ok(())
+}
+
+fn block_entries(block: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut buf = &block[..restarts_offset(block)?];
+    let mut entries = Vec::new();
+    let mut key = Vec::new();
+    while !buf.is_empty() {
+        let shared = get_varint32(&mut buf)? as usize;
+        let non_shared = get_varint32(&mut buf)? as usize;
+        let value_len = get_varint32(&mut buf)? as usize;
+        restore_key(&mut buf, &mut key, shared, non_shared)?;
+        entries.push((key.clone(), take(&mut buf, value_len)?.to_vec()));
+    }
+    Ok(entries)
+}
+
+/// The handles of the data blocks in an index block. With delta encoding, the values have no lengths, and the
+/// handle of a key sharing a prefix is the difference of its size from the previous one's, the block following it.
+fn index_handles(block: &[u8], delta_encoded: bool) -> Result<Vec<BlockHandle>> {
+    if !delta_encoded {
+        return block_entries(block)?
+            .into_iter()
+            .map(|(_, value)| BlockHandle::decode(&mut value.as_slice()))
+            .collect();
+    }
+    let mut buf = &block[..restarts_offset(block)?];
+    let mut handles: Vec<BlockHandle> = Vec::new();
+    let mut key = Vec::new();
+    while !buf.is_empty() {
+        let shared = get_varint32(&mut buf)? as usize;
+        let non_shared = get_varint32(&mut buf)? as usize;
+        restore_key(&mut buf, &mut key, shared, non_shared)?;
+        let handle = match handles.last() {
+            Some(previous) if shared > 0 => {
+                let delta = get_varint64(&mut buf)?;
+                let delta = (delta >> 1) as i64 ^ -((delta & 1) as i64);
+                BlockHandle {
+                    offset: previous.offset + previous.size + BLOCK_TRAILER_SIZE,
+                    size: previous
+                        .size
+                        .checked_add_signed(delta)
+                        .context("bad size delta")?,
+                }
+            }
+            _ => BlockHandle::decode(&mut buf)?,
+        };
+        handles.push(handle);
+    }
+    Ok(handles)
+}
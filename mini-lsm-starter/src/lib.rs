@@ -1,15 +1,58 @@
+pub mod asynk;
+pub mod backup;
+pub mod blob;
 pub mod block;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod cdc;
+pub mod checkpoint;
+pub mod clock;
+pub mod column_family;
 pub mod compact;
-pub mod debug;
+pub mod estimate;
+pub mod event_listener;
+pub mod export;
+pub mod fs;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod ingest;
 pub mod iterators;
 pub mod key;
+#[cfg(feature = "leveldb")]
+pub mod leveldb;
+pub mod live_files;
+pub mod logger;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
+pub mod metrics;
 pub mod mvcc;
+pub mod options;
+pub mod perf_context;
+pub mod pinnable_slice;
+pub mod property;
+pub mod raft;
+pub mod rate_limiter;
+pub mod repair;
+pub mod replication;
+#[cfg(feature = "resp")]
+pub mod resp;
+pub mod row_cache;
+pub mod scheduler;
+pub mod secondary;
+pub mod statistics;
+pub mod streaming;
+pub mod structure;
 pub mod table;
+pub mod ts_oracle;
+pub mod txn_spill;
+pub mod typed;
 pub mod wal;
+pub mod write_buffer_manager;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+
+use crate::column_family::DEFAULT_COLUMN_FAMILY_NAME;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState, MiniLsm};
+use crate::manifest::read_current_manifest;
+use crate::table::FileChecksum;
+
+/// An SST of the LSM tree, see `live_files`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveSstFile {
+    pub column_family: String,
+    pub sst_id: usize,
+    pub path: PathBuf,
+    /// 0 for L0. For tiered compaction, the position of the sorted run from the newest one, starting at 1.
+    pub level: usize,
+    pub size: u64,
+    pub smallest_key: Bytes,
+    pub largest_key: Bytes,
+    /// The commit timestamps of the versions the SST holds.
+    pub min_ts: u64,
+    pub max_ts: u64,
+    /// The checksum the manifest recorded, `None` for SSTs written before checksums were.
+    pub file_checksum: Option<FileChecksum>,
+}
+
+/// The files a copy of the database needs, see `live_files`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveFiles {
+    pub ssts: Vec<LiveSstFile>,
+    /// The manifest `CURRENT` names, and its size when listed: the records appended later are not needed.
+    pub manifest: PathBuf,
+    pub manifest_size: u64,
+    /// The WALs of the memtables not flushed yet, oldest first.
+    pub wals: Vec<PathBuf>,
+    /// The blob files the SSTs point into.
+    pub blob_files: Vec<PathBuf>,
+}
+
+impl LsmStorageInner {
+    /// List the SSTs of every column family, the blob files, the manifest and the WALs, all as they are at one point
+    /// in time.
+    /// Along with `disable_file_deletions`, it lets external tools copy the database while it is written to.
+    pub fn live_files(&self) -> Result<LiveFiles> {
+        // flushes and compactions install their SSTs and record them with the state lock
+        let _state_lock = self.state_lock.lock();
+        let mut ssts = Vec::new();
+        let default_state = self.state.read().clone();
+        let column_families = self.column_families.read().clone();
+        let mut blob_file_ids = BTreeSet::new();
+        for (name, state) in std::iter::once((DEFAULT_COLUMN_FAMILY_NAME, &default_state)).chain(
+            column_families
+                .values()
+                .map(|cf| (cf.name.as_str(), &cf.state)),
+        ) {
+            self.list_live_ssts(name, state, &mut ssts);
+            blob_file_ids.extend(state.blob_file_ids());
+        }
+        let (manifest, manifest_size) = match &self.manifest {
+            Some(manifest) => (manifest.path(), manifest.size()?),
+            None => {
+                let name = read_current_manifest(self.fs.as_ref(), &self.path)?
+                    .with_context(|| format!("{} has no manifest", self.path.display()))?;
+                let path = self.path.join(name);
+                let size = self.fs.open(&path)?.size()?;
+                (path, size)
+            }
+        };
+        let wals = Self::wal_ids(self.fs.as_ref(), &self.path)?
+            .into_iter()
+            .map(|wal_id| self.path_of_wal(wal_id))
+            .collect();
+        Ok(LiveFiles {
+            ssts,
+            manifest,
+            manifest_size,
+            wals,
+            blob_files: blob_file_ids
+                .into_iter()
+                .map(|blob_file_id| self.path_of_blob(blob_file_id))
+                .collect(),
+        })
+    }
+
+    fn list_live_ssts(
+        &self,
+        column_family: &str,
+        state: &LsmStorageState,
+        ssts: &mut Vec<LiveSstFile>,
+    ) {
+        let levels = std::iter::once((0, &state.l0_sstables)).chain(
+            state
+                .levels
+                .iter()
+                .enumerate()
+                .map(|(position, (_, ssts))| (position + 1, ssts)),
+        );
+        for (level, sst_ids) in levels {
+            for sst_id in sst_ids {
+                let sst = &state.sstables[sst_id];
+                ssts.push(LiveSstFile {
+                    column_family: column_family.to_string(),
+                    sst_id: *sst_id,
+                    path: self.path_of_sst(*sst_id),
+                    level,
+                    size: sst.table_size(),
+                    smallest_key: Bytes::copy_from_slice(sst.first_key().key_ref()),
+                    largest_key: Bytes::copy_from_slice(sst.last_key().key_ref()),
+                    min_ts: sst.properties().min_ts,
+                    max_ts: sst.max_ts(),
+                    file_checksum: sst.file_checksum(),
+                });
+            }
+        }
+    }
+}
+
+impl MiniLsm {
+    pub fn live_files(&self) -> Result<LiveFiles> {
+        self.inner.live_files()
+    }
+}
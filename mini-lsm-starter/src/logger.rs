@@ -0,0 +1,121 @@
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::lsm_storage::LsmStorageOptions;
+
+/// Where a database sends its diagnostics instead of the `log` facade, see the `info_log` option. Implemented by
+/// every `log::Log` that is also `Debug`.
+pub trait InfoLog: Log + Debug {}
+
+impl<T: Log + Debug> InfoLog for T {}
+
+/// An `InfoLog` printing every record to stderr, one per line, e.g. for the command line tools.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrLog;
+
+impl Log for StderrLog {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// The `info_log` and `info_log_level` of a database, for the parts of the engine logging on its behalf without
+/// holding its options, e.g. the compaction controllers or the manifest. The default logs through the `log` facade
+/// at every level it enables.
+#[derive(Debug, Clone)]
+pub struct DbLogger {
+    info_log: Option<Arc<dyn InfoLog>>,
+    info_log_level: LevelFilter,
+}
+
+impl DbLogger {
+    /// The logger of the database opened with `options`.
+    pub fn new(options: &LsmStorageOptions) -> Self {
+        Self {
+            info_log: options.info_log.clone(),
+            info_log_level: options.info_log_level,
+        }
+    }
+}
+
+impl Default for DbLogger {
+    fn default() -> Self {
+        Self {
+            info_log: None,
+            info_log_level: LevelFilter::Trace,
+        }
+    }
+}
+
+/// Where `db_log!` sends a diagnostic: the options of a database, or a `DbLogger` taken from them.
+pub(crate) trait LogDestination {
+    fn info_log(&self) -> Option<&Arc<dyn InfoLog>>;
+
+    fn info_log_level(&self) -> LevelFilter;
+
+    /// Log a diagnostic at `level`, through the `info_log` or else the `log` facade, unless the level is above the
+    /// `info_log_level`.
+    fn db_log(&self, level: Level, target: &'static str, args: fmt::Arguments<'_>) {
+        if level > self.info_log_level() {
+            return;
+        }
+        let record = Record::builder()
+            .args(args)
+            .level(level)
+            .target(target)
+            .module_path_static(Some(target))
+            .build();
+        match self.info_log() {
+            Some(info_log) => {
+                if info_log.enabled(record.metadata()) {
+                    info_log.log(&record);
+                }
+            }
+            None => {
+                if level <= log::max_level() && log::logger().enabled(record.metadata()) {
+                    log::logger().log(&record);
+                }
+            }
+        }
+    }
+}
+
+impl LogDestination for LsmStorageOptions {
+    fn info_log(&self) -> Option<&Arc<dyn InfoLog>> {
+        self.info_log.as_ref()
+    }
+
+    fn info_log_level(&self) -> LevelFilter {
+        self.info_log_level
+    }
+}
+
+impl LogDestination for DbLogger {
+    fn info_log(&self) -> Option<&Arc<dyn InfoLog>> {
+        self.info_log.as_ref()
+    }
+
+    fn info_log_level(&self) -> LevelFilter {
+        self.info_log_level
+    }
+}
+
+/// Log a diagnostic of the database opened with the options, e.g. `db_log!(self.options(), Info, "...", ...)`, or
+/// of the one a `DbLogger` was taken from; the parts of the engine not tied to a database use the `log` macros
+/// instead.
+macro_rules! db_log {
+    ($destination:expr, $level:ident, $($arg:tt)+) => {{
+        use $crate::logger::LogDestination as _;
+        ($destination).db_log(log::Level::$level, module_path!(), format_args!($($arg)+))
+    }};
+}
+
+pub(crate) use db_log;
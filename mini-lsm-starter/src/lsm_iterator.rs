@@ -1,27 +1,101 @@
+use std::ops::Bound;
+
 use anyhow::Result;
+use bytes::Bytes;
 
-use crate::{
-    iterators::{merge_iterator::MergeIterator, StorageIterator},
-    mem_table::MemTableIterator,
-};
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::StorageIterator;
+use crate::mem_table::MemTableIterator;
+use crate::perf_context::perf_count;
+use crate::table::{SsTable, SsTableIterator};
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
-type LsmIteratorInner = MergeIterator<MemTableIterator>;
+pub(crate) type LsmIteratorInner = TwoMergeIterator<
+    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
+    MergeIterator<SstConcatIterator>,
+>;
 
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    end_bound: Bound<Bytes>,
+    is_valid: bool,
+    read_ts: u64,
+    prev_key: Vec<u8>,
+    /// The current value when it is read from a blob file.
+    blob_value: Option<Bytes>,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner) -> Result<Self> {
-        let mut iter = Self { inner: iter };
-        iter.skip_blank_entry();
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        end_bound: Bound<Bytes>,
+        read_ts: u64,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            is_valid: iter.is_valid(),
+            inner: iter,
+            end_bound,
+            read_ts,
+            prev_key: Vec::new(),
+            blob_value: None,
+        };
+        iter.move_to_key()?;
         Ok(iter)
     }
-    fn skip_blank_entry(&mut self) {
-        while self.inner.is_valid() && self.inner.value().is_empty() {
-            self.inner.next().unwrap();
+
+    fn next_inner(&mut self) -> Result<()> {
+        self.inner.next()?;
+        if !self.inner.is_valid() {
+            self.is_valid = false;
+            return Ok(());
+        }
+        match self.end_bound.as_ref() {
+            Bound::Unbounded => {}
+            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
+            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
+        }
+        Ok(())
+    }
+
+    /// Go past a version that is not returned.
+    fn skip_inner(&mut self) -> Result<()> {
+        perf_count(|context| context.internal_key_skipped_count += 1);
+        self.next_inner()
+    }
+
+    fn move_to_key(&mut self) -> Result<()> {
+        self.blob_value = None;
+        loop {
+            while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+                self.skip_inner()?;
+            }
+            if !self.inner.is_valid() {
+                break;
+            }
+            self.prev_key.clear();
+            self.prev_key.extend(self.inner.key().key_ref());
+            while self.inner.is_valid()
+                && self.inner.key().key_ref() == self.prev_key
+                && self.inner.key().ts() > self.read_ts
+            {
+                self.skip_inner()?;
+            }
+            if !self.inner.is_valid() {
+                break;
+            }
+            if self.inner.key().key_ref() != self.prev_key {
+                continue;
+            }
+            if !self.inner.value().is_empty() {
+                if let Some(table) = self.inner.blob_index_table() {
+                    self.blob_value = Some(table.read_blob(self.inner.value())?);
+                }
+                break;
+            }
         }
+        Ok(())
     }
 }
 
@@ -29,20 +103,24 @@ impl StorageIterator for LsmIterator {
     type KeyType<'a> = &'a [u8];
 
     fn is_valid(&self) -> bool {
-        self.inner.is_valid()
+        self.is_valid
     }
 
     fn key(&self) -> &[u8] {
-        &self.inner.key().raw_ref()
+        self.inner.key().key_ref()
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.blob_value {
+            Some(value) => value,
+            None => self.inner.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.inner.next()?;
-        self.skip_blank_entry();
+        perf_count(|context| context.iter_next_count += 1);
+        self.next_inner()?;
+        self.move_to_key()?;
         Ok(())
     }
 
@@ -83,6 +161,10 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn blob_index_table(&self) -> Option<&SsTable> {
+        self.iter.blob_index_table()
+    }
+
     fn next(&mut self) -> Result<()> {
         if self.has_errored {
             return Err(anyhow::anyhow!("Iterator has errored"));
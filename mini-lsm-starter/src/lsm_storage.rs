@@ -1,28 +1,64 @@
 #![allow(dead_code)] // REMOVE THIS LINE after fully implementing this functionality
 
-use std::collections::HashMap;
-use std::ops::Bound;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::{Bound, Range};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Ok, Result};
+use anyhow::{bail, Context, Result};
+use arc_swap::{ArcSwap, Guard};
 use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use log::LevelFilter;
 use parking_lot::{Mutex, MutexGuard, RwLock};
+use serde::{Deserialize, Serialize};
 
-use crate::block::Block;
+pub use crate::block::BlockCache;
+use crate::block::{Block, BlockIterator};
+use crate::block::{BlockCacheCounts, BlockCacheStats, CachePriority, SecondaryCache};
+use crate::cdc::ChangeSubscriber;
+use crate::clock::{Clock, Instant};
+use crate::column_family::{ColumnFamilyState, ReplayedColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionOptions, CompactionStats, CompactionStatsRecorder,
+    LeveledCompactionOptions, PlannedCompaction, SimpleLeveledCompactionOptions, SstPartitioner,
 };
+use crate::event_listener::{EventListener, FlushJobInfo};
+use crate::fs::FileSystem;
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
-use crate::manifest::Manifest;
-use crate::mem_table::MemTable;
-use crate::mvcc::LsmMvccInner;
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
-
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::StorageIterator;
+use crate::key::{self, KeySlice};
+use crate::logger::{db_log, DbLogger, InfoLog};
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmIteratorInner};
+use crate::manifest::{
+    current_manifest, manifest_file_name, read_current_manifest, set_current, Manifest,
+    ManifestRecord,
+};
+use crate::mem_table::{map_bound, map_user_key_range, MemTable};
+use crate::mvcc::txn::{ConflictGranularity, SpilledRun, Transaction, TxnStorage};
+use crate::mvcc::{LsmMvccInner, PreparedTxnData, Snapshot, SnapshotStats, TxnLease};
+pub use crate::options::LsmStorageOptionsBuilder;
+use crate::perf_context::{perf_count, PerfTimer};
+use crate::pinnable_slice::PinnableSlice;
+use crate::rate_limiter::RateLimiter;
+use crate::replication::{ReplicatedBatch, ReplicationSink};
+use crate::row_cache::RowCache;
+use crate::scheduler::BackgroundScheduler;
+use crate::statistics::{Histogram, Statistics, Ticker};
+use crate::table::bloom::key_hash;
+use crate::table::{
+    CompressionType, FileChecksum, FileObject, MetadataCaching, SsTable, SsTableBuilder,
+    SsTableIterator,
+};
+use crate::ts_oracle::TimestampOracle;
+use crate::txn_spill::SpilledTxnWrites;
+use crate::wal::WalRecord;
+use crate::write_buffer_manager::WriteBufferManager;
+use crate::write_stall::WriteStallController;
 
 /// Represents the state of the storage engine.
 #[derive(Clone)]
@@ -40,20 +76,21 @@ pub struct LsmStorageState {
     pub sstables: HashMap<usize, Arc<SsTable>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
+    pub(crate) fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
                 ..=*max_levels)
                 .map(|level| (level, Vec::new()))
                 .collect::<Vec<_>>(),
-            CompactionOptions::Tiered(_) => Vec::new(),
+            CompactionOptions::Tiered(_) | CompactionOptions::Fifo(_) => Vec::new(),
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
@@ -64,9 +101,31 @@ impl LsmStorageState {
             sstables: Default::default(),
         }
     }
+
+    /// The SSTs of L0 and of the levels.
+    pub(crate) fn sst_ids(&self) -> impl Iterator<Item = &usize> {
+        self.l0_sstables
+            .iter()
+            .chain(self.levels.iter().flat_map(|(_, ssts)| ssts))
+    }
+
+    /// The blob files the SSTs point into, once for every SST.
+    pub(crate) fn blob_file_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.sst_ids().flat_map(|sst_id| {
+            self.sstables[sst_id]
+                .properties()
+                .blob_files
+                .keys()
+                .copied()
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Persisted to the `OPTIONS` file of the database, except for the shared objects: caches, partitioner, listeners,
+/// rate limiter, statistics, file system, clock and info log. The fields missing from the file take their default
+/// value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LsmStorageOptions {
     // Block size in bytes
     pub block_size: usize,
@@ -77,9 +136,162 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Row cache capacity in bytes, sized independently from the block cache; 0 disables the row cache
+    pub row_cache_capacity: usize,
+    // Whether SST bloom filters and block metadata are charged to the block cache, and whether they may be evicted
+    pub metadata_caching: MetadataCaching,
+    // A file on a fast local disk receiving the blocks evicted from the block cache; None disables it
+    pub secondary_cache: Option<SecondaryCacheOptions>,
+    // Prefetch the L0 tables and the filters of the lower levels into the block cache when opening
+    pub warm_block_cache_on_open: bool,
+    // A block cache shared with other databases, used instead of one sized for this database alone
+    #[serde(skip)]
+    pub block_cache: Option<Arc<BlockCache>>,
+    // Bounds the memtable memory of every database sharing it
+    #[serde(skip)]
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
+    // SSTs built longer ago than this are recompacted even when no level needs it, 0 to disable. Applies to
+    // leveled and universal compaction.
+    pub periodic_compaction_seconds: u64,
+    // SSTs whose share of tombstones is above this ratio are compacted even when no level needs it, 0 to disable.
+    // Applies to leveled and universal compaction.
+    pub tombstone_compaction_ratio: f64,
+    // Bottom-level SSTs holding at least this many bytes of versions and tombstones below the MVCC watermark are
+    // rewritten to drop them, 0 to disable. Applies to leveled and universal compaction.
+    pub bottommost_recompaction_bytes: u64,
+    // Compactions are split into up to this many key ranges compacted in parallel
+    pub max_subcompactions: usize,
+    // Number of threads running the flushes and compactions
+    pub max_background_jobs: usize,
+    // Cuts the compaction output into SSTs at the keys it chooses, e.g. tenant prefix boundaries
+    #[serde(skip)]
+    pub sst_partitioner: Option<Arc<dyn SstPartitioner>>,
+    // Compression of the SSTs written to every level, L0 first. Deeper levels use the last entry, and no entry
+    // means no compression. For tiered compaction the last entry is the bottom tier and the others use L1's.
+    pub compression_per_level: Vec<CompressionType>,
+    // Target size of the SSTs compactions write to every level, with the same indexing as `compression_per_level`.
+    // No entry means `target_sst_size`; the entry of L0 is unused since L0 holds flushed memtables.
+    pub target_sst_size_per_level: Vec<usize>,
+    // Values at least this many bytes long are written to blob files when flushed, the SSTs only storing where they
+    // are so that compactions do not rewrite them; 0 keeps every value in the SSTs
+    pub min_blob_size: usize,
+    // Blob files larger than this percentage of the records the SSTs still point to are garbage collected, 0 to
+    // disable: compactions move the values the SSTs point to into new blob files, and once all the blob files of a
+    // column family are above it, the SSTs pointing into the most wasteful one are rewritten even when no level
+    // needs it, which applies to leveled and universal compaction
+    pub max_blob_space_amplification_percent: u64,
+    // Notified of the flushes and compactions
+    #[serde(skip)]
+    pub event_listeners: Vec<Arc<dyn EventListener>>,
+    // Receive every committed write batch with its commit ts, in commit order, e.g. to ship it to followers
+    #[serde(skip)]
+    pub replication_sinks: Vec<Arc<dyn ReplicationSink>>,
+    // Throttles the bytes compactions read and write, may be shared by several databases; None does not limit them
+    #[serde(skip)]
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Counters and latency histograms of the reads, writes, flushes and compactions, may be shared by several
+    // databases; None does not collect them
+    #[serde(skip)]
+    pub statistics: Option<Arc<Statistics>>,
+    // Where the files of the database are read and written, e.g. to count or limit their IO; None is the local disk
+    #[serde(skip)]
+    pub file_system: Option<Arc<dyn FileSystem>>,
+    // Where the time is read, e.g. a `MockClock` to control it in tests; None is the system clock
+    #[serde(skip)]
+    pub clock: Option<Arc<dyn Clock>>,
+    // The manifest is rolled over to a new file holding a snapshot of the LSM structure once larger than this
+    pub max_manifest_file_size: u64,
+    // Open even if SSTs of the manifest are missing or corrupted, with the latest state of the manifest whose SSTs
+    // are all readable. The SSTs of the later states are moved to `lost`, see `recovery_report`.
+    pub best_effort_recovery: bool,
+    // How often the SSTs the manifest does not know about are looked for and deleted, 0 to only do it when opening
+    pub delete_obsolete_files_period_seconds: u64,
+    // Check the footer, block meta, bloom filter and properties of every SST when opening, and fail listing all
+    // the corrupted ones before anything is written
+    pub paranoid_checks: bool,
+    // Commit ts are hybrid logical clock readings, the milliseconds since the UNIX epoch in the high bits, instead of
+    // a counter
+    pub hybrid_logical_clock: bool,
+    // Transactions spill their buffered writes to a sorted run on disk once they are larger than this many bytes,
+    // merged back at commit; 0 keeps them in memory
+    pub txn_spill_threshold: usize,
+    // What transactions detect conflicts on. Changing it while transactions are prepared misses the conflicts with
+    // the keys they read
+    pub txn_conflict_granularity: ConflictGranularity,
+    // Transactions running for longer than this cannot commit, and once prepared the transactions conflicting with
+    // them roll them back; 0 to never expire. May be overridden by each transaction.
+    pub txn_expiration_millis: u64,
+    // How long a commit waits for the prepared transactions it conflicts with to be resolved before failing; 0 to
+    // fail right away. May be overridden by each transaction.
+    pub txn_lock_timeout_millis: u64,
+    // Snapshots and transactions alive for longer than this are reported with the backtrace of where they were
+    // taken, as they keep compaction from garbage collecting the versions written after them; 0 to not capture the
+    // backtraces. Checked by the background dispatcher.
+    pub snapshot_warn_age_millis: u64,
+    // Gets taking longer than this many microseconds are reported as `tracing` events, with the `tracing` feature;
+    // 0 to not report them
+    pub slow_read_threshold_micros: u64,
+    // Where the diagnostics of the database go, such as the flushes and compactions it runs; None is the `log`
+    // facade
+    #[serde(skip)]
+    pub info_log: Option<Arc<dyn InfoLog>>,
+    // The most verbose diagnostics logged, `Off` to silence them
+    pub info_log_level: LevelFilter,
+    // Writes wait while at least this many immutable memtables of the default column family are left to flush, 0
+    // to never wait for them. At least `num_memtable_limit`, which the flushes start at
+    pub memtable_stop_writes_trigger: usize,
+    // Writes wait while L0 holds at least this many SSTs, 0 to never wait for them
+    pub level0_stop_writes_trigger: usize,
+    // Writes wait while compactions have at least this many bytes left to move, 0 to never wait for them
+    pub hard_pending_compaction_bytes_limit: u64,
+    // The SSTs compactions write to the bottom level are moved to the cold storage of the `file_system`, e.g. the
+    // object store of an `ObjectStoreFileSystem`, and read from there. Ignored by the file systems without one
+    pub cold_bottom_level: bool,
+    // Flush and compact in the writes freezing a memtable, rather than on background threads, and without
+    // subcompactions, so that the database spawns no thread. Always the case on wasm32, which cannot block either:
+    // the rate limiter and the lock timeouts of transactions are not available there
+    pub inline_background_jobs: bool,
+}
+
+/// What a best-effort recovery left out to open the database.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecoveryReport {
+    /// SSTs of the latest state of the manifest that are missing or cannot be opened.
+    pub unreadable_sst_ids: Vec<usize>,
+    /// SSTs of the latest state that are not in the recovered one, including the unreadable ones.
+    pub dropped_sst_ids: Vec<usize>,
+    /// Manifest records applied after the recovered state.
+    pub num_dropped_records: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecondaryCacheOptions {
+    pub path: PathBuf,
+    // Size of the cache file in bytes
+    pub capacity: u64,
 }
 
 impl LsmStorageOptions {
+    /// Compression of the SSTs written to `level`, 0 being L0.
+    pub fn compression_of_level(&self, level: usize) -> CompressionType {
+        let per_level = &self.compression_per_level;
+        per_level
+            .get(level)
+            .or(per_level.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Target size of the SSTs compactions write to `level`.
+    pub fn target_sst_size_of_level(&self, level: usize) -> usize {
+        let per_level = &self.target_sst_size_per_level;
+        per_level
+            .get(level)
+            .or(per_level.last())
+            .copied()
+            .unwrap_or(self.target_sst_size)
+    }
+
     pub fn default_for_week1_test() -> Self {
         Self {
             block_size: 4096,
@@ -88,6 +300,46 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            row_cache_capacity: 0,
+            metadata_caching: MetadataCaching::Table,
+            secondary_cache: None,
+            warm_block_cache_on_open: false,
+            block_cache: None,
+            write_buffer_manager: None,
+            periodic_compaction_seconds: 0,
+            tombstone_compaction_ratio: 0.0,
+            bottommost_recompaction_bytes: 0,
+            max_subcompactions: 1,
+            max_background_jobs: 2,
+            sst_partitioner: None,
+            compression_per_level: Vec::new(),
+            target_sst_size_per_level: Vec::new(),
+            min_blob_size: 0,
+            max_blob_space_amplification_percent: 0,
+            event_listeners: Vec::new(),
+            replication_sinks: Vec::new(),
+            rate_limiter: None,
+            statistics: None,
+            file_system: None,
+            clock: None,
+            max_manifest_file_size: 64 << 20,
+            best_effort_recovery: false,
+            delete_obsolete_files_period_seconds: 6 * 60 * 60,
+            paranoid_checks: false,
+            hybrid_logical_clock: false,
+            txn_spill_threshold: 0,
+            txn_conflict_granularity: ConflictGranularity::Key,
+            txn_expiration_millis: 0,
+            txn_lock_timeout_millis: 0,
+            snapshot_warn_age_millis: 0,
+            slow_read_threshold_micros: 0,
+            info_log: None,
+            info_log_level: LevelFilter::Info,
+            memtable_stop_writes_trigger: 0,
+            level0_stop_writes_trigger: 0,
+            hard_pending_compaction_bytes_limit: 0,
+            cold_bottom_level: false,
+            inline_background_jobs: false,
         }
     }
 
@@ -99,6 +351,46 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            row_cache_capacity: 0,
+            metadata_caching: MetadataCaching::Table,
+            secondary_cache: None,
+            warm_block_cache_on_open: false,
+            block_cache: None,
+            write_buffer_manager: None,
+            periodic_compaction_seconds: 0,
+            tombstone_compaction_ratio: 0.0,
+            bottommost_recompaction_bytes: 0,
+            max_subcompactions: 1,
+            max_background_jobs: 2,
+            sst_partitioner: None,
+            compression_per_level: Vec::new(),
+            target_sst_size_per_level: Vec::new(),
+            min_blob_size: 0,
+            max_blob_space_amplification_percent: 0,
+            event_listeners: Vec::new(),
+            replication_sinks: Vec::new(),
+            rate_limiter: None,
+            statistics: None,
+            file_system: None,
+            clock: None,
+            max_manifest_file_size: 64 << 20,
+            best_effort_recovery: false,
+            delete_obsolete_files_period_seconds: 6 * 60 * 60,
+            paranoid_checks: false,
+            hybrid_logical_clock: false,
+            txn_spill_threshold: 0,
+            txn_conflict_granularity: ConflictGranularity::Key,
+            txn_expiration_millis: 0,
+            txn_lock_timeout_millis: 0,
+            snapshot_warn_age_millis: 0,
+            slow_read_threshold_micros: 0,
+            info_log: None,
+            info_log_level: LevelFilter::Info,
+            memtable_stop_writes_trigger: 0,
+            level0_stop_writes_trigger: 0,
+            hard_pending_compaction_bytes_limit: 0,
+            cold_bottom_level: false,
+            inline_background_jobs: false,
         }
     }
 
@@ -110,6 +402,100 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            row_cache_capacity: 0,
+            metadata_caching: MetadataCaching::Table,
+            secondary_cache: None,
+            warm_block_cache_on_open: false,
+            block_cache: None,
+            write_buffer_manager: None,
+            periodic_compaction_seconds: 0,
+            tombstone_compaction_ratio: 0.0,
+            bottommost_recompaction_bytes: 0,
+            max_subcompactions: 1,
+            max_background_jobs: 2,
+            sst_partitioner: None,
+            compression_per_level: Vec::new(),
+            target_sst_size_per_level: Vec::new(),
+            min_blob_size: 0,
+            max_blob_space_amplification_percent: 0,
+            event_listeners: Vec::new(),
+            replication_sinks: Vec::new(),
+            rate_limiter: None,
+            statistics: None,
+            file_system: None,
+            clock: None,
+            max_manifest_file_size: 64 << 20,
+            best_effort_recovery: false,
+            delete_obsolete_files_period_seconds: 6 * 60 * 60,
+            paranoid_checks: false,
+            hybrid_logical_clock: false,
+            txn_spill_threshold: 0,
+            txn_conflict_granularity: ConflictGranularity::Key,
+            txn_expiration_millis: 0,
+            txn_lock_timeout_millis: 0,
+            snapshot_warn_age_millis: 0,
+            slow_read_threshold_micros: 0,
+            info_log: None,
+            info_log_level: LevelFilter::Info,
+            memtable_stop_writes_trigger: 0,
+            level0_stop_writes_trigger: 0,
+            hard_pending_compaction_bytes_limit: 0,
+            cold_bottom_level: false,
+            inline_background_jobs: false,
+        }
+    }
+}
+
+/// Options for a production database: leveled compaction with dynamic level sizing.
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 64 << 20, // 64MB
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions::default()),
+            enable_wal: false,
+            num_memtable_limit: 2,
+            serializable: false,
+            row_cache_capacity: 0,
+            metadata_caching: MetadataCaching::Table,
+            secondary_cache: None,
+            warm_block_cache_on_open: false,
+            block_cache: None,
+            write_buffer_manager: None,
+            periodic_compaction_seconds: 0,
+            tombstone_compaction_ratio: 0.0,
+            bottommost_recompaction_bytes: 0,
+            max_subcompactions: 1,
+            max_background_jobs: 2,
+            sst_partitioner: None,
+            compression_per_level: Vec::new(),
+            target_sst_size_per_level: Vec::new(),
+            min_blob_size: 0,
+            max_blob_space_amplification_percent: 0,
+            event_listeners: Vec::new(),
+            replication_sinks: Vec::new(),
+            rate_limiter: None,
+            statistics: None,
+            file_system: None,
+            clock: None,
+            max_manifest_file_size: 64 << 20,
+            best_effort_recovery: false,
+            delete_obsolete_files_period_seconds: 6 * 60 * 60,
+            paranoid_checks: false,
+            hybrid_logical_clock: false,
+            txn_spill_threshold: 0,
+            txn_conflict_granularity: ConflictGranularity::Key,
+            txn_expiration_millis: 0,
+            txn_lock_timeout_millis: 0,
+            snapshot_warn_age_millis: 0,
+            slow_read_threshold_micros: 0,
+            info_log: None,
+            info_log_level: LevelFilter::Info,
+            memtable_stop_writes_trigger: 0,
+            level0_stop_writes_trigger: 0,
+            hard_pending_compaction_bytes_limit: 0,
+            cold_bottom_level: false,
+            inline_background_jobs: false,
         }
     }
 }
@@ -119,51 +505,380 @@ pub enum CompactionFilter {
     Prefix(Bytes),
 }
 
+/// A random (version 4) UUID.
+pub(crate) fn new_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// An entry found in an SST: the SST, and the block and range its value is stored at.
+pub(crate) type SstEntry<'a> = (&'a Arc<SsTable>, Arc<Block>, Range<usize>);
+
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
+    /// The default column family.
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
+    /// The other column families by id, always locked after `state` when both are.
+    pub(crate) column_families: RwLock<BTreeMap<u32, ColumnFamilyState>>,
+    pub(crate) next_column_family_id: AtomicU32,
+    /// The column families dropped whose SSTs are not deleted yet, as iterators or compactions may still read them.
+    pub(crate) dropped_column_families: Mutex<Vec<ColumnFamilyState>>,
+    /// The blob files `put_streaming` is writing, kept until their SSTs are installed.
+    pub(crate) pending_blob_files: Mutex<BTreeSet<usize>>,
     pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    pub(crate) path: PathBuf,
+    /// The `file_system` of the options, or the local disk.
+    pub(crate) fs: Arc<dyn FileSystem>,
+    /// The `clock` of the options, or the system's.
+    pub(crate) clock: Arc<dyn Clock>,
     pub(crate) block_cache: Arc<BlockCache>,
+    pub(crate) row_cache: Option<RowCache>,
     next_sst_id: AtomicUsize,
-    pub(crate) options: Arc<LsmStorageOptions>,
-    pub(crate) compaction_controller: CompactionController,
+    /// Swapped by `set_options`.
+    options: ArcSwap<LsmStorageOptions>,
+    compaction_controller: ArcSwap<CompactionController>,
+    /// `None` when opened read-only.
     pub(crate) manifest: Option<Manifest>,
+    read_only: bool,
+    /// Where a secondary instance keeps its own files, see `open_as_secondary`.
+    pub(crate) secondary_path: Option<PathBuf>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) compaction_stats: CompactionStatsRecorder,
+    /// Held while compacting, so that compactions never pick the same SSTs.
+    pub(crate) compaction_lock: Mutex<()>,
+    /// Set when closing, compactions check it between the entries they write.
+    pub(crate) compactions_cancelled: AtomicBool,
+    /// No file is deleted while above 0, see `disable_file_deletions`.
+    file_deletions_disabled: AtomicUsize,
+    recovery_report: RecoveryReport,
+    db_id: String,
+    session_id: String,
+    /// The writes of the prepared transactions recovered from the WALs, until they are committed or rolled back.
+    recovered_prepared_txns: Mutex<BTreeMap<String, Vec<(Bytes, Bytes)>>>,
+    ts_oracle: TimestampOracle,
+    pub(crate) write_stall: WriteStallController,
+    /// The open change streams, sent the writes committed to the default column family.
+    pub(crate) change_subscribers: Mutex<Vec<ChangeSubscriber>>,
+}
+
+impl Drop for LsmStorageInner {
+    fn drop(&mut self) {
+        // release the memtables that were never flushed
+        if let Some(manager) = &self.options().write_buffer_manager {
+            let state = self.state.read();
+            let column_families = self.column_families.read();
+            for state in
+                std::iter::once(&*state).chain(column_families.values().map(|cf| &cf.state))
+            {
+                for memtable in std::iter::once(&state.memtable).chain(state.imm_memtables.iter()) {
+                    manager.free(memtable.approximate_size());
+                }
+            }
+        }
+    }
+}
+
+/// What opening the SSTs of a recovered state takes besides the state, see `LsmStorageInner::open_ssts`.
+pub(crate) struct SstOpenContext<'a> {
+    pub(crate) fs: &'a dyn FileSystem,
+    pub(crate) path: &'a Path,
+    pub(crate) block_cache: &'a Arc<BlockCache>,
+    pub(crate) file_checksums: &'a HashMap<usize, FileChecksum>,
+    pub(crate) options: &'a LsmStorageOptions,
+    /// The SSTs already open, reused instead of opened again.
+    pub(crate) opened: &'a HashMap<usize, Arc<SsTable>>,
+}
+
+/// The LSM structure the records of a manifest lead to, applied one by one.
+pub(crate) struct ManifestReplay {
+    pub(crate) state: LsmStorageState,
+    pub(crate) next_sst_id: usize,
+    /// Memtables are flushed in order, the WALs of the older ones are not recovered.
+    pub(crate) oldest_memtable_id: usize,
+    pub(crate) db_id: Option<String>,
+    pub(crate) file_checksums: HashMap<usize, FileChecksum>,
+    pub(crate) commit_ts_bound: u64,
+    /// The column families besides the default one.
+    pub(crate) column_families: BTreeMap<u32, ReplayedColumnFamily>,
+    pub(crate) next_column_family_id: u32,
+    /// The options of the database, the ones of the column families are resolved against.
+    options: LsmStorageOptions,
+}
+
+impl ManifestReplay {
+    pub(crate) fn new(options: &LsmStorageOptions) -> Self {
+        Self {
+            column_families: BTreeMap::new(),
+            next_column_family_id: DEFAULT_COLUMN_FAMILY_ID + 1,
+            state: LsmStorageState::create(options),
+            options: options.clone(),
+            next_sst_id: 1,
+            oldest_memtable_id: 0,
+            db_id: None,
+            file_checksums: HashMap::new(),
+            commit_ts_bound: 0,
+        }
+    }
+
+    pub(crate) fn apply(
+        &mut self,
+        compaction_controller: &CompactionController,
+        record: ManifestRecord,
+    ) {
+        match record {
+            ManifestRecord::Flush(sst_id) => {
+                if compaction_controller.flush_to_l0() {
+                    self.state.l0_sstables.insert(0, sst_id);
+                } else {
+                    self.state.levels.insert(0, (sst_id, vec![sst_id]));
+                }
+                self.next_sst_id = self.next_sst_id.max(sst_id + 1);
+                self.oldest_memtable_id = self.oldest_memtable_id.max(sst_id + 1);
+            }
+            ManifestRecord::NewMemtable(memtable_id) => {
+                self.next_sst_id = self.next_sst_id.max(memtable_id + 1);
+            }
+            ManifestRecord::Compaction(task, output) => {
+                (self.state, _) =
+                    compaction_controller.apply_compaction_result(&self.state, &task, &output);
+                if let Some(max_id) = output.iter().max() {
+                    self.next_sst_id = self.next_sst_id.max(max_id + 1);
+                }
+            }
+            ManifestRecord::Snapshot {
+                l0_sstables,
+                levels,
+                next_sst_id,
+                db_id,
+                file_checksums,
+                oldest_memtable_id,
+                commit_ts_bound,
+                next_column_family_id,
+            } => {
+                self.next_column_family_id = self.next_column_family_id.max(next_column_family_id);
+                self.commit_ts_bound = self.commit_ts_bound.max(commit_ts_bound);
+                self.oldest_memtable_id = self.oldest_memtable_id.max(oldest_memtable_id);
+                self.state.l0_sstables = l0_sstables;
+                self.state.levels = levels;
+                self.next_sst_id = self.next_sst_id.max(next_sst_id);
+                if !db_id.is_empty() {
+                    self.db_id = Some(db_id);
+                }
+                self.file_checksums.extend(file_checksums);
+            }
+            ManifestRecord::DbId(id) => self.db_id = Some(id),
+            ManifestRecord::FileChecksums(checksums) => self.file_checksums.extend(checksums),
+            ManifestRecord::CommitTsBound(bound) => {
+                self.commit_ts_bound = self.commit_ts_bound.max(bound)
+            }
+            ManifestRecord::CreateColumnFamily {
+                id,
+                name,
+                memtable_id,
+                options,
+            } => {
+                self.next_column_family_id = self.next_column_family_id.max(id + 1);
+                self.column_families.insert(
+                    id,
+                    ReplayedColumnFamily {
+                        name,
+                        state: LsmStorageState::create(&options.resolve(&self.options)),
+                        options,
+                        oldest_memtable_id: memtable_id,
+                    },
+                );
+            }
+            ManifestRecord::DropColumnFamily(id) => {
+                self.column_families.remove(&id);
+            }
+            ManifestRecord::ColumnFamilyFlush {
+                cf_id,
+                memtable_id,
+                sst_id,
+            } => {
+                self.next_sst_id = self.next_sst_id.max(sst_id + 1);
+                if let Some(cf) = self.column_families.get_mut(&cf_id) {
+                    let own_controller = cf.options.compaction_controller(&self.options);
+                    let compaction_controller =
+                        own_controller.as_ref().unwrap_or(compaction_controller);
+                    if compaction_controller.flush_to_l0() {
+                        cf.state.l0_sstables.insert(0, sst_id);
+                    } else {
+                        cf.state.levels.insert(0, (sst_id, vec![sst_id]));
+                    }
+                    cf.oldest_memtable_id = cf.oldest_memtable_id.max(memtable_id + 1);
+                }
+            }
+            ManifestRecord::ColumnFamilyCompaction(cf_id, task, output) => {
+                if let Some(max_id) = output.iter().max() {
+                    self.next_sst_id = self.next_sst_id.max(max_id + 1);
+                }
+                if let Some(cf) = self.column_families.get_mut(&cf_id) {
+                    let own_controller = cf.options.compaction_controller(&self.options);
+                    let compaction_controller =
+                        own_controller.as_ref().unwrap_or(compaction_controller);
+                    (cf.state, _) =
+                        compaction_controller.apply_compaction_result(&cf.state, &task, &output);
+                }
+            }
+            ManifestRecord::ColumnFamilySnapshot {
+                id,
+                name,
+                l0_sstables,
+                levels,
+                oldest_memtable_id,
+                options,
+            } => {
+                self.next_column_family_id = self.next_column_family_id.max(id + 1);
+                // also records the SSTs of an imported column family
+                if let Some(max_id) = l0_sstables
+                    .iter()
+                    .chain(levels.iter().flat_map(|(_, ssts)| ssts))
+                    .max()
+                {
+                    self.next_sst_id = self.next_sst_id.max(max_id + 1);
+                }
+                let mut state = LsmStorageState::create(&options.resolve(&self.options));
+                state.l0_sstables = l0_sstables;
+                state.levels = levels;
+                self.column_families.insert(
+                    id,
+                    ReplayedColumnFamily {
+                        name,
+                        options,
+                        state,
+                        oldest_memtable_id,
+                    },
+                );
+            }
+            ManifestRecord::IngestExternalFiles { cf_id, level, ssts } => {
+                if let Some(max_id) = ssts.iter().max() {
+                    self.next_sst_id = self.next_sst_id.max(max_id + 1);
+                }
+                let state = if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+                    &mut self.state
+                } else if let Some(cf) = self.column_families.get_mut(&cf_id) {
+                    &mut cf.state
+                } else {
+                    return;
+                };
+                if level == 0 {
+                    state.l0_sstables = ssts;
+                } else if let Some((_, level_ssts)) =
+                    state.levels.iter_mut().find(|(id, _)| *id == level)
+                {
+                    *level_ssts = ssts;
+                } else {
+                    state.levels.push((level, ssts));
+                }
+            }
+            ManifestRecord::NewestSst { cf_id, sst_id } => {
+                self.next_sst_id = self.next_sst_id.max(sst_id + 1);
+                let (state, flush_to_l0) = if cf_id == DEFAULT_COLUMN_FAMILY_ID {
+                    (&mut self.state, compaction_controller.flush_to_l0())
+                } else if let Some(cf) = self.column_families.get_mut(&cf_id) {
+                    let own_controller = cf.options.compaction_controller(&self.options);
+                    let compaction_controller =
+                        own_controller.as_ref().unwrap_or(compaction_controller);
+                    (&mut cf.state, compaction_controller.flush_to_l0())
+                } else {
+                    return;
+                };
+                if flush_to_l0 {
+                    state.l0_sstables.insert(0, sst_id);
+                } else {
+                    state.levels.insert(0, (sst_id, vec![sst_id]));
+                }
+            }
+        }
+    }
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
 pub struct MiniLsm {
     pub(crate) inner: Arc<LsmStorageInner>,
-    /// Notifies the L0 flush thread to stop working. (In week 1 day 6)
-    flush_notifier: crossbeam_channel::Sender<()>,
-    /// The handle for the flush thread. (In week 1 day 6)
-    flush_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
-    /// Notifies the compaction thread to stop working. (In week 2)
-    compaction_notifier: crossbeam_channel::Sender<()>,
-    /// The handle for the compaction thread. (In week 2)
-    compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Runs the flushes and compactions, `None` when opened read-only or with `inline_background_jobs`.
+    scheduler: Option<Arc<BackgroundScheduler>>,
+    /// Notifies the dispatcher thread to stop queueing jobs.
+    dispatcher_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the dispatcher thread.
+    dispatcher_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl Drop for MiniLsm {
+    /// Stop the background jobs so that none of them outlives the storage, without flushing the memtables.
     fn drop(&mut self) {
-        self.compaction_notifier.send(()).ok();
-        self.flush_notifier.send(()).ok();
+        if let Err(e) = self.stop_background_jobs(None) {
+            db_log!(
+                self.inner.options(),
+                Error,
+                "failed to stop the background jobs: {}",
+                e
+            );
+        }
     }
 }
 
 impl MiniLsm {
-    pub fn close(&self) -> Result<()> {
-        self.flush_notifier.send(()).ok();
-
-        let mut flush_thread = self.flush_thread.lock();
-        if let Some(flush_thread) = flush_thread.take() {
-            flush_thread
+    /// Stop queueing jobs, cancel the running compactions and wait for the running jobs, at most `timeout`. A
+    /// cancelled compaction deletes the SSTs it wrote, and anything left behind by a crash is deleted when opening.
+    fn stop_background_jobs(&self, timeout: Option<Duration>) -> Result<()> {
+        self.dispatcher_notifier.send(()).ok();
+        if let Some(dispatcher_thread) = self.dispatcher_thread.lock().take() {
+            dispatcher_thread
                 .join()
                 .map_err(|e| anyhow::anyhow!("{:?}", e))?;
         }
+        self.inner.cancel_compactions();
+        let Some(scheduler) = &self.scheduler else {
+            return Ok(());
+        };
+        match timeout {
+            Some(timeout) if !scheduler.shutdown_timeout(timeout) => {
+                bail!("background jobs still running after {:?}", timeout)
+            }
+            Some(_) => {}
+            None => scheduler.shutdown(),
+        }
+        Ok(())
+    }
+
+    /// Stop the background jobs, cancelling the running compactions and waiting for the running flush, then flush
+    /// the remaining immutable memtables.
+    pub fn close(&self) -> Result<()> {
+        self.stop_background_jobs(None)?;
+        self.flush_on_close()
+    }
+
+    /// Same as `close`, but fail without flushing anything if the running jobs do not stop within `timeout`. The
+    /// storage stays consistent, and `close` can be retried.
+    pub fn close_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.stop_background_jobs(Some(timeout))?;
+        self.flush_on_close()
+    }
 
+    fn flush_on_close(&self) -> Result<()> {
+        // ends the change streams
+        self.inner.change_subscribers.lock().clear();
+        if self.inner.is_read_only() {
+            return Ok(());
+        }
+        // the current memtable is not flushed, it is recovered from its WAL
+        self.inner.sync()?;
         while {
             let snapshot = self.inner.state.read();
             !snapshot.imm_memtables.is_empty()
@@ -175,27 +890,66 @@ impl MiniLsm {
         Ok(())
     }
 
+    /// Open the database with the options it was last opened with, see `LsmStorageOptions::load_latest`.
+    pub fn open_latest_options(path: impl AsRef<Path>) -> Result<Arc<Self>> {
+        let options = LsmStorageOptions::load_latest(&path)?;
+        Self::open(path, options)
+    }
+
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
-    pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+    pub fn open(path: impl AsRef<Path>, mut options: LsmStorageOptions) -> Result<Arc<Self>> {
+        if cfg!(target_arch = "wasm32") {
+            options.inline_background_jobs = true;
+        }
         let inner = Arc::new(LsmStorageInner::open(path, options)?);
-        let (tx1, rx) = crossbeam_channel::unbounded();
-        let compaction_thread = inner.spawn_compaction_thread(rx)?;
-        let (tx2, rx) = crossbeam_channel::unbounded();
-        let flush_thread = inner.spawn_flush_thread(rx)?;
+        if inner.options().inline_background_jobs {
+            return Ok(Self::without_background_threads(inner));
+        }
+        let scheduler = Arc::new(BackgroundScheduler::new(
+            inner.options().max_background_jobs,
+            DbLogger::new(&inner.options()),
+        ));
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let dispatcher_thread = inner.spawn_background_dispatcher(scheduler.clone(), rx)?;
         Ok(Arc::new(Self {
             inner,
-            flush_notifier: tx2,
-            flush_thread: Mutex::new(flush_thread),
-            compaction_notifier: tx1,
-            compaction_thread: Mutex::new(compaction_thread),
+            scheduler: Some(scheduler),
+            dispatcher_notifier: tx,
+            dispatcher_thread: Mutex::new(Some(dispatcher_thread)),
         }))
     }
 
-    pub fn new_txn(&self) -> Result<()> {
+    /// Open the database at `path` read-only, see `LsmStorageInner::open_read_only`. No background job runs.
+    pub fn open_read_only(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+        let inner = LsmStorageInner::open_read_only(path, options)?;
+        Ok(Self::without_background_jobs(inner))
+    }
+
+    /// Wrap a storage opened read-only, which never flushes nor compacts.
+    pub(crate) fn without_background_jobs(inner: LsmStorageInner) -> Arc<Self> {
+        Self::without_background_threads(Arc::new(inner))
+    }
+
+    /// Wrap a storage running no background thread, either read-only or with `inline_background_jobs`.
+    fn without_background_threads(inner: Arc<LsmStorageInner>) -> Arc<Self> {
+        let (tx, _) = crossbeam_channel::unbounded();
+        Arc::new(Self {
+            inner,
+            scheduler: None,
+            dispatcher_notifier: tx,
+            dispatcher_thread: Mutex::new(None),
+        })
+    }
+
+    pub fn new_txn(&self) -> Result<Arc<Transaction>> {
         self.inner.new_txn()
     }
 
+    pub fn recovered_prepared_txns(&self) -> Vec<Arc<Transaction>> {
+        self.inner.recovered_prepared_txns()
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
@@ -208,6 +962,40 @@ impl MiniLsm {
         self.inner.get(key)
     }
 
+    pub fn get_pinned(&self, key: &[u8]) -> Result<Option<PinnableSlice>> {
+        self.inner.get_pinned(key)
+    }
+
+    pub async fn get_async(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_async(key).await
+    }
+
+    pub fn get_at_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at_ts(key, read_ts)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.snapshot()
+    }
+
+    pub fn get_with_snapshot(&self, key: &[u8], snapshot: &Snapshot) -> Result<Option<Bytes>> {
+        self.inner.get_with_snapshot(key, snapshot)
+    }
+
+    pub fn scan_with_snapshot(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_with_snapshot(lower, upper, snapshot)
+    }
+
+    /// The commit ts of the latest write, usable with `get_at_ts` and `scan_at_ts` to read this version later.
+    pub fn latest_commit_ts(&self) -> u64 {
+        self.inner.mvcc().latest_commit_ts()
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
@@ -228,9 +1016,25 @@ impl MiniLsm {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_at_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_at_ts(lower, upper, read_ts)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
-        if !self.inner.state.read().memtable.is_empty() {
+        let any_written = !self.inner.state.read().memtable.is_empty()
+            || self
+                .inner
+                .column_families
+                .read()
+                .values()
+                .any(|cf| !cf.state.memtable.is_empty());
+        if any_written {
             self.inner
                 .force_freeze_memtable(&self.inner.state_lock.lock())?;
         }
@@ -243,6 +1047,75 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    pub fn compact_files(&self, sst_ids: &[usize], output_level: usize) -> Result<Vec<usize>> {
+        self.inner.compact_files(sst_ids, output_level)
+    }
+
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.inner.block_cache.stats()
+    }
+
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.inner.compaction_stats()
+    }
+
+    /// The options in effect, including the changes made by `set_options`.
+    pub fn options(&self) -> Arc<LsmStorageOptions> {
+        self.inner.options.load_full()
+    }
+
+    pub fn set_options(&self, changes: &[(&str, &str)]) -> Result<()> {
+        self.inner.set_options(changes)
+    }
+
+    pub fn snapshot_stats(&self) -> SnapshotStats {
+        self.inner.mvcc().snapshot_stats()
+    }
+
+    pub fn level_stats(&self) -> String {
+        self.inner.level_stats()
+    }
+
+    pub fn plan_compactions(&self, max_jobs: usize) -> Vec<PlannedCompaction> {
+        self.inner.plan_compactions(max_jobs)
+    }
+
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        self.inner.recovery_report()
+    }
+
+    pub fn delete_obsolete_files(&self) -> Result<Vec<usize>> {
+        self.inner.delete_obsolete_files()
+    }
+
+    pub fn verify_file_checksums(&self) -> Result<()> {
+        self.inner.verify_file_checksums()
+    }
+
+    pub fn disable_file_deletions(&self) {
+        self.inner.disable_file_deletions()
+    }
+
+    pub fn enable_file_deletions(&self) -> Result<()> {
+        self.inner.enable_file_deletions()
+    }
+
+    pub fn db_id(&self) -> &str {
+        self.inner.db_id()
+    }
+
+    pub fn session_id(&self) -> &str {
+        self.inner.session_id()
+    }
+
+    pub fn block_cache_table_stats(&self) -> Vec<(usize, BlockCacheCounts)> {
+        self.inner.block_cache_table_stats()
+    }
+
+    pub fn warm_block_cache(&self) -> Result<()> {
+        self.inner.warm_block_cache()
+    }
 }
 
 impl LsmStorageInner {
@@ -251,127 +1124,1388 @@ impl LsmStorageInner {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
+    pub(crate) fn mvcc(&self) -> &LsmMvccInner {
+        self.mvcc.as_ref().unwrap()
+    }
+
+    /// Load the blocks most reads go through into the block cache: every data block of the L0 tables, which are all
+    /// probed by a point lookup, then the filters of the lower levels when they live in the cache. Stops once the
+    /// cache is full rather than evicting what was just loaded.
+    pub fn warm_block_cache(&self) -> Result<()> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        let full = || self.block_cache.usage() >= self.block_cache.capacity();
+        for sst_id in &snapshot.l0_sstables {
+            let table = &snapshot.sstables[sst_id];
+            table.warm_filter()?;
+            for block_idx in 0..table.num_of_blocks() {
+                if full() {
+                    return Ok(());
+                }
+                table.read_block_cached(block_idx)?;
+            }
+        }
+        for (_, level_sst_ids) in &snapshot.levels {
+            for sst_id in level_sst_ids {
+                if full() {
+                    return Ok(());
+                }
+                snapshot.sstables[sst_id].warm_filter()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Block cache counters of every live SST, by SST id.
+    pub fn block_cache_table_stats(&self) -> Vec<(usize, BlockCacheCounts)> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        Self::all_sstables(&snapshot)
+            .map(|table| (table.sst_id(), table.block_cache_counts()))
+            .collect()
+    }
+
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let path = path.as_ref();
+        Self::open_with_mode(path.as_ref(), options, false)
+    }
+
+    /// Open an existing database without writing anything to its directory, so that it can be read while another
+    /// process owns it. The WALs are replayed into memtables with `enable_wal`, and ignored without, leaving only
+    /// the flushed writes. Writes fail, and nothing is flushed or compacted.
+    pub(crate) fn open_read_only(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<Self> {
+        Self::open_with_mode(path.as_ref(), options, true)
+    }
+
+    pub(crate) fn open_with_mode(
+        path: &Path,
+        options: LsmStorageOptions,
+        read_only: bool,
+    ) -> Result<Self> {
+        options.validate()?;
+        let fs = options.file_system_or_default();
 
-        if !path.exists() {
-            std::fs::create_dir(path)?;
+        if !fs.exists(path) {
+            if read_only {
+                bail!("{} does not exist", path.display());
+            }
+            fs.create_dir_all(path)?;
+        }
+        if let Some(persisted) = LsmStorageOptions::load(fs.as_ref(), path)? {
+            options.check_compatible(&persisted)?;
         }
+        if !read_only {
+            Self::remove_txn_spills(fs.as_ref(), path)?;
+        }
+
+        let block_cache = match (&options.block_cache, &options.secondary_cache) {
+            (Some(block_cache), _) => block_cache.clone(),
+            (None, secondary) => {
+                let mut block_cache = BlockCache::new(1024 * options.block_size as u64);
+                if let Some(secondary) = secondary {
+                    block_cache = block_cache.with_secondary_cache(SecondaryCache::create(
+                        &secondary.path,
+                        secondary.capacity,
+                    )?);
+                }
+                Arc::new(block_cache)
+            }
+        };
 
-        let state = LsmStorageState::create(&options);
+        let compaction_controller = CompactionController::new(&options);
 
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+        let mut state = LsmStorageState::create(&options);
+        let mut next_sst_id = 1;
+        let mut last_commit_ts = 0;
+        let mut recovery_report = RecoveryReport::default();
+        let mut prepared_txns = BTreeMap::new();
+        let mut commit_ts_bound = 0;
+        let mut column_families = BTreeMap::new();
+        let mut next_column_family_id = DEFAULT_COLUMN_FAMILY_ID + 1;
+        let logger = DbLogger::new(&options);
+        let current = if read_only {
+            read_current_manifest(fs.as_ref(), path)?
+        } else {
+            current_manifest(fs.as_ref(), path, &logger)?
+        };
+        let (manifest, db_id) = match current {
+            None if read_only => bail!("{} has no database", path.display()),
+            None => {
+                if options.enable_wal {
+                    let memtable_id = state.memtable.id();
+                    state.memtable = Arc::new(MemTable::create_with_wal(
+                        memtable_id,
+                        fs.as_ref(),
+                        Self::path_of_wal_static(path, memtable_id),
+                    )?);
+                }
+                let name = manifest_file_name(1);
+                let manifest = Manifest::create(&fs, path.join(&name))?;
+                manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+                let db_id = new_uuid();
+                manifest.add_record_when_init(ManifestRecord::DbId(db_id.clone()))?;
+                // the manifest becomes reachable once it is complete
+                set_current(fs.as_ref(), path, &name)?;
+                (Some(manifest), db_id)
             }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            Some(name) => {
+                let recovered = if read_only {
+                    Manifest::read_records(fs.as_ref(), path.join(&name), &logger)
+                        .map(|records| (None, records))
+                } else {
+                    Manifest::recover(&fs, path.join(&name), &logger)
+                        .map(|(manifest, records)| (Some(manifest), records))
+                };
+                let (manifest, records) =
+                    recovered.with_context(|| format!("failed to recover {}", name))?;
+                let num_records = records.len();
+                let mut replay = ManifestReplay::new(&options);
+                // the latest state whose SSTs are all readable, with the number of records applied
+                let mut consistent_state = (state.clone(), BTreeMap::new(), 0);
+                let mut readable_ssts = HashMap::new();
+                for (applied, record) in records.into_iter().enumerate() {
+                    replay.apply(&compaction_controller, record);
+                    if options.best_effort_recovery
+                        && std::iter::once(&replay.state)
+                            .chain(replay.column_families.values().map(|cf| &cf.state))
+                            .all(|state| {
+                                Self::all_ssts_readable(
+                                    fs.as_ref(),
+                                    path,
+                                    state,
+                                    &mut readable_ssts,
+                                )
+                            })
+                    {
+                        consistent_state = (
+                            replay.state.clone(),
+                            replay.column_families.clone(),
+                            applied + 1,
+                        );
+                    }
+                }
+                let ManifestReplay {
+                    state: replayed_state,
+                    next_sst_id: replayed_next_sst_id,
+                    oldest_memtable_id,
+                    db_id,
+                    file_checksums,
+                    commit_ts_bound: replayed_commit_ts_bound,
+                    column_families: replayed_column_families,
+                    next_column_family_id: replayed_next_column_family_id,
+                    ..
+                } = replay;
+                (state, next_sst_id, commit_ts_bound) = (
+                    replayed_state,
+                    replayed_next_sst_id,
+                    replayed_commit_ts_bound,
+                );
+                (column_families, next_column_family_id) =
+                    (replayed_column_families, replayed_next_column_family_id);
+                if options.best_effort_recovery && consistent_state.2 < num_records {
+                    let (recovered, recovered_column_families, applied) = consistent_state;
+                    recovery_report = Self::drop_inconsistent_ssts(
+                        fs.as_ref(),
+                        path,
+                        &Self::replayed_sst_ids(&state, &column_families),
+                        &Self::replayed_sst_ids(&recovered, &recovered_column_families),
+                        &readable_ssts,
+                        read_only,
+                    )?;
+                    recovery_report.num_dropped_records = num_records - applied;
+                    db_log!(options, Warn, "best-effort recovery: {:?}", recovery_report);
+                    state = recovered;
+                    column_families = recovered_column_families;
+                }
+                if options.paranoid_checks {
+                    Self::check_all_ssts(fs.as_ref(), path, &state)?;
+                    for cf in column_families.values() {
+                        Self::check_all_ssts(fs.as_ref(), path, &cf.state)?;
+                    }
+                }
+
+                let context = SstOpenContext {
+                    fs: fs.as_ref(),
+                    path,
+                    block_cache: &block_cache,
+                    file_checksums: &file_checksums,
+                    options: &options,
+                    opened: &HashMap::new(),
+                };
+                last_commit_ts = Self::open_ssts(&context, &mut state, &compaction_controller)?;
+                last_commit_ts = last_commit_ts.max(Self::open_column_family_ssts(
+                    &context,
+                    &mut column_families,
+                    &compaction_controller,
+                )?);
+                let live_sst_ids = Self::replayed_sst_ids(&state, &column_families);
+                db_log!(options, Info, "{} SSTs opened", live_sst_ids.len());
+                let live_blob_file_ids = std::iter::once(&state)
+                    .chain(column_families.values().map(|cf| &cf.state))
+                    .flat_map(|state| state.blob_file_ids())
+                    .collect::<BTreeSet<_>>();
+
+                if !read_only {
+                    Self::remove_obsolete_ssts(&options, fs.as_ref(), path, &live_sst_ids)?;
+                    Self::remove_obsolete_blob_files(
+                        &options,
+                        fs.as_ref(),
+                        path,
+                        &live_blob_file_ids,
+                    )?;
+                }
+                // the ids of the blob files are not recorded, they come from the counter of the SST ids
+                if let Some(blob_file_id) = live_blob_file_ids.last() {
+                    next_sst_id = next_sst_id.max(blob_file_id + 1);
+                }
+
+                if options.enable_wal {
+                    for wal_id in Self::wal_ids(fs.as_ref(), path)? {
+                        let wal_path = Self::path_of_wal_static(path, wal_id);
+                        let (memtable, records) = if read_only {
+                            MemTable::read_from_wal(wal_id, fs.as_ref(), wal_path)?
+                        } else {
+                            MemTable::recover_from_wal(wal_id, fs.as_ref(), wal_path, &logger)?
+                        };
+                        next_sst_id = next_sst_id.max(wal_id + 1);
+                        let column_families_recovered = Self::recover_column_family_memtables(
+                            &mut column_families,
+                            wal_id,
+                            &records,
+                        );
+                        for record in records {
+                            if let Some(ts) = record.commit_ts() {
+                                last_commit_ts = last_commit_ts.max(ts);
+                            }
+                            match record {
+                                WalRecord::Batch {
+                                    txn_name,
+                                    entries: _,
+                                } => {
+                                    if let Some(txn_name) = txn_name {
+                                        prepared_txns.remove(&txn_name);
+                                    }
+                                }
+                                WalRecord::ColumnFamilyBatch { .. } => {}
+                                WalRecord::Prepare {
+                                    txn_name,
+                                    entries,
+                                    read_key_hashes,
+                                } => {
+                                    prepared_txns
+                                        .insert(txn_name, (wal_id, entries, read_key_hashes));
+                                }
+                                WalRecord::Rollback { txn_name } => {
+                                    prepared_txns.remove(&txn_name);
+                                }
+                            }
+                        }
+                        // the WALs of flushed memtables are only kept for the prepare records they hold. The
+                        // default memtable keeps the WAL of the column families recovered from it.
+                        if wal_id >= oldest_memtable_id
+                            && (!memtable.is_empty() || column_families_recovered)
+                        {
+                            state.imm_memtables.insert(0, Arc::new(memtable));
+                        }
+                    }
+                    db_log!(
+                        options,
+                        Info,
+                        "{} memtables and {} prepared transactions recovered from the WALs",
+                        state.imm_memtables.len(),
+                        prepared_txns.len()
+                    );
+                }
+
+                state.memtable = Arc::new(match &manifest {
+                    Some(_) if options.enable_wal => MemTable::create_with_wal(
+                        next_sst_id,
+                        fs.as_ref(),
+                        Self::path_of_wal_static(path, next_sst_id),
+                    )?,
+                    _ => MemTable::create(next_sst_id),
+                });
+                if let Some(manifest) = &manifest {
+                    manifest.add_record_when_init(ManifestRecord::NewMemtable(next_sst_id))?;
+                }
+                next_sst_id += 1;
+                // databases created before their identity was recorded get one now
+                let db_id = match (db_id, &manifest) {
+                    (Some(db_id), _) => db_id,
+                    (None, manifest) => {
+                        let db_id = new_uuid();
+                        if let Some(manifest) = manifest {
+                            manifest.add_record_when_init(ManifestRecord::DbId(db_id.clone()))?;
+                        }
+                        db_id
+                    }
+                };
+                (manifest, db_id)
             }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
-            ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
         };
 
+        let clock = options.clock_or_default();
+        let ts_oracle =
+            TimestampOracle::new(options.hybrid_logical_clock, clock.clone(), commit_ts_bound);
+        let snapshot_warn_age = (options.snapshot_warn_age_millis > 0)
+            .then(|| Duration::from_millis(options.snapshot_warn_age_millis));
+        // the memtables of the column families are frozen along with the default one, taking its id
+        let column_families = column_families
+            .into_iter()
+            .map(|(id, cf)| (id, cf.into_state(state.memtable.id())))
+            .collect();
         let storage = Self {
             state: Arc::new(RwLock::new(Arc::new(state))),
+            column_families: RwLock::new(column_families),
+            next_column_family_id: AtomicU32::new(next_column_family_id),
+            dropped_column_families: Mutex::new(Vec::new()),
+            pending_blob_files: Mutex::new(BTreeSet::new()),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1024)),
-            next_sst_id: AtomicUsize::new(1),
-            compaction_controller,
-            manifest: None,
-            options: options.into(),
-            mvcc: None,
+            fs,
+            clock,
+            block_cache,
+            row_cache: (options.row_cache_capacity > 0)
+                .then(|| RowCache::new(options.row_cache_capacity)),
+            next_sst_id: AtomicUsize::new(next_sst_id),
+            compaction_controller: ArcSwap::from_pointee(compaction_controller),
+            manifest,
+            read_only,
+            secondary_path: None,
+            options: ArcSwap::from_pointee(options),
+            mvcc: Some(LsmMvccInner::new(last_commit_ts, snapshot_warn_age)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            compaction_stats: CompactionStatsRecorder::default(),
+            compaction_lock: Mutex::new(()),
+            compactions_cancelled: AtomicBool::new(false),
+            file_deletions_disabled: AtomicUsize::new(0),
+            recovery_report,
+            db_id,
+            session_id: new_uuid(),
+            recovered_prepared_txns: Mutex::new(BTreeMap::new()),
+            ts_oracle,
+            write_stall: WriteStallController::default(),
+            change_subscribers: Mutex::new(Vec::new()),
         };
+        if let Some(manager) = &storage.options().write_buffer_manager {
+            for memtable in &storage.state.read().imm_memtables {
+                manager.reserve(memtable.approximate_size());
+            }
+            for cf in storage.column_families.read().values() {
+                for memtable in &cf.state.imm_memtables {
+                    manager.reserve(memtable.approximate_size());
+                }
+            }
+        }
+        // a read-only open leaves the directory, with the prepared transactions of its owner, alone
+        if !read_only {
+            for (name, (wal_id, writes, read_key_hashes)) in prepared_txns {
+                storage.mvcc().prepared_txns.lock().insert(
+                    name.clone(),
+                    PreparedTxnData {
+                        write_key_hashes: writes
+                            .iter()
+                            .map(|(key, _)| {
+                                storage.options().txn_conflict_granularity.key_hash(key)
+                            })
+                            .collect(),
+                        read_key_hashes: read_key_hashes.into_iter().collect(),
+                        wal_id,
+                        lease: None,
+                    },
+                );
+                storage.recovered_prepared_txns.lock().insert(name, writes);
+            }
+            storage.delete_obsolete_wals()?;
+            if storage.recovery_report.num_dropped_records > 0 {
+                // the manifest must not lead back to the dropped state
+                let state_lock = storage.state_lock.lock();
+                if let Some(manifest) = &storage.manifest {
+                    manifest.rollover(&state_lock, &storage.manifest_snapshot())?;
+                }
+            }
+            storage.options().persist(storage.fs.as_ref(), path)?;
+            storage.sync_dir()?;
+        }
+        if storage.options().warm_block_cache_on_open {
+            storage.warm_block_cache()?;
+        }
 
         Ok(storage)
     }
 
-    pub fn sync(&self) -> Result<()> {
-        unimplemented!()
+    /// Open the SSTs of `state` into `state.sstables`, reusing the ones already opened, and return their largest ts.
+    pub(crate) fn open_ssts(
+        context: &SstOpenContext<'_>,
+        state: &mut LsmStorageState,
+        compaction_controller: &CompactionController,
+    ) -> Result<u64> {
+        let SstOpenContext {
+            fs,
+            path,
+            block_cache,
+            file_checksums,
+            options,
+            opened,
+        } = *context;
+        let mut max_ts = 0;
+        let bottom_level_ssts = state
+            .levels
+            .last()
+            .map(|(_, ssts)| ssts.clone())
+            .unwrap_or_default();
+        for sst_id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            let sst = match opened.get(sst_id) {
+                Some(sst) => sst.clone(),
+                None => {
+                    let sst = SsTable::open(
+                        *sst_id,
+                        Some(block_cache.clone()),
+                        FileObject::open(fs, &Self::path_of_sst_static(path, *sst_id))
+                            .with_context(|| format!("failed to open {}.sst", sst_id))?,
+                    )?
+                    .with_file_checksum(file_checksums.get(sst_id).copied())
+                    .with_metadata_caching(options.metadata_caching)
+                    .with_statistics(options.statistics.clone())
+                    .with_cache_priority(
+                        if bottom_level_ssts.contains(sst_id) {
+                            CachePriority::Bottom
+                        } else {
+                            CachePriority::Low
+                        },
+                    );
+                    Arc::new(Self::open_blob_files(fs, path, sst)?)
+                }
+            };
+            max_ts = max_ts.max(sst.max_ts());
+            state.sstables.insert(*sst_id, sst);
+        }
+        if let CompactionController::Leveled(_) = compaction_controller {
+            for (_, ssts) in &mut state.levels {
+                ssts.sort_by(|x, y| {
+                    state.sstables[x]
+                        .first_key()
+                        .cmp(state.sstables[y].first_key())
+                });
+            }
+        }
+        Ok(max_ts)
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
-        let mut compaction_filters = self.compaction_filters.lock();
-        compaction_filters.push(compaction_filter);
+    /// Make the writes to the current memtable durable, nothing to do without `enable_wal`. The WALs of the
+    /// frozen memtables are synced when they are frozen.
+    pub fn sync(&self) -> Result<()> {
+        let memtable = self.state.read().memtable.clone();
+        self.sync_wal(&memtable)
     }
 
-    fn key_within(user_key: &[u8], table_begin: &[u8], table_end: &[u8]) -> bool {
-        table_begin <= user_key && user_key <= table_end
+    /// Sync the WAL of `memtable`, if it has one, counted in the statistics.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "wal_sync",
+            level = "debug",
+            skip_all,
+            fields(memtable_id = memtable.id()),
+            err
+        )
+    )]
+    pub(crate) fn sync_wal(&self, memtable: &MemTable) -> Result<()> {
+        if memtable.wal().is_none() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let timer = PerfTimer::start();
+        memtable.sync_wal()?;
+        timer.stop(|context| &mut context.wal_sync_time);
+        if let Some(statistics) = &self.options().statistics {
+            statistics.record_elapsed(Histogram::WalSync, started);
+            statistics.record_tick(Ticker::WalSynced, 1);
+        }
+        Ok(())
     }
 
-    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
-    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        let state = self.state.read();
-        // 1. find in memtable
-        let mut get_result = state.memtable.get(key);
-        if let Some(bytes) = get_result.as_ref() {
-            // value is &[] so return None
-            if bytes.is_empty() {
-                return Ok(None);
-            }
-            return Ok(get_result);
-        }
-        // 2. find in imm_memtables
-        for imm_t in state.imm_memtables.iter() {
-            get_result = imm_t.get(key);
-            if let Some(bytes) = get_result.as_ref() {
-                if bytes.is_empty() {
-                    return Ok(None);
-                }
-                return Ok(get_result);
-            }
+    /// Delete the SSTs the manifest does not know about, as when opening, and return their ids. Runs every
+    /// `delete_obsolete_files_period_seconds` in the background. Nothing is deleted while a compaction runs, since
+    /// its output is not recorded in the manifest yet, and the SSTs of the dropped column families are left to
+    /// `purge_dropped_column_families`.
+    pub fn delete_obsolete_files(&self) -> Result<Vec<usize>> {
+        self.check_writable()?;
+        if !self.file_deletions_enabled() {
+            return Ok(Vec::new());
         }
-        Ok(get_result)
+        let Some(_compaction_lock) = self.compaction_lock.try_lock() else {
+            return Ok(Vec::new());
+        };
+        self.remove_obsolete_files()
     }
 
-    /// Write a batch of data into the storage. Implement in week 2 day 7.
-    pub fn write_batch<T: AsRef<[u8]>>(&self, _batch: &[WriteBatchRecord<T>]) -> Result<()> {
-        unimplemented!()
+    /// Same as `delete_obsolete_files`, must be called with the compaction lock.
+    fn remove_obsolete_files(&self) -> Result<Vec<usize>> {
+        // flushes hold the state lock until their SST is recorded
+        let _state_lock = self.state_lock.lock();
+        let mut kept_sst_ids = self.live_sst_ids();
+        for cf in self.dropped_column_families.lock().iter() {
+            kept_sst_ids.extend(cf.state.sst_ids());
+        }
+        let options = self.options();
+        Self::remove_obsolete_blob_files(
+            &options,
+            self.fs.as_ref(),
+            &self.path,
+            &self.live_blob_file_ids(),
+        )?;
+        Self::remove_obsolete_ssts(&options, self.fs.as_ref(), &self.path, &kept_sst_ids)
     }
 
-    /// Put a key-value pair into the storage by writing into the current memtable.
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        let state = self.state.read();
-        let new_approximate_size = state.memtable.approximate_size() + key.len() + value.len();
-        state.memtable.set_approximate_size(new_approximate_size);
-        state.memtable.put(key, value)?;
+    /// Stop deleting files, for external tools to copy the live ones, until `enable_file_deletions` is called as
+    /// many times. The SSTs replaced by compactions and of dropped column families are kept, as are the WALs of the
+    /// flushed memtables, and the manifest is not rolled over. Deletions started before are done when it returns.
+    pub fn disable_file_deletions(&self) {
+        self.file_deletions_disabled.fetch_add(1, Ordering::SeqCst);
+        // compactions delete their inputs with the compaction lock, the WALs are deleted with the state lock
+        drop(self.compaction_lock.lock());
+        drop(self.state_lock.lock());
+        drop(self.dropped_column_families.lock());
+    }
 
-        if state.memtable.approximate_size() >= self.options.target_sst_size {
-            drop(state);
-            return self.force_freeze_memtable(&self.state_lock.lock());
+    /// Undo a call to `disable_file_deletions`. Once every call is undone, the files kept meanwhile are deleted.
+    pub fn enable_file_deletions(&self) -> Result<()> {
+        let disabled = self
+            .file_deletions_disabled
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |disabled| {
+                disabled.checked_sub(1)
+            })
+            .unwrap_or(0);
+        if disabled == 0 {
+            bail!("file deletions are not disabled");
+        }
+        if disabled > 1 || self.read_only {
+            return Ok(());
         }
+        let compaction_lock = self.compaction_lock.lock();
+        let removed = self.remove_obsolete_files()?;
+        drop(compaction_lock);
+        self.purge_dropped_column_families()?;
+        let _state_lock = self.state_lock.lock();
+        self.delete_obsolete_wals()?;
+        db_log!(
+            self.options(),
+            Info,
+            "file deletions enabled, {} SSTs kept meanwhile removed",
+            removed.len()
+        );
         Ok(())
     }
 
-    /// Remove a key from the storage by writing an empty value.
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        let state = self.state.read();
-        let new_approximate_size = state.memtable.approximate_size() + key.len();
-        state.memtable.set_approximate_size(new_approximate_size);
-        state.memtable.put(key, &[])?;
+    pub(crate) fn file_deletions_enabled(&self) -> bool {
+        self.file_deletions_disabled.load(Ordering::SeqCst) == 0
+    }
 
-        if state.memtable.approximate_size() >= self.options.target_sst_size {
-            drop(state);
-            return self.force_freeze_memtable(&self.state_lock.lock());
+    /// Read every live SST file and compare its size and checksum with the ones recorded in the manifest, failing
+    /// with all the mismatches. SSTs written before checksums were recorded are skipped.
+    pub fn verify_file_checksums(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for (sst_id, sst) in self
+            .all_column_family_states()
+            .iter()
+            .flat_map(|state| &state.sstables)
+        {
+            let Some(checksum) = sst.file_checksum() else {
+                continue;
+            };
+            if let Err(e) = checksum.verify_file(self.fs.as_ref(), &self.path_of_sst(*sst_id)) {
+                errors.push(format!("{:05}.sst: {:#}", sst_id, e));
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort();
+            bail!(
+                "file checksums mismatch on {} SSTs:\n{}",
+                errors.len(),
+                errors.join("\n")
+            );
         }
         Ok(())
     }
 
-    pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
-        path.as_ref().join(format!("{:05}.sst", id))
+    /// Delete the SSTs the manifest does not know about: the output of a compaction that crashed or was cancelled
+    /// before it was installed, or of a flush that was not recorded, and the compacted SSTs whose deletion failed.
+    fn remove_obsolete_ssts(
+        options: &LsmStorageOptions,
+        fs: &dyn FileSystem,
+        path: &Path,
+        live_sst_ids: &BTreeSet<usize>,
+    ) -> Result<Vec<usize>> {
+        let mut removed = Vec::new();
+        for file_name in fs.list_dir(path)? {
+            let Some(sst_id) = file_name
+                .strip_suffix(".sst")
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if !live_sst_ids.contains(&sst_id) {
+                db_log!(options, Info, "removing obsolete {}.sst", sst_id);
+                fs.remove_file(&Self::path_of_sst_static(path, sst_id))?;
+                removed.push(sst_id);
+            }
+        }
+        Ok(removed)
     }
 
-    pub(crate) fn path_of_sst(&self, id: usize) -> PathBuf {
-        Self::path_of_sst_static(&self.path, id)
+    /// Delete the writes spilled by the transactions of a previous run.
+    pub(crate) fn remove_txn_spills(fs: &dyn FileSystem, path: &Path) -> Result<()> {
+        for file_name in fs.list_dir(path)? {
+            if SpilledTxnWrites::is_spill_file(&file_name) {
+                fs.remove_file(&path.join(file_name))?;
+            }
+        }
+        Ok(())
     }
 
-    pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
-        path.as_ref().join(format!("{:05}.wal", id))
+    /// The ids of the WALs in `path`, in ascending order.
+    pub(crate) fn wal_ids(fs: &dyn FileSystem, path: &Path) -> Result<Vec<usize>> {
+        let mut wal_ids = Vec::new();
+        for file_name in fs.list_dir(path)? {
+            if let Some(wal_id) = file_name
+                .strip_suffix(".wal")
+                .and_then(|id| id.parse::<usize>().ok())
+            {
+                wal_ids.push(wal_id);
+            }
+        }
+        wal_ids.sort();
+        Ok(wal_ids)
+    }
+
+    /// Delete the WALs of the flushed memtables, except the ones from the oldest prepared transaction that is not
+    /// resolved yet, which recovery reads it from.
+    fn delete_obsolete_wals(&self) -> Result<()> {
+        if !self.options().enable_wal || !self.file_deletions_enabled() {
+            return Ok(());
+        }
+        let oldest_memtable_id = {
+            let snapshot = self.state.read();
+            snapshot
+                .imm_memtables
+                .last()
+                .unwrap_or(&snapshot.memtable)
+                .id()
+        };
+        let oldest_prepared_wal_id = self
+            .mvcc()
+            .prepared_txns
+            .lock()
+            .values()
+            .map(|txn| txn.wal_id)
+            .min()
+            .unwrap_or(usize::MAX);
+        for wal_id in Self::wal_ids(self.fs.as_ref(), &self.path)? {
+            if wal_id < oldest_memtable_id.min(oldest_prepared_wal_id) {
+                self.fs.remove_file(&self.path_of_wal(wal_id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether every SST of `state` can be opened, caching the result of every SST in `readable_ssts`.
+    fn all_ssts_readable(
+        fs: &dyn FileSystem,
+        path: &Path,
+        state: &LsmStorageState,
+        readable_ssts: &mut HashMap<usize, bool>,
+    ) -> bool {
+        state.sst_ids().all(|sst_id| {
+            *readable_ssts
+                .entry(*sst_id)
+                .or_insert_with(|| Self::check_sst(fs, path, *sst_id).is_ok())
+        })
+    }
+
+    /// Open an SST and check its block meta.
+    fn check_sst(fs: &dyn FileSystem, path: &Path, sst_id: usize) -> Result<()> {
+        FileObject::open(fs, &Self::path_of_sst_static(path, sst_id))
+            .and_then(|file| SsTable::open(sst_id, None, file))
+            .and_then(|sst| sst.verify_block_meta())
+            .with_context(|| format!("{:05}.sst is corrupted", sst_id))
+    }
+
+    /// Check every SST of `state`, failing with the errors of all the corrupted ones.
+    fn check_all_ssts(fs: &dyn FileSystem, path: &Path, state: &LsmStorageState) -> Result<()> {
+        let errors = state
+            .sst_ids()
+            .filter_map(|sst_id| Self::check_sst(fs, path, *sst_id).err())
+            .map(|e| format!("{:#}", e))
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            bail!(
+                "paranoid checks failed on {} SSTs:\n{}",
+                errors.len(),
+                errors.join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// The SSTs of every column family the manifest leads to.
+    fn replayed_sst_ids(
+        state: &LsmStorageState,
+        column_families: &BTreeMap<u32, ReplayedColumnFamily>,
+    ) -> BTreeSet<usize> {
+        std::iter::once(state)
+            .chain(column_families.values().map(|cf| &cf.state))
+            .flat_map(|state| state.sst_ids())
+            .copied()
+            .collect()
+    }
+
+    /// Move the SSTs of `latest` that are not in `recovered` to `lost`, so that the SSTs of the recovered state
+    /// are not mixed with the later ones. Returns what was dropped.
+    fn drop_inconsistent_ssts(
+        fs: &dyn FileSystem,
+        path: &Path,
+        latest: &BTreeSet<usize>,
+        recovered: &BTreeSet<usize>,
+        readable_ssts: &HashMap<usize, bool>,
+        read_only: bool,
+    ) -> Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+        for sst_id in latest.difference(recovered) {
+            if !readable_ssts.get(sst_id).copied().unwrap_or(false) {
+                report.unreadable_sst_ids.push(*sst_id);
+            }
+            report.dropped_sst_ids.push(*sst_id);
+            if !read_only && fs.exists(&Self::path_of_sst_static(path, *sst_id)) {
+                Self::move_to_lost(fs, path, &format!("{:05}.sst", sst_id))?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// The identity of the database, generated when it was created and kept in the manifest. SSTs record the
+    /// database that wrote them, so that tools can tell whether a file belongs to it.
+    pub fn db_id(&self) -> &str {
+        &self.db_id
+    }
+
+    /// The identity of this open of the database, recorded by the SSTs it writes along with `db_id`.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Whether the storage was opened with `open_read_only`, or as a secondary instance.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail if the storage was opened read-only, before anything is written.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!("{} is opened read-only", self.path.display());
+        }
+        Ok(())
+    }
+
+    /// What a best-effort recovery left out when opening, empty unless `best_effort_recovery` is set.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Make the running and future compactions fail before installing anything, used when closing.
+    pub(crate) fn cancel_compactions(&self) {
+        self.compactions_cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+        let mut compaction_filters = self.compaction_filters.lock();
+        compaction_filters.push(compaction_filter);
+    }
+
+    fn key_within(user_key: &[u8], table_begin: &[u8], table_end: &[u8]) -> bool {
+        table_begin <= user_key && user_key <= table_end
+    }
+
+    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        Ok(self.get_pinned(key)?.map(|value| value.to_bytes()))
+    }
+
+    /// Same as `get`, but a value read from an SST is returned as a slice of the cached block instead of being
+    /// copied out of it.
+    pub fn get_pinned(&self, key: &[u8]) -> Result<Option<PinnableSlice>> {
+        let started = Instant::now();
+        let value = self.lookup_pinned(key)?;
+        self.record_get(started, key, value.as_deref());
+        Ok(value)
+    }
+
+    /// Same as `get_pinned`, without counting the get in the statistics.
+    fn lookup_pinned(&self, key: &[u8]) -> Result<Option<PinnableSlice>> {
+        // observe the row cache epoch before the snapshot, see `RowCache::insert_if_current`
+        let row_cache_epoch = self.row_cache.as_ref().map(|cache| cache.epoch());
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        // read after the snapshot, so that it is at or above the watermark of every compaction installed in the
+        // snapshot: the versions it may have dropped were all shadowed at `read_ts`
+        let read_ts = self.mvcc().latest_commit_ts();
+        if let Some(value) = self.get_without_io(&snapshot, key, read_ts) {
+            return Ok(Some(PinnableSlice::from_bytes(value)).filter(|value| !value.is_empty()));
+        }
+
+        let started = Instant::now();
+        let value = self.get_from_ssts(&snapshot, key, read_ts)?;
+        self.record_sst_read_latency(started);
+        self.fill_row_cache(row_cache_epoch, key, &value);
+        Ok(value.filter(|value| !value.is_empty()))
+    }
+
+    /// Count a get of `key` that returned `value` in the statistics, and report it when slower than
+    /// `slow_read_threshold_micros`.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn record_get(&self, started: Instant, key: &[u8], value: Option<&[u8]>) {
+        let options = self.options();
+        let elapsed = started.elapsed();
+        #[cfg(feature = "tracing")]
+        if options.slow_read_threshold_micros > 0
+            && elapsed.as_micros() > options.slow_read_threshold_micros as u128
+        {
+            tracing::warn!(
+                key = %key.escape_ascii(),
+                found = value.is_some(),
+                elapsed_micros = elapsed.as_micros() as u64,
+                "slow get"
+            );
+        }
+        if let Some(statistics) = &options.statistics {
+            statistics.record_duration(Histogram::Get, elapsed);
+            if let Some(value) = value {
+                statistics.record_tick(Ticker::KeysRead, 1);
+                statistics.record_tick(Ticker::BytesRead, value.len() as u64);
+            }
+        }
+    }
+
+    /// Record the latency of a read that reached the SSTs, which the compactions may slow down.
+    fn record_sst_read_latency(&self, started: Instant) {
+        if let Some(rate_limiter) = &self.options().rate_limiter {
+            rate_limiter.record_read_latency(started.elapsed());
+        }
+    }
+
+    /// Same as `get`, but SST blocks missing from the block cache are read on tokio's blocking pool instead of on
+    /// the calling thread. Must be called within a tokio runtime.
+    pub async fn get_async(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let started = Instant::now();
+        let value = self.lookup_async(key).await?;
+        self.record_get(started, key, value.as_deref());
+        Ok(value)
+    }
+
+    /// Same as `get_async`, without counting the get in the statistics.
+    async fn lookup_async(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let row_cache_epoch = self.row_cache.as_ref().map(|cache| cache.epoch());
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        // see `get_pinned`
+        let read_ts = self.mvcc().latest_commit_ts();
+        if let Some(value) = self.get_without_io(&snapshot, key, read_ts) {
+            return Ok(Some(value).filter(|value| !value.is_empty()));
+        }
+
+        let started = Instant::now();
+        let timer = PerfTimer::start();
+        let mut value = None;
+        let seek_key = KeySlice::from_slice(key, read_ts);
+        for table in self.sst_candidates(&snapshot, key) {
+            let block_idx = table.find_block_idx(seek_key);
+            let mut iter = BlockIterator::create_and_seek_to_key(
+                table.read_block_cached_async(block_idx).await?,
+                seek_key,
+            );
+            if !iter.is_valid() && block_idx + 1 < table.num_of_blocks() {
+                iter = BlockIterator::create_and_seek_to_first(
+                    table.read_block_cached_async(block_idx + 1).await?,
+                );
+            }
+            if iter.is_valid() && iter.key().key_ref() == key {
+                let (block, range) = iter.value_in_block();
+                value = Some(table.read_entry_value_async(block, range).await?);
+                break;
+            }
+        }
+        timer.stop(|context| &mut context.get_from_output_files_time);
+        self.record_sst_read_latency(started);
+        self.fill_row_cache(row_cache_epoch, key, &value);
+        Ok(value
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_bytes()))
+    }
+
+    /// Get the version of a key visible at `read_ts`. Fails if the GC watermark has passed `read_ts`, as compaction
+    /// may have dropped the version that was visible back then.
+    pub fn get_at_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        let started = Instant::now();
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        // check after taking the snapshot, see `LsmMvccInner::advance_gc_watermark`
+        self.check_read_ts(read_ts)?;
+
+        let value = match Self::get_from_memtables(&snapshot, key, read_ts) {
+            Some(value) => Some(PinnableSlice::from_bytes(value)),
+            None => self.get_from_ssts(&snapshot, key, read_ts)?,
+        };
+        let value = value
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_bytes());
+        self.record_get(started, key, value.as_deref());
+        Ok(value)
+    }
+
+    /// Pin the current state for reads through `get_with_snapshot` and `scan_with_snapshot`. Compaction keeps the
+    /// versions the snapshot sees until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        self.mvcc().new_snapshot()
+    }
+
+    /// Get the version of a key visible to `snapshot`.
+    pub fn get_with_snapshot(&self, key: &[u8], snapshot: &Snapshot) -> Result<Option<Bytes>> {
+        self.get_at_ts(key, snapshot.read_ts())
+    }
+
+    fn check_read_ts(&self, read_ts: u64) -> Result<()> {
+        let gc_watermark = self.mvcc().gc_watermark();
+        if read_ts < gc_watermark {
+            bail!(
+                "read ts {} is below the GC watermark {}, older versions may have been garbage collected",
+                read_ts,
+                gc_watermark
+            );
+        }
+        Ok(())
+    }
+
+    /// Look up a key in the memtables and then in the row cache. Returns the empty value for a tombstone.
+    fn get_without_io(
+        &self,
+        snapshot: &LsmStorageState,
+        key: &[u8],
+        read_ts: u64,
+    ) -> Option<Bytes> {
+        Self::get_from_memtables(snapshot, key, read_ts)
+            .or_else(|| self.row_cache.as_ref().and_then(|cache| cache.get(key)))
+    }
+
+    /// Look up the version of a key visible at `read_ts` in the memtables, newest first.
+    pub(crate) fn get_from_memtables(
+        snapshot: &LsmStorageState,
+        key: &[u8],
+        read_ts: u64,
+    ) -> Option<Bytes> {
+        let timer = PerfTimer::start();
+        let mut probed = 0;
+        let value = std::iter::once(&snapshot.memtable)
+            .chain(&snapshot.imm_memtables)
+            .find_map(|memtable| {
+                probed += 1;
+                memtable.get_visible(key, read_ts)
+            });
+        perf_count(|context| context.get_from_memtable_count += probed);
+        timer.stop(|context| &mut context.get_from_memtable_time);
+        value
+    }
+
+    /// Look up the version of a key visible at `read_ts` in the SSTs, newest first.
+    pub(crate) fn get_from_ssts(
+        &self,
+        snapshot: &LsmStorageState,
+        key: &[u8],
+        read_ts: u64,
+    ) -> Result<Option<PinnableSlice>> {
+        let timer = PerfTimer::start();
+        let value = match self.find_in_ssts(snapshot, key, read_ts)? {
+            Some((table, block, range)) => Some(table.read_entry_value(block, range)?),
+            None => None,
+        };
+        timer.stop(|context| &mut context.get_from_output_files_time);
+        Ok(value)
+    }
+
+    /// Find the version of a key visible at `read_ts` in the SSTs, newest first.
+    pub(crate) fn find_in_ssts<'a>(
+        &self,
+        snapshot: &'a LsmStorageState,
+        key: &'a [u8],
+        read_ts: u64,
+    ) -> Result<Option<SstEntry<'a>>> {
+        let seek_key = KeySlice::from_slice(key, read_ts);
+        for table in self.sst_candidates(snapshot, key) {
+            let block_idx = table.find_block_idx(seek_key);
+            let mut iter = BlockIterator::create_and_seek_to_key(
+                table.read_block_cached(block_idx)?,
+                seek_key,
+            );
+            // the visible version may be the first entry of the next block
+            if !iter.is_valid() && block_idx + 1 < table.num_of_blocks() {
+                iter = BlockIterator::create_and_seek_to_first(
+                    table.read_block_cached(block_idx + 1)?,
+                );
+            }
+            if iter.is_valid() && iter.key().key_ref() == key {
+                let (block, range) = iter.value_in_block();
+                return Ok(Some((table, block, range)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// SSTs that may contain a key based on their key range and bloom filter, L0 first and then the levels.
+    fn sst_candidates<'a>(
+        &self,
+        snapshot: &'a LsmStorageState,
+        key: &'a [u8],
+    ) -> impl Iterator<Item = &'a Arc<SsTable>> + 'a {
+        let hash = key_hash(key);
+        let statistics = self.options().statistics.clone();
+        snapshot
+            .l0_sstables
+            .iter()
+            .chain(
+                snapshot
+                    .levels
+                    .iter()
+                    .flat_map(|(_, level_sst_ids)| level_sst_ids.iter()),
+            )
+            .map(|sst_id| &snapshot.sstables[sst_id])
+            .filter(move |table| {
+                if !Self::key_within(key, table.first_key().key_ref(), table.last_key().key_ref()) {
+                    return false;
+                }
+                let may_contain = table.may_contain(hash);
+                perf_count(|context| {
+                    if may_contain {
+                        context.bloom_sst_hit_count += 1;
+                    } else {
+                        context.bloom_sst_miss_count += 1;
+                    }
+                });
+                if let Some(statistics) = &statistics {
+                    let ticker = if may_contain {
+                        Ticker::BloomFilterPositive
+                    } else {
+                        Ticker::BloomFilterUseful
+                    };
+                    statistics.record_tick(ticker, 1);
+                }
+                may_contain
+            })
+    }
+
+    /// Cache a value read from the SSTs. This is the only place a pinned value gets copied.
+    fn fill_row_cache(&self, epoch: Option<u64>, key: &[u8], value: &Option<PinnableSlice>) {
+        if let (Some(row_cache), Some(epoch), Some(value)) = (&self.row_cache, epoch, value) {
+            row_cache.insert_if_current(epoch, key, value.to_bytes());
+        }
+    }
+
+    /// Write a batch of data into the storage at a single commit ts, so that readers see all of it or none.
+    pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
+        self.write_batch_with_new_ts(batch, None).map(|_| ())
+    }
+
+    /// Put a key-value pair into the storage by writing into the current memtable.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Put(key, value)])
+    }
+
+    /// Remove a key from the storage by writing an empty value.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Del(key)])
+    }
+
+    /// Write a batch at the next commit ts, which becomes visible to readers once the whole batch is written.
+    /// Returns the commit ts. The commit of the prepared transaction `txn_name` is synced to the WAL.
+    fn write_batch_with_new_ts<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        txn_name: Option<&str>,
+    ) -> Result<u64> {
+        self.write_cf_batches_with_new_ts(&[(DEFAULT_COLUMN_FAMILY_ID, batch)], txn_name)
+    }
+
+    /// Same as `write_batch_with_new_ts`, with a batch for each column family. Writes beyond the default column
+    /// family are logged as a single record of the default WAL, recovered all together or not at all.
+    pub(crate) fn write_cf_batches_with_new_ts<T: AsRef<[u8]>>(
+        &self,
+        batches: &[(u32, &[WriteBatchRecord<T>])],
+        txn_name: Option<&str>,
+    ) -> Result<u64> {
+        self.write_cf_batches(batches, txn_name, None)
+            .map(|ts| ts.unwrap())
+    }
+
+    /// Write the batches at the next commit ts, or at `replicated_ts` when given. Returns the commit ts, or `None`
+    /// without writing anything if the replicated ts is not after the latest commit ts, the batch being applied
+    /// already.
+    pub(crate) fn write_cf_batches<T: AsRef<[u8]>>(
+        &self,
+        batches: &[(u32, &[WriteBatchRecord<T>])],
+        txn_name: Option<&str>,
+        replicated_ts: Option<u64>,
+    ) -> Result<Option<u64>> {
+        self.check_writable()?;
+        self.wait_for_write_stall();
+        let started = Instant::now();
+        let default_only = matches!(batches, [(DEFAULT_COLUMN_FAMILY_ID, _)]);
+        if txn_name.is_some() && !default_only {
+            bail!("transactions only write to the default column family");
+        }
+        let timer = PerfTimer::start();
+        let _write_lock = self.mvcc().write_lock.lock();
+        timer.stop(|context| &mut context.write_thread_wait_time);
+        // before locking the state, which flushes lock after the state lock
+        let ts = match replicated_ts {
+            None => self.next_commit_ts()?,
+            Some(ts) if ts <= self.mvcc().latest_commit_ts() => return Ok(None),
+            Some(ts) => {
+                self.ts_oracle.advance_to(ts, |bound| {
+                    self.add_manifest_record(
+                        &self.state_lock.lock(),
+                        ManifestRecord::CommitTsBound(bound),
+                    )
+                })?;
+                ts
+            }
+        };
+        let state = self.state.read();
+        let column_families = self.column_families.read();
+        let options = self.options();
+        let mut memtables = Vec::with_capacity(batches.len());
+        // the sizes the memtables are frozen at
+        let mut write_buffer_sizes = Vec::with_capacity(batches.len());
+        for (cf_id, _) in batches {
+            let (memtable, write_buffer_size) = if *cf_id == DEFAULT_COLUMN_FAMILY_ID {
+                (&state.memtable, options.target_sst_size)
+            } else if let Some(cf) = column_families.get(cf_id) {
+                (&cf.state.memtable, cf.options.write_buffer_size(&options))
+            } else {
+                bail!("column family {} was dropped", cf_id);
+            };
+            memtables.push(memtable);
+            write_buffer_sizes.push(write_buffer_size);
+        }
+        let mut batch_size = 0;
+        let mut bytes_written = 0;
+        let mut entries = Vec::with_capacity(batches.len());
+        for ((cf_id, batch), memtable) in batches.iter().zip(&memtables) {
+            let mut cf_batch_size = 0;
+            let mut cf_entries = Vec::with_capacity(batch.len());
+            for record in *batch {
+                let (key, value) = match record {
+                    WriteBatchRecord::Put(key, value) => (key.as_ref(), value.as_ref()),
+                    WriteBatchRecord::Del(key) => (key.as_ref(), &[][..]),
+                };
+                // the row cache only holds the default column family
+                if let (DEFAULT_COLUMN_FAMILY_ID, Some(row_cache)) = (*cf_id, &self.row_cache) {
+                    row_cache.invalidate(key);
+                }
+                cf_batch_size += key.len() + std::mem::size_of::<u64>() + value.len();
+                bytes_written += key.len() + value.len();
+                cf_entries.push((KeySlice::from_slice(key, ts), value));
+            }
+            memtable.set_approximate_size(memtable.approximate_size() + cf_batch_size);
+            batch_size += cf_batch_size;
+            entries.push(cf_entries);
+        }
+        if default_only {
+            state.memtable.put_batch(&entries[0], txn_name)?;
+        } else {
+            let timer = PerfTimer::start();
+            if let Some(wal) = state.memtable.wal() {
+                let wal_entries = batches
+                    .iter()
+                    .zip(&entries)
+                    .flat_map(|((cf_id, _), cf_entries)| {
+                        cf_entries.iter().map(|(key, value)| (*cf_id, *key, *value))
+                    })
+                    .collect::<Vec<_>>();
+                wal.put_column_family_batch(&wal_entries)?;
+            }
+            timer.stop(|context| &mut context.write_wal_time);
+            let timer = PerfTimer::start();
+            for (memtable, cf_entries) in memtables.iter().zip(&entries) {
+                memtable.insert_batch(cf_entries);
+            }
+            timer.stop(|context| &mut context.write_memtable_time);
+        }
+        if txn_name.is_some() {
+            self.sync_wal(&state.memtable)?;
+        }
+        self.mvcc().update_commit_ts(ts);
+        // still with the write lock, for the sinks and the change streams to receive the batches in commit order
+        self.publish_changes(ts, batches);
+        if !options.replication_sinks.is_empty() {
+            let batch = ReplicatedBatch::new(ts, batches);
+            for sink in &options.replication_sinks {
+                sink.on_batch_committed(&batch);
+            }
+        }
+        if let Some(statistics) = &options.statistics {
+            let num_keys = batches.iter().map(|(_, batch)| batch.len()).sum::<usize>();
+            statistics.record_tick(Ticker::KeysWritten, num_keys as u64);
+            statistics.record_tick(Ticker::BytesWritten, bytes_written as u64);
+            statistics.record_elapsed(Histogram::Write, started);
+        }
+        let write_buffer_full = options
+            .write_buffer_manager
+            .as_ref()
+            .is_some_and(|manager| {
+                manager.reserve(batch_size);
+                // memtables already frozen free memory once flushed, freezing more would only leave tiny SSTs
+                manager.should_flush() && state.imm_memtables.is_empty()
+            });
+
+        if write_buffer_full
+            || memtables
+                .iter()
+                .zip(&write_buffer_sizes)
+                .any(|(memtable, size)| memtable.approximate_size() >= *size)
+        {
+            drop(column_families);
+            drop(state);
+            // a flush holds the state lock while writing its SST
+            let stall_started = Instant::now();
+            let state_lock = self.state_lock.lock();
+            if let Some(statistics) = &options.statistics {
+                let stall = u64::try_from(stall_started.elapsed().as_micros()).unwrap_or(u64::MAX);
+                statistics.record_tick(Ticker::StallMicros, stall);
+            }
+            self.force_freeze_memtable(&state_lock)?;
+            if options.inline_background_jobs {
+                drop(state_lock);
+                drop(_write_lock);
+                self.run_background_jobs_inline();
+            }
+        }
+        Ok(Some(ts))
+    }
+
+    /// The commit ts of the next write, recording a new bound of the timestamps when needed. Must be called with
+    /// the write lock.
+    pub(crate) fn next_commit_ts(&self) -> Result<u64> {
+        self.ts_oracle
+            .next_ts(self.mvcc().latest_commit_ts(), |bound| {
+                self.add_manifest_record(
+                    &self.state_lock.lock(),
+                    ManifestRecord::CommitTsBound(bound),
+                )
+            })
+    }
+
+    pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.sst", id))
+    }
+
+    pub(crate) fn path_of_sst(&self, id: usize) -> PathBuf {
+        Self::path_of_sst_static(&self.path, id)
+    }
+
+    pub(crate) fn path_of_blob_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.blob", id))
+    }
+
+    pub(crate) fn path_of_blob(&self, id: usize) -> PathBuf {
+        Self::path_of_blob_static(&self.path, id)
+    }
+
+    pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.wal", id))
+    }
+
+    /// The options in effect, changed by `set_options`.
+    pub(crate) fn options(&self) -> Guard<Arc<LsmStorageOptions>> {
+        self.options.load()
+    }
+
+    pub(crate) fn compaction_controller(&self) -> Guard<Arc<CompactionController>> {
+        self.compaction_controller.load()
+    }
+
+    /// Change the options named in `changes` to the values given as strings, all or none of them, without
+    /// reopening the storage. Besides the mutable `LsmStorageOptions`, `block_cache_capacity` resizes the block
+    /// cache and `rate_limiter_bytes_per_sec` sets the rate of the rate limiter. The new options are persisted to
+    /// the `OPTIONS` file.
+    pub fn set_options(&self, changes: &[(&str, &str)]) -> Result<()> {
+        self.check_writable()?;
+        // the state lock orders the changes, and their writes to the OPTIONS file
+        let _state_lock = self.state_lock.lock();
+        let mut options = LsmStorageOptions::clone(&self.options());
+        let mut block_cache_capacity = None;
+        let mut rate_limiter_bytes_per_sec = None;
+        for (name, value) in changes {
+            match *name {
+                "block_cache_capacity" => {
+                    block_cache_capacity = Some(
+                        value
+                            .parse::<u64>()
+                            .with_context(|| format!("invalid value {:?} for {}", value, name))?,
+                    )
+                }
+                "rate_limiter_bytes_per_sec" => {
+                    let Some(rate_limiter) = &options.rate_limiter else {
+                        bail!("the storage has no rate limiter");
+                    };
+                    if rate_limiter.is_auto_tuned() {
+                        bail!("the rate of an auto-tuned rate limiter cannot be set");
+                    }
+                    rate_limiter_bytes_per_sec = Some(
+                        value
+                            .parse::<u64>()
+                            .with_context(|| format!("invalid value {:?} for {}", value, name))?,
+                    )
+                }
+                _ => options.set_option(name, value)?,
+            }
+        }
+        options.validate()?;
+        options.persist(self.fs.as_ref(), &self.path)?;
+
+        if let Some(capacity) = block_cache_capacity {
+            self.block_cache.set_capacity(capacity);
+        }
+        if let Some(bytes_per_sec) = rate_limiter_bytes_per_sec {
+            options
+                .rate_limiter
+                .as_ref()
+                .unwrap()
+                .set_bytes_per_sec(bytes_per_sec)?;
+        }
+        self.compaction_controller
+            .store(Arc::new(CompactionController::new(&options)));
+        self.options.store(Arc::new(options));
+        for (name, value) in changes {
+            db_log!(self.options(), Info, "set option {} to {}", name, value);
+        }
+        self.update_write_stall();
+        Ok(())
     }
 
     pub(crate) fn path_of_wal(&self, id: usize) -> PathBuf {
@@ -379,22 +2513,166 @@ impl LsmStorageInner {
     }
 
     pub(super) fn sync_dir(&self) -> Result<()> {
-        unimplemented!()
+        self.fs.sync_dir(&self.path)?;
+        Ok(())
+    }
+
+    /// Force freeze the current memtable to an immutable memtable, along with the ones of the other column families
+    /// that were written to
+    pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
+        self.check_writable()?;
+        let memtable_id = self.next_sst_id();
+        let memtable = if self.options().enable_wal {
+            MemTable::create_with_wal(memtable_id, self.fs.as_ref(), self.path_of_wal(memtable_id))?
+        } else {
+            MemTable::create(memtable_id)
+        };
+        let frozen_memtable;
+        {
+            let mut state = self.state.write();
+            let mut temp = state.as_ref().clone();
+            frozen_memtable = state.memtable.clone();
+            temp.imm_memtables.insert(0, frozen_memtable.clone());
+            temp.memtable = Arc::new(memtable);
+            *state = Arc::new(temp);
+            for cf in self.column_families.write().values_mut() {
+                let mut temp = cf.state.as_ref().clone();
+                if !temp.memtable.is_empty() {
+                    temp.imm_memtables.insert(0, temp.memtable.clone());
+                }
+                temp.memtable = Arc::new(MemTable::create(memtable_id));
+                cf.state = Arc::new(temp);
+            }
+        }
+        self.sync_wal(&frozen_memtable)?;
+        self.add_manifest_record(
+            state_lock_observer,
+            ManifestRecord::NewMemtable(memtable_id),
+        )?;
+        Ok(())
+    }
+
+    /// Record a change of the LSM structure in the manifest, and roll the manifest over once it is larger than
+    /// `max_manifest_file_size`.
+    pub(crate) fn add_manifest_record(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        record: ManifestRecord,
+    ) -> Result<()> {
+        self.add_manifest_records(state_lock_observer, &[record])
+    }
+
+    /// Record the change adding `new_ssts` in the manifest, after the checksums of their files.
+    pub(crate) fn add_manifest_record_with_ssts(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        record: ManifestRecord,
+        new_ssts: &[Arc<SsTable>],
+    ) -> Result<()> {
+        self.add_manifest_records_with_ssts(state_lock_observer, vec![record], new_ssts)
+    }
+
+    /// Same as `add_manifest_record_with_ssts`, for changes recorded together.
+    pub(crate) fn add_manifest_records_with_ssts(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        records: Vec<ManifestRecord>,
+        new_ssts: &[Arc<SsTable>],
+    ) -> Result<()> {
+        let file_checksums = new_ssts
+            .iter()
+            .filter_map(|sst| Some((sst.sst_id(), sst.file_checksum()?)))
+            .collect::<Vec<_>>();
+        if file_checksums.is_empty() {
+            return self.add_manifest_records(state_lock_observer, &records);
+        }
+        let records = std::iter::once(ManifestRecord::FileChecksums(file_checksums))
+            .chain(records)
+            .collect::<Vec<_>>();
+        self.add_manifest_records(state_lock_observer, &records)
     }
 
-    /// Force freeze the current memtable to an immutable memtable
-    pub fn force_freeze_memtable(&self, _state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
-        let mut state = self.state.write();
-        let mut temp = state.as_ref().clone();
-        temp.imm_memtables.insert(0, state.memtable.clone());
-        temp.memtable = Arc::new(MemTable::create(self.next_sst_id()));
-        *state = Arc::new(temp);
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "manifest_write",
+            level = "debug",
+            skip_all,
+            fields(num_records = records.len()),
+            err
+        )
+    )]
+    fn add_manifest_records(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        records: &[ManifestRecord],
+    ) -> Result<()> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        manifest.add_records(state_lock_observer, records)?;
+        if manifest.size()? > self.options().max_manifest_file_size && self.file_deletions_enabled()
+        {
+            // the state lock is held, so the state includes every change recorded so far
+            manifest.rollover(state_lock_observer, &self.manifest_snapshot())?;
+            db_log!(
+                self.options(),
+                Info,
+                "manifest rolled over to {}, {} bytes",
+                manifest.path().display(),
+                manifest.size()?
+            );
+            #[cfg(feature = "tracing")]
+            tracing::info!(path = %manifest.path().display(), "rolled the manifest over");
+        }
         Ok(())
     }
 
-    /// Force flush the earliest-created immutable memtable to disk
+    /// The records a rolled over manifest starts with: the snapshot of the default column family, followed by
+    /// the ones of the others.
+    pub(crate) fn manifest_snapshot(&self) -> Vec<ManifestRecord> {
+        let snapshot = self.state.read().clone();
+        let column_families = self.column_families.read().clone();
+        let oldest_memtable_id =
+            |state: &LsmStorageState| state.imm_memtables.last().unwrap_or(&state.memtable).id();
+        let mut records = vec![ManifestRecord::Snapshot {
+            l0_sstables: snapshot.l0_sstables.clone(),
+            levels: snapshot.levels.clone(),
+            next_sst_id: self.next_sst_id.load(Ordering::SeqCst),
+            db_id: self.db_id.clone(),
+            file_checksums: std::iter::once(&snapshot)
+                .chain(column_families.values().map(|cf| &cf.state))
+                .flat_map(|state| &state.sstables)
+                .filter_map(|(sst_id, sst)| Some((*sst_id, sst.file_checksum()?)))
+                .collect(),
+            oldest_memtable_id: oldest_memtable_id(&snapshot),
+            commit_ts_bound: self.ts_oracle.bound(),
+            next_column_family_id: self.next_column_family_id.load(Ordering::SeqCst),
+        }];
+        for (id, cf) in column_families {
+            records.push(ManifestRecord::ColumnFamilySnapshot {
+                id,
+                name: cf.name,
+                l0_sstables: cf.state.l0_sstables.clone(),
+                levels: cf.state.levels.clone(),
+                oldest_memtable_id: oldest_memtable_id(&cf.state),
+                options: cf.options,
+            });
+        }
+        records
+    }
+
+    /// Force flush the earliest-created immutable memtable to disk, along with the ones of the other column
+    /// families frozen with it
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "flush", skip_all, err)
+    )]
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
-        let _state_lock = self.state_lock.lock();
+        self.check_writable()?;
+        let state_lock = self.state_lock.lock();
+        let started = self.clock.instant();
+        let listeners = &self.options().event_listeners;
 
         //플러시할 memtable 찾기
         let flush_memtable;
@@ -406,16 +2684,37 @@ impl LsmStorageInner {
                 .expect("no imm memtables!")
                 .clone();
         }
-
-        //1.4의 SsTableBuilder를 이용하여 SSTable 만들기
-        let mut builder = SsTableBuilder::new(self.options.block_size);
-        flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+        let cf_memtables = self
+            .column_families
+            .read()
+            .iter()
+            .filter_map(|(cf_id, cf)| {
+                let memtable = cf.state.imm_memtables.last()?;
+                (memtable.id() == sst_id).then(|| (*cf_id, memtable.clone()))
+            })
+            .collect::<Vec<_>>();
+        // the default memtable is frozen along with the other column families even when it was not written to, it
+        // leaves no SST then, nor when it is frozen empty on its own, e.g. by two racing `force_flush`. The event
+        // listeners only hear of the flushes of the default column family.
+        let flush_default = !flush_memtable.is_empty();
+        if flush_default {
+            for listener in listeners {
+                listener.on_flush_begin(flush_memtable.id());
+            }
+        }
+
+        let sst = if flush_default {
+            Some(self.build_flushed_sst(&flush_memtable, sst_id, &self.options())?)
+        } else {
+            None
+        };
+        let mut cf_ssts = Vec::with_capacity(cf_memtables.len());
+        for (cf_id, memtable) in cf_memtables {
+            let options = self.column_family_options(cf_id);
+            let sst = self.build_flushed_sst(&memtable, self.next_sst_id(), &options)?;
+            cf_ssts.push((cf_id, memtable, sst));
+        }
 
         // L0 테이블에 추가
         {
@@ -424,17 +2723,171 @@ impl LsmStorageInner {
             // memtable 목록에서 제거
             let mem = snapshot.imm_memtables.pop().unwrap();
             assert_eq!(mem.id(), sst_id);
-            // L0 테이블에 추가
-            snapshot.l0_sstables.insert(0, sst_id);
-            println!("flushed {}.sst with size={}", sst_id, sst.table_size());
-            snapshot.sstables.insert(sst_id, sst);
+            if let Some(sst) = &sst {
+                // L0 테이블에 추가, tiered compaction은 새 tier로 추가
+                if self.compaction_controller().flush_to_l0() {
+                    snapshot.l0_sstables.insert(0, sst_id);
+                } else {
+                    snapshot.levels.insert(0, (sst_id, vec![sst_id]));
+                }
+                db_log!(
+                    self.options(),
+                    Info,
+                    "flushed {}.sst with size={}",
+                    sst_id,
+                    sst.table_size()
+                );
+                #[cfg(feature = "tracing")]
+                tracing::info!(sst_id, size = sst.table_size(), "flushed");
+                self.compaction_stats.record(0, |counters| {
+                    counters.bytes_read_from_upper_level +=
+                        flush_memtable.approximate_size() as u64;
+                    counters.bytes_written += sst.table_size();
+                    counters.compaction_time += self.clock.instant().duration_since(started);
+                });
+                snapshot.sstables.insert(sst_id, sst.clone());
+            }
+            // entries cached while the memtable masked them go stale as soon as the readers stop seeing it, even if
+            // recording the flush fails afterwards
+            if let Some(row_cache) = &self.row_cache {
+                row_cache.invalidate_flushed(&flush_memtable);
+            }
             *guard = Arc::new(snapshot);
+
+            // the column families cannot be dropped while the state lock is held
+            let mut column_families = self.column_families.write();
+            for (cf_id, memtable, sst) in &cf_ssts {
+                let cf = column_families.get_mut(cf_id).unwrap();
+                let mut snapshot = cf.state.as_ref().clone();
+                snapshot.imm_memtables.pop();
+                let own_controller = cf.options.compaction_controller(&self.options());
+                let flush_to_l0 = match &own_controller {
+                    Some(controller) => controller.flush_to_l0(),
+                    None => self.compaction_controller().flush_to_l0(),
+                };
+                if flush_to_l0 {
+                    snapshot.l0_sstables.insert(0, sst.sst_id());
+                } else {
+                    snapshot
+                        .levels
+                        .insert(0, (sst.sst_id(), vec![sst.sst_id()]));
+                }
+                db_log!(
+                    self.options(),
+                    Info,
+                    "flushed {}.sst of column family {} with size={}",
+                    sst.sst_id(),
+                    cf.name,
+                    sst.table_size()
+                );
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    sst_id = sst.sst_id(),
+                    column_family = %cf.name,
+                    size = sst.table_size(),
+                    "flushed"
+                );
+                self.compaction_stats.record(0, |counters| {
+                    counters.bytes_read_from_upper_level += memtable.approximate_size() as u64;
+                    counters.bytes_written += sst.table_size();
+                });
+                snapshot.sstables.insert(sst.sst_id(), sst.clone());
+                cf.state = Arc::new(snapshot);
+            }
+        }
+
+        if self.manifest.is_some() {
+            self.sync_dir()?;
+            let mut records = cf_ssts
+                .iter()
+                .map(|(cf_id, memtable, sst)| ManifestRecord::ColumnFamilyFlush {
+                    cf_id: *cf_id,
+                    memtable_id: memtable.id(),
+                    sst_id: sst.sst_id(),
+                })
+                .collect::<Vec<_>>();
+            // recorded last, since the WAL is not recovered once the default memtable is flushed. Without an SST,
+            // the WAL holds nothing to recover anymore.
+            if sst.is_some() {
+                records.push(ManifestRecord::Flush(sst_id));
+            }
+            let new_ssts = sst
+                .iter()
+                .cloned()
+                .chain(cf_ssts.iter().map(|(_, _, sst)| sst.clone()))
+                .collect::<Vec<_>>();
+            self.add_manifest_records_with_ssts(&state_lock, records, &new_ssts)?;
+            self.delete_obsolete_wals()?;
+        }
+
+        if let Some(statistics) = &self.options().statistics {
+            let bytes_written = sst
+                .iter()
+                .chain(cf_ssts.iter().map(|(_, _, sst)| sst))
+                .map(|sst| sst.table_size())
+                .sum();
+            statistics.record_tick(Ticker::FlushWriteBytes, bytes_written);
         }
 
+        if let Some(manager) = &self.options().write_buffer_manager {
+            manager.free(flush_memtable.approximate_size());
+            for (_, memtable, _) in &cf_ssts {
+                manager.free(memtable.approximate_size());
+            }
+        }
+
+        if let Some(sst) = &sst {
+            let info = FlushJobInfo {
+                sst_id,
+                memtable_size: flush_memtable.approximate_size() as u64,
+                sst_size: sst.table_size(),
+                num_entries: sst.properties().num_entries,
+                flush_time: self.clock.instant().duration_since(started),
+            };
+            for listener in listeners {
+                listener.on_flush_completed(&info);
+            }
+        }
+        self.update_write_stall();
+
         Ok(())
     }
 
-    fn range_overlap(
+    /// Write a memtable to the SST `sst_id`, with the options `options` of its column family.
+    fn build_flushed_sst(
+        &self,
+        memtable: &MemTable,
+        sst_id: usize,
+        options: &LsmStorageOptions,
+    ) -> Result<Arc<SsTable>> {
+        //1.4의 SsTableBuilder를 이용하여 SSTable 만들기
+        let mut builder = SsTableBuilder::new(options.block_size)
+            .with_compression(options.compression_of_level(0))
+            .with_db_identity(&self.db_id, &self.session_id)
+            .with_file_system(self.fs.clone())
+            .with_clock(self.clock.clone());
+        if options.min_blob_size > 0 {
+            builder = builder.with_blob_indexes();
+            self.flush_to_blob_file(memtable, &mut builder, options.min_blob_size)?;
+        } else {
+            memtable.flush(&mut builder)?;
+        }
+        let sst = builder
+            .build(
+                sst_id,
+                Some(self.block_cache.clone()),
+                self.path_of_sst(sst_id),
+            )?
+            .with_metadata_caching(options.metadata_caching)
+            .with_statistics(options.statistics.clone());
+        Ok(Arc::new(Self::open_blob_files(
+            self.fs.as_ref(),
+            &self.path,
+            sst,
+        )?))
+    }
+
+    pub(crate) fn range_overlap(
         user_begin: Bound<&[u8]>,
         user_end: Bound<&[u8]>,
         table_begin: &[u8],
@@ -461,28 +2914,296 @@ impl LsmStorageInner {
         true
     }
 
-    pub fn new_txn(&self) -> Result<()> {
-        // no-op
-        Ok(())
+    /// Start a transaction reading the latest committed state, serializable if `options.serializable`.
+    pub fn new_txn(self: &Arc<Self>) -> Result<Arc<Transaction>> {
+        Ok(self
+            .mvcc()
+            .new_txn(self.clone(), self.options().serializable))
+    }
+
+    /// The transactions recovered from the WALs in the prepared state and not committed or rolled back since, to
+    /// be resolved by the caller.
+    pub fn recovered_prepared_txns(self: &Arc<Self>) -> Vec<Arc<Transaction>> {
+        let prepared_txns = self.mvcc().prepared_txns.lock();
+        let mut recovered = self.recovered_prepared_txns.lock();
+        recovered.retain(|name, _| prepared_txns.contains_key(name));
+        recovered
+            .iter()
+            .map(|(name, writes)| {
+                Arc::new(Transaction::new_prepared(
+                    self.clone(),
+                    self.mvcc().new_txn_snapshot(),
+                    name.clone(),
+                    writes.clone(),
+                ))
+            })
+            .collect()
     }
 
     /// Create an iterator over a range of keys.
     pub fn scan(
         &self,
-        _lower: Bound<&[u8]>,
-        _upper: Bound<&[u8]>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_with_ts(lower, upper, None)
+    }
+
+    /// Create an iterator over the versions of a range of keys visible at `read_ts`. Fails if the GC watermark has
+    /// passed `read_ts`.
+    pub fn scan_at_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let iter = self.scan_with_ts(lower, upper, Some(read_ts))?;
+        // the iterator holds its own snapshot, see `LsmMvccInner::advance_gc_watermark`
+        self.check_read_ts(read_ts)?;
+        Ok(iter)
+    }
+
+    /// Create an iterator over a range of keys as `snapshot` sees them.
+    pub fn scan_with_snapshot(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_at_ts(lower, upper, snapshot.read_ts())
+    }
+
+    /// Create an iterator over the versions of a range of keys visible at `read_ts`, the latest commit ts if
+    /// `None`.
+    pub(crate) fn scan_with_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: Option<u64>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        // see `get_pinned`
+        let read_ts = read_ts.unwrap_or_else(|| self.mvcc().latest_commit_ts());
+        Self::scan_state(&snapshot, lower, upper, read_ts)
+    }
+
+    /// Scan the versions visible at `read_ts` of the LSM tree `snapshot`.
+    pub(crate) fn scan_state(
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let timer = PerfTimer::start();
+        let iter = Self::scan_state_versions(snapshot, lower, upper)?;
+        let iter = LsmIterator::new(iter, map_bound(upper), read_ts)?;
+        timer.stop(|context| &mut context.iter_seek_time);
+        Ok(FusedIterator::new(iter))
+    }
+
+    /// Every version of the LSM tree `snapshot` from `lower`, newest first for each key, including the deletions.
+    /// It goes past `upper`, which only skips the SSTs after it.
+    pub(crate) fn scan_state_versions(
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<LsmIteratorInner> {
+        let (mem_lower, mem_upper) = map_user_key_range(lower, upper);
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
+        memtable_iters.push(Box::new(snapshot.memtable.scan(mem_lower, mem_upper)));
+        for imm_t in snapshot.imm_memtables.iter() {
+            memtable_iters.push(Box::new(imm_t.scan(mem_lower, mem_upper)));
+        }
+        let memtable_iter = MergeIterator::create(memtable_iters);
+
+        let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for sst_id in snapshot.l0_sstables.iter() {
+            let table = snapshot.sstables[sst_id].clone();
+            if Self::range_overlap(
+                lower,
+                upper,
+                table.first_key().key_ref(),
+                table.last_key().key_ref(),
+            ) {
+                let iter = match lower {
+                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
+                        table,
+                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    )?,
+                    Bound::Excluded(key) => {
+                        let mut iter = SsTableIterator::create_and_seek_to_key(
+                            table,
+                            KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        )?;
+                        while iter.is_valid() && iter.key().key_ref() == key {
+                            iter.next()?;
+                        }
+                        iter
+                    }
+                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
+                };
+                l0_iters.push(Box::new(iter));
+            }
+        }
+        let l0_iter = MergeIterator::create(l0_iters);
+
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        for (_, level_sst_ids) in &snapshot.levels {
+            let level_ssts = level_sst_ids
+                .iter()
+                .map(|sst_id| snapshot.sstables[sst_id].clone())
+                .filter(|table| {
+                    Self::range_overlap(
+                        lower,
+                        upper,
+                        table.first_key().key_ref(),
+                        table.last_key().key_ref(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let level_iter = match lower {
+                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key(
+                    level_ssts,
+                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                )?,
+                Bound::Excluded(key) => {
+                    let mut iter = SstConcatIterator::create_and_seek_to_key(
+                        level_ssts,
+                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    )?;
+                    while iter.is_valid() && iter.key().key_ref() == key {
+                        iter.next()?;
+                    }
+                    iter
+                }
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
+            };
+            level_iters.push(Box::new(level_iter));
+        }
+
+        let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
+        TwoMergeIterator::create(iter, MergeIterator::create(level_iters))
+    }
+}
+
+impl TxnStorage for LsmStorageInner {
+    fn mvcc(&self) -> &LsmMvccInner {
+        LsmStorageInner::mvcc(self)
+    }
+
+    fn get_at_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        LsmStorageInner::get_at_ts(self, key, read_ts)
+    }
+
+    fn scan_at_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
+        LsmStorageInner::scan_at_ts(self, lower, upper, read_ts)
+    }
+
+    fn write_batch_at_new_ts(&self, batch: &[WriteBatchRecord<Bytes>]) -> Result<u64> {
+        self.write_batch_with_new_ts(batch, None)
+    }
+
+    fn prepare_txn(
+        &self,
+        name: &str,
+        writes: &[(Bytes, Bytes)],
+        write_key_hashes: HashSet<u32>,
+        read_key_hashes: HashSet<u32>,
+        lease: Option<TxnLease>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        // the memtable cannot be frozen, and its WAL deleted, before the transaction is registered
         let state = self.state.read();
-        let mem_iter = state.memtable.scan(_lower, _upper);
-        let mut iters = vec![Box::new(mem_iter)];
+        let Some(wal) = state.memtable.wal() else {
+            bail!("preparing a transaction requires enable_wal");
+        };
+        wal.put_prepare(
+            name,
+            writes,
+            &read_key_hashes.iter().copied().collect::<Vec<_>>(),
+        )?;
+        wal.sync()?;
+        self.mvcc().prepared_txns.lock().insert(
+            name.to_string(),
+            PreparedTxnData {
+                write_key_hashes,
+                read_key_hashes,
+                wal_id: state.memtable.id(),
+                lease,
+            },
+        );
+        Ok(())
+    }
 
-        for imm_t in state.imm_memtables.iter() {
-            let imm_iter = imm_t.scan(_lower, _upper);
-            iters.push(Box::new(imm_iter));
+    fn commit_prepared_txn(&self, name: &str, batch: &[WriteBatchRecord<Bytes>]) -> Result<u64> {
+        let commit_ts = self.write_batch_with_new_ts(batch, Some(name))?;
+        self.resolve_prepared_txn(name)?;
+        Ok(commit_ts)
+    }
+
+    fn txn_spill_threshold(&self) -> usize {
+        self.options().txn_spill_threshold
+    }
+
+    fn txn_conflict_granularity(&self) -> ConflictGranularity {
+        self.options().txn_conflict_granularity
+    }
+
+    fn txn_expiration(&self) -> Option<Duration> {
+        (self.options().txn_expiration_millis > 0)
+            .then(|| Duration::from_millis(self.options().txn_expiration_millis))
+    }
+
+    fn txn_lock_timeout(&self) -> Duration {
+        Duration::from_millis(self.options().txn_lock_timeout_millis)
+    }
+
+    fn spill_txn_writes(&self, writes: &SkipMap<Bytes, Bytes>) -> Result<Box<dyn SpilledRun>> {
+        // a secondary instance spills to its own directory
+        let dir = match &self.secondary_path {
+            Some(secondary_path) => secondary_path,
+            None => {
+                self.check_writable()?;
+                &self.path
+            }
+        };
+        let id = self.next_sst_id();
+        Ok(Box::new(SpilledTxnWrites::create(
+            self.fs.clone(),
+            id,
+            self.options().block_size,
+            dir.join(format!("{:05}.txn", id)),
+            writes,
+            DbLogger::new(&self.options()),
+        )?))
+    }
+
+    fn rollback_prepared_txn(&self, name: &str) -> Result<()> {
+        {
+            let state = self.state.read();
+            if let Some(wal) = state.memtable.wal() {
+                wal.put_rollback(name)?;
+                wal.sync()?;
+            }
         }
+        self.resolve_prepared_txn(name)
+    }
+}
 
-        let marge_iter = MergeIterator::create(iters);
-        let lsm_iter = LsmIterator::new(marge_iter);
-        Ok(FusedIterator::new(lsm_iter.unwrap()))
+impl LsmStorageInner {
+    fn resolve_prepared_txn(&self, name: &str) -> Result<()> {
+        self.mvcc().prepared_txns.lock().remove(name);
+        self.mvcc().prepared_txn_resolved.notify_all();
+        self.recovered_prepared_txns.lock().remove(name);
+        let _state_lock = self.state_lock.lock();
+        self.delete_obsolete_wals()
     }
 }
@@ -1,17 +1,35 @@
-#![allow(dead_code)] // REMOVE THIS LINE after fully implementing this functionality
-
-use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
+use crate::column_family::ColumnFamilyOptions;
 use crate::compact::CompactionTask;
+use crate::fs::{FileSystem, WritableFile};
+use crate::logger::{db_log, DbLogger};
+use crate::table::FileChecksum;
 
+/// The log of every change to the set of SSTs, replayed on open to rebuild the LSM structure. Each record is
+/// written as | len (u32) | checksum of len (u32) | json | checksum of json (u32) |, the json tagging the record
+/// with its type and format version, see `RECORD_TYPES`.
+///
+/// A crash may leave the last record torn: cut short, or with garbage in place of its bytes. Recovery drops such a
+/// record and truncates the file before it, since it was never acknowledged. An invalid record followed by a valid
+/// one cannot come from a torn write, recovery fails on it rather than dropping the records after it.
+///
+/// Once it grows too large, the manifest is rolled over to a new file starting with a snapshot of the LSM
+/// structure, and the `CURRENT` file is switched to it.
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    fs: Arc<dyn FileSystem>,
+    file: Arc<Mutex<ManifestFile>>,
+}
+
+struct ManifestFile {
+    file: Box<dyn WritableFile>,
+    path: PathBuf,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -19,15 +37,456 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// The whole LSM structure, the first record of a rolled over manifest.
+    Snapshot {
+        l0_sstables: Vec<usize>,
+        levels: Vec<(usize, Vec<usize>)>,
+        next_sst_id: usize,
+        #[serde(default)]
+        db_id: String,
+        #[serde(default)]
+        file_checksums: Vec<(usize, FileChecksum)>,
+        /// The memtables below it are flushed, and their WALs are not recovered.
+        #[serde(default)]
+        oldest_memtable_id: usize,
+        #[serde(default)]
+        commit_ts_bound: u64,
+        /// Above the id of every column family created so far, so that ids are never reused.
+        #[serde(default)]
+        next_column_family_id: u32,
+    },
+    /// The identity of the database, generated when it is created.
+    DbId(String),
+    /// The checksums of new SSTs, written together with the flush or compaction record adding them.
+    FileChecksums(Vec<(usize, FileChecksum)>),
+    /// Every commit ts allocated until the next such record is at most this bound, see `TimestampOracle`.
+    CommitTsBound(u64),
+    /// A column family was created while `memtable_id` was the current memtable, the older WALs hold none of its
+    /// writes.
+    CreateColumnFamily {
+        id: u32,
+        name: String,
+        memtable_id: usize,
+        #[serde(default)]
+        options: ColumnFamilyOptions,
+    },
+    DropColumnFamily(u32),
+    /// The memtable `memtable_id` of a column family besides the default one was flushed to `sst_id`.
+    ColumnFamilyFlush {
+        cf_id: u32,
+        memtable_id: usize,
+        sst_id: usize,
+    },
+    ColumnFamilyCompaction(u32, CompactionTask, Vec<usize>),
+    /// The LSM structure of a column family besides the default one, following the `Snapshot` of a rolled over
+    /// manifest.
+    ColumnFamilySnapshot {
+        id: u32,
+        name: String,
+        l0_sstables: Vec<usize>,
+        levels: Vec<(usize, Vec<usize>)>,
+        oldest_memtable_id: usize,
+        #[serde(default)]
+        options: ColumnFamilyOptions,
+    },
+    /// External SSTs were ingested into `level` of the column family `cf_id`, which now holds `ssts`. The level is
+    /// a new sorted run for tiered compaction, and 0 is L0.
+    IngestExternalFiles {
+        cf_id: u32,
+        level: usize,
+        ssts: Vec<usize>,
+    },
+    /// An SST written besides the flushes, by `put_streaming`, went above the other SSTs of the column family
+    /// `cf_id`: first in L0, or as the newest sorted run for tiered compaction.
+    NewestSst {
+        cf_id: u32,
+        sst_id: usize,
+    },
+}
+
+/// A record type of the manifest. Tags are never reused, and a change to the format of a record bumps its
+/// version. Readers fail on unknown tags and newer versions, unless the writer marked the record ignorable: it
+/// only adds information older readers can do without, so that they can still open the database.
+struct RecordType {
+    tag: u32,
+    /// The name of the `ManifestRecord` variant.
+    name: &'static str,
+    version: u32,
+    ignorable: bool,
+}
+
+const RECORD_TYPES: &[RecordType] = &[
+    RecordType {
+        tag: 1,
+        name: "Flush",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 2,
+        name: "NewMemtable",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 3,
+        name: "Compaction",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 4,
+        name: "Snapshot",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 5,
+        name: "DbId",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 6,
+        name: "FileChecksums",
+        version: 1,
+        ignorable: true,
+    },
+    RecordType {
+        tag: 7,
+        name: "CommitTsBound",
+        version: 1,
+        ignorable: true,
+    },
+    RecordType {
+        tag: 8,
+        name: "CreateColumnFamily",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 9,
+        name: "DropColumnFamily",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 10,
+        name: "ColumnFamilyFlush",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 11,
+        name: "ColumnFamilyCompaction",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 12,
+        name: "ColumnFamilySnapshot",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 13,
+        name: "IngestExternalFiles",
+        version: 1,
+        ignorable: false,
+    },
+    RecordType {
+        tag: 14,
+        name: "NewestSst",
+        version: 1,
+        ignorable: false,
+    },
+];
+
+/// The json of a record. Records written before they were tagged are the bare `ManifestRecord`.
+#[derive(Serialize, Deserialize)]
+struct TaggedRecord {
+    tag: u32,
+    version: u32,
+    #[serde(default)]
+    ignorable: bool,
+    data: serde_json::Value,
+}
+
+impl ManifestRecord {
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        let serde_json::Value::Object(variant) = serde_json::to_value(self)? else {
+            unreachable!("records are not unit variants");
+        };
+        let (name, data) = variant.into_iter().next().unwrap();
+        let record_type = RECORD_TYPES
+            .iter()
+            .find(|record_type| record_type.name == name)
+            .unwrap_or_else(|| panic!("no record type for {}", name));
+        Ok(serde_json::to_vec(&TaggedRecord {
+            tag: record_type.tag,
+            version: record_type.version,
+            ignorable: record_type.ignorable,
+            data,
+        })?)
+    }
+
+    /// Decode the json of a record, `None` if it is an ignorable record this version does not know.
+    pub(crate) fn decode(json: &[u8], logger: &DbLogger) -> Result<Option<Self>> {
+        let value = serde_json::from_slice::<serde_json::Value>(json)?;
+        if value.get("tag").is_none() {
+            return Ok(Some(serde_json::from_value(value)?));
+        }
+        let record = serde_json::from_value::<TaggedRecord>(value)?;
+        let known = RECORD_TYPES
+            .iter()
+            .find(|record_type| record_type.tag == record.tag);
+        match known {
+            Some(record_type) if record.version <= record_type.version => {
+                let mut variant = serde_json::Map::new();
+                variant.insert(record_type.name.to_string(), record.data);
+                Ok(Some(serde_json::from_value(variant.into())?))
+            }
+            _ if record.ignorable => {
+                db_log!(
+                    logger,
+                    Warn,
+                    "skipping manifest record type {} version {}",
+                    record.tag,
+                    record.version
+                );
+                Ok(None)
+            }
+            Some(record_type) => bail!(
+                "manifest record {} of version {} is newer than the supported version {}",
+                record_type.name,
+                record.version,
+                record_type.version
+            ),
+            None => bail!(
+                "unknown manifest record type {}, the database was written by a newer version",
+                record.tag
+            ),
+        }
+    }
+}
+
+/// Frame `json` as a record of the manifest.
+pub(crate) fn encode_frame(json: &[u8], buf: &mut Vec<u8>) {
+    let len = (json.len() as u32).to_be_bytes();
+    buf.put_slice(&len);
+    buf.put_u32(crc32fast::hash(&len));
+    buf.put_slice(json);
+    buf.put_u32(crc32fast::hash(json));
 }
 
+/// The file naming the manifest in use.
+pub(crate) const CURRENT_FILE_NAME: &str = "CURRENT";
+/// The manifest of the databases created before manifests were rolled over, which had no `CURRENT`.
+pub(crate) const LEGACY_MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+pub(crate) fn manifest_file_name(number: usize) -> String {
+    format!("MANIFEST-{:06}", number)
+}
+
+/// The number of a manifest file, 0 for the legacy `MANIFEST`.
+pub(crate) fn manifest_number(path: &Path) -> usize {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("MANIFEST-"))
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Read the name of the manifest in use in `dir`, `None` if there is no `CURRENT` file.
+pub(crate) fn read_current(fs: &dyn FileSystem, dir: &Path) -> Result<Option<String>> {
+    let path = dir.join(CURRENT_FILE_NAME);
+    if !fs.exists(&path) {
+        return Ok(None);
+    }
+    let content = String::from_utf8(fs.read(&path).context("failed to read CURRENT")?)
+        .context("CURRENT is not UTF-8")?;
+    let Some(name) = content.strip_suffix('\n') else {
+        bail!("CURRENT is incomplete: {:?}", content);
+    };
+    if !name.starts_with(LEGACY_MANIFEST_FILE_NAME) || name.contains(std::path::is_separator) {
+        bail!("CURRENT does not name a manifest: {:?}", name);
+    }
+    Ok(Some(name.to_string()))
+}
+
+/// The manifest in use in `dir`, as `current_manifest` finds it, but without changing anything in `dir`.
+pub(crate) fn read_current_manifest(fs: &dyn FileSystem, dir: &Path) -> Result<Option<String>> {
+    match read_current(fs, dir)? {
+        Some(name) => Ok(Some(name)),
+        None if fs.exists(&dir.join(LEGACY_MANIFEST_FILE_NAME)) => {
+            Ok(Some(LEGACY_MANIFEST_FILE_NAME.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Find the manifest in use in `dir`, `None` for a new database. A legacy manifest gets a `CURRENT` naming it.
+///
+/// The manifests `CURRENT` does not name are deleted: they were left by a crash while the database was created or
+/// while the manifest was rolled over, before `CURRENT` was switched to them, and may be incomplete. SSTs without
+/// `CURRENT` are an error, since their database lost its manifest.
+pub(crate) fn current_manifest(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    logger: &DbLogger,
+) -> Result<Option<String>> {
+    let mut current = read_current(fs, dir)?;
+    if current.is_none() && fs.exists(&dir.join(LEGACY_MANIFEST_FILE_NAME)) {
+        set_current(fs, dir, LEGACY_MANIFEST_FILE_NAME)?;
+        current = Some(LEGACY_MANIFEST_FILE_NAME.to_string());
+    }
+    let file_names = fs.list_dir(dir)?;
+    let has_ssts = file_names.iter().any(|name| name.ends_with(".sst"));
+    if current.is_none() && has_ssts {
+        bail!(
+            "{} has SSTs but no CURRENT, the database needs to be repaired",
+            dir.display()
+        );
+    }
+    if let Some(name) = &current {
+        if !fs.exists(&dir.join(name)) {
+            bail!("the manifest {} named by CURRENT does not exist", name);
+        }
+    }
+    for file_name in file_names.iter().map(String::as_str) {
+        let unused_manifest = file_name.starts_with(LEGACY_MANIFEST_FILE_NAME)
+            && current.as_deref() != Some(file_name);
+        if unused_manifest || file_name == format!("{}.tmp", CURRENT_FILE_NAME) {
+            db_log!(logger, Info, "removing unused {}", file_name);
+            fs.remove_file(&dir.join(file_name))?;
+        }
+    }
+    Ok(current)
+}
+
+/// Make `CURRENT` name the manifest `name`, which must be complete and synced. `CURRENT` is written to a temporary
+/// file renamed over it, so that a crash leaves either the old or the new name in it.
+pub(crate) fn set_current(fs: &dyn FileSystem, dir: &Path, name: &str) -> Result<()> {
+    let tmp_path = dir.join(format!("{}.tmp", CURRENT_FILE_NAME));
+    let mut file = fs.create(&tmp_path)?;
+    file.write_all(format!("{}\n", name).as_bytes())?;
+    file.sync()?;
+    fs.rename(&tmp_path, &dir.join(CURRENT_FILE_NAME))?;
+    fs.sync_dir(dir)?;
+    Ok(())
+}
+
+/// The offset of a record cut off by a torn write, with why it does not decode.
+type TornRecord = (usize, anyhow::Error);
+
 impl Manifest {
-    pub fn create(_path: impl AsRef<Path>) -> Result<Self> {
-        unimplemented!()
+    pub fn create(fs: &Arc<dyn FileSystem>, path: impl AsRef<Path>) -> Result<Self> {
+        let file = fs
+            .create_new(path.as_ref())
+            .context("failed to create manifest")?;
+        Ok(Self {
+            fs: fs.clone(),
+            file: Arc::new(Mutex::new(ManifestFile {
+                file,
+                path: path.as_ref().to_path_buf(),
+            })),
+        })
+    }
+
+    pub fn recover(
+        fs: &Arc<dyn FileSystem>,
+        path: impl AsRef<Path>,
+        logger: &DbLogger,
+    ) -> Result<(Self, Vec<ManifestRecord>)> {
+        let path = path.as_ref();
+        let buf = fs.read(path).context("failed to recover manifest")?;
+        let mut file = fs.open_append(path).context("failed to recover manifest")?;
+        let (records, torn) = Self::decode_records(&buf, logger)?;
+        if let Some((offset, e)) = torn {
+            db_log!(
+                logger,
+                Warn,
+                "truncating the manifest at offset {}, torn record: {}",
+                offset,
+                e
+            );
+            file.truncate(offset as u64)?;
+            file.sync()?;
+        }
+        Ok((
+            Self {
+                fs: fs.clone(),
+                file: Arc::new(Mutex::new(ManifestFile {
+                    file,
+                    path: path.to_path_buf(),
+                })),
+            },
+            records,
+        ))
+    }
+
+    /// Read the records of the manifest without changing it, for a reader of a manifest another process may be
+    /// appending to. A torn record at the end is one still being written, and is skipped.
+    pub fn read_records(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        logger: &DbLogger,
+    ) -> Result<Vec<ManifestRecord>> {
+        let buf = fs.read(path.as_ref()).context("failed to read manifest")?;
+        Ok(Self::decode_records(&buf, logger)?.0)
+    }
+
+    /// Decode the records of `buf`, with the torn record ending it.
+    pub(crate) fn decode_records(
+        buf: &[u8],
+        logger: &DbLogger,
+    ) -> Result<(Vec<ManifestRecord>, Option<TornRecord>)> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            match Self::decode_frame(&buf[offset..]) {
+                Ok((json, size)) => {
+                    // the record was written whole, failing to decode it is not a torn write
+                    let record = ManifestRecord::decode(json, logger).with_context(|| {
+                        format!("failed to decode the manifest record at offset {}", offset)
+                    })?;
+                    records.extend(record);
+                    offset += size;
+                }
+                Err(e) if Self::contains_frame(&buf[offset + 1..]) => {
+                    return Err(e.context(format!("manifest corrupted at offset {}", offset)));
+                }
+                Err(e) => return Ok((records, Some((offset, e)))),
+            }
+        }
+        Ok((records, None))
+    }
+
+    /// Check the frame of the record at the beginning of `buf`, returns its json with the size of the frame.
+    fn decode_frame(buf: &[u8]) -> Result<(&[u8], usize)> {
+        let mut buf_ptr = buf;
+        if buf_ptr.remaining() < 8 {
+            bail!("incomplete record header");
+        }
+        let len = buf_ptr.get_u32();
+        if buf_ptr.get_u32() != crc32fast::hash(&len.to_be_bytes()) {
+            bail!("record length checksum mismatched");
+        }
+        let len = len as usize;
+        // a length near `u32::MAX` overflows a 32-bit usize
+        if len.checked_add(4).is_none_or(|n| buf_ptr.remaining() < n) {
+            bail!("incomplete record");
+        }
+        let json = &buf_ptr[..len];
+        if (&buf_ptr[len..]).get_u32() != crc32fast::hash(json) {
+            bail!("record checksum mismatched");
+        }
+        Ok((json, len + 12))
     }
 
-    pub fn recover(_path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
-        unimplemented!()
+    /// Whether a valid record starts anywhere in `buf`, which a torn write cannot leave behind it.
+    fn contains_frame(buf: &[u8]) -> bool {
+        (0..buf.len()).any(|offset| Self::decode_frame(&buf[offset..]).is_ok())
     }
 
     pub fn add_record(
@@ -38,7 +497,65 @@ impl Manifest {
         self.add_record_when_init(record)
     }
 
-    pub fn add_record_when_init(&self, _record: ManifestRecord) -> Result<()> {
-        unimplemented!()
+    /// Append `records` with a single write and sync.
+    pub fn add_records(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        records: &[ManifestRecord],
+    ) -> Result<()> {
+        Self::write_records(self.file.lock().file.as_mut(), records)
+    }
+
+    pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
+        Self::write_record(self.file.lock().file.as_mut(), &record)
+    }
+
+    fn write_record(file: &mut dyn WritableFile, record: &ManifestRecord) -> Result<()> {
+        Self::write_records(file, std::slice::from_ref(record))
+    }
+
+    fn write_records(file: &mut dyn WritableFile, records: &[ManifestRecord]) -> Result<()> {
+        let mut buf = Vec::new();
+        for record in records {
+            encode_frame(&record.encode()?, &mut buf);
+        }
+        // a single write, so that a crash cannot leave a record torn anywhere but at the end
+        file.write_all(&buf)?;
+        file.sync()?;
+        Ok(())
+    }
+
+    /// The manifest file in use.
+    pub fn path(&self) -> PathBuf {
+        self.file.lock().path.clone()
+    }
+
+    /// Size of the manifest file in bytes.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.lock().file.size()?)
+    }
+
+    /// Start a new manifest holding only the records of `snapshot` next to the current one, switch `CURRENT` to it
+    /// and delete the current one. A crash before `CURRENT` is switched leaves the new manifest unused.
+    pub fn rollover(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        snapshot: &[ManifestRecord],
+    ) -> Result<()> {
+        let mut current = self.file.lock();
+        let dir = current
+            .path
+            .parent()
+            .context("manifest has no parent directory")?
+            .to_path_buf();
+        let name = manifest_file_name(manifest_number(&current.path) + 1);
+        let path = dir.join(&name);
+        let mut file = self.fs.create(&path).context("failed to create manifest")?;
+        Self::write_records(file.as_mut(), snapshot)?;
+        set_current(self.fs.as_ref(), &dir, &name)?;
+        let old = std::mem::replace(&mut *current, ManifestFile { file, path });
+        drop(old.file);
+        self.fs.remove_file(&old.path)?;
+        Ok(())
     }
 }
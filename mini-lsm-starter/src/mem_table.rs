@@ -9,20 +9,25 @@ use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
 
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::fs::FileSystem;
 use crate::iterators::StorageIterator;
-use crate::key::{Key, KeySlice};
+use crate::key::{KeyBytes, KeySlice, TS_DEFAULT, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::logger::DbLogger;
+use crate::perf_context::PerfTimer;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{Wal, WalRecord};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
     wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
+    num_deletions: Arc<AtomicUsize>,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -34,6 +39,57 @@ pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
     }
 }
 
+/// Create a bound of `KeyBytes` from a bound of `KeySlice`.
+pub(crate) fn map_key_bound(bound: Bound<KeySlice>) -> Bound<KeyBytes> {
+    match bound {
+        Bound::Included(x) => Bound::Included(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(x.key_ref()),
+            x.ts(),
+        )),
+        Bound::Excluded(x) => Bound::Excluded(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(x.key_ref()),
+            x.ts(),
+        )),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Create a bound of `KeySlice` from a bound of `&[u8]` and a ts.
+pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySlice<'_>> {
+    match bound {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, ts)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, ts)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Create the bounds of `KeySlice` covering every version of the user keys within a range. An excluded bound
+/// excludes all versions of its key.
+pub(crate) fn map_user_key_range<'a>(
+    lower: Bound<&'a [u8]>,
+    upper: Bound<&'a [u8]>,
+) -> (Bound<KeySlice<'a>>, Bound<KeySlice<'a>>) {
+    let lower = match lower {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, TS_RANGE_BEGIN)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, TS_RANGE_END)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let upper = match upper {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, TS_RANGE_END)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, TS_RANGE_BEGIN)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (lower, upper)
+}
+
+fn map_user_key_bytes_range(
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) -> (Bound<KeyBytes>, Bound<KeyBytes>) {
+    let (lower, upper) = map_user_key_range(lower, upper);
+    (map_key_bound(lower), map_key_bound(upper))
+}
+
 impl MemTable {
     /// Create a new mem-table.
     pub fn create(id: usize) -> Self {
@@ -42,25 +98,81 @@ impl MemTable {
             wal: None,
             id,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            num_deletions: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     /// Create a new mem-table with WAL
-    pub fn create_with_wal(_id: usize, _path: impl AsRef<Path>) -> Result<Self> {
-        unimplemented!()
+    pub fn create_with_wal(id: usize, fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let mut memtable = Self::create(id);
+        memtable.wal = Some(Wal::create(fs, path)?);
+        Ok(memtable)
+    }
+
+    /// Create a memtable from WAL, applying its batches. Returns the records of the WAL as well, for the caller
+    /// to resolve the prepared transactions.
+    pub fn recover_from_wal(
+        id: usize,
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        logger: &DbLogger,
+    ) -> Result<(Self, Vec<WalRecord>)> {
+        let (wal, records) = Wal::recover(fs, path, logger)?;
+        let mut memtable = Self::from_wal_records(id, DEFAULT_COLUMN_FAMILY_ID, &records);
+        memtable.wal = Some(wal);
+        Ok((memtable, records))
     }
 
-    /// Create a memtable from WAL
-    pub fn recover_from_wal(_id: usize, _path: impl AsRef<Path>) -> Result<Self> {
-        unimplemented!()
+    /// Same as `recover_from_wal`, but the WAL is only read, and the memtable does not write to it.
+    pub fn read_from_wal(
+        id: usize,
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+    ) -> Result<(Self, Vec<WalRecord>)> {
+        let records = Wal::read_records(fs, path)?;
+        Ok((
+            Self::from_wal_records(id, DEFAULT_COLUMN_FAMILY_ID, &records),
+            records,
+        ))
+    }
+
+    /// Create a memtable without a WAL holding the writes of `records` to the column family `cf_id`.
+    pub(crate) fn from_wal_records(id: usize, cf_id: u32, records: &[WalRecord]) -> Self {
+        let memtable = Self::create(id);
+        let mut size = 0;
+        for record in records {
+            let entries: Box<dyn Iterator<Item = (&KeyBytes, &Bytes)>> = match record {
+                WalRecord::Batch { entries, .. } if cf_id == DEFAULT_COLUMN_FAMILY_ID => {
+                    Box::new(entries.iter().map(|(key, value)| (key, value)))
+                }
+                WalRecord::ColumnFamilyBatch { entries } => Box::new(
+                    entries
+                        .iter()
+                        .filter(|(id, _, _)| *id == cf_id)
+                        .map(|(_, key, value)| (key, value)),
+                ),
+                _ => continue,
+            };
+            for (key, value) in entries {
+                if value.is_empty() {
+                    memtable
+                        .num_deletions
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+                size += key.raw_len() + value.len();
+                memtable.map.insert(key.clone(), value.clone());
+            }
+        }
+        memtable.set_approximate_size(size);
+        memtable
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        self.put(key, value)
+        self.put(KeySlice::from_slice(key, TS_DEFAULT), value)
     }
 
     pub fn for_testing_get_slice(&self, key: &[u8]) -> Option<Bytes> {
-        self.get(key)
+        self.get(KeySlice::from_slice(key, TS_DEFAULT))
     }
 
     pub fn for_testing_scan_slice(
@@ -68,24 +180,69 @@ impl MemTable {
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> MemTableIterator {
-        self.scan(lower, upper)
+        self.scan(
+            map_key_bound_plus_ts(lower, TS_DEFAULT),
+            map_key_bound_plus_ts(upper, TS_DEFAULT),
+        )
+    }
+
+    /// Get the value of an exact key version.
+    pub fn get(&self, key: KeySlice) -> Option<Bytes> {
+        let key = KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key.key_ref()), key.ts());
+        self.map.get(&key).map(|v| v.value().clone())
     }
 
-    /// Get a value by key.
-    pub fn get(&self, _key: &[u8]) -> Option<Bytes> {
-        self.map.get(_key).map(|v| v.value().clone())
+    /// Get the latest version of a key visible at `read_ts`. Returns the empty value for a tombstone.
+    pub fn get_visible(&self, key: &[u8], read_ts: u64) -> Option<Bytes> {
+        let key = Bytes::copy_from_slice(key);
+        let lower = KeyBytes::from_bytes_with_ts(key.clone(), read_ts);
+        let upper = KeyBytes::from_bytes_with_ts(key, TS_RANGE_END);
+        self.map
+            .range((Bound::Included(lower), Bound::Included(upper)))
+            .next()
+            .map(|entry| entry.value().clone())
     }
 
     /// Put a key-value pair into the mem-table.
     ///
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
-    pub fn put(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
-        self.map
-            .insert(Bytes::copy_from_slice(_key), Bytes::copy_from_slice(_value));
+    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.put_batch(&[(key, value)], None)
+    }
+
+    /// Put the key-value pairs of a batch, logged as a single WAL record. `txn_name` is the prepared transaction
+    /// they commit, if any.
+    pub fn put_batch(&self, data: &[(KeySlice, &[u8])], txn_name: Option<&str>) -> Result<()> {
+        let timer = PerfTimer::start();
+        if let Some(ref wal) = self.wal {
+            wal.put_batch(txn_name, data)?;
+        }
+        timer.stop(|context| &mut context.write_wal_time);
+        let timer = PerfTimer::start();
+        self.insert_batch(data);
+        timer.stop(|context| &mut context.write_memtable_time);
         Ok(())
     }
 
+    /// Put the key-value pairs of a batch without logging them, for writes the caller logged itself.
+    pub(crate) fn insert_batch(&self, data: &[(KeySlice, &[u8])]) {
+        for (key, value) in data {
+            if value.is_empty() {
+                self.num_deletions
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+            self.map.insert(
+                key.to_key_vec().into_key_bytes(),
+                Bytes::copy_from_slice(value),
+            );
+        }
+    }
+
+    pub(crate) fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -94,13 +251,13 @@ impl MemTable {
     }
 
     /// Get an iterator over a range of keys.
-    pub fn scan(&self, _lower: Bound<&[u8]>, _upper: Bound<&[u8]>) -> MemTableIterator {
-        let lower = map_bound(_lower);
-        let upper = map_bound(_upper);
+    pub fn scan(&self, _lower: Bound<KeySlice>, _upper: Bound<KeySlice>) -> MemTableIterator {
+        let lower = map_key_bound(_lower);
+        let upper = map_key_bound(_upper);
         let mut mem_iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map: &Arc<SkipMap<Bytes, Bytes>>| map.range((lower, upper)),
-            item: (Bytes::new(), Bytes::new()),
+            iter_builder: |map: &Arc<SkipMap<KeyBytes, Bytes>>| map.range((lower, upper)),
+            item: (KeyBytes::new(), Bytes::new()),
         }
         .build();
 
@@ -110,7 +267,7 @@ impl MemTable {
                 feild.item.1 = entry.value().clone();
             }
             None => {
-                feild.item.0 = Bytes::new();
+                feild.item.0 = KeyBytes::new();
                 feild.item.1 = Bytes::new();
             }
         });
@@ -118,10 +275,39 @@ impl MemTable {
         mem_iter
     }
 
+    /// Number of key and value bytes stored within a key range.
+    pub fn approximate_range_size(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> usize {
+        self.map
+            .range(map_user_key_bytes_range(lower, upper))
+            .map(|entry| entry.key().raw_len() + entry.value().len())
+            .sum()
+    }
+
+    /// Number of key versions in the mem-table, including tombstones.
+    pub fn num_entries(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Number of deletions written to the mem-table. Deleting the same key twice counts twice.
+    pub fn num_deletions(&self) -> usize {
+        self.num_deletions
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Number of entries and tombstones within a key range.
+    pub fn count_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> (usize, usize) {
+        self.map.range(map_user_key_bytes_range(lower, upper)).fold(
+            (0, 0),
+            |(entries, deletions), entry| {
+                (entries + 1, deletions + entry.value().is_empty() as usize)
+            },
+        )
+    }
+
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(KeySlice::from_slice(&entry.key()[..]), &entry.value()[..]);
+            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
         }
         Ok(())
     }
@@ -146,8 +332,13 @@ impl MemTable {
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    KeyBytes,
+    (Bound<KeyBytes>, Bound<KeyBytes>),
+    KeyBytes,
+    Bytes,
+>;
 
 /// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
 /// chapter for more information.
@@ -156,13 +347,13 @@ type SkipMapRangeIter<'a> =
 #[self_referencing]
 pub struct MemTableIterator {
     /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<KeyBytes, Bytes>>,
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
-    item: (Bytes, Bytes),
+    item: (KeyBytes, Bytes),
 }
 
 impl StorageIterator for MemTableIterator {
@@ -173,7 +364,7 @@ impl StorageIterator for MemTableIterator {
     }
 
     fn key(&self) -> KeySlice {
-        KeySlice::from_slice(&self.borrow_item().0)
+        self.borrow_item().0.as_key_slice()
     }
 
     fn is_valid(&self) -> bool {
@@ -188,7 +379,7 @@ impl StorageIterator for MemTableIterator {
                 feild.item.1 = entry.value().clone();
             }
             None => {
-                feild.item.0 = Bytes::new();
+                feild.item.0 = KeyBytes::new();
                 feild.item.1 = Bytes::new();
             }
         });
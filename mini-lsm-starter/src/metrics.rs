@@ -0,0 +1,175 @@
+use std::fmt::Write;
+
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::statistics::{Histogram, Ticker};
+
+/// Prefix of the names of the metrics.
+const PREFIX: &str = "mini_lsm";
+
+/// The quantiles the histograms are exported at.
+const QUANTILES: [f64; 3] = [0.5, 0.95, 0.99];
+
+/// Builds a page of the Prometheus text exposition format.
+#[derive(Default)]
+struct PrometheusPage(String);
+
+impl PrometheusPage {
+    /// Add the metric `name` of `kind`, with a sample for every label set and value.
+    fn metric<'a>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        samples: impl IntoIterator<Item = (&'a str, f64)>,
+    ) {
+        writeln!(self.0, "# HELP {}_{} {}", PREFIX, name, help).unwrap();
+        writeln!(self.0, "# TYPE {}_{} {}", PREFIX, name, kind).unwrap();
+        for (labels, value) in samples {
+            self.sample(name, labels, value);
+        }
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: f64) {
+        if labels.is_empty() {
+            writeln!(self.0, "{}_{} {}", PREFIX, name, value).unwrap();
+        } else {
+            writeln!(self.0, "{}_{}{{{}}} {}", PREFIX, name, labels, value).unwrap();
+        }
+    }
+
+    /// Add the gauge `name` with a sample for every level, labelled with its number.
+    fn per_level(&mut self, name: &str, help: &str, values: impl IntoIterator<Item = f64>) {
+        let labels = values
+            .into_iter()
+            .enumerate()
+            .map(|(level, value)| (format!("level=\"{}\"", level), value))
+            .collect::<Vec<_>>();
+        self.metric(
+            name,
+            "gauge",
+            help,
+            labels
+                .iter()
+                .map(|(labels, value)| (labels.as_str(), *value)),
+        );
+    }
+}
+
+/// The name of a ticker or histogram of the `Statistics` as a metric, e.g. `wal_synced` for `wal.synced`.
+fn metric_name(name: &str) -> String {
+    name.replace('.', "_")
+}
+
+impl LsmStorageInner {
+    /// The health of the engine in the Prometheus text exposition format, for a scrape endpoint to serve: the
+    /// files, sizes and pending compaction bytes of every level of the default column family, the memtables, the
+    /// block cache, and the counters and latency histograms of the `statistics` option when it is set. The
+    /// histograms are summaries, and their quantiles are estimated from power-of-two buckets.
+    pub fn prometheus_metrics(&self) -> String {
+        let mut page = PrometheusPage::default();
+        let levels = self.compaction_stats().levels;
+        page.per_level(
+            "level_files",
+            "SST files of the level, 0 for L0; tiered compaction numbers the sorted runs from 1.",
+            levels.iter().map(|level| level.num_files as f64),
+        );
+        page.per_level(
+            "level_bytes",
+            "Bytes of the SSTs of the level.",
+            levels.iter().map(|level| level.size_bytes as f64),
+        );
+        page.per_level(
+            "pending_compaction_bytes",
+            "Estimated bytes compactions still have to move out of the level.",
+            levels
+                .iter()
+                .map(|level| level.pending_compaction_bytes as f64),
+        );
+
+        let state = self.state.read().clone();
+        page.metric(
+            "immutable_memtables",
+            "gauge",
+            "Frozen memtables waiting to be flushed.",
+            [("", state.imm_memtables.len() as f64)],
+        );
+        let memtable_bytes = std::iter::once(&state.memtable)
+            .chain(&state.imm_memtables)
+            .map(|memtable| memtable.approximate_size())
+            .sum::<usize>();
+        page.metric(
+            "memtable_bytes",
+            "gauge",
+            "Approximate bytes of the current and frozen memtables.",
+            [("", memtable_bytes as f64)],
+        );
+
+        let cache = self.block_cache.stats();
+        page.metric(
+            "block_cache_capacity_bytes",
+            "gauge",
+            "Capacity of the block cache.",
+            [("", cache.capacity as f64)],
+        );
+        page.metric(
+            "block_cache_usage_bytes",
+            "gauge",
+            "Bytes charged to the block cache.",
+            [("", cache.usage as f64)],
+        );
+        page.metric(
+            "block_cache_lookups_total",
+            "counter",
+            "Lookups of the block cache by result.",
+            [
+                ("result=\"hit\"", cache.counts.hits as f64),
+                ("result=\"miss\"", cache.counts.misses as f64),
+            ],
+        );
+        page.metric(
+            "block_cache_hit_ratio",
+            "gauge",
+            "Share of the block cache lookups served from the cache.",
+            [("", cache.counts.hit_ratio())],
+        );
+
+        if let Some(statistics) = &self.options().statistics {
+            for ticker in Ticker::ALL {
+                page.metric(
+                    &format!("{}_total", metric_name(ticker.name())),
+                    "counter",
+                    &format!("Statistics ticker {}.", ticker.name()),
+                    [("", statistics.ticker(ticker) as f64)],
+                );
+            }
+            for histogram in Histogram::ALL {
+                let name = metric_name(histogram.name());
+                let data = statistics.histogram(histogram);
+                let quantiles = QUANTILES
+                    .iter()
+                    .map(|quantile| {
+                        let labels = format!("quantile=\"{}\"", quantile);
+                        (labels, data.percentile(quantile * 100.0))
+                    })
+                    .collect::<Vec<_>>();
+                page.metric(
+                    &name,
+                    "summary",
+                    &format!("Statistics histogram {}.", histogram.name()),
+                    quantiles
+                        .iter()
+                        .map(|(labels, value)| (labels.as_str(), *value)),
+                );
+                page.sample(&format!("{}_sum", name), "", data.sum as f64);
+                page.sample(&format!("{}_count", name), "", data.count as f64);
+            }
+        }
+        page.0
+    }
+}
+
+impl MiniLsm {
+    pub fn prometheus_metrics(&self) -> String {
+        self.inner.prometheus_metrics()
+    }
+}
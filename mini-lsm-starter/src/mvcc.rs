@@ -5,15 +5,27 @@ pub mod txn;
 mod watermark;
 
 use std::{
+    backtrace::Backtrace,
     collections::{BTreeMap, HashSet},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use parking_lot::Mutex;
+// `std::time` panics on wasm32, which reads the time of the browser instead
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
-use crate::lsm_storage::LsmStorageInner;
+use parking_lot::{Condvar, Mutex};
 
-use self::{txn::Transaction, watermark::Watermark};
+use self::{
+    txn::{Transaction, TxnStorage},
+    watermark::Watermark,
+};
 
 pub(crate) struct CommittedTxnData {
     pub(crate) key_hashes: HashSet<u32>,
@@ -23,20 +35,105 @@ pub(crate) struct CommittedTxnData {
     pub(crate) commit_ts: u64,
 }
 
+/// A transaction prepared but not committed or rolled back yet. Transactions writing a key it wrote or read, or
+/// under serializability reading a key it wrote, conflict with it.
+pub(crate) struct PreparedTxnData {
+    pub(crate) write_key_hashes: HashSet<u32>,
+    /// Only recorded for serializable transactions.
+    pub(crate) read_key_hashes: HashSet<u32>,
+    /// The WAL holding its prepare record, kept with the later ones until the transaction is resolved.
+    pub(crate) wal_id: usize,
+    /// `None` for the transactions that do not expire, including the ones recovered after a crash.
+    pub(crate) lease: Option<TxnLease>,
+}
+
+/// Until when a prepared transaction holds its keys. Once it expired, a transaction conflicting with it rolls it
+/// back, and sets `reclaimed` so that its commit fails.
+pub(crate) struct TxnLease {
+    pub(crate) expires_at: Instant,
+    pub(crate) reclaimed: Arc<AtomicBool>,
+}
+
 pub(crate) struct LsmMvccInner {
     pub(crate) write_lock: Mutex<()>,
     pub(crate) commit_lock: Mutex<()>,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    /// The prepared transactions by name.
+    pub(crate) prepared_txns: Mutex<BTreeMap<String, PreparedTxnData>>,
+    /// Notified, with `prepared_txns`, when a prepared transaction is committed or rolled back.
+    pub(crate) prepared_txn_resolved: Condvar,
+    /// The watermark used by the latest compaction. Versions below it may have been garbage collected, so a read at
+    /// an older ts could miss them.
+    pub(crate) gc_watermark: AtomicU64,
+    pub(crate) live_snapshots: Arc<Mutex<LiveSnapshots>>,
+}
+
+/// Statistics of the live snapshots, including the ones of the transactions. A snapshot that is never dropped keeps
+/// the watermark at its read ts, so that compaction garbage collects nothing written after it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotStats {
+    pub num_snapshots: usize,
+    /// The snapshots of transactions, among `num_snapshots`.
+    pub num_txns: usize,
+    /// The smallest ts pinned, the watermark.
+    pub oldest_read_ts: Option<u64>,
+    /// How long ago the oldest live snapshot was taken.
+    pub oldest_age: Option<Duration>,
+}
+
+/// The live snapshots by id, which is the order they were taken in.
+#[derive(Default)]
+pub(crate) struct LiveSnapshots {
+    next_id: u64,
+    snapshots: BTreeMap<u64, LiveSnapshot>,
+    /// Snapshots alive for longer than this are reported with the backtrace of where they were taken, which is
+    /// only captured when set.
+    warn_age: Option<Duration>,
+    /// The snapshots below this id were already reported.
+    warned_id: u64,
+}
+
+struct LiveSnapshot {
+    read_ts: u64,
+    taken_at: Instant,
+    is_txn: bool,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl LiveSnapshots {
+    fn register(&mut self, read_ts: u64, is_txn: bool) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let backtrace = self.warn_age.map(|_| Arc::new(Backtrace::force_capture()));
+        self.snapshots.insert(
+            id,
+            LiveSnapshot {
+                read_ts,
+                taken_at: Instant::now(),
+                is_txn,
+                backtrace,
+            },
+        );
+        id
+    }
 }
 
 impl LsmMvccInner {
-    pub fn new(initial_ts: u64) -> Self {
+    /// `snapshot_warn_age` enables the warnings of `warn_old_snapshots`.
+    pub fn new(initial_ts: u64, snapshot_warn_age: Option<Duration>) -> Self {
         Self {
             write_lock: Mutex::new(()),
             commit_lock: Mutex::new(()),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            prepared_txns: Mutex::new(BTreeMap::new()),
+            prepared_txn_resolved: Condvar::new(),
+            gc_watermark: AtomicU64::new(0),
+            live_snapshots: Arc::new(Mutex::new(LiveSnapshots {
+                warn_age: snapshot_warn_age,
+                ..Default::default()
+            })),
         }
     }
 
@@ -54,7 +151,149 @@ impl LsmMvccInner {
         ts.1.watermark().unwrap_or(ts.0)
     }
 
-    pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
-        unimplemented!()
+    /// Reads at a ts strictly below this ts may not see the versions they should.
+    pub fn gc_watermark(&self) -> u64 {
+        self.gc_watermark.load(Ordering::SeqCst)
+    }
+
+    /// Record the watermark a compaction garbage collects with. Must be called before the compaction result is
+    /// installed, so that a reader observing the new state also observes the new GC watermark.
+    pub fn advance_gc_watermark(&self, watermark: u64) {
+        self.gc_watermark.fetch_max(watermark, Ordering::SeqCst);
+    }
+
+    pub fn new_snapshot(&self) -> Snapshot {
+        Snapshot::new(self.ts.clone(), self.live_snapshots.clone(), false)
+    }
+
+    /// A snapshot counted as the one of a transaction.
+    pub(crate) fn new_txn_snapshot(&self) -> Snapshot {
+        Snapshot::new(self.ts.clone(), self.live_snapshots.clone(), true)
+    }
+
+    /// Number of distinct ts pinned by snapshots.
+    pub fn num_retained_snapshots(&self) -> usize {
+        self.ts.lock().1.num_retained_snapshots()
+    }
+
+    pub fn snapshot_stats(&self) -> SnapshotStats {
+        let oldest_read_ts = self.ts.lock().1.watermark();
+        let live_snapshots = self.live_snapshots.lock();
+        SnapshotStats {
+            num_snapshots: live_snapshots.snapshots.len(),
+            num_txns: live_snapshots
+                .snapshots
+                .values()
+                .filter(|snapshot| snapshot.is_txn)
+                .count(),
+            oldest_read_ts,
+            oldest_age: live_snapshots
+                .snapshots
+                .first_key_value()
+                .map(|(_, snapshot)| snapshot.taken_at.elapsed()),
+        }
+    }
+
+    /// Print a warning, with the backtrace of where it was taken, for every snapshot alive for longer than the warn
+    /// age and not reported yet, and return how many were reported.
+    pub fn warn_old_snapshots(&self) -> usize {
+        let mut old_snapshots = Vec::new();
+        {
+            let mut live_snapshots = self.live_snapshots.lock();
+            let Some(warn_age) = live_snapshots.warn_age else {
+                return 0;
+            };
+            let mut warned_id = live_snapshots.warned_id;
+            for (id, snapshot) in live_snapshots.snapshots.range(warned_id..) {
+                let age = snapshot.taken_at.elapsed();
+                // taken in id order, the next ones are younger
+                if age < warn_age {
+                    break;
+                }
+                let backtrace = snapshot
+                    .backtrace
+                    .clone()
+                    .expect("captured with a warn age");
+                old_snapshots.push((snapshot.is_txn, snapshot.read_ts, age, backtrace));
+                warned_id = id + 1;
+            }
+            live_snapshots.warned_id = warned_id;
+        }
+        // resolving the backtraces is slow, snapshots can be taken meanwhile
+        for (is_txn, read_ts, age, backtrace) in &old_snapshots {
+            log::warn!(
+                "{} at ts {} alive for {:?}, versions written after it cannot be garbage collected; taken at:\n{}",
+                if *is_txn { "transaction" } else { "snapshot" },
+                read_ts,
+                age,
+                backtrace,
+            );
+        }
+        old_snapshots.len()
+    }
+
+    /// Start a transaction reading at the latest commit ts, which stays pinned until it is dropped.
+    pub fn new_txn(&self, inner: Arc<dyn TxnStorage>, serializable: bool) -> Arc<Transaction> {
+        Arc::new(Transaction::new(
+            inner,
+            self.new_txn_snapshot(),
+            serializable,
+        ))
+    }
+}
+
+/// A read view pinned at the commit ts it was taken at. While it is alive, the watermark stays at or below its ts,
+/// so compaction keeps the versions it reads. Reads through it see the same state until it is dropped.
+pub struct Snapshot {
+    read_ts: u64,
+    ts: Arc<Mutex<(u64, Watermark)>>,
+    id: u64,
+    live_snapshots: Arc<Mutex<LiveSnapshots>>,
+}
+
+impl Snapshot {
+    /// Pin the latest commit ts.
+    pub(crate) fn new(
+        ts: Arc<Mutex<(u64, Watermark)>>,
+        live_snapshots: Arc<Mutex<LiveSnapshots>>,
+        is_txn: bool,
+    ) -> Self {
+        let read_ts = {
+            let mut guard = ts.lock();
+            let read_ts = guard.0;
+            guard.1.add_reader(read_ts);
+            read_ts
+        };
+        let id = live_snapshots.lock().register(read_ts, is_txn);
+        Self {
+            read_ts,
+            ts,
+            id,
+            live_snapshots,
+        }
+    }
+
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+}
+
+/// A clone is tracked as a snapshot taken where it was cloned.
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        self.ts.lock().1.add_reader(self.read_ts);
+        Self {
+            read_ts: self.read_ts,
+            ts: self.ts.clone(),
+            id: self.live_snapshots.lock().register(self.read_ts, false),
+            live_snapshots: self.live_snapshots.clone(),
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.ts.lock().1.remove_reader(self.read_ts);
+        self.live_snapshots.lock().snapshots.remove(&self.id);
     }
 }
@@ -1,57 +1,730 @@
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
 use std::{
     collections::HashSet,
+    fmt,
     ops::Bound,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use anyhow::Result;
+// `std::time` panics on wasm32, which reads the time of the browser instead
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use anyhow::{bail, Result};
 use bytes::Bytes;
-use crossbeam_skiplist::SkipMap;
+use crossbeam_skiplist::{map::Entry, SkipMap};
 use ouroboros::self_referencing;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
 
+use super::{CommittedTxnData, LsmMvccInner, PreparedTxnData, Snapshot, TxnLease};
 use crate::{
     iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
     lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::LsmStorageInner,
+    lsm_storage::WriteBatchRecord,
+    mem_table::map_bound,
 };
 
+/// What a transaction needs from the storage. The reference solutions share this module, so it does not depend on
+/// the storage type.
+pub(crate) trait TxnStorage: Send + Sync {
+    fn mvcc(&self) -> &LsmMvccInner;
+
+    /// Get the version of a key visible at `read_ts`.
+    fn get_at_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>>;
+
+    /// Scan the versions visible at `read_ts`.
+    fn scan_at_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>>;
+
+    /// Write `batch` at a new commit ts, visible to readers all at once, and return the commit ts.
+    fn write_batch_at_new_ts(&self, batch: &[WriteBatchRecord<Bytes>]) -> Result<u64>;
+
+    /// Durably log the writes of the transaction `name` as prepared, and register it in `mvcc().prepared_txns`.
+    fn prepare_txn(
+        &self,
+        name: &str,
+        writes: &[(Bytes, Bytes)],
+        write_key_hashes: HashSet<u32>,
+        read_key_hashes: HashSet<u32>,
+        lease: Option<TxnLease>,
+    ) -> Result<()>;
+
+    /// Durably write `batch` at a new commit ts as the commit of the prepared transaction `name`, unregister it and
+    /// return the commit ts.
+    fn commit_prepared_txn(&self, name: &str, batch: &[WriteBatchRecord<Bytes>]) -> Result<u64>;
+
+    /// Durably log the rollback of the prepared transaction `name` and unregister it.
+    fn rollback_prepared_txn(&self, name: &str) -> Result<()>;
+
+    /// How long new transactions may run before they expire, `None` if they never do.
+    fn txn_expiration(&self) -> Option<Duration>;
+
+    /// How long new transactions wait at commit for the prepared transactions they conflict with to be resolved.
+    fn txn_lock_timeout(&self) -> Duration;
+
+    /// Transactions spill their buffered writes to disk once they are larger than this many bytes, 0 to never
+    /// spill.
+    fn txn_spill_threshold(&self) -> usize;
+
+    /// The granularity of the conflicts of every transaction, which must be the same for their hashes to match.
+    fn txn_conflict_granularity(&self) -> ConflictGranularity;
+
+    /// Write the buffered writes of a transaction to a sorted run on disk.
+    fn spill_txn_writes(&self, writes: &SkipMap<Bytes, Bytes>) -> Result<Box<dyn SpilledRun>>;
+}
+
+/// Writes of a transaction spilled to disk, sorted by key, with deletions as empty values. The storage cleans it up
+/// when dropped.
+pub(crate) trait SpilledRun: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>>;
+
+    /// The writes within a range, in key order.
+    fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<Vec<(Bytes, Bytes)>>;
+}
+
+/// A transaction could not commit, as another transaction committed after its read ts a write to a key it read, or
+/// under snapshot isolation to a key it wrote, or a prepared transaction is about to. Nothing of the transaction
+/// was written, it may be retried from the start to read the new values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitConflict {
+    pub read_ts: u64,
+    /// The commit ts of the first conflicting transaction, `None` for a prepared transaction not committed yet.
+    pub conflicting_commit_ts: Option<u64>,
+}
+
+impl fmt::Display for CommitConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.conflicting_commit_ts {
+            Some(commit_ts) => write!(
+                f,
+                "transaction reading at ts {} conflicts with the transaction committed at ts {}",
+                self.read_ts, commit_ts
+            ),
+            None => write!(
+                f,
+                "transaction reading at ts {} conflicts with a prepared transaction",
+                self.read_ts
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CommitConflict {}
+
+/// A transaction ran for longer than its expiration, so it cannot commit or prepare anymore. A prepared transaction
+/// that expired still commits, unless a transaction conflicting with it rolled it back meanwhile. Nothing of the
+/// transaction was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxnExpired {
+    pub read_ts: u64,
+}
+
+impl fmt::Display for TxnExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction reading at ts {} expired", self.read_ts)
+    }
+}
+
+impl std::error::Error for TxnExpired {}
+
+/// An optimistic transaction. It reads the snapshot at its read ts and buffers its writes, which become visible
+/// together at commit. The commit fails with a `CommitConflict` if a transaction committed after the read ts wrote
+/// a key this one read when serializable, or a key this one wrote otherwise. Writes made outside of transactions
+/// are not checked.
+///
+/// A named transaction may also commit in two phases: `prepare` validates it and durably logs its writes, after
+/// which it can no longer conflict, and survives crashes until `commit` or `rollback`.
+///
+/// A transaction may expire, so that a stalled one cannot hold the keys of a prepared transaction forever: past
+/// its expiration it fails to commit and prepare with `TxnExpired`, and once prepared the transactions conflicting
+/// with it roll it back. The expiration of the prepared transactions of a two-phase commit must be longer than the
+/// coordinator takes to decide, as the participants whose prepared transaction was rolled back cannot commit.
 pub struct Transaction {
-    pub(crate) read_ts: u64,
-    pub(crate) inner: Arc<LsmStorageInner>,
+    pub(crate) snapshot: Snapshot,
+    pub(crate) inner: Arc<dyn TxnStorage>,
     pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
     pub(crate) committed: Arc<AtomicBool>,
-    /// Write set and read set
-    pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
+    pub(crate) serializable: bool,
+    pub(crate) conflict_granularity: ConflictGranularity,
+    /// Hashes of the keys written and read, see `ConflictGranularity`
+    pub(crate) key_hashes: Mutex<(HashSet<u32>, HashSet<u32>)>,
+    pub(crate) savepoints: Mutex<Savepoints>,
+    pub(crate) name: Mutex<Option<String>>,
+    pub(crate) prepared: AtomicBool,
+    /// Writes moved out of `local_storage` once it grew over the spill threshold, oldest first.
+    pub(crate) spilled_runs: Mutex<Vec<Box<dyn SpilledRun>>>,
+    /// Why spilling the writes failed, which fails the next commit or prepare. No spill is attempted after it.
+    pub(crate) spill_error: Mutex<Option<anyhow::Error>>,
+    /// Approximate size of the writes in `local_storage`.
+    pub(crate) buffered_size: AtomicUsize,
+    pub(crate) started_at: Instant,
+    pub(crate) expiration: Mutex<Option<Duration>>,
+    pub(crate) lock_timeout: Mutex<Duration>,
+    /// Set once prepared and expired, when a conflicting transaction rolled it back.
+    pub(crate) reclaimed: Arc<AtomicBool>,
+}
+
+/// The savepoints of a transaction, oldest first, with the writes to undo to roll back to them.
+#[derive(Default)]
+pub(crate) struct Savepoints {
+    savepoints: Vec<Savepoint>,
+    /// The value each key had in the local storage before a write made since the oldest savepoint, `None` if it
+    /// was not written yet.
+    undo_log: Vec<(Bytes, Option<Bytes>)>,
+}
+
+struct Savepoint {
+    undo_log_len: usize,
+    write_set: HashSet<u32>,
+}
+
+/// The unit transactions detect conflicts on. A prefix is tracked as a single hash for all the keys sharing it,
+/// which is cheaper for transactions writing many keys of a prefix, e.g. the entries of a secondary index, but
+/// makes transactions touching distinct keys of the same prefix conflict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictGranularity {
+    #[default]
+    Key,
+    /// The first bytes of the keys, shorter keys being their own prefix.
+    Prefix(usize),
+}
+
+impl ConflictGranularity {
+    /// The hash conflicts on `key` are detected with.
+    pub(crate) fn key_hash(self, key: &[u8]) -> u32 {
+        match self {
+            Self::Key => farmhash::hash32(key),
+            Self::Prefix(len) => farmhash::hash32(&key[..key.len().min(len)]),
+        }
+    }
 }
 
 impl Transaction {
+    pub(crate) fn new(inner: Arc<dyn TxnStorage>, snapshot: Snapshot, serializable: bool) -> Self {
+        Self {
+            snapshot,
+            conflict_granularity: inner.txn_conflict_granularity(),
+            expiration: Mutex::new(inner.txn_expiration()),
+            lock_timeout: Mutex::new(inner.txn_lock_timeout()),
+            inner,
+            local_storage: Arc::new(SkipMap::new()),
+            committed: Arc::new(AtomicBool::new(false)),
+            serializable,
+            key_hashes: Mutex::new((HashSet::new(), HashSet::new())),
+            savepoints: Mutex::new(Savepoints::default()),
+            name: Mutex::new(None),
+            prepared: AtomicBool::new(false),
+            spilled_runs: Mutex::new(Vec::new()),
+            spill_error: Mutex::new(None),
+            buffered_size: AtomicUsize::new(0),
+            started_at: Instant::now(),
+            reclaimed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A transaction recovered in the prepared state with its writes. It does not expire.
+    pub(crate) fn new_prepared(
+        inner: Arc<dyn TxnStorage>,
+        snapshot: Snapshot,
+        name: String,
+        writes: Vec<(Bytes, Bytes)>,
+    ) -> Self {
+        let txn = Self::new(inner, snapshot, false);
+        for (key, value) in writes {
+            let key_hash = txn.conflict_granularity.key_hash(&key);
+            txn.key_hashes.lock().0.insert(key_hash);
+            txn.local_storage.insert(key, value);
+        }
+        *txn.name.lock() = Some(name);
+        *txn.expiration.lock() = None;
+        txn.prepared.store(true, Ordering::SeqCst);
+        txn
+    }
+
+    pub fn read_ts(&self) -> u64 {
+        self.snapshot.read_ts()
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.name.lock().clone()
+    }
+
+    /// Name the transaction, which is required to prepare it. The names of the prepared transactions are unique.
+    pub fn set_name(&self, name: &str) -> Result<()> {
+        self.check_not_prepared()?;
+        if name.is_empty() {
+            bail!("transaction names cannot be empty");
+        }
+        *self.name.lock() = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn is_prepared(&self) -> bool {
+        self.prepared.load(Ordering::SeqCst)
+    }
+
+    /// Let the transaction run for `expiration` from its start, or forever with `None`, instead of the expiration
+    /// of the storage options. It cannot change once prepared.
+    pub fn set_expiration(&self, expiration: Option<Duration>) -> Result<()> {
+        self.check_not_prepared()?;
+        *self.expiration.lock() = expiration;
+        Ok(())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| Instant::now() >= expires_at)
+    }
+
+    fn expires_at(&self) -> Option<Instant> {
+        self.expiration
+            .lock()
+            .map(|expiration| self.started_at + expiration)
+    }
+
+    /// Wait up to `lock_timeout` at commit for the prepared transactions this one conflicts with, instead of the
+    /// lock timeout of the storage options.
+    pub fn set_lock_timeout(&self, lock_timeout: Duration) {
+        *self.lock_timeout.lock() = lock_timeout;
+    }
+
+    /// End the transaction with `TxnExpired` if it expired.
+    fn check_not_expired(&self) -> Result<()> {
+        if self.is_expired() {
+            self.committed.store(true, Ordering::SeqCst);
+            return Err(TxnExpired {
+                read_ts: self.read_ts(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn check_not_committed(&self) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            bail!("transaction already committed");
+        }
+        Ok(())
+    }
+
+    fn check_not_prepared(&self) -> Result<()> {
+        self.check_not_committed()?;
+        if self.is_prepared() {
+            bail!("transaction already prepared");
+        }
+        Ok(())
+    }
+
+    /// End the transaction with the error a spill of its writes met.
+    fn check_spilled(&self) -> Result<()> {
+        if let Some(e) = self.spill_error.lock().take() {
+            self.committed.store(true, Ordering::SeqCst);
+            return Err(e.context("failed to spill the writes of the transaction"));
+        }
+        Ok(())
+    }
+
+    /// Get the value written by the transaction, or else the one visible at its read ts.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        unimplemented!()
+        self.check_not_committed()?;
+        if let Some(entry) = self.local_storage.get(key) {
+            return Ok(Some(entry.value().clone()).filter(|value| !value.is_empty()));
+        }
+        for run in self.spilled_runs.lock().iter().rev() {
+            if let Some(value) = run.get(key)? {
+                return Ok(Some(value).filter(|value| !value.is_empty()));
+            }
+        }
+        self.key_hashes
+            .lock()
+            .1
+            .insert(self.conflict_granularity.key_hash(key));
+        self.inner.get_at_ts(key, self.read_ts())
     }
 
+    /// Scan the writes of the transaction merged with the snapshot at its read ts, the writes taking precedence.
+    /// The keys the iterator goes over are added to the read set, the keys missing from the range are not checked.
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
-        unimplemented!()
+        self.check_not_committed()?;
+        let (lower_bytes, upper_bytes) = (map_bound(lower), map_bound(upper));
+        let mut local_iter = TxnLocalIteratorBuilder {
+            map: self.writes_in_range(lower, upper)?,
+            iter_builder: |map| map.range((lower_bytes, upper_bytes)),
+            item: (Bytes::new(), Bytes::new()),
+        }
+        .build();
+        local_iter.next()?;
+        let storage_iter = self.inner.scan_at_ts(lower, upper, self.read_ts())?;
+        TxnIterator::create(
+            self.clone(),
+            TwoMergeIterator::create(local_iter, storage_iter)?,
+        )
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) {
-        unimplemented!()
+        assert!(
+            !self.committed.load(Ordering::SeqCst),
+            "transaction already committed"
+        );
+        assert!(!self.is_prepared(), "transaction already prepared");
+        let key = Bytes::copy_from_slice(key);
+        let mut savepoints = self.savepoints.lock();
+        if !savepoints.savepoints.is_empty() {
+            let previous = self.local_storage.get(&key).map(|entry| entry.value().clone());
+            savepoints.undo_log.push((key.clone(), previous));
+        }
+        self.key_hashes
+            .lock()
+            .0
+            .insert(self.conflict_granularity.key_hash(&key));
+        let size = key.len() + value.len();
+        self.local_storage.insert(key, Bytes::copy_from_slice(value));
+        let buffered_size = self.buffered_size.fetch_add(size, Ordering::SeqCst) + size;
+        let threshold = self.inner.txn_spill_threshold();
+        // the undo log needs the previous values in memory
+        if threshold > 0
+            && buffered_size >= threshold
+            && savepoints.savepoints.is_empty()
+            && self.spill_error.lock().is_none()
+        {
+            self.spill();
+        }
+    }
+
+    /// Move the buffered writes to a sorted run on disk. They stay in memory if it fails, and the error is kept
+    /// for `commit` and `prepare` to fail with.
+    fn spill(&self) {
+        let mut spilled_runs = self.spilled_runs.lock();
+        match self.inner.spill_txn_writes(&self.local_storage) {
+            Ok(run) => {
+                // the run is readable before the writes leave the buffer
+                spilled_runs.push(run);
+                self.local_storage.clear();
+                self.buffered_size.store(0, Ordering::SeqCst);
+            }
+            Err(e) => *self.spill_error.lock() = Some(e),
+        }
+    }
+
+    /// Number of sorted runs the buffered writes were spilled to.
+    pub fn num_spilled_runs(&self) -> usize {
+        self.spilled_runs.lock().len()
+    }
+
+    /// The writes of the transaction within a range, merged from the spilled runs and the buffer.
+    fn writes_in_range(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Arc<SkipMap<Bytes, Bytes>>> {
+        let spilled_runs = self.spilled_runs.lock();
+        if spilled_runs.is_empty() {
+            return Ok(self.local_storage.clone());
+        }
+        let writes = SkipMap::new();
+        for run in spilled_runs.iter() {
+            for (key, value) in run.scan(lower, upper)? {
+                writes.insert(key, value);
+            }
+        }
+        for entry in self.local_storage.range((map_bound(lower), map_bound(upper))) {
+            writes.insert(entry.key().clone(), entry.value().clone());
+        }
+        Ok(Arc::new(writes))
     }
 
     pub fn delete(&self, key: &[u8]) {
-        unimplemented!()
+        self.put(key, &[]);
+    }
+
+    /// Mark the current writes of the transaction, so that the later ones can be undone by
+    /// `rollback_to_savepoint`. Savepoints nest.
+    pub fn set_savepoint(&self) -> Result<()> {
+        self.check_not_prepared()?;
+        let write_set = self.key_hashes.lock().0.clone();
+        let mut savepoints = self.savepoints.lock();
+        let undo_log_len = savepoints.undo_log.len();
+        savepoints.savepoints.push(Savepoint {
+            undo_log_len,
+            write_set,
+        });
+        Ok(())
+    }
+
+    /// Undo the writes made since the latest savepoint and remove it. The keys only written since then are no
+    /// longer checked for conflicts under snapshot isolation, the reads stay in the read set.
+    pub fn rollback_to_savepoint(&self) -> Result<()> {
+        self.check_not_prepared()?;
+        let mut savepoints = self.savepoints.lock();
+        let Some(savepoint) = savepoints.savepoints.pop() else {
+            bail!("no savepoint to roll back to");
+        };
+        for (key, previous) in savepoints.undo_log.drain(savepoint.undo_log_len..).rev() {
+            match previous {
+                Some(value) => {
+                    self.local_storage.insert(key, value);
+                }
+                None => {
+                    self.local_storage.remove(&key);
+                }
+            }
+        }
+        self.key_hashes.lock().0 = savepoint.write_set;
+        if savepoints.savepoints.is_empty() {
+            savepoints.undo_log.clear();
+        }
+        Ok(())
     }
 
+    /// Remove the latest savepoint, keeping the writes made since.
+    pub fn pop_savepoint(&self) -> Result<()> {
+        self.check_not_prepared()?;
+        let mut savepoints = self.savepoints.lock();
+        if savepoints.savepoints.pop().is_none() {
+            bail!("no savepoint to pop");
+        }
+        if savepoints.savepoints.is_empty() {
+            savepoints.undo_log.clear();
+        }
+        Ok(())
+    }
+
+    /// Validate the transaction against those committed after its read ts and the prepared ones, then write all
+    /// its writes at a new commit ts. A transaction that wrote nothing always commits, unless it expired. A
+    /// prepared transaction is not validated again, and its commit is durable once this returns. A failure to spill
+    /// the writes ends the transaction with its error.
     pub fn commit(&self) -> Result<()> {
-        unimplemented!()
+        if self.committed.swap(true, Ordering::SeqCst) {
+            bail!("transaction already committed");
+        }
+        let mvcc = self.inner.mvcc();
+        if self.is_prepared() {
+            let _commit_lock = mvcc.commit_lock.lock();
+            if self.reclaimed.load(Ordering::SeqCst) {
+                return Err(TxnExpired {
+                    read_ts: self.read_ts(),
+                }
+                .into());
+            }
+            let name = self.name().expect("prepared transactions are named");
+            let commit_ts = match self
+                .write_batch()
+                .and_then(|batch| self.inner.commit_prepared_txn(&name, &batch))
+            {
+                Ok(commit_ts) => commit_ts,
+                Err(e) => {
+                    // still prepared, so it may be committed or rolled back again
+                    self.committed.store(false, Ordering::SeqCst);
+                    return Err(e);
+                }
+            };
+            let (write_set, _) = std::mem::take(&mut *self.key_hashes.lock());
+            self.record_commit(mvcc, commit_ts, write_set);
+            self.spilled_runs.lock().clear();
+            return Ok(());
+        }
+        self.check_spilled()?;
+        self.check_not_expired()?;
+        let (write_set, read_set) = std::mem::take(&mut *self.key_hashes.lock());
+        if write_set.is_empty() {
+            return Ok(());
+        }
+        let _commit_lock = self.validate(mvcc, &write_set, &read_set)?;
+        let commit_ts = self.inner.write_batch_at_new_ts(&self.write_batch()?)?;
+        self.record_commit(mvcc, commit_ts, write_set);
+        self.spilled_runs.lock().clear();
+        Ok(())
     }
-}
 
-impl Drop for Transaction {
-    fn drop(&mut self) {}
+    /// The first phase of a two-phase commit: validate the transaction as `commit` does, then durably log its
+    /// writes, so that it survives crashes. A prepared transaction cannot conflict anymore, while the transactions
+    /// conflicting with it fail. It must be named, and requires `enable_wal`. Its writes stay invisible until
+    /// `commit`, and after a crash `recovered_prepared_txns` of the storage returns it to be committed or rolled
+    /// back. A conflict, the expiration or a failed spill ends the transaction, as for `commit`.
+    pub fn prepare(&self) -> Result<()> {
+        self.check_not_prepared()?;
+        let Some(name) = self.name() else {
+            bail!("a transaction must be named to be prepared");
+        };
+        self.check_spilled()?;
+        self.check_not_expired()?;
+        let mvcc = self.inner.mvcc();
+        let (write_set, read_set) = self.key_hashes.lock().clone();
+        let _commit_lock = match self.validate(mvcc, &write_set, &read_set) {
+            Ok(commit_lock) => commit_lock,
+            Err(e) => {
+                self.committed.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+        };
+        if mvcc.prepared_txns.lock().contains_key(&name) {
+            bail!("a transaction named {} is already prepared", name);
+        }
+        let read_set = if self.serializable {
+            read_set
+        } else {
+            HashSet::new()
+        };
+        let writes = self
+            .writes_in_range(Bound::Unbounded, Bound::Unbounded)?
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect::<Vec<_>>();
+        let lease = self.expires_at().map(|expires_at| TxnLease {
+            expires_at,
+            reclaimed: self.reclaimed.clone(),
+        });
+        self.inner
+            .prepare_txn(&name, &writes, write_set, read_set, lease)?;
+        self.prepared.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Discard the writes of the transaction and end it. A prepared transaction is durably rolled back, unless
+    /// it expired and was already.
+    pub fn rollback(&self) -> Result<()> {
+        if self.committed.swap(true, Ordering::SeqCst) {
+            bail!("transaction already committed");
+        }
+        if self.is_prepared() {
+            let name = self.name().expect("prepared transactions are named");
+            let _commit_lock = self.inner.mvcc().commit_lock.lock();
+            if self.reclaimed.load(Ordering::SeqCst) {
+                // rolled back by a conflicting transaction
+            } else if let Err(e) = self.inner.rollback_prepared_txn(&name) {
+                self.committed.store(false, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        self.local_storage.clear();
+        self.spilled_runs.lock().clear();
+        *self.key_hashes.lock() = Default::default();
+        Ok(())
+    }
+
+    /// Take the commit lock and check the transaction against those committed after its read ts and the prepared
+    /// ones. The expired prepared transactions it conflicts with are rolled back, and the others waited on up to the
+    /// lock timeout, without the commit lock so that they can be resolved.
+    fn validate<'a>(
+        &self,
+        mvcc: &'a LsmMvccInner,
+        write_set: &HashSet<u32>,
+        read_set: &HashSet<u32>,
+    ) -> Result<MutexGuard<'a, ()>> {
+        let checked_keys = if self.serializable {
+            read_set
+        } else {
+            write_set
+        };
+        let conflicts_with_prepared = |txn: &PreparedTxnData| {
+            !txn.write_key_hashes.is_disjoint(write_set)
+                || !txn.read_key_hashes.is_disjoint(write_set)
+                || (self.serializable && !txn.write_key_hashes.is_disjoint(read_set))
+        };
+        let deadline = Instant::now() + *self.lock_timeout.lock();
+        loop {
+            let commit_lock = mvcc.commit_lock.lock();
+            let conflict = mvcc
+                .committed_txns
+                .lock()
+                .range(self.read_ts() + 1..)
+                .find(|(_, txn)| !txn.key_hashes.is_disjoint(checked_keys))
+                .map(|(commit_ts, _)| *commit_ts);
+            if let Some(conflicting_commit_ts) = conflict {
+                return Err(CommitConflict {
+                    read_ts: self.read_ts(),
+                    conflicting_commit_ts: Some(conflicting_commit_ts),
+                }
+                .into());
+            }
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            let mut pending = Vec::new();
+            for (name, txn) in mvcc.prepared_txns.lock().iter() {
+                if !conflicts_with_prepared(txn) {
+                    continue;
+                }
+                match &txn.lease {
+                    Some(lease) if now >= lease.expires_at => {
+                        expired.push((name.clone(), lease.reclaimed.clone()))
+                    }
+                    lease => pending.push((name.clone(), lease.as_ref().map(|l| l.expires_at))),
+                }
+            }
+            for (name, reclaimed) in expired {
+                self.inner.rollback_prepared_txn(&name)?;
+                reclaimed.store(true, Ordering::SeqCst);
+            }
+            if pending.is_empty() {
+                return Ok(commit_lock);
+            }
+            if now >= deadline {
+                return Err(CommitConflict {
+                    read_ts: self.read_ts(),
+                    conflicting_commit_ts: None,
+                }
+                .into());
+            }
+            drop(commit_lock);
+            // woken up when one is resolved, or expires and can be rolled back
+            let wake_at = pending
+                .iter()
+                .filter_map(|(_, expires_at)| *expires_at)
+                .fold(deadline, Instant::min);
+            let mut prepared_txns = mvcc.prepared_txns.lock();
+            if pending
+                .iter()
+                .all(|(name, _)| prepared_txns.contains_key(name))
+            {
+                mvcc.prepared_txn_resolved.wait_for(
+                    &mut prepared_txns,
+                    wake_at.saturating_duration_since(Instant::now()),
+                );
+            }
+        }
+    }
+
+    fn write_batch(&self) -> Result<Vec<WriteBatchRecord<Bytes>>> {
+        Ok(self
+            .writes_in_range(Bound::Unbounded, Bound::Unbounded)?
+            .iter()
+            .map(|entry| {
+                if entry.value().is_empty() {
+                    WriteBatchRecord::Del(entry.key().clone())
+                } else {
+                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+                }
+            })
+            .collect())
+    }
+
+    /// Record the commit for the validation of the running transactions. Must be called with the commit lock.
+    fn record_commit(&self, mvcc: &LsmMvccInner, commit_ts: u64, write_set: HashSet<u32>) {
+        let mut committed_txns = mvcc.committed_txns.lock();
+        committed_txns.insert(
+            commit_ts,
+            CommittedTxnData {
+                key_hashes: write_set,
+                read_ts: self.read_ts(),
+                commit_ts,
+            },
+        );
+        // running transactions read at or above the watermark, older commits cannot conflict with them
+        let watermark = mvcc.watermark();
+        *committed_txns = committed_txns.split_off(&(watermark + 1));
+    }
 }
 
 type SkipMapRangeIter<'a> =
@@ -69,28 +742,39 @@ pub struct TxnLocalIterator {
     item: (Bytes, Bytes),
 }
 
+impl TxnLocalIterator {
+    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+        entry
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .unwrap_or_default()
+    }
+}
+
 impl StorageIterator for TxnLocalIterator {
     type KeyType<'a> = &'a [u8];
 
     fn value(&self) -> &[u8] {
-        unimplemented!()
+        &self.borrow_item().1
     }
 
     fn key(&self) -> &[u8] {
-        unimplemented!()
+        &self.borrow_item().0
     }
 
     fn is_valid(&self) -> bool {
-        unimplemented!()
+        !self.borrow_item().0.is_empty()
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        let item = self.with_iter_mut(|iter| Self::entry_to_item(iter.next()));
+        self.with_item_mut(|current| *current = item);
+        Ok(())
     }
 }
 
+/// Iterates over the keys of a transaction, skipping the ones it deleted.
 pub struct TxnIterator {
-    _txn: Arc<Transaction>,
+    txn: Arc<Transaction>,
     iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
 }
 
@@ -99,7 +783,24 @@ impl TxnIterator {
         txn: Arc<Transaction>,
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
-        unimplemented!()
+        let mut iter = Self { txn, iter };
+        iter.skip_deletes()?;
+        Ok(iter)
+    }
+
+    /// Move to the next key that is not deleted, and add it to the read set.
+    fn skip_deletes(&mut self) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value().is_empty() {
+            self.iter.next()?;
+        }
+        if self.iter.is_valid() {
+            self.txn
+                .key_hashes
+                .lock()
+                .1
+                .insert(self.txn.conflict_granularity.key_hash(self.iter.key()));
+        }
+        Ok(())
     }
 }
 
@@ -119,7 +820,8 @@ impl StorageIterator for TxnIterator {
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        self.iter.next()?;
+        self.skip_deletes()
     }
 
     fn num_active_iterators(&self) -> usize {
@@ -0,0 +1,594 @@
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use log::LevelFilter;
+
+use crate::block::BlockCache;
+use crate::clock::{default_clock, Clock};
+use crate::compact::{CompactionOptions, SstPartitioner};
+use crate::event_listener::EventListener;
+use crate::fs::{default_file_system, FileSystem, LocalFileSystem};
+use crate::logger::InfoLog;
+use crate::lsm_storage::{LsmStorageOptions, SecondaryCacheOptions};
+use crate::mvcc::txn::ConflictGranularity;
+use crate::rate_limiter::RateLimiter;
+use crate::replication::ReplicationSink;
+use crate::statistics::Statistics;
+use crate::table::{CompressionType, MetadataCaching};
+use crate::write_buffer_manager::WriteBufferManager;
+
+impl LsmStorageOptions {
+    /// A builder starting from the default options.
+    pub fn builder() -> LsmStorageOptionsBuilder {
+        LsmStorageOptionsBuilder {
+            options: Self::default(),
+        }
+    }
+
+    /// Check that the options are consistent with each other. Done when opening the storage, before anything is
+    /// read or written.
+    pub fn validate(&self) -> Result<()> {
+        if self.block_size == 0 {
+            bail!("block_size must be positive");
+        }
+        if self.target_sst_size == 0 {
+            bail!("target_sst_size must be positive");
+        }
+        if let Some(level) = self
+            .target_sst_size_per_level
+            .iter()
+            .skip(1)
+            .position(|size| *size == 0)
+        {
+            bail!(
+                "target_sst_size_per_level of L{} must be positive",
+                level + 1
+            );
+        }
+        if self.num_memtable_limit == 0 {
+            bail!("num_memtable_limit must be at least 1 for the memtable being written");
+        }
+        if self.memtable_stop_writes_trigger != 0
+            && self.memtable_stop_writes_trigger < self.num_memtable_limit
+        {
+            bail!(
+                "memtable_stop_writes_trigger must be at least num_memtable_limit ({}), or writes would wait for \
+                 flushes that never start",
+                self.num_memtable_limit
+            );
+        }
+        if self.max_background_jobs == 0 {
+            bail!("max_background_jobs must be at least 1 to run the flushes");
+        }
+        if self.max_subcompactions == 0 {
+            bail!("max_subcompactions must be at least 1");
+        }
+        if !(0.0..=1.0).contains(&self.tombstone_compaction_ratio) {
+            bail!(
+                "tombstone_compaction_ratio must be between 0 and 1, got {}",
+                self.tombstone_compaction_ratio
+            );
+        }
+        if self.block_cache.is_some() && self.secondary_cache.is_some() {
+            bail!("a secondary cache must be attached to the shared block cache when creating it");
+        }
+        if let Some(secondary_cache) = &self.secondary_cache {
+            if secondary_cache.capacity < self.block_size as u64 {
+                bail!(
+                    "the secondary cache of {} bytes cannot hold a block of {} bytes",
+                    secondary_cache.capacity,
+                    self.block_size
+                );
+            }
+        }
+        if self.txn_conflict_granularity == ConflictGranularity::Prefix(0) {
+            bail!("an empty conflict prefix would make every transaction conflict");
+        }
+        match &self.compaction_options {
+            CompactionOptions::Leveled(options) => {
+                if options.max_levels == 0 || options.level_size_multiplier < 2 {
+                    bail!("leveled compaction needs a level and a level_size_multiplier of at least 2");
+                }
+            }
+            CompactionOptions::Tiered(options) => {
+                if options.num_tiers < 2 || options.min_merge_width < 2 {
+                    bail!("tiered compaction needs num_tiers and min_merge_width of at least 2");
+                }
+            }
+            CompactionOptions::Simple(options) => {
+                if options.max_levels == 0 {
+                    bail!("simple leveled compaction needs a level");
+                }
+            }
+            CompactionOptions::Fifo(_) | CompactionOptions::NoCompaction => {}
+        }
+        Ok(())
+    }
+
+    /// The `file_system`, the local disk if it is not set.
+    pub(crate) fn file_system_or_default(&self) -> Arc<dyn FileSystem> {
+        self.file_system.clone().unwrap_or_else(default_file_system)
+    }
+
+    /// The `clock`, the system clock if it is not set.
+    pub(crate) fn clock_or_default(&self) -> Arc<dyn Clock> {
+        self.clock.clone().unwrap_or_else(default_clock)
+    }
+
+    /// The options the database at `path` was last opened with, from its `OPTIONS` file on the local disk. The
+    /// caches, partitioner, listeners, rate limiter, file system and clock are not persisted and must be set again.
+    pub fn load_latest(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load_latest_from(&LocalFileSystem, path)
+    }
+
+    /// Same as `load_latest`, reading the `OPTIONS` file from `fs`.
+    pub fn load_latest_from(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        Self::load(fs, path)?
+            .with_context(|| format!("{} has no {} file", path.display(), OPTIONS_FILE_NAME))
+    }
+
+    pub(crate) fn load(fs: &dyn FileSystem, dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(OPTIONS_FILE_NAME);
+        if !fs.exists(&path) {
+            return Ok(None);
+        }
+        let json = fs.read(&path)?;
+        let options = serde_json::from_slice(&json)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(options))
+    }
+
+    /// Write the options to the `OPTIONS` file of `dir`, through a temporary file renamed over it so that a crash
+    /// leaves either the old or the new options.
+    pub(crate) fn persist(&self, fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", OPTIONS_FILE_NAME));
+        let mut file = fs.create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync()?;
+        fs.rename(&tmp_path, &dir.join(OPTIONS_FILE_NAME))?;
+        Ok(())
+    }
+
+    /// Check that the database created with the `persisted` options can be opened with these. The compaction
+    /// strategy cannot change, as the manifest records its tasks and layout, except from no compaction to the
+    /// strategies also flushing to L0. Nor can the leveled strategies have fewer levels than the SSTs may be in.
+    pub(crate) fn check_compatible(&self, persisted: &Self) -> Result<()> {
+        let (style, persisted_style) = (
+            compaction_style(&self.compaction_options),
+            compaction_style(&persisted.compaction_options),
+        );
+        let from_l0_only = matches!(
+            persisted.compaction_options,
+            CompactionOptions::NoCompaction
+        ) && !matches!(self.compaction_options, CompactionOptions::Tiered(_));
+        if style != persisted_style && !from_l0_only {
+            bail!(
+                "the database was created with {} compaction and cannot be opened with {} compaction",
+                persisted_style,
+                style
+            );
+        }
+        let max_levels = |options: &CompactionOptions| match options {
+            CompactionOptions::Leveled(options) => options.max_levels,
+            CompactionOptions::Simple(options) => options.max_levels,
+            _ => 0,
+        };
+        let (levels, persisted_levels) = (
+            max_levels(&self.compaction_options),
+            max_levels(&persisted.compaction_options),
+        );
+        if levels < persisted_levels {
+            bail!(
+                "the database was created with {} levels and cannot be opened with {}",
+                persisted_levels,
+                levels
+            );
+        }
+        Ok(())
+    }
+}
+
+impl LsmStorageOptions {
+    /// Set the option `name` to `value`, parsed from a string, if it can change while the storage is open. The
+    /// storage-wide options, such as the block size or the compaction strategy, need reopening.
+    pub(crate) fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        match (name, &mut self.compaction_options) {
+            ("target_sst_size", _) => self.target_sst_size = parse(name, value)?,
+            ("num_memtable_limit", _) => self.num_memtable_limit = parse(name, value)?,
+            ("min_blob_size", _) => self.min_blob_size = parse(name, value)?,
+            ("max_blob_space_amplification_percent", _) => {
+                self.max_blob_space_amplification_percent = parse(name, value)?
+            }
+            ("periodic_compaction_seconds", _) => {
+                self.periodic_compaction_seconds = parse(name, value)?
+            }
+            ("tombstone_compaction_ratio", _) => {
+                self.tombstone_compaction_ratio = parse(name, value)?
+            }
+            ("bottommost_recompaction_bytes", _) => {
+                self.bottommost_recompaction_bytes = parse(name, value)?
+            }
+            ("max_subcompactions", _) => self.max_subcompactions = parse(name, value)?,
+            ("max_manifest_file_size", _) => self.max_manifest_file_size = parse(name, value)?,
+            ("delete_obsolete_files_period_seconds", _) => {
+                self.delete_obsolete_files_period_seconds = parse(name, value)?
+            }
+            ("txn_spill_threshold", _) => self.txn_spill_threshold = parse(name, value)?,
+            ("txn_expiration_millis", _) => self.txn_expiration_millis = parse(name, value)?,
+            ("txn_lock_timeout_millis", _) => self.txn_lock_timeout_millis = parse(name, value)?,
+            ("slow_read_threshold_micros", _) => {
+                self.slow_read_threshold_micros = parse(name, value)?
+            }
+            ("info_log_level", _) => self.info_log_level = parse(name, value)?,
+            ("memtable_stop_writes_trigger", _) => {
+                self.memtable_stop_writes_trigger = parse(name, value)?
+            }
+            ("level0_stop_writes_trigger", _) => {
+                self.level0_stop_writes_trigger = parse(name, value)?
+            }
+            ("hard_pending_compaction_bytes_limit", _) => {
+                self.hard_pending_compaction_bytes_limit = parse(name, value)?
+            }
+            ("cold_bottom_level", _) => self.cold_bottom_level = parse(name, value)?,
+            ("level0_file_num_compaction_trigger", CompactionOptions::Leveled(options)) => {
+                options.level0_file_num_compaction_trigger = parse(name, value)?
+            }
+            ("level0_file_num_compaction_trigger", CompactionOptions::Simple(options)) => {
+                options.level0_file_num_compaction_trigger = parse(name, value)?
+            }
+            ("level_size_multiplier", CompactionOptions::Leveled(options)) => {
+                options.level_size_multiplier = parse(name, value)?
+            }
+            ("base_level_size_mb", CompactionOptions::Leveled(options)) => {
+                options.base_level_size_mb = parse(name, value)?
+            }
+            ("size_ratio_percent", CompactionOptions::Simple(options)) => {
+                options.size_ratio_percent = parse(name, value)?
+            }
+            ("num_tiers", CompactionOptions::Tiered(options)) => {
+                options.num_tiers = parse(name, value)?
+            }
+            ("size_ratio", CompactionOptions::Tiered(options)) => {
+                options.size_ratio = parse(name, value)?
+            }
+            ("min_merge_width", CompactionOptions::Tiered(options)) => {
+                options.min_merge_width = parse(name, value)?
+            }
+            ("max_size_amplification_percent", CompactionOptions::Tiered(options)) => {
+                options.max_size_amplification_percent = parse(name, value)?
+            }
+            ("max_table_files_size", CompactionOptions::Fifo(options)) => {
+                options.max_table_files_size = parse(name, value)?
+            }
+            ("ttl_seconds", CompactionOptions::Fifo(options)) => {
+                options.ttl_seconds = parse(name, value)?
+            }
+            (
+                "level0_file_num_compaction_trigger"
+                | "level_size_multiplier"
+                | "base_level_size_mb"
+                | "size_ratio_percent"
+                | "num_tiers"
+                | "size_ratio"
+                | "min_merge_width"
+                | "max_size_amplification_percent"
+                | "max_table_files_size"
+                | "ttl_seconds",
+                compaction_options,
+            ) => bail!(
+                "{} is not an option of {} compaction",
+                name,
+                compaction_style(compaction_options)
+            ),
+            (
+                "block_size"
+                | "target_sst_size_per_level"
+                | "compression_per_level"
+                | "compaction_options"
+                | "max_background_jobs"
+                | "secondary_cache"
+                | "row_cache_capacity"
+                | "metadata_caching"
+                | "warm_block_cache_on_open"
+                | "enable_wal"
+                | "best_effort_recovery"
+                | "paranoid_checks"
+                | "serializable"
+                | "hybrid_logical_clock"
+                | "txn_conflict_granularity"
+                | "snapshot_warn_age_millis",
+                _,
+            ) => bail!("{} cannot be changed without reopening the storage", name),
+            _ => bail!("unknown option {}", name),
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("invalid value {:?} for {}", value, name))
+}
+
+/// The file holding the options the database was last opened with.
+pub(crate) const OPTIONS_FILE_NAME: &str = "OPTIONS";
+
+fn compaction_style(options: &CompactionOptions) -> &'static str {
+    match options {
+        CompactionOptions::Leveled(_) => "leveled",
+        CompactionOptions::Tiered(_) => "tiered",
+        CompactionOptions::Fifo(_) => "FIFO",
+        CompactionOptions::Simple(_) => "simple leveled",
+        CompactionOptions::NoCompaction => "no",
+    }
+}
+
+/// Builds `LsmStorageOptions`, validated by `build`. Every setter sets the field of the same name.
+#[derive(Debug, Clone)]
+pub struct LsmStorageOptionsBuilder {
+    options: LsmStorageOptions,
+}
+
+impl LsmStorageOptionsBuilder {
+    pub fn build(self) -> Result<LsmStorageOptions> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+
+    // Layout and memtables
+
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.options.block_size = block_size;
+        self
+    }
+
+    pub fn target_sst_size(mut self, target_sst_size: usize) -> Self {
+        self.options.target_sst_size = target_sst_size;
+        self
+    }
+
+    pub fn target_sst_size_per_level(mut self, target_sst_size_per_level: Vec<usize>) -> Self {
+        self.options.target_sst_size_per_level = target_sst_size_per_level;
+        self
+    }
+
+    pub fn min_blob_size(mut self, min_blob_size: usize) -> Self {
+        self.options.min_blob_size = min_blob_size;
+        self
+    }
+
+    pub fn max_blob_space_amplification_percent(mut self, percent: u64) -> Self {
+        self.options.max_blob_space_amplification_percent = percent;
+        self
+    }
+
+    pub fn num_memtable_limit(mut self, num_memtable_limit: usize) -> Self {
+        self.options.num_memtable_limit = num_memtable_limit;
+        self
+    }
+
+    pub fn write_buffer_manager(mut self, write_buffer_manager: Arc<WriteBufferManager>) -> Self {
+        self.options.write_buffer_manager = Some(write_buffer_manager);
+        self
+    }
+
+    pub fn compression_per_level(mut self, compression_per_level: Vec<CompressionType>) -> Self {
+        self.options.compression_per_level = compression_per_level;
+        self
+    }
+
+    // Compaction
+
+    pub fn compaction_options(mut self, compaction_options: CompactionOptions) -> Self {
+        self.options.compaction_options = compaction_options;
+        self
+    }
+
+    pub fn periodic_compaction_seconds(mut self, periodic_compaction_seconds: u64) -> Self {
+        self.options.periodic_compaction_seconds = periodic_compaction_seconds;
+        self
+    }
+
+    pub fn tombstone_compaction_ratio(mut self, tombstone_compaction_ratio: f64) -> Self {
+        self.options.tombstone_compaction_ratio = tombstone_compaction_ratio;
+        self
+    }
+
+    pub fn bottommost_recompaction_bytes(mut self, bottommost_recompaction_bytes: u64) -> Self {
+        self.options.bottommost_recompaction_bytes = bottommost_recompaction_bytes;
+        self
+    }
+
+    pub fn max_subcompactions(mut self, max_subcompactions: usize) -> Self {
+        self.options.max_subcompactions = max_subcompactions;
+        self
+    }
+
+    pub fn max_background_jobs(mut self, max_background_jobs: usize) -> Self {
+        self.options.max_background_jobs = max_background_jobs;
+        self
+    }
+
+    pub fn sst_partitioner(mut self, sst_partitioner: Arc<dyn SstPartitioner>) -> Self {
+        self.options.sst_partitioner = Some(sst_partitioner);
+        self
+    }
+
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.options.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn statistics(mut self, statistics: Arc<Statistics>) -> Self {
+        self.options.statistics = Some(statistics);
+        self
+    }
+
+    pub fn slow_read_threshold_micros(mut self, slow_read_threshold_micros: u64) -> Self {
+        self.options.slow_read_threshold_micros = slow_read_threshold_micros;
+        self
+    }
+
+    pub fn info_log(mut self, info_log: Arc<dyn InfoLog>) -> Self {
+        self.options.info_log = Some(info_log);
+        self
+    }
+
+    pub fn info_log_level(mut self, info_log_level: LevelFilter) -> Self {
+        self.options.info_log_level = info_log_level;
+        self
+    }
+
+    pub fn memtable_stop_writes_trigger(mut self, memtable_stop_writes_trigger: usize) -> Self {
+        self.options.memtable_stop_writes_trigger = memtable_stop_writes_trigger;
+        self
+    }
+
+    pub fn level0_stop_writes_trigger(mut self, level0_stop_writes_trigger: usize) -> Self {
+        self.options.level0_stop_writes_trigger = level0_stop_writes_trigger;
+        self
+    }
+
+    pub fn hard_pending_compaction_bytes_limit(
+        mut self,
+        hard_pending_compaction_bytes_limit: u64,
+    ) -> Self {
+        self.options.hard_pending_compaction_bytes_limit = hard_pending_compaction_bytes_limit;
+        self
+    }
+
+    pub fn cold_bottom_level(mut self, cold_bottom_level: bool) -> Self {
+        self.options.cold_bottom_level = cold_bottom_level;
+        self
+    }
+
+    pub fn inline_background_jobs(mut self, inline_background_jobs: bool) -> Self {
+        self.options.inline_background_jobs = inline_background_jobs;
+        self
+    }
+
+    pub fn event_listener(mut self, event_listener: Arc<dyn EventListener>) -> Self {
+        self.options.event_listeners.push(event_listener);
+        self
+    }
+
+    pub fn replication_sink(mut self, replication_sink: Arc<dyn ReplicationSink>) -> Self {
+        self.options.replication_sinks.push(replication_sink);
+        self
+    }
+
+    pub fn file_system(mut self, file_system: Arc<dyn FileSystem>) -> Self {
+        self.options.file_system = Some(file_system);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.options.clock = Some(clock);
+        self
+    }
+
+    // Caches
+
+    pub fn block_cache(mut self, block_cache: Arc<BlockCache>) -> Self {
+        self.options.block_cache = Some(block_cache);
+        self
+    }
+
+    pub fn secondary_cache(mut self, secondary_cache: SecondaryCacheOptions) -> Self {
+        self.options.secondary_cache = Some(secondary_cache);
+        self
+    }
+
+    pub fn row_cache_capacity(mut self, row_cache_capacity: usize) -> Self {
+        self.options.row_cache_capacity = row_cache_capacity;
+        self
+    }
+
+    pub fn metadata_caching(mut self, metadata_caching: MetadataCaching) -> Self {
+        self.options.metadata_caching = metadata_caching;
+        self
+    }
+
+    pub fn warm_block_cache_on_open(mut self, warm_block_cache_on_open: bool) -> Self {
+        self.options.warm_block_cache_on_open = warm_block_cache_on_open;
+        self
+    }
+
+    // Durability and recovery
+
+    pub fn enable_wal(mut self, enable_wal: bool) -> Self {
+        self.options.enable_wal = enable_wal;
+        self
+    }
+
+    pub fn max_manifest_file_size(mut self, max_manifest_file_size: u64) -> Self {
+        self.options.max_manifest_file_size = max_manifest_file_size;
+        self
+    }
+
+    pub fn best_effort_recovery(mut self, best_effort_recovery: bool) -> Self {
+        self.options.best_effort_recovery = best_effort_recovery;
+        self
+    }
+
+    pub fn paranoid_checks(mut self, paranoid_checks: bool) -> Self {
+        self.options.paranoid_checks = paranoid_checks;
+        self
+    }
+
+    pub fn delete_obsolete_files_period_seconds(
+        mut self,
+        delete_obsolete_files_period_seconds: u64,
+    ) -> Self {
+        self.options.delete_obsolete_files_period_seconds = delete_obsolete_files_period_seconds;
+        self
+    }
+
+    // Transactions and snapshots
+
+    pub fn serializable(mut self, serializable: bool) -> Self {
+        self.options.serializable = serializable;
+        self
+    }
+
+    pub fn hybrid_logical_clock(mut self, hybrid_logical_clock: bool) -> Self {
+        self.options.hybrid_logical_clock = hybrid_logical_clock;
+        self
+    }
+
+    pub fn txn_spill_threshold(mut self, txn_spill_threshold: usize) -> Self {
+        self.options.txn_spill_threshold = txn_spill_threshold;
+        self
+    }
+
+    pub fn txn_conflict_granularity(
+        mut self,
+        txn_conflict_granularity: ConflictGranularity,
+    ) -> Self {
+        self.options.txn_conflict_granularity = txn_conflict_granularity;
+        self
+    }
+
+    pub fn txn_expiration_millis(mut self, txn_expiration_millis: u64) -> Self {
+        self.options.txn_expiration_millis = txn_expiration_millis;
+        self
+    }
+
+    pub fn txn_lock_timeout_millis(mut self, txn_lock_timeout_millis: u64) -> Self {
+        self.options.txn_lock_timeout_millis = txn_lock_timeout_millis;
+        self
+    }
+
+    pub fn snapshot_warn_age_millis(mut self, snapshot_warn_age_millis: u64) -> Self {
+        self.options.snapshot_warn_age_millis = snapshot_warn_age_millis;
+        self
+    }
+}
@@ -0,0 +1,98 @@
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use crate::clock::Instant;
+
+/// What the `PerfContext` of a thread records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PerfLevel {
+    /// Nothing, the default.
+    #[default]
+    Disable,
+    /// The counts only.
+    EnableCount,
+    /// The counts and the timings, which read the clock around every step timed.
+    EnableTime,
+}
+
+/// Counts and timings of the operations run on the current thread since the last `reset_perf_context`, at the
+/// `PerfLevel` set for the thread. Reset it before a get, scan or write and read it after to see where the time
+/// of that one request went. The async gets count on the threads of the runtime they run on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PerfContext {
+    /// Memtables probed by the gets, and the time spent probing them.
+    pub get_from_memtable_count: u64,
+    pub get_from_memtable_time: Duration,
+    /// Time the gets spent looking up the SSTs.
+    pub get_from_output_files_time: Duration,
+    /// Lookups of an SST its bloom filter let through, and the ones it ruled out.
+    pub bloom_sst_hit_count: u64,
+    pub bloom_sst_miss_count: u64,
+    /// Data blocks found in the block cache.
+    pub block_cache_hit_count: u64,
+    /// Data blocks read from the SSTs, their bytes, and the time spent reading them.
+    pub block_read_count: u64,
+    pub block_read_byte: u64,
+    pub block_read_time: Duration,
+    /// Time the scans spent creating their iterators and seeking them to the start of the range.
+    pub iter_seek_time: Duration,
+    /// Calls to `next` of the scan iterators.
+    pub iter_next_count: u64,
+    /// Versions the scan iterators went past: shadowed, above the read ts, or deleted.
+    pub internal_key_skipped_count: u64,
+    /// Time the writes waited for the other writes to finish.
+    pub write_thread_wait_time: Duration,
+    /// Time the writes spent appending to the WAL, then inserting into the memtables.
+    pub write_wal_time: Duration,
+    pub write_memtable_time: Duration,
+    /// Time spent syncing the WAL.
+    pub wal_sync_time: Duration,
+}
+
+thread_local! {
+    static PERF_LEVEL: Cell<PerfLevel> = const { Cell::new(PerfLevel::Disable) };
+    static PERF_CONTEXT: RefCell<PerfContext> = RefCell::new(PerfContext::default());
+}
+
+/// Set what the `PerfContext` of the current thread records.
+pub fn set_perf_level(level: PerfLevel) {
+    PERF_LEVEL.with(|perf_level| perf_level.set(level));
+}
+
+pub fn perf_level() -> PerfLevel {
+    PERF_LEVEL.with(|perf_level| perf_level.get())
+}
+
+/// The `PerfContext` of the current thread.
+pub fn perf_context() -> PerfContext {
+    PERF_CONTEXT.with(|context| context.borrow().clone())
+}
+
+/// Zero the `PerfContext` of the current thread.
+pub fn reset_perf_context() {
+    PERF_CONTEXT.with(|context| *context.borrow_mut() = PerfContext::default());
+}
+
+/// Update the `PerfContext` of the current thread, unless it is disabled.
+pub(crate) fn perf_count(update: impl FnOnce(&mut PerfContext)) {
+    if perf_level() >= PerfLevel::EnableCount {
+        PERF_CONTEXT.with(|context| update(&mut context.borrow_mut()));
+    }
+}
+
+/// Times a step for the `PerfContext` of the current thread, reading the clock only at `PerfLevel::EnableTime`.
+pub(crate) struct PerfTimer(Option<Instant>);
+
+impl PerfTimer {
+    pub(crate) fn start() -> Self {
+        Self((perf_level() >= PerfLevel::EnableTime).then(Instant::now))
+    }
+
+    /// Add the time since the start to the timing `field` returns.
+    pub(crate) fn stop(self, field: impl FnOnce(&mut PerfContext) -> &mut Duration) {
+        if let Some(started) = self.0 {
+            let elapsed = started.elapsed();
+            PERF_CONTEXT.with(|context| *field(&mut context.borrow_mut()) += elapsed);
+        }
+    }
+}
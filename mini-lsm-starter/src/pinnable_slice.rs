@@ -0,0 +1,76 @@
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::block::Block;
+
+/// A value returned by `get_pinned`. A value read from an SST references the decoded block it lives in, keeping that
+/// block alive (even after the block cache evicts it) until the slice is dropped, so it is never copied out. Values
+/// from the memtables or the row cache share their buffers.
+#[derive(Clone)]
+pub struct PinnableSlice(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Bytes(Bytes),
+    Block {
+        block: Arc<Block>,
+        range: Range<usize>,
+    },
+}
+
+impl PinnableSlice {
+    pub(crate) fn from_bytes(bytes: Bytes) -> Self {
+        Self(Repr::Bytes(bytes))
+    }
+
+    pub(crate) fn from_block(block: Arc<Block>, range: Range<usize>) -> Self {
+        Self(Repr::Block { block, range })
+    }
+
+    /// Whether the slice pins a block rather than owning a buffer.
+    pub fn is_pinned(&self) -> bool {
+        matches!(self.0, Repr::Block { .. })
+    }
+
+    /// Convert into `Bytes`. Values pinned in a block are copied, everything else is a cheap clone.
+    pub fn to_bytes(&self) -> Bytes {
+        match &self.0 {
+            Repr::Bytes(bytes) => bytes.clone(),
+            Repr::Block { block, range } => Bytes::copy_from_slice(&block.data[range.clone()]),
+        }
+    }
+}
+
+impl Deref for PinnableSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Bytes(bytes) => bytes,
+            Repr::Block { block, range } => &block.data[range.clone()],
+        }
+    }
+}
+
+impl AsRef<[u8]> for PinnableSlice {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl std::fmt::Debug for PinnableSlice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PinnableSlice")
+            .field("value", &Bytes::copy_from_slice(self))
+            .field("pinned", &self.is_pinned())
+            .finish()
+    }
+}
+
+impl PartialEq<[u8]> for PinnableSlice {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
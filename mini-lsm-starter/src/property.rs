@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::table::SsTable;
+use crate::write_stall::WriteStallCondition;
+
+/// The prefix of every property name.
+pub const PROPERTY_PREFIX: &str = "cobblesdb.";
+
+/// Number of files listed by `cobblesdb.hot-files`.
+const HOT_FILES: usize = 10;
+
+/// The properties `get_property` knows, without the per-level ones which take the level after their name, e.g.
+/// `cobblesdb.num-files-at-level2`: `num-files-at-level`, `size-at-level` and `compression-ratio-at-level`.
+pub const PROPERTIES: &[&str] = &[
+    "cobblesdb.num-levels",
+    "cobblesdb.total-sst-files-size",
+    "cobblesdb.estimate-pending-compaction-bytes",
+    "cobblesdb.estimate-num-keys",
+    "cobblesdb.num-immutable-mem-table",
+    "cobblesdb.cur-size-active-mem-table",
+    "cobblesdb.cur-size-all-mem-tables",
+    "cobblesdb.num-entries-active-mem-table",
+    "cobblesdb.num-deletes-active-mem-table",
+    "cobblesdb.block-cache-capacity",
+    "cobblesdb.block-cache-usage",
+    "cobblesdb.block-cache-pinned-usage",
+    "cobblesdb.num-snapshots",
+    "cobblesdb.oldest-snapshot-age-seconds",
+    "cobblesdb.latest-commit-ts",
+    "cobblesdb.levelstats",
+    "cobblesdb.sstables",
+    "cobblesdb.hot-files",
+    "cobblesdb.is-write-stopped",
+    "cobblesdb.stats",
+];
+
+impl LsmStorageInner {
+    /// The property `name` of the default column family, one of `PROPERTIES` or a per-level property, formatted
+    /// as a string; `None` if there is no such property, or for `cobblesdb.stats` without the `statistics` option.
+    /// For quick introspection from scripts; the typed APIs the properties come from are cheaper to poll.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        let name = name.strip_prefix(PROPERTY_PREFIX)?;
+        let per_level = |prefix: &str| -> Option<usize> { name.strip_prefix(prefix)?.parse().ok() };
+        if let Some(level) = per_level("num-files-at-level") {
+            return Some(self.level_ssts(level)?.len().to_string());
+        }
+        if let Some(level) = per_level("size-at-level") {
+            let ssts = self.level_ssts(level)?;
+            return Some(
+                ssts.iter()
+                    .map(|sst| sst.table_size())
+                    .sum::<u64>()
+                    .to_string(),
+            );
+        }
+        if let Some(level) = per_level("compression-ratio-at-level") {
+            let ssts = self.level_ssts(level)?;
+            let raw_size = ssts
+                .iter()
+                .map(|sst| sst.properties().raw_key_size + sst.properties().raw_value_size)
+                .sum::<u64>();
+            let size = ssts.iter().map(|sst| sst.table_size()).sum::<u64>();
+            let ratio = if size == 0 {
+                0.0
+            } else {
+                raw_size as f64 / size as f64
+            };
+            return Some(format!("{:.3}", ratio));
+        }
+        let state = self.state.read().clone();
+        let value = match name {
+            "num-levels" => (state.levels.len() + 1).to_string(),
+            "total-sst-files-size" => state
+                .sstables
+                .values()
+                .map(|sst| sst.table_size())
+                .sum::<u64>()
+                .to_string(),
+            "estimate-pending-compaction-bytes" => self
+                .compaction_stats()
+                .levels
+                .iter()
+                .map(|level| level.pending_compaction_bytes)
+                .sum::<u64>()
+                .to_string(),
+            "estimate-num-keys" => self.estimate_num_keys().to_string(),
+            "num-immutable-mem-table" => state.imm_memtables.len().to_string(),
+            "cur-size-active-mem-table" => state.memtable.approximate_size().to_string(),
+            "cur-size-all-mem-tables" => std::iter::once(&state.memtable)
+                .chain(&state.imm_memtables)
+                .map(|memtable| memtable.approximate_size())
+                .sum::<usize>()
+                .to_string(),
+            "num-entries-active-mem-table" => state.memtable.num_entries().to_string(),
+            "num-deletes-active-mem-table" => state.memtable.num_deletions().to_string(),
+            "block-cache-capacity" => self.block_cache.capacity().to_string(),
+            "block-cache-usage" => self.block_cache.usage().to_string(),
+            "block-cache-pinned-usage" => self.block_cache.stats().pinned_usage.to_string(),
+            "num-snapshots" => self.mvcc().snapshot_stats().num_snapshots.to_string(),
+            "oldest-snapshot-age-seconds" => self
+                .mvcc()
+                .snapshot_stats()
+                .oldest_age
+                .map_or(0, |age| age.as_secs())
+                .to_string(),
+            "latest-commit-ts" => self.mvcc().latest_commit_ts().to_string(),
+            "levelstats" => self.level_stats(),
+            "sstables" => self.dump_structure().to_string(),
+            "is-write-stopped" => {
+                let stopped = self.write_stall_stats().current.condition;
+                u64::from(stopped == WriteStallCondition::Stopped).to_string()
+            }
+            "hot-files" => self
+                .hottest_files(HOT_FILES)
+                .iter()
+                .map(|file| format!("{}\n", file))
+                .collect(),
+            "stats" => self.statistics()?.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Same as `get_property`, for the properties that are integers.
+    pub fn get_int_property(&self, name: &str) -> Option<u64> {
+        self.get_property(name)?.parse().ok()
+    }
+
+    /// The SSTs of `level` of the default column family, 0 for L0, `None` past the last level. For tiered
+    /// compaction the levels are the sorted runs, newest first.
+    fn level_ssts(&self, level: usize) -> Option<Vec<Arc<SsTable>>> {
+        let state = self.state.read().clone();
+        let ids = if level == 0 {
+            &state.l0_sstables
+        } else {
+            &state.levels.get(level - 1)?.1
+        };
+        Some(ids.iter().map(|id| state.sstables[id].clone()).collect())
+    }
+}
+
+impl MiniLsm {
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        self.inner.get_property(name)
+    }
+
+    pub fn get_int_property(&self, name: &str) -> Option<u64> {
+        self.inner.get_int_property(name)
+    }
+}
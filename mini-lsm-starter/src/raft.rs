@@ -0,0 +1,84 @@
+use anyhow::{bail, Result};
+
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use crate::lsm_storage::{LsmStorageInner, MiniLsm, WriteBatchRecord};
+
+/// The column family holding the index of the last Raft log entry applied, created by the first
+/// `apply_batch_with_index`. It is reserved: nothing else should write to it.
+pub const RAFT_STATE_COLUMN_FAMILY_NAME: &str = "raft_state";
+
+const APPLIED_INDEX_KEY: &[u8] = b"applied_index";
+
+impl LsmStorageInner {
+    fn raft_state_cf(&self) -> Result<ColumnFamily> {
+        match self.cf(RAFT_STATE_COLUMN_FAMILY_NAME) {
+            Some(cf) => Ok(cf),
+            None => self.create_cf(RAFT_STATE_COLUMN_FAMILY_NAME),
+        }
+    }
+
+    /// Apply the writes of the Raft log entry `raft_index` to the default column family. The index is written with
+    /// them in a single WAL record, so that after a crash the database has both or neither, and
+    /// `last_applied_index` tells where to resume applying the log. The entries must be applied in index order by a
+    /// single thread, as a Raft state machine does: an entry not after the last one applied was applied already and
+    /// is skipped, returning false. An entry without writes still moves the index.
+    pub fn apply_batch_with_index<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        raft_index: u64,
+    ) -> Result<bool> {
+        if raft_index == 0 {
+            bail!("raft index must be positive");
+        }
+        if raft_index <= self.last_applied_index()? {
+            return Ok(false);
+        }
+        let cf = self.raft_state_cf()?;
+        let batch = batch
+            .iter()
+            .map(|record| match record {
+                WriteBatchRecord::Put(key, value) => {
+                    WriteBatchRecord::Put(key.as_ref(), value.as_ref())
+                }
+                WriteBatchRecord::Del(key) => WriteBatchRecord::Del(key.as_ref()),
+            })
+            .collect::<Vec<_>>();
+        let index = raft_index.to_be_bytes();
+        let index_batch = [WriteBatchRecord::Put(APPLIED_INDEX_KEY, &index[..])];
+        let mut batches = vec![(cf.id(), &index_batch[..])];
+        if !batch.is_empty() {
+            batches.insert(0, (DEFAULT_COLUMN_FAMILY_ID, &batch[..]));
+        }
+        self.write_cf_batches_with_new_ts(&batches, None)?;
+        Ok(true)
+    }
+
+    /// The index of the last Raft log entry `apply_batch_with_index` applied, recovered with the writes after a
+    /// restart, 0 if none was.
+    pub fn last_applied_index(&self) -> Result<u64> {
+        let Some(cf) = self.cf(RAFT_STATE_COLUMN_FAMILY_NAME) else {
+            return Ok(0);
+        };
+        let Some(value) = self.get_cf(&cf, APPLIED_INDEX_KEY)? else {
+            return Ok(0);
+        };
+        match <[u8; 8]>::try_from(value.as_ref()) {
+            Ok(index) => Ok(u64::from_be_bytes(index)),
+            Err(_) => bail!("applied raft index is corrupted"),
+        }
+    }
+}
+
+impl MiniLsm {
+    pub fn apply_batch_with_index<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        raft_index: u64,
+    ) -> Result<bool> {
+        self.inner.apply_batch_with_index(batch, raft_index)
+    }
+
+    pub fn last_applied_index(&self) -> Result<u64> {
+        self.inner.last_applied_index()
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+
+use crate::clock::Instant;
+
+/// How often an auto-tuned limiter adjusts its rate.
+const TUNE_INTERVAL: Duration = Duration::from_millis(100);
+/// An auto-tuned limiter waits for this many reads before judging the latency.
+const MIN_LATENCY_SAMPLES: usize = 20;
+/// An auto-tuned limiter never goes below this fraction of its maximum rate, nor moves up by more at once.
+const RATE_STEP_DIVISOR: u64 = 20;
+
+/// Throttles the bytes compactions read and write, so that they leave disk bandwidth to the foreground reads. It
+/// may be shared by several databases to bound their compactions together.
+///
+/// A fixed limiter allows `bytes_per_sec`. An auto-tuned limiter starts at its maximum rate and halves it whenever
+/// the p99 latency of the reads reaching the SSTs goes over the target, then raises it back step by step while the
+/// latency stays under it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_bytes_per_sec: u64,
+    read_latency_target: Option<Duration>,
+    state: Mutex<RateLimiterState>,
+}
+
+#[derive(Debug)]
+struct RateLimiterState {
+    bytes_per_sec: u64,
+    /// Bytes that may be consumed without waiting, negative while the requests are ahead of the rate.
+    available: f64,
+    last_refill: Instant,
+    read_latencies: Vec<Duration>,
+    last_tune: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self::create(bytes_per_sec, None)
+    }
+
+    /// A limiter adjusting its rate, up to `max_bytes_per_sec`, to keep the p99 foreground read latency under
+    /// `read_latency_target`.
+    pub fn new_auto_tuned(max_bytes_per_sec: u64, read_latency_target: Duration) -> Self {
+        Self::create(max_bytes_per_sec, Some(read_latency_target))
+    }
+
+    fn create(max_bytes_per_sec: u64, read_latency_target: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            max_bytes_per_sec: max_bytes_per_sec.max(1),
+            read_latency_target,
+            state: Mutex::new(RateLimiterState {
+                bytes_per_sec: max_bytes_per_sec.max(1),
+                available: 0.0,
+                last_refill: now,
+                read_latencies: Vec::new(),
+                last_tune: now,
+            }),
+        }
+    }
+
+    /// The rate currently allowed.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.state.lock().bytes_per_sec
+    }
+
+    /// Change the rate of a fixed limiter, an auto-tuned one keeps tuning its own.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) -> Result<()> {
+        if self.is_auto_tuned() {
+            bail!("the rate of an auto-tuned rate limiter cannot be set");
+        }
+        self.state.lock().bytes_per_sec = bytes_per_sec.max(1);
+        Ok(())
+    }
+
+    pub fn is_auto_tuned(&self) -> bool {
+        self.read_latency_target.is_some()
+    }
+
+    /// Consume `bytes`, sleeping as long as the requests so far are ahead of the rate.
+    pub fn request(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock();
+            self.tune(&mut state);
+            let now = Instant::now();
+            let rate = state.bytes_per_sec as f64;
+            // at most 100ms worth of unused bandwidth is saved up for bursts
+            state.available = (state.available
+                + now.duration_since(state.last_refill).as_secs_f64() * rate)
+                .min(rate / 10.0);
+            state.last_refill = now;
+            state.available -= bytes as f64;
+            if state.available >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-state.available / rate)
+        };
+        std::thread::sleep(wait);
+    }
+
+    /// Record the latency of a foreground read, ignored unless the limiter is auto-tuned.
+    pub fn record_read_latency(&self, latency: Duration) {
+        if self.is_auto_tuned() {
+            let mut state = self.state.lock();
+            state.read_latencies.push(latency);
+            self.tune(&mut state);
+        }
+    }
+
+    fn tune(&self, state: &mut RateLimiterState) {
+        let Some(target) = self.read_latency_target else {
+            return;
+        };
+        if state.last_tune.elapsed() < TUNE_INTERVAL {
+            return;
+        }
+        let step = (self.max_bytes_per_sec / RATE_STEP_DIVISOR).max(1);
+        if state.read_latencies.len() < MIN_LATENCY_SAMPLES {
+            // too few reads to be slowed down by the compactions
+            if state.read_latencies.is_empty() {
+                state.bytes_per_sec = (state.bytes_per_sec + step).min(self.max_bytes_per_sec);
+                state.last_tune = Instant::now();
+            }
+            return;
+        }
+        let latencies = &mut state.read_latencies;
+        latencies.sort();
+        let p99 = latencies[latencies.len() * 99 / 100];
+        latencies.clear();
+        state.bytes_per_sec = if p99 > target {
+            (state.bytes_per_sec / 2).max(step)
+        } else {
+            (state.bytes_per_sec + step).min(self.max_bytes_per_sec)
+        };
+        state.last_tune = Instant::now();
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::compact::CompactionOptions;
+use crate::fs::FileSystem;
+use crate::logger::db_log;
+use crate::lsm_storage::{new_uuid, LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm};
+use crate::manifest::{manifest_file_name, manifest_number, set_current, Manifest, ManifestRecord};
+use crate::table::{FileChecksum, FileObject, SsTable};
+
+/// Where repairing moves the files it does not use, in the database directory.
+pub const LOST_DIR_NAME: &str = "lost";
+
+/// What `repair` salvaged.
+#[derive(Debug, Clone, Default)]
+pub struct RepairSummary {
+    /// The SSTs in the rebuilt manifest.
+    pub sst_ids: Vec<usize>,
+    /// The unreadable SSTs and old manifests, now in the `lost` directory.
+    pub lost_files: Vec<PathBuf>,
+}
+
+impl LsmStorageInner {
+    /// Rebuild the manifest of the database at `path` from its SSTs, for when the manifest is lost or corrupted.
+    /// The database must not be open.
+    ///
+    /// Every SST that opens and whose blocks all pass their checksums is kept, the others are moved to `lost`. They
+    /// are assigned to levels (sorted runs for tiered compaction) from the oldest to the newest by their largest
+    /// timestamp, so that every SST is above the older ones it overlaps. Compacted SSTs whose deletion was
+    /// interrupted come back, so removed versions may be visible again until they are compacted.
+    /// The repaired database keeps the identity most SSTs were written with. Which memtables were flushed is lost
+    /// with the manifest, so all the WALs left are replayed on the next open: the unflushed writes come back, and so
+    /// do the versions of the WALs already flushed, at their original timestamps. SSTs do not record their column
+    /// family, so all of them go to the default one.
+    pub fn repair(path: impl AsRef<Path>, options: &LsmStorageOptions) -> Result<RepairSummary> {
+        let path = path.as_ref();
+        let fs = options.file_system_or_default();
+        let mut summary = RepairSummary::default();
+        let mut ssts = Vec::new();
+        let mut manifests = Vec::new();
+        for file_name in fs.list_dir(path)? {
+            if file_name.starts_with("MANIFEST") {
+                manifests.push(file_name.to_string());
+            }
+            let Some(sst_id) = file_name
+                .strip_suffix(".sst")
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            match Self::open_for_repair(fs.as_ref(), path, sst_id) {
+                Ok(sst) => ssts.push(sst),
+                Err(e) => {
+                    db_log!(options, Warn, "{}.sst is unreadable: {:#}", sst_id, e);
+                    summary
+                        .lost_files
+                        .push(Self::move_to_lost(fs.as_ref(), path, &file_name)?);
+                }
+            }
+        }
+
+        let db_id = Self::db_id_of_ssts(&ssts).unwrap_or_else(new_uuid);
+        let state = Self::assign_levels(options, ssts);
+        summary.sst_ids = state.sstables.keys().copied().collect();
+        summary.sst_ids.sort();
+        let file_checksums = summary
+            .sst_ids
+            .iter()
+            .map(|sst_id| {
+                let checksum =
+                    FileChecksum::of_file(fs.as_ref(), &Self::path_of_sst_static(path, *sst_id))?;
+                Ok((*sst_id, checksum))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let number = manifests
+            .iter()
+            .map(|name| manifest_number(Path::new(name)))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let name = manifest_file_name(number);
+        let manifest = Manifest::create(&fs, path.join(&name))?;
+        manifest.add_record_when_init(ManifestRecord::Snapshot {
+            l0_sstables: state.l0_sstables.clone(),
+            levels: state.levels.clone(),
+            next_sst_id: summary.sst_ids.last().map_or(1, |id| id + 1),
+            db_id,
+            file_checksums,
+            // which memtables were flushed is lost with the manifest, all the WALs left are recovered
+            oldest_memtable_id: 0,
+            commit_ts_bound: 0,
+            next_column_family_id: 0,
+        })?;
+        set_current(fs.as_ref(), path, &name)?;
+        for manifest in manifests {
+            summary
+                .lost_files
+                .push(Self::move_to_lost(fs.as_ref(), path, &manifest)?);
+        }
+        db_log!(
+            options,
+            Info,
+            "repaired with {} SSTs, L0: {:?}, levels: {:?}",
+            summary.sst_ids.len(),
+            state.l0_sstables,
+            state.levels
+        );
+        Ok(summary)
+    }
+
+    fn open_for_repair(fs: &dyn FileSystem, path: &Path, sst_id: usize) -> Result<SsTable> {
+        let sst = SsTable::open(
+            sst_id,
+            None,
+            FileObject::open(fs, &Self::path_of_sst_static(path, sst_id))?,
+        )?;
+        for block_idx in 0..sst.num_of_blocks() {
+            sst.read_block(block_idx)
+                .with_context(|| format!("block {} is corrupted", block_idx))?;
+        }
+        Ok(sst)
+    }
+
+    /// The database most SSTs were written by, which is kept as the identity of the repaired database.
+    fn db_id_of_ssts(ssts: &[SsTable]) -> Option<String> {
+        let mut counts = HashMap::<&str, usize>::new();
+        for sst in ssts {
+            let db_id = &sst.properties().db_id;
+            if !db_id.is_empty() {
+                *counts.entry(db_id).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(db_id, _)| db_id.to_string())
+    }
+
+    pub(crate) fn move_to_lost(
+        fs: &dyn FileSystem,
+        path: &Path,
+        file_name: &str,
+    ) -> Result<PathBuf> {
+        let lost_dir = path.join(LOST_DIR_NAME);
+        fs.create_dir_all(&lost_dir)?;
+        let lost_path = lost_dir.join(file_name);
+        fs.rename(&path.join(file_name), &lost_path)?;
+        Ok(lost_path)
+    }
+
+    /// Assign the SSTs to levels, keeping every SST above the older ones it overlaps. An SST goes into the level
+    /// right above the highest one it overlaps, L0 when it overlaps L1 or an L0 SST, and the bottom level when it
+    /// overlaps nothing.
+    fn assign_levels(options: &LsmStorageOptions, mut ssts: Vec<SsTable>) -> LsmStorageState {
+        let mut state = LsmStorageState::create(options);
+        let tiered = matches!(options.compaction_options, CompactionOptions::Tiered(_));
+        ssts.sort_by_key(|sst| (sst.max_ts(), sst.sst_id()));
+        for sst in ssts {
+            let sst_id = sst.sst_id();
+            let overlaps = |ids: &[usize]| {
+                ids.iter().any(|id| {
+                    let other = &state.sstables[id];
+                    other.first_key().key_ref() <= sst.last_key().key_ref()
+                        && sst.first_key().key_ref() <= other.last_key().key_ref()
+                })
+            };
+            let in_l0 = state.l0_sstables.iter().any(|id| overlaps(&[*id]));
+            let highest_overlapping = state.levels.iter().position(|(_, ids)| overlaps(ids));
+            match (in_l0, highest_overlapping) {
+                (false, None) if !state.levels.is_empty() => {
+                    state.levels.last_mut().unwrap().1.push(sst_id)
+                }
+                (false, Some(level)) if level > 0 => state.levels[level - 1].1.push(sst_id),
+                _ if tiered => state.levels.insert(0, (sst_id, vec![sst_id])),
+                _ => state.l0_sstables.insert(0, sst_id),
+            }
+            state.sstables.insert(sst_id, Arc::new(sst));
+        }
+        for (_, ids) in &mut state.levels {
+            ids.sort_by(|x, y| {
+                state.sstables[x]
+                    .first_key()
+                    .cmp(state.sstables[y].first_key())
+            });
+        }
+        state
+    }
+}
+
+impl MiniLsm {
+    /// Rebuild the manifest of the database at `path` from its SSTs, see `LsmStorageInner::repair`.
+    pub fn repair(path: impl AsRef<Path>, options: &LsmStorageOptions) -> Result<RepairSummary> {
+        LsmStorageInner::repair(path, options)
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt::Debug;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm, WriteBatchRecord};
+
+const PUT: u8 = 0;
+const DEL: u8 = 1;
+
+/// A committed write batch, as shipped from a primary to its followers: the records of every column family it
+/// wrote to, by id, and the commit ts they were all written at. The commit ts of the batches a database commits
+/// always go up, so they sequence the batches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicatedBatch {
+    pub commit_ts: u64,
+    pub column_families: Vec<(u32, Vec<WriteBatchRecord<Bytes>>)>,
+}
+
+/// Receives the write batches of a database once they are committed, e.g. to ship them to followers. A batch is
+/// committed once it is in the WAL and the memtables, as all the writes and transactions do, but the values put by
+/// streaming and the ingested SSTs are not batches and do not come here. The callback runs with the write lock
+/// held, so that the batches come in commit order, and every write waits for it: it should only hand the batch
+/// over, e.g. to a channel.
+pub trait ReplicationSink: Send + Sync + Debug {
+    fn on_batch_committed(&self, batch: &ReplicatedBatch);
+}
+
+impl ReplicatedBatch {
+    pub(crate) fn new<T: AsRef<[u8]>>(
+        commit_ts: u64,
+        batches: &[(u32, &[WriteBatchRecord<T>])],
+    ) -> Self {
+        let column_families = batches
+            .iter()
+            .map(|(cf_id, batch)| {
+                let records = batch
+                    .iter()
+                    .map(|record| match record {
+                        WriteBatchRecord::Put(key, value) => WriteBatchRecord::Put(
+                            Bytes::copy_from_slice(key.as_ref()),
+                            Bytes::copy_from_slice(value.as_ref()),
+                        ),
+                        WriteBatchRecord::Del(key) => {
+                            WriteBatchRecord::Del(Bytes::copy_from_slice(key.as_ref()))
+                        }
+                    })
+                    .collect();
+                (*cf_id, records)
+            })
+            .collect();
+        Self {
+            commit_ts,
+            column_families,
+        }
+    }
+
+    /// The records written to the default column family.
+    pub fn records(&self) -> &[WriteBatchRecord<Bytes>] {
+        self.column_families
+            .iter()
+            .find(|(cf_id, _)| *cf_id == DEFAULT_COLUMN_FAMILY_ID)
+            .map_or(&[], |(_, records)| records.as_slice())
+    }
+
+    /// The batch as bytes, to ship it: the commit ts, then the records of each column family.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = Vec::new();
+        buf.put_u64(self.commit_ts);
+        buf.put_u32(self.column_families.len() as u32);
+        for (cf_id, records) in &self.column_families {
+            buf.put_u32(*cf_id);
+            buf.put_u32(records.len() as u32);
+            for record in records {
+                match record {
+                    WriteBatchRecord::Put(key, value) => {
+                        buf.put_u8(PUT);
+                        buf.put_u32(key.len() as u32);
+                        buf.put_slice(key);
+                        buf.put_u32(value.len() as u32);
+                        buf.put_slice(value);
+                    }
+                    WriteBatchRecord::Del(key) => {
+                        buf.put_u8(DEL);
+                        buf.put_u32(key.len() as u32);
+                        buf.put_slice(key);
+                    }
+                }
+            }
+        }
+        buf.into()
+    }
+
+    /// The batch `encode` wrote.
+    pub fn decode(mut buf: Bytes) -> Result<Self> {
+        let commit_ts = get_u64(&mut buf)?;
+        let mut column_families = Vec::new();
+        for _ in 0..get_u32(&mut buf)? {
+            let cf_id = get_u32(&mut buf)?;
+            let mut records = Vec::new();
+            for _ in 0..get_u32(&mut buf)? {
+                let record = match get_u8(&mut buf)? {
+                    PUT => {
+                        let key = get_len_prefixed(&mut buf)?;
+                        WriteBatchRecord::Put(key, get_len_prefixed(&mut buf)?)
+                    }
+                    DEL => WriteBatchRecord::Del(get_len_prefixed(&mut buf)?),
+                    record_type => bail!("unknown record type {}", record_type),
+                };
+                records.push(record);
+            }
+            column_families.push((cf_id, records));
+        }
+        if buf.has_remaining() {
+            bail!("{} bytes after the replicated batch", buf.remaining());
+        }
+        Ok(Self {
+            commit_ts,
+            column_families,
+        })
+    }
+}
+
+fn ensure_remaining(buf: &Bytes, len: usize) -> Result<()> {
+    if buf.remaining() < len {
+        bail!("replicated batch truncated");
+    }
+    Ok(())
+}
+
+fn get_u8(buf: &mut Bytes) -> Result<u8> {
+    ensure_remaining(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+fn get_u32(buf: &mut Bytes) -> Result<u32> {
+    ensure_remaining(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+fn get_u64(buf: &mut Bytes) -> Result<u64> {
+    ensure_remaining(buf, 8)?;
+    Ok(buf.get_u64())
+}
+
+fn get_len_prefixed(buf: &mut Bytes) -> Result<Bytes> {
+    let len = get_u32(buf)? as usize;
+    ensure_remaining(buf, len)?;
+    Ok(buf.split_to(len))
+}
+
+impl LsmStorageInner {
+    /// Apply a batch committed by the primary, at its commit ts rather than at a new one, so that the follower
+    /// holds the same versions, readable at the same ts. The batches must be applied in commit order, and nothing
+    /// else should write to the follower. A batch whose commit ts is not after the latest one of the follower is
+    /// taken as applied already and skipped, returning false, so that the primary can resend the batches after
+    /// `latest_commit_ts` when a follower reconnects or restarts. The column families of the batch must exist with
+    /// the same ids, e.g. created in the same order.
+    pub fn apply_replicated_batch(&self, batch: &ReplicatedBatch) -> Result<bool> {
+        let batches = batch
+            .column_families
+            .iter()
+            .map(|(cf_id, records)| (*cf_id, records.as_slice()))
+            .collect::<Vec<_>>();
+        Ok(self
+            .write_cf_batches(&batches, None, Some(batch.commit_ts))?
+            .is_some())
+    }
+}
+
+impl MiniLsm {
+    pub fn apply_replicated_batch(&self, batch: &ReplicatedBatch) -> Result<bool> {
+        self.inner.apply_replicated_batch(batch)
+    }
+}
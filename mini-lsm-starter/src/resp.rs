@@ -0,0 +1,608 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{BufMut, Bytes};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::asynk::AsyncLsmStorage;
+use crate::clock::Clock;
+use crate::logger::db_log;
+use crate::lsm_storage::WriteBatchRecord;
+
+/// The longest bulk string read, as Redis's `proto-max-bulk-len`.
+const MAX_BULK_LEN: usize = 512 << 20;
+
+/// The most elements of an array read.
+const MAX_ARRAY_LEN: usize = 1 << 20;
+
+/// The deepest arrays nest in a frame read by `read_frame`.
+const MAX_FRAME_DEPTH: usize = 32;
+
+/// The longest line read, of an inline command or the header of a frame.
+const MAX_LINE_LEN: usize = 64 << 10;
+
+/// The keys a `SCAN` examines when its `COUNT` is not given.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// The unfinished `SCAN`s a connection keeps the position of, the oldest being forgotten past it.
+const MAX_SCAN_CURSORS: usize = 1024;
+
+/// The bytes before a stored value, the big-endian UNIX milliseconds it expires at, 0 if it does not.
+const EXPIRES_AT_LEN: usize = 8;
+
+/// A RESP2 frame, of a command sent by a client or of its reply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    /// The null bulk string, the reply for a missing key.
+    Null,
+    Array(Vec<Frame>),
+}
+
+impl Frame {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Frame::Simple(s) => {
+                buf.put_u8(b'+');
+                buf.put_slice(s.as_bytes());
+            }
+            Frame::Error(s) => {
+                buf.put_u8(b'-');
+                buf.put_slice(s.as_bytes());
+            }
+            Frame::Integer(n) => buf.put_slice(format!(":{}", n).as_bytes()),
+            Frame::Bulk(data) => {
+                buf.put_slice(format!("${}\r\n", data.len()).as_bytes());
+                buf.put_slice(data);
+            }
+            Frame::Null => buf.put_slice(b"$-1"),
+            Frame::Array(frames) => {
+                buf.put_slice(format!("*{}\r\n", frames.len()).as_bytes());
+                for frame in frames {
+                    frame.encode(buf);
+                }
+                return;
+            }
+        }
+        buf.put_slice(b"\r\n");
+    }
+
+    /// A command, as an array of its arguments.
+    pub fn command<T: AsRef<[u8]>>(args: &[T]) -> Self {
+        Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_ref())))
+                .collect(),
+        )
+    }
+}
+
+/// Read the line ending at the next CRLF, without it; `None` at the end of the input.
+async fn read_line<R: AsyncBufRead + Unpin + Send>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE_LEN as u64 + 2)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\r\n") {
+        bail!("line not ending with CRLF or too long");
+    }
+    line.truncate(line.len() - 2);
+    Ok(Some(line))
+}
+
+fn parse_int(line: &[u8]) -> Result<i64> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .with_context(|| format!("invalid integer {:?}", String::from_utf8_lossy(line)))
+}
+
+fn parse_len(line: &[u8], max: usize) -> Result<Option<usize>> {
+    match parse_int(line)? {
+        -1 => Ok(None),
+        len if len >= 0 && len as u64 <= max as u64 => Ok(Some(len as usize)),
+        len => bail!("invalid length {}", len),
+    }
+}
+
+/// Read a bulk string of `len` bytes and its CRLF. The buffer grows as the bytes arrive rather than taking `len`
+/// bytes upfront, which a client could otherwise make up to `MAX_BULK_LEN` without sending any.
+async fn read_bulk<R: AsyncBufRead + Unpin + Send>(reader: &mut R, len: usize) -> Result<Bytes> {
+    let mut data = Vec::new();
+    (&mut *reader)
+        .take(len as u64 + 2)
+        .read_to_end(&mut data)
+        .await?;
+    if data.len() < len + 2 {
+        bail!("truncated bulk string");
+    }
+    if !data.ends_with(b"\r\n") {
+        bail!("bulk string not ending with CRLF");
+    }
+    data.truncate(len);
+    Ok(data.into())
+}
+
+/// Read the words of an inline command, a line not starting with a RESP type.
+fn inline_command(line: &[u8]) -> Vec<Bytes> {
+    line.split(|b| *b == b' ')
+        .filter(|word| !word.is_empty())
+        .map(Bytes::copy_from_slice)
+        .collect()
+}
+
+/// Read the next command, `None` at the end of the input: the arguments of an array of bulk strings, or of an inline
+/// command. A command is never nested, so an array inside it, as any other frame, is a protocol error.
+pub async fn read_command<R: AsyncBufRead + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Option<Vec<Bytes>>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let (kind, rest) = line.split_first().context("empty line")?;
+    match kind {
+        b'*' => {
+            let len = parse_len(rest, MAX_ARRAY_LEN)?.context("a command is not a null array")?;
+            let mut args = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                let line = read_line(reader).await?.context("truncated array")?;
+                let Some((b'$', rest)) = line.split_first() else {
+                    bail!("a command is an array of bulk strings");
+                };
+                let len =
+                    parse_len(rest, MAX_BULK_LEN)?.context("a command has no null argument")?;
+                args.push(read_bulk(reader, len).await?);
+            }
+            Ok(Some(args))
+        }
+        b'+' | b'-' | b':' | b'$' => bail!("a command is an array of bulk strings"),
+        _ => Ok(Some(inline_command(&line))),
+    }
+}
+
+/// Read the next frame, `None` at the end of the input, as a client reads a reply. A line not starting with a RESP
+/// type is an inline command, read as an array of its space-separated words. Arrays nest at most `MAX_FRAME_DEPTH`
+/// deep.
+pub async fn read_frame<R: AsyncBufRead + Unpin + Send>(reader: &mut R) -> Result<Option<Frame>> {
+    read_nested_frame(reader, MAX_FRAME_DEPTH).await
+}
+
+fn read_nested_frame<'a, R: AsyncBufRead + Unpin + Send>(
+    reader: &'a mut R,
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = Result<Option<Frame>>> + Send + 'a>> {
+    Box::pin(async move {
+        let Some(line) = read_line(reader).await? else {
+            return Ok(None);
+        };
+        let (kind, rest) = line.split_first().context("empty line")?;
+        let text = || String::from_utf8_lossy(rest).into_owned();
+        let frame = match kind {
+            b'+' => Frame::Simple(text()),
+            b'-' => Frame::Error(text()),
+            b':' => Frame::Integer(parse_int(rest)?),
+            b'$' => match parse_len(rest, MAX_BULK_LEN)? {
+                None => Frame::Null,
+                Some(len) => Frame::Bulk(read_bulk(reader, len).await?),
+            },
+            b'*' => match parse_len(rest, MAX_ARRAY_LEN)? {
+                None => Frame::Null,
+                Some(_) if depth == 0 => bail!("arrays nested too deep"),
+                Some(len) => {
+                    let mut frames = Vec::with_capacity(len.min(1024));
+                    for _ in 0..len {
+                        let frame = read_nested_frame(reader, depth - 1).await?;
+                        frames.push(frame.context("truncated array")?);
+                    }
+                    Frame::Array(frames)
+                }
+            },
+            _ => Frame::Array(inline_command(&line).into_iter().map(Frame::Bulk).collect()),
+        };
+        Ok(Some(frame))
+    })
+}
+
+/// Whether `key` matches the glob-style `pattern` of `SCAN ... MATCH`: `*` matches any bytes, `?` any byte,
+/// `[abc]`, `[a-z]` and `[^a]` a byte of the set or not of it, and `\` escapes the next byte.
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // where to resume after the last `*`, and how much of the key it then matches
+    let mut backtrack = None;
+    while k < key.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, k));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_set(pattern, p + 1, key[k]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == key[k]).then_some(p + 2),
+            Some(byte) => (*byte == key[k]).then_some(p + 1),
+            None => None,
+        };
+        match (matched, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                k += 1;
+            }
+            (None, Some((star_p, star_k))) => {
+                p = star_p;
+                k = star_k + 1;
+                backtrack = Some((star_p, star_k + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p.min(pattern.len())..].iter().all(|b| *b == b'*')
+}
+
+/// Match `byte` against the set starting at `start`, just after its `[`; where the pattern continues if it matches.
+fn match_set(pattern: &[u8], start: usize, byte: u8) -> Option<usize> {
+    let mut i = start;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            i += 1;
+            matched |= pattern[i] == byte;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            let (low, high) = (
+                pattern[i].min(pattern[i + 2]),
+                pattern[i].max(pattern[i + 2]),
+            );
+            matched |= (low..=high).contains(&byte);
+            i += 2;
+        } else {
+            matched |= pattern[i] == byte;
+        }
+        i += 1;
+    }
+    // an unclosed set runs to the end of the pattern
+    (matched != negate).then_some((i + 1).min(pattern.len()))
+}
+
+/// The bytes of the pattern before its first wildcard, which every key it matches starts with.
+fn literal_prefix(pattern: &[u8]) -> &[u8] {
+    let end = pattern
+        .iter()
+        .position(|b| matches!(b, b'*' | b'?' | b'[' | b'\\'))
+        .unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// The positions of the unfinished `SCAN`s of a connection, by cursor. The cursors are integers, as clients expect,
+/// standing for the key the scan resumes at.
+#[derive(Default)]
+struct ScanCursors {
+    resume_keys: BTreeMap<u64, Bytes>,
+    next_cursor: u64,
+}
+
+impl ScanCursors {
+    fn insert(&mut self, resume_key: Bytes) -> u64 {
+        if self.resume_keys.len() >= MAX_SCAN_CURSORS {
+            self.resume_keys.pop_first();
+        }
+        // 0 starts and ends a scan
+        self.next_cursor += 1;
+        self.resume_keys.insert(self.next_cursor, resume_key);
+        self.next_cursor
+    }
+}
+
+fn wrong_args(command: &str) -> Frame {
+    Frame::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command
+    ))
+}
+
+fn syntax_error() -> Frame {
+    Frame::Error("ERR syntax error".to_string())
+}
+
+/// A server of a subset of the Redis protocol over a database: `GET`, `SET` with `EX` and `PX`, `DEL`, `MGET`,
+/// `TTL`, `SCAN` with `MATCH` and `COUNT`, plus `PING`, `COMMAND` and `QUIT` for the clients sending them.
+///
+/// The values are stored behind the milliseconds they expire at, so the database is only read and written through
+/// the server. An expired key reads as missing but stays on disk until it is written again or deleted. Unlike Redis
+/// the commands do not run one at a time: the keys of a `DEL` are deleted in a single batch, but the count it
+/// replies may miss a concurrent write.
+pub struct RespServer {
+    storage: Arc<AsyncLsmStorage>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RespServer {
+    pub fn new(storage: Arc<AsyncLsmStorage>) -> Self {
+        let clock = storage.storage().options().clock_or_default();
+        Self { storage, clock }
+    }
+
+    /// Serve the connections accepted from the listener, each on its own task, until accepting fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = server.handle_connection(stream).await {
+                    db_log!(
+                        server.storage.storage().options(),
+                        Warn,
+                        "RESP connection from {} failed: {:#}",
+                        peer,
+                        err
+                    );
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        let (reader, writer) = stream.into_split();
+        let (mut reader, mut writer) = (BufReader::new(reader), BufWriter::new(writer));
+        let mut cursors = ScanCursors::default();
+        let mut buf = Vec::new();
+        loop {
+            let args = match read_command(&mut reader).await {
+                Ok(Some(args)) => args,
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    // as Redis, reply and close the connection, whose input can no longer be framed
+                    buf.clear();
+                    Frame::Error(format!("ERR Protocol error: {:#}", err)).encode(&mut buf);
+                    writer.write_all(&buf).await?;
+                    writer.flush().await?;
+                    return Ok(());
+                }
+            };
+            let reply = match args {
+                args if args.is_empty() => continue,
+                args if args[0].eq_ignore_ascii_case(b"QUIT") => {
+                    writer.write_all(b"+OK\r\n").await?;
+                    writer.flush().await?;
+                    return Ok(());
+                }
+                args => self.execute(&args, &mut cursors).await,
+            };
+            buf.clear();
+            reply.encode(&mut buf);
+            writer.write_all(&buf).await?;
+            // replies to pipelined commands are flushed together
+            if reader.buffer().is_empty() {
+                writer.flush().await?;
+            }
+        }
+    }
+
+    async fn execute(&self, args: &[Bytes], cursors: &mut ScanCursors) -> Frame {
+        let command = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        let args = &args[1..];
+        let result = match command.as_str() {
+            "ping" => match args {
+                [] => Ok(Frame::Simple("PONG".to_string())),
+                [message] => Ok(Frame::Bulk(message.clone())),
+                _ => Ok(wrong_args(&command)),
+            },
+            "command" => Ok(Frame::Array(Vec::new())),
+            "get" => match args {
+                [key] => self.get(key).await,
+                _ => Ok(wrong_args(&command)),
+            },
+            "mget" if !args.is_empty() => self.mget(args).await,
+            "set" if args.len() >= 2 => self.set(args).await,
+            "del" if !args.is_empty() => self.del(args).await,
+            "ttl" => match args {
+                [key] => self.ttl(key).await,
+                _ => Ok(wrong_args(&command)),
+            },
+            "scan" if !args.is_empty() => self.scan(args, cursors).await,
+            "mget" | "set" | "del" | "scan" => Ok(wrong_args(&command)),
+            _ => Ok(Frame::Error(format!("ERR unknown command '{}'", command))),
+        };
+        result.unwrap_or_else(|err| Frame::Error(format!("ERR {:#}", err)))
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.clock.unix_millis()
+    }
+
+    /// The value of the key and the milliseconds it expires at, `None` if it is missing or expired.
+    async fn read_live(&self, key: &[u8]) -> Result<Option<(Bytes, u64)>> {
+        match self.storage.get(key).await? {
+            Some(stored) => {
+                let (value, expires_at) = decode_value(stored)?;
+                Ok(is_live(expires_at, self.now_millis()).then_some((value, expires_at)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Frame> {
+        Ok(match self.read_live(key).await? {
+            Some((value, _)) => Frame::Bulk(value),
+            None => Frame::Null,
+        })
+    }
+
+    async fn mget(&self, keys: &[Bytes]) -> Result<Frame> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(Frame::Array(values))
+    }
+
+    async fn set(&self, args: &[Bytes]) -> Result<Frame> {
+        let (key, value) = (&args[0], &args[1]);
+        let mut expires_at = 0;
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            let unit_millis = if option.eq_ignore_ascii_case(b"EX") {
+                1000
+            } else if option.eq_ignore_ascii_case(b"PX") {
+                1
+            } else {
+                return Ok(syntax_error());
+            };
+            let Some(Ok(time)) = options.next().map(|time| parse_int(time)) else {
+                return Ok(syntax_error());
+            };
+            if expires_at != 0 {
+                return Ok(syntax_error());
+            }
+            match u64::try_from(time).ok().filter(|time| *time > 0) {
+                Some(time) => {
+                    expires_at = self
+                        .now_millis()
+                        .saturating_add(time.saturating_mul(unit_millis))
+                }
+                None => {
+                    return Ok(Frame::Error(
+                        "ERR invalid expire time in 'set' command".to_string(),
+                    ))
+                }
+            }
+        }
+        self.storage
+            .put(key, &encode_value(value, expires_at))
+            .await?;
+        Ok(Frame::Simple("OK".to_string()))
+    }
+
+    async fn del(&self, keys: &[Bytes]) -> Result<Frame> {
+        let mut deleted = 0;
+        for key in keys {
+            if self.read_live(key).await?.is_some() {
+                deleted += 1;
+            }
+        }
+        let batch = keys
+            .iter()
+            .map(|key| WriteBatchRecord::Del(key.as_ref()))
+            .collect::<Vec<_>>();
+        self.storage.write_batch(&batch).await?;
+        Ok(Frame::Integer(deleted))
+    }
+
+    async fn ttl(&self, key: &[u8]) -> Result<Frame> {
+        Ok(Frame::Integer(match self.read_live(key).await? {
+            None => -2,
+            Some((_, 0)) => -1,
+            // rounded to the nearest second, as Redis does
+            Some((_, expires_at)) => {
+                ((expires_at.saturating_sub(self.now_millis()) + 500) / 1000) as i64
+            }
+        }))
+    }
+
+    async fn scan(&self, args: &[Bytes], cursors: &mut ScanCursors) -> Result<Frame> {
+        let cursor = match std::str::from_utf8(&args[0])
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            Some(cursor) => cursor,
+            None => return Ok(Frame::Error("ERR invalid cursor".to_string())),
+        };
+        let mut pattern = None;
+        let mut count = DEFAULT_SCAN_COUNT;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            let Some(value) = options.next() else {
+                return Ok(syntax_error());
+            };
+            if option.eq_ignore_ascii_case(b"MATCH") {
+                pattern = Some(value.clone());
+            } else if option.eq_ignore_ascii_case(b"COUNT") {
+                match parse_int(value)
+                    .ok()
+                    .and_then(|count| usize::try_from(count).ok())
+                {
+                    Some(value) if value > 0 => count = value,
+                    _ => return Ok(syntax_error()),
+                }
+            } else {
+                return Ok(syntax_error());
+            }
+        }
+
+        let prefix = pattern.as_deref().map(literal_prefix).unwrap_or_default();
+        let lower = match cursor {
+            0 => Bytes::copy_from_slice(prefix),
+            cursor => match cursors.resume_keys.remove(&cursor) {
+                Some(resume_key) => resume_key.max(Bytes::copy_from_slice(prefix)),
+                None => return Ok(Frame::Error("ERR invalid cursor".to_string())),
+            },
+        };
+        let mut scan = self
+            .storage
+            .scan(Bound::Included(&lower), Bound::Unbounded)
+            .await?;
+        let now = self.now_millis();
+        let mut keys = Vec::new();
+        let mut examined = 0;
+        let mut next_cursor = 0;
+        while let Some((key, stored)) = scan.next().await? {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            if examined == count {
+                next_cursor = cursors.insert(key);
+                break;
+            }
+            examined += 1;
+            let (_, expires_at) = decode_value(stored)?;
+            if is_live(expires_at, now)
+                && pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob_match(pattern, &key))
+            {
+                keys.push(Frame::Bulk(key));
+            }
+        }
+        Ok(Frame::Array(vec![
+            Frame::Bulk(next_cursor.to_string().into()),
+            Frame::Array(keys),
+        ]))
+    }
+}
+
+fn encode_value(value: &[u8], expires_at: u64) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(EXPIRES_AT_LEN + value.len());
+    stored.put_u64(expires_at);
+    stored.put_slice(value);
+    stored
+}
+
+/// The value and the milliseconds it expires at of a stored value.
+fn decode_value(stored: Bytes) -> Result<(Bytes, u64)> {
+    if stored.len() < EXPIRES_AT_LEN {
+        bail!(
+            "value of {} bytes not written by the RESP server",
+            stored.len()
+        );
+    }
+    let expires_at = u64::from_be_bytes(stored[..EXPIRES_AT_LEN].try_into().unwrap());
+    Ok((stored.slice(EXPIRES_AT_LEN..), expires_at))
+}
+
+fn is_live(expires_at: u64, now_millis: u64) -> bool {
+    expires_at == 0 || expires_at > now_millis
+}
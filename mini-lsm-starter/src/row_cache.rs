@@ -0,0 +1,80 @@
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::mem_table::MemTable;
+
+/// A key-value cache sitting above the block cache. A hit lets `get` skip block decoding and the binary search inside
+/// the block entirely. Only values read from SSTs are cached: the memtables are always probed first, so an entry can
+/// only go stale once a newer write has been flushed out of the memtables.
+pub struct RowCache {
+    cache: moka::sync::Cache<Bytes, Bytes>,
+    /// Bumped every time a memtable is flushed. A reader remembers the epoch before taking its state snapshot and
+    /// only fills the cache if no flush happened in between, otherwise it could install a value that a concurrent
+    /// flush has just superseded.
+    epoch: RwLock<u64>,
+}
+
+impl RowCache {
+    /// Create a row cache holding up to `capacity` bytes of keys and values.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: moka::sync::Cache::builder()
+                .weigher(|key: &Bytes, value: &Bytes| {
+                    (key.len() + value.len()).try_into().unwrap_or(u32::MAX)
+                })
+                .max_capacity(capacity as u64)
+                .build(),
+            epoch: RwLock::new(0),
+        }
+    }
+
+    /// Get a cached value. An empty value is a cached tombstone.
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.cache.get(key)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        *self.epoch.read()
+    }
+
+    /// Cache a value read from the SSTs, unless a flush happened since `epoch` was observed.
+    pub fn insert_if_current(&self, epoch: u64, key: &[u8], value: Bytes) {
+        let current = self.epoch.read();
+        if *current == epoch {
+            self.cache.insert(Bytes::copy_from_slice(key), value);
+        }
+    }
+
+    /// Drop the cached value of a key that has just been written.
+    pub fn invalidate(&self, key: &[u8]) {
+        self.cache.invalidate(key);
+    }
+
+    /// Drop the cached value of a key that has just been written straight to an SST, see `put_streaming`.
+    pub fn invalidate_written_to_sst(&self, key: &[u8]) {
+        let mut epoch = self.epoch.write();
+        *epoch += 1;
+        self.cache.invalidate(key);
+    }
+
+    /// Drop every key of a memtable that has just been flushed to an SST.
+    pub fn invalidate_flushed(&self, memtable: &MemTable) {
+        let mut epoch = self.epoch.write();
+        *epoch += 1;
+        for entry in memtable.map.iter() {
+            self.cache.invalidate(entry.key().key_ref());
+        }
+    }
+
+    /// Drop every cached value, when the SSTs changed without going through the flushes of this storage.
+    pub fn invalidate_all(&self) {
+        let mut epoch = self.epoch.write();
+        *epoch += 1;
+        self.cache.invalidate_all();
+    }
+
+    /// Approximate number of bytes held by the cache.
+    pub fn weighted_size(&self) -> u64 {
+        self.cache.weighted_size()
+    }
+}
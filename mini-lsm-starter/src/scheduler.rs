@@ -0,0 +1,201 @@
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::clock::Instant;
+use crate::logger::{db_log, DbLogger};
+
+/// Priority of a background job. Flushes go first so that writes are not stalled by a full set of immutable
+/// memtables, then compactions out of L0, which bound the number of SSTs a read has to check, then the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JobPriority {
+    Compaction,
+    L0Compaction,
+    Flush,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct QueuedJob {
+    priority: JobPriority,
+    seq: u64,
+    job: Job,
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for QueuedJob {}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedJob {
+    /// Higher priorities first, then the jobs scheduled earlier.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    jobs: BinaryHeap<QueuedJob>,
+    next_seq: u64,
+    running: usize,
+    shutdown: bool,
+}
+
+struct SchedulerShared {
+    state: Mutex<SchedulerState>,
+    /// Signalled when a job is queued or on shutdown.
+    job_available: Condvar,
+    /// Signalled when a job finishes.
+    job_finished: Condvar,
+    logger: DbLogger,
+}
+
+/// A bounded pool of threads running the flushes and compactions of a database, highest priority first.
+///
+/// Once shut down, the scheduler refuses new jobs and discards the queued ones; the running jobs are always left to
+/// finish so that no flush or compaction is interrupted halfway.
+pub struct BackgroundScheduler {
+    shared: Arc<SchedulerShared>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl BackgroundScheduler {
+    /// Start `num_threads` worker threads, at least one, logging the jobs that panic through `logger`.
+    pub fn new(num_threads: usize, logger: DbLogger) -> Self {
+        let shared = Arc::new(SchedulerShared {
+            state: Mutex::default(),
+            job_available: Condvar::new(),
+            job_finished: Condvar::new(),
+            logger,
+        });
+        let workers = (0..num_threads.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || Self::run_worker(&shared))
+            })
+            .collect();
+        Self {
+            shared,
+            workers: Mutex::new(workers),
+        }
+    }
+
+    fn run_worker(shared: &SchedulerShared) {
+        loop {
+            let job = {
+                let mut state = shared.state.lock();
+                loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    if let Some(job) = state.jobs.pop() {
+                        state.running += 1;
+                        break job;
+                    }
+                    shared.job_available.wait(&mut state);
+                }
+            };
+            // a panicking job must not take the worker down with it
+            if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job.job)).is_err() {
+                db_log!(shared.logger, Error, "background job panicked");
+            }
+            shared.state.lock().running -= 1;
+            shared.job_finished.notify_all();
+        }
+    }
+
+    /// Queue a job, returns false if the scheduler is shut down.
+    pub fn schedule(&self, priority: JobPriority, job: impl FnOnce() + Send + 'static) -> bool {
+        let mut state = self.shared.state.lock();
+        if state.shutdown {
+            return false;
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.jobs.push(QueuedJob {
+            priority,
+            seq,
+            job: Box::new(job),
+        });
+        self.shared.job_available.notify_one();
+        true
+    }
+
+    pub fn num_queued_jobs(&self) -> usize {
+        self.shared.state.lock().jobs.len()
+    }
+
+    pub fn num_running_jobs(&self) -> usize {
+        self.shared.state.lock().running
+    }
+
+    /// Block until no job is queued or running.
+    pub fn wait_for_idle(&self) {
+        let mut state = self.shared.state.lock();
+        while !state.jobs.is_empty() || state.running > 0 {
+            self.shared.job_finished.wait(&mut state);
+        }
+    }
+
+    /// Stop accepting jobs and discard the queued ones without waiting for the running ones.
+    fn signal_shutdown(&self) {
+        let mut state = self.shared.state.lock();
+        state.shutdown = true;
+        state.jobs.clear();
+        self.shared.job_available.notify_all();
+    }
+
+    /// Stop the scheduler: the queued jobs are discarded and the running ones finish before this returns.
+    pub fn shutdown(&self) {
+        self.signal_shutdown();
+        for worker in self.workers.lock().drain(..) {
+            worker.join().ok();
+        }
+    }
+
+    /// Same as `shutdown`, but give up waiting for the running jobs after `timeout`. Returns whether they all
+    /// finished, otherwise they keep running in the background.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> bool {
+        self.signal_shutdown();
+        let deadline = Instant::now() + timeout;
+        {
+            let mut state = self.shared.state.lock();
+            while state.running > 0 {
+                if self
+                    .shared
+                    .job_finished
+                    .wait_for(
+                        &mut state,
+                        deadline.saturating_duration_since(Instant::now()),
+                    )
+                    .timed_out()
+                {
+                    return false;
+                }
+            }
+        }
+        self.shutdown();
+        true
+    }
+}
+
+impl Drop for BackgroundScheduler {
+    fn drop(&mut self) {
+        self.signal_shutdown();
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::logger::{db_log, DbLogger};
+use crate::lsm_storage::{
+    LsmStorageInner, LsmStorageOptions, ManifestReplay, MiniLsm, SstOpenContext,
+};
+use crate::manifest::{read_current_manifest, Manifest};
+use crate::mem_table::MemTable;
+use crate::wal::WalRecord;
+
+/// How many times catching up starts over when the primary deleted a file it was about to read.
+const CATCH_UP_ATTEMPTS: usize = 3;
+
+impl LsmStorageInner {
+    /// Open the database at `primary_path` as a secondary instance: read-only like `open_read_only`, but catching up
+    /// with the primary writing to it on `try_catch_up_with_primary`. The files of the secondary itself, the
+    /// spilled writes of its transactions, go to `secondary_path`, created if it does not exist.
+    pub(crate) fn open_as_secondary(
+        primary_path: impl AsRef<Path>,
+        secondary_path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<Self> {
+        let secondary_path = secondary_path.as_ref();
+        let fs = options.file_system_or_default();
+        fs.create_dir_all(secondary_path)?;
+        Self::remove_txn_spills(fs.as_ref(), secondary_path)?;
+        let mut storage = Self::open_with_mode(primary_path.as_ref(), options, true)?;
+        storage.secondary_path = Some(secondary_path.to_path_buf());
+        Ok(storage)
+    }
+
+    /// Refresh the view of a secondary instance from the manifest and WALs of the primary, so that reads see what
+    /// the primary flushed, compacted, and with `enable_wal` wrote since. The SSTs already open are kept. The
+    /// primary does not know about the snapshots of the secondary, which lose the versions it compacts away.
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        if self.secondary_path.is_none() {
+            bail!("only a secondary instance catches up with its primary");
+        }
+        let mut attempts = 1;
+        loop {
+            match self.catch_up_with_primary() {
+                Err(e) if attempts < CATCH_UP_ATTEMPTS && is_not_found(&e) => {
+                    // the primary rolled over its manifest, or deleted the WAL of a flushed memtable or the input
+                    // of a compaction, after the secondary found it
+                    db_log!(
+                        self.options(),
+                        Info,
+                        "catching up with the primary again: {:#}",
+                        e
+                    );
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn catch_up_with_primary(&self) -> Result<()> {
+        // orders the catch-ups
+        let _state_lock = self.state_lock.lock();
+        let Some(name) = read_current_manifest(self.fs.as_ref(), &self.path)? else {
+            bail!("{} has no database", self.path.display());
+        };
+        let options = self.options();
+        let records = Manifest::read_records(
+            self.fs.as_ref(),
+            self.path.join(&name),
+            &DbLogger::new(&options),
+        )
+        .with_context(|| format!("failed to read {}", name))?;
+        let compaction_controller = self.compaction_controller();
+        let mut replay = ManifestReplay::new(&options);
+        for record in records {
+            replay.apply(&compaction_controller, record);
+        }
+
+        let current = self.state.read().clone();
+        let opened = self
+            .all_column_family_states()
+            .iter()
+            .flat_map(|state| state.sstables.clone())
+            .collect::<HashMap<_, _>>();
+        let mut state = replay.state;
+        let mut column_families = replay.column_families;
+        let context = SstOpenContext {
+            fs: self.fs.as_ref(),
+            path: &self.path,
+            block_cache: &self.block_cache,
+            file_checksums: &replay.file_checksums,
+            options: &options,
+            opened: &opened,
+        };
+        let mut last_commit_ts = Self::open_ssts(&context, &mut state, &compaction_controller)?;
+        last_commit_ts = last_commit_ts.max(Self::open_column_family_ssts(
+            &context,
+            &mut column_families,
+            &compaction_controller,
+        )?);
+        if options.enable_wal {
+            for wal_id in Self::wal_ids(self.fs.as_ref(), &self.path)? {
+                if wal_id < replay.oldest_memtable_id {
+                    continue;
+                }
+                let (memtable, records) =
+                    MemTable::read_from_wal(wal_id, self.fs.as_ref(), self.path_of_wal(wal_id))?;
+                let column_families_recovered =
+                    Self::recover_column_family_memtables(&mut column_families, wal_id, &records);
+                if let Some(ts) = records.iter().filter_map(WalRecord::commit_ts).max() {
+                    last_commit_ts = last_commit_ts.max(ts);
+                }
+                if !memtable.is_empty() || column_families_recovered {
+                    state.imm_memtables.insert(0, Arc::new(memtable));
+                }
+            }
+        }
+        // the memtable of the secondary stays empty, as it is never written
+        state.memtable = current.memtable.clone();
+        let num_ssts = state.sstables.len();
+        let num_memtables = state.imm_memtables.len();
+        let memtable_id = state.memtable.id();
+        *self.state.write() = Arc::new(state);
+        *self.column_families.write() = column_families
+            .into_iter()
+            .map(|(id, cf)| (id, cf.into_state(memtable_id)))
+            .collect();
+        self.next_column_family_id
+            .store(replay.next_column_family_id, Ordering::SeqCst);
+        if let Some(row_cache) = &self.row_cache {
+            row_cache.invalidate_all();
+        }
+        let mvcc = self.mvcc();
+        mvcc.update_commit_ts(mvcc.latest_commit_ts().max(last_commit_ts));
+        db_log!(
+            self.options(),
+            Info,
+            "caught up with the primary: {} SSTs and {} memtables",
+            num_ssts,
+            num_memtables
+        );
+        Ok(())
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
+impl MiniLsm {
+    /// Open the database at `primary_path` as a secondary instance, see `LsmStorageInner::open_as_secondary`.
+    pub fn open_as_secondary(
+        primary_path: impl AsRef<Path>,
+        secondary_path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<Arc<Self>> {
+        let inner = LsmStorageInner::open_as_secondary(primary_path, secondary_path, options)?;
+        Ok(Self::without_background_jobs(inner))
+    }
+
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        self.inner.try_catch_up_with_primary()
+    }
+}
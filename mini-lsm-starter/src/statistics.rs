@@ -0,0 +1,393 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::clock::Instant;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+
+/// Counters of the `Statistics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ticker {
+    /// Bytes of the values returned by the gets.
+    BytesRead,
+    /// Bytes of the keys and values written.
+    BytesWritten,
+    /// Gets that found a value.
+    KeysRead,
+    /// Keys put or deleted.
+    KeysWritten,
+    /// Lookups of an SST skipped because its bloom filter showed the key is not there.
+    BloomFilterUseful,
+    /// Lookups of an SST its bloom filter let through.
+    BloomFilterPositive,
+    /// Blocks found in the block cache.
+    BlockCacheHit,
+    /// Blocks read from the SSTs into the block cache.
+    BlockCacheMiss,
+    /// Bytes of the SSTs written by the flushes.
+    FlushWriteBytes,
+    /// Bytes of the input SSTs of the compactions.
+    CompactReadBytes,
+    /// Bytes of the SSTs written by the compactions.
+    CompactWriteBytes,
+    /// Syncs of the WAL.
+    WalSynced,
+    /// Microseconds the writes waited to freeze a full memtable while a flush was writing its SST, and while the
+    /// writes were stopped, see `write_stall_stats`.
+    StallMicros,
+}
+
+impl Ticker {
+    pub const ALL: [Ticker; 13] = [
+        Ticker::BytesRead,
+        Ticker::BytesWritten,
+        Ticker::KeysRead,
+        Ticker::KeysWritten,
+        Ticker::BloomFilterUseful,
+        Ticker::BloomFilterPositive,
+        Ticker::BlockCacheHit,
+        Ticker::BlockCacheMiss,
+        Ticker::FlushWriteBytes,
+        Ticker::CompactReadBytes,
+        Ticker::CompactWriteBytes,
+        Ticker::WalSynced,
+        Ticker::StallMicros,
+    ];
+
+    /// The name in the dump, the one RocksDB gives to the same counter.
+    pub fn name(self) -> &'static str {
+        match self {
+            Ticker::BytesRead => "bytes.read",
+            Ticker::BytesWritten => "bytes.written",
+            Ticker::KeysRead => "number.keys.read",
+            Ticker::KeysWritten => "number.keys.written",
+            Ticker::BloomFilterUseful => "bloom.filter.useful",
+            Ticker::BloomFilterPositive => "bloom.filter.full.positive",
+            Ticker::BlockCacheHit => "block.cache.hit",
+            Ticker::BlockCacheMiss => "block.cache.miss",
+            Ticker::FlushWriteBytes => "flush.write.bytes",
+            Ticker::CompactReadBytes => "compact.read.bytes",
+            Ticker::CompactWriteBytes => "compact.write.bytes",
+            Ticker::WalSynced => "wal.synced",
+            Ticker::StallMicros => "stall.micros",
+        }
+    }
+}
+
+/// Latency histograms of the `Statistics`, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Histogram {
+    /// Gets, from the call until the value is found.
+    Get,
+    /// Writes, from the call until the batch is in the memtables.
+    Write,
+    /// Syncs of the WAL.
+    WalSync,
+    /// Reads of a data block from an SST, when not in the block cache.
+    BlockRead,
+}
+
+impl Histogram {
+    pub const ALL: [Histogram; 4] = [
+        Histogram::Get,
+        Histogram::Write,
+        Histogram::WalSync,
+        Histogram::BlockRead,
+    ];
+
+    /// The name in the dump, the one RocksDB gives to the same histogram.
+    pub fn name(self) -> &'static str {
+        match self {
+            Histogram::Get => "db.get.micros",
+            Histogram::Write => "db.write.micros",
+            Histogram::WalSync => "wal.file.sync.micros",
+            Histogram::BlockRead => "sst.read.micros",
+        }
+    }
+}
+
+/// Number of buckets of a histogram: bucket `i` holds the values `i` bits long, from `2^(i-1)` up to `2^i`, the last
+/// one every longer value.
+const NUM_BUCKETS: usize = 64;
+
+/// The values recorded in a histogram, see `Statistics::histogram`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramData {
+    pub count: u64,
+    pub sum: u64,
+    pub min: u64,
+    pub max: u64,
+    buckets: [u64; NUM_BUCKETS],
+}
+
+impl Default for HistogramData {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0,
+            min: 0,
+            max: 0,
+            buckets: [0; NUM_BUCKETS],
+        }
+    }
+}
+
+impl HistogramData {
+    /// Record a value, e.g. a latency.
+    pub fn add(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += value;
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[bucket.min(NUM_BUCKETS - 1)] += 1;
+    }
+
+    /// Add the values recorded in `other`, e.g. by another thread.
+    pub fn merge(&mut self, other: &HistogramData) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.sum += other.sum;
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// The value below which `percentile` percent of the values fall, interpolated within its bucket.
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let threshold = self.count as f64 * percentile / 100.0;
+        let mut cumulative = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            if (cumulative + count) as f64 >= threshold {
+                let low = if bucket == 0 { 0 } else { 1u64 << (bucket - 1) };
+                let high = if bucket == 0 { 1 } else { 1u64 << bucket };
+                let (low, high) = (low.max(self.min) as f64, high.min(self.max) as f64);
+                let fraction = (threshold - cumulative as f64) / count as f64;
+                return low + (high - low).max(0.0) * fraction;
+            }
+            cumulative += count;
+        }
+        self.max as f64
+    }
+}
+
+/// Counters and latency histograms of a database since it was opened, or since the last `reset`. Attached with the
+/// `statistics` option; shared by several databases, it adds up all of them. Its `Display` is a dump of every
+/// counter and histogram, one per line.
+#[derive(Debug)]
+pub struct Statistics {
+    tickers: [AtomicU64; Ticker::ALL.len()],
+    histograms: [Mutex<HistogramData>; Histogram::ALL.len()],
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self {
+            tickers: std::array::from_fn(|_| AtomicU64::new(0)),
+            histograms: std::array::from_fn(|_| Mutex::new(HistogramData::default())),
+        }
+    }
+
+    pub fn ticker(&self, ticker: Ticker) -> u64 {
+        self.tickers[ticker as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn histogram(&self, histogram: Histogram) -> HistogramData {
+        self.histograms[histogram as usize].lock().clone()
+    }
+
+    /// Zero every counter and histogram.
+    pub fn reset(&self) {
+        for ticker in &self.tickers {
+            ticker.store(0, Ordering::Relaxed);
+        }
+        for histogram in &self.histograms {
+            *histogram.lock() = HistogramData::default();
+        }
+    }
+
+    pub(crate) fn record_tick(&self, ticker: Ticker, count: u64) {
+        self.tickers[ticker as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_elapsed(&self, histogram: Histogram, started: Instant) {
+        self.record_duration(histogram, started.elapsed());
+    }
+
+    pub(crate) fn record_duration(&self, histogram: Histogram, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.histograms[histogram as usize].lock().add(micros);
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ticker in Ticker::ALL {
+            writeln!(f, "{} COUNT : {}", ticker.name(), self.ticker(ticker))?;
+        }
+        for histogram in Histogram::ALL {
+            let data = self.histogram(histogram);
+            writeln!(
+                f,
+                "{} P50 : {:.1} P95 : {:.1} P99 : {:.1} MAX : {} COUNT : {} SUM : {}",
+                histogram.name(),
+                data.percentile(50.0),
+                data.percentile(95.0),
+                data.percentile(99.0),
+                data.max,
+                data.count,
+                data.sum
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads of an SST file that were not served by the block cache: its data blocks, and its bloom filter when the
+/// filter lives in the block cache. Kept by every SST whether or not the `statistics` option is set.
+#[derive(Debug, Default)]
+pub struct FileIoCounters {
+    bytes_read: AtomicU64,
+    latency: Mutex<HistogramData>,
+}
+
+/// A point-in-time copy of `FileIoCounters`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileIoStats {
+    pub reads: u64,
+    pub bytes_read: u64,
+    /// Latency of the reads, in microseconds.
+    pub latency: HistogramData,
+}
+
+impl FileIoCounters {
+    pub fn stats(&self) -> FileIoStats {
+        let latency = self.latency.lock().clone();
+        FileIoStats {
+            reads: latency.count,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            latency,
+        }
+    }
+
+    pub(crate) fn record_read(&self, len: u64, started: Instant) {
+        let micros = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.bytes_read.fetch_add(len, Ordering::Relaxed);
+        self.latency.lock().add(micros);
+    }
+}
+
+/// The reads of a live SST, see `LsmStorageInner::hottest_files`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotFile {
+    pub sst_id: usize,
+    /// 0 for L0; tiered compaction numbers the sorted runs from 1.
+    pub level: usize,
+    pub file_size: u64,
+    pub io: FileIoStats,
+}
+
+impl fmt::Display for HotFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latency = &self.io.latency;
+        write!(
+            f,
+            "{}.sst L{} SIZE : {} READS : {} BYTES : {} P50 : {:.1} P99 : {:.1} MAX : {} SUM : {}",
+            self.sst_id,
+            self.level,
+            self.file_size,
+            self.io.reads,
+            self.io.bytes_read,
+            latency.percentile(50.0),
+            latency.percentile(99.0),
+            latency.max,
+            latency.sum
+        )
+    }
+}
+
+impl LsmStorageInner {
+    /// The `statistics` the database was opened with.
+    pub fn statistics(&self) -> Option<Arc<Statistics>> {
+        self.options().statistics.clone()
+    }
+
+    /// The reads of every live SST of the default column family since it was opened or written, by SST id.
+    pub fn file_io_stats(&self) -> Vec<(usize, FileIoStats)> {
+        self.hottest_files(usize::MAX)
+            .into_iter()
+            .map(|file| (file.sst_id, file.io))
+            .collect()
+    }
+
+    /// The `n` live SSTs of the default column family that spent the most time reading from disk, the longest
+    /// first. A file far ahead of the others, or with a much higher P99, is the one behind slow reads.
+    pub fn hottest_files(&self, n: usize) -> Vec<HotFile> {
+        let state = self.state.read().clone();
+        let levels =
+            std::iter::once(&state.l0_sstables).chain(state.levels.iter().map(|(_, ids)| ids));
+        let mut files = levels
+            .enumerate()
+            .flat_map(|(level, ids)| ids.iter().map(move |id| (level, id)))
+            .map(|(level, id)| {
+                let table = &state.sstables[id];
+                HotFile {
+                    sst_id: *id,
+                    level,
+                    file_size: table.table_size(),
+                    io: table.io_stats(),
+                }
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_key(|file| {
+            let latency = &file.io.latency;
+            (std::cmp::Reverse((latency.sum, latency.count)), file.sst_id)
+        });
+        files.truncate(n);
+        files
+    }
+}
+
+impl MiniLsm {
+    pub fn statistics(&self) -> Option<Arc<Statistics>> {
+        self.inner.statistics()
+    }
+
+    pub fn file_io_stats(&self) -> Vec<(usize, FileIoStats)> {
+        self.inner.file_io_stats()
+    }
+
+    pub fn hottest_files(&self, n: usize) -> Vec<HotFile> {
+        self.inner.hottest_files(n)
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::blob::{BlobFileBuilder, BlobReference, ValueReader, STREAM_CHUNK_SIZE};
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME};
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::logger::{db_log, DbLogger};
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::manifest::ManifestRecord;
+use crate::table::SsTableBuilder;
+
+impl LsmStorageInner {
+    /// Same as `put_streaming_cf`, in the default column family.
+    pub fn put_streaming(&self, key: &[u8], reader: impl Read) -> Result<()> {
+        let cf = self.cf(DEFAULT_COLUMN_FAMILY_NAME).unwrap();
+        self.put_streaming_cf(&cf, key, reader)
+    }
+
+    /// Put the value `reader` reads until its end without holding it in memory: it goes to a new blob file in
+    /// chunks of `STREAM_CHUNK_SIZE` bytes, each with its own checksum, and the key to an SST of its own placed
+    /// above the other SSTs of `cf`, skipping the memtable and the WAL. The memtables holding a version of the key
+    /// are flushed first, as they are read before the SSTs. Read it back a chunk at a time with `get_streaming`.
+    pub fn put_streaming_cf(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        mut reader: impl Read,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let blob_file_id = self.next_sst_id();
+        // not deleted as obsolete before the SST pointing into it is installed
+        self.pending_blob_files.lock().insert(blob_file_id);
+        let mut written = vec![self.path_of_blob(blob_file_id)];
+        let result = self.write_streamed_value(cf, key, &mut reader, blob_file_id, &mut written);
+        self.pending_blob_files.lock().remove(&blob_file_id);
+        if let Err(e) = result {
+            for path in written.iter().filter(|path| self.fs.exists(path)) {
+                if let Err(e) = self.fs.remove_file(path) {
+                    db_log!(
+                        self.options(),
+                        Warn,
+                        "failed to remove {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Write the value to the blob file `blob_file_id` and install the SST pointing into it, pushing the path of
+    /// the SST to `written`.
+    fn write_streamed_value(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        reader: &mut impl Read,
+        blob_file_id: usize,
+        written: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let options = self.column_family_options(cf.id());
+        let mut blob_file = BlobFileBuilder::create(
+            self.fs.as_ref(),
+            written[0].clone(),
+            blob_file_id,
+            DbLogger::new(&options),
+        )?;
+        let index = blob_file.add_stream(reader, STREAM_CHUNK_SIZE)?;
+        if index.size == 0 {
+            bail!("cannot put an empty value by streaming, use delete instead");
+        }
+        blob_file.finish()?;
+
+        // nothing deletes the SST as obsolete before it is installed
+        let _compaction_lock = self.compaction_lock.lock();
+        let _write_lock = self.mvcc().write_lock.lock();
+        let Some(state) = self.column_family_state(cf.id()) else {
+            bail!("column family {} was dropped", cf.name());
+        };
+        // no write of the key can come in between while the write lock is held
+        if Self::get_from_memtables(&state, key, TS_RANGE_BEGIN).is_some() {
+            self.flush_column_family(cf)?;
+        }
+        let ts = self.next_commit_ts()?;
+        let sst_id = self.next_sst_id();
+        written.push(self.path_of_sst(sst_id));
+        let mut builder = SsTableBuilder::new(options.block_size)
+            .with_compression(options.compression_of_level(0))
+            .with_db_identity(self.db_id(), self.session_id())
+            .with_file_system(self.fs.clone())
+            .with_clock(self.clock.clone())
+            .with_blob_indexes();
+        builder.add_blob_reference(KeySlice::from_slice(key, ts), &BlobReference::Stream(index));
+        let sst = builder
+            .build(
+                sst_id,
+                Some(self.block_cache.clone()),
+                self.path_of_sst(sst_id),
+            )?
+            .with_metadata_caching(options.metadata_caching)
+            .with_statistics(options.statistics.clone());
+        let sst = Arc::new(Self::open_blob_files(self.fs.as_ref(), &self.path, sst)?);
+        self.sync_dir()?;
+
+        let state_lock = self.state_lock.lock();
+        let Some(state) = self.column_family_state(cf.id()) else {
+            bail!("column family {} was dropped", cf.name());
+        };
+        let mut new_state = state.as_ref().clone();
+        new_state.sstables.insert(sst_id, sst.clone());
+        if self
+            .column_family_compaction_controller(cf.id())
+            .flush_to_l0()
+        {
+            new_state.l0_sstables.insert(0, sst_id);
+        } else {
+            new_state.levels.insert(0, (sst_id, vec![sst_id]));
+        }
+        let record = ManifestRecord::NewestSst {
+            cf_id: cf.id(),
+            sst_id,
+        };
+        self.add_manifest_records_with_ssts(&state_lock, vec![record], &[sst])?;
+        self.set_column_family_state(cf.id(), new_state);
+        drop(state_lock);
+        self.mvcc().update_commit_ts(ts);
+        // after the commit ts, see `RowCache::insert_if_current`
+        if let (true, Some(row_cache)) = (cf.id() == DEFAULT_COLUMN_FAMILY_ID, &self.row_cache) {
+            row_cache.invalidate_written_to_sst(key);
+        }
+        db_log!(
+            self.options(),
+            Info,
+            "streamed {} bytes to {}.blob, pointed to by {}.sst",
+            index.size,
+            blob_file_id,
+            sst_id
+        );
+        Ok(())
+    }
+
+    /// Same as `get_streaming_cf`, in the default column family.
+    pub fn get_streaming(&self, key: &[u8]) -> Result<Option<ValueReader>> {
+        let cf = self.cf(DEFAULT_COLUMN_FAMILY_NAME).unwrap();
+        self.get_streaming_cf(&cf, key)
+    }
+
+    /// Same as `get_cf`, returning a reader of the value instead of the value. A value written by `put_streaming`
+    /// is read from its blob file a chunk at a time, each one checked against its checksum; the others are read as
+    /// a whole. The row cache is not used.
+    pub fn get_streaming_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<ValueReader>> {
+        let Some(snapshot) = self.column_family_state(cf.id()) else {
+            bail!("column family {} was dropped", cf.name());
+        };
+        // see `get_pinned`
+        let read_ts = self.mvcc().latest_commit_ts();
+        if let Some(value) = Self::get_from_memtables(&snapshot, key, read_ts) {
+            return Ok((!value.is_empty()).then(|| ValueReader::from_bytes(value)));
+        }
+        let Some((table, block, range)) = self.find_in_ssts(&snapshot, key, read_ts)? else {
+            return Ok(None);
+        };
+        let reader = table.value_reader(block, range)?;
+        Ok((reader.size() > 0).then_some(reader))
+    }
+}
+
+impl MiniLsm {
+    pub fn put_streaming(&self, key: &[u8], reader: impl Read) -> Result<()> {
+        self.inner.put_streaming(key, reader)
+    }
+
+    pub fn put_streaming_cf(&self, cf: &ColumnFamily, key: &[u8], reader: impl Read) -> Result<()> {
+        self.inner.put_streaming_cf(cf, key, reader)
+    }
+
+    pub fn get_streaming(&self, key: &[u8]) -> Result<Option<ValueReader>> {
+        self.inner.get_streaming(key)
+    }
+
+    pub fn get_streaming_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<ValueReader>> {
+        self.inner.get_streaming_cf(cf, key)
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::column_family::ColumnFamily;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState, MiniLsm};
+use crate::mem_table::MemTable;
+
+/// A memtable of an `LsmStructure`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemTableStructure {
+    pub id: usize,
+    /// Whether it is frozen, waiting to be flushed.
+    pub frozen: bool,
+    /// Number of key versions, including tombstones.
+    pub num_entries: usize,
+    pub num_deletions: usize,
+    pub approximate_size: usize,
+}
+
+/// An SST of an `LsmStructure`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SstStructure {
+    pub id: usize,
+    /// The smallest and the largest user keys.
+    pub first_key: Vec<u8>,
+    pub last_key: Vec<u8>,
+    pub size: u64,
+    /// Number of entries, including tombstones.
+    pub num_entries: u64,
+    pub num_deletions: u64,
+    /// The smallest and the largest commit ts of the entries.
+    pub min_ts: u64,
+    pub max_ts: u64,
+    /// When the SST was built, in seconds since the UNIX epoch. Zero for SSTs written before it was recorded.
+    pub creation_time: u64,
+}
+
+/// A level of an `LsmStructure`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelStructure {
+    /// 0 for L0, otherwise the id the state knows the level by: its number, or the id of the sorted run for tiered
+    /// compaction.
+    pub level: usize,
+    /// The SSTs in the order they are read, newest first for L0 and by key for the others.
+    pub ssts: Vec<SstStructure>,
+}
+
+impl LevelStructure {
+    pub fn size(&self) -> u64 {
+        self.ssts.iter().map(|sst| sst.size).sum()
+    }
+}
+
+/// The structure of the LSM tree of a column family at one point in time, see `LsmStorageInner::dump_structure`.
+/// Serializable for tools to read it, and displayed as a tree for people.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LsmStructure {
+    /// The memtable being written, then the frozen ones from the newest.
+    pub memtables: Vec<MemTableStructure>,
+    /// L0 first.
+    pub levels: Vec<LevelStructure>,
+}
+
+impl LsmStructure {
+    fn of_state(state: &LsmStorageState) -> Self {
+        let memtable = |memtable: &MemTable, frozen| MemTableStructure {
+            id: memtable.id(),
+            frozen,
+            num_entries: memtable.num_entries(),
+            num_deletions: memtable.num_deletions(),
+            approximate_size: memtable.approximate_size(),
+        };
+        let memtables = std::iter::once(memtable(&state.memtable, false))
+            .chain(state.imm_memtables.iter().map(|imm| memtable(imm, true)))
+            .collect();
+        let sst = |id: &usize| {
+            let table = &state.sstables[id];
+            let properties = table.properties();
+            SstStructure {
+                id: *id,
+                first_key: table.first_key().key_ref().to_vec(),
+                last_key: table.last_key().key_ref().to_vec(),
+                size: table.table_size(),
+                num_entries: properties.num_entries,
+                num_deletions: properties.num_deletions,
+                min_ts: properties.min_ts,
+                max_ts: table.max_ts(),
+                creation_time: properties.creation_time,
+            }
+        };
+        let levels = std::iter::once((0, &state.l0_sstables))
+            .chain(state.levels.iter().map(|(level, ssts)| (*level, ssts)))
+            .map(|(level, ssts)| LevelStructure {
+                level,
+                ssts: ssts.iter().map(sst).collect(),
+            })
+            .collect();
+        Self { memtables, levels }
+    }
+}
+
+impl fmt::Display for LsmStructure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for memtable in &self.memtables {
+            writeln!(
+                f,
+                "{} {}: {} entries, {} deletions, {} bytes",
+                if memtable.frozen {
+                    "frozen memtable"
+                } else {
+                    "memtable"
+                },
+                memtable.id,
+                memtable.num_entries,
+                memtable.num_deletions,
+                memtable.approximate_size
+            )?;
+        }
+        for level in &self.levels {
+            writeln!(
+                f,
+                "L{} ({}): {} bytes",
+                level.level,
+                level.ssts.len(),
+                level.size()
+            )?;
+            for sst in &level.ssts {
+                writeln!(
+                    f,
+                    "  {}.sst [{}, {}]: {} entries, {} deletions, {} bytes, ts {}..={}",
+                    sst.id,
+                    sst.first_key.escape_ascii(),
+                    sst.last_key.escape_ascii(),
+                    sst.num_entries,
+                    sst.num_deletions,
+                    sst.size,
+                    sst.min_ts,
+                    sst.max_ts
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LsmStorageInner {
+    /// The memtables and the SSTs of every level of the default column family. Print it to see the tree.
+    pub fn dump_structure(&self) -> LsmStructure {
+        LsmStructure::of_state(&self.state.read())
+    }
+
+    /// Same as `dump_structure`, for the column family `cf`.
+    pub fn dump_structure_cf(&self, cf: &ColumnFamily) -> Result<LsmStructure> {
+        match self.column_family_state(cf.id()) {
+            Some(state) => Ok(LsmStructure::of_state(&state)),
+            None => bail!("column family {} was dropped", cf.name()),
+        }
+    }
+}
+
+impl MiniLsm {
+    pub fn dump_structure(&self) -> LsmStructure {
+        self.inner.dump_structure()
+    }
+
+    pub fn dump_structure_cf(&self, cf: &ColumnFamily) -> Result<LsmStructure> {
+        self.inner.dump_structure_cf(cf)
+    }
+}
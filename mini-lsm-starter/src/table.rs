@@ -1,22 +1,36 @@
 pub(crate) mod bloom;
 mod builder;
+mod compression;
+mod dump;
 mod iterator;
+mod properties;
 
-use std::fs::File;
+use std::collections::HashMap;
+use std::ops::{Bound, Range};
 use std::path::Path;
 use std::sync::Arc;
-use std::io::{Cursor, Read}; 
+use std::io::{Cursor, Write}; 
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, ReadBytesExt};
+use serde::{Deserialize, Serialize};
 
 pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
+pub use compression::CompressionType;
+pub use dump::{dump_sst, SstDumpOptions};
+use bytes::{Buf, BufMut, Bytes};
 pub use iterator::SsTableIterator;
+pub use properties::TableProperties;
 
-use crate::block::Block;
+use crate::blob::{BlobFile, BlobReference, StreamIndex, ValueReader, VALUE_KIND_INLINE};
+use crate::clock::Instant;
+use crate::fs::{FileSystem, ReadableFile};
+use crate::block::{Block, BlockCacheCounters, BlockCacheCounts, CachePriority, CacheReservation};
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::perf_context::{perf_count, perf_level, PerfLevel};
+use crate::pinnable_slice::PinnableSlice;
+use crate::statistics::{FileIoCounters, FileIoStats, Histogram, Statistics, Ticker};
 
 use self::bloom::Bloom;
 
@@ -38,9 +52,9 @@ impl BlockMeta {
         for meta in block_meta {
             estimated_size += std::mem::size_of::<u32>(); // Offset size
             estimated_size += std::mem::size_of::<u16>(); // First key length size
-            estimated_size += meta.first_key.len(); // First key data size
+            estimated_size += meta.first_key.raw_len(); // First key data and timestamp size
             estimated_size += std::mem::size_of::<u16>(); // Last key length size
-            estimated_size += meta.last_key.len(); // Last key data size
+            estimated_size += meta.last_key.raw_len(); // Last key data and timestamp size
         }
         estimated_size += std::mem::size_of::<u32>(); // Checksum size
 
@@ -51,10 +65,12 @@ impl BlockMeta {
 
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.len() as u16);
-            buf.put_slice(meta.first_key.raw_ref());
-            buf.put_u16(meta.last_key.len() as u16);
-            buf.put_slice(meta.last_key.raw_ref());
+            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put_slice(meta.first_key.key_ref());
+            buf.put_u64(meta.first_key.ts());
+            buf.put_u16(meta.last_key.key_len() as u16);
+            buf.put_slice(meta.last_key.key_ref());
+            buf.put_u64(meta.last_key.ts());
         }
 
         // Calculate and append CRC32 checksum
@@ -66,24 +82,42 @@ impl BlockMeta {
 
     /// Decode block meta from a buffer.
     /// 
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<Vec<BlockMeta>> {
+    pub fn decode_block_meta(buf: &[u8]) -> Result<Vec<BlockMeta>> {
+        if buf.len() < 8 {
+            bail!("meta too short");
+        }
+        // verified before decoding, so that garbage is not decoded
+        let (mut buf, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(&buf[4..]) {
+            bail!("meta checksum mismatched");
+        }
+        let ensure_remaining = |buf: &[u8], len: usize| -> Result<()> {
+            if buf.remaining() < len {
+                bail!("meta truncated");
+            }
+            Ok(())
+        };
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
-        let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
         for _ in 0..num {
+            ensure_remaining(buf, 6)?;
             let offset = buf.get_u32() as usize;
             let first_key_len = buf.get_u16() as usize;
-            let first_key = KeyBytes::from_bytes(buf.copy_to_bytes(first_key_len));
+            ensure_remaining(buf, first_key_len + 10)?;
+            let first_key =
+                KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
             let last_key_len: usize = buf.get_u16() as usize;
-            let last_key = KeyBytes::from_bytes(buf.copy_to_bytes(last_key_len));
+            ensure_remaining(buf, last_key_len + 8)?;
+            let last_key =
+                KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
             block_meta.push(BlockMeta {
                 offset,
                 first_key,
                 last_key,
             });
         }
-        if buf.get_u32() != checksum {
-            bail!("meta checksum mismatched");
+        if buf.has_remaining() {
+            bail!("{} bytes left after the meta", buf.remaining());
         }
 
         Ok(block_meta)
@@ -91,18 +125,25 @@ impl BlockMeta {
 }
 
 /// A file object wrapper.
-pub struct FileObject(Option<File>, u64);
+pub struct FileObject(Option<Arc<dyn ReadableFile>>, u64);
+
+fn read_file_at(file: &dyn ReadableFile, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut data = vec![0; len as usize];
+    file.read_at(&mut data[..], offset)?;
+    Ok(data)
+}
 
 impl FileObject {
     /// Read data from the file at a given offset and length.
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
-        use std::os::unix::fs::FileExt;
-        let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
-        Ok(data)
+        read_file_at(self.0.as_deref().unwrap(), offset, len)
+    }
+
+    /// Read data from the file at a given offset and length on tokio's blocking pool, so that async executor
+    /// threads never wait on disk. Must be called within a tokio runtime.
+    pub async fn read_async(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let file = self.0.as_ref().unwrap().clone();
+        tokio::task::spawn_blocking(move || read_file_at(file.as_ref(), offset, len)).await?
     }
 
     /// Get the size of the file in bytes.
@@ -111,23 +152,74 @@ impl FileObject {
     }
 
     /// Create a new file object and write data to the disk.
-    pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
-        Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
-            data.len() as u64,
-        ))
+    pub fn create(fs: &dyn FileSystem, path: &Path, data: Vec<u8>) -> Result<Self> {
+        let mut file = fs.create(path)?;
+        file.write_all(&data)?;
+        file.sync()?;
+        drop(file);
+        Ok(FileObject(Some(Arc::from(fs.open(path)?)), data.len() as u64))
     }
 
     /// Open an existing file object.
-    pub fn open(path: &Path) -> Result<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
-        Ok(FileObject(Some(file), size))
+    pub fn open(fs: &dyn FileSystem, path: &Path) -> Result<Self> {
+        let file = fs.open(path)?;
+        let size = file.size()?;
+        Ok(FileObject(Some(Arc::from(file)), size))
     }
 }
 
+/// Size and CRC32 of a whole SST file, recorded in the manifest when the SST is written. Unlike the block
+/// checksums, it also covers the footer and the padding between the sections, and it can be checked without
+/// decoding the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChecksum {
+    pub size: u64,
+    pub crc32: u32,
+}
+
+impl FileChecksum {
+    pub fn of_data(data: &[u8]) -> Self {
+        Self {
+            size: data.len() as u64,
+            crc32: crc32fast::hash(data),
+        }
+    }
+
+    /// Read the whole file at `path` and compute its checksum.
+    pub fn of_file(fs: &dyn FileSystem, path: &Path) -> Result<Self> {
+        Ok(Self::of_data(&fs.read(path)?))
+    }
+
+    /// Check that the file at `path` still has this size and checksum.
+    pub fn verify_file(&self, fs: &dyn FileSystem, path: &Path) -> Result<()> {
+        let actual = Self::of_file(fs, path)?;
+        if actual.size != self.size {
+            bail!("file size is {}, expected {}", actual.size, self.size);
+        }
+        if actual.crc32 != self.crc32 {
+            bail!(
+                "file checksum is {:#010x}, expected {:#010x}",
+                actual.crc32,
+                self.crc32
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Where an SSTable keeps its bloom filter and block metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MetadataCaching {
+    /// Held by the table, outside of the block cache's accounting.
+    #[default]
+    Table,
+    /// The bloom filter lives in the block cache at a high priority and is re-read from the file once evicted. The
+    /// block metadata is needed by every read, so it stays pinned in the table and is charged to the cache.
+    Cached,
+    /// Both are held by the table and charged to the block cache, so they are never evicted.
+    Pinned,
+}
+
 /// Structure representing an SSTable.
 pub struct SsTable {
     /// The underlying file object.
@@ -138,16 +230,38 @@ pub struct SsTable {
     pub(crate) block_meta_offset: usize,
     /// Identifier for the SSTable.
     id: usize,
+    /// Identifies the SSTable's blocks in the block cache. Allocated by the cache, as a shared cache serves SSTs of
+    /// several databases whose ids overlap.
+    cache_id: usize,
     /// Optional block cache to improve read performance.
     block_cache: Option<Arc<BlockCache>>,
+    /// Block cache hits, misses, inserts and evictions of this SSTable's blocks.
+    cache_counters: Arc<BlockCacheCounters>,
+    /// Priority of the SSTable's data blocks in the block cache.
+    cache_priority: CachePriority,
     /// First key present in the SSTable.
     first_key: KeyBytes,
     /// Last key present in the SSTable.
     last_key: KeyBytes,
-    /// Optional Bloom filter for quick existence checks.
-    pub(crate) bloom: Option<Bloom>,
+    /// Optional Bloom filter for quick existence checks, `None` when it lives in the block cache.
+    pub(crate) bloom: Option<Arc<Bloom>>,
+    /// Offset and length of the encoded bloom filter in `file`.
+    bloom_range: (u64, u64),
+    metadata_caching: MetadataCaching,
+    /// Charge of the metadata pinned by the table against the block cache.
+    cache_reservation: Option<CacheReservation>,
+    /// Statistics about the entries of the SSTable.
+    pub(crate) properties: TableProperties,
     /// Maximum timestamp stored in the SSTable.
     max_ts: u64,
+    /// Checksum of the whole file, known for SSTs written or recorded in the manifest since checksums exist.
+    file_checksum: Option<FileChecksum>,
+    /// The blob files the table points into, by id, see `with_blob_files`.
+    blob_files: HashMap<usize, Arc<BlobFile>>,
+    /// Where the block cache lookups and the block reads are counted, see `with_statistics`.
+    statistics: Option<Arc<Statistics>>,
+    /// Reads of the file past the block cache.
+    io_counters: FileIoCounters,
 }
 
 impl SsTable {
@@ -160,45 +274,63 @@ impl SsTable {
     /// Open an SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         let len = file.size(); // Get the length of the file
-        println!("File length: {}", len);
+        log::trace!("File length: {}", len);
+        if len < 16 {
+            bail!("file of {} bytes is too short for a footer", len);
+        }
         
     
-        // Read the last 4 bytes of the file to get the bloom filter offset
-        let raw_bloom_offset = file.read(len - 4, 4)?;
+        // Read the last 4 bytes of the file to get the properties offset
+        let raw_properties_offset = file.read(len - 4, 4)?;
+        let properties_offset = (&raw_properties_offset[..]).get_u32() as u64;
+        if properties_offset < 4 || properties_offset >= len - 4 {
+            bail!("Properties offset is out of file length range. Offset: {}, File length: {}", properties_offset, len);
+        }
+        let raw_properties = file.read(properties_offset, len - 4 - properties_offset)?;
+        let properties = TableProperties::decode(&raw_properties)
+            .with_context(|| format!("invalid properties at offset {}", properties_offset))?;
+
+        // Read the 4 bytes preceding the properties to get the bloom filter offset
+        let raw_bloom_offset = file.read(properties_offset - 4, 4)?;
         let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        println!("Raw bloom offset bytes: {:?}", raw_bloom_offset);
-        println!("Bloom filter offset: {}", bloom_offset);
+        log::trace!("Raw bloom offset bytes: {:?}", raw_bloom_offset);
+        log::trace!("Bloom filter offset: {}", bloom_offset);
     
         // Ensure the bloom filter offset is within the file length range
-        if bloom_offset >= len {
+        if bloom_offset < 4 || bloom_offset >= properties_offset - 4 {
             bail!("Bloom filter offset is out of file length range. Offset: {}, File length: {}", bloom_offset, len);
         }
     
         // Calculate the length of the bloom filter data
-        let bloom_filter_len = len - 4 - bloom_offset;
+        let bloom_filter_len = properties_offset - 4 - bloom_offset;
         if bloom_filter_len == 0 {
             bail!("Bloom filter length is zero"); // Bail out if the bloom filter length is zero
         }
     
         // Read the bloom filter data from the file
         let raw_bloom = file.read(bloom_offset, bloom_filter_len)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
+        let bloom_filter = Bloom::decode(&raw_bloom)
+            .with_context(|| format!("invalid bloom filter at offset {}", bloom_offset))?;
     
         // Read the 4 bytes preceding the bloom filter to get the block metadata offset
         let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        println!("Raw block meta offset bytes: {:?}", raw_meta_offset);
-        println!("Block metadata offset: {}", block_meta_offset);
+        log::trace!("Raw block meta offset bytes: {:?}", raw_meta_offset);
+        log::trace!("Block metadata offset: {}", block_meta_offset);
     
         // Ensure the block metadata offset is valid
-        if block_meta_offset >= bloom_offset {
+        if block_meta_offset + 4 > bloom_offset {
             bail!("Block metadata offset is invalid. Offset: {}, Bloom filter offset: {}", block_meta_offset, bloom_offset);
         }
     
         // Calculate the length of the block metadata
         let block_meta_len = bloom_offset - 4 - block_meta_offset;
         let raw_meta = file.read(block_meta_offset, block_meta_len)?;
-        let block_meta = BlockMeta::decode_block_meta(&raw_meta)?;
+        let block_meta = BlockMeta::decode_block_meta(&raw_meta)
+            .with_context(|| format!("invalid block meta at offset {}", block_meta_offset))?;
+        if block_meta.is_empty() {
+            bail!("no data block in block meta at offset {}", block_meta_offset);
+        }
     
         Ok(Self {
             file,
@@ -207,9 +339,20 @@ impl SsTable {
             block_meta,
             block_meta_offset: block_meta_offset as usize,
             id,
+            cache_id: block_cache.as_ref().map_or(id, |cache| cache.new_table_id()),
             block_cache,
-            bloom: Some(bloom_filter),
-            max_ts: 0,
+            cache_counters: Arc::default(),
+            cache_priority: CachePriority::Low,
+            bloom: Some(Arc::new(bloom_filter)),
+            bloom_range: (bloom_offset, bloom_filter_len),
+            metadata_caching: MetadataCaching::Table,
+            cache_reservation: None,
+            max_ts: properties.max_ts,
+            properties,
+            file_checksum: None,
+            blob_files: HashMap::new(),
+            statistics: None,
+            io_counters: FileIoCounters::default(),
         })
     }
     
@@ -226,11 +369,22 @@ impl SsTable {
             block_meta: vec![],
             block_meta_offset: 0,
             id,
+            cache_id: id,
             block_cache: None,
+            cache_counters: Arc::default(),
+            cache_priority: CachePriority::Low,
             first_key,
             last_key,
             bloom: None,
+            bloom_range: (0, 0),
+            metadata_caching: MetadataCaching::Table,
+            cache_reservation: None,
+            properties: TableProperties::default(),
             max_ts: 0,
+            file_checksum: None,
+            blob_files: HashMap::new(),
+            statistics: None,
+            io_counters: FileIoCounters::default(),
         }
     }
 
@@ -241,38 +395,232 @@ impl SsTable {
         }
     
         if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
-            Ok(blk)
+            let mut missed = false;
+            let block = block_cache.try_get_with_for_table(
+                (self.cache_id, block_idx),
+                Some(&self.cache_counters),
+                self.cache_priority,
+                || {
+                    missed = true;
+                    self.read_block(block_idx)
+                },
+            );
+            self.record_block_cache_lookup(missed);
+            block
         } else {
             self.read_block(block_idx)
         }
     }
     
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let (offset, len) = self.block_range(block_idx)?;
+        let started = Instant::now();
+        let block_data_with_chksum = self.file.read(offset, len)?;
+        self.record_block_read(started, len);
+        Self::decode_block_with_checksum(&block_data_with_chksum)
+    }
+
+    fn record_block_read(&self, started: Instant, len: u64) {
+        self.io_counters.record_read(len, started);
+        if let Some(statistics) = &self.statistics {
+            statistics.record_elapsed(Histogram::BlockRead, started);
+        }
+        perf_count(|context| {
+            context.block_read_count += 1;
+            context.block_read_byte += len;
+            if perf_level() >= PerfLevel::EnableTime {
+                context.block_read_time += started.elapsed();
+            }
+        });
+    }
+
+    fn record_block_cache_lookup(&self, missed: bool) {
+        if !missed {
+            perf_count(|context| context.block_cache_hit_count += 1);
+        }
+        if let Some(statistics) = &self.statistics {
+            let ticker = if missed {
+                Ticker::BlockCacheMiss
+            } else {
+                Ticker::BlockCacheHit
+            };
+            statistics.record_tick(ticker, 1);
+        }
+    }
+
+    /// Read a data block through the block cache without blocking the async executor on a cache miss.
+    pub async fn read_block_cached_async(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let Some(ref block_cache) = self.block_cache else {
+            return self.read_block_async(block_idx).await;
+        };
+        let owner = Some(&self.cache_counters);
+        if let Some(blk) = block_cache.get_for_table(&(self.cache_id, block_idx), owner) {
+            self.record_block_cache_lookup(false);
+            return Ok(blk);
+        }
+        if block_cache.has_secondary_cache() {
+            let (block_cache, owner, key, priority) = (
+                block_cache.clone(),
+                self.cache_counters.clone(),
+                (self.cache_id, block_idx),
+                self.cache_priority,
+            );
+            let blk = tokio::task::spawn_blocking(move || {
+                block_cache.get_from_secondary(&key, Some(&owner), priority)
+            })
+            .await?;
+            if let Some(blk) = blk {
+                self.record_block_cache_lookup(false);
+                return Ok(blk);
+            }
+        }
+        self.record_block_cache_lookup(true);
+        let blk = self.read_block_async(block_idx).await?;
+        block_cache.insert_for_table((self.cache_id, block_idx), blk.clone(), owner, self.cache_priority);
+        Ok(blk)
+    }
+
+    pub async fn read_block_async(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let (offset, len) = self.block_range(block_idx)?;
+        let started = Instant::now();
+        let block_data_with_chksum = self.file.read_async(offset, len).await?;
+        self.record_block_read(started, len);
+        Self::decode_block_with_checksum(&block_data_with_chksum)
+    }
+
+    /// Offset and length of a data block including its trailing checksum.
+    fn block_range(&self, block_idx: usize) -> Result<(u64, u64)> {
         if block_idx >= self.block_meta.len() {
             bail!("block index out of bounds: {}", block_idx);
         }
-    
+
         let offset = self.block_meta[block_idx].offset;
         let offset_end = self
             .block_meta
             .get(block_idx + 1)
             .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
-    
-        let block_data_with_chksum: Vec<u8> = self
-            .file
-            .read(offset as u64, (offset_end - offset) as u64)?;
-        let block_data = &block_data_with_chksum[..block_len];
+        Ok((offset as u64, (offset_end - offset) as u64))
+    }
+
+    /// Decode a data block stored as its (possibly compressed) encoding, the compression type and a checksum of
+    /// both.
+    fn decode_block_with_checksum(block_data_with_chksum: &[u8]) -> Result<Arc<Block>> {
+        if block_data_with_chksum.len() < 5 {
+            bail!("block too short");
+        }
+        let block_len = block_data_with_chksum.len() - 4;
         let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-    
-        if checksum != crc32fast::hash(block_data) {
+        if checksum != crc32fast::hash(&block_data_with_chksum[..block_len]) {
             bail!("block checksum mismatched");
         }
-    
-        Ok(Arc::new(Block::decode(block_data)))
+
+        let compression = CompressionType::from_u8(block_data_with_chksum[block_len - 1])?;
+        let block_data = &block_data_with_chksum[..block_len - 1];
+        Ok(Arc::new(match compression {
+            CompressionType::None => Block::decode(block_data)?,
+            compression => Block::decode(&compression.decompress(block_data)?)?,
+        }))
+    }
+
+    /// Approximate number of data bytes the key range occupies in this table, counting every block that may overlap
+    /// with it. Tables without block metadata report their whole size.
+    pub fn approximate_range_size(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> u64 {
+        if self.block_meta.is_empty() {
+            return self.table_size();
+        }
+        let begin = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => self
+                .block_meta
+                .partition_point(|meta| meta.last_key.key_ref() < key),
+            Bound::Unbounded => 0,
+        };
+        let end = match upper {
+            Bound::Included(key) | Bound::Excluded(key) => self
+                .block_meta
+                .partition_point(|meta| meta.first_key.key_ref() <= key),
+            Bound::Unbounded => self.block_meta.len(),
+        };
+        if begin >= end {
+            return 0;
+        }
+        let begin_offset = self.block_meta[begin].offset;
+        let end_offset = self
+            .block_meta
+            .get(end)
+            .map_or(self.block_meta_offset, |meta| meta.offset);
+        (end_offset - begin_offset) as u64
+    }
+
+    /// Move the bloom filter and block metadata under the block cache's accounting. Does nothing without a
+    /// block cache.
+    pub fn with_metadata_caching(mut self, caching: MetadataCaching) -> Self {
+        let Some(block_cache) = self.block_cache.clone() else {
+            return self;
+        };
+        let index_charge: usize = self
+            .block_meta
+            .iter()
+            .map(|meta| {
+                std::mem::size_of::<BlockMeta>() + meta.first_key.raw_len() + meta.last_key.raw_len()
+            })
+            .sum();
+        let bloom_charge = self.bloom.as_ref().map_or(0, |bloom| bloom.filter.len());
+        let charge = match caching {
+            MetadataCaching::Table => 0,
+            MetadataCaching::Cached => {
+                if let Some(bloom) = self.bloom.take() {
+                    block_cache.insert_filter(self.cache_id, bloom, Some(&self.cache_counters));
+                }
+                index_charge
+            }
+            MetadataCaching::Pinned => index_charge + bloom_charge,
+        };
+        self.cache_reservation =
+            (charge > 0).then(|| block_cache.reserve(self.cache_id, charge as u64));
+        self.metadata_caching = caching;
+        self
+    }
+
+    /// Set the priority the data blocks are cached at.
+    pub fn with_cache_priority(self, cache_priority: CachePriority) -> Self {
+        Self {
+            cache_priority,
+            ..self
+        }
+    }
+
+    fn read_bloom(&self) -> Result<Arc<Bloom>> {
+        let (offset, len) = self.bloom_range;
+        let started = Instant::now();
+        let data = self.file.read(offset, len)?;
+        self.io_counters.record_read(len, started);
+        Ok(Arc::new(Bloom::decode(&data)?))
+    }
+
+    /// Whether the table may contain a key with the given hash according to its bloom filter. A filter that fails to
+    /// be read back from the file is treated as a match, leaving it to the block reads to report the error.
+    pub fn may_contain(&self, hash: u32) -> bool {
+        if let Some(bloom) = &self.bloom {
+            return bloom.may_contain(hash);
+        }
+        match (&self.block_cache, self.metadata_caching) {
+            (Some(block_cache), MetadataCaching::Cached) => block_cache
+                .try_get_filter_with(self.cache_id, Some(&self.cache_counters), || self.read_bloom())
+                .map_or(true, |bloom| bloom.may_contain(hash)),
+            _ => true,
+        }
+    }
+
+    /// Load the bloom filter into the block cache if it lives there.
+    pub fn warm_filter(&self) -> Result<()> {
+        if let (Some(block_cache), MetadataCaching::Cached, None) =
+            (&self.block_cache, self.metadata_caching, &self.bloom)
+        {
+            block_cache.try_get_filter_with(self.cache_id, Some(&self.cache_counters), || {
+                self.read_bloom()
+            })?;
+        }
+        Ok(())
     }
 
     /// Find the block index that may contain a given `key`.
@@ -287,6 +635,42 @@ impl SsTable {
         self.block_meta.len()
     }
 
+    /// Check that the block meta describes ordered, non-overlapping data blocks filling the file up to the block
+    /// meta, without reading the blocks.
+    pub fn verify_block_meta(&self) -> Result<()> {
+        for (block_idx, meta) in self.block_meta.iter().enumerate() {
+            let previous = block_idx
+                .checked_sub(1)
+                .map(|previous| &self.block_meta[previous]);
+            match previous {
+                None if meta.offset != 0 => {
+                    bail!("block 0 at offset {} does not start the file", meta.offset)
+                }
+                // the smallest block holds a compression type and a checksum
+                Some(previous) if meta.offset < previous.offset + 5 => bail!(
+                    "block {} at offset {} overlaps the previous block",
+                    block_idx,
+                    meta.offset
+                ),
+                _ => {}
+            }
+            if meta.offset + 5 > self.block_meta_offset {
+                bail!(
+                    "block {} at offset {} is past the block meta at offset {}",
+                    block_idx,
+                    meta.offset,
+                    self.block_meta_offset
+                );
+            }
+            if meta.first_key > meta.last_key
+                || previous.is_some_and(|previous| previous.last_key >= meta.first_key)
+            {
+                bail!("block {} at offset {} has unordered keys", block_idx, meta.offset);
+            }
+        }
+        Ok(())
+    }
+
     /// Get the first key in the SSTable.
     pub fn first_key(&self) -> &KeyBytes {
         &self.first_key
@@ -307,8 +691,150 @@ impl SsTable {
         self.id
     }
 
+    /// Get the properties recorded when the SSTable was built.
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
     /// Get the maximum timestamp stored in the SSTable.
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    /// Get the checksum of the whole file, `None` when it was not recorded.
+    pub fn file_checksum(&self) -> Option<FileChecksum> {
+        self.file_checksum
+    }
+
+    /// Attach the checksum the manifest recorded for the file.
+    pub(crate) fn with_file_checksum(self, file_checksum: Option<FileChecksum>) -> Self {
+        Self {
+            file_checksum,
+            ..self
+        }
+    }
+
+    /// Attach the blob files the table points into, every one of `properties().blob_files`.
+    pub(crate) fn with_blob_files(self, blob_files: HashMap<usize, Arc<BlobFile>>) -> Self {
+        Self { blob_files, ..self }
+    }
+
+    /// Count the block cache lookups and the block reads of the table in `statistics`.
+    pub(crate) fn with_statistics(self, statistics: Option<Arc<Statistics>>) -> Self {
+        Self { statistics, ..self }
+    }
+
+    /// Get the blob files the table points into.
+    pub fn blob_files(&self) -> impl Iterator<Item = &Arc<BlobFile>> {
+        self.blob_files.values()
+    }
+
+    /// The value of an entry stored as `stored` in a data block, without its kind for the inline values of the
+    /// tables with tagged values. An encoded `BlobReference` when `is_blob_reference` says so.
+    pub(crate) fn entry_value<'a>(&self, stored: &'a [u8]) -> &'a [u8] {
+        if self.properties.tagged_values && !self.is_blob_reference(stored) {
+            stored.get(1..).unwrap_or_default()
+        } else {
+            stored
+        }
+    }
+
+    /// Whether the value of the entry stored as `stored` is a `BlobReference`.
+    pub(crate) fn is_blob_reference(&self, stored: &[u8]) -> bool {
+        self.properties.tagged_values
+            && stored
+                .first()
+                .is_some_and(|kind| *kind != VALUE_KIND_INLINE)
+    }
+
+    /// Read the whole value the encoded `BlobReference` of an entry points to.
+    pub fn read_blob(&self, reference: &[u8]) -> Result<Bytes> {
+        self.read_blob_reference(&BlobReference::decode(reference)?)
+    }
+
+    pub(crate) fn read_blob_reference(&self, reference: &BlobReference) -> Result<Bytes> {
+        self.blob_file_of(reference.file_id())?
+            .read_reference(reference)
+    }
+
+    /// A reader of the chunks of a stream in the blob files of the table.
+    pub(crate) fn stream_reader(&self, index: &StreamIndex) -> Result<ValueReader> {
+        let blob_file = self.blob_file_of(index.file_id)?;
+        Ok(ValueReader::from_stream(blob_file.clone(), *index))
+    }
+
+    fn blob_file_of(&self, blob_file_id: usize) -> Result<&Arc<BlobFile>> {
+        match self.blob_files.get(&blob_file_id) {
+            Some(blob_file) => Ok(blob_file),
+            None => bail!(
+                "{}.sst points into {}.blob, which is not attached to it",
+                self.id,
+                blob_file_id
+            ),
+        }
+    }
+
+    /// A reader of the value of the entry stored in `block` at `range`, reading a value written by `put_streaming`
+    /// a chunk at a time.
+    pub(crate) fn value_reader(&self, block: Arc<Block>, range: Range<usize>) -> Result<ValueReader> {
+        let stored = &block.data[range.clone()];
+        if self.is_blob_reference(stored) {
+            if let BlobReference::Stream(index) = BlobReference::decode(stored)? {
+                return self.stream_reader(&index);
+            }
+        }
+        Ok(ValueReader::from_bytes(
+            self.read_entry_value(block, range)?.to_bytes(),
+        ))
+    }
+
+    /// The value of the entry stored in `block` at `range`, read from its blob file when it is not in the block.
+    pub(crate) fn read_entry_value(
+        &self,
+        block: Arc<Block>,
+        range: Range<usize>,
+    ) -> Result<PinnableSlice> {
+        if !self.properties.tagged_values {
+            return Ok(PinnableSlice::from_block(block, range));
+        }
+        let stored = &block.data[range.clone()];
+        if self.is_blob_reference(stored) {
+            return Ok(PinnableSlice::from_bytes(self.read_blob(stored)?));
+        }
+        let start = (range.start + 1).min(range.end);
+        Ok(PinnableSlice::from_block(block, start..range.end))
+    }
+
+    /// Same as `read_entry_value`, reading the blob file on tokio's blocking pool. Must be called within a tokio
+    /// runtime.
+    pub(crate) async fn read_entry_value_async(
+        &self,
+        block: Arc<Block>,
+        range: Range<usize>,
+    ) -> Result<PinnableSlice> {
+        let stored = &block.data[range.clone()];
+        if !self.is_blob_reference(stored) {
+            return self.read_entry_value(block, range);
+        }
+        let reference = BlobReference::decode(stored)?;
+        let blob_file = self.blob_file_of(reference.file_id())?.clone();
+        let value =
+            tokio::task::spawn_blocking(move || blob_file.read_reference(&reference)).await??;
+        Ok(PinnableSlice::from_bytes(value))
+    }
+
+    /// Get the priority the data blocks are cached at.
+    pub fn cache_priority(&self) -> CachePriority {
+        self.cache_priority
+    }
+
+    /// Get the block cache counters of this SSTable.
+    pub fn block_cache_counts(&self) -> BlockCacheCounts {
+        self.cache_counters.counts()
+    }
+
+    /// Get the reads of this SSTable's file past the block cache.
+    pub fn io_stats(&self) -> FileIoStats {
+        self.io_counters.stats()
+    }
 }
@@ -1,7 +1,7 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
-use anyhow::Result;
-use bytes::{BufMut, Bytes, BytesMut};
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 /// Implements a bloom filter
 pub struct Bloom {
@@ -44,21 +44,36 @@ impl<T: AsMut<[u8]>> BitSliceMut for T {
     }
 }
 
+/// Hash a user key for bloom filter probing. SST builders and readers must agree on it.
+pub fn key_hash(key: &[u8]) -> u32 {
+    xxhash_rust::xxh32::xxh32(key, 0)
+}
+
 impl Bloom {
     /// Decode a bloom filter
     pub fn decode(buf: &[u8]) -> Result<Self> {
-        let filter = &buf[..buf.len() - 1];
-        let k = buf[buf.len() - 1];
+        if buf.len() < 5 {
+            bail!("bloom filter too short");
+        }
+        let (data, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(data) {
+            bail!("bloom filter checksum mismatched");
+        }
+        let filter = &data[..data.len() - 1];
+        let k = data[data.len() - 1];
         Ok(Self {
             filter: filter.to_vec().into(),
             k,
         })
     }
 
-    /// Encode a bloom filter
+    /// Encode a bloom filter, followed by a checksum
     pub fn encode(&self, buf: &mut Vec<u8>) {
+        let begin = buf.len();
         buf.extend(&self.filter);
         buf.put_u8(self.k);
+        let checksum = crc32fast::hash(&buf[begin..]);
+        buf.put_u32(checksum);
     }
 
     /// Get bloom filter bits per key from entries count and FPR
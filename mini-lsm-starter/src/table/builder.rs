@@ -1,17 +1,21 @@
 #![allow(unused_variables)] // TODO: remove this lint after implementing this mod
 #![allow(dead_code)] // TODO: remove this lint after implementing this mod
 
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use xxhash_rust::xxh32::Xxh32;
 use anyhow::Result;
 use bytes::BufMut;
 
-use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
-use crate::block::BlockBuilder;
+use super::bloom::{key_hash, Bloom};
+use super::{
+    BlockMeta, CompressionType, FileChecksum, FileObject, MetadataCaching, SsTable, TableProperties,
+};
+use crate::blob::{BlobReference, VALUE_KIND_INLINE};
+use crate::block::{BlockBuilder, CachePriority};
+use crate::clock::{default_clock, Clock};
+use crate::fs::{default_file_system, FileSystem};
 use crate::key::{KeySlice, KeyVec};
 use crate::lsm_storage::BlockCache;
 
@@ -33,6 +37,18 @@ pub struct SsTableBuilder {
     key_hashes: Vec<u32>,
     // Maximum timestamp of all keys.
     max_ts: u64,
+    // Minimum timestamp of all keys, `u64::MAX` before any is added.
+    min_ts: u64,
+    // Statistics about the added entries.
+    properties: TableProperties,
+    // Codec of the data blocks.
+    compression: CompressionType,
+    // Where the table is written.
+    fs: Arc<dyn FileSystem>,
+    // Gives the creation time of the table.
+    clock: Arc<dyn Clock>,
+    // Whether the values start with their kind, see `with_blob_indexes`.
+    tagged_values: bool,
 }
 
 impl SsTableBuilder {
@@ -47,58 +63,114 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            min_ts: u64::MAX,
+            properties: TableProperties::default(),
+            compression: CompressionType::None,
+            fs: default_file_system(),
+            clock: default_clock(),
+            tagged_values: false,
         }
     }
 
+    /// Compress the data blocks with `compression`. Blocks that do not get any smaller are stored as they are.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Write the table to `fs` instead of the local disk.
+    pub fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    /// Read the creation time of the table from `clock` instead of the system.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Stamp the table with the identity of the database and of the session writing it.
+    pub fn with_db_identity(mut self, db_id: &str, db_session_id: &str) -> Self {
+        self.properties.db_id = db_id.to_string();
+        self.properties.db_session_id = db_session_id.to_string();
+        self
+    }
+
+    /// Start every value with a byte telling it from a `BlobReference`, so that `add_blob_reference` can be called.
+    pub fn with_blob_indexes(mut self) -> Self {
+        self.tagged_values = true;
+        self.properties.tagged_values = true;
+        self
+    }
+
     /// Adds a key-value pair to the SSTable.
     ///
     /// # Arguments
     /// * `key`: The key to add.
     /// * `value`: The value associated with the key.
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        if self.tagged_values {
+            let mut stored = Vec::with_capacity(value.len() + 1);
+            stored.put_u8(VALUE_KIND_INLINE);
+            stored.put_slice(value);
+            self.add_entry(key, value, &stored);
+        } else {
+            self.add_entry(key, value, value);
+        }
+    }
+
+    /// Add a key whose value is in a blob file, storing `reference` instead of the value. The builder must have
+    /// been created `with_blob_indexes`.
+    pub fn add_blob_reference(&mut self, key: KeySlice, reference: &BlobReference) {
+        assert!(
+            self.tagged_values,
+            "blob references are added to a builder without tagged values"
+        );
+        let mut stored = Vec::new();
+        reference.encode(&mut stored);
+        *self
+            .properties
+            .blob_files
+            .entry(reference.file_id())
+            .or_default() += reference.record_size();
+        self.add_entry(key, &stored, &stored);
+    }
+
+    /// Add an entry whose value reads as `value`, stored as `stored` in the block.
+    fn add_entry(&mut self, key: KeySlice, value: &[u8], stored: &[u8]) {
         // If the first key is empty, set it to the current key.
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
 
-        // Generate timestamp
-        let timestamp = generate_timestamp();
-        println!("Timestamp: {}", timestamp);
-
         // Update max timestamp if necessary
-        if timestamp > self.max_ts {
-            self.max_ts = timestamp;
-        }
+        self.max_ts = self.max_ts.max(key.ts());
+        self.min_ts = self.min_ts.min(key.ts());
 
         // Generate and store the hash of the key using Xxh32
-        let mut hasher = Xxh32::new(0);
-        hasher.update(key.into_inner());
-        let hash = hasher.digest();
-        println!("Key hash: {}", hash);
+        let hash = key_hash(key.key_ref());
+        log::trace!("Key hash: {}", hash);
         self.key_hashes.push(hash);
+        self.properties.add(key.key_ref(), value);
+        if !self.last_key.is_empty() && self.last_key.key_ref() == key.key_ref() {
+            // an older version, it goes away once the version added before it is below the watermark
+            self.properties
+                .add_garbage(key.raw_len() + value.len(), self.last_key.ts());
+        } else if value.is_empty() {
+            self.properties.add_garbage(key.raw_len(), key.ts());
+        }
 
         // Try to add the key-value pair to the current block.
-        if !self.builder.add(key, value) {
+        if !self.builder.add(key, stored) {
             // If the current block is full, finish the block and start a new one.
             self.finish_block();
-            assert!(self.builder.add(key, value));
+            assert!(self.builder.add(key, stored));
             self.first_key.set_from_slice(key);
         }
 
         // Update last key to the current key
         self.last_key.set_from_slice(key);
-
-        // Define the function to generate a timestamp
-        fn generate_timestamp() -> u64 {
-            // Get current system time
-            let now = SystemTime::now();
-
-            // Calculate duration since UNIX epoch
-            let duration = now.duration_since(UNIX_EPOCH).expect("Time went backwards");
-
-            // Return the number of seconds as u64
-            duration.as_secs()
-        }
     }
 
     /// Get the estimated size of the SSTable.
@@ -118,8 +190,14 @@ impl SsTableBuilder {
             first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
         });
-        let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
+        let (compression, block) = match self.compression.compress(&encoded_block) {
+            Some(compressed) => (self.compression, compressed),
+            None => (CompressionType::None, encoded_block.to_vec()),
+        };
+        let block_begin = self.data.len();
+        self.data.extend(block);
+        self.data.put_u8(compression.to_u8());
+        let checksum = crc32fast::hash(&self.data[block_begin..]);
         self.data.put_u32(checksum);
     }
 
@@ -160,22 +238,44 @@ impl SsTableBuilder {
         bloom.encode(&mut buf);
         buf.put_u32(bloom_offset as u32); // Record the Bloom filter offset
 
+        // Record the table properties and their offset
+        self.properties.num_data_blocks = self.meta.len() as u64;
+        self.properties.max_ts = self.max_ts;
+        self.properties.min_ts = self.min_ts.min(self.max_ts);
+        self.properties.creation_time = self.clock.unix_seconds();
+        self.properties.compression = self.compression;
+        let properties_offset = buf.len();
+        self.properties.encode(&mut buf);
+        buf.put_u32(properties_offset as u32);
+
      
 
         // Create the FileObject and write the buffer to disk
-        let file = FileObject::create(path.as_ref(), buf)?;
+        let file_checksum = FileChecksum::of_data(&buf);
+        let file = FileObject::create(self.fs.as_ref(), path.as_ref(), buf)?;
 
         // Return the constructed SsTable with all relevant metadata
         Ok(SsTable {
             id,
+            cache_id: block_cache.as_ref().map_or(id, |cache| cache.new_table_id()),
             file,
             first_key: self.meta.first().unwrap().first_key.clone(),
             last_key: self.meta.last().unwrap().last_key.clone(),
             block_meta: self.meta,
             block_meta_offset: meta_offset, // Offset where block metadata starts
             block_cache,
-            bloom: Some(bloom),
+            cache_counters: Arc::default(),
+            cache_priority: CachePriority::Low,
+            bloom: Some(Arc::new(bloom)),
+            bloom_range: (bloom_offset as u64, (properties_offset - 4 - bloom_offset) as u64),
+            metadata_caching: MetadataCaching::Table,
+            cache_reservation: None,
+            properties: self.properties,
             max_ts: self.max_ts, // Use the latest timestamp tracked
+            file_checksum: Some(file_checksum),
+            blob_files: HashMap::new(),
+            statistics: None,
+            io_counters: Default::default(),
         })
     }
 
@@ -0,0 +1,136 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+use serde::{Deserialize, Serialize};
+
+/// Codec of the data blocks of an SST. Every block records the codec it was written with, so SSTs and levels
+/// using different codecs can be read alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionType {
+    #[default]
+    None,
+    /// A byte-oriented LZ77 codec, fast to decode and effective on the repeated key prefixes and values of a block.
+    Lz,
+}
+
+impl CompressionType {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz => 1,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Lz),
+            _ => bail!("unknown compression type {}", value),
+        }
+    }
+
+    /// Compress `data`, or return `None` when it does not get any smaller.
+    pub(crate) fn compress(self, data: &[u8]) -> Option<Vec<u8>> {
+        let compressed = match self {
+            Self::None => return None,
+            Self::Lz => lz_compress(data),
+        };
+        (compressed.len() < data.len()).then_some(compressed)
+    }
+
+    pub(crate) fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz => lz_decompress(data),
+        }
+    }
+}
+
+// A compressed block is the decompressed length (u32) followed by tokens. A token byte below 0x80 is followed by
+// that many plus one literal bytes, otherwise it is a match of `(token & 0x7f) + MIN_MATCH` bytes copied from the
+// u16 distance that follows.
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = 0x7f + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn hash4(data: &[u8]) -> usize {
+    let word = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    (word.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn put_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.put_u8((chunk.len() - 1) as u8);
+        out.put_slice(chunk);
+    }
+}
+
+fn lz_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 4);
+    out.put_u32(data.len() as u32);
+    // the last position every hashed 4-byte sequence was seen at
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let (mut pos, mut literal_start) = (0, 0);
+    while pos + MIN_MATCH <= data.len() {
+        let hash = hash4(&data[pos..]);
+        let candidate = table[hash];
+        table[hash] = pos;
+        if candidate == usize::MAX
+            || pos - candidate > MAX_DISTANCE
+            || data[candidate..candidate + MIN_MATCH] != data[pos..pos + MIN_MATCH]
+        {
+            pos += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while len < MAX_MATCH && pos + len < data.len() && data[candidate + len] == data[pos + len]
+        {
+            len += 1;
+        }
+        put_literals(&mut out, &data[literal_start..pos]);
+        out.put_u8(0x80 | (len - MIN_MATCH) as u8);
+        out.put_u16((pos - candidate) as u16);
+        pos += len;
+        literal_start = pos;
+    }
+    put_literals(&mut out, &data[literal_start..]);
+    out
+}
+
+fn lz_decompress(mut data: &[u8]) -> Result<Vec<u8>> {
+    if data.remaining() < 4 {
+        bail!("compressed block too short");
+    }
+    let len = data.get_u32() as usize;
+    let mut out = Vec::with_capacity(len);
+    while data.has_remaining() {
+        let token = data.get_u8() as usize;
+        if token < 0x80 {
+            let num_literals = token + 1;
+            if data.remaining() < num_literals {
+                bail!("compressed block truncated");
+            }
+            out.put_slice(&data[..num_literals]);
+            data.advance(num_literals);
+        } else {
+            if data.remaining() < 2 {
+                bail!("compressed block truncated");
+            }
+            let match_len = (token & 0x7f) + MIN_MATCH;
+            let distance = data.get_u16() as usize;
+            if distance == 0 || distance > out.len() {
+                bail!("invalid match distance {}", distance);
+            }
+            // the match may overlap the bytes it produces
+            let start = out.len() - distance;
+            for i in 0..match_len {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    if out.len() != len {
+        bail!("decompressed {} bytes, expected {}", out.len(), len);
+    }
+    Ok(out)
+}
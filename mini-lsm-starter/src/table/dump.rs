@@ -0,0 +1,165 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use super::{CompressionType, FileChecksum, FileObject, SsTable};
+use crate::block::BlockIterator;
+use crate::fs::FileSystem;
+
+/// What `dump_sst` prints besides the footer, the properties and the block metadata.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SstDumpOptions {
+    /// Print every entry, each version of a key on its own.
+    pub entries: bool,
+    /// Print the commit ts of the entries.
+    pub timestamps: bool,
+    /// Print the keys and values in hex rather than escaped.
+    pub hex: bool,
+    /// Read every data block to check its checksum, and print the checksum of the whole file.
+    pub verify_checksums: bool,
+}
+
+fn format_bytes(bytes: &[u8], hex: bool) -> String {
+    if hex {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    } else {
+        bytes.escape_ascii().to_string()
+    }
+}
+
+/// Print the SST at `path` to `out`: the offsets of its sections, its properties, the metadata of its blocks and the
+/// shape of its bloom filter, then its entries if asked to. The footer, the bloom filter and the properties are
+/// checked when the table is opened. A data block failing its checksum is reported and the dump goes on, then it
+/// fails once everything is printed.
+pub fn dump_sst(
+    fs: &dyn FileSystem,
+    path: &Path,
+    options: &SstDumpOptions,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let file = FileObject::open(fs, path)?;
+    let table = SsTable::open(0, None, file)?;
+    let (bloom_offset, bloom_len) = table.bloom_range;
+    writeln!(out, "footer:")?;
+    writeln!(out, "  file size: {}", table.table_size())?;
+    writeln!(out, "  block meta offset: {}", table.block_meta_offset)?;
+    writeln!(
+        out,
+        "  bloom filter offset: {} ({} bytes)",
+        bloom_offset, bloom_len
+    )?;
+    writeln!(out, "  properties offset: {}", bloom_offset + bloom_len + 4)?;
+    writeln!(
+        out,
+        "properties: {}",
+        serde_json::to_string_pretty(&table.properties)?
+    )?;
+
+    let mut corrupted_blocks = 0;
+    writeln!(out, "blocks: {}", table.num_of_blocks())?;
+    for (block_idx, meta) in table.block_meta.iter().enumerate() {
+        let (offset, len) = table.block_range(block_idx)?;
+        let mut line = format!(
+            "  block {}: offset {} size {} first key {} @ {} last key {} @ {}",
+            block_idx,
+            offset,
+            len,
+            format_bytes(meta.first_key.key_ref(), options.hex),
+            meta.first_key.ts(),
+            format_bytes(meta.last_key.key_ref(), options.hex),
+            meta.last_key.ts(),
+        );
+        if options.verify_checksums {
+            let data = table.file.read(offset, len)?;
+            match SsTable::decode_block_with_checksum(&data) {
+                Ok(block) => {
+                    let compression =
+                        CompressionType::from_u8(data[data.len() - 5]).unwrap_or_default();
+                    line += &format!(
+                        " entries {} compression {:?}",
+                        block.offsets.len(),
+                        compression
+                    );
+                }
+                Err(e) => {
+                    corrupted_blocks += 1;
+                    line += &format!(" CORRUPTED: {}", e);
+                }
+            }
+        }
+        writeln!(out, "{}", line)?;
+    }
+
+    if let Some(bloom) = &table.bloom {
+        let num_bits = bloom.filter.len() * 8;
+        let num_bits_set = bloom
+            .filter
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum::<usize>();
+        let fill = num_bits_set as f64 / num_bits.max(1) as f64;
+        writeln!(out, "bloom filter:")?;
+        writeln!(
+            out,
+            "  bits: {} ({} set, {:.1}%)",
+            num_bits,
+            num_bits_set,
+            fill * 100.0
+        )?;
+        writeln!(out, "  hash functions: {}", bloom.k)?;
+        writeln!(
+            out,
+            "  bits per entry: {:.1}",
+            num_bits as f64 / table.properties.num_entries.max(1) as f64
+        )?;
+        writeln!(
+            out,
+            "  estimated false positive rate: {:.4}%",
+            fill.powi(bloom.k as i32) * 100.0
+        )?;
+    }
+
+    if options.verify_checksums {
+        let checksum = FileChecksum::of_file(fs, path)?;
+        writeln!(
+            out,
+            "file checksum: crc32 {:08x} size {}",
+            checksum.crc32, checksum.size
+        )?;
+    }
+
+    if options.entries {
+        writeln!(out, "entries:")?;
+        for block_idx in 0..table.num_of_blocks() {
+            let mut iter = BlockIterator::create_and_seek_to_first(table.read_block(block_idx)?);
+            while iter.is_valid() {
+                let key = format_bytes(iter.key().key_ref(), options.hex);
+                let key = if options.timestamps {
+                    format!("{} @ {}", key, iter.key().ts())
+                } else {
+                    key
+                };
+                let stored = iter.value();
+                let value = if table.is_blob_reference(stored) {
+                    "(blob reference)".to_string()
+                } else if table.entry_value(stored).is_empty() {
+                    "(deleted)".to_string()
+                } else {
+                    format_bytes(table.entry_value(stored), options.hex)
+                };
+                writeln!(out, "  {} => {}", key, value)?;
+                iter.next();
+            }
+        }
+    }
+
+    if corrupted_blocks > 0 {
+        bail!(
+            "{} of {} data blocks are corrupted",
+            corrupted_blocks,
+            table.num_of_blocks()
+        );
+    }
+    Ok(())
+}
@@ -94,7 +94,13 @@ impl StorageIterator for SsTableIterator {
 
     /// Returns the value held by the underlying block iterator.
     fn value(&self) -> &[u8] {
-        self.blk_iter.value()
+        self.table.entry_value(self.blk_iter.value())
+    }
+
+    fn blob_index_table(&self) -> Option<&SsTable> {
+        self.table
+            .is_blob_reference(self.blk_iter.value())
+            .then_some(self.table.as_ref())
     }
 
     /// Returns whether the current block iterator is valid or not.
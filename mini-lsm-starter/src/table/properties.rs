@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+use serde::{Deserialize, Serialize};
+
+use super::CompressionType;
+
+/// Statistics about the content of an SST, written after the bloom filter when the table is built.
+///
+/// Properties are stored as JSON followed by a checksum. Fields missing from an older file decode to their default
+/// value, so new properties can be added without rewriting existing tables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TableProperties {
+    /// Number of entries, including tombstones.
+    pub num_entries: u64,
+    /// Number of tombstones, i.e. entries with an empty value.
+    pub num_deletions: u64,
+    /// Total size of all keys in bytes.
+    pub raw_key_size: u64,
+    /// Total size of all values in bytes.
+    pub raw_value_size: u64,
+    /// Number of data blocks.
+    pub num_data_blocks: u64,
+    /// Largest commit timestamp of all entries.
+    pub max_ts: u64,
+    /// Smallest commit timestamp of all entries. Zero for tables written before it was recorded.
+    pub min_ts: u64,
+    /// When the table was built, in seconds since the UNIX epoch. Zero for tables written before it was recorded.
+    pub creation_time: u64,
+    /// Bytes of the versions shadowed by a newer version of their key and of the tombstones. A compaction to the
+    /// bottom level drops all of them once the MVCC watermark reaches `garbage_ts`.
+    pub garbage_bytes: u64,
+    /// Largest commit timestamp the garbage is waiting for: of the tombstones, and of the versions shadowing older
+    /// ones.
+    pub garbage_ts: u64,
+    /// Codec the data blocks were compressed with, blocks that would not get smaller are stored uncompressed.
+    pub compression: CompressionType,
+    /// The database that wrote the table, see `LsmStorageInner::db_id`. Empty for tables written before it was
+    /// recorded.
+    pub db_id: String,
+    /// The session, i.e. the open of the database, that wrote the table.
+    pub db_session_id: String,
+    /// Whether every value starts with a byte telling an inline value from a `BlobReference`, set for the tables
+    /// written while blob files may be pointed to.
+    pub tagged_values: bool,
+    /// Bytes of the records, values and checksums, the table points to in each blob file, by blob file id.
+    pub blob_files: BTreeMap<usize, u64>,
+}
+
+impl TableProperties {
+    /// Record an entry added to the table.
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.num_entries += 1;
+        if value.is_empty() {
+            self.num_deletions += 1;
+        }
+        self.raw_key_size += key.len() as u64;
+        self.raw_value_size += value.len() as u64;
+    }
+
+    /// Record an entry that a compaction to the bottom level drops once the watermark reaches `ts`.
+    pub(crate) fn add_garbage(&mut self, size: usize, ts: u64) {
+        self.garbage_bytes += size as u64;
+        self.garbage_ts = self.garbage_ts.max(ts);
+    }
+
+    /// Bytes a compaction to the bottom level at `watermark` drops, all or nothing.
+    pub fn reclaimable_bytes(&self, watermark: u64) -> u64 {
+        if self.garbage_ts <= watermark {
+            self.garbage_bytes
+        } else {
+            0
+        }
+    }
+
+    /// Estimated number of live keys. Every tombstone is assumed to shadow one older entry.
+    pub fn estimated_num_keys(&self) -> u64 {
+        self.num_entries.saturating_sub(2 * self.num_deletions)
+    }
+
+    /// Share of the entries that are tombstones.
+    pub fn deletion_ratio(&self) -> f64 {
+        if self.num_entries == 0 {
+            return 0.0;
+        }
+        self.num_deletions as f64 / self.num_entries as f64
+    }
+
+    /// Encode the properties into a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let data = serde_json::to_vec(self).expect("table properties are always serializable");
+        let checksum = crc32fast::hash(&data);
+        buf.put_slice(&data);
+        buf.put_u32(checksum);
+    }
+
+    /// Decode the properties from a buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 4 {
+            bail!("table properties too short");
+        }
+        let (data, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(data) {
+            bail!("table properties checksum mismatched");
+        }
+        Ok(serde_json::from_slice(data)?)
+    }
+}
@@ -8,3 +8,94 @@ mod week1_day3;
 
 mod week1_day4;
 mod week1_day6;
+mod row_cache;
+mod async_get;
+mod estimate;
+mod pinned_get;
+mod time_travel;
+mod block_cache;
+mod shared_cache;
+mod leveled_compaction;
+mod universal_compaction;
+mod fifo_compaction;
+mod periodic_compaction;
+mod subcompaction;
+mod background_jobs;
+mod trivial_move;
+mod tombstone_compaction;
+mod bottommost_recompaction;
+mod level_stats;
+mod compact_files;
+mod sst_partitioner;
+mod per_level_options;
+mod event_listener;
+mod plan_compactions;
+mod graceful_close;
+mod rate_limiter;
+mod manifest_checksums;
+mod manifest_rollover;
+mod manifest_current;
+mod repair;
+mod best_effort_recovery;
+mod obsolete_files;
+mod paranoid_checks;
+mod db_identity;
+mod file_checksums;
+mod manifest_versioning;
+mod snapshots;
+mod mvcc_gc;
+mod transactions;
+mod txn_savepoints;
+mod two_phase_commit;
+mod ts_oracle;
+mod txn_scan;
+mod txn_spill;
+mod txn_conflict_granularity;
+mod txn_expiration;
+mod snapshot_diagnostics;
+mod options_builder;
+mod options_file;
+mod dynamic_options;
+mod read_only;
+mod secondary;
+mod file_system;
+mod in_memory_fs;
+mod fault_injection;
+mod clock;
+mod column_families;
+mod checkpoint;
+mod backup;
+mod ingest;
+mod file_deletions;
+mod live_files;
+mod export;
+mod blob;
+mod blob_gc;
+mod streaming;
+mod statistics;
+mod perf_context;
+#[cfg(feature = "tracing")]
+mod tracing_spans;
+mod metrics;
+mod structure;
+mod logger;
+mod property;
+mod file_io_stats;
+mod write_stall;
+mod asynk;
+#[cfg(feature = "capi")]
+mod capi;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "resp")]
+mod resp;
+mod typed;
+#[cfg(feature = "object-store")]
+mod object_store;
+#[cfg(feature = "leveldb")]
+mod leveldb;
+mod replication;
+mod cdc;
+mod raft;
+mod sst_dump;
+mod malformed_input;
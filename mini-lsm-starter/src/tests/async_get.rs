@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use self::harness::sync;
+
+use super::*;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[tokio::test]
+async fn test_get_async() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.row_cache_capacity = 1 << 20;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for i in 0..1000 {
+        storage
+            .put(
+                format!("{:05}", i).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    sync(&storage);
+    storage.delete(b"00001").unwrap();
+    sync(&storage);
+    storage.put(b"00002", b"in-memtable").unwrap();
+
+    for i in [0, 500, 999] {
+        let key = format!("{:05}", i);
+        assert_eq!(
+            storage.get_async(key.as_bytes()).await.unwrap(),
+            Some(Bytes::from(format!("value{}", i)))
+        );
+        assert_eq!(
+            storage.get_async(key.as_bytes()).await.unwrap(),
+            storage.get(key.as_bytes()).unwrap()
+        );
+    }
+    assert_eq!(storage.get_async(b"00001").await.unwrap(), None);
+    assert_eq!(
+        storage.get_async(b"00002").await.unwrap(),
+        Some(Bytes::from_static(b"in-memtable"))
+    );
+    assert_eq!(storage.get_async(b"99999").await.unwrap(), None);
+}
@@ -0,0 +1,74 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::simple_leveled_wal_options;
+use crate::asynk::AsyncLsmStorage;
+use crate::lsm_storage::WriteBatchRecord;
+
+#[tokio::test]
+async fn test_async_storage() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        AsyncLsmStorage::open(dir.path(), simple_leveled_wal_options())
+            .await
+            .unwrap(),
+    );
+    let committed = storage.storage().inner.mvcc().latest_commit_ts();
+    let writes = (0..300)
+        .map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let key = format!("key_{:03}", i);
+                storage.put(key.as_bytes(), b"value").await
+            })
+        })
+        .collect::<Vec<_>>();
+    for write in writes {
+        write.await.unwrap().unwrap();
+    }
+    // the writes queued while the first group was written are written together
+    let commits = storage.storage().inner.mvcc().latest_commit_ts() - committed;
+    assert!(commits < 300, "{} commits", commits);
+
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Del(&b"key_000"[..]),
+            WriteBatchRecord::Put(b"key_001", b"new"),
+        ])
+        .await
+        .unwrap();
+    storage.flush().await.unwrap();
+    storage.delete(b"key_002").await.unwrap();
+    storage.sync().await.unwrap();
+
+    assert_eq!(storage.get(b"key_000").await.unwrap(), None);
+    assert_eq!(
+        storage.get(b"key_001").await.unwrap(),
+        Some(Bytes::from_static(b"new"))
+    );
+    assert_eq!(storage.get(b"key_002").await.unwrap(), None);
+    assert_eq!(
+        storage.get(b"key_299").await.unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+
+    // more entries than a chunk
+    let mut scan = storage
+        .scan(Bound::Excluded(b"key_001"), Bound::Unbounded)
+        .await
+        .unwrap();
+    let mut keys = Vec::new();
+    while let Some((key, value)) = scan.next().await.unwrap() {
+        assert_eq!(value, Bytes::from_static(b"value"));
+        keys.push(key);
+    }
+    let expected = (3..300)
+        .map(|i| Bytes::from(format!("key_{:03}", i)))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+    assert_eq!(scan.next().await.unwrap(), None);
+    storage.close().await.unwrap();
+}
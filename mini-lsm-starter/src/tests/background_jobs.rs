@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::logger::DbLogger;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::scheduler::{BackgroundScheduler, JobPriority};
+
+/// Occupy the only worker of `scheduler` until the returned sender is dropped.
+fn block_worker(scheduler: &BackgroundScheduler) -> crossbeam_channel::Sender<()> {
+    let (started_tx, started_rx) = crossbeam_channel::bounded(1);
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(0);
+    scheduler.schedule(JobPriority::Compaction, move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().ok();
+    });
+    started_rx.recv().unwrap();
+    release_tx
+}
+
+#[test]
+fn test_jobs_run_by_priority() {
+    let scheduler = BackgroundScheduler::new(1, DbLogger::default());
+    let release = block_worker(&scheduler);
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for (name, priority) in [
+        ("compaction 1", JobPriority::Compaction),
+        ("l0 compaction", JobPriority::L0Compaction),
+        ("compaction 2", JobPriority::Compaction),
+        ("flush", JobPriority::Flush),
+    ] {
+        let order = order.clone();
+        assert!(scheduler.schedule(priority, move || order.lock().push(name)));
+    }
+    assert_eq!(scheduler.num_queued_jobs(), 4);
+    assert_eq!(scheduler.num_running_jobs(), 1);
+
+    drop(release);
+    scheduler.wait_for_idle();
+    assert_eq!(
+        *order.lock(),
+        vec!["flush", "l0 compaction", "compaction 1", "compaction 2"]
+    );
+}
+
+#[test]
+fn test_jobs_bounded_by_pool_size() {
+    let scheduler = BackgroundScheduler::new(2, DbLogger::default());
+    let running = Arc::new(Mutex::new((0, 0)));
+    for _ in 0..8 {
+        let running = running.clone();
+        scheduler.schedule(JobPriority::Compaction, move || {
+            {
+                let mut running = running.lock();
+                running.0 += 1;
+                running.1 = running.1.max(running.0);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            running.lock().0 -= 1;
+        });
+    }
+    scheduler.wait_for_idle();
+    assert_eq!(running.lock().1, 2);
+}
+
+#[test]
+fn test_shutdown_waits_for_running_job_and_discards_queued() {
+    let scheduler = Arc::new(BackgroundScheduler::new(1, DbLogger::default()));
+    let release = block_worker(&scheduler);
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    {
+        let finished = finished.clone();
+        scheduler.schedule(JobPriority::Flush, move || finished.lock().push("queued"));
+    }
+
+    let handle = {
+        let scheduler = scheduler.clone();
+        std::thread::spawn(move || scheduler.shutdown())
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!handle.is_finished());
+    drop(release);
+    handle.join().unwrap();
+
+    // the queued job was discarded, and no new job is accepted
+    assert!(finished.lock().is_empty());
+    assert_eq!(scheduler.num_queued_jobs(), 0);
+    assert!(!scheduler.schedule(JobPriority::Flush, || {}));
+}
+
+#[test]
+fn test_background_flush_and_compaction_with_one_job() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 1 << 14;
+    options.max_background_jobs = 1;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..2000 {
+        storage
+            .put(format!("key_{:05}", i).as_bytes(), &[b'v'; 64])
+            .unwrap();
+    }
+
+    // the single worker flushes the memtables and compacts them out of L0
+    let mut compacted = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(50));
+        let state = storage.inner.state.read();
+        if state.imm_memtables.len() < 2
+            && state.l0_sstables.len() < 2
+            && state.levels.iter().any(|(_, ssts)| !ssts.is_empty())
+        {
+            compacted = true;
+            break;
+        }
+    }
+    assert!(compacted);
+    for i in (0..2000).step_by(97) {
+        assert_eq!(
+            storage.get(format!("key_{:05}", i).as_bytes()).unwrap(),
+            Some(bytes::Bytes::from_static(&[b'v'; 64]))
+        );
+    }
+    storage.close().unwrap();
+    assert!(storage.inner.state.read().imm_memtables.is_empty());
+}
+
+#[test]
+fn test_shutdown_timeout() {
+    let scheduler = BackgroundScheduler::new(1, DbLogger::default());
+    let release = block_worker(&scheduler);
+    assert!(scheduler.schedule(JobPriority::Flush, || {}));
+
+    // the queued job is discarded, the running one does not finish in time
+    assert!(!scheduler.shutdown_timeout(Duration::from_millis(50)));
+    assert_eq!(scheduler.num_queued_jobs(), 0);
+    assert_eq!(scheduler.num_running_jobs(), 1);
+
+    drop(release);
+    assert!(scheduler.shutdown_timeout(Duration::from_secs(10)));
+    assert_eq!(scheduler.num_running_jobs(), 0);
+}
+
+#[test]
+fn test_inline_background_jobs() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 1 << 14;
+    options.max_subcompactions = 4;
+    options.inline_background_jobs = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..2000 {
+        storage
+            .put(format!("key_{:05}", i).as_bytes(), &[b'v'; 64])
+            .unwrap();
+        // the writes freezing a memtable flush and compact before returning
+        let state = storage.inner.state.read();
+        assert!(state.imm_memtables.len() < 2);
+        assert!(state.l0_sstables.len() < 2);
+    }
+    assert!(storage
+        .inner
+        .state
+        .read()
+        .levels
+        .iter()
+        .any(|(_, ssts)| !ssts.is_empty()));
+    for i in (0..2000).step_by(97) {
+        assert_eq!(
+            storage.get(format!("key_{:05}", i).as_bytes()).unwrap(),
+            Some(bytes::Bytes::from_static(&[b'v'; 64]))
+        );
+    }
+    storage.close().unwrap();
+}
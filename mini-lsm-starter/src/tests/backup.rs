@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::backup::{restore_from_backup, BackupEngine, BackupInfo};
+use crate::fs::{FileSystem, InMemoryFileSystem};
+use crate::lsm_storage::LsmStorageInner;
+
+fn sst_paths(backup: &BackupInfo) -> HashSet<String> {
+    backup
+        .files
+        .iter()
+        .filter(|file| file.name.ends_with(".sst"))
+        .map(|file| file.path.clone())
+        .collect()
+}
+
+fn shared_files(path: &Path) -> HashSet<String> {
+    std::fs::read_dir(path.join("shared"))
+        .unwrap()
+        .map(|entry| format!("shared/{}", entry.unwrap().file_name().to_str().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_incremental_backup() {
+    let dir = tempdir().unwrap();
+    let backup_path = dir.path().join("backup");
+    let storage =
+        LsmStorageInner::open(dir.path().join("db"), simple_leveled_wal_options()).unwrap();
+    let engine = BackupEngine::open(&backup_path).unwrap();
+    for i in 0..10 {
+        storage
+            .put(format!("key_{}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    sync(&storage);
+    let first = engine.create_backup(&storage).unwrap();
+    assert_eq!(first.id, 1);
+    assert_eq!(sst_paths(&first).len(), 1);
+    assert!(first.files.iter().any(|file| file.name == "CURRENT"));
+
+    // only the new SST is copied
+    storage.put(b"key_10", b"value").unwrap();
+    sync(&storage);
+    storage.put(b"unflushed", b"value").unwrap();
+    let second = engine.create_backup(&storage).unwrap();
+    assert_eq!(second.id, 2);
+    assert_eq!(sst_paths(&second).len(), 2);
+    assert!(sst_paths(&second).is_superset(&sst_paths(&first)));
+    assert_eq!(shared_files(&backup_path), sst_paths(&second));
+    assert!(second.files.iter().any(|file| file.name.ends_with(".wal")));
+    assert_eq!(
+        engine.backups().unwrap(),
+        vec![first.clone(), second.clone()]
+    );
+
+    // the SSTs of the deleted backup are kept while the others hold them
+    assert_eq!(engine.purge_old_backups(1).unwrap(), vec![1]);
+    assert_eq!(engine.backups().unwrap(), vec![second.clone()]);
+    assert_eq!(shared_files(&backup_path), sst_paths(&second));
+    assert!(engine.backup(1).is_err());
+    assert!(engine.delete_backup(1).is_err());
+
+    storage.force_full_compaction().unwrap();
+    let third = engine.create_backup(&storage).unwrap();
+    assert!(sst_paths(&third).is_disjoint(&sst_paths(&second)));
+    engine.delete_backup(second.id).unwrap();
+    assert_eq!(shared_files(&backup_path), sst_paths(&third));
+    for file in &third.files {
+        file.checksum
+            .verify_file(&crate::fs::LocalFileSystem, &backup_path.join(&file.path))
+            .unwrap();
+    }
+    assert_eq!(engine.purge_old_backups(1).unwrap(), Vec::<u64>::new());
+}
+
+#[test]
+fn test_backup_engine_removes_leftovers() {
+    let fs = Arc::new(InMemoryFileSystem::new());
+    let mut options = simple_leveled_wal_options();
+    options.file_system = Some(fs.clone());
+    let storage = LsmStorageInner::open("/db", options).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    sync(&storage);
+    let engine = BackupEngine::open_with_file_system("/backup", fs.clone()).unwrap();
+    let backup = engine.create_backup(&storage).unwrap();
+    drop(engine);
+
+    // left by a backup that did not finish
+    for path in [
+        "/backup/staging/CURRENT",
+        "/backup/shared/00009_0_0.sst",
+        "/backup/meta/2.tmp",
+    ] {
+        let mut file = fs.create(path.as_ref()).unwrap();
+        file.write_all(b"partial").unwrap();
+    }
+    let engine = BackupEngine::open_with_file_system("/backup", fs.clone()).unwrap();
+    for path in [
+        "/backup/staging/CURRENT",
+        "/backup/shared/00009_0_0.sst",
+        "/backup/meta/2.tmp",
+    ] {
+        assert!(!fs.exists(path.as_ref()), "{} is not removed", path);
+    }
+    assert_eq!(engine.backups().unwrap(), vec![backup.clone()]);
+    for file in &backup.files {
+        assert!(fs.exists(&Path::new("/backup").join(&file.path)));
+    }
+    assert_eq!(engine.create_backup(&storage).unwrap().id, 2);
+}
+
+#[test]
+fn test_restore_from_backup() {
+    let dir = tempdir().unwrap();
+    let backup_path = dir.path().join("backup");
+    let storage =
+        LsmStorageInner::open(dir.path().join("db"), simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    let engine = BackupEngine::open(&backup_path).unwrap();
+    storage.put(b"key", b"first").unwrap();
+    sync(&storage);
+    let first = engine.create_backup(&storage).unwrap();
+    storage.put(b"key", b"second").unwrap();
+    sync(&storage);
+    storage.put(b"unflushed", b"value").unwrap();
+    storage.put_cf(&cf, b"cf_key", b"cf_value").unwrap();
+    let second = engine.create_backup(&storage).unwrap();
+    storage.put(b"key", b"after").unwrap();
+    drop(storage);
+    drop(engine);
+
+    let target = dir.path().join("restored");
+    restore_from_backup(&backup_path, &target, second.id).unwrap();
+    assert!(restore_from_backup(&backup_path, &target, second.id).is_err());
+    let restored = LsmStorageInner::open(&target, simple_leveled_wal_options()).unwrap();
+    assert_eq!(restored.get(b"key").unwrap(), Some(Bytes::from("second")));
+    assert_eq!(
+        restored.get(b"unflushed").unwrap(),
+        Some(Bytes::from("value"))
+    );
+    let cf = restored.cf("cf").unwrap();
+    assert_eq!(
+        restored.get_cf(&cf, b"cf_key").unwrap(),
+        Some(Bytes::from("cf_value"))
+    );
+    drop(restored);
+
+    // a corrupted file fails the restore, which leaves nothing behind
+    let corrupted = second
+        .files
+        .iter()
+        .find(|file| file.name.ends_with(".sst") && !first.files.contains(file))
+        .unwrap();
+    let corrupted_path = backup_path.join(&corrupted.path);
+    let mut data = std::fs::read(&corrupted_path).unwrap();
+    data[0] ^= 1;
+    std::fs::write(&corrupted_path, data).unwrap();
+    let target = dir.path().join("partial");
+    let error = restore_from_backup(&backup_path, &target, second.id).unwrap_err();
+    assert!(format!("{:#}", error).contains("checksum"), "{:#}", error);
+    assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+    assert!(restore_from_backup(&backup_path, &target, 3).is_err());
+    restore_from_backup(&backup_path, &target, first.id).unwrap();
+    let restored = LsmStorageInner::open(&target, simple_leveled_wal_options()).unwrap();
+    assert_eq!(restored.get(b"key").unwrap(), Some(Bytes::from("first")));
+    assert_eq!(restored.get(b"unflushed").unwrap(), None);
+}
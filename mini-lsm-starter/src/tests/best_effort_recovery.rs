@@ -0,0 +1,108 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, sync};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, RecoveryReport};
+
+fn options(best_effort_recovery: bool) -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.best_effort_recovery = best_effort_recovery;
+    options
+}
+
+/// Flush `key` written with `value`, and return the id of the SST.
+fn flush(storage: &LsmStorageInner, key: &str, value: &str) -> usize {
+    storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+    sync(storage);
+    storage.state.read().l0_sstables[0]
+}
+
+fn get(storage: &LsmStorageInner, key: &str) -> Option<Bytes> {
+    storage.get(key.as_bytes()).unwrap()
+}
+
+#[test]
+fn test_best_effort_recovery_drops_latest_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, options(false)).unwrap();
+    flush(&storage, "a", "1");
+    let second = flush(&storage, "b", "2");
+    let third = flush(&storage, "c", "3");
+    drop(storage);
+    // the second SST is lost and the third has garbage in place of its footer
+    std::fs::remove_file(LsmStorageInner::path_of_sst_static(&dir, second)).unwrap();
+    std::fs::write(LsmStorageInner::path_of_sst_static(&dir, third), b"garbage").unwrap();
+
+    assert!(LsmStorageInner::open(&dir, options(false)).is_err());
+    let storage = LsmStorageInner::open(&dir, options(true)).unwrap();
+    let report = storage.recovery_report();
+    assert_eq!(report.unreadable_sst_ids, vec![second, third]);
+    assert_eq!(report.dropped_sst_ids, vec![second, third]);
+    // the records from the second flush on, except the checksums written before it
+    assert_eq!(report.num_dropped_records, 4);
+    assert_eq!(get(&storage, "a"), Some(Bytes::from("1")));
+    assert_eq!(get(&storage, "b"), None);
+    assert!(dir
+        .path()
+        .join("lost")
+        .join(format!("{:05}.sst", third))
+        .exists());
+
+    // the database is writable, and opens normally once recovered
+    storage.put(b"d", b"4").unwrap();
+    sync(&storage);
+    drop(storage);
+    let storage = LsmStorageInner::open(&dir, options(false)).unwrap();
+    assert_eq!(storage.recovery_report(), &RecoveryReport::default());
+    assert_eq!(get(&storage, "a"), Some(Bytes::from("1")));
+    assert_eq!(get(&storage, "d"), Some(Bytes::from("4")));
+}
+
+#[test]
+fn test_best_effort_recovery_keeps_consistent_prefix() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, options(false)).unwrap();
+    // overlapping, so that they are merged rather than moved
+    flush(&storage, "a", "1");
+    flush(&storage, "a", "2");
+    storage.trigger_compaction().unwrap();
+    let compacted = storage.state.read().levels.last().unwrap().1.clone();
+    assert_eq!(compacted.len(), 1);
+    let latest = flush(&storage, "c", "3");
+    drop(storage);
+    // the compaction output is lost, and its inputs were deleted
+    std::fs::remove_file(LsmStorageInner::path_of_sst_static(&dir, compacted[0])).unwrap();
+
+    let storage = LsmStorageInner::open(&dir, options(true)).unwrap();
+    // the memtable of the latest flush was created before the compaction output
+    assert_eq!(
+        storage.recovery_report().dropped_sst_ids,
+        vec![latest, compacted[0]]
+    );
+    assert_eq!(
+        storage.recovery_report().unreadable_sst_ids,
+        vec![compacted[0]]
+    );
+    // the latest flush is readable but not consistent with any earlier state, only the empty one is left
+    assert!(storage.state.read().sstables.is_empty());
+    assert_eq!(get(&storage, "c"), None);
+    assert!(dir
+        .path()
+        .join("lost")
+        .join(format!("{:05}.sst", latest))
+        .exists());
+}
+
+#[test]
+fn test_best_effort_recovery_without_damage() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, options(true)).unwrap();
+    flush(&storage, "a", "1");
+    flush(&storage, "b", "2");
+    let state = storage.state.read().clone();
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options(true)).unwrap();
+    assert_eq!(storage.recovery_report(), &RecoveryReport::default());
+    assert_eq!(storage.state.read().l0_sstables, state.l0_sstables);
+}
@@ -0,0 +1,120 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_lsm_iter_result_by_key, simple_leveled_wal_options, sync};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options() -> LsmStorageOptions {
+    let mut options = simple_leveled_wal_options();
+    options.min_blob_size = 100;
+    options
+}
+
+fn blob_files(path: &Path) -> Vec<String> {
+    let mut names = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".blob"))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn large_value(i: usize, len: usize) -> Bytes {
+    Bytes::from(format!("{:05}", i).repeat(len / 5))
+}
+
+#[tokio::test]
+async fn test_blob_files() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put(b"a", &large_value(1, 200)).unwrap();
+    // longer than the SST blocks could hold
+    storage.put(b"b", &large_value(2, 100_000)).unwrap();
+    storage.put(b"c", b"small").unwrap();
+    storage.put_cf(&cf, b"a", &large_value(3, 200)).unwrap();
+    sync(&storage);
+    assert_eq!(blob_files(dir.path()).len(), 2);
+    let snapshot = storage.snapshot();
+    storage.put(b"a", &large_value(4, 200)).unwrap();
+    storage.delete(b"c").unwrap();
+    sync(&storage);
+    assert_eq!(blob_files(dir.path()).len(), 3);
+
+    let check = |storage: &LsmStorageInner| {
+        assert_eq!(storage.get(b"a").unwrap(), Some(large_value(4, 200)));
+        assert_eq!(storage.get(b"b").unwrap(), Some(large_value(2, 100_000)));
+        assert_eq!(storage.get(b"c").unwrap(), None);
+        let cf = storage.cf("cf").unwrap();
+        assert_eq!(
+            storage.get_cf(&cf, b"a").unwrap(),
+            Some(large_value(3, 200))
+        );
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("a"), large_value(4, 200)),
+                (Bytes::from("b"), large_value(2, 100_000)),
+            ],
+        );
+    };
+    check(&storage);
+    assert_eq!(
+        storage.get_with_snapshot(b"a", &snapshot).unwrap(),
+        Some(large_value(1, 200))
+    );
+    assert_eq!(
+        storage.get_with_snapshot(b"c", &snapshot).unwrap(),
+        Some(Bytes::from("small"))
+    );
+    assert_eq!(
+        storage.get_async(b"b").await.unwrap(),
+        Some(large_value(2, 100_000))
+    );
+
+    // compactions keep pointing into the blob files
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    assert_eq!(
+        storage.get_with_snapshot(b"a", &snapshot).unwrap(),
+        Some(large_value(1, 200))
+    );
+    let live_files = storage.live_files().unwrap();
+    assert_eq!(live_files.blob_files.len(), 3);
+    let checkpoint_path = dir.path().join("checkpoint");
+    storage.checkpoint(&checkpoint_path).unwrap();
+    drop(snapshot);
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    check(&storage);
+    let checkpoint = LsmStorageInner::open(&checkpoint_path, options()).unwrap();
+    check(&checkpoint);
+    drop(checkpoint);
+
+    // only the blob files the SSTs point into are kept
+    storage.put(b"a", b"small").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    storage.put(b"d", &large_value(5, 200)).unwrap();
+    sync(&storage);
+    assert!(storage.delete_obsolete_files().unwrap().is_empty());
+    let live_blob_files = storage
+        .live_files()
+        .unwrap()
+        .blob_files
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(blob_files(dir.path()), live_blob_files);
+    assert_eq!(live_blob_files.len(), 3);
+    assert_eq!(storage.get(b"d").unwrap(), Some(large_value(5, 200)));
+    let cf = storage.cf("cf").unwrap();
+    let error = storage.export_cf(&cf, dir.path().join("export")).unwrap_err();
+    assert!(error.to_string().contains("blob files"), "{:#}", error);
+}
@@ -0,0 +1,102 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, simple_leveled_wal_options, sync};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn large_value(i: usize) -> Bytes {
+    Bytes::from(format!("{:05}", i).repeat(40))
+}
+
+/// Write 10 large values to a blob file, then overwrite 7 of them with small values.
+fn write_garbage(storage: &LsmStorageInner) {
+    for i in 0..10 {
+        storage
+            .put(format!("key_{:02}", i).as_bytes(), &large_value(i))
+            .unwrap();
+    }
+    sync(storage);
+    for i in 0..7 {
+        storage
+            .put(format!("key_{:02}", i).as_bytes(), b"small")
+            .unwrap();
+    }
+    sync(storage);
+}
+
+fn check_values(storage: &LsmStorageInner) {
+    for i in 0..10 {
+        let expected = if i < 7 {
+            Bytes::from("small")
+        } else {
+            large_value(i)
+        };
+        assert_eq!(
+            storage.get(format!("key_{:02}", i).as_bytes()).unwrap(),
+            Some(expected)
+        );
+    }
+}
+
+fn simple_leveled_options() -> LsmStorageOptions {
+    let mut options = simple_leveled_wal_options();
+    options.min_blob_size = 100;
+    options.max_blob_space_amplification_percent = 200;
+    options
+}
+
+#[test]
+fn test_compaction_relocates_wasteful_blob_files() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_options()).unwrap();
+    write_garbage(&storage);
+    storage.force_full_compaction().unwrap();
+    let usage = storage.state.read().blob_file_usage();
+    assert_eq!(usage.len(), 1);
+    let (&blob_file_id, usage) = usage.iter().next().unwrap();
+    assert_eq!(usage.live_bytes * 10, usage.size * 3);
+    assert_eq!(usage.space_amplification_percent(), 333);
+
+    // the next compaction moves the values out of it
+    storage.force_full_compaction().unwrap();
+    let usage = storage.state.read().blob_file_usage();
+    assert_eq!(usage.len(), 1);
+    let (&new_blob_file_id, usage) = usage.iter().next().unwrap();
+    assert_ne!(new_blob_file_id, blob_file_id);
+    assert_eq!(usage.size, usage.live_bytes);
+    assert!(!storage.path_of_blob(blob_file_id).exists());
+    check_values(&storage);
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_options()).unwrap();
+    check_values(&storage);
+}
+
+#[test]
+fn test_blob_garbage_collection_task() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    options.min_blob_size = 100;
+    options.max_blob_space_amplification_percent = 200;
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    write_garbage(&storage);
+    // L0 to the base level, dropping the overwritten values but not moving the others yet
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    let usage = storage.state.read().blob_file_usage();
+    let (&blob_file_id, usage) = usage.iter().next().unwrap();
+    assert!(usage.space_amplification_percent() > 200);
+
+    // no level needs compacting, the SSTs pointing into the blob file are rewritten
+    storage.trigger_compaction().unwrap();
+    let usage = storage.state.read().blob_file_usage();
+    assert!(!usage.contains_key(&blob_file_id));
+    assert!(!storage.path_of_blob(blob_file_id).exists());
+    check_values(&storage);
+
+    // nothing left to collect
+    let state = storage.state.read().clone();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+}
@@ -0,0 +1,338 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use self::harness::sync;
+
+use super::*;
+use crate::block::{BlockBuilder, BlockCache, CachePriority, SecondaryCache};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::MetadataCaching;
+
+fn test_block() -> Arc<crate::block::Block> {
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(KeySlice::for_testing_from_slice_no_ts(b"key"), b"value"));
+    Arc::new(builder.build())
+}
+
+/// Bytes charged to the cache for a block.
+fn charge(block: &crate::block::Block) -> u64 {
+    (block.data.len() + block.offsets.len() * 2) as u64
+}
+
+#[test]
+fn test_sharded_block_cache() {
+    let block = test_block();
+    let cache = BlockCache::with_num_shards(1000 * charge(&block), 8);
+    for sst_id in 0..10 {
+        for block_idx in 0..50 {
+            cache.insert((sst_id, block_idx), block.clone());
+        }
+    }
+    assert!(cache.get(&(3, 7)).is_some());
+    assert!(cache.get(&(10, 0)).is_none());
+    let loaded = cache
+        .try_get_with((10, 0), || Ok::<_, anyhow::Error>(block.clone()))
+        .unwrap();
+    assert!(Arc::ptr_eq(&loaded, &block));
+
+    let stats = cache.stats();
+    assert_eq!(stats.num_shards, 8);
+    assert_eq!(stats.capacity, 1000 * charge(&block));
+    assert_eq!(stats.entry_count, 501);
+    assert_eq!(stats.usage, 501 * charge(&block));
+    assert_eq!(stats.shard_entry_counts.len(), 8);
+    // every shard receives a share of the blocks
+    assert!(stats.shard_entry_counts.iter().all(|&count| count > 20));
+
+    cache.invalidate(&(3, 7));
+    assert!(cache.get(&(3, 7)).is_none());
+    cache.invalidate_all();
+    assert_eq!(cache.stats().entry_count, 0);
+}
+
+#[test]
+fn test_sharded_block_cache_capacity() {
+    let block = test_block();
+    let cache = BlockCache::with_num_shards(64 * charge(&block), 4);
+    for block_idx in 0..1000 {
+        cache.insert((0, block_idx), block.clone());
+    }
+    let stats = cache.stats();
+    assert!(stats.entry_count <= 64);
+    assert!(stats.usage <= stats.capacity);
+    assert!(stats.shard_entry_counts.iter().all(|&count| count <= 16));
+    // the most recently inserted blocks are kept
+    assert!(cache.get(&(0, 999)).is_some());
+}
+
+#[test]
+fn test_block_cache_counters() {
+    let block = test_block();
+    let cache = BlockCache::with_num_shards(4 * charge(&block), 1);
+    assert!(cache.get(&(0, 0)).is_none());
+    let load = || Ok::<_, anyhow::Error>(block.clone());
+    cache.try_get_with((0, 0), load).unwrap();
+    cache.try_get_with((0, 0), load).unwrap();
+    for block_idx in 1..20 {
+        cache.insert((0, block_idx), block.clone());
+    }
+    let counts = cache.stats().counts;
+    assert_eq!(counts.hits, 1);
+    assert_eq!(counts.misses, 2);
+    assert_eq!(counts.inserts, 20);
+    assert_eq!(counts.evictions, 16);
+    assert!((counts.hit_ratio() - 1.0 / 3.0).abs() < 1e-9);
+
+    // explicit invalidations are not evictions
+    cache.invalidate_all();
+    assert_eq!(cache.stats().counts.evictions, counts.evictions);
+}
+
+#[test]
+fn test_block_cache_table_stats() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    for i in 0..100 {
+        storage
+            .put(format!("{:05}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    sync(&storage);
+    let scan = || {
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        while iter.is_valid() {
+            iter.next().unwrap();
+        }
+    };
+    scan();
+    let table_stats = storage.block_cache_table_stats();
+    assert_eq!(table_stats.len(), 1);
+    let first = table_stats[0].1;
+    assert!(first.misses > 0);
+    assert_eq!(first.inserts, first.misses);
+
+    scan();
+    let (_, second) = storage.block_cache_table_stats()[0];
+    assert_eq!(second.misses, first.misses);
+    assert!(second.hits > first.hits);
+    let global = storage.block_cache.stats().counts;
+    assert_eq!(global.hits, second.hits);
+    assert_eq!(global.misses, second.misses);
+}
+
+#[test]
+fn test_pinned_charges() {
+    let block = test_block();
+    let cache = Arc::new(BlockCache::with_num_shards(8 * charge(&block), 1));
+    for block_idx in 0..8 {
+        cache.insert((0, block_idx), block.clone());
+    }
+    let reservation = cache.reserve(1, 4 * charge(&block));
+    let stats = cache.stats();
+    assert_eq!(stats.pinned_usage, 4 * charge(&block));
+    assert_eq!(stats.usage, stats.capacity);
+    // the least recently used blocks made room for the pinned charge
+    assert_eq!(stats.entry_count, 4);
+    assert!(cache.get(&(0, 0)).is_none());
+    assert!(cache.get(&(0, 7)).is_some());
+
+    cache.invalidate_all();
+    assert_eq!(cache.stats().usage, 4 * charge(&block));
+    drop(reservation);
+    let stats = cache.stats();
+    assert_eq!(stats.usage, 0);
+    assert_eq!(stats.pinned_usage, 0);
+}
+
+#[test]
+fn test_metadata_caching() {
+    for caching in [MetadataCaching::Cached, MetadataCaching::Pinned] {
+        let dir = tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.metadata_caching = caching;
+        let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+        for i in 0..100 {
+            storage
+                .put(format!("{:05}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        sync(&storage);
+        let stats = storage.block_cache.stats();
+        assert!(stats.pinned_usage > 0);
+        match caching {
+            // only the filter is cached, and it is never evicted by data blocks
+            MetadataCaching::Cached => assert_eq!(stats.entry_count, 1),
+            _ => assert_eq!(stats.entry_count, 0),
+        }
+
+        for _ in 0..2 {
+            assert_eq!(&storage.get(b"00042").unwrap().unwrap()[..], b"value");
+            assert!(storage.get(b"00042x").unwrap().is_none());
+            // a filter evicted from the cache is read back from the file
+            storage.block_cache.invalidate_all();
+        }
+        assert_eq!(storage.block_cache.stats().pinned_usage, stats.pinned_usage);
+
+        // the charge is released along with the table
+        storage.delete(b"00042").unwrap();
+        storage.force_full_compaction().unwrap();
+        let pinned_usage = storage.block_cache.stats().pinned_usage;
+        assert!(pinned_usage > 0 && pinned_usage <= stats.pinned_usage);
+        let block_cache = storage.block_cache.clone();
+        drop(storage);
+        assert_eq!(block_cache.stats().pinned_usage, 0);
+    }
+}
+
+#[test]
+fn test_secondary_cache() {
+    let dir = tempdir().unwrap();
+    let block = test_block();
+    let secondary = SecondaryCache::create(dir.path().join("cache"), 1 << 20).unwrap();
+    let cache = BlockCache::with_num_shards(2 * charge(&block), 1).with_secondary_cache(secondary);
+    for block_idx in 0..10 {
+        cache.insert((0, block_idx), block.clone());
+    }
+    let secondary = cache.stats().secondary.unwrap();
+    assert_eq!(secondary.inserts, 8);
+    assert_eq!(secondary.entry_count, 8);
+
+    // evicted blocks are served from the secondary cache and moved back into memory
+    assert!(cache.get(&(0, 0)).is_none());
+    let loaded = cache
+        .try_get_with((0, 0), || -> anyhow::Result<_> {
+            panic!("read from the SST")
+        })
+        .unwrap();
+    assert_eq!(loaded.data, block.data);
+    assert_eq!(loaded.offsets, block.offsets);
+    assert!(cache.get(&(0, 0)).is_some());
+    assert_eq!(cache.stats().secondary.unwrap().hits, 1);
+
+    cache.invalidate_all();
+    assert_eq!(cache.stats().secondary.unwrap().entry_count, 0);
+    let loaded = cache
+        .try_get_with((0, 1), || Ok::<_, anyhow::Error>(block.clone()))
+        .unwrap();
+    assert!(Arc::ptr_eq(&loaded, &block));
+}
+
+#[test]
+fn test_secondary_cache_ring() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("cache");
+    let block = test_block();
+    let secondary = SecondaryCache::create(&path, 1 << 10).unwrap();
+    secondary.insert((0, 0), &block).unwrap();
+    let record_len = secondary.stats().usage;
+    for block_idx in 1..100 {
+        secondary.insert((0, block_idx), &block).unwrap();
+    }
+    // the oldest blocks were overwritten
+    let stats = secondary.stats();
+    assert!(stats.usage <= stats.capacity);
+    assert_eq!(stats.entry_count, stats.usage / record_len);
+    assert!(secondary.get(&(0, 0)).is_none());
+    assert!(secondary.get(&(0, 99)).is_some());
+
+    // a corrupted record is a miss
+    std::fs::write(&path, vec![0xff; 1 << 10]).unwrap();
+    assert!(secondary.get(&(0, 99)).is_none());
+
+    // nothing survives re-creating the cache
+    let secondary = SecondaryCache::create(&path, 1 << 10).unwrap();
+    assert_eq!(secondary.stats().entry_count, 0);
+    assert!(secondary.get(&(0, 99)).is_none());
+}
+
+#[test]
+fn test_warm_block_cache() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.metadata_caching = MetadataCaching::Cached;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for round in 0..2 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("{:05}", i).as_bytes(),
+                    format!("value{round}").as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&storage);
+    }
+    let num_blocks: usize = {
+        let state = storage.state.read();
+        state
+            .l0_sstables
+            .iter()
+            .map(|id| state.sstables[id].num_of_blocks())
+            .sum()
+    };
+    storage.block_cache.invalidate_all();
+    assert_eq!(storage.block_cache.stats().entry_count, 0);
+
+    storage.warm_block_cache().unwrap();
+    let warmed = storage.block_cache.stats();
+    assert_eq!(warmed.entry_count as usize, num_blocks + 2);
+
+    assert_eq!(&storage.get(b"00042").unwrap().unwrap()[..], b"value1");
+    let counts = storage.block_cache.stats().counts;
+    assert_eq!(counts.misses, warmed.counts.misses);
+    assert!(counts.hits > warmed.counts.hits);
+}
+
+#[test]
+fn test_cache_priority() {
+    let block = test_block();
+    let cache = BlockCache::with_num_shards(4 * charge(&block), 1);
+    for block_idx in 0..2 {
+        cache.insert_for_table((0, block_idx), block.clone(), None, CachePriority::Low);
+    }
+    for block_idx in 0..4 {
+        cache.insert_for_table((1, block_idx), block.clone(), None, CachePriority::Bottom);
+    }
+    // bottom priority blocks are evicted first, even when used more recently
+    assert!(cache.get(&(0, 0)).is_some());
+    assert!(cache.get(&(0, 1)).is_some());
+    assert!(cache.get(&(1, 0)).is_none());
+    assert!(cache.get(&(1, 3)).is_some());
+    for block_idx in 2..5 {
+        cache.insert_for_table((0, block_idx), block.clone(), None, CachePriority::Low);
+    }
+    assert!(cache.get(&(1, 3)).is_none());
+    assert!(cache.get(&(0, 0)).is_none());
+    assert!(cache.get(&(0, 4)).is_some());
+
+    // the bottommost level is cached at bottom priority, the levels above it are not
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    storage.put(b"key", b"value").unwrap();
+    sync(&storage);
+    let l0_table = storage
+        .state
+        .read()
+        .sstables
+        .values()
+        .next()
+        .unwrap()
+        .clone();
+    assert_eq!(l0_table.cache_priority(), CachePriority::Low);
+    storage.force_full_compaction().unwrap();
+    let l1_table = storage
+        .state
+        .read()
+        .sstables
+        .values()
+        .next()
+        .unwrap()
+        .clone();
+    assert_eq!(l1_table.cache_priority(), CachePriority::Bottom);
+}
@@ -0,0 +1,121 @@
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+/// Number of entries and garbage bytes of all SSTs.
+fn count_garbage(storage: &LsmStorageInner) -> (u64, u64) {
+    let state = storage.state.read();
+    state
+        .sstables
+        .values()
+        .fold((0, 0), |(entries, garbage), sst| {
+            (
+                entries + sst.properties().num_entries,
+                garbage + sst.properties().garbage_bytes,
+            )
+        })
+}
+
+fn recompaction_options(bottommost_recompaction_bytes: u64) -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.bottommost_recompaction_bytes = bottommost_recompaction_bytes;
+    options
+}
+
+#[test]
+fn test_garbage_recorded_in_table_properties() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, recompaction_options(0)).unwrap();
+    put_range(&storage, 0, 10, "v1");
+    put_range(&storage, 0, 10, "v2");
+    storage.delete(b"key_00000").unwrap();
+    let latest_ts = storage.mvcc().latest_commit_ts();
+    sync(&storage);
+
+    let state = storage.state.read();
+    let properties = state.sstables[&state.l0_sstables[0]].properties();
+    // 10 versions shadowed by v2, 1 shadowed by the tombstone, and the tombstone itself
+    let key_len = "key_00000".len() + 8;
+    assert_eq!(
+        properties.garbage_bytes,
+        (11 * (key_len + 2) + key_len) as u64
+    );
+    assert_eq!(properties.garbage_ts, latest_ts);
+    assert_eq!(properties.reclaimable_bytes(latest_ts - 1), 0);
+}
+
+#[test]
+fn test_leveled_bottommost_recompaction_waits_for_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, recompaction_options(1)).unwrap();
+    put_range(&storage, 0, 100, "v1");
+    sync(&storage);
+    // a snapshot reading v1 keeps it alive through the compaction to the bottom level
+    let snapshot_ts = storage.mvcc().latest_commit_ts();
+    storage.mvcc().ts.lock().1.add_reader(snapshot_ts);
+    put_range(&storage, 0, 100, "v2");
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    assert_eq!(count_garbage(&storage).0, 200);
+
+    // nothing is reclaimable while the snapshot is alive
+    let state = storage.state.read().clone();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+
+    storage.mvcc().ts.lock().1.remove_reader(snapshot_ts);
+    storage.trigger_compaction().unwrap();
+    assert_eq!(count_garbage(&storage), (100, 0));
+    assert_eq!(
+        storage.get(b"key_00000").unwrap(),
+        Some(bytes::Bytes::from_static(b"v2"))
+    );
+
+    // the rewritten level holds no garbage and is left alone
+    let state = storage.state.read().clone();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+}
+
+#[test]
+fn test_universal_bottommost_recompaction() {
+    let dir = tempdir().unwrap();
+    let universal_options = |bottommost_recompaction_bytes| {
+        let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+            TieredCompactionOptions {
+                num_tiers: 10,
+                max_size_amplification_percent: 200,
+                size_ratio: 1,
+                min_merge_width: 2,
+            },
+        ));
+        options.bottommost_recompaction_bytes = bottommost_recompaction_bytes;
+        options
+    };
+    let storage = LsmStorageInner::open(&dir, universal_options(0)).unwrap();
+    put_range(&storage, 0, 100, "v1");
+    for i in 0..50 {
+        storage.delete(format!("key_{:05}", i).as_bytes()).unwrap();
+    }
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    let (entries, garbage_bytes) = count_garbage(&storage);
+    assert_eq!(entries, 150);
+    drop(storage);
+
+    // below the threshold the tier is left alone
+    let storage = LsmStorageInner::open(&dir, universal_options(garbage_bytes + 1)).unwrap();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(count_garbage(&storage), (150, garbage_bytes));
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, universal_options(garbage_bytes)).unwrap();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels.len(), 1);
+    assert_eq!(count_garbage(&storage), (50, 0));
+    assert_eq!(storage.get(b"key_00000").unwrap(), None);
+    assert!(storage.get(b"key_00050").unwrap().is_some());
+}
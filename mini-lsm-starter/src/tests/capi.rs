@@ -0,0 +1,171 @@
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use tempfile::tempdir;
+
+use crate::capi::*;
+
+fn last_error() -> String {
+    unsafe { CStr::from_ptr(mini_lsm_last_error()) }
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+unsafe fn get(db: *mut MiniLsmDb, key: &[u8]) -> Option<Vec<u8>> {
+    let (mut value, mut len) = (ptr::null_mut(), 0);
+    match mini_lsm_get(db, key.as_ptr(), key.len(), &mut value, &mut len) {
+        MINI_LSM_OK => {
+            let found = std::slice::from_raw_parts(value, len).to_vec();
+            mini_lsm_free(value, len);
+            Some(found)
+        }
+        MINI_LSM_NOT_FOUND => None,
+        code => panic!("get failed with {}: {}", code, last_error()),
+    }
+}
+
+#[test]
+fn test_capi() {
+    let dir = tempdir().unwrap();
+    let path = CString::new(dir.path().to_str().unwrap()).unwrap();
+    let name = |name: &str| CString::new(name).unwrap();
+    unsafe {
+        let options = mini_lsm_options_create();
+        assert_eq!(
+            mini_lsm_options_set(options, name("enable_wal").as_ptr(), name("true").as_ptr()),
+            MINI_LSM_OK
+        );
+        assert_eq!(
+            mini_lsm_options_set(options, name("no_such_option").as_ptr(), name("1").as_ptr()),
+            MINI_LSM_INVALID_ARGUMENT
+        );
+        assert!(last_error().contains("no_such_option"));
+        assert_eq!(
+            mini_lsm_options_set(options, name("block_size").as_ptr(), name("big").as_ptr()),
+            MINI_LSM_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            mini_lsm_options_set(options, name("block_size").as_ptr(), name("0").as_ptr()),
+            MINI_LSM_OK
+        );
+        let mut db = ptr::null_mut();
+        assert_eq!(
+            mini_lsm_open(path.as_ptr(), options, &mut db),
+            MINI_LSM_INVALID_ARGUMENT
+        );
+        assert!(db.is_null());
+        mini_lsm_options_set(options, name("block_size").as_ptr(), name("4096").as_ptr());
+        assert_eq!(mini_lsm_open(path.as_ptr(), options, &mut db), MINI_LSM_OK);
+        mini_lsm_options_destroy(options);
+
+        assert_eq!(
+            mini_lsm_put(db, b"a".as_ptr(), 1, b"1".as_ptr(), 1),
+            MINI_LSM_OK
+        );
+        assert_eq!(get(db, b"a"), Some(b"1".to_vec()));
+        assert_eq!(get(db, b"b"), None);
+        assert_eq!(
+            mini_lsm_put(ptr::null_mut(), b"a".as_ptr(), 1, b"1".as_ptr(), 1),
+            MINI_LSM_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            mini_lsm_put(db, ptr::null(), 1, b"1".as_ptr(), 1),
+            MINI_LSM_INVALID_ARGUMENT
+        );
+
+        let batch = mini_lsm_writebatch_create();
+        for key in [b"b", b"c", b"d"] {
+            mini_lsm_writebatch_put(batch, key.as_ptr(), 1, b"2".as_ptr(), 1);
+        }
+        mini_lsm_writebatch_delete(batch, b"a".as_ptr(), 1);
+        assert_eq!(mini_lsm_writebatch_count(batch), 4);
+        assert_eq!(mini_lsm_write(db, batch), MINI_LSM_OK);
+        mini_lsm_writebatch_clear(batch);
+        assert_eq!(mini_lsm_writebatch_count(batch), 0);
+        mini_lsm_writebatch_destroy(batch);
+        assert_eq!(get(db, b"a"), None);
+        assert_eq!(mini_lsm_flush(db), MINI_LSM_OK);
+        assert_eq!(mini_lsm_delete(db, b"d".as_ptr(), 1), MINI_LSM_OK);
+        assert_eq!(mini_lsm_sync(db), MINI_LSM_OK);
+
+        let mut iter = ptr::null_mut();
+        assert_eq!(
+            mini_lsm_iter_create(db, b"a".as_ptr(), 1, true, ptr::null(), 0, false, &mut iter),
+            MINI_LSM_OK
+        );
+        let mut entries = Vec::new();
+        while mini_lsm_iter_valid(iter) {
+            let (mut key_len, mut value_len) = (0, 0);
+            let key = mini_lsm_iter_key(iter, &mut key_len);
+            let value = mini_lsm_iter_value(iter, &mut value_len);
+            entries.push((
+                std::slice::from_raw_parts(key, key_len).to_vec(),
+                std::slice::from_raw_parts(value, value_len).to_vec(),
+            ));
+            assert_eq!(mini_lsm_iter_next(iter), MINI_LSM_OK);
+        }
+        assert!(mini_lsm_iter_key(iter, ptr::null_mut()).is_null());
+        assert_eq!(mini_lsm_iter_next(iter), MINI_LSM_INVALID_ARGUMENT);
+        mini_lsm_iter_destroy(iter);
+        assert_eq!(
+            entries,
+            vec![
+                (b"b".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"2".to_vec())
+            ]
+        );
+
+        assert_eq!(
+            mini_lsm_set_option(db, name("num_memtable_limit").as_ptr(), name("3").as_ptr()),
+            MINI_LSM_OK
+        );
+        assert_eq!(
+            mini_lsm_set_option(db, name("block_size").as_ptr(), name("1").as_ptr()),
+            MINI_LSM_INVALID_ARGUMENT
+        );
+        assert_eq!(mini_lsm_close(db), MINI_LSM_OK);
+
+        let options = mini_lsm_options_create();
+        mini_lsm_options_set(options, name("enable_wal").as_ptr(), name("true").as_ptr());
+        assert_eq!(mini_lsm_open(path.as_ptr(), options, &mut db), MINI_LSM_OK);
+        mini_lsm_options_destroy(options);
+        assert_eq!(get(db, b"c"), Some(b"2".to_vec()));
+        assert_eq!(get(db, b"d"), None);
+        assert_eq!(mini_lsm_close(db), MINI_LSM_OK);
+    }
+}
+
+#[test]
+fn test_capi_db_shared_by_threads() {
+    let dir = tempdir().unwrap();
+    let path = CString::new(dir.path().to_str().unwrap()).unwrap();
+    let mut db = ptr::null_mut();
+    unsafe {
+        let options = mini_lsm_options_create();
+        assert_eq!(mini_lsm_open(path.as_ptr(), options, &mut db), MINI_LSM_OK);
+        mini_lsm_options_destroy(options);
+    }
+    // a raw pointer is not `Send`, the handle crosses threads as an address
+    let handle = db as usize;
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            scope.spawn(move || unsafe {
+                let db = handle as *mut MiniLsmDb;
+                for i in 0..100 {
+                    let key = format!("key_{}_{:03}", thread, i);
+                    assert_eq!(
+                        mini_lsm_put(db, key.as_ptr(), key.len(), b"1".as_ptr(), 1),
+                        MINI_LSM_OK
+                    );
+                    assert_eq!(get(db, key.as_bytes()), Some(b"1".to_vec()));
+                }
+                assert_eq!(mini_lsm_flush(db), MINI_LSM_OK);
+            });
+        }
+    });
+    unsafe {
+        assert_eq!(get(db, b"key_3_099"), Some(b"1".to_vec()));
+        assert_eq!(mini_lsm_close(db), MINI_LSM_OK);
+    }
+}
@@ -0,0 +1,103 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::cdc::Change;
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+
+fn change(key: &str, value: Option<&str>, commit_ts: u64) -> Change {
+    Change {
+        key: Bytes::copy_from_slice(key.as_bytes()),
+        value: value.map(|value| Bytes::copy_from_slice(value.as_bytes())),
+        commit_ts,
+    }
+}
+
+#[test]
+fn test_subscribe_backfills_then_tails() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    let from_ts = storage.latest_commit_ts();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"a", b"1").unwrap();
+    // backfilled from an SST and from the memtable
+    storage.force_flush().unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"z", b"1").unwrap();
+
+    let mut all = storage
+        .subscribe(Bound::Unbounded, Bound::Unbounded, from_ts)
+        .unwrap();
+    let mut range = storage
+        .subscribe(Bound::Included(b"a"), Bound::Excluded(b"c"), from_ts)
+        .unwrap();
+    let backfill = vec![
+        change("b", Some("1"), from_ts + 1),
+        change("a", Some("1"), from_ts + 2),
+        change("b", None, from_ts + 3),
+        change("z", Some("1"), from_ts + 4),
+    ];
+    for expected in &backfill {
+        assert_eq!(all.try_next().as_ref(), Some(expected));
+    }
+    for expected in backfill.iter().filter(|change| change.key != "z") {
+        assert_eq!(range.try_next().as_ref(), Some(expected));
+    }
+    assert_eq!(all.try_next(), None);
+    assert_eq!(range.try_next(), None);
+
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"c"[..], &b"2"[..]),
+            WriteBatchRecord::Put(&b"a"[..], &b"1"[..]),
+            WriteBatchRecord::Put(&b"a"[..], &b"2"[..]),
+            WriteBatchRecord::Del(&b"z"[..]),
+        ])
+        .unwrap();
+    let batch_ts = storage.latest_commit_ts();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"3");
+    txn.commit().unwrap();
+    let txn_ts = storage.latest_commit_ts();
+    let live = vec![
+        change("a", Some("2"), batch_ts),
+        change("c", Some("2"), batch_ts),
+        change("z", None, batch_ts),
+        change("b", Some("3"), txn_ts),
+    ];
+    for expected in &live {
+        assert_eq!(
+            all.next_timeout(Duration::from_secs(1)).as_ref(),
+            Some(expected)
+        );
+    }
+    for expected in live
+        .iter()
+        .filter(|change| change.key == "a" || change.key == "b")
+    {
+        assert_eq!(
+            range.next_timeout(Duration::from_secs(1)).as_ref(),
+            Some(expected)
+        );
+    }
+
+    // a subscription from the present only gets the new changes
+    let mut latest = storage
+        .subscribe(Bound::Unbounded, Bound::Unbounded, txn_ts)
+        .unwrap();
+    assert_eq!(latest.try_next(), None);
+    storage.put(b"d", b"1").unwrap();
+    assert_eq!(
+        latest.next_timeout(Duration::from_secs(1)),
+        Some(change("d", Some("1"), txn_ts + 1))
+    );
+    storage.close().unwrap();
+    assert_eq!(all.next(), Some(change("d", Some("1"), txn_ts + 1)));
+    assert_eq!(all.next(), None);
+}
@@ -0,0 +1,94 @@
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::fs::{FileSystem, InMemoryFileSystem};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options(enable_wal: bool) -> LsmStorageOptions {
+    let mut options = simple_leveled_wal_options();
+    options.enable_wal = enable_wal;
+    options
+}
+
+#[test]
+fn test_checkpoint() {
+    let dir = tempdir().unwrap();
+    let source_path = dir.path().join("source");
+    let checkpoint_path = dir.path().join("checkpoint");
+    let storage = LsmStorageInner::open(&source_path, options(true)).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    for i in 0..10 {
+        storage
+            .put(format!("key_{}", i).as_bytes(), b"flushed")
+            .unwrap();
+    }
+    sync(&storage);
+    storage.put(b"key_0", b"in_wal").unwrap();
+    storage.put_cf(&cf, b"cf_key", b"cf_value").unwrap();
+    storage.checkpoint(&checkpoint_path).unwrap();
+    assert!(storage.checkpoint(&checkpoint_path).is_err());
+    // the SSTs are linked rather than copied
+    let sst_ids = storage.live_sst_ids();
+    assert!(!sst_ids.is_empty());
+    for sst_id in sst_ids {
+        let linked = std::fs::metadata(checkpoint_path.join(format!("{:05}.sst", sst_id)));
+        let original = std::fs::metadata(storage.path_of_sst(sst_id)).unwrap();
+        assert_eq!(linked.unwrap().ino(), original.ino());
+    }
+
+    // changes made after the checkpoint are not part of it
+    storage.put(b"key_1", b"after").unwrap();
+    storage.delete(b"key_2").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+
+    drop(storage);
+
+    let checkpoint = LsmStorageInner::open(&checkpoint_path, options(true)).unwrap();
+    assert_eq!(
+        checkpoint.get(b"key_0").unwrap(),
+        Some(Bytes::from("in_wal"))
+    );
+    assert_eq!(
+        checkpoint.get(b"key_1").unwrap(),
+        Some(Bytes::from("flushed"))
+    );
+    assert_eq!(
+        checkpoint.get(b"key_2").unwrap(),
+        Some(Bytes::from("flushed"))
+    );
+    let cf = checkpoint.cf("cf").unwrap();
+    assert_eq!(
+        checkpoint.get_cf(&cf, b"cf_key").unwrap(),
+        Some(Bytes::from("cf_value"))
+    );
+    // the checkpoint is a database of its own
+    checkpoint.put(b"key_3", b"checkpoint").unwrap();
+    drop(checkpoint);
+    let storage = LsmStorageInner::open(&source_path, options(true)).unwrap();
+    assert_eq!(storage.get(b"key_1").unwrap(), Some(Bytes::from("after")));
+    assert_eq!(storage.get(b"key_2").unwrap(), None);
+    assert_eq!(storage.get(b"key_3").unwrap(), Some(Bytes::from("flushed")));
+}
+
+#[test]
+fn test_checkpoint_without_wal() {
+    let fs = Arc::new(InMemoryFileSystem::new());
+    let mut options = options(false);
+    options.file_system = Some(fs.clone());
+    let storage = LsmStorageInner::open("/db", options.clone()).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    // the memtable is flushed, it is lost otherwise
+    storage.checkpoint("/checkpoint").unwrap();
+    assert!(storage.state.read().memtable.is_empty());
+    assert!(fs.exists("/checkpoint/CURRENT".as_ref()));
+    storage.put(b"key", b"after").unwrap();
+    drop(storage);
+
+    let checkpoint = LsmStorageInner::open("/checkpoint", options).unwrap();
+    assert_eq!(checkpoint.get(b"key").unwrap(), Some(Bytes::from("value")));
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::clock::{Clock, MockClock};
+use crate::compact::{CompactionOptions, FifoCompactionOptions, LeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::ts_oracle::hlc_physical_millis;
+
+fn mock_clock() -> Arc<MockClock> {
+    Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000)))
+}
+
+#[test]
+fn test_fifo_ttl_follows_the_clock() {
+    let dir = tempdir().unwrap();
+    let clock = mock_clock();
+    let mut options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(FifoCompactionOptions {
+            max_table_files_size: u64::MAX,
+            ttl_seconds: 3600,
+        }));
+    options.clock = Some(clock.clone());
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    storage.put(b"old", b"1").unwrap();
+    sync(&storage);
+    let old_sst = storage.state.read().l0_sstables[0];
+    assert_eq!(
+        storage.state.read().sstables[&old_sst]
+            .properties()
+            .creation_time,
+        clock.unix_seconds()
+    );
+
+    clock.advance(Duration::from_secs(1800));
+    storage.put(b"new", b"1").unwrap();
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 2);
+
+    // the first SST is an hour old now, however little real time passed
+    clock.advance(Duration::from_secs(1800));
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 1);
+    assert!(!storage.path_of_sst(old_sst).exists());
+    assert_eq!(storage.get(b"old").unwrap(), None);
+    assert_eq!(storage.get(b"new").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_periodic_compaction_follows_the_clock() {
+    let dir = tempdir().unwrap();
+    let clock = mock_clock();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 2,
+        },
+    ));
+    options.periodic_compaction_seconds = 3600;
+    options.hybrid_logical_clock = true;
+    options.clock = Some(clock.clone());
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    assert_eq!(
+        hlc_physical_millis(storage.mvcc().latest_commit_ts()),
+        clock.unix_millis()
+    );
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    let sst_id = storage.state.read().l0_sstables[0];
+
+    // the SST is moved from L0 to the bottommost level as it is, then rewritten there
+    clock.advance(Duration::from_secs(7200));
+    storage.trigger_compaction().unwrap();
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    let rewritten = state.levels.last().unwrap().1[0];
+    assert_ne!(rewritten, sst_id);
+    assert_eq!(
+        state.sstables[&rewritten].properties().creation_time,
+        clock.unix_seconds()
+    );
+}
@@ -0,0 +1,548 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_lsm_iter_result_by_key, simple_leveled_wal_options, sync};
+use crate::column_family::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY_NAME};
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm, WriteBatchRecord};
+use crate::table::CompressionType;
+
+#[test]
+fn test_column_families_are_isolated() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let users = storage.create_cf("users").unwrap();
+    assert!(storage.create_cf("users").is_err());
+    assert!(storage.create_cf(DEFAULT_COLUMN_FAMILY_NAME).is_err());
+    assert_eq!(storage.list_cfs(), vec!["default", "users"]);
+
+    storage.put(b"a", b"default").unwrap();
+    storage.put_cf(&users, b"a", b"users").unwrap();
+    storage.put_cf(&users, b"b", b"users").unwrap();
+    storage.delete_cf(&users, b"b").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("default")));
+    assert_eq!(
+        storage.get_cf(&users, b"a").unwrap(),
+        Some(Bytes::from("users"))
+    );
+    assert_eq!(storage.get_cf(&users, b"b").unwrap(), None);
+
+    // flushed together, each into SSTs of its own
+    sync(&storage);
+    storage.put_cf(&users, b"c", b"users").unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 1);
+    assert_eq!(
+        storage
+            .column_family_state(users.id())
+            .unwrap()
+            .l0_sstables
+            .len(),
+        1
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf(&users, Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("users")),
+            (Bytes::from("c"), Bytes::from("users")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(Bytes::from("a"), Bytes::from("default"))],
+    );
+}
+
+#[test]
+fn test_column_family_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    for round in 0..4 {
+        for i in 0..50 {
+            storage
+                .put_cf(
+                    &cf,
+                    format!("key_{:03}", i).as_bytes(),
+                    format!("{round}").as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&storage);
+        storage.trigger_compaction().unwrap();
+    }
+    let state = storage.column_family_state(cf.id()).unwrap();
+    assert!(state.l0_sstables.len() < 2);
+    assert!(state.levels.iter().any(|(_, ssts)| !ssts.is_empty()));
+    assert_eq!(
+        storage.get_cf(&cf, b"key_007").unwrap(),
+        Some(Bytes::from("3"))
+    );
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.cf("cf").unwrap();
+    for i in 0..50 {
+        assert_eq!(
+            storage
+                .get_cf(&cf, format!("key_{:03}", i).as_bytes())
+                .unwrap(),
+            Some(Bytes::from("3"))
+        );
+    }
+}
+
+#[test]
+fn test_column_families_recover_from_the_wal() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put_cf(&cf, b"flushed", b"1").unwrap();
+    sync(&storage);
+    storage.put_cf(&cf, b"frozen", b"1").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put_cf(&cf, b"current", b"1").unwrap();
+    storage.put(b"default", b"1").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    assert_eq!(storage.list_cfs(), vec!["default", "cf"]);
+    let cf = storage.cf("cf").unwrap();
+    for key in ["flushed", "frozen", "current"] {
+        assert_eq!(
+            storage.get_cf(&cf, key.as_bytes()).unwrap(),
+            Some(Bytes::from("1"))
+        );
+        assert_eq!(storage.get(key.as_bytes()).unwrap(), None);
+    }
+    assert_eq!(storage.get_cf(&cf, b"default").unwrap(), None);
+    assert_eq!(storage.get(b"default").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_drop_column_family() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put_cf(&cf, b"a", b"1").unwrap();
+    sync(&storage);
+    let sst_id = storage.column_family_state(cf.id()).unwrap().l0_sstables[0];
+    storage.put_cf(&cf, b"b", b"1").unwrap();
+    storage.sync().unwrap();
+
+    assert!(storage.drop_cf(DEFAULT_COLUMN_FAMILY_NAME).is_err());
+    storage.drop_cf("cf").unwrap();
+    assert!(storage.drop_cf("cf").is_err());
+    assert!(!storage.path_of_sst(sst_id).exists());
+    assert!(storage.get_cf(&cf, b"a").is_err());
+    assert!(storage.put_cf(&cf, b"a", b"2").is_err());
+
+    // a column family created with the same name starts empty, and the old handle still fails
+    let recreated = storage.create_cf("cf").unwrap();
+    assert_ne!(recreated.id(), cf.id());
+    assert_eq!(storage.get_cf(&recreated, b"a").unwrap(), None);
+    assert!(storage.get_cf(&cf, b"a").is_err());
+    drop(storage);
+
+    // the writes of the dropped column family left in the WAL are not recovered
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let recreated = storage.cf("cf").unwrap();
+    assert_eq!(storage.get_cf(&recreated, b"b").unwrap(), None);
+    assert_eq!(storage.cf("cf").unwrap().id(), recreated.id());
+}
+
+#[test]
+fn test_manifest_rollover_keeps_column_families() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_leveled_wal_options();
+    options.max_manifest_file_size = 0;
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    let first = storage.create_cf("first").unwrap();
+    let second = storage.create_cf("second").unwrap();
+    storage.put_cf(&first, b"a", b"1").unwrap();
+    storage.put_cf(&second, b"a", b"2").unwrap();
+    sync(&storage);
+    storage.drop_cf("first").unwrap();
+    storage.put_cf(&second, b"b", b"2").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    assert_eq!(storage.list_cfs(), vec!["default", "second"]);
+    let second = storage.cf("second").unwrap();
+    assert_eq!(
+        storage.get_cf(&second, b"a").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    assert_eq!(
+        storage.get_cf(&second, b"b").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    // ids are never reused
+    assert!(storage.create_cf("third").unwrap().id() > second.id());
+}
+
+#[test]
+fn test_column_family_options() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_leveled_wal_options();
+    options.max_manifest_file_size = 0;
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    assert!(storage
+        .create_cf_with_options(
+            "invalid",
+            ColumnFamilyOptions {
+                block_size: Some(0),
+                ..Default::default()
+            },
+        )
+        .is_err());
+    let cf = storage
+        .create_cf_with_options(
+            "cf",
+            ColumnFamilyOptions {
+                block_size: Some(64),
+                compression_per_level: Some(vec![CompressionType::Lz]),
+                compaction_options: Some(CompactionOptions::NoCompaction),
+                write_buffer_size: None,
+            },
+        )
+        .unwrap();
+    for round in 0..3 {
+        for i in 0..20 {
+            let key = format!("key_{:03}", i);
+            let value = format!("value_{}_{:03}", round, i);
+            storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+            storage
+                .put_cf(&cf, key.as_bytes(), value.as_bytes())
+                .unwrap();
+        }
+        sync(&storage);
+        storage.trigger_compaction().unwrap();
+    }
+
+    // only the default column family is compacted, each one writes its SSTs with its own options
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.len() < 3);
+    let sst = &state.sstables[&state.l0_sstables[0]];
+    assert_eq!(sst.properties().compression, CompressionType::None);
+    assert_eq!(sst.properties().num_data_blocks, 1);
+    let cf_state = storage.column_family_state(cf.id()).unwrap();
+    assert_eq!(cf_state.l0_sstables.len(), 3);
+    for sst in cf_state.sstables.values() {
+        assert_eq!(sst.properties().compression, CompressionType::Lz);
+        assert!(sst.properties().num_data_blocks > 1);
+    }
+    drop(storage);
+
+    // the options are recorded in the manifest, rolled over at every record here
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    let cf = storage.cf("cf").unwrap();
+    storage.put_cf(&cf, b"key_000", b"value_3_000").unwrap();
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    let cf_state = storage.column_family_state(cf.id()).unwrap();
+    assert_eq!(cf_state.l0_sstables.len(), 4);
+    assert_eq!(
+        cf_state.sstables[&cf_state.l0_sstables[0]]
+            .properties()
+            .compression,
+        CompressionType::Lz
+    );
+    assert_eq!(
+        storage.get_cf(&cf, b"key_000").unwrap(),
+        Some(Bytes::from("value_3_000"))
+    );
+    assert_eq!(
+        storage.get_cf(&cf, b"key_019").unwrap(),
+        Some(Bytes::from("value_2_019"))
+    );
+}
+
+#[test]
+fn test_column_family_write_buffer_size() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage
+        .create_cf_with_options(
+            "cf",
+            ColumnFamilyOptions {
+                write_buffer_size: Some(1024),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    storage.put(b"default", b"1").unwrap();
+    for i in 0..100 {
+        storage
+            .put_cf(&cf, format!("key_{:03}", i).as_bytes(), &[b'x'; 32])
+            .unwrap();
+    }
+    // the memtables of all the column families are frozen together
+    let cf_state = storage.column_family_state(cf.id()).unwrap();
+    assert!(cf_state.imm_memtables.len() >= 2);
+    assert!(cf_state
+        .imm_memtables
+        .iter()
+        .all(|memtable| memtable.approximate_size() < 1024 + 64));
+    assert!(storage.state.read().imm_memtables.len() >= cf_state.imm_memtables.len());
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.cf("cf").unwrap();
+    assert_eq!(
+        storage.column_families.read()[&cf.id()]
+            .options
+            .write_buffer_size,
+        Some(1024)
+    );
+    assert_eq!(storage.get(b"default").unwrap(), Some(Bytes::from("1")));
+    for i in 0..100 {
+        assert_eq!(
+            storage
+                .get_cf(&cf, format!("key_{:03}", i).as_bytes())
+                .unwrap(),
+            Some(Bytes::from(vec![b'x'; 32]))
+        );
+    }
+}
+
+#[test]
+fn test_drop_column_family_in_use() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put_cf(&cf, b"a", b"1").unwrap();
+    sync(&storage);
+    let sst_id = storage.column_family_state(cf.id()).unwrap().l0_sstables[0];
+    let mut iter = storage
+        .scan_cf(&cf, Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+
+    // the SSTs outlive the drop while the iterator reads them
+    storage.drop_cf("cf").unwrap();
+    assert!(storage.get_cf(&cf, b"a").is_err());
+    assert!(storage.purge_dropped_column_families().unwrap().is_empty());
+    assert!(storage.delete_obsolete_files().unwrap().is_empty());
+    assert!(storage.path_of_sst(sst_id).exists());
+    check_lsm_iter_result_by_key(&mut iter, vec![(Bytes::from("a"), Bytes::from("1"))]);
+    drop(iter);
+    assert_eq!(
+        storage.purge_dropped_column_families().unwrap(),
+        vec![sst_id]
+    );
+    assert!(!storage.path_of_sst(sst_id).exists());
+    assert!(!storage.has_dropped_column_families());
+}
+
+#[test]
+fn test_drop_column_family_purged_in_the_background() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put_cf(&cf, b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let sst_id = storage
+        .inner
+        .column_family_state(cf.id())
+        .unwrap()
+        .l0_sstables[0];
+    let iter = storage
+        .scan_cf(&cf, Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    storage.drop_cf("cf").unwrap();
+    // writes to other column families go on meanwhile
+    let other = storage.create_cf("other").unwrap();
+    storage.put_cf(&other, b"a", b"2").unwrap();
+    assert!(storage.inner.path_of_sst(sst_id).exists());
+    drop(iter);
+
+    let mut purged = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(50));
+        if !storage.inner.path_of_sst(sst_id).exists() {
+            purged = true;
+            break;
+        }
+    }
+    assert!(purged);
+    assert_eq!(
+        storage.get_cf(&other, b"a").unwrap(),
+        Some(Bytes::from("2"))
+    );
+}
+
+#[test]
+fn test_write_batch_across_column_families() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let default = storage.cf(DEFAULT_COLUMN_FAMILY_NAME).unwrap();
+    let first = storage.create_cf("first").unwrap();
+    let second = storage.create_cf("second").unwrap();
+    storage.put_cf(&first, b"deleted", b"1").unwrap();
+    let before = storage.snapshot();
+    let commit_ts = storage.mvcc().latest_commit_ts();
+    storage
+        .write_batch_multi_cf(&[
+            (&default, &[WriteBatchRecord::Put("key", "default")][..]),
+            (
+                &first,
+                &[
+                    WriteBatchRecord::Put("key", "first"),
+                    WriteBatchRecord::Del("deleted"),
+                ],
+            ),
+            (&second, &[WriteBatchRecord::Put("key", "second")]),
+        ])
+        .unwrap();
+    // one commit ts for the whole batch
+    assert_eq!(storage.mvcc().latest_commit_ts(), commit_ts + 1);
+
+    let after = storage.snapshot();
+    for (cf, value) in [
+        (&default, "default"),
+        (&first, "first"),
+        (&second, "second"),
+    ] {
+        assert_eq!(
+            storage.get_cf_with_snapshot(cf, b"key", &before).unwrap(),
+            None
+        );
+        assert_eq!(
+            storage.get_cf_with_snapshot(cf, b"key", &after).unwrap(),
+            Some(Bytes::from(value))
+        );
+    }
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf_with_snapshot(&first, Bound::Unbounded, Bound::Unbounded, &before)
+            .unwrap(),
+        vec![(Bytes::from("deleted"), Bytes::from("1"))],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf_with_snapshot(&first, Bound::Unbounded, Bound::Unbounded, &after)
+            .unwrap(),
+        vec![(Bytes::from("key"), Bytes::from("first"))],
+    );
+    drop((before, after));
+
+    // nothing of a batch naming a dropped column family is written
+    storage.drop_cf("second").unwrap();
+    assert!(storage
+        .write_batch_multi_cf(&[
+            (&first, &[WriteBatchRecord::Put("other", "first")][..]),
+            (&second, &[WriteBatchRecord::Put("other", "second")]),
+        ])
+        .is_err());
+    assert_eq!(storage.get_cf(&first, b"other").unwrap(), None);
+    storage.sync().unwrap();
+    drop(storage);
+
+    // the batch is recovered from the WAL as a whole
+    let storage = LsmStorageInner::open(&dir, simple_leveled_wal_options()).unwrap();
+    let first = storage.cf("first").unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("default")));
+    assert_eq!(
+        storage.get_cf(&first, b"key").unwrap(),
+        Some(Bytes::from("first"))
+    );
+    assert_eq!(storage.get_cf(&first, b"deleted").unwrap(), None);
+}
+
+#[test]
+fn test_export_and_import_column_family() {
+    let dir = tempdir().unwrap();
+    let export_dir = tempdir().unwrap();
+    let export_path = export_dir.path().join("export");
+    let source =
+        LsmStorageInner::open(dir.path().join("source"), simple_leveled_wal_options()).unwrap();
+    let cf = source
+        .create_cf_with_options(
+            "cf",
+            ColumnFamilyOptions {
+                block_size: Some(256),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    for round in 0..3 {
+        for i in 0..20 {
+            source
+                .put_cf(
+                    &cf,
+                    format!("key_{:03}", i).as_bytes(),
+                    format!("value_{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&source);
+        source.trigger_compaction().unwrap();
+    }
+    // the memtable is flushed by the export
+    source.put_cf(&cf, b"unflushed", b"1").unwrap();
+    let exported = source.export_cf(&cf, &export_path).unwrap();
+    assert_eq!(exported.name, "cf");
+    assert_eq!(exported.options.block_size, Some(256));
+    assert!(exported.levels.iter().any(|(_, ssts)| !ssts.is_empty()));
+    assert!(source.export_cf(&cf, &export_path).is_err());
+
+    let target_path = dir.path().join("target");
+    let target = LsmStorageInner::open(&target_path, simple_leveled_wal_options()).unwrap();
+    target.put(b"key_000", b"default").unwrap();
+    let imported = target.import_cf("imported", &export_path).unwrap();
+    assert!(target.import_cf("imported", &export_path).is_err());
+    assert!(target.mvcc().latest_commit_ts() >= exported.max_ts);
+    assert_eq!(
+        target.get_cf(&imported, b"key_007").unwrap(),
+        Some(Bytes::from("value_2"))
+    );
+    assert_eq!(
+        target.get_cf(&imported, b"unflushed").unwrap(),
+        Some(Bytes::from("1"))
+    );
+    // newer writes shadow the imported versions
+    target.put_cf(&imported, b"key_001", b"new").unwrap();
+    assert_eq!(
+        target.get_cf(&imported, b"key_001").unwrap(),
+        Some(Bytes::from("new"))
+    );
+    assert_eq!(
+        target.get(b"key_000").unwrap(),
+        Some(Bytes::from("default"))
+    );
+    target.sync().unwrap();
+    drop(target);
+
+    // the SSTs flushed after reopening do not take the ids of the imported ones
+    let target = LsmStorageInner::open(&target_path, simple_leveled_wal_options()).unwrap();
+    let imported = target.cf("imported").unwrap();
+    sync(&target);
+    drop(target);
+    let target = LsmStorageInner::open(&target_path, simple_leveled_wal_options()).unwrap();
+    let imported_state = target.column_family_state(imported.id()).unwrap();
+    assert_eq!(
+        imported_state.sstables.len(),
+        exported.file_checksums.len() + 1
+    );
+    for i in 2..20 {
+        assert_eq!(
+            target
+                .get_cf(&imported, format!("key_{:03}", i).as_bytes())
+                .unwrap(),
+            Some(Bytes::from("value_2"))
+        );
+    }
+    assert_eq!(
+        target.get_cf(&imported, b"key_001").unwrap(),
+        Some(Bytes::from("new"))
+    );
+    target.verify_file_checksums().unwrap();
+}
@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{generate_sst_with_ts, leveled_options};
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn leveled_storage(dir: &tempfile::TempDir) -> LsmStorageInner {
+    let storage = LsmStorageInner::open(dir, leveled_options()).unwrap();
+    storage.mvcc().update_commit_ts(100);
+    storage
+}
+
+/// Add an SST with the keys `begin..end` written at `ts` to `level`, 0 being L0, and return its id.
+fn add_sst(storage: &LsmStorageInner, level: usize, begin: usize, end: usize, ts: u64) -> usize {
+    let id = storage.next_sst_id();
+    let data = (begin..end)
+        .map(|i| {
+            (
+                (Bytes::from(format!("key_{:05}", i)), ts),
+                Bytes::from(format!("value_{ts}")),
+            )
+        })
+        .collect();
+    let sst = generate_sst_with_ts(id, storage.path_of_sst(id), data, None);
+    let mut state = storage.state.read().as_ref().clone();
+    state.sstables.insert(id, Arc::new(sst));
+    if level == 0 {
+        state.l0_sstables.insert(0, id);
+    } else {
+        state.levels[level - 1].1.push(id);
+    }
+    *storage.state.write() = Arc::new(state);
+    id
+}
+
+fn get(storage: &LsmStorageInner, i: usize) -> Option<Bytes> {
+    storage.get(format!("key_{:05}", i).as_bytes()).unwrap()
+}
+
+#[test]
+fn test_compact_files_into_lower_level() {
+    let dir = tempdir().unwrap();
+    let storage = leveled_storage(&dir);
+    let l2_0 = add_sst(&storage, 2, 0, 50, 1);
+    let l2_1 = add_sst(&storage, 2, 50, 100, 1);
+    let l2_2 = add_sst(&storage, 2, 200, 300, 1);
+    let l1 = add_sst(&storage, 1, 20, 80, 2);
+
+    let output = storage.compact_files(&[l1, l2_0, l2_1], 2).unwrap();
+    assert!(!output.is_empty());
+    {
+        let state = storage.state.read();
+        assert!(state.levels[0].1.is_empty());
+        assert_eq!(state.levels[1].1, [&output[..], &[l2_2]].concat());
+    }
+    for sst_id in [l1, l2_0, l2_1] {
+        assert!(!storage.path_of_sst(sst_id).exists());
+    }
+    assert_eq!(get(&storage, 10), Some(Bytes::from("value_1")));
+    assert_eq!(get(&storage, 20), Some(Bytes::from("value_2")));
+    assert_eq!(get(&storage, 90), Some(Bytes::from("value_1")));
+    assert_eq!(get(&storage, 250), Some(Bytes::from("value_1")));
+    assert_eq!(
+        storage.compaction_stats().levels[2]
+            .counters
+            .num_compactions,
+        1
+    );
+}
+
+#[test]
+fn test_compact_files_oldest_l0_ssts_through_empty_levels() {
+    let dir = tempdir().unwrap();
+    let storage = leveled_storage(&dir);
+    let oldest = add_sst(&storage, 0, 0, 100, 1);
+    let newest = add_sst(&storage, 0, 0, 100, 2);
+
+    // the newest L0 SST cannot go below the oldest one
+    assert!(storage.compact_files(&[newest], 3).is_err());
+
+    let output = storage.compact_files(&[oldest], 3).unwrap();
+    let state = storage.state.read().clone();
+    assert_eq!(state.l0_sstables, vec![newest]);
+    assert_eq!(state.levels[2].1, output);
+    assert_eq!(get(&storage, 0), Some(Bytes::from("value_2")));
+}
+
+#[test]
+fn test_compact_files_in_place() {
+    let dir = tempdir().unwrap();
+    let storage = leveled_storage(&dir);
+    let first = add_sst(&storage, 3, 0, 50, 1);
+    let second = add_sst(&storage, 3, 50, 100, 1);
+
+    let output = storage.compact_files(&[first], 3).unwrap();
+    assert_ne!(output, vec![first]);
+    assert_eq!(
+        storage.state.read().levels[2].1,
+        [&output[..], &[second]].concat()
+    );
+    assert_eq!(get(&storage, 0), Some(Bytes::from("value_1")));
+}
+
+#[test]
+fn test_compact_files_rejects_illegal_selection() {
+    let dir = tempdir().unwrap();
+    let storage = leveled_storage(&dir);
+    let l3 = add_sst(&storage, 3, 0, 100, 1);
+    let l2 = add_sst(&storage, 2, 0, 100, 2);
+    let l1 = add_sst(&storage, 1, 50, 150, 3);
+    let l1_other = add_sst(&storage, 1, 200, 300, 3);
+    let state = storage.state.read().clone();
+
+    // empty selections, unknown SSTs and out of range levels
+    assert!(storage.compact_files(&[], 2).is_err());
+    assert!(storage.compact_files(&[12345], 2).is_err());
+    assert!(storage.compact_files(&[l1], 0).is_err());
+    assert!(storage.compact_files(&[l1], 4).is_err());
+    assert!(storage.compact_files(&[l1, l1], 2).is_err());
+    // an SST cannot move up
+    assert!(storage.compact_files(&[l3], 2).is_err());
+    // the overlapping SST of the output level is missing
+    assert!(storage.compact_files(&[l1], 2).is_err());
+    // L2 overlaps but is skipped
+    assert!(storage.compact_files(&[l1, l3], 3).is_err());
+    // inputs from two levels above the output level
+    assert!(storage.compact_files(&[l1, l2, l3], 3).is_err());
+    assert_eq!(storage.state.read().levels, state.levels);
+
+    // the non-overlapping SST goes through
+    storage.compact_files(&[l1_other], 3).unwrap();
+    assert_eq!(storage.state.read().levels[0].1, vec![l1]);
+}
+
+#[test]
+fn test_compact_files_requires_leveled_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+            SimpleLeveledCompactionOptions {
+                size_ratio_percent: 200,
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 3,
+            },
+        )),
+    )
+    .unwrap();
+    let sst_id = add_sst(&storage, 1, 0, 10, 1);
+    assert!(storage.compact_files(&[sst_id], 2).is_err());
+}
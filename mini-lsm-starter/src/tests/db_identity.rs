@@ -0,0 +1,85 @@
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, sync};
+use crate::fs::LocalFileSystem;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::manifest::read_current;
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    // rolled over on every record
+    options.max_manifest_file_size = 1;
+    options
+}
+
+fn flush(storage: &LsmStorageInner, key: &[u8]) -> usize {
+    storage.put(key, b"value").unwrap();
+    sync(storage);
+    storage.state.read().l0_sstables[0]
+}
+
+fn identity_of_sst(storage: &LsmStorageInner, sst_id: usize) -> (String, String) {
+    let properties = storage.state.read().sstables[&sst_id].properties().clone();
+    (properties.db_id, properties.db_session_id)
+}
+
+#[test]
+fn test_db_id_persisted() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, options()).unwrap();
+    let (db_id, session_id) = (
+        storage.db_id().to_string(),
+        storage.session_id().to_string(),
+    );
+    assert_eq!(db_id.len(), 36);
+    assert_eq!(&db_id[14..15], "4");
+    assert_ne!(db_id, session_id);
+    let first = flush(&storage, b"a");
+    assert_eq!(
+        identity_of_sst(&storage, first),
+        (db_id.clone(), session_id.clone())
+    );
+    drop(storage);
+
+    // kept through reopening and rolling the manifest over, with a new session
+    let storage = LsmStorageInner::open(&dir, options()).unwrap();
+    assert_ne!(
+        read_current(&LocalFileSystem, dir.path()).unwrap().unwrap(),
+        "MANIFEST-000001"
+    );
+    assert_eq!(storage.db_id(), db_id);
+    assert_ne!(storage.session_id(), session_id);
+    assert_eq!(
+        identity_of_sst(&storage, first),
+        (db_id.clone(), session_id.clone())
+    );
+    flush(&storage, b"a");
+    storage.trigger_compaction().unwrap();
+    let compacted = storage.state.read().levels.last().unwrap().1[0];
+    assert_eq!(
+        identity_of_sst(&storage, compacted),
+        (db_id.clone(), storage.session_id().to_string())
+    );
+
+    // another database gets another identity
+    let other_dir = tempdir().unwrap();
+    let other = LsmStorageInner::open(&other_dir, options()).unwrap();
+    assert_ne!(other.db_id(), db_id);
+}
+
+#[test]
+fn test_repair_keeps_db_id() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, options()).unwrap();
+    flush(&storage, b"a");
+    flush(&storage, b"b");
+    let db_id = storage.db_id().to_string();
+    drop(storage);
+    let current = read_current(&LocalFileSystem, dir.path()).unwrap().unwrap();
+    std::fs::remove_file(dir.path().join(current)).unwrap();
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+
+    LsmStorageInner::repair(&dir, &options()).unwrap();
+    let storage = LsmStorageInner::open(&dir, options()).unwrap();
+    assert_eq!(storage.db_id(), db_id);
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::rate_limiter::RateLimiter;
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.compaction_options = CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    });
+    options.rate_limiter = Some(Arc::new(RateLimiter::new(1 << 20)));
+    options
+}
+
+#[test]
+fn test_set_options() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage
+        .set_options(&[
+            ("target_sst_size", "4096"),
+            ("num_memtable_limit", "4"),
+            ("level0_file_num_compaction_trigger", "8"),
+            ("block_cache_capacity", "65536"),
+            ("rate_limiter_bytes_per_sec", "1024"),
+        ])
+        .unwrap();
+
+    let options = storage.options();
+    assert_eq!(options.target_sst_size, 4096);
+    assert_eq!(options.num_memtable_limit, 4);
+    let CompactionOptions::Leveled(leveled) = &options.compaction_options else {
+        panic!("unexpected compaction options");
+    };
+    assert_eq!(leveled.level0_file_num_compaction_trigger, 8);
+    assert_eq!(options.rate_limiter.as_ref().unwrap().bytes_per_sec(), 1024);
+    assert_eq!(storage.block_cache_stats().capacity, 65536);
+
+    // the changes are persisted, and the storage still works with them
+    let persisted = LsmStorageOptions::load_latest(&dir).unwrap();
+    assert_eq!(persisted.target_sst_size, 4096);
+    for i in 0..100 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), &[b'v'; 100])
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    assert_eq!(
+        storage.get(b"key_050").unwrap().as_deref(),
+        Some(&[b'v'; 100][..])
+    );
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_set_options_rejects_invalid_changes() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for changes in [
+        vec![("no_such_option", "1")],
+        vec![("block_size", "8192")],
+        vec![("num_tiers", "4")],
+        vec![("target_sst_size", "large")],
+        // each change is valid on its own, but not all of them together
+        vec![("num_memtable_limit", "4"), ("max_subcompactions", "0")],
+    ] {
+        assert!(storage.set_options(&changes).is_err(), "{:?}", changes);
+    }
+    // none of the changes were applied
+    let options = storage.options();
+    assert_eq!(options.num_memtable_limit, 50);
+    assert_eq!(options.max_subcompactions, 1);
+    storage.close().unwrap();
+}
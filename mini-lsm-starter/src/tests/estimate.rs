@@ -0,0 +1,88 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use self::harness::sync;
+
+use super::*;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[test]
+fn test_approximate_sizes() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    let value = "1".repeat(100);
+    for i in 0..2000 {
+        storage
+            .put(format!("{:05}", i).as_bytes(), value.as_bytes())
+            .unwrap();
+        if i % 500 == 499 {
+            sync(&storage);
+        }
+    }
+    storage.put(b"99999", value.as_bytes()).unwrap();
+
+    let all = (Bound::Unbounded, Bound::Unbounded);
+    let first_half = (
+        Bound::Included(&b"00000"[..]),
+        Bound::Excluded(&b"01000"[..]),
+    );
+    let tiny = (
+        Bound::Included(&b"00100"[..]),
+        Bound::Included(&b"00100"[..]),
+    );
+    let outside = (Bound::Excluded(&b"02000"[..]), Bound::Unbounded);
+    let sizes = storage.get_approximate_sizes(&[all, first_half, tiny, outside], false);
+    let data_size = 2000 * (5 + 100);
+    assert!(sizes[0] >= data_size && sizes[0] < data_size * 2);
+    assert!(sizes[1] * 3 > sizes[0] && sizes[1] * 3 < sizes[0] * 2);
+    assert!(sizes[2] > 0 && sizes[2] <= 4096);
+    assert_eq!(sizes[3], 0);
+
+    let sizes = storage.get_approximate_sizes(&[outside, first_half], true);
+    assert_eq!(sizes[0], 5 + 8 + 100);
+    assert!(sizes[1] * 3 > sizes[0] && sizes[1] * 3 < data_size * 4);
+}
+
+#[test]
+fn test_estimate_num_keys() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    for i in 0..1000 {
+        storage
+            .put(format!("{:05}", i).as_bytes(), b"2333333")
+            .unwrap();
+    }
+    sync(&storage);
+    {
+        let snapshot = storage.state.read();
+        let table = &snapshot.sstables[&snapshot.l0_sstables[0]];
+        assert_eq!(table.properties().num_entries, 1000);
+        assert_eq!(table.properties().num_deletions, 0);
+        assert_eq!(table.properties().raw_key_size, 5000);
+        assert_eq!(table.properties().raw_value_size, 7000);
+    }
+    assert_eq!(storage.estimate_num_keys(), 1000);
+
+    for i in 0..100 {
+        storage.delete(format!("{:05}", i).as_bytes()).unwrap();
+    }
+    sync(&storage);
+    for i in 1000..1200 {
+        storage
+            .put(format!("{:05}", i).as_bytes(), b"2333333")
+            .unwrap();
+    }
+    assert_eq!(storage.estimate_num_keys(), 1100);
+
+    let in_range =
+        storage.estimate_num_keys_in_range(Bound::Included(b"00500"), Bound::Excluded(b"01100"));
+    assert!((550..=650).contains(&in_range), "{}", in_range);
+    assert_eq!(
+        storage.estimate_num_keys_in_range(Bound::Included(b"01100"), Bound::Unbounded),
+        100
+    );
+}
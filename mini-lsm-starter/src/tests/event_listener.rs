@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::event_listener::{CompactionJobInfo, EventListener, FlushJobInfo};
+use crate::lsm_storage::LsmStorageInner;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    FlushBegin(usize),
+    FlushCompleted(FlushJobInfo),
+    CompactionBegin(CompactionJobInfo),
+    CompactionCompleted(CompactionJobInfo),
+}
+
+#[derive(Debug, Default)]
+struct RecordingListener {
+    events: Mutex<Vec<Event>>,
+}
+
+impl EventListener for RecordingListener {
+    fn on_flush_begin(&self, memtable_id: usize) {
+        self.events.lock().push(Event::FlushBegin(memtable_id));
+    }
+
+    fn on_flush_completed(&self, info: &FlushJobInfo) {
+        self.events.lock().push(Event::FlushCompleted(info.clone()));
+    }
+
+    fn on_compaction_begin(&self, info: &CompactionJobInfo) {
+        self.events
+            .lock()
+            .push(Event::CompactionBegin(info.clone()));
+    }
+
+    fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+        self.events
+            .lock()
+            .push(Event::CompactionCompleted(info.clone()));
+    }
+}
+
+#[test]
+fn test_flush_and_compaction_events() {
+    let dir = tempdir().unwrap();
+    let listener = Arc::new(RecordingListener::default());
+    let mut options = leveled_options();
+    options.event_listeners = vec![listener.clone()];
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 50, 150, "value");
+    sync(&storage);
+
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    {
+        let events = listener.events.lock();
+        assert_eq!(events.len(), 4);
+        for (events, sst_id) in events.chunks(2).zip(l0_sstables.iter().rev()) {
+            assert_eq!(events[0], Event::FlushBegin(*sst_id));
+            let Event::FlushCompleted(info) = &events[1] else {
+                panic!("expected a flush completion, got {:?}", events[1]);
+            };
+            assert_eq!(info.sst_id, *sst_id);
+            assert_eq!(info.num_entries, 100);
+            assert!(info.memtable_size > 0);
+            assert_eq!(
+                info.sst_size,
+                storage.state.read().sstables[sst_id].table_size()
+            );
+        }
+    }
+
+    listener.events.lock().clear();
+    storage.trigger_compaction().unwrap();
+    let events = listener.events.lock();
+    assert_eq!(events.len(), 2);
+    let Event::CompactionBegin(begin) = &events[0] else {
+        panic!("expected a compaction start, got {:?}", events[0]);
+    };
+    assert_eq!(begin.output_level, 3);
+    assert_eq!(begin.input_sst_ids, l0_sstables);
+    assert!(begin.output_sst_ids.is_empty());
+    let Event::CompactionCompleted(completed) = &events[1] else {
+        panic!("expected a compaction completion, got {:?}", events[1]);
+    };
+    assert_eq!(completed.output_level, 3);
+    assert_eq!(completed.input_sst_ids, l0_sstables);
+    assert_eq!(completed.output_sst_ids, storage.state.read().levels[2].1);
+    assert_eq!(completed.counters.num_compactions, 1);
+    assert_eq!(
+        completed.counters.bytes_written,
+        storage.compaction_stats().levels[3].counters.bytes_written
+    );
+}
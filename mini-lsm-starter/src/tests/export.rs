@@ -0,0 +1,75 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_lsm_iter_result_by_key, simple_leveled_wal_options, sync};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options() -> LsmStorageOptions {
+    let mut options = simple_leveled_wal_options();
+    options.block_size = 64;
+    options.target_sst_size = 256;
+    options
+}
+
+#[test]
+fn test_export_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path().join("db"), options()).unwrap();
+    for i in 0..50 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"old")
+            .unwrap();
+    }
+    sync(&storage);
+    for i in 0..50 {
+        if i % 2 == 0 {
+            storage.delete(format!("key_{:03}", i).as_bytes()).unwrap();
+        } else if i % 5 == 0 {
+            storage
+                .put(format!("key_{:03}", i).as_bytes(), b"new")
+                .unwrap();
+        }
+    }
+    let snapshot = storage.snapshot();
+    storage.put(b"key_001", b"after").unwrap();
+    storage.delete(b"key_003").unwrap();
+    storage.put(b"key_100", b"after").unwrap();
+
+    let export_dir = dir.path().join("export");
+    let exported = storage.export_snapshot(&snapshot, &export_dir).unwrap();
+    assert!(exported.len() > 1);
+    assert_eq!(
+        exported.iter().map(|info| info.num_entries).sum::<u64>(),
+        25
+    );
+    for pair in exported.windows(2) {
+        assert!(pair[0].largest_key < pair[1].smallest_key);
+    }
+    assert!(storage.export_snapshot(&snapshot, &export_dir).is_err());
+
+    let imported = LsmStorageInner::open(dir.path().join("imported"), options()).unwrap();
+    let paths = exported.iter().map(|info| &info.path).collect::<Vec<_>>();
+    imported.ingest_external_files(&paths).unwrap();
+    let expected = (0..50)
+        .filter(|i| i % 2 == 1)
+        .map(|i| {
+            let value = if i % 5 == 0 { "new" } else { "old" };
+            (Bytes::from(format!("key_{:03}", i)), Bytes::from(value))
+        })
+        .collect();
+    check_lsm_iter_result_by_key(
+        &mut imported.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+
+    // nothing is visible to the snapshot
+    let cf = storage.create_cf("cf").unwrap();
+    let empty_dir = dir.path().join("empty");
+    let exported = storage
+        .export_snapshot_cf(&cf, &storage.snapshot(), &empty_dir)
+        .unwrap();
+    assert!(exported.is_empty());
+    assert!(empty_dir.exists());
+}
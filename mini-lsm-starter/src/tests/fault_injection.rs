@@ -0,0 +1,156 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::fs::{FaultInjectionFileSystem, InMemoryFileSystem};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+const DB_PATH: &str = "/db";
+
+fn fault_injection_fs() -> Arc<FaultInjectionFileSystem> {
+    Arc::new(FaultInjectionFileSystem::new(Arc::new(
+        InMemoryFileSystem::new(),
+    )))
+}
+
+fn options(fs: &Arc<FaultInjectionFileSystem>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.compaction_options = CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    options.file_system = Some(fs.clone());
+    options
+}
+
+fn open(fs: &Arc<FaultInjectionFileSystem>) -> LsmStorageInner {
+    LsmStorageInner::open(DB_PATH, options(fs)).unwrap()
+}
+
+/// Crash and reopen the database.
+fn crash_and_reopen(
+    fs: &Arc<FaultInjectionFileSystem>,
+    storage: LsmStorageInner,
+) -> LsmStorageInner {
+    fs.crash().unwrap();
+    drop(storage);
+    LsmStorageInner::open(DB_PATH, options(fs)).unwrap()
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:03}", i).into_bytes()
+}
+
+fn flush(storage: &LsmStorageInner) -> anyhow::Result<()> {
+    storage.force_freeze_memtable(&storage.state_lock.lock())?;
+    storage.force_flush_next_imm_memtable()
+}
+
+#[test]
+fn test_crash_loses_only_unsynced_writes() {
+    let fs = fault_injection_fs();
+    let mut storage = open(&fs);
+    let mut synced = 0;
+    for round in 0..6 {
+        for i in synced..synced + 20 {
+            storage.put(&key(i), b"synced").unwrap();
+        }
+        storage.sync().unwrap();
+        synced += 20;
+        match round % 3 {
+            0 => flush(&storage).unwrap(),
+            1 => {
+                flush(&storage).unwrap();
+                storage.force_full_compaction().unwrap();
+            }
+            _ => {}
+        }
+        storage.put(&key(synced), b"unsynced").unwrap();
+        storage = crash_and_reopen(&fs, storage);
+        for i in 0..synced {
+            assert_eq!(
+                storage.get(&key(i)).unwrap(),
+                Some(Bytes::from("synced")),
+                "round {}",
+                round
+            );
+        }
+        assert_eq!(storage.get(&key(synced)).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_write_failures_never_lose_synced_writes() {
+    let mut num_write_operations = 0;
+    loop {
+        let fs = fault_injection_fs();
+        let storage = open(&fs);
+        for i in 0..20 {
+            storage.put(&key(i), b"1").unwrap();
+        }
+        storage.sync().unwrap();
+        // the flush fails at its nth write operation, and the database crashes
+        fs.fail_writes_after(num_write_operations);
+        let result = flush(&storage).and_then(|_| storage.force_full_compaction());
+        fs.clear_faults();
+        let storage = crash_and_reopen(&fs, storage);
+        for i in 0..20 {
+            assert_eq!(storage.get(&key(i)).unwrap(), Some(Bytes::from("1")));
+        }
+        if result.is_ok() {
+            break;
+        }
+        assert!(fs.num_injected_faults() > 0);
+        num_write_operations += 1;
+    }
+    assert!(num_write_operations > 3);
+}
+
+#[test]
+fn test_short_reads_are_detected() {
+    let fs = fault_injection_fs();
+    let storage = open(&fs);
+    for i in 0..100 {
+        storage.put(&key(i), b"value").unwrap();
+    }
+    flush(&storage).unwrap();
+    drop(storage);
+
+    let storage = open(&fs);
+    fs.short_reads(1);
+    assert!(storage.get(&key(0)).is_err());
+    assert_eq!(fs.num_injected_faults(), 1);
+    assert_eq!(storage.get(&key(0)).unwrap(), Some(Bytes::from("value")));
+}
+
+#[test]
+fn test_handles_opened_before_a_crash_fail() {
+    let fs = fault_injection_fs();
+    let storage = open(&fs);
+    storage.put(b"a", b"1").unwrap();
+    fs.crash().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    assert!(storage.sync().is_err());
+    assert!(flush(&storage).is_err());
+}
+
+#[test]
+fn test_flushing_empty_memtables_leaves_no_sst() {
+    let fs = fault_injection_fs();
+    let storage = open(&fs);
+    storage.put(b"a", b"1").unwrap();
+    flush(&storage).unwrap();
+    // two `force_flush` racing freeze the memtable, then the empty one after it
+    for _ in 0..2 {
+        flush(&storage).unwrap();
+    }
+    assert_eq!(storage.state.read().l0_sstables.len(), 1);
+    assert!(storage.state.read().imm_memtables.is_empty());
+    let storage = crash_and_reopen(&fs, storage);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert!(storage.scan(Bound::Unbounded, Bound::Unbounded).is_ok());
+}
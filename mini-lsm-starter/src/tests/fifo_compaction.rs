@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{generate_sst, sync};
+use crate::compact::{CompactionOptions, FifoCompactionController, FifoCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn fifo_options(max_table_files_size: u64, ttl_seconds: u64) -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(FifoCompactionOptions {
+        max_table_files_size,
+        ttl_seconds,
+    }))
+}
+
+#[test]
+fn test_fifo_drops_oldest_ssts_over_size_limit() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, fifo_options(u64::MAX, 0)).unwrap();
+    for i in 0..5 {
+        for j in 0..100 {
+            storage
+                .put(format!("key_{i}_{:03}", j).as_bytes(), b"value")
+                .unwrap();
+        }
+        sync(&storage);
+    }
+    let state = storage.state.read().clone();
+    let ssts = state.l0_sstables.clone();
+    let sst_size = state.sstables[&ssts[0]].table_size();
+    drop(state);
+    drop(storage);
+
+    // reopen with room for three SSTs
+    let storage =
+        LsmStorageInner::open(&dir, fifo_options(sst_size * 3 + sst_size / 2, 0)).unwrap();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().l0_sstables, ssts[..3].to_vec());
+    for sst_id in &ssts[3..] {
+        assert!(!storage.path_of_sst(*sst_id).exists());
+    }
+    assert_eq!(storage.get(b"key_0_042").unwrap(), None);
+    assert_eq!(storage.get(b"key_1_042").unwrap(), None);
+    assert_eq!(&storage.get(b"key_4_042").unwrap().unwrap()[..], b"value");
+
+    // nothing more to delete
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().l0_sstables, ssts[..3].to_vec());
+}
+
+#[test]
+fn test_fifo_drops_expired_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, fifo_options(u64::MAX, 3600)).unwrap();
+    let mut state = storage.state.read().as_ref().clone();
+    let data = |key: &str| vec![(Bytes::from(key.to_string()), Bytes::from("value"))];
+    let mut old_sst = generate_sst(1, dir.path().join("1.sst"), data("a"), None);
+    old_sst.properties.creation_time = 0;
+    let new_sst = generate_sst(2, dir.path().join("2.sst"), data("b"), None);
+    state.sstables.insert(1, Arc::new(old_sst));
+    state.sstables.insert(2, Arc::new(new_sst));
+    state.l0_sstables = vec![2, 1];
+
+    let controller = FifoCompactionController::new(FifoCompactionOptions {
+        max_table_files_size: u64::MAX,
+        ttl_seconds: 3600,
+    });
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.sst_ids_to_delete, vec![1]);
+    let (state, files_to_remove) = controller.apply_compaction_result(&state, &task, &[]);
+    assert_eq!(state.l0_sstables, vec![2]);
+    assert_eq!(files_to_remove, vec![1]);
+    assert!(controller.generate_compaction_task(&state).is_none());
+}
@@ -0,0 +1,58 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+
+#[test]
+fn test_disable_file_deletions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    for round in 0..2 {
+        storage
+            .put(b"key", format!("{}", round).as_bytes())
+            .unwrap();
+        storage.put_cf(&cf, b"key", b"value").unwrap();
+        sync(&storage);
+    }
+    storage.disable_file_deletions();
+    storage.disable_file_deletions();
+    let mut kept = storage
+        .live_sst_ids()
+        .iter()
+        .map(|sst_id| storage.path_of_sst(*sst_id))
+        .collect::<Vec<_>>();
+    let wal = storage.path_of_wal(storage.state.read().memtable.id());
+    storage.put(b"key", b"2").unwrap();
+    sync(&storage);
+    kept.push(wal);
+    storage.force_full_compaction().unwrap();
+    storage.drop_cf("cf").unwrap();
+    assert!(storage.delete_obsolete_files().unwrap().is_empty());
+    assert!(storage.purge_dropped_column_families().unwrap().is_empty());
+    for path in &kept {
+        assert!(path.exists(), "{} is deleted", path.display());
+    }
+
+    // until every call is undone
+    storage.enable_file_deletions().unwrap();
+    assert!(kept.iter().all(|path| path.exists()));
+    storage.enable_file_deletions().unwrap();
+    for path in &kept {
+        assert!(!path.exists(), "{} is kept", path.display());
+    }
+    assert!(storage.enable_file_deletions().is_err());
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("2")));
+
+    // deleted right away again
+    storage.put(b"key", b"3").unwrap();
+    sync(&storage);
+    let compacted = storage
+        .live_sst_ids()
+        .iter()
+        .map(|sst_id| storage.path_of_sst(*sst_id))
+        .collect::<Vec<_>>();
+    storage.force_full_compaction().unwrap();
+    assert!(compacted.iter().all(|path| !path.exists()));
+}
@@ -0,0 +1,43 @@
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+
+#[test]
+fn test_file_io_stats() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_wal_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    sync(&storage);
+    storage.put(b"b", b"1").unwrap();
+    sync(&storage);
+    let state = storage.state.read().clone();
+    let (cold, hot) = (state.l0_sstables[1], state.l0_sstables[0]);
+    drop(state);
+
+    assert_eq!(storage.file_io_stats().len(), 2);
+    assert!(storage
+        .file_io_stats()
+        .iter()
+        .all(|(_, stats)| stats.reads == 0));
+    for _ in 0..3 {
+        assert_eq!(storage.get(b"b").unwrap().as_deref(), Some(&b"1"[..]));
+    }
+    assert_eq!(storage.get(b"a").unwrap().as_deref(), Some(&b"1"[..]));
+
+    let hottest = storage.hottest_files(10);
+    assert_eq!(hottest.len(), 2);
+    let stats_of = |id| hottest.iter().find(|file| file.sst_id == id).unwrap();
+    // the block cache serves the repeated gets after the first read of each file
+    assert_eq!(stats_of(hot).io.reads, 1);
+    assert_eq!(stats_of(cold).io.reads, 1);
+    assert!(stats_of(hot).io.bytes_read > 0);
+    assert!(hottest.iter().all(|file| file.level == 0));
+    assert!(hottest[0].io.latency.sum >= hottest[1].io.latency.sum);
+    assert_eq!(storage.hottest_files(1), hottest[..1]);
+
+    let listed = storage.get_property("cobblesdb.hot-files").unwrap();
+    assert_eq!(listed.lines().count(), 2);
+    assert!(listed.contains(&format!("{}.sst L0", hot)), "{}", listed);
+    assert!(listed.contains("READS : 1"), "{}", listed);
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::fs::{FileSystem, LocalFileSystem, ReadableFile, WritableFile};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+/// The local disk, recording the files written and opened, and refusing to create SSTs once frozen.
+#[derive(Debug, Default)]
+struct RecordingFileSystem {
+    written: Mutex<BTreeSet<String>>,
+    opened: Mutex<BTreeSet<String>>,
+    frozen: Mutex<bool>,
+}
+
+impl RecordingFileSystem {
+    fn record_write(&self, path: &Path) -> io::Result<()> {
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        if *self.frozen.lock() && name.ends_with(".sst") {
+            return Err(io::Error::other("quota exceeded"));
+        }
+        self.written.lock().insert(name);
+        Ok(())
+    }
+}
+
+impl FileSystem for RecordingFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.record_write(path)?;
+        LocalFileSystem.create(path)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.record_write(path)?;
+        LocalFileSystem.create_new(path)
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.record_write(path)?;
+        LocalFileSystem.open_append(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadableFile>> {
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        self.opened.lock().insert(name);
+        LocalFileSystem.open(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        LocalFileSystem.exists(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        LocalFileSystem.list_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        LocalFileSystem.create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        LocalFileSystem.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.record_write(to)?;
+        LocalFileSystem.rename(from, to)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        LocalFileSystem.sync_dir(path)
+    }
+}
+
+fn options(fs: &Arc<RecordingFileSystem>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.compaction_options = CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    options.file_system = Some(fs.clone());
+    options
+}
+
+#[test]
+fn test_every_file_goes_through_the_file_system() {
+    let dir = tempdir().unwrap();
+    let fs = Arc::new(RecordingFileSystem::default());
+    let storage = MiniLsm::open(&dir, options(&fs)).unwrap();
+    for i in 0..3 {
+        storage
+            .put(format!("key_{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    storage.put(b"in_wal", b"value").unwrap();
+    storage.close().unwrap();
+
+    let opened_before = fs.opened.lock().len();
+    let storage = MiniLsm::open(&dir, options(&fs)).unwrap();
+    assert_eq!(storage.get(b"key_1").unwrap(), Some(Bytes::from("value")));
+    assert_eq!(storage.get(b"in_wal").unwrap(), Some(Bytes::from("value")));
+    assert!(fs.opened.lock().len() > opened_before);
+    storage.close().unwrap();
+
+    let written = fs.written.lock().clone();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let name = entry.unwrap().file_name().into_string().unwrap();
+        assert!(
+            written.contains(&name),
+            "{} was not written by the file system",
+            name
+        );
+    }
+    assert!(written.iter().any(|name| name.ends_with(".sst")));
+    assert!(written.iter().any(|name| name.ends_with(".wal")));
+    assert!(written.contains("CURRENT"));
+    assert!(written.contains("OPTIONS"));
+}
+
+#[test]
+fn test_file_system_errors_fail_the_flush() {
+    let dir = tempdir().unwrap();
+    let fs = Arc::new(RecordingFileSystem::default());
+    let storage = MiniLsm::open(&dir, options(&fs)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+
+    *fs.frozen.lock() = true;
+    storage.put(b"b", b"2").unwrap();
+    let e = storage.force_flush().unwrap_err();
+    assert!(format!("{:#}", e).contains("quota exceeded"), "{:#}", e);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+
+    *fs.frozen.lock() = false;
+    storage.force_flush().unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{generate_sst, leveled_options, put_range, sync};
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+
+fn num_sst_files(path: &std::path::Path) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_str()
+                .unwrap()
+                .ends_with(".sst")
+        })
+        .count()
+}
+
+#[test]
+fn test_cancelled_compaction_leaves_no_file() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 50, 150, "value");
+    sync(&storage);
+    let state = storage.state.read().clone();
+
+    storage.cancel_compactions();
+    assert!(storage.trigger_compaction().is_err());
+    assert_eq!(storage.state.read().l0_sstables, state.l0_sstables);
+    assert_eq!(storage.state.read().levels, state.levels);
+    assert_eq!(num_sst_files(dir.path()), 2);
+    assert_eq!(
+        storage.get(b"key_00120").unwrap(),
+        Some(Bytes::from("value"))
+    );
+}
+
+#[test]
+fn test_obsolete_ssts_removed_on_open() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    // the output of a compaction that never got installed
+    let orphan_id = storage.next_sst_id();
+    generate_sst(
+        orphan_id,
+        storage.path_of_sst(orphan_id),
+        vec![(Bytes::from("key_00000"), Bytes::from("stale"))],
+        None,
+    );
+    drop(storage);
+    assert_eq!(num_sst_files(dir.path()), 2);
+
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    assert_eq!(num_sst_files(dir.path()), 1);
+    assert!(!storage.path_of_sst(orphan_id).exists());
+    assert_eq!(
+        storage.get(b"key_00000").unwrap(),
+        Some(Bytes::from("value"))
+    );
+}
+
+#[test]
+fn test_close_with_timeout_flushes_memtables() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, leveled_options()).unwrap();
+    put_range(&storage.inner, 0, 100, "value");
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.close_with_timeout(Duration::from_secs(10)).unwrap();
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    assert_eq!(
+        storage.get(b"key_00099").unwrap(),
+        Some(Bytes::from("value"))
+    );
+}
+
+#[test]
+fn test_drop_stops_background_jobs() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, leveled_options()).unwrap();
+    let inner = storage.inner.clone();
+    drop(storage);
+    // no dispatcher or worker thread holds the storage anymore
+    assert_eq!(Arc::strong_count(&inner), 1);
+}
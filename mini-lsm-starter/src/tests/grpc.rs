@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+
+use crate::asynk::AsyncLsmStorage;
+use crate::grpc::proto::key_value_client::KeyValueClient;
+use crate::grpc::proto::write_operation::Op;
+use crate::grpc::proto::{
+    BatchWriteRequest, Bound, DeleteRequest, GetRequest, PutRequest, ScanRequest, WriteOperation,
+};
+use crate::grpc::KeyValueService;
+use crate::lsm_storage::LsmStorageOptions;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_grpc_service() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    let storage = Arc::new(AsyncLsmStorage::open(dir.path(), options).await.unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(KeyValueService::new(storage.clone()).into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let mut client = KeyValueClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let put = |key: &str, value: &str| PutRequest {
+        key: key.into(),
+        value: value.into(),
+    };
+    client.put(put("a", "1")).await.unwrap();
+    let found = client
+        .get(GetRequest { key: "a".into() })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(found.found);
+    assert_eq!(found.value, b"1");
+    client
+        .delete(DeleteRequest { key: "a".into() })
+        .await
+        .unwrap();
+    assert!(
+        !client
+            .get(GetRequest { key: "a".into() })
+            .await
+            .unwrap()
+            .into_inner()
+            .found
+    );
+
+    let operations = (0..300)
+        .map(|i| WriteOperation {
+            op: Some(Op::Put(put(&format!("key_{:03}", i), "value"))),
+        })
+        .chain(std::iter::once(WriteOperation {
+            op: Some(Op::Delete(DeleteRequest {
+                key: "key_000".into(),
+            })),
+        }))
+        .collect();
+    client
+        .batch_write(BatchWriteRequest { operations })
+        .await
+        .unwrap();
+    let invalid = BatchWriteRequest {
+        operations: vec![WriteOperation { op: None }],
+    };
+    assert_eq!(
+        client.batch_write(invalid).await.unwrap_err().code(),
+        tonic::Code::InvalidArgument
+    );
+
+    let mut stream = client
+        .scan(ScanRequest {
+            lower: Some(Bound {
+                key: "key_001".into(),
+                inclusive: false,
+            }),
+            upper: None,
+            limit: 200,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    // written after the scan started, not seen by it
+    client.put(put("key_100", "new")).await.unwrap();
+    let mut keys = Vec::new();
+    let mut num_responses = 0;
+    while let Some(response) = stream.message().await.unwrap() {
+        num_responses += 1;
+        for entry in response.entries {
+            assert_eq!(entry.value, b"value");
+            keys.push(String::from_utf8(entry.key).unwrap());
+        }
+    }
+    let expected = (2..202)
+        .map(|i| format!("key_{:03}", i))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+    assert_eq!(num_responses, 2);
+
+    server.abort();
+    storage.close().await.unwrap();
+}
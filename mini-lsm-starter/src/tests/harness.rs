@@ -13,7 +13,7 @@ use crate::{
     },
     iterators::{merge_iterator::MergeIterator, StorageIterator},
     key::{KeySlice, TS_ENABLED},
-    lsm_storage::{BlockCache, LsmStorageInner, LsmStorageState, MiniLsm},
+    lsm_storage::{BlockCache, LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm},
     table::{SsTable, SsTableBuilder, SsTableIterator},
 };
 
@@ -210,6 +210,42 @@ pub fn generate_sst_with_ts(
     builder.build(id, block_cache, path.as_ref()).unwrap()
 }
 
+/// The week 2 test options with the WAL enabled and a simple leveled compaction of 3 levels compacting every two L0
+/// SSTs.
+pub fn simple_leveled_wal_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.enable_wal = true;
+    options
+}
+
+/// The week 2 test options with a leveled compaction of 3 levels compacting every two L0 SSTs, from a base level of
+/// 1MB growing twice as large at every level.
+pub fn leveled_options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 3,
+        },
+    ))
+}
+
+/// Put `value` in the keys from `key_{begin:05}` to `key_{end:05}`, excluded, without flushing them.
+pub fn put_range(storage: &LsmStorageInner, begin: usize, end: usize, value: &str) {
+    for i in begin..end {
+        storage
+            .put(format!("key_{:05}", i).as_bytes(), value.as_bytes())
+            .unwrap();
+    }
+}
+
 pub fn sync(storage: &LsmStorageInner) {
     storage
         .force_freeze_memtable(&storage.state_lock.lock())
@@ -274,14 +310,14 @@ pub fn compaction_bench(storage: Arc<MiniLsm>) {
         expected_key_value_pairs,
     );
 
-    storage.dump_structure();
+    print!("{}", storage.dump_structure());
 
     println!("This test case does not guarantee your compaction algorithm produces a LSM state as expected. It only does minimal checks on the size of the levels. Please use the compaction simulator to check if the compaction is correctly going on.");
 }
 
 pub fn check_compaction_ratio(storage: Arc<MiniLsm>) {
     let state = storage.inner.state.read().clone();
-    let compaction_options = storage.inner.options.compaction_options.clone();
+    let compaction_options = storage.inner.options().compaction_options.clone();
     let mut level_size = Vec::new();
     let l0_sst_num = state.l0_sstables.len();
     for (_, files) in &state.levels {
@@ -306,7 +342,7 @@ pub fn check_compaction_ratio(storage: Arc<MiniLsm>) {
         .num_active_iterators();
     let num_memtables = storage.inner.state.read().imm_memtables.len() + 1;
     match compaction_options {
-        CompactionOptions::NoCompaction => unreachable!(),
+        CompactionOptions::NoCompaction | CompactionOptions::Fifo(_) => unreachable!(),
         CompactionOptions::Simple(SimpleLeveledCompactionOptions {
             size_ratio_percent,
             level0_file_num_compaction_trigger,
@@ -0,0 +1,106 @@
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::fs::{FileSystem, InMemoryFileSystem};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+const DB_PATH: &str = "/mini-lsm-in-memory-test/db";
+
+fn options(fs: &Arc<InMemoryFileSystem>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.target_sst_size = 1 << 12;
+    options.compaction_options = CompactionOptions::Leveled(LeveledCompactionOptions {
+        level0_file_num_compaction_trigger: 2,
+        level_size_multiplier: 2,
+        base_level_size_mb: 1,
+        max_levels: 3,
+    });
+    options.file_system = Some(fs.clone());
+    options
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_engine_runs_in_memory() {
+    let fs = Arc::new(InMemoryFileSystem::new());
+    let storage = MiniLsm::open(DB_PATH, options(&fs)).unwrap();
+    for round in 0..3 {
+        for i in 0..500 {
+            storage
+                .put(&key(i), format!("value_{}_{}", i, round).as_bytes())
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    storage.delete(&key(0)).unwrap();
+    storage.close().unwrap();
+    assert!(fs.total_size() > 0);
+
+    let storage = MiniLsm::open(DB_PATH, options(&fs)).unwrap();
+    assert_eq!(storage.get(&key(0)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key(499)).unwrap(),
+        Some(Bytes::from("value_499_2"))
+    );
+    storage.close().unwrap();
+
+    // the memtable is recovered from its WAL when the storage is not closed
+    let storage = LsmStorageInner::open(DB_PATH, options(&fs)).unwrap();
+    storage.put(b"unflushed", b"1").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = LsmStorageInner::open(DB_PATH, options(&fs)).unwrap();
+    assert_eq!(storage.get(b"unflushed").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(
+        storage.get(&key(1)).unwrap(),
+        Some(Bytes::from("value_1_2"))
+    );
+
+    assert!(!Path::new(DB_PATH).parent().unwrap().exists());
+}
+
+#[test]
+fn test_in_memory_file_operations() {
+    let fs = InMemoryFileSystem::new();
+    let dir = Path::new("/dir");
+    assert_eq!(
+        fs.create(&dir.join("a")).err().map(|e| e.kind()),
+        Some(ErrorKind::NotFound)
+    );
+    fs.create_dir_all(dir).unwrap();
+    let mut file = fs.create(&dir.join("a")).unwrap();
+    file.write_all(b"hello").unwrap();
+    assert!(fs.create_new(&dir.join("a")).is_err());
+
+    let reader = fs.open(&dir.join("a")).unwrap();
+    let mut buf = [0; 4];
+    reader.read_at(&mut buf, 1).unwrap();
+    assert_eq!(&buf, b"ello");
+    assert!(reader.read_at(&mut buf, 2).is_err());
+
+    fs.create(&dir.join("b")).unwrap();
+    fs.rename(&dir.join("a"), &dir.join("b")).unwrap();
+    assert!(!fs.exists(&dir.join("a")));
+    assert_eq!(fs.read(&dir.join("b")).unwrap(), b"hello");
+    fs.create_dir_all(&dir.join("sub")).unwrap();
+    let mut names = fs.list_dir(dir).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["b", "sub"]);
+
+    file.write_all(b" world").unwrap();
+    fs.remove_file(&dir.join("b")).unwrap();
+    assert_eq!(reader.size().unwrap(), 11);
+    assert_eq!(
+        fs.open(&dir.join("b")).err().map(|e| e.kind()),
+        Some(ErrorKind::NotFound)
+    );
+}
@@ -0,0 +1,188 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_lsm_iter_result_by_key, sync};
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionOptions};
+use crate::ingest::SstFileWriter;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options(compaction_options: CompactionOptions) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    options.enable_wal = true;
+    options
+}
+
+fn simple_leveled() -> CompactionOptions {
+    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    })
+}
+
+fn write_external(dir: &Path, name: &str, keys: impl Iterator<Item = usize>) -> PathBuf {
+    let path = dir.join(name);
+    let mut writer = SstFileWriter::create(&path, &options(simple_leveled())).unwrap();
+    for i in keys {
+        writer
+            .put(
+                format!("key_{:03}", i).as_bytes(),
+                format!("external_{}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+fn sst_files(path: &Path) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_str()
+                .unwrap()
+                .ends_with(".sst")
+        })
+        .count()
+}
+
+#[test]
+fn test_sst_file_writer() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("external.sst");
+    let mut writer = SstFileWriter::create(&path, &options(simple_leveled())).unwrap();
+    writer.put(b"a", b"1").unwrap();
+    writer.put(b"c", b"3").unwrap();
+    assert!(writer.put(b"b", b"2").is_err());
+    assert!(writer.put(b"c", b"3").is_err());
+    assert!(writer.put(b"d", b"").is_err());
+    let info = writer.finish().unwrap();
+    assert_eq!(info.path, path);
+    assert_eq!(info.smallest_key, Bytes::from("a"));
+    assert_eq!(info.largest_key, Bytes::from("c"));
+    assert_eq!(info.num_entries, 2);
+    assert_eq!(info.file_size, std::fs::metadata(&path).unwrap().len());
+    assert!(SstFileWriter::create(&path, &options(simple_leveled())).is_err());
+    let empty = SstFileWriter::create(dir.path().join("empty.sst"), &options(simple_leveled()));
+    assert!(empty.unwrap().finish().is_err());
+}
+
+#[test]
+fn test_ingest_external_files() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("db");
+    let storage = LsmStorageInner::open(&db_path, options(simple_leveled())).unwrap();
+    for i in 0..10 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    sync(&storage);
+    storage.put(b"key_050", b"value").unwrap();
+    let first = write_external(dir.path(), "first.sst", 100..150);
+    let second = write_external(dir.path(), "second.sst", 10..50);
+    let snapshot = storage.snapshot();
+    storage.ingest_external_files(&[&first, &second]).unwrap();
+    assert!(first.exists());
+    let state = storage.state.read().clone();
+    assert_eq!(state.levels.last().unwrap().1.len(), 2);
+    assert_eq!(
+        storage.get(b"key_010").unwrap(),
+        Some(Bytes::from("external_10"))
+    );
+    // written below every timestamp
+    assert_eq!(
+        storage.get_with_snapshot(b"key_149", &snapshot).unwrap(),
+        Some(Bytes::from("external_149"))
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(b"key_048"), Bound::Included(b"key_100"))
+            .unwrap(),
+        vec![
+            (Bytes::from("key_048"), Bytes::from("external_48")),
+            (Bytes::from("key_049"), Bytes::from("external_49")),
+            (Bytes::from("key_050"), Bytes::from("value")),
+            (Bytes::from("key_100"), Bytes::from("external_100")),
+        ],
+    );
+    drop(snapshot);
+
+    // overlapping the memtable, the SSTs or each other
+    let num_ssts = sst_files(&db_path);
+    let overlapping = [
+        write_external(dir.path(), "memtable.sst", 50..51),
+        write_external(dir.path(), "sst.sst", 5..6),
+        write_external(dir.path(), "ingested.sst", 120..121),
+    ];
+    for path in &overlapping {
+        assert!(storage.ingest_external_files(&[path]).is_err());
+    }
+    let third = write_external(dir.path(), "third.sst", 200..210);
+    let fourth = write_external(dir.path(), "fourth.sst", 205..220);
+    assert!(storage.ingest_external_files(&[&third, &fourth]).is_err());
+    assert_eq!(sst_files(&db_path), num_ssts);
+
+    // the new SSTs do not take the ids of the ingested ones
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = LsmStorageInner::open(&db_path, options(simple_leveled())).unwrap();
+    assert_eq!(
+        storage.get(b"key_120").unwrap(),
+        Some(Bytes::from("external_120"))
+    );
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    drop(storage);
+    let storage = LsmStorageInner::open(&db_path, options(simple_leveled())).unwrap();
+    for (key, value) in [
+        ("key_000", "value"),
+        ("key_030", "external_30"),
+        ("key_050", "value"),
+        ("key_140", "external_140"),
+    ] {
+        assert_eq!(
+            storage.get(key.as_bytes()).unwrap(),
+            Some(Bytes::from(value))
+        );
+    }
+}
+
+#[test]
+fn test_ingest_external_files_tiered() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("db");
+    let tiered = || {
+        options(CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }))
+    };
+    let storage = LsmStorageInner::open(&db_path, tiered()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put_cf(&cf, b"key_000", b"value").unwrap();
+    sync(&storage);
+    let external = write_external(dir.path(), "external.sst", 1..5);
+    storage.ingest_external_files_cf(&cf, &[&external]).unwrap();
+    let state = storage.column_family_state(cf.id()).unwrap();
+    assert_eq!(state.levels.len(), 2);
+    assert_eq!(state.levels[1].1, vec![state.levels[1].0]);
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&db_path, tiered()).unwrap();
+    let cf = storage.cf("cf").unwrap();
+    assert_eq!(
+        storage.get_cf(&cf, b"key_003").unwrap(),
+        Some(Bytes::from("external_3"))
+    );
+    assert_eq!(storage.get(b"key_003").unwrap(), None);
+}
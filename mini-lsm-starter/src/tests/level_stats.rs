@@ -0,0 +1,98 @@
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[test]
+fn test_leveled_level_stats() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 50, 150, "value");
+    sync(&storage);
+
+    // two L0 SSTs reach the L0 trigger
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.levels.len(), 4);
+    let l0 = &stats.levels[0];
+    assert_eq!(l0.num_files, 2);
+    assert_eq!(l0.pending_compaction_bytes, l0.size_bytes);
+    assert_eq!(l0.counters.num_compactions, 2);
+    assert_eq!(l0.counters.bytes_written, l0.size_bytes);
+    assert!(l0.counters.bytes_read_from_upper_level > 0);
+
+    storage.trigger_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.levels[0].num_files, 0);
+    assert_eq!(stats.levels[0].pending_compaction_bytes, 0);
+    let l3 = &stats.levels[3];
+    assert_eq!(l3.counters.num_compactions, 1);
+    assert_eq!(l3.counters.bytes_read_from_upper_level, l0.size_bytes);
+    assert_eq!(l3.counters.bytes_read_from_level, 0);
+    assert_eq!(l3.counters.bytes_written, l3.size_bytes);
+    assert!(!l3.counters.compaction_time.is_zero());
+    assert!(l3.write_amplification() > 0.0);
+    assert_eq!(l3.read_amplification(), 1.0);
+    assert!(stats.write_amplification() > 1.0);
+
+    // a compaction of a non-overlapping SST moves it
+    put_range(&storage, 200, 300, "value");
+    sync(&storage);
+    put_range(&storage, 300, 400, "value");
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    let moved = &stats.levels[3].counters;
+    assert_eq!(moved.num_compactions, 2);
+    assert_eq!(moved.bytes_written, l3.counters.bytes_written);
+    assert!(moved.bytes_moved > 0);
+
+    let formatted = storage.level_stats();
+    let lines = formatted.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 6);
+    assert!(lines[0].contains("W-Amp"));
+    assert!(lines[1].trim_start().starts_with("L0"));
+    assert!(lines[4].trim_start().starts_with("L3"));
+    assert!(lines[5].trim_start().starts_with("Sum"));
+}
+
+#[test]
+fn test_universal_level_stats() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+            TieredCompactionOptions {
+                num_tiers: 3,
+                max_size_amplification_percent: 200,
+                size_ratio: 1,
+                min_merge_width: 2,
+            },
+        )),
+    )
+    .unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+
+    // flushes create tiers, counted against L0
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.levels.len(), 4);
+    assert_eq!(stats.levels[0].counters.num_compactions, 3);
+    assert!(stats.levels[1..].iter().all(|level| level.num_files == 1));
+    assert!(stats.levels[1].pending_compaction_bytes > 0);
+
+    storage.trigger_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.levels.len(), 2);
+    let tier = &stats.levels[1];
+    assert_eq!(tier.counters.num_compactions, 1);
+    assert!(tier.counters.bytes_read_from_upper_level > 0);
+    assert!(tier.counters.bytes_read_from_level > 0);
+    assert_eq!(tier.counters.bytes_written, tier.size_bytes);
+}
@@ -0,0 +1,77 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn expected_value(i: usize) -> Option<String> {
+    if (1000..1100).contains(&i) && i.is_multiple_of(5) {
+        None
+    } else if i < 300 && i.is_multiple_of(3) {
+        Some(format!("new_{}", i))
+    } else {
+        Some(format!("value_{:040}", i))
+    }
+}
+
+#[test]
+fn test_import_leveldb() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("leveldb");
+    let mut leveldb_options = rusty_leveldb::Options {
+        write_buffer_size: 8192,
+        block_size: 512,
+        ..Default::default()
+    };
+    leveldb_options.compressor = 1;
+    let mut db = rusty_leveldb::DB::open(&source, leveldb_options).unwrap();
+    for i in 0..2000 {
+        db.put(
+            format!("key_{:04}", i).as_bytes(),
+            format!("value_{:040}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    db.compact_range(b"key_0000", b"key_9999").unwrap();
+    // left in the WAL
+    for i in (0..300).step_by(3) {
+        db.put(
+            format!("key_{:04}", i).as_bytes(),
+            format!("new_{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    for i in (1000..1100).step_by(5) {
+        db.delete(format!("key_{:04}", i).as_bytes()).unwrap();
+    }
+    db.close().unwrap();
+    drop(db);
+
+    let path = dir.path().join("imported");
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let summary = MiniLsm::import_leveldb(&source, &path, options.clone()).unwrap();
+    assert!(summary.num_source_ssts > 0);
+    assert!(summary.num_wal_batches > 0);
+    assert_eq!(summary.num_keys, 1980);
+    assert!(summary.num_ssts > 0);
+    assert!(MiniLsm::import_leveldb(&source, &path, options.clone()).is_err());
+
+    let storage = MiniLsm::open(&path, options).unwrap();
+    for i in 0..2000 {
+        let value = storage.get(format!("key_{:04}", i).as_bytes()).unwrap();
+        assert_eq!(
+            value.as_deref(),
+            expected_value(i).as_deref().map(str::as_bytes)
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut num_keys = 0;
+    while iter.is_valid() {
+        num_keys += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(num_keys, 1980);
+    storage.close().unwrap();
+}
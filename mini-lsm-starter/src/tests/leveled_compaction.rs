@@ -0,0 +1,113 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_compaction_ratio, compaction_bench, generate_sst};
+use crate::compact::{CompactionOptions, LeveledCompactionController, LeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm};
+
+fn leveled_options(max_levels: usize) -> LeveledCompactionOptions {
+    LeveledCompactionOptions {
+        level0_file_num_compaction_trigger: 2,
+        level_size_multiplier: 2,
+        base_level_size_mb: 1,
+        max_levels,
+    }
+}
+
+fn key_range(begin: usize, end: usize, value_size: usize) -> Vec<(Bytes, Bytes)> {
+    (begin..end)
+        .map(|i| {
+            (
+                Bytes::from(format!("key_{:05}", i)),
+                Bytes::from(vec![b'v'; value_size]),
+            )
+        })
+        .collect()
+}
+
+/// Add an SST holding `data` to `level`, 0 being L0.
+fn add_sst(
+    state: &mut LsmStorageState,
+    dir: &tempfile::TempDir,
+    id: usize,
+    level: usize,
+    data: Vec<(Bytes, Bytes)>,
+) {
+    let sst = generate_sst(id, dir.path().join(format!("{id}.sst")), data, None);
+    state.sstables.insert(id, sst.into());
+    if level == 0 {
+        state.l0_sstables.insert(0, id);
+    } else {
+        state.levels[level - 1].1.push(id);
+    }
+}
+
+fn empty_state(dir: &tempfile::TempDir, max_levels: usize) -> LsmStorageState {
+    let storage = LsmStorageInner::open(
+        dir.path().join("db"),
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(leveled_options(
+            max_levels,
+        ))),
+    )
+    .unwrap();
+    let state = storage.state.read().as_ref().clone();
+    state
+}
+
+#[test]
+fn test_integration() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(leveled_options(4))),
+    )
+    .unwrap();
+
+    compaction_bench(storage.clone());
+    check_compaction_ratio(storage.clone());
+}
+
+#[test]
+fn test_l0_compacts_into_dynamic_base_level() {
+    let dir = tempdir().unwrap();
+    let controller = LeveledCompactionController::new(leveled_options(4));
+    let mut state = empty_state(&dir, 4);
+    add_sst(&mut state, &dir, 1, 0, key_range(0, 10, 16));
+    add_sst(&mut state, &dir, 2, 0, key_range(5, 15, 16));
+    add_sst(&mut state, &dir, 3, 4, key_range(0, 8, 16));
+    add_sst(&mut state, &dir, 4, 4, key_range(100, 110, 16));
+
+    // the bottommost level is below the base level size, so L0 goes straight to it
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.upper_level_sst_ids, vec![2, 1]);
+    assert_eq!(task.lower_level, 4);
+    assert_eq!(task.lower_level_sst_ids, vec![3]);
+    assert!(task.is_lower_level_bottom_level);
+
+    add_sst(&mut state, &dir, 5, 0, key_range(0, 15, 16));
+    let (state, files_to_remove) = controller.apply_compaction_result(&state, &task, &[5]);
+    assert_eq!(files_to_remove, vec![2, 1, 3]);
+    // the L0 SST flushed while compacting stays
+    assert_eq!(state.l0_sstables, vec![5]);
+    assert_eq!(state.levels[3].1, vec![5, 4]);
+}
+
+#[test]
+fn test_pick_sst_with_min_overlap_ratio() {
+    let dir = tempdir().unwrap();
+    let controller = LeveledCompactionController::new(leveled_options(2));
+    let mut state = empty_state(&dir, 2);
+    // L1 is above the base level and both of its SSTs are to be moved down; the oldest one overlaps a large
+    // SST in L2 while the newest overlaps nothing
+    add_sst(&mut state, &dir, 1, 1, key_range(0, 10, 16));
+    add_sst(&mut state, &dir, 2, 1, key_range(100, 110, 16));
+    add_sst(&mut state, &dir, 3, 2, key_range(0, 50, 1024));
+
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(1));
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert_eq!(task.lower_level, 2);
+    assert!(task.lower_level_sst_ids.is_empty());
+    assert!(task.is_lower_level_bottom_level);
+}
@@ -0,0 +1,58 @@
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+
+#[test]
+fn test_live_files() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_wal_options()).unwrap();
+    let cf = storage.create_cf("cf").unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let first_ts = storage.mvcc().latest_commit_ts();
+    storage.put(b"c", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let last_ts = storage.mvcc().latest_commit_ts();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    storage.put(b"d", b"1").unwrap();
+    storage.put_cf(&cf, b"e", b"1").unwrap();
+    sync(&storage);
+    storage.put(b"unflushed", b"1").unwrap();
+
+    let live_files = storage.live_files().unwrap();
+    assert_eq!(
+        live_files
+            .ssts
+            .iter()
+            .map(|sst| sst.sst_id)
+            .collect::<BTreeSet<_>>(),
+        storage.live_sst_ids()
+    );
+    for sst in &live_files.ssts {
+        assert_eq!(std::fs::metadata(&sst.path).unwrap().len(), sst.size);
+        assert!(sst.min_ts <= sst.max_ts);
+        assert!(sst.file_checksum.is_some());
+    }
+    let compacted = live_files.ssts.iter().find(|sst| sst.level == 1).unwrap();
+    assert_eq!(compacted.column_family, "default");
+    assert_eq!(compacted.smallest_key, Bytes::from("a"));
+    assert_eq!(compacted.largest_key, Bytes::from("c"));
+    assert_eq!((compacted.min_ts, compacted.max_ts), (first_ts, last_ts));
+    let cf_sst = live_files
+        .ssts
+        .iter()
+        .find(|sst| sst.column_family == "cf")
+        .unwrap();
+    assert_eq!(cf_sst.level, 0);
+    assert_eq!(cf_sst.smallest_key, Bytes::from("e"));
+
+    let current = std::fs::read_to_string(dir.path().join("CURRENT")).unwrap();
+    assert_eq!(live_files.manifest, dir.path().join(current.trim_end()));
+    assert!(std::fs::metadata(&live_files.manifest).unwrap().len() >= live_files.manifest_size);
+    assert!(!live_files.wals.is_empty());
+    assert!(live_files.wals.iter().all(|wal| wal.exists()));
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+
+/// Keeps the level and message of every record.
+#[derive(Debug, Default)]
+struct RecordingLog(Mutex<Vec<(Level, String)>>);
+
+impl Log for RecordingLog {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        self.0
+            .lock()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn test_info_log() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_leveled_wal_options();
+    let info_log = Arc::new(RecordingLog::default());
+    options.info_log = Some(info_log.clone());
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    sync(&storage);
+    let records = info_log.0.lock().clone();
+    assert!(
+        records
+            .iter()
+            .any(|(level, message)| *level == Level::Info && message.starts_with("flushed ")),
+        "{:?}",
+        records
+    );
+
+    // silenced, except for the change of the level itself
+    storage.set_options(&[("info_log_level", "off")]).unwrap();
+    let num_records = info_log.0.lock().len();
+    storage.put(b"b", b"1").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(info_log.0.lock().len(), num_records);
+
+    storage.set_options(&[("info_log_level", "warn")]).unwrap();
+    sync(&storage);
+    assert_eq!(info_log.0.lock().len(), num_records);
+    assert!(storage.set_options(&[("info_log_level", "loud")]).is_err());
+    drop(storage);
+
+    options.info_log_level = LevelFilter::Debug;
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    let records = info_log.0.lock()[num_records..].to_vec();
+    assert!(
+        records
+            .iter()
+            .any(|(_, message)| message.ends_with(" SSTs opened")),
+        "{:?}",
+        records
+    );
+    assert_eq!(storage.options().info_log_level, LevelFilter::Debug);
+}
+
+#[test]
+fn test_info_log_of_compaction_controller() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    let info_log = Arc::new(RecordingLog::default());
+    options.info_log = Some(info_log.clone());
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    for key in [b"a", b"b"] {
+        storage.put(key, b"1").unwrap();
+        sync(&storage);
+    }
+    let snapshot = storage.state.read().clone();
+    assert!(storage
+        .compaction_controller()
+        .generate_compaction_task(&snapshot)
+        .is_some());
+    let records = info_log.0.lock().clone();
+    assert!(
+        records
+            .iter()
+            .any(|(_, message)| message.starts_with("compact L0 SSTs to base level")),
+        "{:?}",
+        records
+    );
+}
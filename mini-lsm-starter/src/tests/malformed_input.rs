@@ -0,0 +1,66 @@
+use crate::block::{Block, BlockBuilder};
+use crate::key::KeySlice;
+use crate::table::BlockMeta;
+
+#[test]
+fn test_malformed_block_fails_to_decode() {
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(
+        KeySlice::for_testing_from_slice_with_ts(b"key", 1),
+        b"value"
+    ));
+    assert!(builder.add(
+        KeySlice::for_testing_from_slice_with_ts(b"key2", 1),
+        b"value"
+    ));
+    let encoded = builder.build().encode();
+    assert!(Block::decode(&encoded).is_ok());
+
+    assert!(Block::decode(&[]).is_err());
+    // no entry
+    assert!(Block::decode(&[0]).is_err());
+    // more offsets than bytes
+    assert!(Block::decode(&[0, 0, 200]).is_err());
+    for len in 1..encoded.len() {
+        assert!(
+            Block::decode(&encoded[encoded.len() - len..]).is_err(),
+            "{}",
+            len
+        );
+    }
+    // a value length pointing past the block
+    let mut corrupted = encoded.to_vec();
+    corrupted[2 + 3 + 8] = 0xff;
+    assert!(Block::decode(&corrupted).is_err());
+    // a second entry starting before the first one
+    let mut corrupted = encoded.to_vec();
+    let offsets_start = corrupted.len() - 5;
+    corrupted[offsets_start + 2..offsets_start + 4].copy_from_slice(&[0, 0]);
+    assert!(Block::decode(&corrupted).is_err());
+}
+
+#[test]
+fn test_truncated_block_meta_fails_to_decode() {
+    let meta = BlockMeta {
+        offset: 0,
+        first_key: KeySlice::for_testing_from_slice_with_ts(b"a", 1)
+            .to_key_vec()
+            .into_key_bytes(),
+        last_key: KeySlice::for_testing_from_slice_with_ts(b"b", 1)
+            .to_key_vec()
+            .into_key_bytes(),
+    };
+    let mut encoded = Vec::new();
+    BlockMeta::encode_block_meta(std::slice::from_ref(&meta), &mut encoded);
+    assert_eq!(BlockMeta::decode_block_meta(&encoded).unwrap(), vec![meta]);
+
+    // the number of blocks is not covered by the checksum
+    encoded[3] = 2;
+    assert!(BlockMeta::decode_block_meta(&encoded).is_err());
+    // cut short, with a matching checksum
+    encoded[3] = 1;
+    let truncated = &encoded[..encoded.len() - 8];
+    let mut buf = truncated.to_vec();
+    buf.extend(crc32fast::hash(&truncated[4..]).to_be_bytes());
+    assert!(BlockMeta::decode_block_meta(&buf).is_err());
+}
@@ -0,0 +1,101 @@
+use std::fs::OpenOptions;
+
+use tempfile::tempdir;
+
+use crate::fs::default_file_system;
+use crate::logger::DbLogger;
+use crate::manifest::{Manifest, ManifestRecord};
+
+fn flushed_ids(records: &[ManifestRecord]) -> Vec<usize> {
+    records
+        .iter()
+        .map(|record| match record {
+            ManifestRecord::Flush(sst_id) => *sst_id,
+            _ => panic!("unexpected record"),
+        })
+        .collect()
+}
+
+/// Write a manifest of `n` flush records, and return the file size after each record.
+fn write_manifest(path: &std::path::Path, n: usize) -> Vec<u64> {
+    let manifest = Manifest::create(&default_file_system(), path).unwrap();
+    (0..n)
+        .map(|sst_id| {
+            manifest
+                .add_record_when_init(ManifestRecord::Flush(sst_id))
+                .unwrap();
+            std::fs::metadata(path).unwrap().len()
+        })
+        .collect()
+}
+
+fn flip_bit(path: &std::path::Path, offset: u64) {
+    let mut data = std::fs::read(path).unwrap();
+    data[offset as usize] ^= 0x10;
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn test_manifest_truncated_at_torn_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let sizes = write_manifest(&path, 3);
+    let record_begin = sizes[1];
+
+    // cut anywhere in the last record, including its header
+    for len in record_begin..sizes[2] {
+        write_manifest(&dir.path().join(format!("MANIFEST.{len}")), 3);
+        let path = dir.path().join(format!("MANIFEST.{len}"));
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+        let (manifest, records) =
+            Manifest::recover(&default_file_system(), &path, &DbLogger::default()).unwrap();
+        assert_eq!(flushed_ids(&records), vec![0, 1]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), record_begin);
+
+        // new records go after the truncated one
+        manifest
+            .add_record_when_init(ManifestRecord::Flush(10))
+            .unwrap();
+        drop(manifest);
+        let (_, records) =
+            Manifest::recover(&default_file_system(), &path, &DbLogger::default()).unwrap();
+        assert_eq!(flushed_ids(&records), vec![0, 1, 10]);
+    }
+}
+
+#[test]
+fn test_manifest_corrupted_last_record_dropped() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let sizes = write_manifest(&path, 3);
+    // a torn write may leave garbage instead of the record, in its payload or in its header
+    for offset in [sizes[2] - 2, sizes[1] + 1] {
+        let path = dir.path().join(format!("MANIFEST.{offset}"));
+        write_manifest(&path, 3);
+        flip_bit(&path, offset);
+        let (_, records) =
+            Manifest::recover(&default_file_system(), &path, &DbLogger::default()).unwrap();
+        assert_eq!(flushed_ids(&records), vec![0, 1]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), sizes[1]);
+    }
+}
+
+#[test]
+fn test_manifest_corruption_before_valid_records() {
+    let dir = tempdir().unwrap();
+    let sizes = write_manifest(&dir.path().join("MANIFEST"), 3);
+    // a flipped bit in the payload of the first record, and in the length of the second one
+    for offset in [sizes[0] - 6, sizes[0] + 2] {
+        let path = dir.path().join(format!("MANIFEST.{offset}"));
+        write_manifest(&path, 3);
+        flip_bit(&path, offset);
+        assert!(Manifest::recover(&default_file_system(), &path, &DbLogger::default()).is_err());
+        // nothing was truncated
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), sizes[2]);
+    }
+}
@@ -0,0 +1,97 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::fs::LocalFileSystem;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::manifest::{manifest_file_name, read_current};
+
+fn open(path: &std::path::Path) -> anyhow::Result<LsmStorageInner> {
+    LsmStorageInner::open(path, LsmStorageOptions::default_for_week1_test())
+}
+
+/// Create a database holding one flushed SST.
+fn create_database(path: &std::path::Path) {
+    let storage = open(path).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    sync(&storage);
+}
+
+#[test]
+fn test_crash_while_creating_database() {
+    let dir = tempdir().unwrap();
+    // the first manifest was being written, CURRENT was not
+    std::fs::write(dir.path().join(manifest_file_name(1)), b"\x00\x00\x01").unwrap();
+    std::fs::write(dir.path().join("CURRENT.tmp"), b"MANIF").unwrap();
+
+    let storage = open(dir.path()).unwrap();
+    assert_eq!(
+        read_current(&LocalFileSystem, dir.path()).unwrap(),
+        Some(manifest_file_name(1))
+    );
+    assert!(!dir.path().join("CURRENT.tmp").exists());
+    storage.put(b"key", b"value").unwrap();
+    sync(&storage);
+    drop(storage);
+    assert_eq!(
+        open(dir.path()).unwrap().get(b"key").unwrap(),
+        Some(Bytes::from("value"))
+    );
+}
+
+#[test]
+fn test_crash_while_rolling_manifest_over() {
+    let dir = tempdir().unwrap();
+    create_database(dir.path());
+    // the next manifest was being written, CURRENT still names the first one
+    std::fs::write(dir.path().join(manifest_file_name(2)), b"garbage").unwrap();
+
+    let storage = open(dir.path()).unwrap();
+    assert!(!dir.path().join(manifest_file_name(2)).exists());
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("value")));
+}
+
+#[test]
+fn test_legacy_manifest_gets_current() {
+    let dir = tempdir().unwrap();
+    create_database(dir.path());
+    std::fs::rename(
+        dir.path().join(manifest_file_name(1)),
+        dir.path().join("MANIFEST"),
+    )
+    .unwrap();
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+
+    let storage = open(dir.path()).unwrap();
+    assert_eq!(
+        read_current(&LocalFileSystem, dir.path()).unwrap(),
+        Some("MANIFEST".to_string())
+    );
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("value")));
+}
+
+#[test]
+fn test_invalid_current_rejected() {
+    let dir = tempdir().unwrap();
+    create_database(dir.path());
+    let current = std::fs::read(dir.path().join("CURRENT")).unwrap();
+
+    // an unterminated name, a file that is not a manifest, a missing manifest
+    for content in [&b"MANIFEST-0000"[..], b"00001.sst\n", b"MANIFEST-000007\n"] {
+        std::fs::write(dir.path().join("CURRENT"), content).unwrap();
+        assert!(open(dir.path()).is_err());
+        // nothing is deleted
+        assert!(dir.path().join(manifest_file_name(1)).exists());
+    }
+
+    // without CURRENT the SSTs would be lost by creating a new database
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    assert!(open(dir.path()).is_err());
+    assert!(dir.path().join(manifest_file_name(1)).exists());
+
+    std::fs::write(dir.path().join("CURRENT"), current).unwrap();
+    assert_eq!(
+        open(dir.path()).unwrap().get(b"key").unwrap(),
+        Some(Bytes::from("value"))
+    );
+}
@@ -0,0 +1,108 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::fs::LocalFileSystem;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::manifest::{manifest_file_name, read_current};
+
+fn small_manifest_options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.max_manifest_file_size = 512;
+    options
+}
+
+fn manifest_files(path: &std::path::Path) -> Vec<String> {
+    let mut names = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("MANIFEST"))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Flush and compact enough times for the manifest to be rolled over several times.
+fn write_rounds(storage: &LsmStorageInner, rounds: usize) {
+    for round in 0..rounds {
+        put_range(
+            storage,
+            round * 10,
+            round * 10 + 100,
+            &format!("value_{round}"),
+        );
+        sync(storage);
+        storage.trigger_compaction().unwrap();
+    }
+}
+
+#[test]
+fn test_manifest_rolled_over() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, small_manifest_options()).unwrap();
+    assert_eq!(manifest_files(dir.path()), vec![manifest_file_name(1)]);
+    write_rounds(&storage, 10);
+
+    // only the manifest in use is left, and it stays small
+    let current = read_current(&LocalFileSystem, dir.path()).unwrap().unwrap();
+    assert_ne!(current, manifest_file_name(1));
+    assert_eq!(manifest_files(dir.path()), vec![current.clone()]);
+    let size = std::fs::metadata(dir.path().join(&current)).unwrap().len();
+    assert!(size < 2048, "manifest is {} bytes", size);
+
+    let state = storage.state.read().clone();
+    drop(storage);
+    let storage = LsmStorageInner::open(&dir, small_manifest_options()).unwrap();
+    {
+        let recovered = storage.state.read();
+        assert_eq!(recovered.l0_sstables, state.l0_sstables);
+        assert_eq!(recovered.levels, state.levels);
+    }
+    assert_eq!(
+        storage.get(b"key_00000").unwrap(),
+        Some(Bytes::from("value_0"))
+    );
+    assert_eq!(
+        storage.get(b"key_00185").unwrap(),
+        Some(Bytes::from("value_9"))
+    );
+    // SST ids are not reused after recovering from a snapshot
+    let max_sst_id = *state.sstables.keys().max().unwrap();
+    assert!(storage.next_sst_id() > max_sst_id);
+}
+
+#[test]
+fn test_legacy_manifest_rolled_over() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    put_range(&storage, 0, 100, "value_0");
+    sync(&storage);
+    drop(storage);
+    // the layout of the databases created before the manifest was rolled over
+    std::fs::rename(
+        dir.path().join(manifest_file_name(1)),
+        dir.path().join("MANIFEST"),
+    )
+    .unwrap();
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+
+    let storage = LsmStorageInner::open(&dir, small_manifest_options()).unwrap();
+    assert_eq!(
+        storage.get(b"key_00050").unwrap(),
+        Some(Bytes::from("value_0"))
+    );
+    write_rounds(&storage, 5);
+    let current = read_current(&LocalFileSystem, dir.path()).unwrap().unwrap();
+    assert_eq!(manifest_files(dir.path()), vec![current]);
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, small_manifest_options()).unwrap();
+    assert_eq!(
+        storage.get(b"key_00000").unwrap(),
+        Some(Bytes::from("value_0"))
+    );
+    assert_eq!(
+        storage.get(b"key_00139").unwrap(),
+        Some(Bytes::from("value_4"))
+    );
+}
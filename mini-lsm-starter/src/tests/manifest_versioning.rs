@@ -0,0 +1,110 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::fs::{default_file_system, LocalFileSystem};
+use crate::logger::DbLogger;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::manifest::{encode_frame, read_current, Manifest, ManifestRecord};
+
+fn current_manifest_path(dir: &Path) -> PathBuf {
+    dir.join(read_current(&LocalFileSystem, dir).unwrap().unwrap())
+}
+
+/// Append a record with the raw `json` to the manifest in use.
+fn append_record(dir: &Path, json: &str) {
+    let mut buf = Vec::new();
+    encode_frame(json.as_bytes(), &mut buf);
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(current_manifest_path(dir))
+        .unwrap()
+        .write_all(&buf)
+        .unwrap();
+}
+
+fn open(dir: &Path) -> anyhow::Result<LsmStorageInner> {
+    LsmStorageInner::open(dir, LsmStorageOptions::default_for_week1_test())
+}
+
+/// A database with `key` flushed to an SST.
+fn create(dir: &Path) {
+    let storage = open(dir).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    sync(&storage);
+}
+
+#[test]
+fn test_records_tagged() {
+    let dir = tempdir().unwrap();
+    create(dir.path());
+    let (_, records) = Manifest::recover(
+        &default_file_system(),
+        current_manifest_path(dir.path()),
+        &DbLogger::default(),
+    )
+    .unwrap();
+    assert!(records
+        .iter()
+        .any(|record| matches!(record, ManifestRecord::Flush(_))));
+    let content =
+        String::from_utf8_lossy(&std::fs::read(current_manifest_path(dir.path())).unwrap())
+            .to_string();
+    assert!(content.contains(r#"{"tag":1,"version":1,"ignorable":false,"data":"#));
+    assert!(content.contains(r#"{"tag":6,"version":1,"ignorable":true,"data":"#));
+}
+
+#[test]
+fn test_unknown_ignorable_records_skipped() {
+    let dir = tempdir().unwrap();
+    create(dir.path());
+    append_record(
+        dir.path(),
+        r#"{"tag":1000,"version":1,"ignorable":true,"data":{"blob_files":[1,2]}}"#,
+    );
+    // a newer version of an ignorable record
+    append_record(
+        dir.path(),
+        r#"{"tag":6,"version":2,"ignorable":true,"data":{"checksums":[]}}"#,
+    );
+    // records written before they were tagged
+    append_record(dir.path(), r#"{"NewMemtable":20}"#);
+
+    let storage = open(dir.path()).unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("value")));
+    assert!(storage.next_sst_id() > 20);
+}
+
+#[test]
+fn test_unknown_records_rejected() {
+    for (json, error) in [
+        (
+            r#"{"tag":1000,"version":1,"ignorable":false,"data":null}"#,
+            "unknown manifest record type 1000",
+        ),
+        (
+            r#"{"tag":1,"version":2,"ignorable":false,"data":1}"#,
+            "manifest record Flush of version 2 is newer than the supported version 1",
+        ),
+    ] {
+        let dir = tempdir().unwrap();
+        create(dir.path());
+        append_record(dir.path(), json);
+        let size = std::fs::metadata(current_manifest_path(dir.path()))
+            .unwrap()
+            .len();
+
+        let e = format!("{:#}", open(dir.path()).err().unwrap());
+        assert!(e.contains(error), "{}", e);
+        // not mistaken for a torn write
+        assert_eq!(
+            std::fs::metadata(current_manifest_path(dir.path()))
+                .unwrap()
+                .len(),
+            size
+        );
+    }
+}
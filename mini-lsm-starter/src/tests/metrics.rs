@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+use crate::statistics::Statistics;
+
+fn sample(page: &str, name: &str) -> f64 {
+    page.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no sample {} in\n{}", name, page))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_prometheus_metrics() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_leveled_wal_options();
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    sync(&storage);
+    storage.get(b"a").unwrap();
+    let page = storage.prometheus_metrics();
+    assert_eq!(sample(&page, "mini_lsm_level_files{level=\"0\"}"), 1.0);
+    assert!(sample(&page, "mini_lsm_level_bytes{level=\"0\"}") > 0.0);
+    assert_eq!(sample(&page, "mini_lsm_level_files{level=\"1\"}"), 0.0);
+    assert_eq!(
+        sample(&page, "mini_lsm_pending_compaction_bytes{level=\"0\"}"),
+        0.0
+    );
+    assert_eq!(sample(&page, "mini_lsm_immutable_memtables"), 0.0);
+    assert_eq!(
+        sample(&page, "mini_lsm_block_cache_lookups_total{result=\"miss\"}"),
+        1.0
+    );
+    assert_eq!(sample(&page, "mini_lsm_block_cache_hit_ratio"), 0.0);
+    assert!(page.contains("# TYPE mini_lsm_level_files gauge\n"));
+    // without the statistics option
+    assert!(!page.contains("wal_synced"));
+    drop(storage);
+
+    let statistics = Arc::new(Statistics::new());
+    options.statistics = Some(statistics);
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.sync().unwrap();
+    let page = storage.prometheus_metrics();
+    assert_eq!(sample(&page, "mini_lsm_wal_synced_total"), 1.0);
+    assert_eq!(sample(&page, "mini_lsm_number_keys_written_total"), 1.0);
+    assert!(page.contains("# TYPE mini_lsm_wal_file_sync_micros summary\n"));
+    assert!(sample(&page, "mini_lsm_wal_file_sync_micros{quantile=\"0.99\"}") >= 0.0);
+    assert_eq!(sample(&page, "mini_lsm_wal_file_sync_micros_count"), 1.0);
+    assert_eq!(sample(&page, "mini_lsm_stall_micros_total"), 0.0);
+    // every sample follows the declaration of its metric
+    let mut declared = Vec::new();
+    for line in page.lines() {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            declared.push(declaration.split(' ').next().unwrap().to_string());
+        } else if !line.starts_with('#') {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(
+                declared
+                    .iter()
+                    .any(|metric| name.starts_with(metric.as_str())),
+                "{}",
+                line
+            );
+        }
+    }
+}
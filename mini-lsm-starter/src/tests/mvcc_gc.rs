@@ -0,0 +1,49 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+/// Number of entries in all SSTs.
+fn num_entries(storage: &LsmStorageInner) -> u64 {
+    let state = storage.state.read();
+    state
+        .sstables
+        .values()
+        .map(|sst| sst.properties().num_entries)
+        .sum()
+}
+
+#[test]
+fn test_compaction_collects_versions_below_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    sync(&storage);
+    let snapshot = storage.snapshot();
+    storage.put(b"a", b"3").unwrap();
+    storage.delete(b"b").unwrap();
+    sync(&storage);
+    assert_eq!(num_entries(&storage), 5);
+
+    // a=1 is shadowed at the watermark, the other versions are still visible to the snapshot or to new reads
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_entries(&storage), 4);
+    assert_eq!(
+        storage.get_with_snapshot(b"a", &snapshot).unwrap(),
+        Some(Bytes::from_static(b"2"))
+    );
+    assert_eq!(
+        storage.get_with_snapshot(b"b", &snapshot).unwrap(),
+        Some(Bytes::from_static(b"1"))
+    );
+
+    // without readers, only a=3 is left: the tombstone has nothing left to hide in the bottom level
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_entries(&storage), 1);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"3")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
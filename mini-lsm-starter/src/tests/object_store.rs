@@ -0,0 +1,116 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use object_store::memory::InMemory;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::fs::{FileSystem, LocalFileSystem, ObjectStoreFileSystem};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn local_ssts(path: &Path) -> Vec<String> {
+    let mut ssts = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".sst"))
+        .collect::<Vec<_>>();
+    ssts.sort();
+    ssts
+}
+
+fn stored_ssts(store: &InMemory) -> Vec<String> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listing = runtime
+        .block_on(store.list_with_delimiter(Some(&ObjectPath::from("db"))))
+        .unwrap();
+    let mut ssts = listing
+        .objects
+        .iter()
+        .map(|object| object.location.filename().unwrap().to_string())
+        .collect::<Vec<_>>();
+    ssts.sort();
+    ssts
+}
+
+#[test]
+fn test_cold_bottom_level_in_object_store() {
+    let dir = tempdir().unwrap();
+    let store = Arc::new(InMemory::new());
+    let fs = Arc::new(
+        ObjectStoreFileSystem::new(
+            Arc::new(LocalFileSystem),
+            dir.path(),
+            store.clone(),
+            ObjectPath::from("db"),
+        )
+        .unwrap(),
+    );
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.file_system = Some(fs.clone());
+    options.cold_bottom_level = true;
+    let storage = MiniLsm::open(dir.path(), options.clone()).unwrap();
+    for i in 0..500 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"old")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in 0..500 {
+        if i % 2 == 0 {
+            storage
+                .put(format!("key_{:03}", i).as_bytes(), b"new")
+                .unwrap();
+        }
+    }
+    storage.force_flush().unwrap();
+    assert_eq!(local_ssts(dir.path()).len(), 2);
+    assert!(stored_ssts(&store).is_empty());
+
+    // the output of the compaction is uploaded and its local copy removed, and its input removed
+    storage.force_full_compaction().unwrap();
+    let cold = stored_ssts(&store);
+    assert!(!cold.is_empty());
+    assert!(local_ssts(dir.path()).is_empty());
+    for name in &cold {
+        assert!(fs.exists(&dir.path().join(name)));
+        assert!(fs.list_dir(dir.path()).unwrap().contains(name));
+    }
+    // read back through range GETs
+    for i in 0..500 {
+        let expected: &[u8] = if i % 2 == 0 { b"new" } else { b"old" };
+        let value = storage.get(format!("key_{:03}", i).as_bytes()).unwrap();
+        assert_eq!(value.as_deref(), Some(expected));
+    }
+    storage.put(b"key_000", b"newest").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(local_ssts(dir.path()).len(), 1);
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    assert_eq!(
+        storage.get(b"key_000").unwrap().as_deref(),
+        Some(&b"newest"[..])
+    );
+    assert_eq!(
+        storage.get(b"key_499").unwrap().as_deref(),
+        Some(&b"old"[..])
+    );
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut num_keys = 0;
+    while iter.is_valid() {
+        num_keys += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(num_keys, 500);
+    // compacting the cold SSTs again deletes their objects
+    storage.force_full_compaction().unwrap();
+    let recompacted = stored_ssts(&store);
+    assert!(cold.iter().all(|name| !recompacted.contains(name)));
+    assert!(local_ssts(dir.path()).is_empty());
+    storage.close().unwrap();
+}
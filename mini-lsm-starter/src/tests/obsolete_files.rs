@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+/// Leave an SST the manifest does not know about, as a crash before recording it does.
+fn write_orphan(path: &std::path::Path, sst_id: usize) -> std::path::PathBuf {
+    let orphan = LsmStorageInner::path_of_sst_static(path, sst_id);
+    std::fs::write(&orphan, b"orphan").unwrap();
+    orphan
+}
+
+#[test]
+fn test_delete_obsolete_files() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    sync(&storage);
+    let live = storage.state.read().l0_sstables[0];
+    let orphan = write_orphan(dir.path(), 100);
+
+    // a running compaction may be writing it
+    {
+        let _compaction_lock = storage.compaction_lock.lock();
+        assert!(storage.delete_obsolete_files().unwrap().is_empty());
+        assert!(orphan.exists());
+    }
+
+    assert_eq!(storage.delete_obsolete_files().unwrap(), vec![100]);
+    assert!(!orphan.exists());
+    assert!(LsmStorageInner::path_of_sst_static(&dir, live).exists());
+    assert!(storage.delete_obsolete_files().unwrap().is_empty());
+}
+
+#[test]
+fn test_obsolete_files_deleted_periodically() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.delete_obsolete_files_period_seconds = 1;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let orphan = write_orphan(dir.path(), 100);
+
+    let started = Instant::now();
+    while orphan.exists() {
+        assert!(started.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    storage.close().unwrap();
+}
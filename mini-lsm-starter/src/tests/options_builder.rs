@@ -0,0 +1,77 @@
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, SecondaryCacheOptions};
+use crate::mvcc::txn::ConflictGranularity;
+use crate::table::CompressionType;
+
+#[test]
+fn test_builder_sets_options() {
+    let options = LsmStorageOptions::builder()
+        .block_size(8192)
+        .target_sst_size(4 << 20)
+        .num_memtable_limit(4)
+        .compaction_options(CompactionOptions::NoCompaction)
+        .compression_per_level(vec![CompressionType::None, CompressionType::Lz])
+        .enable_wal(true)
+        .serializable(true)
+        .build()
+        .unwrap();
+    assert_eq!(options.block_size, 8192);
+    assert_eq!(options.target_sst_size, 4 << 20);
+    assert_eq!(options.num_memtable_limit, 4);
+    assert!(matches!(
+        options.compaction_options,
+        CompactionOptions::NoCompaction
+    ));
+    assert_eq!(options.compression_of_level(2), CompressionType::Lz);
+    assert!(options.enable_wal && options.serializable);
+    // the others keep their default
+    assert_eq!(
+        options.max_manifest_file_size,
+        LsmStorageOptions::default().max_manifest_file_size
+    );
+}
+
+#[test]
+fn test_builder_rejects_inconsistent_options() {
+    let invalid = [
+        LsmStorageOptions::builder().block_size(0),
+        LsmStorageOptions::builder().num_memtable_limit(0),
+        LsmStorageOptions::builder().max_background_jobs(0),
+        LsmStorageOptions::builder().tombstone_compaction_ratio(1.5),
+        LsmStorageOptions::builder().target_sst_size_per_level(vec![0, 1 << 20, 0]),
+        LsmStorageOptions::builder().txn_conflict_granularity(ConflictGranularity::Prefix(0)),
+        LsmStorageOptions::builder().secondary_cache(SecondaryCacheOptions {
+            path: "cache".into(),
+            capacity: 1024,
+        }),
+        LsmStorageOptions::builder().compaction_options(CompactionOptions::Tiered(
+            TieredCompactionOptions {
+                num_tiers: 1,
+                max_size_amplification_percent: 200,
+                size_ratio: 1,
+                min_merge_width: 2,
+            },
+        )),
+    ];
+    for builder in invalid {
+        let debug = format!("{:?}", builder);
+        assert!(builder.build().is_err(), "{}", debug);
+    }
+    // L0 does not use its target size
+    LsmStorageOptions::builder()
+        .target_sst_size_per_level(vec![0, 1 << 20])
+        .build()
+        .unwrap();
+}
+
+#[test]
+fn test_open_validates_options() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.num_memtable_limit = 0;
+    assert!(LsmStorageInner::open(&dir, options).is_err());
+    // nothing was written
+    assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+}
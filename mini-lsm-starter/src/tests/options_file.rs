@@ -0,0 +1,72 @@
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+use crate::mvcc::txn::ConflictGranularity;
+use crate::table::CompressionType;
+
+fn leveled_options(max_levels: usize) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.compaction_options = CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels,
+        base_level_size_mb: 1,
+    });
+    options
+}
+
+#[test]
+fn test_reopen_with_persisted_options() {
+    let dir = tempdir().unwrap();
+    assert!(LsmStorageOptions::load_latest(&dir).is_err());
+    let mut options = leveled_options(3);
+    options.block_size = 8192;
+    options.enable_wal = true;
+    options.compression_per_level = vec![CompressionType::None, CompressionType::Lz];
+    options.txn_conflict_granularity = ConflictGranularity::Prefix(4);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.close().unwrap();
+
+    let loaded = LsmStorageOptions::load_latest(&dir).unwrap();
+    assert_eq!(loaded.block_size, 8192);
+    assert!(loaded.enable_wal);
+    assert_eq!(loaded.compression_of_level(1), CompressionType::Lz);
+    assert_eq!(
+        loaded.txn_conflict_granularity,
+        ConflictGranularity::Prefix(4)
+    );
+    let CompactionOptions::Leveled(leveled) = loaded.compaction_options else {
+        panic!("not leveled compaction: {:?}", loaded.compaction_options);
+    };
+    assert_eq!(leveled.max_levels, 3);
+
+    let storage = MiniLsm::open_latest_options(&dir).unwrap();
+    assert_eq!(storage.get(b"a").unwrap().as_deref(), Some(&b"1"[..]));
+}
+
+#[test]
+fn test_incompatible_options_are_rejected() {
+    let dir = tempdir().unwrap();
+    drop(LsmStorageInner::open(&dir, leveled_options(3)).unwrap());
+
+    let mut tiered = LsmStorageOptions::default_for_week1_test();
+    tiered.compaction_options = CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    });
+    assert!(LsmStorageInner::open(&dir, tiered).is_err());
+    assert!(LsmStorageInner::open(&dir, leveled_options(2)).is_err());
+    // the mutable options may change, and are persisted
+    let mut options = leveled_options(4);
+    options.block_size = 1024;
+    drop(LsmStorageInner::open(&dir, options).unwrap());
+    assert_eq!(
+        LsmStorageOptions::load_latest(&dir).unwrap().block_size,
+        1024
+    );
+    assert!(LsmStorageInner::open(&dir, leveled_options(3)).is_err());
+}
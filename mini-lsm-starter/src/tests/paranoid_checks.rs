@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options(paranoid_checks: bool) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.paranoid_checks = paranoid_checks;
+    options
+}
+
+/// Create a database of `n` SSTs, and return their paths from the oldest.
+fn create_ssts(path: &Path, n: usize) -> Vec<PathBuf> {
+    let storage = LsmStorageInner::open(path, options(false)).unwrap();
+    for i in 0..n {
+        storage
+            .put(format!("key_{i}").as_bytes(), b"value")
+            .unwrap();
+        sync(&storage);
+    }
+    let mut ssts = storage.state.read().l0_sstables.clone();
+    ssts.reverse();
+    ssts.into_iter()
+        .map(|sst_id| LsmStorageInner::path_of_sst_static(path, sst_id))
+        .collect()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> usize {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+/// The offsets of the block meta and of the bloom filter, read from the footer.
+fn footer_offsets(data: &[u8]) -> (usize, usize) {
+    let properties_offset = read_u32(data, data.len() - 4);
+    let bloom_offset = read_u32(data, properties_offset - 4);
+    (read_u32(data, bloom_offset - 4), bloom_offset)
+}
+
+fn flip_byte(path: &Path, offset: usize) {
+    let mut data = std::fs::read(path).unwrap();
+    data[offset] ^= 0xff;
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn test_paranoid_checks_report_every_corrupted_sst() {
+    let dir = tempdir().unwrap();
+    let ssts = create_ssts(dir.path(), 3);
+    let (meta_offset, _) = footer_offsets(&std::fs::read(&ssts[0]).unwrap());
+    flip_byte(&ssts[0], meta_offset + 6);
+    let (_, bloom_offset) = footer_offsets(&std::fs::read(&ssts[2]).unwrap());
+    flip_byte(&ssts[2], bloom_offset + 1);
+
+    let error = LsmStorageInner::open(&dir, options(true))
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(error.contains("on 2 SSTs"), "{}", error);
+    let name = |path: &PathBuf| path.file_name().unwrap().to_str().unwrap().to_string();
+    assert!(error.contains(&format!(
+        "{} is corrupted: invalid block meta at offset {}: meta checksum mismatched",
+        name(&ssts[0]),
+        meta_offset
+    )));
+    assert!(error.contains(&format!(
+        "{} is corrupted: invalid bloom filter at offset {}: bloom filter checksum mismatched",
+        name(&ssts[2]),
+        bloom_offset
+    )));
+    assert!(!error.contains(&name(&ssts[1])));
+}
+
+#[test]
+fn test_paranoid_checks_verify_block_meta() {
+    let dir = tempdir().unwrap();
+    let ssts = create_ssts(dir.path(), 1);
+    // the first block moved away from the start of the file, with a valid checksum
+    let mut data = std::fs::read(&ssts[0]).unwrap();
+    let (meta_offset, bloom_offset) = footer_offsets(&data);
+    data[meta_offset + 4..meta_offset + 8].copy_from_slice(&3u32.to_be_bytes());
+    let checksum_offset = bloom_offset - 8;
+    let checksum = crc32fast::hash(&data[meta_offset + 4..checksum_offset]);
+    data[checksum_offset..checksum_offset + 4].copy_from_slice(&checksum.to_be_bytes());
+    std::fs::write(&ssts[0], data).unwrap();
+
+    drop(LsmStorageInner::open(&dir, options(false)).unwrap());
+    let error = LsmStorageInner::open(&dir, options(true)).err().unwrap();
+    assert!(
+        format!("{:#}", error).contains("block 0 at offset 3 does not start the file"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn test_paranoid_checks_pass() {
+    let dir = tempdir().unwrap();
+    create_ssts(dir.path(), 3);
+    let storage = LsmStorageInner::open(&dir, options(true)).unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 3);
+}
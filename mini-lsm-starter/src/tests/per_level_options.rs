@@ -0,0 +1,101 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::CompressionType;
+
+/// The value of every key, 70 bytes.
+const VALUE: &str = "value_0value_0value_0value_0value_0value_0value_0value_0value_0value_0";
+
+#[test]
+fn test_lz_compression_roundtrip() {
+    let repeated = b"key_00001value_1key_00002value_2".repeat(100);
+    let compressed = CompressionType::Lz.compress(&repeated).unwrap();
+    assert!(compressed.len() < repeated.len() / 4);
+    assert_eq!(
+        CompressionType::Lz.decompress(&compressed).unwrap(),
+        repeated
+    );
+
+    let runs = [b"a".repeat(1000), b"ab".repeat(300), (0..=255).collect()].concat();
+    let compressed = CompressionType::Lz.compress(&runs).unwrap();
+    assert_eq!(CompressionType::Lz.decompress(&compressed).unwrap(), runs);
+
+    // data that does not get smaller is left alone
+    assert!(CompressionType::Lz.compress(b"abc").is_none());
+    assert!(CompressionType::None.compress(&repeated).is_none());
+    assert!(CompressionType::Lz.decompress(&compressed[..10]).is_err());
+}
+
+#[test]
+fn test_compression_per_level() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    options.compression_per_level = vec![
+        CompressionType::None,
+        CompressionType::None,
+        CompressionType::Lz,
+    ];
+    assert_eq!(options.compression_of_level(3), CompressionType::Lz);
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    put_range(&storage, 0, 1000, VALUE);
+    sync(&storage);
+    let l0_size = {
+        let state = storage.state.read();
+        let sst = &state.sstables[&state.l0_sstables[0]];
+        assert_eq!(sst.properties().compression, CompressionType::None);
+        sst.table_size()
+    };
+    put_range(&storage, 1000, 2000, VALUE);
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    let (level, bottom_level) = state.levels.last().unwrap();
+    assert_eq!(*level, 3);
+    let bottom_size = bottom_level
+        .iter()
+        .map(|id| {
+            let sst = &state.sstables[id];
+            assert_eq!(sst.properties().compression, CompressionType::Lz);
+            sst.table_size()
+        })
+        .sum::<u64>();
+    assert!(bottom_size < l0_size);
+    for i in [0, 999, 1000, 1999] {
+        assert_eq!(
+            storage.get(format!("key_{:05}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(VALUE))
+        );
+    }
+    drop(storage);
+
+    // the blocks are read back after reopening
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    assert_eq!(storage.get(b"key_01234").unwrap(), Some(Bytes::from(VALUE)));
+}
+
+#[test]
+fn test_target_sst_size_per_level() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    options.target_sst_size_per_level =
+        vec![options.target_sst_size, options.target_sst_size, 16 << 10];
+    assert_eq!(options.target_sst_size_of_level(1), options.target_sst_size);
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    put_range(&storage, 0, 1000, VALUE);
+    sync(&storage);
+    put_range(&storage, 500, 1500, VALUE);
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+
+    let state = storage.state.read().clone();
+    let (_, bottom_level) = state.levels.last().unwrap();
+    // about 130KB of data cut into SSTs of 16KB
+    assert!(bottom_level.len() >= 6);
+    for id in bottom_level {
+        assert!(state.sstables[id].table_size() < 24 << 10);
+    }
+}
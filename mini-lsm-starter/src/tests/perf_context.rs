@@ -0,0 +1,85 @@
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::LsmStorageInner;
+use crate::perf_context::{
+    perf_context, reset_perf_context, set_perf_level, PerfContext, PerfLevel,
+};
+
+fn open(path: &std::path::Path) -> Arc<LsmStorageInner> {
+    Arc::new(LsmStorageInner::open(path, simple_leveled_wal_options()).unwrap())
+}
+
+#[test]
+fn test_perf_context() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path());
+    // disabled unless set for the thread
+    storage.put(b"a", b"1").unwrap();
+    storage.get(b"a").unwrap();
+    assert_eq!(perf_context(), PerfContext::default());
+
+    set_perf_level(PerfLevel::EnableCount);
+    storage.get(b"a").unwrap();
+    let context = perf_context();
+    assert_eq!(context.get_from_memtable_count, 1);
+    assert_eq!(context.get_from_memtable_time, Duration::ZERO);
+
+    sync(&storage);
+    reset_perf_context();
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), "1");
+    let context = perf_context();
+    assert_eq!(context.get_from_memtable_count, 1);
+    assert_eq!(context.bloom_sst_hit_count, 1);
+    assert_eq!(context.block_read_count, 1);
+    assert!(context.block_read_byte > 0);
+    assert_eq!(context.block_cache_hit_count, 0);
+    reset_perf_context();
+    storage.get(b"a").unwrap();
+    let context = perf_context();
+    assert_eq!(context.block_read_count, 0);
+    assert_eq!(context.block_cache_hit_count, 1);
+
+    // the operations of other threads are not counted
+    reset_perf_context();
+    let other = storage.clone();
+    std::thread::spawn(move || {
+        set_perf_level(PerfLevel::EnableTime);
+        other.get(b"a").unwrap();
+        assert_eq!(perf_context().block_cache_hit_count, 1);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(perf_context(), PerfContext::default());
+
+    set_perf_level(PerfLevel::EnableTime);
+    storage.put(b"b", b"1").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    let context = perf_context();
+    assert!(context.write_wal_time > Duration::ZERO);
+    assert!(context.write_memtable_time > Duration::ZERO);
+    storage.sync().unwrap();
+    assert!(perf_context().wal_sync_time > Duration::ZERO);
+
+    reset_perf_context();
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    assert_eq!(keys, vec![b"a".to_vec(), b"c".to_vec()]);
+    let context = perf_context();
+    assert!(context.iter_seek_time > Duration::ZERO);
+    assert_eq!(context.iter_next_count, 2);
+    // the put and the tombstone of b, and the older version of c
+    assert_eq!(context.internal_key_skipped_count, 3);
+    set_perf_level(PerfLevel::Disable);
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::generate_sst;
+use crate::compact::{
+    CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
+    TieredCompactionController, TieredCompactionOptions,
+};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::SsTable;
+
+fn leveled_options() -> LeveledCompactionOptions {
+    LeveledCompactionOptions {
+        level0_file_num_compaction_trigger: 2,
+        level_size_multiplier: 2,
+        base_level_size_mb: 1,
+        max_levels: 2,
+    }
+}
+
+/// An SST holding `key_000..key_009`, every other key deleted, built `age` seconds ago.
+fn old_sst(storage: &LsmStorageInner, age: u64) -> Arc<SsTable> {
+    let data = (0..10)
+        .map(|i| {
+            let value = if i % 2 == 0 { "value" } else { "" };
+            (Bytes::from(format!("key_{:03}", i)), Bytes::from(value))
+        })
+        .collect();
+    let id = storage.next_sst_id();
+    let mut sst = generate_sst(id, storage.path_of_sst(id), data, None);
+    sst.properties.creation_time -= age;
+    Arc::new(sst)
+}
+
+#[test]
+fn test_leveled_periodic_compaction_rewrites_bottommost_sst() {
+    let dir = tempdir().unwrap();
+    let mut options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(leveled_options()));
+    options.periodic_compaction_seconds = 3600;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    let mut state = storage.state.read().as_ref().clone();
+    let fresh = old_sst(&storage, 0);
+    let old = old_sst(&storage, 7200);
+    state.l0_sstables = vec![fresh.sst_id()];
+    state.levels[1].1 = vec![old.sst_id()];
+    state.sstables.insert(fresh.sst_id(), fresh.clone());
+    state.sstables.insert(old.sst_id(), old.clone());
+
+    // no level is over its target, but the bottommost SST is due and is rewritten in place
+    let task = LeveledCompactionController::new(leveled_options())
+        .with_periodic_compaction_seconds(3600)
+        .generate_compaction_task(&state)
+        .unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    assert_eq!(task.upper_level_sst_ids, vec![old.sst_id()]);
+    assert_eq!(task.lower_level, 2);
+    assert!(task.lower_level_sst_ids.is_empty());
+    assert!(task.is_lower_level_bottom_level);
+    assert!(LeveledCompactionController::new(leveled_options())
+        .generate_compaction_task(&state)
+        .is_none());
+
+    *storage.state.write() = Arc::new(state);
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert_eq!(state.l0_sstables, vec![fresh.sst_id()]);
+    assert_eq!(state.levels[1].1.len(), 1);
+    let rewritten = &state.sstables[&state.levels[1].1[0]];
+    assert_ne!(rewritten.sst_id(), old.sst_id());
+    // the tombstones are purged from the bottommost level
+    assert_eq!(rewritten.properties().num_entries, 5);
+    assert_eq!(rewritten.properties().num_deletions, 0);
+    assert!(!storage.path_of_sst(old.sst_id()).exists());
+
+    // the rewritten SST is not due any more
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+    assert_eq!(storage.state.read().l0_sstables, state.l0_sstables);
+}
+
+#[test]
+fn test_universal_periodic_compaction_compacts_all_tiers() {
+    let dir = tempdir().unwrap();
+    let universal_options = TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    };
+    let storage = LsmStorageInner::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+            universal_options.clone(),
+        )),
+    )
+    .unwrap();
+    let mut state = storage.state.read().as_ref().clone();
+    let fresh = old_sst(&storage, 0);
+    let old = old_sst(&storage, 7200);
+    state.levels = vec![
+        (fresh.sst_id(), vec![fresh.sst_id()]),
+        (old.sst_id(), vec![old.sst_id()]),
+    ];
+    state.sstables.insert(fresh.sst_id(), fresh);
+    state.sstables.insert(old.sst_id(), old);
+
+    // fewer tiers than `num_tiers`, so only periodic compaction applies
+    let controller = TieredCompactionController::new(universal_options.clone());
+    assert!(controller.generate_compaction_task(&state).is_none());
+    let task = TieredCompactionController::new(universal_options)
+        .with_periodic_compaction_seconds(3600)
+        .generate_compaction_task(&state)
+        .unwrap();
+    assert_eq!(task.tiers, state.levels);
+    assert!(task.bottom_tier_included);
+}
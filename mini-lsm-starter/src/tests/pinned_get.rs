@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use self::harness::sync;
+
+use super::*;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[test]
+fn test_get_pinned() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    for i in 0..100 {
+        storage
+            .put(
+                format!("{:05}", i).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    sync(&storage);
+    storage.delete(b"00001").unwrap();
+    storage.put(b"00002", b"in-memtable").unwrap();
+
+    let value = storage.get_pinned(b"00050").unwrap().unwrap();
+    assert!(value.is_pinned());
+    assert_eq!(&*value, b"value50");
+    assert_eq!(value.to_bytes(), storage.get(b"00050").unwrap().unwrap());
+
+    let value = storage.get_pinned(b"00002").unwrap().unwrap();
+    assert!(!value.is_pinned());
+    assert_eq!(&*value, b"in-memtable");
+
+    assert!(storage.get_pinned(b"00001").unwrap().is_none());
+    assert!(storage.get_pinned(b"99999").unwrap().is_none());
+
+    // a pinned value stays readable after the block cache drops the block
+    let value = storage.get_pinned(b"00099").unwrap().unwrap();
+    storage.block_cache.invalidate_all();
+    assert_eq!(&*value, b"value99");
+}
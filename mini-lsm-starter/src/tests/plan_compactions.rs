@@ -0,0 +1,75 @@
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::scheduler::JobPriority;
+
+#[test]
+fn test_plan_leveled_compactions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 50, 150, "value");
+    sync(&storage);
+    let state = storage.state.read().clone();
+    let l0_sstables = state.l0_sstables.clone();
+
+    // the two overlapping L0 SSTs are merged into the base level
+    let plan = storage.plan_compactions(10);
+    assert!(storage.plan_compactions(0).is_empty());
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].priority, JobPriority::L0Compaction);
+    assert_eq!(plan[0].output_level, 3);
+    assert_eq!(plan[0].task.input_sst_ids(), l0_sstables);
+    assert!(!plan[0].is_trivial_move);
+    let input_bytes = l0_sstables
+        .iter()
+        .map(|id| state.sstables[id].table_size())
+        .sum::<u64>();
+    assert_eq!(plan[0].input_bytes, input_bytes);
+    assert_eq!(plan[0].estimated_output_bytes, input_bytes);
+    assert_eq!(plan[0].output_sst_ids.len(), 1);
+    assert!(!state.sstables.contains_key(&plan[0].output_sst_ids[0]));
+    // nothing was compacted
+    assert_eq!(storage.state.read().l0_sstables, l0_sstables);
+    assert_eq!(storage.state.read().levels, state.levels);
+
+    // the first planned compaction is the one that runs
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert!(state.levels[2].1.iter().all(|id| !l0_sstables.contains(id)));
+    assert!(storage.plan_compactions(10).is_empty());
+}
+
+#[test]
+fn test_plan_universal_compactions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+            TieredCompactionOptions {
+                num_tiers: 3,
+                max_size_amplification_percent: 200,
+                size_ratio: 1,
+                min_merge_width: 2,
+            },
+        )),
+    )
+    .unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+
+    let plan = storage.plan_compactions(10);
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].priority, JobPriority::Compaction);
+    assert_eq!(plan[0].output_level, 1);
+    assert_eq!(plan[0].task.input_sst_ids().len(), 3);
+    assert_eq!(storage.state.read().levels.len(), 3);
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+use crate::property::PROPERTIES;
+use crate::statistics::Statistics;
+
+#[test]
+fn test_get_property() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_leveled_wal_options();
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    sync(&storage);
+    storage.delete(b"a").unwrap();
+
+    assert_eq!(
+        storage.get_int_property("cobblesdb.num-files-at-level0"),
+        Some(1)
+    );
+    assert_eq!(
+        storage.get_int_property("cobblesdb.num-files-at-level3"),
+        Some(0)
+    );
+    assert_eq!(storage.get_property("cobblesdb.num-files-at-level4"), None);
+    let size = storage
+        .get_int_property("cobblesdb.size-at-level0")
+        .unwrap();
+    assert!(size > 0);
+    assert_eq!(
+        storage.get_int_property("cobblesdb.total-sst-files-size"),
+        Some(size)
+    );
+    assert!(storage
+        .get_property("cobblesdb.compression-ratio-at-level0")
+        .unwrap()
+        .parse::<f64>()
+        .is_ok());
+    assert_eq!(storage.get_int_property("cobblesdb.num-levels"), Some(4));
+    assert_eq!(
+        storage.get_int_property("cobblesdb.num-entries-active-mem-table"),
+        Some(1)
+    );
+    assert_eq!(
+        storage.get_int_property("cobblesdb.num-deletes-active-mem-table"),
+        Some(1)
+    );
+    assert_eq!(
+        storage.get_int_property("cobblesdb.estimate-pending-compaction-bytes"),
+        Some(0)
+    );
+    let snapshot = storage.snapshot();
+    assert_eq!(storage.get_int_property("cobblesdb.num-snapshots"), Some(1));
+    drop(snapshot);
+    assert!(storage
+        .get_property("cobblesdb.levelstats")
+        .unwrap()
+        .contains("L0"));
+    // unknown, or without the statistics option
+    assert_eq!(storage.get_property("cobblesdb.no-such-property"), None);
+    assert_eq!(storage.get_property("num-levels"), None);
+    assert_eq!(storage.get_int_property("cobblesdb.sstables"), None);
+    assert_eq!(storage.get_property("cobblesdb.stats"), None);
+    for name in PROPERTIES.iter().filter(|name| **name != "cobblesdb.stats") {
+        assert!(storage.get_property(name).is_some(), "{}", name);
+    }
+    drop(storage);
+
+    options.statistics = Some(Arc::new(Statistics::new()));
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    assert!(storage
+        .get_property("cobblesdb.stats")
+        .unwrap()
+        .contains("number.keys.written COUNT : 0"));
+    assert_eq!(
+        storage.get_int_property("cobblesdb.block-cache-usage"),
+        storage.get_int_property("cobblesdb.block-cache-pinned-usage")
+    );
+}
@@ -0,0 +1,56 @@
+use tempfile::tempdir;
+
+use super::harness::simple_leveled_wal_options;
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+
+fn options() -> LsmStorageOptions {
+    let mut options = simple_leveled_wal_options();
+    options.compaction_options = CompactionOptions::NoCompaction;
+    options
+}
+
+#[test]
+fn test_apply_batch_with_index() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.last_applied_index().unwrap(), 0);
+    assert!(storage
+        .apply_batch_with_index(&[WriteBatchRecord::Put(b"a", b"1")], 1)
+        .unwrap());
+    // a no-op entry of a new leader
+    assert!(storage.apply_batch_with_index::<&[u8]>(&[], 2).unwrap());
+    assert!(storage
+        .apply_batch_with_index(
+            &[
+                WriteBatchRecord::Put(b"b", b"2"),
+                WriteBatchRecord::Del(b"a")
+            ],
+            3
+        )
+        .unwrap());
+    assert_eq!(storage.last_applied_index().unwrap(), 3);
+    // replayed after a restart
+    assert!(!storage
+        .apply_batch_with_index(&[WriteBatchRecord::Put(&b"a"[..], &b"stale"[..])], 1)
+        .unwrap());
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert!(storage.apply_batch_with_index::<&[u8]>(&[], 0).is_err());
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.last_applied_index().unwrap(), 3);
+    assert_eq!(storage.get(b"b").unwrap().as_deref(), Some(&b"2"[..]));
+    storage.force_flush().unwrap();
+    storage
+        .apply_batch_with_index(&[WriteBatchRecord::Put(b"c", b"3")], 4)
+        .unwrap();
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.last_applied_index().unwrap(), 4);
+    assert_eq!(storage.get(b"c").unwrap().as_deref(), Some(&b"3"[..]));
+    assert!(storage
+        .list_cfs()
+        .contains(&crate::raft::RAFT_STATE_COLUMN_FAMILY_NAME.to_string()));
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+use crate::rate_limiter::RateLimiter;
+
+const MB: u64 = 1 << 20;
+
+#[test]
+fn test_fixed_rate() {
+    let limiter = RateLimiter::new(MB);
+    assert!(!limiter.is_auto_tuned());
+    let started = Instant::now();
+    for _ in 0..4 {
+        limiter.request(100 << 10);
+    }
+    assert!(started.elapsed() >= Duration::from_millis(350));
+    // the read latency only matters to auto-tuned limiters
+    limiter.record_read_latency(Duration::from_secs(1));
+    assert_eq!(limiter.bytes_per_sec(), MB);
+}
+
+#[test]
+fn test_auto_tuned_rate() {
+    let limiter = RateLimiter::new_auto_tuned(10 * MB, Duration::from_millis(1));
+    assert!(limiter.is_auto_tuned());
+    let record = |latency: Duration| {
+        for _ in 0..20 {
+            limiter.record_read_latency(latency);
+        }
+        std::thread::sleep(Duration::from_millis(110));
+        limiter.record_read_latency(latency);
+    };
+
+    // slow reads halve the rate
+    record(Duration::from_millis(10));
+    assert_eq!(limiter.bytes_per_sec(), 5 * MB);
+    record(Duration::from_millis(10));
+    assert_eq!(limiter.bytes_per_sec(), 5 * MB / 2);
+
+    // fast reads raise it by a twentieth of the maximum at a time
+    record(Duration::from_micros(100));
+    assert_eq!(limiter.bytes_per_sec(), 5 * MB / 2 + MB / 2);
+}
+
+#[test]
+fn test_compaction_throttled() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    let limiter = Arc::new(RateLimiter::new(2 * MB));
+    options.rate_limiter = Some(limiter.clone());
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    let value = vec![b'v'; 100];
+    for _ in 0..2 {
+        for i in 0..1000 {
+            storage
+                .put(format!("key_{:05}", i).as_bytes(), &value)
+                .unwrap();
+        }
+        sync(&storage);
+    }
+
+    // more than 200KB read and 100KB written
+    let started = Instant::now();
+    storage.trigger_compaction().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert!(storage.state.read().l0_sstables.is_empty());
+    assert_eq!(storage.get(b"key_00999").unwrap(), Some(value.into()));
+}
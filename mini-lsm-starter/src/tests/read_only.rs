@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options(enable_wal: bool) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = enable_wal;
+    options
+}
+
+/// The files of the directory with their sizes.
+fn files(path: &Path) -> BTreeMap<String, u64> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (
+                entry.file_name().into_string().unwrap(),
+                entry.metadata().unwrap().len(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_read_only_open_alongside_the_owner() {
+    let dir = tempdir().unwrap();
+    let primary = MiniLsm::open(&dir, options(true)).unwrap();
+    primary.put(b"flushed", b"1").unwrap();
+    primary.force_flush().unwrap();
+    primary.put(b"in_wal", b"2").unwrap();
+    primary.sync().unwrap();
+    let files_before = files(dir.path());
+
+    let reader = MiniLsm::open_read_only(&dir, options(true)).unwrap();
+    assert_eq!(reader.get(b"flushed").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(reader.get(b"in_wal").unwrap(), Some(Bytes::from("2")));
+    assert!(reader.put(b"c", b"3").is_err());
+    assert!(reader.delete(b"flushed").is_err());
+    assert!(reader.force_flush().is_err());
+    assert!(reader.force_full_compaction().is_err());
+    assert!(reader.set_options(&[("num_memtable_limit", "4")]).is_err());
+    let txn = reader.new_txn().unwrap();
+    assert_eq!(txn.get(b"in_wal").unwrap(), Some(Bytes::from("2")));
+    txn.put(b"c", b"3");
+    assert!(txn.commit().is_err());
+    reader.close().unwrap();
+    drop(reader);
+
+    // ignoring the WALs leaves the flushed writes
+    let reader = MiniLsm::open_read_only(&dir, options(false)).unwrap();
+    assert_eq!(reader.get(b"flushed").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(reader.get(b"in_wal").unwrap(), None);
+    drop(reader);
+
+    assert_eq!(files(dir.path()), files_before);
+    primary.put(b"c", b"3").unwrap();
+    assert_eq!(primary.get(b"in_wal").unwrap(), Some(Bytes::from("2")));
+    primary.close().unwrap();
+}
+
+#[test]
+fn test_read_only_open_needs_a_database() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    assert!(MiniLsm::open_read_only(&path, options(false)).is_err());
+    assert!(!path.exists());
+    std::fs::create_dir(&path).unwrap();
+    assert!(MiniLsm::open_read_only(&path, options(false)).is_err());
+    assert!(files(&path).is_empty());
+}
@@ -0,0 +1,204 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, sync};
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::fs::LocalFileSystem;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm};
+use crate::manifest::read_current;
+
+fn universal_options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    }))
+}
+
+/// Write overlapping key ranges, `key_{i}` last written in the round `i / 10 - 9` (or 0).
+fn write_rounds(storage: &LsmStorageInner, rounds: usize, compact: bool) {
+    for round in 0..rounds {
+        for i in round * 10..round * 10 + 100 {
+            storage
+                .put(
+                    format!("key_{:05}", i).as_bytes(),
+                    format!("value_{round}").as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(storage);
+        if compact {
+            storage.trigger_compaction().unwrap();
+        }
+    }
+}
+
+fn check_contents(storage: &LsmStorageInner, rounds: usize) {
+    for i in 0..rounds * 10 + 90 {
+        let round = (i / 10).min(rounds - 1);
+        assert_eq!(
+            storage.get(format!("key_{:05}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value_{round}"))),
+            "key_{:05}",
+            i
+        );
+    }
+}
+
+/// The SSTs of every level are sorted and do not overlap.
+fn check_levels(state: &LsmStorageState) {
+    for (_, ids) in &state.levels {
+        for pair in ids.windows(2) {
+            assert!(state.sstables[&pair[0]].last_key() < state.sstables[&pair[1]].first_key());
+        }
+    }
+}
+
+fn remove_manifest(path: &std::path::Path) {
+    let current = read_current(&LocalFileSystem, path).unwrap().unwrap();
+    std::fs::remove_file(path.join(current)).unwrap();
+    std::fs::remove_file(path.join("CURRENT")).unwrap();
+}
+
+#[test]
+fn test_repair_lost_manifest() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    write_rounds(&storage, 6, true);
+    let num_ssts = storage.state.read().sstables.len();
+    drop(storage);
+    remove_manifest(dir.path());
+    assert!(LsmStorageInner::open(&dir, leveled_options()).is_err());
+
+    let summary = LsmStorageInner::repair(&dir, &leveled_options()).unwrap();
+    assert_eq!(summary.sst_ids.len(), num_ssts);
+    assert!(summary.lost_files.is_empty());
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    check_levels(&storage.state.read());
+    check_contents(&storage, 6);
+    // the repaired database keeps working
+    storage.put(b"key_00020", b"value_new").unwrap();
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    assert_eq!(
+        storage.get(b"key_00020").unwrap(),
+        Some(Bytes::from("value_new"))
+    );
+}
+
+#[test]
+fn test_repair_moves_corrupted_files_to_lost() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    write_rounds(&storage, 3, false);
+    let newest = storage.state.read().l0_sstables[0];
+    drop(storage);
+    // garbage in the manifest, and in the first block of the newest SST
+    let current = read_current(&LocalFileSystem, dir.path()).unwrap().unwrap();
+    std::fs::write(dir.path().join(&current), b"garbage").unwrap();
+    let sst_path = LsmStorageInner::path_of_sst_static(dir.path(), newest);
+    let mut data = std::fs::read(&sst_path).unwrap();
+    data[10] ^= 0xff;
+    std::fs::write(&sst_path, data).unwrap();
+
+    let summary = LsmStorageInner::repair(&dir, &leveled_options()).unwrap();
+    assert!(!summary.sst_ids.contains(&newest));
+    let lost = dir.path().join("lost");
+    assert_eq!(
+        summary.lost_files,
+        vec![lost.join(format!("{:05}.sst", newest)), lost.join(current)]
+    );
+    assert!(!sst_path.exists());
+
+    // the first two rounds are left
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    check_levels(&storage.state.read());
+    check_contents(&storage, 2);
+    assert_eq!(storage.get(b"key_00115").unwrap(), None);
+}
+
+#[test]
+fn test_repair_assigns_levels() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    write_rounds(&storage, 3, false);
+    // overlapping nothing
+    storage.put(b"other", b"value").unwrap();
+    sync(&storage);
+    let flushed = storage.state.read().l0_sstables.clone();
+    drop(storage);
+    remove_manifest(dir.path());
+
+    LsmStorageInner::repair(&dir, &leveled_options()).unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    {
+        let state = storage.state.read();
+        // every SST overlaps the older ones and goes one level up, the one overlapping nothing goes to the
+        // bottom level
+        assert!(state.l0_sstables.is_empty());
+        assert_eq!(state.levels[0].1, vec![flushed[1]]);
+        assert_eq!(state.levels[1].1, vec![flushed[2]]);
+        assert_eq!(state.levels[2].1, vec![flushed[3], flushed[0]]);
+    }
+    check_contents(&storage, 3);
+}
+
+#[test]
+fn test_repair_universal_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, universal_options()).unwrap();
+    write_rounds(&storage, 5, true);
+    drop(storage);
+    remove_manifest(dir.path());
+
+    LsmStorageInner::repair(&dir, &universal_options()).unwrap();
+    let storage = LsmStorageInner::open(&dir, universal_options()).unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        check_levels(&state);
+    }
+    check_contents(&storage, 5);
+}
+
+#[test]
+fn test_repair_replays_wals() {
+    let dir = tempdir().unwrap();
+    let mut options = leveled_options();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..100 {
+        storage
+            .put(format!("key_{:05}", i).as_bytes(), b"value_0")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in 50..150 {
+        storage
+            .put(format!("key_{:05}", i).as_bytes(), b"value_1")
+            .unwrap();
+    }
+    storage.sync().unwrap();
+    // dropped without flushing, the last writes are only in the WAL
+    drop(storage);
+    remove_manifest(dir.path());
+
+    MiniLsm::repair(&dir, &options).unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..150 {
+        let expected = if i < 50 { "value_0" } else { "value_1" };
+        assert_eq!(
+            storage.get(format!("key_{:05}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(expected)),
+            "key_{:05}",
+            i
+        );
+    }
+    // the replayed versions stay below the new ones
+    storage.put(b"key_00100", b"value_2").unwrap();
+    assert_eq!(
+        storage.get(b"key_00100").unwrap(),
+        Some(Bytes::from("value_2"))
+    );
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use super::harness::simple_leveled_wal_options;
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use crate::replication::{ReplicatedBatch, ReplicationSink};
+
+/// Ships the batches as bytes, as over a network.
+#[derive(Debug, Default)]
+struct ShippingSink {
+    shipped: Mutex<Vec<Bytes>>,
+}
+
+impl ReplicationSink for ShippingSink {
+    fn on_batch_committed(&self, batch: &ReplicatedBatch) {
+        self.shipped.lock().push(batch.encode());
+    }
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = simple_leveled_wal_options();
+    options.compaction_options = CompactionOptions::NoCompaction;
+    options
+}
+
+#[test]
+fn test_replicate_to_follower() {
+    let dir = tempdir().unwrap();
+    let sink = Arc::new(ShippingSink::default());
+    let mut primary_options = options();
+    primary_options.replication_sinks = vec![sink.clone()];
+    let primary = MiniLsm::open(dir.path().join("primary"), primary_options).unwrap();
+    let follower = MiniLsm::open(dir.path().join("follower"), options()).unwrap();
+    let primary_cf = primary.create_cf("meta").unwrap();
+    follower.create_cf("meta").unwrap();
+
+    primary.put(b"a", b"1").unwrap();
+    let first_ts = primary.latest_commit_ts();
+    primary.put(b"a", b"2").unwrap();
+    primary
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"b"[..], &b"1"[..]),
+            WriteBatchRecord::Del(&b"a"[..]),
+        ])
+        .unwrap();
+    primary
+        .write_batch_multi_cf(&[
+            (
+                &primary_cf,
+                &[WriteBatchRecord::Put(&b"c"[..], &b"1"[..])][..],
+            ),
+            (
+                &primary.cf("default").unwrap(),
+                &[WriteBatchRecord::Put(&b"d"[..], &b"1"[..])][..],
+            ),
+        ])
+        .unwrap();
+    let txn = primary.new_txn().unwrap();
+    txn.put(b"e", b"1");
+    txn.commit().unwrap();
+    let shipped = std::mem::take(&mut *sink.shipped.lock());
+    assert_eq!(shipped.len(), 5);
+    let batches = shipped
+        .into_iter()
+        .map(|bytes| ReplicatedBatch::decode(bytes).unwrap())
+        .collect::<Vec<_>>();
+    assert!(batches.windows(2).all(|w| w[0].commit_ts < w[1].commit_ts));
+    assert_eq!(batches[0].commit_ts, first_ts);
+    assert_eq!(
+        batches[0].records(),
+        &[WriteBatchRecord::Put(Bytes::from("a"), Bytes::from("1"))]
+    );
+
+    for batch in &batches[..3] {
+        assert!(follower.apply_replicated_batch(batch).unwrap());
+    }
+    // applied already
+    assert!(!follower.apply_replicated_batch(&batches[1]).unwrap());
+    follower.close().unwrap();
+    drop(follower);
+
+    // resume after a restart from the latest commit ts of the follower
+    let follower = MiniLsm::open(dir.path().join("follower"), options()).unwrap();
+    let resume_ts = follower.latest_commit_ts();
+    assert_eq!(resume_ts, batches[2].commit_ts);
+    for batch in batches.iter().filter(|batch| batch.commit_ts > resume_ts) {
+        assert!(follower.apply_replicated_batch(batch).unwrap());
+    }
+    assert_eq!(follower.latest_commit_ts(), primary.latest_commit_ts());
+    for key in [&b"a"[..], b"b", b"d", b"e"] {
+        assert_eq!(follower.get(key).unwrap(), primary.get(key).unwrap());
+    }
+    assert_eq!(
+        follower.get_at_ts(b"a", first_ts).unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert_eq!(
+        follower
+            .get_cf(&follower.cf("meta").unwrap(), b"c")
+            .unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert!(ReplicatedBatch::decode(batches[0].encode().slice(1..)).is_err());
+    primary.close().unwrap();
+    follower.close().unwrap();
+}
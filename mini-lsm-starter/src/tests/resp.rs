@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::asynk::AsyncLsmStorage;
+use crate::clock::MockClock;
+use crate::lsm_storage::LsmStorageOptions;
+use crate::resp::{read_frame, Frame, RespServer};
+
+async fn call(stream: &mut BufReader<TcpStream>, args: &[&str]) -> Frame {
+    let mut buf = Vec::new();
+    Frame::command(args).encode(&mut buf);
+    stream.get_mut().write_all(&buf).await.unwrap();
+    read_frame(stream).await.unwrap().unwrap()
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+fn ok() -> Frame {
+    Frame::Simple("OK".to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resp_server() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(MockClock::new(SystemTime::now()));
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.clock = Some(clock.clone());
+    let storage = Arc::new(AsyncLsmStorage::open(dir.path(), options).await.unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Arc::new(RespServer::new(storage.clone())).serve(listener));
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+    assert_eq!(
+        call(&mut stream, &["PING"]).await,
+        Frame::Simple("PONG".to_string())
+    );
+    assert_eq!(call(&mut stream, &["SET", "a", "1"]).await, ok());
+    assert_eq!(
+        call(&mut stream, &["set", "b", "2", "EX", "10"]).await,
+        ok()
+    );
+    assert_eq!(
+        call(&mut stream, &["SET", "c", "3", "PX", "1500"]).await,
+        ok()
+    );
+    assert_eq!(call(&mut stream, &["GET", "a"]).await, bulk("1"));
+    assert_eq!(call(&mut stream, &["GET", "missing"]).await, Frame::Null);
+    assert_eq!(
+        call(&mut stream, &["MGET", "a", "missing", "b"]).await,
+        Frame::Array(vec![bulk("1"), Frame::Null, bulk("2")])
+    );
+    assert_eq!(call(&mut stream, &["TTL", "a"]).await, Frame::Integer(-1));
+    assert_eq!(call(&mut stream, &["TTL", "b"]).await, Frame::Integer(10));
+    assert_eq!(call(&mut stream, &["TTL", "c"]).await, Frame::Integer(2));
+    assert_eq!(
+        call(&mut stream, &["TTL", "missing"]).await,
+        Frame::Integer(-2)
+    );
+    assert!(matches!(
+        call(&mut stream, &["SET", "d", "4", "EX", "0"]).await,
+        Frame::Error(_)
+    ));
+    assert!(matches!(call(&mut stream, &["GET"]).await, Frame::Error(_)));
+    assert!(matches!(
+        call(&mut stream, &["HSET", "h", "f", "v"]).await,
+        Frame::Error(_)
+    ));
+
+    // c expires, and is neither read nor counted as deleted
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(call(&mut stream, &["GET", "c"]).await, Frame::Null);
+    assert_eq!(call(&mut stream, &["TTL", "c"]).await, Frame::Integer(-2));
+    assert_eq!(call(&mut stream, &["TTL", "b"]).await, Frame::Integer(8));
+    assert_eq!(
+        call(&mut stream, &["DEL", "a", "c", "missing"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(call(&mut stream, &["GET", "a"]).await, Frame::Null);
+
+    for i in 0..25 {
+        let key = format!("user:{:02}", i);
+        assert_eq!(call(&mut stream, &["SET", &key, "x"]).await, ok());
+    }
+    assert_eq!(call(&mut stream, &["SET", "user:x", "x"]).await, ok());
+    // a full scan, a few keys at a time, sees every live key once
+    let mut cursor = "0".to_string();
+    let mut keys = Vec::new();
+    loop {
+        let Frame::Array(reply) = call(&mut stream, &["SCAN", &cursor, "COUNT", "7"]).await else {
+            panic!("not an array");
+        };
+        let [Frame::Bulk(next), Frame::Array(batch)] = &reply[..] else {
+            panic!("unexpected reply {:?}", reply);
+        };
+        assert!(batch.len() <= 7);
+        keys.extend(batch.iter().cloned());
+        cursor = String::from_utf8(next.to_vec()).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    let mut expected = vec![bulk("b")];
+    expected.extend((0..25).map(|i| bulk(&format!("user:{:02}", i))));
+    expected.push(bulk("user:x"));
+    assert_eq!(keys, expected);
+
+    assert_eq!(
+        call(
+            &mut stream,
+            &["SCAN", "0", "MATCH", "user:?[05]", "COUNT", "100"]
+        )
+        .await,
+        Frame::Array(vec![
+            bulk("0"),
+            Frame::Array(vec![
+                bulk("user:00"),
+                bulk("user:05"),
+                bulk("user:10"),
+                bulk("user:15"),
+                bulk("user:20")
+            ]),
+        ])
+    );
+    assert!(matches!(
+        call(&mut stream, &["SCAN", "42"]).await,
+        Frame::Error(_)
+    ));
+
+    // pipelined inline commands are replied in order
+    stream
+        .get_mut()
+        .write_all(b"GET b\r\nDEL b\r\nQUIT\r\n")
+        .await
+        .unwrap();
+    assert_eq!(read_frame(&mut stream).await.unwrap(), Some(bulk("2")));
+    assert_eq!(
+        read_frame(&mut stream).await.unwrap(),
+        Some(Frame::Integer(1))
+    );
+    assert_eq!(read_frame(&mut stream).await.unwrap(), Some(ok()));
+    assert_eq!(read_frame(&mut stream).await.unwrap(), None);
+    storage.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_resp_nested_arrays() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week1_test();
+    let storage = Arc::new(AsyncLsmStorage::open(dir.path(), options).await.unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Arc::new(RespServer::new(storage.clone())).serve(listener));
+
+    // a command nesting arrays is refused at its first nested header, however deep it goes
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(&b"*1\r\n".repeat(100_000))
+        .await
+        .unwrap();
+    assert!(matches!(
+        read_frame(&mut stream).await.unwrap(),
+        Some(Frame::Error(err)) if err.starts_with("ERR Protocol error")
+    ));
+    assert_eq!(read_frame(&mut stream).await.unwrap(), None);
+
+    // announcing a huge bulk string allocates nothing until its bytes arrive
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(b"*2\r\n$3\r\nGET\r\n$536870912\r\nkey\r\n")
+        .await
+        .unwrap();
+    stream.get_mut().shutdown().await.unwrap();
+    assert!(matches!(
+        read_frame(&mut stream).await.unwrap(),
+        Some(Frame::Error(err)) if err.contains("truncated bulk string")
+    ));
+
+    // the server keeps serving other connections
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    assert_eq!(call(&mut stream, &["SET", "a", "1"]).await, ok());
+    assert_eq!(call(&mut stream, &["GET", "a"]).await, bulk("1"));
+
+    // a reply nesting arrays too deep is refused rather than overflowing the stack
+    let mut reply = &b"*1\r\n".repeat(100_000)[..];
+    assert!(read_frame(&mut reply).await.is_err());
+    storage.close().await.unwrap();
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use self::harness::sync;
+
+use super::*;
+use crate::fs::{FaultInjectionFileSystem, InMemoryFileSystem};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn row_cache_storage(dir: &tempfile::TempDir) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.row_cache_capacity = 1 << 20;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+#[test]
+fn test_row_cache_fill_on_sst_get() {
+    let dir = tempdir().unwrap();
+    let storage = row_cache_storage(&dir);
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    sync(&storage);
+
+    let row_cache = storage.row_cache.as_ref().unwrap();
+    assert!(row_cache.get(b"1").is_none());
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"233")));
+    assert_eq!(row_cache.get(b"1"), Some(Bytes::from_static(b"233")));
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"233")));
+    // keys served from memtables are never cached
+    storage.put(b"3", b"23333").unwrap();
+    assert_eq!(
+        storage.get(b"3").unwrap(),
+        Some(Bytes::from_static(b"23333"))
+    );
+    assert!(row_cache.get(b"3").is_none());
+}
+
+#[test]
+fn test_row_cache_invalidate_on_write() {
+    let dir = tempdir().unwrap();
+    let storage = row_cache_storage(&dir);
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    sync(&storage);
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"233")));
+    assert_eq!(
+        storage.get(b"2").unwrap(),
+        Some(Bytes::from_static(b"2333"))
+    );
+
+    storage.put(b"1", b"new").unwrap();
+    storage.delete(b"2").unwrap();
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"new")));
+    assert_eq!(storage.get(b"2").unwrap(), None);
+    sync(&storage);
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"new")));
+    assert_eq!(storage.get(b"2").unwrap(), None);
+    // the tombstone itself is cached after it was read from the SST
+    assert_eq!(
+        storage.row_cache.as_ref().unwrap().get(b"2"),
+        Some(Bytes::new())
+    );
+    assert_eq!(storage.get(b"2").unwrap(), None);
+}
+
+#[test]
+fn test_row_cache_stale_fill_after_flush() {
+    let dir = tempdir().unwrap();
+    let storage = row_cache_storage(&dir);
+    storage.put(b"1", b"233").unwrap();
+    sync(&storage);
+
+    // a reader that observed the epoch before a flush must not fill the cache
+    let row_cache = storage.row_cache.as_ref().unwrap();
+    let epoch = row_cache.epoch();
+    storage.put(b"1", b"new").unwrap();
+    sync(&storage);
+    row_cache.insert_if_current(epoch, b"1", Bytes::from_static(b"233"));
+    assert!(row_cache.get(b"1").is_none());
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"new")));
+}
+
+fn flush(storage: &LsmStorageInner) -> anyhow::Result<()> {
+    storage.force_freeze_memtable(&storage.state_lock.lock())?;
+    storage.force_flush_next_imm_memtable()
+}
+
+#[test]
+fn test_row_cache_invalidated_by_failed_flush() {
+    let mut num_write_operations = 0;
+    loop {
+        let fs = Arc::new(FaultInjectionFileSystem::new(Arc::new(
+            InMemoryFileSystem::new(),
+        )));
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.row_cache_capacity = 1 << 20;
+        options.file_system = Some(fs.clone());
+        let storage = LsmStorageInner::open("/db", options).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        sync(&storage);
+
+        // a reader racing with the overwrite fills the cache with the value the memtable now masks
+        let row_cache = storage.row_cache.as_ref().unwrap();
+        let epoch = row_cache.epoch();
+        storage.put(b"1", b"new").unwrap();
+        row_cache.insert_if_current(epoch, b"1", Bytes::from_static(b"233"));
+        assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"new")));
+
+        // the flush fails at its nth write operation, possibly once the memtable is gone and only the SST is left
+        fs.fail_writes_after(num_write_operations);
+        let result = flush(&storage);
+        fs.clear_faults();
+        assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from_static(b"new")));
+        if result.is_ok() {
+            break;
+        }
+        num_write_operations += 1;
+    }
+    assert!(num_write_operations > 1);
+}
@@ -0,0 +1,76 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options
+}
+
+fn value(value: &'static str) -> Option<Bytes> {
+    Some(Bytes::from(value))
+}
+
+#[test]
+fn test_secondary_catches_up_with_primary() {
+    let dir = tempdir().unwrap();
+    let (primary_path, secondary_path) = (dir.path().join("primary"), dir.path().join("secondary"));
+    let primary = MiniLsm::open(&primary_path, options()).unwrap();
+    primary.put(b"a", b"1").unwrap();
+    primary.force_flush().unwrap();
+
+    let secondary = MiniLsm::open_as_secondary(&primary_path, &secondary_path, options()).unwrap();
+    assert_eq!(secondary.get(b"a").unwrap(), value("1"));
+    assert!(secondary.put(b"b", b"2").is_err());
+
+    primary.put(b"a", b"2").unwrap();
+    primary.put(b"b", b"1").unwrap();
+    primary.force_flush().unwrap();
+    primary.force_full_compaction().unwrap();
+    primary.put(b"c", b"1").unwrap();
+    primary.sync().unwrap();
+    let snapshot = secondary.snapshot();
+    assert_eq!(secondary.get(b"b").unwrap(), None);
+
+    secondary.try_catch_up_with_primary().unwrap();
+    assert_eq!(secondary.get(b"a").unwrap(), value("2"));
+    assert_eq!(secondary.get(b"b").unwrap(), value("1"));
+    assert_eq!(secondary.get(b"c").unwrap(), value("1"));
+    // the snapshot does not see the writes caught up with
+    assert_eq!(secondary.get_with_snapshot(b"b", &snapshot).unwrap(), None);
+    assert_eq!(secondary.get_with_snapshot(b"c", &snapshot).unwrap(), None);
+
+    // nothing changed, nothing to catch up
+    secondary.try_catch_up_with_primary().unwrap();
+    assert_eq!(secondary.get(b"c").unwrap(), value("1"));
+    primary.close().unwrap();
+    secondary.close().unwrap();
+}
+
+#[test]
+fn test_secondary_spills_to_its_own_directory() {
+    let dir = tempdir().unwrap();
+    let (primary_path, secondary_path) = (dir.path().join("primary"), dir.path().join("secondary"));
+    let primary = MiniLsm::open(&primary_path, options()).unwrap();
+    primary.put(b"a", b"1").unwrap();
+    primary.close().unwrap();
+
+    let mut secondary_options = options();
+    secondary_options.txn_spill_threshold = 64;
+    let secondary =
+        MiniLsm::open_as_secondary(&primary_path, &secondary_path, secondary_options).unwrap();
+    let txn = secondary.new_txn().unwrap();
+    for i in 0..10 {
+        txn.put(format!("key_{}", i).as_bytes(), b"value");
+    }
+    assert!(txn.num_spilled_runs() > 0);
+    assert!(std::fs::read_dir(&secondary_path).unwrap().count() > 0);
+    assert_eq!(txn.get(b"key_0").unwrap(), value("value"));
+    assert_eq!(txn.get(b"a").unwrap(), value("1"));
+    assert!(txn.commit().is_err());
+
+    let read_only = MiniLsm::open_read_only(&primary_path, options()).unwrap();
+    assert!(read_only.try_catch_up_with_primary().is_err());
+}
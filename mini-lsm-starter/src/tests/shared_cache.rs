@@ -0,0 +1,74 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use self::harness::{check_lsm_iter_result_by_key, sync};
+
+use super::*;
+use crate::block::BlockCache;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, SecondaryCacheOptions};
+use crate::write_buffer_manager::WriteBufferManager;
+
+#[test]
+fn test_shared_block_cache() {
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let (dir1, dir2) = (tempdir().unwrap(), tempdir().unwrap());
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_cache = Some(block_cache.clone());
+    let db1 = Arc::new(LsmStorageInner::open(&dir1, options.clone()).unwrap());
+    let db2 = Arc::new(LsmStorageInner::open(&dir2, options.clone()).unwrap());
+
+    // both databases flush an SST with the same id
+    db1.put(b"key", b"db1").unwrap();
+    db2.put(b"key", b"db2").unwrap();
+    sync(&db1);
+    sync(&db2);
+    for _ in 0..2 {
+        check_lsm_iter_result_by_key(
+            &mut db1.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![(Bytes::from("key"), Bytes::from("db1"))],
+        );
+        check_lsm_iter_result_by_key(
+            &mut db2.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![(Bytes::from("key"), Bytes::from("db2"))],
+        );
+    }
+    assert_eq!(block_cache.stats().entry_count, 2);
+
+    options.secondary_cache = Some(SecondaryCacheOptions {
+        path: dir1.path().join("cache"),
+        capacity: 1 << 20,
+    });
+    assert!(LsmStorageInner::open(tempdir().unwrap(), options).is_err());
+}
+
+#[test]
+fn test_write_buffer_manager() {
+    let manager = Arc::new(WriteBufferManager::new(1000));
+    let (dir1, dir2) = (tempdir().unwrap(), tempdir().unwrap());
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.write_buffer_manager = Some(manager.clone());
+    let db1 = Arc::new(LsmStorageInner::open(&dir1, options.clone()).unwrap());
+    let db2 = Arc::new(LsmStorageInner::open(&dir2, options).unwrap());
+
+    db1.put(b"1", &[b'x'; 400]).unwrap();
+    db2.put(b"2", &[b'x'; 400]).unwrap();
+    assert_eq!(manager.memory_usage(), 2 * (1 + 8 + 400));
+    assert!(db2.state.read().imm_memtables.is_empty());
+
+    // going over the shared budget freezes the memtable of the writer
+    db2.put(b"3", &[b'x'; 400]).unwrap();
+    assert!(manager.should_flush());
+    assert_eq!(db2.state.read().imm_memtables.len(), 1);
+    assert!(db1.state.read().imm_memtables.is_empty());
+
+    db2.force_flush_next_imm_memtable().unwrap();
+    assert_eq!(manager.memory_usage(), 1 + 8 + 400);
+    assert!(!manager.should_flush());
+
+    drop(db1);
+    drop(db2);
+    assert_eq!(manager.memory_usage(), 0);
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::SnapshotStats;
+
+#[test]
+fn test_snapshot_stats() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    assert_eq!(storage.mvcc().snapshot_stats(), SnapshotStats::default());
+
+    storage.put(b"a", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"a", b"2").unwrap();
+    let txn = storage.new_txn().unwrap();
+    let clone = snapshot.clone();
+    let stats = storage.mvcc().snapshot_stats();
+    assert_eq!(stats.num_snapshots, 3);
+    assert_eq!(stats.num_txns, 1);
+    assert_eq!(stats.oldest_read_ts, Some(snapshot.read_ts()));
+    assert!(stats.oldest_age.is_some());
+
+    drop(snapshot);
+    drop(clone);
+    let stats = storage.mvcc().snapshot_stats();
+    assert_eq!(stats.num_snapshots, 1);
+    assert_eq!(stats.oldest_read_ts, Some(txn.read_ts()));
+    drop(txn);
+    assert_eq!(storage.mvcc().snapshot_stats(), SnapshotStats::default());
+}
+
+#[test]
+fn test_warn_old_snapshots() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.snapshot_warn_age_millis = 20;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let leaked = storage.snapshot();
+    let _txn = storage.new_txn().unwrap();
+    assert_eq!(storage.mvcc().warn_old_snapshots(), 0);
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(storage.mvcc().warn_old_snapshots(), 2);
+    // reported once
+    assert_eq!(storage.mvcc().warn_old_snapshots(), 0);
+
+    let _late = storage.snapshot();
+    drop(leaked);
+    assert_eq!(storage.mvcc().warn_old_snapshots(), 0);
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(storage.mvcc().warn_old_snapshots(), 1);
+}
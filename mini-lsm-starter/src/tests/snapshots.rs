@@ -0,0 +1,79 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_lsm_iter_result_by_key, sync};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[test]
+fn test_snapshot_reads_survive_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    sync(&storage);
+    let snapshot = storage.snapshot();
+    assert_eq!(snapshot.read_ts(), storage.mvcc().latest_commit_ts());
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+
+    // the compaction kept the versions the snapshot reads
+    assert_eq!(
+        storage.get_with_snapshot(b"a", &snapshot).unwrap(),
+        Some(Bytes::from_static(b"1"))
+    );
+    assert_eq!(
+        storage.get_with_snapshot(b"b", &snapshot).unwrap(),
+        Some(Bytes::from_static(b"1"))
+    );
+    assert_eq!(storage.get_with_snapshot(b"c", &snapshot).unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+            .unwrap(),
+        vec![
+            (Bytes::from_static(b"a"), Bytes::from_static(b"1")),
+            (Bytes::from_static(b"b"), Bytes::from_static(b"1")),
+        ],
+    );
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+
+    // released, the old versions are collected by the next compaction
+    let read_ts = snapshot.read_ts();
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert!(storage.get_at_ts(b"a", read_ts).is_err());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+}
+
+#[test]
+fn test_snapshots_ref_counted() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let first = storage.snapshot();
+    let second = storage.snapshot();
+    let cloned = first.clone();
+    storage.put(b"a", b"2").unwrap();
+    let newer = storage.snapshot();
+    assert_eq!(storage.mvcc().num_retained_snapshots(), 2);
+    assert_eq!(storage.mvcc().watermark(), first.read_ts());
+
+    // the ts stays pinned until its last snapshot is dropped
+    drop(first);
+    drop(second);
+    assert_eq!(storage.mvcc().watermark(), cloned.read_ts());
+    drop(cloned);
+    assert_eq!(storage.mvcc().watermark(), newer.read_ts());
+    drop(newer);
+    assert_eq!(storage.mvcc().num_retained_snapshots(), 0);
+    assert_eq!(
+        storage.mvcc().watermark(),
+        storage.mvcc().latest_commit_ts()
+    );
+}
@@ -0,0 +1,73 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::generate_sst_with_ts;
+use crate::fs::LocalFileSystem;
+use crate::table::{dump_sst, SstDumpOptions};
+
+fn dump(path: &std::path::Path, options: &SstDumpOptions) -> (String, anyhow::Result<()>) {
+    let mut out = Vec::new();
+    let result = dump_sst(&LocalFileSystem, path, options, &mut out);
+    (String::from_utf8(out).unwrap(), result)
+}
+
+#[test]
+fn test_sst_dump() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let data = (0..100)
+        .map(|i| {
+            let key = Bytes::from(format!("key_{:03}", i));
+            let value = if i == 7 {
+                Bytes::new()
+            } else {
+                Bytes::from(format!("value_{}", i))
+            };
+            ((key, 100 - i as u64), value)
+        })
+        .collect();
+    let table = generate_sst_with_ts(1, &path, data, None);
+    assert!(table.num_of_blocks() > 1);
+
+    let (out, result) = dump(&path, &SstDumpOptions::default());
+    result.unwrap();
+    assert!(out.contains("\"num_entries\": 100"));
+    assert!(out.contains(&format!("blocks: {}", table.num_of_blocks())));
+    assert!(out.contains("block 0: offset 0 "));
+    assert!(out.contains("hash functions: "));
+    assert!(!out.contains("entries:"));
+
+    let options = SstDumpOptions {
+        entries: true,
+        timestamps: true,
+        verify_checksums: true,
+        ..Default::default()
+    };
+    let (out, result) = dump(&path, &options);
+    result.unwrap();
+    assert!(out.contains("  key_000 @ 100 => value_0\n"));
+    assert!(out.contains("  key_007 @ 93 => (deleted)\n"));
+    assert!(out.contains("file checksum: crc32 "));
+    assert!(out.contains(" compression None"));
+    let options = SstDumpOptions {
+        entries: true,
+        hex: true,
+        ..Default::default()
+    };
+    assert!(dump(&path, &options).0.contains("  6b65795f303031 => "));
+
+    // a flipped byte in the second block
+    let mut file = std::fs::read(&path).unwrap();
+    let offset = table.block_meta[1].offset;
+    file[offset + 1] ^= 0xff;
+    std::fs::write(&path, file).unwrap();
+    let options = SstDumpOptions {
+        verify_checksums: true,
+        ..Default::default()
+    };
+    let (out, result) = dump(&path, &options);
+    assert!(result.is_err());
+    assert!(out.contains("block 1: "));
+    assert!(out.contains("CORRUPTED: block checksum mismatched"));
+    assert!(out.contains("block 2: "));
+}
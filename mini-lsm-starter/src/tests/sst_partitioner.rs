@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, sync};
+use crate::compact::FixedPrefixPartitioner;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+const TENANT_PREFIX_LEN: usize = "tenant_0".len();
+
+fn partitioned_options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.sst_partitioner = Some(Arc::new(FixedPrefixPartitioner::new(TENANT_PREFIX_LEN)));
+    options
+}
+
+fn put_tenants(storage: &LsmStorageInner, tenants: usize, keys: usize, value: &str) {
+    for tenant in 0..tenants {
+        for i in 0..keys {
+            storage
+                .put(
+                    format!("tenant_{tenant}_key_{:05}", i).as_bytes(),
+                    value.as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+    sync(storage);
+}
+
+/// The tenant prefixes of the SSTs in the levels below L0.
+fn sst_prefixes(storage: &LsmStorageInner) -> Vec<(Bytes, Bytes)> {
+    let state = storage.state.read();
+    state
+        .levels
+        .iter()
+        .flat_map(|(_, sst_ids)| sst_ids)
+        .map(|id| {
+            let sst = &state.sstables[id];
+            (
+                Bytes::copy_from_slice(&sst.first_key().key_ref()[..TENANT_PREFIX_LEN]),
+                Bytes::copy_from_slice(&sst.last_key().key_ref()[..TENANT_PREFIX_LEN]),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_compaction_output_cut_at_partitions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, partitioned_options()).unwrap();
+    put_tenants(&storage, 3, 50, "v1");
+    put_tenants(&storage, 3, 50, "v2");
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+
+    let prefixes = sst_prefixes(&storage);
+    assert_eq!(prefixes.len(), 3);
+    for (first, last) in prefixes {
+        assert_eq!(first, last);
+    }
+    assert_eq!(
+        storage.get(b"tenant_1_key_00010").unwrap(),
+        Some(Bytes::from("v2"))
+    );
+}
+
+#[test]
+fn test_sst_spanning_partitions_not_moved() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, partitioned_options()).unwrap();
+    // the two L0 SSTs do not overlap, but each holds two tenants
+    put_tenants(&storage, 2, 50, "v1");
+    for tenant in 2..4 {
+        for i in 0..50 {
+            storage
+                .put(format!("tenant_{tenant}_key_{:05}", i).as_bytes(), b"v1")
+                .unwrap();
+        }
+    }
+    sync(&storage);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    storage.trigger_compaction().unwrap();
+
+    let state = storage.state.read().clone();
+    let (_, base_level) = state.levels.last().unwrap();
+    assert_eq!(base_level.len(), 4);
+    assert!(l0_sstables.iter().all(|id| !base_level.contains(id)));
+    for (first, last) in sst_prefixes(&storage) {
+        assert_eq!(first, last);
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+use crate::statistics::{Histogram, HistogramData, Statistics, Ticker};
+
+#[test]
+fn test_statistics() {
+    let dir = tempdir().unwrap();
+    let mut options = simple_leveled_wal_options();
+    let statistics = Arc::new(Statistics::new());
+    options.statistics = Some(statistics.clone());
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    for i in 0..100 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    storage.delete(b"key_000").unwrap();
+    assert_eq!(statistics.ticker(Ticker::KeysWritten), 101);
+    assert_eq!(statistics.ticker(Ticker::BytesWritten), 100 * 12 + 7);
+    assert_eq!(statistics.histogram(Histogram::Write).count, 101);
+    storage.sync().unwrap();
+    assert!(statistics.ticker(Ticker::WalSynced) >= 1);
+    assert_eq!(
+        statistics.histogram(Histogram::WalSync).count,
+        statistics.ticker(Ticker::WalSynced)
+    );
+
+    // from the memtable
+    assert!(storage.get(b"key_001").unwrap().is_some());
+    assert!(storage.get(b"key_000").unwrap().is_none());
+    assert_eq!(statistics.ticker(Ticker::KeysRead), 1);
+    assert_eq!(statistics.ticker(Ticker::BytesRead), 5);
+    sync(&storage);
+    assert!(statistics.ticker(Ticker::FlushWriteBytes) > 0);
+
+    // from the SST, the second time from the block cache
+    assert!(storage.get(b"key_002").unwrap().is_some());
+    assert_eq!(statistics.ticker(Ticker::BloomFilterPositive), 1);
+    assert_eq!(statistics.ticker(Ticker::BlockCacheMiss), 1);
+    assert_eq!(statistics.histogram(Histogram::BlockRead).count, 1);
+    assert!(storage.get(b"key_002").unwrap().is_some());
+    assert_eq!(statistics.ticker(Ticker::BlockCacheHit), 1);
+    assert_eq!(statistics.ticker(Ticker::KeysRead), 3);
+    assert_eq!(statistics.histogram(Histogram::Get).count, 4);
+    assert_eq!(
+        statistics.ticker(Ticker::BloomFilterPositive)
+            + statistics.ticker(Ticker::BloomFilterUseful),
+        2
+    );
+
+    storage.put(b"key_001", b"newer").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    assert!(statistics.ticker(Ticker::CompactReadBytes) > 0);
+    assert!(statistics.ticker(Ticker::CompactWriteBytes) > 0);
+
+    let dump = statistics.to_string();
+    assert!(dump.contains("number.keys.written COUNT : 102"), "{}", dump);
+    assert!(dump.contains("db.get.micros P50 : "), "{}", dump);
+    assert_eq!(
+        dump.lines().count(),
+        Ticker::ALL.len() + Histogram::ALL.len()
+    );
+
+    statistics.reset();
+    assert!(Ticker::ALL
+        .iter()
+        .all(|ticker| statistics.ticker(*ticker) == 0));
+    assert_eq!(statistics.histogram(Histogram::Get).count, 0);
+}
+
+#[test]
+fn test_histogram_percentiles() {
+    let statistics = Statistics::new();
+    for micros in 1..=100 {
+        statistics.record_duration(Histogram::Get, Duration::from_micros(micros));
+    }
+    let data = statistics.histogram(Histogram::Get);
+    assert_eq!(
+        (data.count, data.sum, data.min, data.max),
+        (100, 5050, 1, 100)
+    );
+    assert_eq!(data.average(), 50.5);
+    let p50 = data.percentile(50.0);
+    assert!((32.0..64.0).contains(&p50), "{}", p50);
+    let p99 = data.percentile(99.0);
+    assert!((64.0..=100.0).contains(&p99), "{}", p99);
+    assert_eq!(data.percentile(100.0), 100.0);
+}
+
+#[test]
+fn test_histogram_merge() {
+    let mut merged = HistogramData::default();
+    let mut other = HistogramData::default();
+    for value in 1..=50 {
+        merged.add(value);
+        other.add(value + 50);
+    }
+    merged.merge(&other);
+    merged.merge(&HistogramData::default());
+    let statistics = Statistics::new();
+    for micros in 1..=100 {
+        statistics.record_duration(Histogram::Get, Duration::from_micros(micros));
+    }
+    assert_eq!(merged, statistics.histogram(Histogram::Get));
+}
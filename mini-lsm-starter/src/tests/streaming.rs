@@ -0,0 +1,150 @@
+use std::io::{Cursor, Read};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_lsm_iter_result_by_key, sync};
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options(compaction_options: CompactionOptions) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    options.enable_wal = true;
+    options
+}
+
+fn simple_leveled_options() -> LsmStorageOptions {
+    options(CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    }))
+}
+
+/// A value of 3 and a half chunks.
+fn large_value() -> Vec<u8> {
+    (0..(7 << 19)).map(|i| (i % 251) as u8).collect()
+}
+
+fn read_streaming(storage: &LsmStorageInner, key: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = storage.get_streaming(key).unwrap()?;
+    let mut value = Vec::new();
+    reader.read_to_end(&mut value).unwrap();
+    assert_eq!(reader.size(), value.len() as u64);
+    Some(value)
+}
+
+fn blob_files(path: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "blob"))
+        .collect()
+}
+
+#[test]
+fn test_streaming() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_options()).unwrap();
+    storage.put(b"a", b"small").unwrap();
+    storage.put(b"b", b"small").unwrap();
+    let snapshot = storage.snapshot();
+    // the older version in the memtable is flushed, not read instead
+    storage
+        .put_streaming(b"a", Cursor::new(large_value()))
+        .unwrap();
+    assert!(storage.state.read().memtable.is_empty());
+    assert_eq!(blob_files(dir.path()).len(), 1);
+    assert!(storage
+        .put_streaming(b"empty", Cursor::new(Vec::new()))
+        .is_err());
+    assert_eq!(blob_files(dir.path()).len(), 1);
+    storage.put(b"c", b"small").unwrap();
+    storage
+        .put_streaming(b"d", Cursor::new(b"short".to_vec()))
+        .unwrap();
+    storage.delete(b"d").unwrap();
+
+    let check = |storage: &LsmStorageInner| {
+        assert_eq!(read_streaming(storage, b"a"), Some(large_value()));
+        assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from(large_value())));
+        assert_eq!(read_streaming(storage, b"c"), Some(b"small".to_vec()));
+        assert_eq!(read_streaming(storage, b"d"), None);
+        assert_eq!(read_streaming(storage, b"empty"), None);
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("a"), Bytes::from(large_value())),
+                (Bytes::from("b"), Bytes::from("small")),
+                (Bytes::from("c"), Bytes::from("small")),
+            ],
+        );
+    };
+    check(&storage);
+    assert_eq!(
+        storage.get_with_snapshot(b"a", &snapshot).unwrap(),
+        Some(Bytes::from("small"))
+    );
+
+    // compactions keep pointing into the blob file
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    drop(snapshot);
+    drop(storage);
+
+    // recovered from the manifest, the value is not in the WAL
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_options()).unwrap();
+    check(&storage);
+    drop(storage);
+
+    // the largest blob file holds the value of `a`, corrupt its third chunk
+    let blob_file = blob_files(dir.path())
+        .into_iter()
+        .max_by_key(|path| std::fs::metadata(path).unwrap().len())
+        .unwrap();
+    let mut data = std::fs::read(&blob_file).unwrap();
+    data[(5 << 19) + 10] ^= 1;
+    std::fs::write(&blob_file, data).unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_options()).unwrap();
+    let mut reader = storage.get_streaming(b"a").unwrap().unwrap();
+    let mut chunk = vec![0; 1 << 20];
+    reader.read_exact(&mut chunk).unwrap();
+    assert_eq!(chunk, large_value()[..1 << 20]);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    assert!(storage.get(b"a").is_err());
+}
+
+#[test]
+fn test_streaming_tiered() {
+    let options = || {
+        options(CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }))
+    };
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    storage.put(b"a", b"small").unwrap();
+    sync(&storage);
+    storage
+        .put_streaming(b"a", Cursor::new(large_value()))
+        .unwrap();
+    // the newest sorted run
+    let state = storage.state.read().clone();
+    assert_eq!(state.levels.len(), 2);
+    let (_, newest) = &state.levels[0];
+    assert_eq!(state.sstables[&newest[0]].properties().num_entries, 1);
+    assert_eq!(read_streaming(&storage, b"a"), Some(large_value()));
+    drop(storage);
+
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+    assert_eq!(read_streaming(&storage, b"a"), Some(large_value()));
+    storage.put(b"a", b"newer").unwrap();
+    assert_eq!(read_streaming(&storage, b"a"), Some(b"newer".to_vec()));
+}
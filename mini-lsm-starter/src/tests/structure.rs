@@ -0,0 +1,67 @@
+use tempfile::tempdir;
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::structure::LsmStructure;
+use crate::lsm_storage::LsmStorageInner;
+
+#[test]
+fn test_dump_structure() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_wal_options()).unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"d", b"1").unwrap();
+    sync(&storage);
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"e", b"4").unwrap();
+
+    let structure = storage.dump_structure();
+    let memtables = &structure.memtables;
+    assert_eq!(memtables.len(), 2);
+    assert!(!memtables[0].frozen && memtables[1].frozen);
+    assert!(memtables[0].id > memtables[1].id);
+    assert_eq!((memtables[0].num_entries, memtables[1].num_entries), (1, 1));
+    assert_eq!(structure.levels.len(), 4);
+    assert!(structure.levels[0].ssts.is_empty());
+    let level = structure
+        .levels
+        .iter()
+        .find(|level| !level.ssts.is_empty())
+        .unwrap();
+    assert_eq!(level.ssts.len(), 1);
+    let sst = &level.ssts[0];
+    assert_eq!(
+        (&sst.first_key[..], &sst.last_key[..]),
+        (&b"a"[..], &b"d"[..])
+    );
+    assert_eq!(sst.size, level.size());
+    assert!(sst.min_ts <= sst.max_ts && sst.max_ts > 0);
+    assert!(sst.creation_time > 0);
+
+    let json = serde_json::to_string(&structure).unwrap();
+    assert_eq!(
+        serde_json::from_str::<LsmStructure>(&json).unwrap(),
+        structure
+    );
+    let printed = structure.to_string();
+    assert!(printed.starts_with("memtable "), "{}", printed);
+    assert!(printed.contains("\nL0 (0): 0 bytes\n"), "{}", printed);
+    assert!(
+        printed.contains(&format!("  {}.sst [a, d]: ", sst.id)),
+        "{}",
+        printed
+    );
+
+    let cf = storage.create_cf("other").unwrap();
+    storage.put_cf(&cf, b"x", b"1").unwrap();
+    let structure = storage.dump_structure_cf(&cf).unwrap();
+    assert_eq!(structure.memtables[0].num_entries, 1);
+    storage.drop_cf("other").unwrap();
+    assert!(storage.dump_structure_cf(&cf).is_err());
+}
@@ -0,0 +1,90 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{
+    check_compaction_ratio, check_lsm_iter_result_by_key, compaction_bench, sync,
+};
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_full_compaction_split_into_subcompactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.max_subcompactions = 4;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    // four overlapping L0 SSTs starting at key_000, key_100, key_200 and key_300
+    for i in 0..4 {
+        for j in i * 100..i * 100 + 150 {
+            storage
+                .put(
+                    format!("key_{:03}", j).as_bytes(),
+                    format!("value_{i}").as_bytes(),
+                )
+                .unwrap();
+        }
+        if i == 1 {
+            storage.delete(b"key_120").unwrap();
+        }
+        sync(&storage);
+    }
+
+    storage.force_full_compaction().unwrap();
+    let state = storage.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    // one SST per subcompaction, split at the first keys of the inputs
+    let l1 = &state.levels[0].1;
+    assert_eq!(l1.len(), 4);
+    let first_keys = l1
+        .iter()
+        .map(|id| state.sstables[id].first_key().key_ref().to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        first_keys,
+        vec![
+            b"key_000".to_vec(),
+            b"key_100".to_vec(),
+            b"key_200".to_vec(),
+            b"key_300".to_vec()
+        ]
+    );
+    for pair in l1.windows(2) {
+        assert!(state.sstables[&pair[0]].last_key() < state.sstables[&pair[1]].first_key());
+    }
+
+    let expected = (0..450)
+        .filter(|j| *j != 120)
+        .map(|j| {
+            // the latest SST covering a key is the last one starting before it
+            let i = (j / 100).min(3);
+            (
+                Bytes::from(format!("key_{:03}", j)),
+                Bytes::from(format!("value_{i}")),
+            )
+        })
+        .collect();
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+#[test]
+fn test_leveled_compaction_with_subcompactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+
+    compaction_bench(storage.clone());
+    check_compaction_ratio(storage.clone());
+}
@@ -0,0 +1,73 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use self::harness::{check_lsm_iter_result_by_key, sync};
+
+use super::*;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[test]
+fn test_time_travel_reads() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let ts1 = storage.mvcc().latest_commit_ts();
+    sync(&storage);
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    let ts2 = storage.mvcc().latest_commit_ts();
+    sync(&storage);
+    storage.put(b"a", b"3").unwrap();
+    storage.put(b"c", b"3").unwrap();
+    let ts3 = storage.mvcc().latest_commit_ts();
+
+    for (ts, a, b, c) in [
+        (ts1, Some("1"), Some("1"), None),
+        (ts2, Some("2"), None, None),
+        (ts3, Some("3"), None, Some("3")),
+        (0, None, None, None),
+    ] {
+        let expected = |value: Option<&str>| value.map(|v| Bytes::copy_from_slice(v.as_bytes()));
+        assert_eq!(storage.get_at_ts(b"a", ts).unwrap(), expected(a));
+        assert_eq!(storage.get_at_ts(b"b", ts).unwrap(), expected(b));
+        assert_eq!(storage.get_at_ts(b"c", ts).unwrap(), expected(c));
+    }
+    assert_eq!(
+        storage.get(b"a").unwrap(),
+        storage.get_at_ts(b"a", ts3).unwrap()
+    );
+
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_at_ts(Bound::Unbounded, Bound::Unbounded, ts1)
+            .unwrap(),
+        vec![
+            (Bytes::from_static(b"a"), Bytes::from_static(b"1")),
+            (Bytes::from_static(b"b"), Bytes::from_static(b"1")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_at_ts(Bound::Excluded(b"a"), Bound::Unbounded, ts3)
+            .unwrap(),
+        vec![(Bytes::from_static(b"c"), Bytes::from_static(b"3"))],
+    );
+
+    // compaction collects every version below the watermark, old snapshots are gone afterwards
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    assert!(storage.get_at_ts(b"a", ts2).is_err());
+    assert!(storage
+        .scan_at_ts(Bound::Unbounded, Bound::Unbounded, ts1)
+        .is_err());
+    assert_eq!(
+        storage.get_at_ts(b"a", ts3).unwrap(),
+        Some(Bytes::from_static(b"3"))
+    );
+    assert_eq!(storage.get_at_ts(b"b", ts3).unwrap(), None);
+}
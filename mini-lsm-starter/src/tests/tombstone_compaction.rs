@@ -0,0 +1,128 @@
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, put_range, sync};
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn delete_range(storage: &LsmStorageInner, begin: usize, end: usize) {
+    for i in begin..end {
+        storage.delete(format!("key_{:05}", i).as_bytes()).unwrap();
+    }
+    sync(storage);
+}
+
+/// Number of entries and tombstones in all SSTs.
+fn count_entries(storage: &LsmStorageInner) -> (u64, u64) {
+    let state = storage.state.read();
+    state
+        .sstables
+        .values()
+        .fold((0, 0), |(entries, deletions), sst| {
+            (
+                entries + sst.properties().num_entries,
+                deletions + sst.properties().num_deletions,
+            )
+        })
+}
+
+fn tombstone_options(tombstone_compaction_ratio: f64) -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.tombstone_compaction_ratio = tombstone_compaction_ratio;
+    options
+}
+
+#[test]
+fn test_leveled_compacts_sst_dense_in_tombstones() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, tombstone_options(0.5)).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 100, 200, "value");
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+
+    // a single L0 SST is below the L0 trigger, but it holds nothing but tombstones
+    delete_range(&storage, 0, 100);
+    assert_eq!(storage.state.read().l0_sstables.len(), 1);
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    assert_eq!(count_entries(&storage), (100, 0));
+    assert_eq!(storage.get(b"key_00000").unwrap(), None);
+    assert!(storage.get(b"key_00100").unwrap().is_some());
+
+    // the bottom level is not compacted again
+    let state = storage.state.read().clone();
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels, state.levels);
+}
+
+#[test]
+fn test_leveled_tombstone_compaction_disabled() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, tombstone_options(0.0)).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 100, 200, "value");
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    delete_range(&storage, 0, 100);
+
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 1);
+    assert_eq!(count_entries(&storage), (300, 100));
+}
+
+#[test]
+fn test_universal_merges_dense_tier_into_bottom_tier() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 10,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        },
+    ));
+    options.tombstone_compaction_ratio = 0.5;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    put_range(&storage, 100, 200, "value");
+    sync(&storage);
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels.len(), 2);
+
+    delete_range(&storage, 50, 100);
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels.len(), 1);
+    assert_eq!(count_entries(&storage), (150, 0));
+    assert_eq!(storage.get(b"key_00050").unwrap(), None);
+    assert!(storage.get(b"key_00049").unwrap().is_some());
+}
+
+#[test]
+fn test_full_compaction_keeps_tombstones_above_lower_levels() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    put_range(&storage, 0, 100, "value");
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.read().levels[0].1.is_empty());
+    assert!(!storage.state.read().levels[1].1.is_empty());
+
+    // L2 still holds the keys, so the tombstones compacted into L1 must stay
+    delete_range(&storage, 0, 50);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(count_entries(&storage), (150, 50));
+    assert_eq!(storage.get(b"key_00000").unwrap(), None);
+    assert!(storage.get(b"key_00050").unwrap().is_some());
+}
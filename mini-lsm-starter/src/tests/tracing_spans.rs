@@ -0,0 +1,93 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use super::harness::{simple_leveled_wal_options, sync};
+use crate::lsm_storage::LsmStorageInner;
+
+/// Records the names of the spans and the messages of the events.
+#[derive(Default)]
+struct Recorder {
+    next_id: AtomicU64,
+    spans: Mutex<Vec<&'static str>>,
+    events: Mutex<Vec<String>>,
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.spans.lock().push(span.metadata().name());
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        self.events.lock().push(visitor.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn test_tracing_spans() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), simple_leveled_wal_options()).unwrap();
+    let recorder = Arc::new(Recorder::default());
+    tracing::subscriber::with_default(recorder.clone(), || {
+        storage.put(b"a", b"1").unwrap();
+        storage.sync().unwrap();
+        sync(&storage);
+        storage.put(b"a", b"2").unwrap();
+        sync(&storage);
+        storage.force_full_compaction().unwrap();
+        // the first get reads the block from the compacted SST, more than a microsecond
+        storage
+            .set_options(&[("slow_read_threshold_micros", "1")])
+            .unwrap();
+        storage.get(b"a").unwrap();
+        storage
+            .set_options(&[("slow_read_threshold_micros", "0")])
+            .unwrap();
+        storage.get(b"a").unwrap();
+    });
+    let spans = recorder.spans.lock().clone();
+    for name in ["wal_sync", "flush", "manifest_write", "compaction"] {
+        assert!(spans.contains(&name), "{:?}", spans);
+    }
+    let events = recorder.events.lock().clone();
+    for message in ["flushed", "running compaction task", "compaction finished"] {
+        assert!(events.iter().any(|event| event == message), "{:?}", events);
+    }
+    assert_eq!(
+        events.iter().filter(|event| *event == "slow get").count(),
+        1,
+        "{:?}",
+        events
+    );
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, WriteBatchRecord};
+use crate::mvcc::txn::CommitConflict;
+
+fn storage(dir: &tempfile::TempDir, serializable: bool) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = serializable;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn value(value: &'static str) -> Option<Bytes> {
+    Some(Bytes::from_static(value.as_bytes()))
+}
+
+#[test]
+fn test_txn_isolation() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, true);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    let concurrent = storage.new_txn().unwrap();
+    txn.put(b"a", b"2");
+    txn.delete(b"b");
+    txn.put(b"c", b"2");
+    // the writes are only visible to the transaction until it commits
+    assert_eq!(txn.get(b"a").unwrap(), value("2"));
+    assert_eq!(txn.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"a").unwrap(), value("1"));
+    txn.commit().unwrap();
+    assert!(txn.get(b"a").is_err());
+    assert!(txn.commit().is_err());
+
+    // all at a single commit ts
+    let commit_ts = storage.mvcc().latest_commit_ts();
+    assert_eq!(storage.get_at_ts(b"a", commit_ts - 1).unwrap(), value("1"));
+    assert_eq!(storage.get(b"a").unwrap(), value("2"));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), value("2"));
+    // a transaction started before reads its snapshot
+    assert_eq!(concurrent.get(b"a").unwrap(), value("1"));
+    assert_eq!(concurrent.get(b"c").unwrap(), None);
+    // and read-only transactions always commit
+    concurrent.commit().unwrap();
+}
+
+#[test]
+fn test_txn_serializable_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, true);
+    storage.put(b"a", b"1").unwrap();
+    let first = storage.new_txn().unwrap();
+    let second = storage.new_txn().unwrap();
+    let blind_write = storage.new_txn().unwrap();
+    let read_ts = second.read_ts();
+
+    first.get(b"a").unwrap();
+    first.put(b"a", b"2");
+    second.get(b"a").unwrap();
+    second.put(b"b", b"2");
+    blind_write.put(b"a", b"3");
+    first.commit().unwrap();
+    let commit_ts = storage.mvcc().latest_commit_ts();
+
+    // the second transaction read a key written after its read ts
+    let e = second.commit().unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<CommitConflict>(),
+        Some(&CommitConflict {
+            read_ts,
+            conflicting_commit_ts: Some(commit_ts),
+        })
+    );
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    // writing without reading does not conflict
+    blind_write.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), value("3"));
+}
+
+#[test]
+fn test_txn_snapshot_isolation_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, false);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+
+    // a write skew commits under snapshot isolation
+    let first = storage.new_txn().unwrap();
+    let second = storage.new_txn().unwrap();
+    first.get(b"a").unwrap();
+    first.put(b"b", b"2");
+    second.get(b"b").unwrap();
+    second.put(b"a", b"2");
+    first.commit().unwrap();
+    second.commit().unwrap();
+
+    // the second writer of a key loses
+    let first = storage.new_txn().unwrap();
+    let second = storage.new_txn().unwrap();
+    first.put(b"a", b"3");
+    second.put(b"a", b"4");
+    first.commit().unwrap();
+    let e = second.commit().unwrap_err();
+    assert!(e.downcast_ref::<CommitConflict>().is_some(), "{}", e);
+    assert_eq!(storage.get(b"a").unwrap(), value("3"));
+}
+
+#[test]
+fn test_committed_txns_collected_below_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, true);
+    for i in 0..10 {
+        let txn = storage.new_txn().unwrap();
+        txn.put(b"a", format!("{}", i).as_bytes());
+        txn.commit().unwrap();
+    }
+    // only the last commit is above the watermark of the transaction committing it
+    assert_eq!(storage.mvcc().committed_txns.lock().len(), 1);
+    assert_eq!(storage.mvcc().num_retained_snapshots(), 0);
+}
+
+#[test]
+fn test_write_batch_single_commit_ts() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, false);
+    storage.put(b"b", b"1").unwrap();
+    let ts = storage.mvcc().latest_commit_ts();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"a"[..], &b"1"[..]),
+            WriteBatchRecord::Del(&b"b"[..]),
+        ])
+        .unwrap();
+    assert_eq!(storage.mvcc().latest_commit_ts(), ts + 1);
+    assert_eq!(storage.get(b"a").unwrap(), value("1"));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get_at_ts(b"b", ts).unwrap(), value("1"));
+}
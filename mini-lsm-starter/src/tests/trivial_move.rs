@@ -0,0 +1,123 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{leveled_options, sync};
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageOptions};
+
+fn put_numbered_range(storage: &LsmStorageInner, begin: usize, end: usize) {
+    for i in begin..end {
+        storage
+            .put(
+                format!("key_{:05}", i).as_bytes(),
+                format!("value_{i}").as_bytes(),
+            )
+            .unwrap();
+    }
+    sync(storage);
+}
+
+fn check_range(storage: &LsmStorageInner, begin: usize, end: usize) {
+    for i in begin..end {
+        assert_eq!(
+            storage.get(format!("key_{:05}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value_{i}")))
+        );
+    }
+}
+
+#[test]
+fn test_sequential_l0_ssts_moved_to_base_level() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    put_numbered_range(&storage, 0, 100);
+    put_numbered_range(&storage, 100, 200);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    assert_eq!(l0_sstables.len(), 2);
+
+    storage.trigger_compaction().unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        // the base level holds the very same SSTs, sorted by key
+        let (_, base_level) = state.levels.last().unwrap();
+        assert_eq!(
+            *base_level,
+            l0_sstables.iter().rev().copied().collect::<Vec<_>>()
+        );
+    }
+    for sst_id in &l0_sstables {
+        assert!(storage.path_of_sst(*sst_id).exists());
+    }
+    check_range(&storage, 0, 200);
+    drop(storage);
+
+    // the manifest records the move
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert_eq!(
+            state.levels.last().unwrap().1,
+            l0_sstables.iter().rev().copied().collect::<Vec<_>>()
+        );
+    }
+    check_range(&storage, 0, 200);
+}
+
+#[test]
+fn test_overlapping_l0_ssts_rewritten() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    put_numbered_range(&storage, 0, 100);
+    put_numbered_range(&storage, 50, 150);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+
+    storage.trigger_compaction().unwrap();
+    let base_level = storage.state.read().levels.last().unwrap().1.clone();
+    assert!(!base_level.is_empty());
+    assert!(base_level.iter().all(|id| !l0_sstables.contains(id)));
+    for sst_id in &l0_sstables {
+        assert!(!storage.path_of_sst(*sst_id).exists());
+    }
+    check_range(&storage, 0, 150);
+}
+
+#[test]
+fn test_simple_leveled_move_into_empty_level() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+            SimpleLeveledCompactionOptions {
+                size_ratio_percent: 200,
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 3,
+            },
+        )),
+    )
+    .unwrap();
+    put_numbered_range(&storage, 100, 200);
+    put_numbered_range(&storage, 0, 100);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read();
+    assert!(state.l0_sstables.is_empty());
+    assert_eq!(state.levels[0].1, l0_sstables);
+}
+
+#[test]
+fn test_no_trivial_move_with_compaction_filter() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, leveled_options()).unwrap();
+    storage.add_compaction_filter(CompactionFilter::Prefix(Bytes::from("key_000")));
+    put_numbered_range(&storage, 0, 100);
+    put_numbered_range(&storage, 100, 200);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+
+    storage.trigger_compaction().unwrap();
+    let base_level = storage.state.read().levels.last().unwrap().1.clone();
+    assert!(base_level.iter().all(|id| !l0_sstables.contains(id)));
+    check_range(&storage, 100, 200);
+}
@@ -0,0 +1,63 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::ts_oracle::hlc_physical_millis;
+
+#[test]
+fn test_commit_ts_monotonic_across_restarts() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"a", b"2").unwrap();
+    let commit_ts = storage.mvcc().latest_commit_ts();
+    // without a WAL the memtable is lost, with the versions at the latest ts
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    assert!(storage.mvcc().latest_commit_ts() < commit_ts);
+    storage.put(b"a", b"3").unwrap();
+    let commit_ts = storage.mvcc().latest_commit_ts();
+    storage.put(b"a", b"4").unwrap();
+    assert_eq!(storage.mvcc().latest_commit_ts(), commit_ts + 1);
+    let commit_ts = storage.mvcc().latest_commit_ts();
+    drop(storage);
+
+    // the bound survives the manifest rolling over
+    options.max_manifest_file_size = 0;
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"5").unwrap();
+    drop(storage);
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    storage.put(b"a", b"6").unwrap();
+    assert!(storage.mvcc().latest_commit_ts() > commit_ts + 1);
+}
+
+#[test]
+fn test_hybrid_logical_clock() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.hybrid_logical_clock = true;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    let before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    storage.put(b"a", b"1").unwrap();
+    let first = storage.mvcc().latest_commit_ts();
+    storage.put(b"a", b"2").unwrap();
+    let second = storage.mvcc().latest_commit_ts();
+    assert!(second > first);
+    assert!(hlc_physical_millis(first) >= before);
+    assert!(hlc_physical_millis(second) < before + 60_000);
+
+    // the clock going backwards does not move the ts back
+    storage.mvcc().update_commit_ts(second + (3_600_000 << 16));
+    storage.put(b"a", b"3").unwrap();
+    assert_eq!(
+        storage.mvcc().latest_commit_ts(),
+        second + (3_600_000 << 16) + 1
+    );
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::txn::CommitConflict;
+
+fn open(dir: &tempfile::TempDir) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn value(value: &'static str) -> Option<Bytes> {
+    Some(Bytes::from_static(value.as_bytes()))
+}
+
+#[test]
+fn test_wal_recovers_unflushed_memtables() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let flushed_memtable_id = storage.state.read().memtable.id();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"3").unwrap();
+    let commit_ts = storage.mvcc().latest_commit_ts();
+    assert!(!storage.path_of_wal(flushed_memtable_id).exists());
+    // not closed, nothing else is flushed
+    drop(storage);
+
+    let storage = open(&dir);
+    assert_eq!(storage.state.read().imm_memtables.len(), 2);
+    assert_eq!(storage.mvcc().latest_commit_ts(), commit_ts);
+    assert_eq!(storage.get(b"a").unwrap(), value("2"));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), value("3"));
+}
+
+#[test]
+fn test_prepared_txn_survives_crash_and_commits() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    // only named transactions can be prepared
+    assert!(txn.prepare().is_err());
+    txn.set_name("xid-1").unwrap();
+    txn.put(b"a", b"2");
+    txn.put(b"b", b"2");
+    txn.prepare().unwrap();
+    assert!(txn.is_prepared());
+    assert_eq!(storage.get(b"a").unwrap(), value("1"));
+    assert!(txn.prepare().is_err());
+    drop(txn);
+    drop(storage);
+
+    let storage = open(&dir);
+    let recovered = storage.recovered_prepared_txns();
+    assert_eq!(recovered.len(), 1);
+    let txn = &recovered[0];
+    assert_eq!(txn.name().as_deref(), Some("xid-1"));
+    assert!(txn.is_prepared());
+    assert_eq!(txn.get(b"b").unwrap(), value("2"));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+
+    // the prepared transaction holds on to the keys it wrote
+    let other = storage.new_txn().unwrap();
+    other.put(b"a", b"3");
+    let e = other.commit().unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<CommitConflict>(),
+        Some(&CommitConflict {
+            read_ts: other.read_ts(),
+            conflicting_commit_ts: None,
+        })
+    );
+    let named = storage.new_txn().unwrap();
+    named.set_name("xid-1").unwrap();
+    assert!(named.prepare().is_err());
+
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), value("2"));
+    assert_eq!(storage.get(b"b").unwrap(), value("2"));
+    assert!(storage.recovered_prepared_txns().is_empty());
+    drop(recovered);
+    drop(other);
+    drop(named);
+    drop(storage);
+
+    let storage = open(&dir);
+    assert!(storage.recovered_prepared_txns().is_empty());
+    assert_eq!(storage.get(b"a").unwrap(), value("2"));
+    assert_eq!(storage.get(b"b").unwrap(), value("2"));
+}
+
+#[test]
+fn test_prepared_txn_keeps_its_wal_until_rolled_back() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let txn = storage.new_txn().unwrap();
+    txn.set_name("xid-1").unwrap();
+    txn.put(b"a", b"1");
+    txn.prepare().unwrap();
+    let prepared_wal_id = storage.state.read().memtable.id();
+    storage.put(b"b", b"1").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    // flushed, but recovery needs the prepare record
+    assert!(storage.path_of_wal(prepared_wal_id).exists());
+    drop(txn);
+    drop(storage);
+
+    let storage = open(&dir);
+    assert!(storage.state.read().imm_memtables.is_empty());
+    let recovered = storage.recovered_prepared_txns();
+    assert_eq!(recovered.len(), 1);
+    recovered[0].rollback().unwrap();
+    assert!(recovered[0].commit().is_err());
+    assert!(!storage.path_of_wal(prepared_wal_id).exists());
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), value("1"));
+    drop(recovered);
+    drop(storage);
+
+    let storage = open(&dir);
+    assert!(storage.recovered_prepared_txns().is_empty());
+    assert_eq!(storage.get(b"a").unwrap(), None);
+}
+
+#[test]
+fn test_prepare_requires_wal() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    let txn = storage.new_txn().unwrap();
+    txn.set_name("xid-1").unwrap();
+    txn.put(b"a", b"1");
+    assert!(txn.prepare().is_err());
+    assert!(!txn.is_prepared());
+    // an unprepared transaction rolls back without anything to log
+    txn.rollback().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+}
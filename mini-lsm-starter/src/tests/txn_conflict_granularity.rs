@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::txn::{CommitConflict, ConflictGranularity};
+
+fn storage(
+    dir: &tempfile::TempDir,
+    txn_conflict_granularity: ConflictGranularity,
+) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = true;
+    options.txn_conflict_granularity = txn_conflict_granularity;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+/// Commits a transaction reading `read` and writing `write`, racing with another one writing `other_write`.
+fn commit_racing(
+    storage: &Arc<LsmStorageInner>,
+    read: &[u8],
+    write: &[u8],
+    other_write: &[u8],
+) -> bool {
+    let txn = storage.new_txn().unwrap();
+    txn.get(read).unwrap();
+    txn.put(write, b"1");
+    let other = storage.new_txn().unwrap();
+    other.put(other_write, b"2");
+    other.commit().unwrap();
+    match txn.commit() {
+        Ok(()) => true,
+        Err(e) => {
+            assert!(e.downcast_ref::<CommitConflict>().is_some());
+            false
+        }
+    }
+}
+
+#[test]
+fn test_key_granularity() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, ConflictGranularity::Key);
+    assert!(commit_racing(&storage, b"user_1:name", b"x", b"user_1:age"));
+    assert!(!commit_racing(
+        &storage,
+        b"user_1:name",
+        b"x",
+        b"user_1:name"
+    ));
+}
+
+#[test]
+fn test_prefix_granularity() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, ConflictGranularity::Prefix(6));
+    // distinct keys of the same prefix conflict
+    assert!(!commit_racing(
+        &storage,
+        b"user_1:name",
+        b"x",
+        b"user_1:age"
+    ));
+    assert!(commit_racing(
+        &storage,
+        b"user_1:name",
+        b"x",
+        b"user_2:name"
+    ));
+    // keys shorter than the prefix are their own prefix
+    assert!(commit_racing(&storage, b"user", b"x", b"user_1"));
+    assert!(!commit_racing(&storage, b"user", b"x", b"user"));
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::txn::{CommitConflict, Transaction, TxnExpired};
+
+fn open(dir: &tempfile::TempDir) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn prepare(
+    storage: &Arc<LsmStorageInner>,
+    name: &str,
+    expiration: Option<Duration>,
+) -> Arc<Transaction> {
+    let txn = storage.new_txn().unwrap();
+    txn.set_expiration(expiration).unwrap();
+    txn.set_name(name).unwrap();
+    txn.put(b"a", name.as_bytes());
+    txn.prepare().unwrap();
+    txn
+}
+
+fn is_conflict(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<CommitConflict>().is_some()
+}
+
+#[test]
+fn test_expired_txn_cannot_commit() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.txn_expiration_millis = 10;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"1");
+    let never = storage.new_txn().unwrap();
+    never.set_expiration(None).unwrap();
+    never.put(b"b", b"1");
+    thread::sleep(Duration::from_millis(20));
+
+    assert!(txn.is_expired());
+    let e = txn.commit().unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<TxnExpired>(),
+        Some(&TxnExpired {
+            read_ts: txn.read_ts()
+        })
+    );
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    never.commit().unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_expired_prepared_txn_is_rolled_back_by_conflicts() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let stalled = prepare(&storage, "stalled", Some(Duration::from_millis(50)));
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"1");
+    assert!(is_conflict(&txn.commit().unwrap_err()));
+    thread::sleep(Duration::from_millis(60));
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"1");
+    txn.commit().unwrap();
+
+    let e = stalled.commit().unwrap_err();
+    assert!(e.downcast_ref::<TxnExpired>().is_some());
+    stalled.rollback().unwrap_err();
+    drop(stalled);
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = open(&dir);
+    assert!(storage.recovered_prepared_txns().is_empty());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_expired_prepared_txn_commits_without_conflicts() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let txn = prepare(&storage, "slow", Some(Duration::from_millis(10)));
+    thread::sleep(Duration::from_millis(20));
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("slow")));
+}
+
+#[test]
+fn test_commit_waits_for_prepared_txns() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+
+    // resolved while waiting
+    let prepared = prepare(&storage, "resolved", None);
+    let rollback = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        prepared.rollback().unwrap();
+    });
+    let txn = storage.new_txn().unwrap();
+    txn.set_lock_timeout(Duration::from_secs(10));
+    txn.put(b"a", b"1");
+    txn.commit().unwrap();
+    rollback.join().unwrap();
+
+    // expires while waiting
+    let _stalled = prepare(&storage, "stalled", Some(Duration::from_millis(50)));
+    let txn = storage.new_txn().unwrap();
+    txn.set_lock_timeout(Duration::from_secs(10));
+    txn.put(b"a", b"2");
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("2")));
+
+    // times out
+    let _stalled = prepare(&storage, "pending", None);
+    let txn = storage.new_txn().unwrap();
+    txn.set_lock_timeout(Duration::from_millis(20));
+    txn.put(b"a", b"3");
+    let start = Instant::now();
+    assert!(is_conflict(&txn.commit().unwrap_err()));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::txn::CommitConflict;
+
+fn storage(dir: &tempfile::TempDir, serializable: bool) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = serializable;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn value(value: &'static str) -> Option<Bytes> {
+    Some(Bytes::from_static(value.as_bytes()))
+}
+
+#[test]
+fn test_rollback_to_nested_savepoints() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, true);
+    storage.put(b"a", b"0").unwrap();
+    let txn = storage.new_txn().unwrap();
+    assert!(txn.rollback_to_savepoint().is_err());
+    assert!(txn.pop_savepoint().is_err());
+
+    txn.put(b"a", b"1");
+    txn.set_savepoint().unwrap();
+    txn.put(b"a", b"2");
+    txn.put(b"b", b"2");
+    txn.set_savepoint().unwrap();
+    txn.delete(b"a");
+    txn.put(b"c", b"3");
+    assert_eq!(txn.get(b"a").unwrap(), None);
+
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), value("2"));
+    assert_eq!(txn.get(b"b").unwrap(), value("2"));
+    assert_eq!(txn.get(b"c").unwrap(), None);
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), value("1"));
+    assert_eq!(txn.get(b"b").unwrap(), None);
+    assert!(txn.rollback_to_savepoint().is_err());
+
+    // a popped savepoint keeps the writes, the rollback goes to the one before
+    txn.set_savepoint().unwrap();
+    txn.put(b"d", b"4");
+    txn.set_savepoint().unwrap();
+    txn.put(b"e", b"5");
+    txn.pop_savepoint().unwrap();
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"d").unwrap(), None);
+    assert_eq!(txn.get(b"e").unwrap(), None);
+
+    txn.put(b"f", b"6");
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), value("1"));
+    assert_eq!(storage.get(b"f").unwrap(), value("6"));
+    for key in [b"b", b"c", b"d", b"e"] {
+        assert_eq!(storage.get(key).unwrap(), None);
+    }
+    assert!(txn.set_savepoint().is_err());
+}
+
+#[test]
+fn test_rollback_releases_write_conflicts() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, false);
+    let txn = storage.new_txn().unwrap();
+    let other = storage.new_txn().unwrap();
+    txn.put(b"a", b"1");
+    txn.set_savepoint().unwrap();
+    txn.put(b"b", b"1");
+    other.put(b"b", b"2");
+    other.commit().unwrap();
+
+    // the key written by the other transaction is no longer written by this one
+    txn.rollback_to_savepoint().unwrap();
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), value("1"));
+    assert_eq!(storage.get(b"b").unwrap(), value("2"));
+
+    // without the rollback, both write b
+    let txn = storage.new_txn().unwrap();
+    let other = storage.new_txn().unwrap();
+    txn.set_savepoint().unwrap();
+    txn.put(b"b", b"3");
+    other.put(b"b", b"4");
+    other.commit().unwrap();
+    let e = txn.commit().unwrap_err();
+    assert!(e.downcast_ref::<CommitConflict>().is_some(), "{}", e);
+}
@@ -0,0 +1,85 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::check_lsm_iter_result_by_key;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::txn::CommitConflict;
+
+fn storage(dir: &tempfile::TempDir, serializable: bool) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = serializable;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn entry(key: &'static str, value: &'static str) -> (Bytes, Bytes) {
+    (Bytes::from(key), Bytes::from(value))
+}
+
+#[test]
+fn test_txn_scan_reads_its_writes() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, false);
+    for key in ["a", "b", "c", "d"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"2");
+    txn.delete(b"c");
+    txn.put(b"e", b"2");
+    txn.delete(b"f");
+    // written after the read ts
+    storage.put(b"d", b"3").unwrap();
+    storage.put(b"g", b"3").unwrap();
+
+    check_lsm_iter_result_by_key(
+        &mut txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            entry("a", "1"),
+            entry("b", "2"),
+            entry("d", "1"),
+            entry("e", "2"),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut txn
+            .scan(Bound::Excluded(b"a"), Bound::Included(b"d"))
+            .unwrap(),
+        vec![entry("b", "2"), entry("d", "1")],
+    );
+    txn.commit().unwrap();
+    assert!(txn.scan(Bound::Unbounded, Bound::Unbounded).is_err());
+}
+
+#[test]
+fn test_txn_scan_adds_to_the_read_set() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir, true);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    check_lsm_iter_result_by_key(&mut iter, vec![entry("a", "1"), entry("b", "1")]);
+    txn.put(b"c", b"2");
+    storage.put(b"b", b"2").unwrap();
+    let other = storage.new_txn().unwrap();
+    other.put(b"b", b"3");
+    other.commit().unwrap();
+
+    let e = txn.commit().unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<CommitConflict>(),
+        Some(&CommitConflict {
+            read_ts: txn.read_ts(),
+            conflicting_commit_ts: Some(storage.mvcc().latest_commit_ts()),
+        })
+    );
+}
@@ -0,0 +1,143 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::check_lsm_iter_result_by_key;
+use crate::fs::{FaultInjectionFileSystem, InMemoryFileSystem};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn storage(dir: &tempfile::TempDir) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.txn_spill_threshold = 256;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn key(i: usize) -> Bytes {
+    Bytes::from(format!("key_{:03}", i))
+}
+
+fn num_spill_files(path: &Path) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_str().unwrap().ends_with(".txn")
+        })
+        .count()
+}
+
+#[test]
+fn test_txn_spills_large_write_buffers() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir);
+    storage.put(&key(100), b"storage").unwrap();
+    let txn = storage.new_txn().unwrap();
+    for i in 0..100 {
+        txn.put(&key(i), format!("value_{}", i).as_bytes());
+    }
+    assert!(txn.num_spilled_runs() > 1);
+    assert_eq!(num_spill_files(dir.path()), txn.num_spilled_runs());
+    // newer writes shadow the spilled ones
+    txn.put(&key(0), b"new");
+    txn.delete(&key(1));
+    assert_eq!(txn.get(&key(0)).unwrap(), Some(Bytes::from("new")));
+    assert_eq!(txn.get(&key(1)).unwrap(), None);
+    assert_eq!(txn.get(&key(50)).unwrap(), Some(Bytes::from("value_50")));
+    check_lsm_iter_result_by_key(
+        &mut txn
+            .scan(Bound::Unbounded, Bound::Included(&key(2)))
+            .unwrap(),
+        vec![
+            (key(0), Bytes::from("new")),
+            (key(2), Bytes::from("value_2")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut txn
+            .scan(Bound::Excluded(&key(98)), Bound::Unbounded)
+            .unwrap(),
+        vec![
+            (key(99), Bytes::from("value_99")),
+            (key(100), Bytes::from("storage")),
+        ],
+    );
+
+    txn.commit().unwrap();
+    assert_eq!(num_spill_files(dir.path()), 0);
+    assert_eq!(storage.get(&key(0)).unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(&key(1)).unwrap(), None);
+    for i in 2..100 {
+        assert_eq!(
+            storage.get(&key(i)).unwrap(),
+            Some(Bytes::from(format!("value_{}", i)))
+        );
+    }
+}
+
+#[test]
+fn test_txn_spill_with_savepoints_and_rollback() {
+    let dir = tempdir().unwrap();
+    let storage = storage(&dir);
+    let txn = storage.new_txn().unwrap();
+    for i in 0..20 {
+        txn.put(&key(i), b"spilled");
+    }
+    let num_spilled_runs = txn.num_spilled_runs();
+    assert!(num_spilled_runs > 0);
+
+    // nothing is spilled while a savepoint needs the previous values
+    txn.set_savepoint().unwrap();
+    for i in 0..20 {
+        txn.put(&key(i), b"overwritten");
+    }
+    assert_eq!(txn.num_spilled_runs(), num_spilled_runs);
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(&key(0)).unwrap(), Some(Bytes::from("spilled")));
+
+    txn.rollback().unwrap();
+    assert_eq!(num_spill_files(dir.path()), 0);
+    assert_eq!(storage.get(&key(0)).unwrap(), None);
+}
+
+#[test]
+fn test_txn_spill_failure_fails_commit() {
+    let fs = Arc::new(FaultInjectionFileSystem::new(Arc::new(
+        InMemoryFileSystem::new(),
+    )));
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.txn_spill_threshold = 256;
+    options.file_system = Some(fs.clone());
+    let storage = Arc::new(LsmStorageInner::open("/db", options).unwrap());
+    let txn = storage.new_txn().unwrap();
+    fs.fail_writes_after(0);
+    for i in 0..100 {
+        txn.put(&key(i), format!("value_{}", i).as_bytes());
+    }
+    fs.clear_faults();
+    // the writes stay buffered, and a single spill was attempted
+    assert_eq!(txn.num_spilled_runs(), 0);
+    assert_eq!(fs.num_injected_faults(), 1);
+    assert_eq!(txn.get(&key(50)).unwrap(), Some(Bytes::from("value_50")));
+    let err = txn.commit().unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("failed to spill"),
+        "{:#}",
+        err
+    );
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(&key(50)).unwrap(), None);
+
+    // so does prepare
+    let txn = storage.new_txn().unwrap();
+    txn.set_name("spill").unwrap();
+    fs.fail_writes_after(0);
+    for i in 0..100 {
+        txn.put(&key(i), b"value");
+    }
+    fs.clear_faults();
+    assert!(txn.prepare().is_err());
+    assert!(!txn.is_prepared());
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::typed::{decode_key, encode_key, Batch, Db, Json, OrderedKey, ValueCodec};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Order {
+    item: String,
+    quantity: u32,
+}
+
+fn check_order<K: OrderedKey + PartialOrd + std::fmt::Debug>(mut keys: Vec<K>) {
+    keys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let encoded = keys.iter().map(encode_key).collect::<Vec<_>>();
+    for (key, encoded) in keys.iter().zip(&encoded) {
+        let decoded: K = decode_key(encoded).unwrap();
+        assert_eq!(decoded.partial_cmp(key), Some(std::cmp::Ordering::Equal));
+    }
+    for window in encoded.windows(2) {
+        assert!(
+            window[0] <= window[1],
+            "{:?} sorts after {:?}",
+            window[0],
+            window[1]
+        );
+    }
+}
+
+#[test]
+fn test_ordered_key_encoding() {
+    check_order(vec![0u64, 1, 255, 256, u64::MAX]);
+    check_order(vec![i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+    check_order(vec![i8::MIN, -1, 0, i8::MAX]);
+    check_order(vec![
+        f64::NEG_INFINITY,
+        -2.5,
+        -0.0,
+        0.0,
+        1e-300,
+        3.0,
+        f64::INFINITY,
+    ]);
+    check_order(
+        ["", "\0", "\0\0", "a", "a\0", "a\0b", "ab", "b", "\u{e9}"]
+            .map(String::from)
+            .to_vec(),
+    );
+    check_order(vec![None, Some(false), Some(true)]);
+    // composite keys sort by their first element, whatever the length of the string
+    check_order(vec![
+        ("a".to_string(), 2u32),
+        ("a".to_string(), 10),
+        ("ab".to_string(), 1),
+        ("b".to_string(), 0),
+    ]);
+    check_order(vec![(-1i32, vec![0u8, 1]), (-1, vec![1]), (0, vec![])]);
+    assert!(decode_key::<u32>(&[0, 0, 1]).is_err());
+    assert!(decode_key::<String>(b"abc").is_err());
+    assert!(decode_key::<u8>(&[1, 2]).is_err());
+}
+
+#[test]
+fn test_typed_db() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    let orders: Db<(String, i64), Order> = Db::new(storage.clone());
+    let order = |item: &str, quantity| Order {
+        item: item.to_string(),
+        quantity,
+    };
+    let key = |user: &str, id: i64| (user.to_string(), id);
+
+    orders.put(&key("bob", 1), &order("pen", 3)).unwrap();
+    let mut batch = Batch::new();
+    batch.put(&key("alice", -5), &order("ink", 1)).unwrap();
+    batch.put(&key("alice", 20), &order("pad", 2)).unwrap();
+    batch.put(&key("alice", 3), &order("nib", 9)).unwrap();
+    batch.put(&key("al", 7), &order("cap", 1)).unwrap();
+    batch.delete(&key("bob", 1));
+    assert_eq!(batch.len(), 5);
+    orders.write(&batch).unwrap();
+    storage.force_flush().unwrap();
+    orders.put(&key("bob", 2), &order("cup", 4)).unwrap();
+    assert_eq!(orders.get(&key("bob", 1)).unwrap(), None);
+    assert_eq!(orders.get(&key("bob", 2)).unwrap(), Some(order("cup", 4)));
+
+    let alice = orders
+        .scan_prefix(&"alice".to_string())
+        .unwrap()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        alice,
+        vec![
+            (key("alice", -5), order("ink", 1)),
+            (key("alice", 3), order("nib", 9)),
+            (key("alice", 20), order("pad", 2)),
+        ]
+    );
+    let range = orders
+        .scan(key("alice", 0)..=key("bob", 2))
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        range,
+        vec![key("alice", 3), key("alice", 20), key("bob", 2)]
+    );
+    assert_eq!(orders.scan(..).unwrap().count(), 5);
+
+    // a value not of the codec fails the scan once, then ends it
+    storage
+        .put(&encode_key(&key("am", 0)), b"not json")
+        .unwrap();
+    let mut iter = orders.scan(key("am", 0)..).unwrap();
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+
+    assert!(Json::decode::<Order>(&Json::encode(&order("a", 1)).unwrap()).is_ok());
+}
+
+#[cfg(any(feature = "bincode", feature = "msgpack"))]
+fn check_codec<C: ValueCodec>() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    let db: Db<u64, Order, C> = Db::new(storage);
+    let value = Order {
+        item: "pen".to_string(),
+        quantity: 3,
+    };
+    db.put(&7, &value).unwrap();
+    assert_eq!(db.get(&7).unwrap(), Some(value));
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn test_bincode_codec() {
+    check_codec::<crate::typed::Bincode>();
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn test_msgpack_codec() {
+    check_codec::<crate::typed::MessagePack>();
+}
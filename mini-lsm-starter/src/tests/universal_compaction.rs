@@ -0,0 +1,106 @@
+use tempfile::tempdir;
+
+use super::harness::{check_compaction_ratio, compaction_bench, sync};
+use crate::compact::{CompactionOptions, TieredCompactionController, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+fn universal_options(min_merge_width: usize) -> TieredCompactionOptions {
+    TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width,
+    }
+}
+
+#[test]
+fn test_integration() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(universal_options(2))),
+    )
+    .unwrap();
+
+    compaction_bench(storage.clone());
+    check_compaction_ratio(storage.clone());
+}
+
+#[test]
+fn test_min_merge_width() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(universal_options(3))),
+    )
+    .unwrap();
+    let mut state = storage.state.read().as_ref().clone();
+    // newest first: the first two tiers alone would already satisfy the size ratio
+    state.levels = vec![(9, vec![9, 10]), (7, vec![7]), (1, vec![1, 2])];
+
+    let task = TieredCompactionController::new(universal_options(2))
+        .generate_compaction_task(&state)
+        .unwrap();
+    assert_eq!(task.tiers, state.levels[..2].to_vec());
+    assert!(!task.bottom_tier_included);
+
+    let controller = TieredCompactionController::new(universal_options(3));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, state.levels);
+    assert!(task.bottom_tier_included);
+
+    // a tier flushed while compacting stays on top
+    state.levels.insert(0, (11, vec![11]));
+    let (state, mut files_to_remove) = controller.apply_compaction_result(&state, &task, &[12, 13]);
+    assert_eq!(state.levels, vec![(11, vec![11]), (12, vec![12, 13])]);
+    files_to_remove.sort();
+    assert_eq!(files_to_remove, vec![1, 2, 7, 9, 10]);
+}
+
+#[test]
+fn test_recover_tiers_from_manifest() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(universal_options(2)));
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    for i in 0..4 {
+        for j in 0..100 {
+            storage
+                .put(
+                    format!("key_{:03}", j).as_bytes(),
+                    format!("value_{i}").as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&storage);
+    }
+    storage.trigger_compaction().unwrap();
+    let levels = storage.state.read().levels.clone();
+    assert!(levels.len() < 4);
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    assert_eq!(storage.state.read().levels, levels);
+    assert_eq!(&storage.get(b"key_042").unwrap().unwrap()[..], b"value_3");
+    // new SSTs do not reuse the ids of the recovered ones
+    storage.put(b"key_042", b"value_4").unwrap();
+    sync(&storage);
+    let state = storage.state.read().clone();
+    let (newest_tier, _) = state.levels[0];
+    assert!(levels.iter().all(|(tier, _)| *tier < newest_tier));
+    assert_eq!(state.levels.len(), levels.len() + 1);
+}
+
+#[test]
+fn test_universal_is_read_as_tiered() {
+    let json = r#"{"Universal":{"num_tiers":3,"max_size_amplification_percent":200,"size_ratio":1,"min_merge_width":2}}"#;
+    let options = serde_json::from_str::<CompactionOptions>(json).unwrap();
+    let CompactionOptions::Tiered(options) = options else {
+        panic!("universal compaction options read as {:?}", options);
+    };
+    assert_eq!(options.min_merge_width, 2);
+    assert_eq!(
+        serde_json::to_value(CompactionOptions::Tiered(options)).unwrap()["Tiered"]["num_tiers"],
+        3
+    );
+}
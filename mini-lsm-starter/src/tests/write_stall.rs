@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::compact::CompactionOptions;
+use crate::event_listener::EventListener;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::statistics::{Statistics, Ticker};
+use crate::write_stall::{WriteStallCause, WriteStallCondition, WriteStallInfo};
+
+#[derive(Debug, Default)]
+struct StallListener {
+    changes: Mutex<Vec<WriteStallInfo>>,
+}
+
+impl EventListener for StallListener {
+    fn on_stall_conditions_changed(&self, info: &WriteStallInfo) {
+        self.changes.lock().push(info.clone());
+    }
+}
+
+#[test]
+fn test_level0_stop_writes_trigger() {
+    let dir = tempdir().unwrap();
+    let listener = Arc::new(StallListener::default());
+    let statistics = Arc::new(Statistics::new());
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.event_listeners.push(listener.clone());
+    options.statistics = Some(statistics.clone());
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for key in [b"a", b"b"] {
+        storage.put(key, b"1").unwrap();
+        sync(&storage);
+    }
+    assert_eq!(
+        storage.write_stall_stats().current.condition,
+        WriteStallCondition::Normal
+    );
+
+    storage
+        .set_options(&[("level0_stop_writes_trigger", "2")])
+        .unwrap();
+    let stats = storage.write_stall_stats();
+    assert_eq!(stats.current.condition, WriteStallCondition::Stopped);
+    assert_eq!(stats.current.cause, Some(WriteStallCause::Level0Files));
+    assert_eq!(stats.current.num_l0_files, 2);
+    assert_eq!(stats.num_stalls, 1);
+    assert_eq!(
+        storage.get_int_property("cobblesdb.is-write-stopped"),
+        Some(1)
+    );
+
+    let written = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (storage, written) = (storage.clone(), written.clone());
+        std::thread::spawn(move || {
+            storage.put(b"c", b"1").unwrap();
+            written.store(true, Ordering::SeqCst);
+        })
+    };
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!written.load(Ordering::SeqCst));
+    assert!(storage.write_stall_stats().stall_time >= Duration::from_millis(200));
+
+    storage
+        .set_options(&[("level0_stop_writes_trigger", "3")])
+        .unwrap();
+    writer.join().unwrap();
+    assert!(written.load(Ordering::SeqCst));
+    let stats = storage.write_stall_stats();
+    assert_eq!(stats.current.condition, WriteStallCondition::Normal);
+    assert_eq!(stats.num_stalls, 1);
+    assert!(statistics.ticker(Ticker::StallMicros) > 0);
+    assert_eq!(
+        storage.get_int_property("cobblesdb.is-write-stopped"),
+        Some(0)
+    );
+
+    let changes = listener.changes.lock().clone();
+    assert_eq!(changes.len(), 2, "{:?}", changes);
+    assert_eq!(changes[0].cause, Some(WriteStallCause::Level0Files));
+    assert_eq!(changes[1].condition, WriteStallCondition::Normal);
+    assert_eq!(changes[1].cause, None);
+}
+
+#[test]
+fn test_memtable_stop_writes_trigger_below_flush() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.num_memtable_limit = 2;
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    assert!(storage
+        .set_options(&[("memtable_stop_writes_trigger", "1")])
+        .is_err());
+    storage
+        .set_options(&[("memtable_stop_writes_trigger", "2")])
+        .unwrap();
+    storage.put(b"a", b"1").unwrap();
+    assert_eq!(
+        storage.write_stall_stats().current.condition,
+        WriteStallCondition::Normal
+    );
+}
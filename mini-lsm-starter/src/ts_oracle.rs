@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::clock::Clock;
+
+/// The low bits of a hybrid logical clock ts count the commits within a millisecond.
+pub const HLC_LOGICAL_BITS: u32 = 16;
+
+/// Number of commit ts, or of milliseconds for a hybrid logical clock, allocated between two bounds recorded in the
+/// manifest.
+const LEASE_LEN: u64 = 1 << 16;
+
+/// Allocates the commit ts of the writes and transactions. They always go up, also across restarts and when the
+/// wall clock goes backwards: every ts is below a bound recorded in the manifest beforehand, and the ts after a
+/// restart are above it, even if the versions written at the latest ts were lost or garbage collected.
+///
+/// A plain oracle counts the commits. A hybrid logical clock puts the milliseconds since the UNIX epoch in the high
+/// bits, so that ts can be compared with wall clock times, and counts the commits of a millisecond in the low ones.
+#[derive(Debug)]
+pub(crate) struct TimestampOracle {
+    hybrid_logical_clock: bool,
+    /// Read by the hybrid logical clock.
+    clock: Arc<dyn Clock>,
+    /// The bound recorded when the database was opened, the ts allocated before may go up to it.
+    recovered_bound: u64,
+    /// Allocated ts are at most this bound, recorded in the manifest. It is raised before it is recorded, so that
+    /// a manifest rolled over meanwhile does not record the previous one.
+    bound: AtomicU64,
+}
+
+impl TimestampOracle {
+    pub(crate) fn new(
+        hybrid_logical_clock: bool,
+        clock: Arc<dyn Clock>,
+        recovered_bound: u64,
+    ) -> Self {
+        Self {
+            hybrid_logical_clock,
+            clock,
+            recovered_bound,
+            bound: AtomicU64::new(recovered_bound),
+        }
+    }
+
+    /// The commit ts after `latest_commit_ts`. When it goes above the recorded bound, `persist` records a new one
+    /// first. Must be called with the write lock.
+    pub(crate) fn next_ts(
+        &self,
+        latest_commit_ts: u64,
+        persist: impl FnOnce(u64) -> Result<()>,
+    ) -> Result<u64> {
+        let mut ts = (latest_commit_ts + 1).max(self.recovered_bound + 1);
+        if self.hybrid_logical_clock {
+            ts = ts.max(self.clock.unix_millis() << HLC_LOGICAL_BITS);
+        }
+        self.advance_to(ts, persist)?;
+        Ok(ts)
+    }
+
+    /// Make `ts`, allocated elsewhere, e.g. by the primary of a replicated batch, below the recorded bound, with
+    /// `persist` recording a new one when it is not. Must be called with the write lock.
+    pub(crate) fn advance_to(
+        &self,
+        ts: u64,
+        persist: impl FnOnce(u64) -> Result<()>,
+    ) -> Result<()> {
+        let bound = self.bound.load(Ordering::SeqCst);
+        if ts > bound {
+            let lease = if self.hybrid_logical_clock {
+                LEASE_LEN << HLC_LOGICAL_BITS
+            } else {
+                LEASE_LEN
+            };
+            self.bound.store(ts + lease, Ordering::SeqCst);
+            if let Err(e) = persist(ts + lease) {
+                self.bound.store(bound, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The bound to record when rolling the manifest over.
+    pub(crate) fn bound(&self) -> u64 {
+        self.bound.load(Ordering::SeqCst)
+    }
+}
+
+/// The milliseconds since the UNIX epoch of a hybrid logical clock ts.
+pub fn hlc_physical_millis(ts: u64) -> u64 {
+    ts >> HLC_LOGICAL_BITS
+}
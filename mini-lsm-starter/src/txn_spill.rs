@@ -0,0 +1,105 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+
+use crate::fs::FileSystem;
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_DEFAULT};
+use crate::logger::{db_log, DbLogger};
+use crate::mvcc::txn::SpilledRun;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+/// Writes of a transaction spilled to a temporary SST, deleted when dropped. Deletions are empty values.
+pub(crate) struct SpilledTxnWrites {
+    sst: Arc<SsTable>,
+    fs: Arc<dyn FileSystem>,
+    path: PathBuf,
+    logger: DbLogger,
+}
+
+impl SpilledTxnWrites {
+    pub(crate) fn create(
+        fs: Arc<dyn FileSystem>,
+        id: usize,
+        block_size: usize,
+        path: impl AsRef<Path>,
+        writes: &SkipMap<Bytes, Bytes>,
+        logger: DbLogger,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut builder = SsTableBuilder::new(block_size).with_file_system(fs.clone());
+        for entry in writes.iter() {
+            builder.add(KeySlice::from_slice(entry.key(), TS_DEFAULT), entry.value());
+        }
+        Ok(Self {
+            sst: Arc::new(builder.build(id, None, path)?),
+            fs,
+            path: path.to_path_buf(),
+            logger,
+        })
+    }
+
+    /// Whether `name` is the file name of spilled writes, left behind if the database crashed.
+    pub(crate) fn is_spill_file(name: &str) -> bool {
+        name.ends_with(".txn")
+    }
+}
+
+impl SpilledRun for SpilledTxnWrites {
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let iter = SsTableIterator::create_and_seek_to_key(
+            self.sst.clone(),
+            KeySlice::from_slice(key, TS_DEFAULT),
+        )?;
+        Ok((iter.is_valid() && iter.key().key_ref() == key)
+            .then(|| Bytes::copy_from_slice(iter.value())))
+    }
+
+    fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<Vec<(Bytes, Bytes)>> {
+        let mut iter = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => SsTableIterator::create_and_seek_to_key(
+                self.sst.clone(),
+                KeySlice::from_slice(key, TS_DEFAULT),
+            )?,
+            Bound::Unbounded => SsTableIterator::create_and_seek_to_first(self.sst.clone())?,
+        };
+        if let Bound::Excluded(key) = lower {
+            if iter.is_valid() && iter.key().key_ref() == key {
+                iter.next()?;
+            }
+        }
+        let mut writes = Vec::new();
+        while iter.is_valid() {
+            let key = iter.key().key_ref();
+            match upper {
+                Bound::Included(upper) if key > upper => break,
+                Bound::Excluded(upper) if key >= upper => break,
+                _ => {}
+            }
+            writes.push((
+                Bytes::copy_from_slice(key),
+                Bytes::copy_from_slice(iter.value()),
+            ));
+            iter.next()?;
+        }
+        Ok(writes)
+    }
+}
+
+impl Drop for SpilledTxnWrites {
+    fn drop(&mut self) {
+        if let Err(e) = self.fs.remove_file(&self.path) {
+            db_log!(
+                self.logger,
+                Warn,
+                "failed to remove {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,188 @@
+mod codec;
+mod key;
+
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(feature = "bincode")]
+pub use codec::Bincode;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+pub use codec::{Json, ValueCodec};
+pub use key::{decode_key, encode_key, OrderedKey};
+
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{MiniLsm, WriteBatchRecord};
+
+/// Ties a type to the keys, values and codec it works with, while staying `Send` and `Sync` whatever they are.
+type Marker<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+/// A database of typed keys and values: the keys are encoded with `OrderedKey`, so that scans return them in their
+/// order, and the values with the codec `C`. Every key of the database must be of this encoding, which the typed
+/// databases sharing one tell apart by a leading element of their keys, e.g. `(u8, K)`.
+pub struct Db<K, V, C = Json> {
+    storage: Arc<MiniLsm>,
+    _marker: Marker<K, V, C>,
+}
+
+impl<K, V, C> Clone for Db<K, V, C> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: OrderedKey, V: Serialize + DeserializeOwned, C: ValueCodec> Db<K, V, C> {
+    pub fn new(storage: Arc<MiniLsm>) -> Self {
+        Self {
+            storage,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The database of the encoded keys and values.
+    pub fn storage(&self) -> &Arc<MiniLsm> {
+        &self.storage
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.storage.get(&encode_key(key))? {
+            Some(value) => Ok(Some(C::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, key: &K, value: &V) -> Result<()> {
+        self.storage.put(&encode_key(key), &C::encode(value)?)
+    }
+
+    pub fn delete(&self, key: &K) -> Result<()> {
+        self.storage.delete(&encode_key(key))
+    }
+
+    /// Write a batch atomically.
+    pub fn write(&self, batch: &Batch<K, V, C>) -> Result<()> {
+        self.storage.write_batch(&batch.records)
+    }
+
+    /// Iterate over the keys in the range, in their order.
+    pub fn scan(&self, range: impl RangeBounds<K>) -> Result<Iter<K, V, C>> {
+        let lower = range.start_bound().map(encode_key);
+        let upper = range.end_bound().map(encode_key);
+        let iter = self
+            .storage
+            .scan(as_slice_bound(&lower), as_slice_bound(&upper))?;
+        Ok(Iter::new(iter))
+    }
+
+    /// Iterate over the keys starting with `prefix`, the leading elements of a composite key: the `(user, item)`
+    /// keys of a user are scanned with the prefix `(user,)`, or just `user`.
+    pub fn scan_prefix<P: OrderedKey>(&self, prefix: &P) -> Result<Iter<K, V, C>> {
+        let lower = encode_key(prefix);
+        let upper = prefix_end(&lower);
+        let iter = self
+            .storage
+            .scan(Bound::Included(&lower), as_slice_bound(&upper))?;
+        Ok(Iter::new(iter))
+    }
+}
+
+fn as_slice_bound(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    bound.as_ref().map(|key| key.as_slice())
+}
+
+/// The bound past every key starting with `prefix`: the prefix with its last byte not 0xff incremented and the ones
+/// after it removed, unbounded if there is none.
+fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    match prefix.iter().rposition(|byte| *byte != 0xff) {
+        Some(last) => {
+            let mut end = prefix[..=last].to_vec();
+            end[last] += 1;
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    }
+}
+
+/// Writes to a `Db`, applied all at once by `Db::write`.
+pub struct Batch<K, V, C = Json> {
+    records: Vec<WriteBatchRecord<Vec<u8>>>,
+    _marker: Marker<K, V, C>,
+}
+
+impl<K, V, C> Default for Batch<K, V, C> {
+    fn default() -> Self {
+        Self {
+            records: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: OrderedKey, V: Serialize, C: ValueCodec> Batch<K, V, C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &K, value: &V) -> Result<()> {
+        self.records
+            .push(WriteBatchRecord::Put(encode_key(key), C::encode(value)?));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &K) {
+        self.records.push(WriteBatchRecord::Del(encode_key(key)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+/// The keys and values of a scan of a `Db`, ending after the first error.
+pub struct Iter<K, V, C = Json> {
+    iter: Option<FusedIterator<LsmIterator>>,
+    _marker: Marker<K, V, C>,
+}
+
+impl<K, V, C> Iter<K, V, C> {
+    fn new(iter: FusedIterator<LsmIterator>) -> Self {
+        Self {
+            iter: Some(iter),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: OrderedKey, V: DeserializeOwned, C: ValueCodec> Iterator for Iter<K, V, C> {
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let iter = self.iter.as_mut()?;
+        if !iter.is_valid() {
+            self.iter = None;
+            return None;
+        }
+        let entry = decode_key(iter.key()).and_then(|key| Ok((key, C::decode(iter.value())?)));
+        let result = entry.and_then(|entry| {
+            iter.next()?;
+            Ok(entry)
+        });
+        if result.is_err() {
+            self.iter = None;
+        }
+        Some(result)
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// How a `Db` encodes its values.
+pub trait ValueCodec {
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>>;
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V>;
+}
+
+/// Values as JSON, readable by other tools but the largest.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl ValueCodec for Json {
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Values as bincode, compact but unable to add or remove the fields of a struct already written.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl ValueCodec for Bincode {
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Values as MessagePack, the structs as maps of their field names so that fields may be added later.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl ValueCodec for MessagePack {
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
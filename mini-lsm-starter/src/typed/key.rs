@@ -0,0 +1,201 @@
+use anyhow::{bail, Context, Result};
+use bytes::BufMut;
+
+/// A key encoded so that the encodings sort as the keys do, by comparing their bytes. A tuple is encoded as its
+/// elements one after the other, so composite keys sort by their first element, then their second and so on, and
+/// the keys starting with the same elements are a range of the database.
+pub trait OrderedKey: Sized {
+    fn encode_key(&self, buf: &mut Vec<u8>);
+
+    /// Decode a key from the front of `buf`, leaving in it what follows.
+    fn decode_key(buf: &mut &[u8]) -> Result<Self>;
+}
+
+/// The encoding of a key.
+pub fn encode_key<K: OrderedKey>(key: &K) -> Vec<u8> {
+    let mut buf = Vec::new();
+    key.encode_key(&mut buf);
+    buf
+}
+
+/// Decode a key making up all of `buf`.
+pub fn decode_key<K: OrderedKey>(mut buf: &[u8]) -> Result<K> {
+    let key = K::decode_key(&mut buf)?;
+    if !buf.is_empty() {
+        bail!("{} bytes after the end of the key", buf.len());
+    }
+    Ok(key)
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        bail!("key truncated, {} bytes missing", len - buf.len());
+    }
+    let (taken, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(taken)
+}
+
+macro_rules! impl_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            // big-endian, so the most significant byte is compared first
+            impl OrderedKey for $ty {
+                fn encode_key(&self, buf: &mut Vec<u8>) {
+                    buf.put_slice(&self.to_be_bytes());
+                }
+
+                fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+                    let bytes = take(buf, std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            // with the sign bit flipped, the negative numbers sort before the others
+            impl OrderedKey for $ty {
+                fn encode_key(&self, buf: &mut Vec<u8>) {
+                    ((*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1))).encode_key(buf);
+                }
+
+                fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+                    Ok((<$unsigned>::decode_key(buf)? ^ (1 << (<$unsigned>::BITS - 1))) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64, u128);
+impl_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl OrderedKey for bool {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(*self as u8);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        match u8::decode_key(buf)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => bail!("invalid bool {}", byte),
+        }
+    }
+}
+
+/// The bits of a float, the sign bit flipped for the positive ones and all of them for the negative ones, sort as the
+/// floats do, with -0.0 before 0.0 and the NaNs at the ends.
+impl OrderedKey for f64 {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        let bits = self.to_bits();
+        let bits = if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits ^ (1 << 63)
+        };
+        bits.encode_key(buf);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        let bits = u64::decode_key(buf)?;
+        let bits = if bits >> 63 == 1 {
+            bits ^ (1 << 63)
+        } else {
+            !bits
+        };
+        Ok(f64::from_bits(bits))
+    }
+}
+
+/// The bytes with each 0 escaped as 0, 0xff, and ending with 0, 1, so that they sort before the longer bytes they are
+/// a prefix of, whatever follows them in a composite key.
+impl OrderedKey for Vec<u8> {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self, buf);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = u8::decode_key(buf).context("unterminated bytes")?;
+            if byte != 0 {
+                bytes.push(byte);
+                continue;
+            }
+            match u8::decode_key(buf).context("unterminated bytes")? {
+                0xff => bytes.push(0),
+                1 => return Ok(bytes),
+                escape => bail!("invalid escape {} in bytes", escape),
+            }
+        }
+    }
+}
+
+/// Encoded as its UTF-8 bytes, which sort as the code points do.
+impl OrderedKey for String {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), buf);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        Ok(String::from_utf8(Vec::decode_key(buf)?)?)
+    }
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    for byte in bytes {
+        buf.put_u8(*byte);
+        if *byte == 0 {
+            buf.put_u8(0xff);
+        }
+    }
+    buf.put_slice(&[0, 1]);
+}
+
+/// `None` sorts before every `Some`.
+impl<T: OrderedKey> OrderedKey for Option<T> {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        match self {
+            None => buf.put_u8(0),
+            Some(value) => {
+                buf.put_u8(1);
+                value.encode_key(buf);
+            }
+        }
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+        match u8::decode_key(buf)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_key(buf)?)),
+            tag => bail!("invalid option tag {}", tag),
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: OrderedKey),+> OrderedKey for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(buf);)+
+            }
+
+            fn decode_key(buf: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::decode_key(buf)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
@@ -1,33 +1,323 @@
-#![allow(dead_code)] // REMOVE THIS LINE after fully implementing this functionality
-
-use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::sync::Arc;
 
-use anyhow::Result;
-use bytes::Bytes;
-use crossbeam_skiplist::SkipMap;
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
 use parking_lot::Mutex;
 
+use crate::fs::{FileSystem, WritableFile};
+use crate::key::{KeyBytes, KeySlice};
+use crate::logger::{db_log, DbLogger};
+
+/// The write-ahead log of a memtable. Every record is framed as its length (u32), the record and its crc32 (u32),
+/// so that a record torn by a crash is detected and dropped with the records after it.
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    file: Arc<Mutex<BufWriter<Box<dyn WritableFile>>>>,
+}
+
+/// A record of the WAL, as recovered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalRecord {
+    /// Writes at a single commit ts. `txn_name` is set when they commit a prepared transaction.
+    Batch {
+        txn_name: Option<String>,
+        entries: Vec<(KeyBytes, Bytes)>,
+    },
+    /// The writes of a prepared transaction, applied once it commits. `read_key_hashes` are the hashes of the keys
+    /// it read, only recorded for serializable transactions.
+    Prepare {
+        txn_name: String,
+        entries: Vec<(Bytes, Bytes)>,
+        read_key_hashes: Vec<u32>,
+    },
+    /// A prepared transaction was rolled back.
+    Rollback { txn_name: String },
+    /// Writes at a single commit ts to several column families, or to one other than the default one, each entry
+    /// with the id of its column family.
+    ColumnFamilyBatch {
+        entries: Vec<(u32, KeyBytes, Bytes)>,
+    },
 }
 
+impl WalRecord {
+    /// The commit ts of the writes of the record, `None` if it holds none yet.
+    pub fn commit_ts(&self) -> Option<u64> {
+        match self {
+            WalRecord::Batch { entries, .. } => entries.iter().map(|(key, _)| key.ts()).max(),
+            WalRecord::ColumnFamilyBatch { entries } => {
+                entries.iter().map(|(_, key, _)| key.ts()).max()
+            }
+            WalRecord::Prepare { .. } | WalRecord::Rollback { .. } => None,
+        }
+    }
+}
+
+const BATCH: u8 = 0;
+const PREPARE: u8 = 1;
+const ROLLBACK: u8 = 2;
+const COLUMN_FAMILY_BATCH: u8 = 3;
+
 impl Wal {
-    pub fn create(_path: impl AsRef<Path>) -> Result<Self> {
-        unimplemented!()
+    pub fn create(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs
+            .create(path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
     }
 
-    pub fn recover(_path: impl AsRef<Path>, _skiplist: &SkipMap<Bytes, Bytes>) -> Result<Self> {
-        unimplemented!()
+    /// Open the WAL to append to it and return its records. A torn or corrupted record ends the log, it is cut
+    /// off with anything after it.
+    pub fn recover(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        logger: &DbLogger,
+    ) -> Result<(Self, Vec<WalRecord>)> {
+        let path = path.as_ref();
+        let buf = Bytes::from(
+            fs.read(path)
+                .with_context(|| format!("failed to open {}", path.display()))?,
+        );
+        let mut file = fs.open_append(path)?;
+        let (records, valid_len) = Self::decode_records(path, &buf)?;
+        if valid_len < buf.len() {
+            db_log!(
+                logger,
+                Warn,
+                "{}: dropping {} bytes of torn or corrupted records",
+                path.display(),
+                buf.len() - valid_len
+            );
+            file.truncate(valid_len as u64)?;
+            file.sync()?;
+        }
+        Ok((
+            Self {
+                file: Arc::new(Mutex::new(BufWriter::new(file))),
+            },
+            records,
+        ))
     }
 
-    pub fn put(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
-        unimplemented!()
+    /// Read the records of the WAL without changing it, for a reader of a WAL another process may be appending to.
+    /// A torn or corrupted record ends the log.
+    pub fn read_records(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Vec<WalRecord>> {
+        let path = path.as_ref();
+        let buf = Bytes::from(
+            fs.read(path)
+                .with_context(|| format!("failed to open {}", path.display()))?,
+        );
+        Ok(Self::decode_records(path, &buf)?.0)
     }
 
+    /// The records of the complete frames at the start of `buf`, with their length.
+    pub(crate) fn decode_records(path: &Path, buf: &Bytes) -> Result<(Vec<WalRecord>, usize)> {
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        while let Some(payload) = Self::decode_frame(&buf[valid_len..]) {
+            let start = valid_len + 4;
+            records.push(
+                Self::decode_record(buf.slice(start..start + payload.len()))
+                    .with_context(|| format!("invalid record in {}", path.display()))?,
+            );
+            valid_len = start + payload.len() + 4;
+        }
+        Ok((records, valid_len))
+    }
+
+    /// The record of the frame at the start of `buf`, if it is complete and its checksum matches.
+    fn decode_frame(mut buf: &[u8]) -> Option<&[u8]> {
+        if buf.remaining() < 4 {
+            return None;
+        }
+        let len = buf.get_u32() as usize;
+        // the length may be garbage, which overflows a 32-bit usize
+        if len.checked_add(4).is_none_or(|n| buf.remaining() < n) {
+            return None;
+        }
+        let payload = &buf[..len];
+        buf.advance(len);
+        (buf.get_u32() == crc32fast::hash(payload)).then_some(payload)
+    }
+
+    pub(crate) fn decode_record(mut buf: Bytes) -> Result<WalRecord> {
+        let record = match get_u8(&mut buf)? {
+            BATCH => {
+                let txn_name = Some(get_name(&mut buf)?).filter(|name| !name.is_empty());
+                let mut entries = Vec::new();
+                for _ in 0..get_u32(&mut buf)? {
+                    let key_len = get_u16(&mut buf)? as usize;
+                    let key = get_bytes(&mut buf, key_len)?;
+                    let ts = get_u64(&mut buf)?;
+                    let value_len = get_u32(&mut buf)? as usize;
+                    let value = get_bytes(&mut buf, value_len)?;
+                    entries.push((KeyBytes::from_bytes_with_ts(key, ts), value));
+                }
+                WalRecord::Batch { txn_name, entries }
+            }
+            PREPARE => {
+                let txn_name = get_name(&mut buf)?;
+                let mut entries = Vec::new();
+                for _ in 0..get_u32(&mut buf)? {
+                    let key_len = get_u16(&mut buf)? as usize;
+                    let key = get_bytes(&mut buf, key_len)?;
+                    let value_len = get_u32(&mut buf)? as usize;
+                    entries.push((key, get_bytes(&mut buf, value_len)?));
+                }
+                let read_key_hashes = (0..get_u32(&mut buf)?)
+                    .map(|_| get_u32(&mut buf))
+                    .collect::<Result<_>>()?;
+                WalRecord::Prepare {
+                    txn_name,
+                    entries,
+                    read_key_hashes,
+                }
+            }
+            ROLLBACK => WalRecord::Rollback {
+                txn_name: get_name(&mut buf)?,
+            },
+            COLUMN_FAMILY_BATCH => {
+                let mut entries = Vec::new();
+                for _ in 0..get_u32(&mut buf)? {
+                    let cf_id = get_u32(&mut buf)?;
+                    let key_len = get_u16(&mut buf)? as usize;
+                    let key = get_bytes(&mut buf, key_len)?;
+                    let ts = get_u64(&mut buf)?;
+                    let value_len = get_u32(&mut buf)? as usize;
+                    let value = get_bytes(&mut buf, value_len)?;
+                    entries.push((cf_id, KeyBytes::from_bytes_with_ts(key, ts), value));
+                }
+                WalRecord::ColumnFamilyBatch { entries }
+            }
+            record_type => bail!("unknown WAL record type {}", record_type),
+        };
+        if buf.has_remaining() {
+            bail!("{} bytes left after the WAL record", buf.remaining());
+        }
+        Ok(record)
+    }
+
+    fn add_record(&self, record: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(record.len() + 8);
+        frame.put_u32(record.len() as u32);
+        frame.put_slice(record);
+        frame.put_u32(crc32fast::hash(record));
+        self.file.lock().write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Log writes at a single commit ts, `txn_name` being the prepared transaction they commit if any.
+    pub fn put_batch(&self, txn_name: Option<&str>, entries: &[(KeySlice, &[u8])]) -> Result<()> {
+        let mut record = Vec::new();
+        record.put_u8(BATCH);
+        put_name(&mut record, txn_name.unwrap_or_default());
+        record.put_u32(entries.len() as u32);
+        for (key, value) in entries {
+            record.put_u16(key.key_len() as u16);
+            record.put_slice(key.key_ref());
+            record.put_u64(key.ts());
+            record.put_u32(value.len() as u32);
+            record.put_slice(value);
+        }
+        self.add_record(&record)
+    }
+
+    /// Log writes at a single commit ts to several column families, as a single record.
+    pub fn put_column_family_batch(&self, entries: &[(u32, KeySlice, &[u8])]) -> Result<()> {
+        let mut record = Vec::new();
+        record.put_u8(COLUMN_FAMILY_BATCH);
+        record.put_u32(entries.len() as u32);
+        for (cf_id, key, value) in entries {
+            record.put_u32(*cf_id);
+            record.put_u16(key.key_len() as u16);
+            record.put_slice(key.key_ref());
+            record.put_u64(key.ts());
+            record.put_u32(value.len() as u32);
+            record.put_slice(value);
+        }
+        self.add_record(&record)
+    }
+
+    pub fn put_prepare(
+        &self,
+        txn_name: &str,
+        entries: &[(Bytes, Bytes)],
+        read_key_hashes: &[u32],
+    ) -> Result<()> {
+        let mut record = Vec::new();
+        record.put_u8(PREPARE);
+        put_name(&mut record, txn_name);
+        record.put_u32(entries.len() as u32);
+        for (key, value) in entries {
+            record.put_u16(key.len() as u16);
+            record.put_slice(key);
+            record.put_u32(value.len() as u32);
+            record.put_slice(value);
+        }
+        record.put_u32(read_key_hashes.len() as u32);
+        for hash in read_key_hashes {
+            record.put_u32(*hash);
+        }
+        self.add_record(&record)
+    }
+
+    pub fn put_rollback(&self, txn_name: &str) -> Result<()> {
+        let mut record = Vec::new();
+        record.put_u8(ROLLBACK);
+        put_name(&mut record, txn_name);
+        self.add_record(&record)
+    }
+
+    /// Make the records logged so far durable.
     pub fn sync(&self) -> Result<()> {
-        unimplemented!()
+        let mut file = self.file.lock();
+        file.flush()?;
+        file.get_mut().sync()?;
+        Ok(())
+    }
+}
+
+fn put_name(buf: &mut Vec<u8>, name: &str) {
+    buf.put_u16(name.len() as u16);
+    buf.put_slice(name.as_bytes());
+}
+
+fn ensure_remaining(buf: &Bytes, len: usize) -> Result<()> {
+    if buf.remaining() < len {
+        bail!("WAL record truncated");
     }
+    Ok(())
+}
+
+fn get_u8(buf: &mut Bytes) -> Result<u8> {
+    ensure_remaining(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+fn get_u16(buf: &mut Bytes) -> Result<u16> {
+    ensure_remaining(buf, 2)?;
+    Ok(buf.get_u16())
+}
+
+fn get_u32(buf: &mut Bytes) -> Result<u32> {
+    ensure_remaining(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+fn get_u64(buf: &mut Bytes) -> Result<u64> {
+    ensure_remaining(buf, 8)?;
+    Ok(buf.get_u64())
+}
+
+fn get_bytes(buf: &mut Bytes, len: usize) -> Result<Bytes> {
+    ensure_remaining(buf, len)?;
+    Ok(buf.split_to(len))
+}
+
+fn get_name(buf: &mut Bytes) -> Result<String> {
+    let len = get_u16(buf)? as usize;
+    Ok(String::from_utf8(get_bytes(buf, len)?.to_vec())?)
 }
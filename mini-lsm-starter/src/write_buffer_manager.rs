@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bounds the memtable memory of every database sharing it. Each database charges the writes to its memtables and
+/// releases them once flushed; when the total goes over `buffer_size`, databases freeze and flush their memtables
+/// early instead of waiting for them to reach `target_sst_size`.
+#[derive(Debug)]
+pub struct WriteBufferManager {
+    buffer_size: usize,
+    memory_usage: AtomicUsize,
+}
+
+impl WriteBufferManager {
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn reserve(&self, size: usize) {
+        self.memory_usage.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn free(&self, size: usize) {
+        self.memory_usage.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Bytes of memtable data charged by all databases.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    pub fn should_flush(&self) -> bool {
+        self.memory_usage() >= self.buffer_size
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::clock::Instant;
+use crate::logger::db_log;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::statistics::Ticker;
+
+/// How often a stopped write checks the triggers again, in case it missed the wakeup of the change clearing them.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether the writes go through or wait for the flushes and compactions to catch up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    #[default]
+    Normal,
+    Stopped,
+}
+
+/// The trigger that stopped the writes, the first one reached in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCause {
+    /// `memtable_stop_writes_trigger`
+    MemtableLimit,
+    /// `level0_stop_writes_trigger`
+    Level0Files,
+    /// `hard_pending_compaction_bytes_limit`
+    PendingCompactionBytes,
+}
+
+/// The write stall condition of a database, with the shape of the LSM it was decided on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteStallInfo {
+    pub condition: WriteStallCondition,
+    /// `None` when the writes are not stopped.
+    pub cause: Option<WriteStallCause>,
+    /// Immutable memtables of the default column family left to flush.
+    pub num_imm_memtables: usize,
+    pub num_l0_files: usize,
+    /// Bytes compactions have left to move, only estimated with `hard_pending_compaction_bytes_limit` and 0
+    /// otherwise.
+    pub pending_compaction_bytes: u64,
+}
+
+/// The current write stall condition, and the stalls since the database was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    pub current: WriteStallInfo,
+    /// Times the writes were stopped.
+    pub num_stalls: u64,
+    /// How long the writes were stopped, including the current stall.
+    pub stall_time: Duration,
+}
+
+#[derive(Default)]
+struct WriteStallState {
+    current: WriteStallInfo,
+    num_stalls: u64,
+    stall_time: Duration,
+    stopped_since: Option<Instant>,
+}
+
+/// Keeps the write stall condition up to date and wakes the stopped writes once it clears.
+#[derive(Default)]
+pub(crate) struct WriteStallController {
+    state: Mutex<WriteStallState>,
+    cleared: Condvar,
+}
+
+impl LsmStorageInner {
+    /// The write stall condition the LSM leads to now.
+    fn evaluate_write_stall(&self) -> WriteStallInfo {
+        let options = self.options();
+        let snapshot = self.state.read().clone();
+        let pending_compaction_bytes = if options.hard_pending_compaction_bytes_limit == 0 {
+            0
+        } else {
+            self.pending_compaction_bytes(&snapshot).iter().sum()
+        };
+        let reached = |trigger: u64, value: u64| trigger != 0 && value >= trigger;
+        let cause = if reached(
+            options.memtable_stop_writes_trigger as u64,
+            snapshot.imm_memtables.len() as u64,
+        ) {
+            Some(WriteStallCause::MemtableLimit)
+        } else if reached(
+            options.level0_stop_writes_trigger as u64,
+            snapshot.l0_sstables.len() as u64,
+        ) {
+            Some(WriteStallCause::Level0Files)
+        } else if reached(
+            options.hard_pending_compaction_bytes_limit,
+            pending_compaction_bytes,
+        ) {
+            Some(WriteStallCause::PendingCompactionBytes)
+        } else {
+            None
+        };
+        WriteStallInfo {
+            condition: if cause.is_some() {
+                WriteStallCondition::Stopped
+            } else {
+                WriteStallCondition::Normal
+            },
+            cause,
+            num_imm_memtables: snapshot.imm_memtables.len(),
+            num_l0_files: snapshot.l0_sstables.len(),
+            pending_compaction_bytes,
+        }
+    }
+
+    /// Evaluate the write stall condition after the LSM or the options changed, waking the stopped writes if it
+    /// cleared. The event listeners are notified when the writes are stopped or resumed, or stopped for another
+    /// cause.
+    pub(crate) fn update_write_stall(&self) -> WriteStallInfo {
+        let mut state = self.write_stall.state.lock();
+        let info = self.evaluate_write_stall();
+        let previous = std::mem::replace(&mut state.current, info.clone());
+        let now = self.clock.instant();
+        match (state.stopped_since, info.condition) {
+            (None, WriteStallCondition::Stopped) => {
+                state.stopped_since = Some(now);
+                state.num_stalls += 1;
+            }
+            (Some(since), WriteStallCondition::Normal) => {
+                state.stall_time += now.duration_since(since);
+                state.stopped_since = None;
+                self.write_stall.cleared.notify_all();
+            }
+            _ => {}
+        }
+        drop(state);
+        if previous.cause == info.cause {
+            return info;
+        }
+        match info.cause {
+            Some(cause) => db_log!(
+                self.options(),
+                Warn,
+                "writes stopped by {:?}: {} immutable memtables, {} L0 SSTs, {} pending compaction bytes",
+                cause,
+                info.num_imm_memtables,
+                info.num_l0_files,
+                info.pending_compaction_bytes
+            ),
+            None => db_log!(self.options(), Info, "writes resumed"),
+        }
+        for listener in &self.options().event_listeners {
+            listener.on_stall_conditions_changed(&info);
+        }
+        info
+    }
+
+    /// Wait before a write while the writes are stopped, until the flushes and compactions clear the stall or the
+    /// database closes. The time waited is counted by the `StallMicros` ticker.
+    pub(crate) fn wait_for_write_stall(&self) {
+        let options = self.options();
+        if options.memtable_stop_writes_trigger == 0
+            && options.level0_stop_writes_trigger == 0
+            && options.hard_pending_compaction_bytes_limit == 0
+        {
+            // unless the triggers were just unset, which updated the condition
+            return;
+        }
+        if self.update_write_stall().condition == WriteStallCondition::Normal {
+            return;
+        }
+        if options.inline_background_jobs {
+            // nothing else would clear the stall
+            self.run_background_jobs_inline();
+            return;
+        }
+        let started = Instant::now();
+        loop {
+            {
+                let mut state = self.write_stall.state.lock();
+                if state.current.condition == WriteStallCondition::Stopped {
+                    self.write_stall
+                        .cleared
+                        .wait_for(&mut state, RECHECK_INTERVAL);
+                }
+            }
+            if self.compactions_cancelled.load(Ordering::Acquire)
+                || self.update_write_stall().condition == WriteStallCondition::Normal
+            {
+                break;
+            }
+        }
+        if let Some(statistics) = &self.options().statistics {
+            let stall = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+            statistics.record_tick(Ticker::StallMicros, stall);
+        }
+    }
+
+    /// Whether the writes are stopped and by what, and how long they were stopped since opening. Writes are only
+    /// stopped by the `*_stop_writes_trigger` and `hard_pending_compaction_bytes_limit` options; they also wait
+    /// for a flush to freeze a full memtable, which is only counted by the `StallMicros` ticker.
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        let state = self.write_stall.state.lock();
+        let ongoing = state.stopped_since.map_or(Duration::ZERO, |since| {
+            self.clock.instant().duration_since(since)
+        });
+        WriteStallStats {
+            current: state.current.clone(),
+            num_stalls: state.num_stalls,
+            stall_time: state.stall_time + ongoing,
+        }
+    }
+}
+
+impl MiniLsm {
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        self.inner.write_stall_stats()
+    }
+}